@@ -52,6 +52,15 @@ impl JsonWriter {
         }
     }
 
+    pub fn write_null(&mut self) {
+        self.buffer.extend_from_slice(b"null");
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        let literal: &[u8] = if value { b"true" } else { b"false" };
+        self.buffer.extend_from_slice(literal);
+    }
+
     pub fn write_separator(&mut self) {
         self.buffer.push(b',');
     }
@@ -91,6 +100,31 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn write_null_value() -> anyhow::Result<()> {
+        let mut jw = JsonWriter::new();
+        jw.write_open_obj();
+        jw.write_key("temperature")?;
+        jw.write_null();
+        jw.write_close_obj();
+        assert_eq!(jw.into_string()?, r#"{"temperature":null}"#);
+        Ok(())
+    }
+
+    #[test]
+    fn write_bool_values() -> anyhow::Result<()> {
+        let mut jw = JsonWriter::new();
+        jw.write_open_obj();
+        jw.write_key("enabled")?;
+        jw.write_bool(true);
+        jw.write_separator();
+        jw.write_key("failed")?;
+        jw.write_bool(false);
+        jw.write_close_obj();
+        assert_eq!(jw.into_string()?, r#"{"enabled":true,"failed":false}"#);
+        Ok(())
+    }
+
     #[test]
     fn write_key_with_quote() -> anyhow::Result<()> {
         let mut jw = JsonWriter::with_capacity(128);
@@ -0,0 +1,102 @@
+#![cfg(feature = "integration-test")]
+// These tests require a docker daemon to start a mosquitto broker.
+// Run them by calling 'cargo test --features integration-test' from the base path of the crate
+
+use mqtt_client::{Config, Message, MqttClient, MqttCredentials, Topic};
+use sha2::{Digest, Sha512};
+use std::time::Duration;
+use tempfile::TempDir;
+use testcontainers::images::generic::{GenericImage, WaitFor};
+use testcontainers::{clients, Docker};
+use tokio::time::sleep;
+
+const MOSQUITTO_CONF: &str = r#"
+port 1883
+allow_anonymous false
+password_file /mosquitto/config/passwd
+log_dest stdout
+"#;
+
+/// A mosquitto password file entry, as generated by `mosquitto_passwd`
+fn password_entry(username: &str, password: &str) -> String {
+    let salt = b"thin-edge-io";
+    let mut hasher = Sha512::new();
+    hasher.update(password.as_bytes());
+    hasher.update(salt);
+    format!(
+        "{}:$6${}${}\n",
+        username,
+        base64::encode(salt),
+        base64::encode(hasher.finalize())
+    )
+}
+
+fn mosquitto(config_dir: &TempDir) -> GenericImage {
+    std::fs::write(config_dir.path().join("mosquitto.conf"), MOSQUITTO_CONF).unwrap();
+    std::fs::write(
+        config_dir.path().join("passwd"),
+        password_entry("device", "correct-password"),
+    )
+    .unwrap();
+
+    GenericImage::new("eclipse-mosquitto:1.6")
+        .with_volume(
+            config_dir.path().to_string_lossy().to_string(),
+            "/mosquitto/config",
+        )
+        .with_wait_for(WaitFor::message_on_stdout("mosquitto version"))
+}
+
+async fn publish_and_receive(
+    config: Config,
+) -> Result<Option<Message>, mqtt_client::MqttClientError> {
+    let topic = Topic::new("test/credentials")?;
+    let subscriber = config.connect("credentials_subscriber").await?;
+    let mut received = subscriber.subscribe(topic.filter()).await?;
+
+    let publisher = config.connect("credentials_publisher").await?;
+    let _ = publisher
+        .publish(Message::new(&topic, "authenticated"))
+        .await?;
+
+    tokio::select! {
+        msg = received.next() => Ok(msg),
+        _ = sleep(Duration::from_secs(2)) => Ok(None)
+    }
+}
+
+#[tokio::test]
+async fn correct_credentials_are_accepted() {
+    let config_dir = TempDir::new().unwrap();
+    let docker = clients::Cli::default();
+    let broker = docker.run(mosquitto(&config_dir));
+    let port = broker
+        .get_host_port(1883)
+        .expect("The broker port is exposed");
+
+    let config = Config::new("localhost", port)
+        .with_credentials(MqttCredentials::new("device", "correct-password"));
+
+    match publish_and_receive(config).await {
+        Ok(Some(message)) => assert_eq!(message.payload_str().unwrap(), "authenticated"),
+        Ok(None) => panic!("Got no message after 2s"),
+        Err(err) => panic!("Got an error: {}", err),
+    }
+}
+
+#[tokio::test]
+async fn wrong_credentials_are_rejected() {
+    let config_dir = TempDir::new().unwrap();
+    let docker = clients::Cli::default();
+    let broker = docker.run(mosquitto(&config_dir));
+    let port = broker
+        .get_host_port(1883)
+        .expect("The broker port is exposed");
+
+    let config = Config::new("localhost", port)
+        .with_credentials(MqttCredentials::new("device", "wrong-password"));
+
+    let result = publish_and_receive(config).await;
+
+    assert!(matches!(result, Err(_) | Ok(None)));
+}
@@ -0,0 +1,145 @@
+#![cfg(feature = "integration-test")]
+// These tests require a docker daemon to start a mosquitto broker.
+// Run them by calling 'cargo test --features integration-test' from the base path of the crate
+
+use mqtt_client::{Config, Message, MqttClient, MqttTlsConfig, Topic};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tempfile::TempDir;
+use testcontainers::images::generic::{GenericImage, WaitFor};
+use testcontainers::{clients, Docker};
+use tokio::time::sleep;
+
+const MOSQUITTO_CONF: &str = r#"
+port 8883
+cafile /mosquitto/certs/ca.pem
+certfile /mosquitto/certs/server.pem
+keyfile /mosquitto/certs/server.key
+require_certificate true
+log_dest stdout
+"#;
+
+/// The files of a PKI with a CA, a broker certificate and a client certificate
+struct TestPki {
+    dir: TempDir,
+}
+
+impl TestPki {
+    fn new() -> TestPki {
+        let dir = TempDir::new().unwrap();
+
+        let mut ca_params = CertificateParams::new(vec![]);
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = Certificate::from_params(ca_params).unwrap();
+        write(dir.path(), "ca.pem", &ca.serialize_pem().unwrap());
+
+        let server =
+            Certificate::from_params(CertificateParams::new(vec!["localhost".into()])).unwrap();
+        let server_cert = server.serialize_pem_with_signer(&ca).unwrap();
+        write(dir.path(), "server.pem", &server_cert);
+        write(
+            dir.path(),
+            "server.key",
+            &server.serialize_private_key_pem(),
+        );
+
+        let client =
+            Certificate::from_params(CertificateParams::new(vec!["client".into()])).unwrap();
+        let client_cert = client.serialize_pem_with_signer(&ca).unwrap();
+        write(dir.path(), "client.pem", &client_cert);
+        write(
+            dir.path(),
+            "client.key",
+            &client.serialize_private_key_pem(),
+        );
+
+        let rogue = Certificate::from_params(CertificateParams::new(vec!["rogue".into()])).unwrap();
+        write(dir.path(), "rogue.pem", &rogue.serialize_pem().unwrap());
+        write(dir.path(), "rogue.key", &rogue.serialize_private_key_pem());
+
+        write(dir.path(), "mosquitto.conf", MOSQUITTO_CONF);
+        TestPki { dir }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    fn client_tls_config(&self, cert: &str, key: &str) -> MqttTlsConfig {
+        MqttTlsConfig {
+            ca_cert_path: self.path("ca.pem"),
+            client_cert_path: self.path(cert),
+            client_key_path: self.path(key),
+        }
+    }
+}
+
+fn write(dir: &Path, name: &str, content: &str) {
+    std::fs::write(dir.join(name), content).unwrap();
+}
+
+fn mosquitto(pki: &TestPki) -> GenericImage {
+    let certs = pki.dir.path().to_string_lossy().to_string();
+    GenericImage::new("eclipse-mosquitto:1.6")
+        .with_volume(&certs, "/mosquitto/certs")
+        .with_volume(
+            pki.path("mosquitto.conf").to_string_lossy().to_string(),
+            "/mosquitto/config/mosquitto.conf",
+        )
+        .with_wait_for(WaitFor::message_on_stdout("mosquitto version"))
+}
+
+async fn publish_and_receive(
+    config: Config,
+) -> Result<Option<Message>, mqtt_client::MqttClientError> {
+    let topic = Topic::new("test/tls")?;
+    let subscriber = config.connect("tls_subscriber").await?;
+    let mut received = subscriber.subscribe(topic.filter()).await?;
+
+    let publisher = config.connect("tls_publisher").await?;
+    let _ = publisher
+        .publish(Message::new(&topic, "authenticated"))
+        .await?;
+
+    tokio::select! {
+        msg = received.next() => Ok(msg),
+        _ = sleep(Duration::from_secs(2)) => Ok(None)
+    }
+}
+
+#[tokio::test]
+async fn connect_with_a_client_certificate() {
+    let pki = TestPki::new();
+    let docker = clients::Cli::default();
+    let broker = docker.run(mosquitto(&pki));
+    let port = broker
+        .get_host_port(8883)
+        .expect("The broker port is exposed");
+
+    let config =
+        Config::new("localhost", port).with_tls(pki.client_tls_config("client.pem", "client.key"));
+
+    match publish_and_receive(config).await {
+        Ok(Some(message)) => assert_eq!(message.payload_str().unwrap(), "authenticated"),
+        Ok(None) => panic!("Got no message after 2s"),
+        Err(err) => panic!("Got an error: {}", err),
+    }
+}
+
+#[tokio::test]
+async fn a_certificate_not_signed_by_the_ca_is_rejected() {
+    let pki = TestPki::new();
+    let docker = clients::Cli::default();
+    let broker = docker.run(mosquitto(&pki));
+    let port = broker
+        .get_host_port(8883)
+        .expect("The broker port is exposed");
+
+    let config =
+        Config::new("localhost", port).with_tls(pki.client_tls_config("rogue.pem", "rogue.key"));
+
+    let result = publish_and_receive(config).await;
+
+    assert!(matches!(result, Err(_) | Ok(None)));
+}
@@ -0,0 +1,31 @@
+//! A sink publishing thin-edge JSON measurements to an AMQP broker.
+//!
+//! The sink uses [lapin](https://docs.rs/lapin), hence speaks AMQP 0-9-1,
+//! the protocol of RabbitMQ and of most AMQP brokers exposing exchanges and routing keys.
+//!
+//! ```no_run
+//! use amqp_sink::{AmqpSinkVisitor, DeliveryMode};
+//! use std::time::Duration;
+//! use thin_edge_json::measurement::GroupedMeasurementVisitor;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), anyhow::Error> {
+//! let mut visitor = AmqpSinkVisitor::connect("amqp://localhost:5672/%2f", "amq.topic")
+//!     .await?
+//!     .with_delivery_mode(DeliveryMode::Transient)
+//!     .with_message_ttl(Duration::from_secs(60));
+//!
+//! visitor.measurement("temperature", 25.5)?;
+//! visitor.start_group("location")?;
+//! visitor.measurement("alti", 2100.4)?;
+//! visitor.end_group()?;
+//!
+//! // Publishes `{"temperature":25.5}` with the routing key `tedge.measurements`
+//! // and `{"location":{"alti":2100.4}}` with the routing key `tedge.measurements.location`
+//! visitor.flush().await?;
+//! # Ok(()) }
+//! ```
+
+mod publish;
+
+pub use publish::{default_routing_key, AmqpSinkError, AmqpSinkVisitor, DeliveryMode};
@@ -0,0 +1,26 @@
+//! A sink writing thin-edge JSON measurements as Apache Avro records.
+//!
+//! ```no_run
+//! use avro_sink::{measurement_schema, AvroSinkVisitor};
+//! use std::fs::File;
+//! use thin_edge_json::measurement::GroupedMeasurementVisitor;
+//!
+//! # fn main() -> Result<(), anyhow::Error> {
+//! let schema = measurement_schema();
+//! let file = File::create("/var/tedge/measurements.avro")?;
+//! let mut visitor = AvroSinkVisitor::container_file(&schema, file);
+//!
+//! visitor.measurement("temperature", 25.5)?;
+//! visitor.start_group("location")?;
+//! visitor.measurement("alti", 2100.4)?;
+//! visitor.end_group()?;
+//! visitor.flush()?; // Appends a record to the file, compressed with Snappy
+//!
+//! visitor.into_inner()?.sync_all()?;
+//! # Ok(()) }
+//! ```
+
+mod write;
+
+pub use avro_rs::Codec;
+pub use write::{measurement_schema, AvroSinkError, AvroSinkVisitor};
@@ -0,0 +1,409 @@
+use avro_rs::rabin::Rabin;
+use avro_rs::types::Value;
+use avro_rs::{to_avro_datum, Codec, Schema, Writer};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use std::collections::HashMap;
+use std::io::Write;
+use thin_edge_json::context::MeasurementContext;
+use thin_edge_json::measurement::GroupedMeasurementVisitor;
+use thin_edge_json::serialize::MeasurementStreamError;
+use thin_edge_json::series::FlatMeasurementSeries;
+
+/// The marker of a single-object encoded record, followed by the schema fingerprint
+const SINGLE_OBJECT_MARKER: [u8; 2] = [0xC3, 0x01];
+
+const MEASUREMENT_SCHEMA: &str = r#"
+{
+    "type": "record",
+    "name": "Measurement",
+    "namespace": "io.thin_edge",
+    "fields": [
+        {
+            "name": "time",
+            "type": ["null", {"type": "long", "logicalType": "timestamp-micros"}],
+            "default": null
+        },
+        {
+            "name": "measurements",
+            "type": {"type": "map", "values": "double"}
+        },
+        {
+            "name": "groups",
+            "type": {"type": "map", "values": {"type": "map", "values": "double"}}
+        },
+        {
+            "name": "trace_id",
+            "type": ["null", "string"],
+            "default": null
+        },
+        {
+            "name": "span_id",
+            "type": ["null", "string"],
+            "default": null
+        }
+    ]
+}
+"#;
+
+#[derive(thiserror::Error, Debug)]
+pub enum AvroSinkError {
+    #[error("Failed to encode the measurements as Avro: {0}")]
+    EncodingError(#[from] avro_rs::Error),
+
+    #[error("Failed to write the Avro output: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+/// The Avro schema of the records, mirroring thin-edge JSON with one record per message:
+///
+/// * `time`: the timestamp of the measurements, in microseconds since the epoch, UTC, if any
+/// * `measurements`: the values of the top-level measurements, by name
+/// * `groups`: the values of the grouped measurements, by group then by name
+/// * `trace_id`, `span_id`: the trace context of the measurements, in hexadecimal, if any
+pub fn measurement_schema() -> Schema {
+    Schema::parse_str(MEASUREMENT_SCHEMA).expect("The measurement schema is valid")
+}
+
+enum Encoder<'a, W: Write> {
+    SingleObject {
+        schema: &'a Schema,
+        /// The marker followed by the fingerprint of the schema
+        header: Vec<u8>,
+        output: W,
+    },
+    ContainerFile(Writer<'a, W>),
+}
+
+/// A visitor that writes the measurements as Avro records, as defined by `measurement_schema()`.
+///
+/// The measurements are gathered and written as a record on `flush()`.
+/// The records are written either:
+///
+/// * as an Avro object container file, starting with a header holding the schema,
+///   the records being compressed with Snappy unless another codec is given,
+/// * or using the single-object encoding, each record being prefixed by the fingerprint
+///   of the schema, as for messages sent to a broker.
+///
+/// The units of the measurements are not written, and the timestamps are converted to UTC.
+/// The baggage of the trace context is not written either.
+pub struct AvroSinkVisitor<'a, W: Write> {
+    encoder: Encoder<'a, W>,
+    series: FlatMeasurementSeries,
+    context: Option<MeasurementContext>,
+}
+
+impl<'a, W: Write> AvroSinkVisitor<'a, W> {
+    /// Write the records to an object container file, using Snappy compression.
+    pub fn container_file(schema: &'a Schema, output: W) -> Self {
+        Self::container_file_with_codec(schema, output, Codec::Snappy)
+    }
+
+    /// Write the records to an object container file, using the given compression codec.
+    pub fn container_file_with_codec(schema: &'a Schema, output: W, codec: Codec) -> Self {
+        Self {
+            encoder: Encoder::ContainerFile(Writer::with_codec(schema, output, codec)),
+            series: FlatMeasurementSeries::new(),
+            context: None,
+        }
+    }
+
+    /// Write the records one after the other using the single-object encoding.
+    pub fn single_object(schema: &'a Schema, output: W) -> Self {
+        let mut header = SINGLE_OBJECT_MARKER.to_vec();
+        header.extend_from_slice(&schema.fingerprint::<Rabin>().bytes);
+        Self {
+            encoder: Encoder::SingleObject {
+                schema,
+                header,
+                output,
+            },
+            series: FlatMeasurementSeries::new(),
+            context: None,
+        }
+    }
+
+    /// Write the measurements gathered since the previous flush as a record.
+    ///
+    /// Nothing is written if no measurements nor timestamp have been given.
+    pub fn flush(&mut self) -> Result<(), AvroSinkError> {
+        let series = std::mem::take(&mut self.series);
+        let context = self.context.take();
+        if series.is_empty() && series.timestamp.is_none() {
+            return Ok(());
+        }
+
+        let record = measurement_record(series, context);
+        match &mut self.encoder {
+            Encoder::SingleObject {
+                schema,
+                header,
+                output,
+            } => {
+                let datum = to_avro_datum(schema, record)?;
+                output.write_all(header)?;
+                output.write_all(&datum)?;
+                output.flush()?;
+            }
+            Encoder::ContainerFile(writer) => {
+                writer.append(record)?;
+                writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the underlying output, once all the records written.
+    pub fn into_inner(self) -> Result<W, AvroSinkError> {
+        match self.encoder {
+            Encoder::SingleObject { output, .. } => Ok(output),
+            Encoder::ContainerFile(writer) => Ok(writer.into_inner()?),
+        }
+    }
+}
+
+fn measurement_record(series: FlatMeasurementSeries, context: Option<MeasurementContext>) -> Value {
+    let time = match series.timestamp {
+        Some(timestamp) => {
+            let micros =
+                timestamp.timestamp() * 1_000_000 + timestamp.timestamp_subsec_micros() as i64;
+            Value::Union(Box::new(Value::TimestampMicros(micros)))
+        }
+        None => Value::Union(Box::new(Value::Null)),
+    };
+
+    let mut measurements = HashMap::new();
+    let mut groups: HashMap<String, HashMap<String, Value>> = HashMap::new();
+    for measurement in series.measurements {
+        let value = Value::Double(measurement.value);
+        match measurement.group {
+            Some(group) => {
+                groups
+                    .entry(group)
+                    .or_default()
+                    .insert(measurement.name, value);
+            }
+            None => {
+                measurements.insert(measurement.name, value);
+            }
+        }
+    }
+    let groups = groups
+        .into_iter()
+        .map(|(group, measurements)| (group, Value::Map(measurements)))
+        .collect();
+
+    let (trace_id, span_id) = match context {
+        Some(context) => (
+            Value::Union(Box::new(Value::String(context.trace_id_hex()))),
+            Value::Union(Box::new(Value::String(context.span_id_hex()))),
+        ),
+        None => (
+            Value::Union(Box::new(Value::Null)),
+            Value::Union(Box::new(Value::Null)),
+        ),
+    };
+
+    Value::Record(vec![
+        ("time".to_string(), time),
+        ("measurements".to_string(), Value::Map(measurements)),
+        ("groups".to_string(), Value::Map(groups)),
+        ("trace_id".to_string(), trace_id),
+        ("span_id".to_string(), span_id),
+    ])
+}
+
+impl<'a, W: Write> GroupedMeasurementVisitor for AvroSinkVisitor<'a, W> {
+    type Error = MeasurementStreamError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.series.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.series.measurement(name, value)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.series.start_group(group)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.series.end_group()
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.context = Some(context);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use avro_rs::{from_avro_datum, Reader};
+    use chrono::TimeZone;
+
+    fn visit(visitor: &mut AvroSinkVisitor<Vec<u8>>) -> anyhow::Result<()> {
+        visitor.timestamp(
+            FixedOffset::east(2 * 3600)
+                .ymd(2021, 4, 30)
+                .and_hms_micro(17, 0, 0, 500),
+        )?;
+        visitor.measurement("temperature", 25.5)?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.measurement("longi", 2200.4)?;
+        visitor.end_group()?;
+        visitor.flush()?;
+
+        visitor.measurement("temperature", 26.0)?;
+        visitor.flush()?;
+        Ok(())
+    }
+
+    fn map(entries: Vec<(&str, Value)>) -> Value {
+        Value::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    fn expected_records() -> Vec<Value> {
+        let record = |time: Value, measurements: Value, groups: Value| {
+            Value::Record(vec![
+                ("time".to_string(), time),
+                ("measurements".to_string(), measurements),
+                ("groups".to_string(), groups),
+                ("trace_id".to_string(), Value::Union(Box::new(Value::Null))),
+                ("span_id".to_string(), Value::Union(Box::new(Value::Null))),
+            ])
+        };
+        vec![
+            record(
+                Value::Union(Box::new(Value::TimestampMicros(1_619_794_800_000_500))),
+                map(vec![("temperature", Value::Double(25.5))]),
+                map(vec![(
+                    "location",
+                    map(vec![
+                        ("alti", Value::Double(2100.4)),
+                        ("longi", Value::Double(2200.4)),
+                    ]),
+                )]),
+            ),
+            record(
+                Value::Union(Box::new(Value::Null)),
+                map(vec![("temperature", Value::Double(26.0))]),
+                map(vec![]),
+            ),
+        ]
+    }
+
+    fn read_container_file(bytes: &[u8]) -> anyhow::Result<Vec<Value>> {
+        let mut records = Vec::new();
+        for record in Reader::new(bytes)? {
+            records.push(record?);
+        }
+        Ok(records)
+    }
+
+    fn read_single_objects(schema: &Schema, mut bytes: &[u8]) -> anyhow::Result<Vec<Value>> {
+        let fingerprint = schema.fingerprint::<Rabin>().bytes;
+        let mut records = Vec::new();
+        while !bytes.is_empty() {
+            assert_eq!(bytes[..2], SINGLE_OBJECT_MARKER);
+            assert_eq!(bytes[2..10], fingerprint[..]);
+            bytes = &bytes[10..];
+            records.push(from_avro_datum(schema, &mut bytes, None)?);
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn records_are_written_to_a_compressed_container_file() -> anyhow::Result<()> {
+        let schema = measurement_schema();
+        let mut visitor = AvroSinkVisitor::container_file(&schema, Vec::new());
+        visit(&mut visitor)?;
+        let bytes = visitor.into_inner()?;
+
+        assert!(bytes.windows(6).any(|window| window == b"snappy"));
+        assert_eq!(read_container_file(&bytes)?, expected_records());
+        Ok(())
+    }
+
+    #[test]
+    fn records_are_written_to_an_uncompressed_container_file() -> anyhow::Result<()> {
+        let schema = measurement_schema();
+        let mut visitor =
+            AvroSinkVisitor::container_file_with_codec(&schema, Vec::new(), Codec::Null);
+        visit(&mut visitor)?;
+
+        assert_eq!(
+            read_container_file(&visitor.into_inner()?)?,
+            expected_records()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn records_are_written_using_the_single_object_encoding() -> anyhow::Result<()> {
+        let schema = measurement_schema();
+        let mut visitor = AvroSinkVisitor::single_object(&schema, Vec::new());
+        visit(&mut visitor)?;
+
+        assert_eq!(
+            read_single_objects(&schema, &visitor.into_inner()?)?,
+            expected_records()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn the_trace_context_is_written_along_the_record_it_has_been_set_for() -> anyhow::Result<()> {
+        let schema = measurement_schema();
+        let mut visitor = AvroSinkVisitor::single_object(&schema, Vec::new());
+        visitor.set_context(MeasurementContext::new([0x4b; 16], [0xf0; 8]))?;
+        visitor.measurement("temperature", 25.5)?;
+        visitor.flush()?;
+        visitor.measurement("temperature", 26.0)?;
+        visitor.flush()?;
+
+        let records = read_single_objects(&schema, &visitor.into_inner()?)?;
+        let field = |record: &Value, name: &str| match record {
+            Value::Record(fields) => fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value.clone()),
+            _ => None,
+        };
+        assert_eq!(
+            field(&records[0], "trace_id"),
+            Some(Value::Union(Box::new(Value::String(
+                "4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b".into()
+            ))))
+        );
+        assert_eq!(
+            field(&records[0], "span_id"),
+            Some(Value::Union(Box::new(Value::String(
+                "f0f0f0f0f0f0f0f0".into()
+            ))))
+        );
+        assert_eq!(
+            field(&records[1], "trace_id"),
+            Some(Value::Union(Box::new(Value::Null)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn nothing_is_written_when_there_is_no_measurements() -> anyhow::Result<()> {
+        let schema = measurement_schema();
+        let mut visitor = AvroSinkVisitor::single_object(&schema, Vec::new());
+
+        visitor.flush()?;
+        assert!(visitor.into_inner()?.is_empty());
+        Ok(())
+    }
+}
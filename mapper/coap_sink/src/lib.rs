@@ -0,0 +1,18 @@
+//! A sink sending thin-edge JSON measurements to a CoAP endpoint, over UDP.
+//!
+//! ```no_run
+//! use coap_sink::CoapSinkVisitor;
+//! use thin_edge_json::measurement::GroupedMeasurementVisitor;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), anyhow::Error> {
+//! let mut visitor = CoapSinkVisitor::connect("coap://gateway.local/measurements").await?;
+//!
+//! visitor.measurement("temperature", 25.5)?;
+//! visitor.flush().await?; // POSTs `{"temperature":25.5}` as a confirmable message
+//! # Ok(()) }
+//! ```
+
+mod visitor;
+
+pub use visitor::{CoapMessageType, CoapSinkError, CoapSinkVisitor};
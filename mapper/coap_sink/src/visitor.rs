@@ -0,0 +1,490 @@
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use coap_lite::{
+    CoapRequest, ContentFormat, MessageClass, MessageType, Packet, RequestType, ResponseType,
+};
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thin_edge_json::context::MeasurementContext;
+use thin_edge_json::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use thin_edge_json::serialize::{ThinEdgeJsonSerializationError, ThinEdgeJsonSerializer};
+use tokio::net::UdpSocket;
+use tokio::time::{timeout_at, Instant};
+use url::Url;
+
+const COAP_SCHEME: &str = "coap";
+const DEFAULT_PORT: u16 = 5683;
+
+/// The default transmission parameters of RFC 7252, section 4.8
+const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_secs(2);
+const DEFAULT_MAX_RETRANSMIT: u32 = 4;
+
+/// Large enough for any acknowledgement, which has no payload
+const MAX_DATAGRAM_SIZE: usize = 1152;
+
+#[derive(thiserror::Error, Debug)]
+pub enum CoapSinkError {
+    #[error("Invalid CoAP URI {uri:?}: {reason}")]
+    InvalidUri { uri: String, reason: String },
+
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+
+    #[error("Invalid CoAP message: {0}")]
+    MessageError(String),
+
+    #[error("No acknowledgement received after {attempts} attempts")]
+    Timeout { attempts: u32 },
+
+    #[error("The measurements have been reset by the server")]
+    Reset,
+
+    #[error("The server failed to process the measurements: {code}")]
+    Rejected { code: String },
+
+    #[error(transparent)]
+    SerializationError(#[from] ThinEdgeJsonSerializationError),
+}
+
+/// The CoAP message type used to send the measurements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoapMessageType {
+    /// The server acknowledges each message, which is retransmitted till acknowledged.
+    Confirmable,
+
+    /// The messages are sent once, with no acknowledgement.
+    NonConfirmable,
+}
+
+/// A visitor that POSTs the measurements as thin-edge JSON to a CoAP endpoint.
+///
+/// The measurements are gathered into a thin-edge JSON payload which is sent on `flush()`.
+///
+/// * A confirmable message is retransmitted if not acknowledged within the ACK timeout,
+///   the timeout being doubled for each retransmission, as specified by RFC 7252.
+///   The flush fails if no acknowledgement is received after the last retransmission.
+/// * A non-confirmable message is sent once and the flush returns as soon as it is sent.
+///
+/// The payload of a message must fit a single UDP datagram: block-wise transfers are not supported.
+pub struct CoapSinkVisitor {
+    socket: UdpSocket,
+    path: String,
+    message_type: CoapMessageType,
+    ack_timeout: Duration,
+    max_retransmit: u32,
+    message_id: u16,
+    serializer: ThinEdgeJsonSerializer,
+    is_empty: bool,
+}
+
+impl CoapSinkVisitor {
+    /// Create a visitor sending the measurements to a `coap://host[:port]/path` URI.
+    ///
+    /// The port defaults to 5683 and the messages are confirmable by default.
+    pub async fn connect(uri: &str) -> Result<Self, CoapSinkError> {
+        let invalid_uri = |reason: &str| CoapSinkError::InvalidUri {
+            uri: uri.to_string(),
+            reason: reason.to_string(),
+        };
+        let url = Url::parse(uri).map_err(|err| invalid_uri(&err.to_string()))?;
+        if url.scheme() != COAP_SCHEME {
+            return Err(invalid_uri("the scheme must be coap"));
+        }
+        let server = url
+            .socket_addrs(|| Some(DEFAULT_PORT))?
+            .into_iter()
+            .next()
+            .ok_or_else(|| invalid_uri("the host cannot be resolved"))?;
+
+        let local: SocketAddr = if server.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(server).await?;
+
+        Ok(Self {
+            socket,
+            path: url.path().trim_start_matches('/').to_string(),
+            message_type: CoapMessageType::Confirmable,
+            ack_timeout: DEFAULT_ACK_TIMEOUT,
+            max_retransmit: DEFAULT_MAX_RETRANSMIT,
+            message_id: initial_message_id(),
+            serializer: ThinEdgeJsonSerializer::new(),
+            is_empty: true,
+        })
+    }
+
+    /// Set the type of the messages, confirmable or not.
+    pub fn with_message_type(self, message_type: CoapMessageType) -> Self {
+        Self {
+            message_type,
+            ..self
+        }
+    }
+
+    /// Set the delay to wait for the acknowledgement of a confirmable message before retransmitting it,
+    /// the delay being doubled for each subsequent retransmission.
+    pub fn with_ack_timeout(self, ack_timeout: Duration) -> Self {
+        Self {
+            ack_timeout,
+            ..self
+        }
+    }
+
+    /// Set the maximum number of retransmissions of a confirmable message.
+    pub fn with_max_retransmit(self, max_retransmit: u32) -> Self {
+        Self {
+            max_retransmit,
+            ..self
+        }
+    }
+
+    /// Post the measurements gathered since the previous flush.
+    ///
+    /// Nothing is sent if no measurements have been gathered.
+    pub async fn flush(&mut self) -> Result<(), CoapSinkError> {
+        let mut serializer = std::mem::take(&mut self.serializer);
+        let is_empty = std::mem::replace(&mut self.is_empty, true);
+        let payload = serializer.into_string()?;
+        if is_empty {
+            return Ok(());
+        }
+
+        self.message_id = self.message_id.wrapping_add(1);
+        let message = self.request(payload.into_bytes())?;
+        match self.message_type {
+            CoapMessageType::NonConfirmable => {
+                self.socket.send(&message).await?;
+                Ok(())
+            }
+            CoapMessageType::Confirmable => self.send_confirmable(&message).await,
+        }
+    }
+
+    fn request(&self, payload: Vec<u8>) -> Result<Vec<u8>, CoapSinkError> {
+        let mut request: CoapRequest<SocketAddr> = CoapRequest::new();
+        request.set_method(RequestType::Post);
+        request.set_path(&self.path);
+
+        let message_type = match self.message_type {
+            CoapMessageType::Confirmable => MessageType::Confirmable,
+            CoapMessageType::NonConfirmable => MessageType::NonConfirmable,
+        };
+        request.message.header.set_type(message_type);
+        request.message.header.message_id = self.message_id;
+        request
+            .message
+            .set_token(self.message_id.to_be_bytes().to_vec());
+        request
+            .message
+            .set_content_format(ContentFormat::ApplicationJSON);
+        request.message.payload = payload;
+
+        request
+            .message
+            .to_bytes()
+            .map_err(|err| CoapSinkError::MessageError(err.to_string()))
+    }
+
+    async fn send_confirmable(&self, message: &[u8]) -> Result<(), CoapSinkError> {
+        let mut timeout = self.ack_timeout;
+        for _ in 0..=self.max_retransmit {
+            self.socket.send(message).await?;
+            if let Some(ack) = self.receive_ack(Instant::now() + timeout).await? {
+                return check_ack(&ack);
+            }
+            timeout *= 2;
+        }
+
+        Err(CoapSinkError::Timeout {
+            attempts: self.max_retransmit + 1,
+        })
+    }
+
+    /// Wait for the acknowledgement of the current message till the deadline,
+    /// ignoring any other datagram, as the late acknowledgements of previous messages.
+    async fn receive_ack(&self, deadline: Instant) -> Result<Option<Packet>, CoapSinkError> {
+        let mut buffer = [0u8; MAX_DATAGRAM_SIZE];
+        loop {
+            let size = match timeout_at(deadline, self.socket.recv(&mut buffer)).await {
+                Ok(received) => received?,
+                Err(_elapsed) => return Ok(None),
+            };
+            let packet = match Packet::from_bytes(&buffer[..size]) {
+                Ok(packet) => packet,
+                Err(_) => continue,
+            };
+            if packet.header.message_id != self.message_id {
+                continue;
+            }
+            match packet.header.get_type() {
+                MessageType::Acknowledgement | MessageType::Reset => return Ok(Some(packet)),
+                _ => continue,
+            }
+        }
+    }
+}
+
+/// An empty acknowledgement announces a separate response:
+/// the measurements have been received, hence the flush is complete.
+fn check_ack(ack: &Packet) -> Result<(), CoapSinkError> {
+    if ack.header.get_type() == MessageType::Reset {
+        return Err(CoapSinkError::Reset);
+    }
+    match ack.header.code {
+        MessageClass::Empty => Ok(()),
+        MessageClass::Response(response) if is_success(response) => Ok(()),
+        _ => Err(CoapSinkError::Rejected {
+            code: ack.header.get_code(),
+        }),
+    }
+}
+
+fn is_success(response: ResponseType) -> bool {
+    matches!(
+        response,
+        ResponseType::Created
+            | ResponseType::Deleted
+            | ResponseType::Valid
+            | ResponseType::Changed
+            | ResponseType::Content
+            | ResponseType::Continue
+    )
+}
+
+/// RFC 7252 recommends a randomized initial message id,
+/// so a restarted sink doesn't reuse the ids of its previous messages.
+fn initial_message_id() -> u16 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.subsec_nanos() as u16)
+        .unwrap_or_default()
+}
+
+impl GroupedMeasurementVisitor for CoapSinkVisitor {
+    type Error = ThinEdgeJsonSerializationError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.serializer.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.measurement(name, value)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.serializer.start_group(group)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.serializer.end_group()
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.measurement_with_unit(name, value, unit)
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.serializer.start_group_with_timestamp(group, timestamp)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.nullable_measurement(name, value)
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.complex_measurement(name, real, imaginary)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.annotated_measurement(name, value, quality)
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.serializer.set_context(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use tokio::sync::mpsc;
+
+    /// How a stub server replies to each request it receives, in order
+    #[derive(Clone, Copy)]
+    enum Reply {
+        Ignore,
+        Ack(ResponseType),
+    }
+
+    /// A stub CoAP server, forwarding the requests it receives
+    /// as (message type, message id, path, payload)
+    async fn stub_server(
+        replies: Vec<Reply>,
+    ) -> anyhow::Result<(
+        String,
+        mpsc::UnboundedReceiver<(MessageType, u16, String, String)>,
+    )> {
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let uri = format!("coap://{}/measurements", socket.local_addr()?);
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let mut buffer = [0u8; MAX_DATAGRAM_SIZE];
+            let mut replies = replies.into_iter();
+            while let Ok((size, client)) = socket.recv_from(&mut buffer).await {
+                let packet = Packet::from_bytes(&buffer[..size]).unwrap();
+                let message_type = packet.header.get_type();
+                let message_id = packet.header.message_id;
+                let token = packet.get_token().to_vec();
+                let payload = String::from_utf8(packet.payload.clone()).unwrap();
+                let request: CoapRequest<SocketAddr> = CoapRequest::from_packet(packet, client);
+                let _ = sender.send((message_type, message_id, request.get_path(), payload));
+
+                if let Some(Reply::Ack(response)) = replies.next() {
+                    let mut ack = Packet::new();
+                    ack.header.set_type(MessageType::Acknowledgement);
+                    ack.header.message_id = message_id;
+                    ack.header.code = MessageClass::Response(response);
+                    ack.set_token(token);
+                    let _ = socket.send_to(&ack.to_bytes().unwrap(), client).await;
+                }
+            }
+        });
+
+        Ok((uri, receiver))
+    }
+
+    #[tokio::test]
+    async fn confirmable_messages_are_acknowledged() -> anyhow::Result<()> {
+        let (uri, mut requests) = stub_server(vec![Reply::Ack(ResponseType::Created)]).await?;
+        let mut visitor = CoapSinkVisitor::connect(&uri).await?;
+
+        visitor.measurement("temperature", 25.5)?;
+        visitor.flush().await?;
+
+        let (message_type, _, path, payload) = requests.recv().await.unwrap();
+        assert_eq!(message_type, MessageType::Confirmable);
+        assert_eq!(path, "measurements");
+        assert_eq!(payload, r#"{"temperature":25.5}"#);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unacknowledged_messages_are_retransmitted() -> anyhow::Result<()> {
+        let (uri, mut requests) = stub_server(vec![
+            Reply::Ignore,
+            Reply::Ignore,
+            Reply::Ack(ResponseType::Changed),
+        ])
+        .await?;
+        let mut visitor = CoapSinkVisitor::connect(&uri)
+            .await?
+            .with_ack_timeout(Duration::from_millis(20));
+
+        visitor.measurement("temperature", 25.5)?;
+        visitor.flush().await?;
+
+        let (_, first_id, _, first_payload) = requests.recv().await.unwrap();
+        for _ in 0..2 {
+            let (_, message_id, _, payload) = requests.recv().await.unwrap();
+            assert_eq!(message_id, first_id);
+            assert_eq!(payload, first_payload);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn the_flush_fails_after_the_last_retransmission() -> anyhow::Result<()> {
+        let (uri, _requests) = stub_server(vec![]).await?;
+        let mut visitor = CoapSinkVisitor::connect(&uri)
+            .await?
+            .with_ack_timeout(Duration::from_millis(10))
+            .with_max_retransmit(2);
+
+        visitor.measurement("temperature", 25.5)?;
+        assert_matches!(
+            visitor.flush().await,
+            Err(CoapSinkError::Timeout { attempts: 3 })
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn non_confirmable_messages_are_sent_once() -> anyhow::Result<()> {
+        let (uri, mut requests) = stub_server(vec![]).await?;
+        let mut visitor = CoapSinkVisitor::connect(&uri)
+            .await?
+            .with_message_type(CoapMessageType::NonConfirmable);
+
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.end_group()?;
+        visitor.flush().await?;
+
+        let (message_type, _, _, payload) = requests.recv().await.unwrap();
+        assert_eq!(message_type, MessageType::NonConfirmable);
+        assert_eq!(payload, r#"{"location":{"alti":2100.4}}"#);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejected_messages_are_reported() -> anyhow::Result<()> {
+        let (uri, _requests) = stub_server(vec![Reply::Ack(ResponseType::BadRequest)]).await?;
+        let mut visitor = CoapSinkVisitor::connect(&uri).await?;
+
+        visitor.measurement("temperature", 25.5)?;
+        assert_matches!(
+            visitor.flush().await,
+            Err(CoapSinkError::Rejected { code }) if code == "4.00"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn nothing_is_sent_without_measurements() -> anyhow::Result<()> {
+        let (uri, mut requests) = stub_server(vec![]).await?;
+        let mut visitor = CoapSinkVisitor::connect(&uri).await?;
+
+        visitor.flush().await?;
+
+        let received = tokio::time::timeout(Duration::from_millis(50), requests.recv()).await;
+        assert!(received.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn only_coap_uris_are_supported() {
+        assert_matches!(
+            CoapSinkVisitor::connect("http://localhost/measurements").await,
+            Err(CoapSinkError::InvalidUri { .. })
+        );
+    }
+}
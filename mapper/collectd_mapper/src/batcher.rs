@@ -15,6 +15,10 @@ use tracing::{error, log::warn};
 
 use crate::collectd::CollectdMessage;
 use crate::error::*;
+use crate::retry::RetryPublishQueue;
+
+/// The delay between two attempts to publish the messages that failed to be published
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
 
 #[derive(Debug)]
 pub struct MessageBatch {
@@ -170,6 +174,7 @@ pub struct MessageBatchPublisher {
     receiver: UnboundedReceiver<MeasurementGrouper>,
     mqtt_client: Arc<dyn MqttClient>,
     target_topic: Topic,
+    retry_queue: RetryPublishQueue,
 }
 
 impl MessageBatchPublisher {
@@ -177,18 +182,40 @@ impl MessageBatchPublisher {
         receiver: UnboundedReceiver<MeasurementGrouper>,
         mqtt_client: Arc<dyn MqttClient>,
         target_topic: Topic,
+        retry_queue: RetryPublishQueue,
     ) -> Self {
         Self {
             receiver,
             mqtt_client,
             target_topic,
+            retry_queue,
         }
     }
 
     pub async fn run(&mut self) {
-        while let Some(message_grouper) = self.receiver.recv().await {
-            if let Err(err) = self.publish_as_mqtt_message(message_grouper).await {
-                error!("Error publishing the measurement batch: {}", err);
+        // Created once, so the retries are not postponed by each incoming batch
+        let mut retry_interval = time::interval(RETRY_INTERVAL);
+        loop {
+            select! {
+                maybe_message_grouper = self.receiver.recv() => {
+                    match maybe_message_grouper {
+                        Some(message_grouper) => {
+                            if let Err(err) = self.publish_as_mqtt_message(message_grouper).await {
+                                error!("Error publishing the measurement batch: {}", err);
+                            }
+                        }
+                        None => break,
+                    }
+                }
+                _ = retry_interval.tick(), if self.retry_queue.pending_count() > 0 => {
+                    if let Err(err) = self.retry_queue.retry(self.mqtt_client.as_ref()).await {
+                        warn!(
+                            "Failed to publish again {} measurement batches: {}",
+                            self.retry_queue.pending_count(),
+                            err
+                        );
+                    }
+                }
             }
         }
 
@@ -204,7 +231,9 @@ impl MessageBatchPublisher {
 
         let tedge_message = Message::new(&self.target_topic, tedge_json_serializer.bytes()?);
 
-        self.mqtt_client.publish(tedge_message).await?;
+        self.retry_queue
+            .publish(self.mqtt_client.as_ref(), tedge_message)
+            .await?;
 
         Ok(())
     }
@@ -277,15 +306,19 @@ mod tests {
         let (_sender, receiver) = tokio::sync::mpsc::unbounded_channel::<MeasurementGrouper>();
 
         let mut mqtt_client = MockMqttClient::new();
-        mqtt_client.expect_publish().times(1).returning(|message| {
-            assert_eq!(message.topic.name, "tedge/measurements"); // The test assertion happens here
-            Ok(123)
-        });
+        mqtt_client
+            .expect_publish_and_wait_for_ack()
+            .times(1)
+            .returning(|message| {
+                assert_eq!(message.topic.name, "tedge/measurements"); // The test assertion happens here
+                Ok(())
+            });
 
         let mut publisher = MessageBatchPublisher::new(
             receiver,
             Arc::new(mqtt_client),
             Topic::new("tedge/measurements")?,
+            RetryPublishQueue::new(16, Arc::new(WallClock)),
         );
         publisher.publish_as_mqtt_message(message_grouper).await?;
 
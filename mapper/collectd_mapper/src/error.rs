@@ -1,5 +1,6 @@
 use crate::collectd::CollectdError;
 use mqtt_client::MqttClientError;
+use std::time::Duration;
 use thin_edge_json::{
     group::{MeasurementGrouper, MeasurementGrouperError},
     serialize::ThinEdgeJsonSerializationError,
@@ -23,6 +24,9 @@ pub enum DeviceMonitorError {
     #[error(transparent)]
     BatchingError(#[from] SendError<MeasurementGrouper>),
 
+    #[error("The broker has not acknowledged the message within {0:?}")]
+    PublishNotAcknowledged(Duration),
+
     #[error("Home directory is not found.")]
     HomeDirNotFound,
 }
@@ -2,11 +2,13 @@ mod batcher;
 mod collectd;
 mod error;
 mod monitor;
+mod retry;
 
 use tracing::{debug_span, info, Instrument};
 
 use crate::error::*;
 use crate::monitor::{DeviceMonitor, DeviceMonitorConfig};
+use mqtt_client::MqttCredentials;
 use std::path::PathBuf;
 use tedge_config::*;
 
@@ -27,7 +29,13 @@ async fn main() -> anyhow::Result<()> {
 
     info!("{} starting!", APP_NAME);
 
-    let device_monitor_config = DeviceMonitorConfig::default().with_port(mqtt_port()?);
+    let tedge_config = config_repository()?.load()?;
+    let device_monitor_config =
+        DeviceMonitorConfig::default().with_port(tedge_config.query(MqttPortSetting)?.into());
+    let device_monitor_config = match mqtt_credentials(&tedge_config)? {
+        Some(credentials) => device_monitor_config.with_credentials(credentials),
+        None => device_monitor_config,
+    };
 
     let device_monitor = DeviceMonitor::new(device_monitor_config);
     device_monitor
@@ -38,10 +46,13 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
-fn mqtt_port() -> anyhow::Result<u16> {
-    let config_repository = config_repository()?;
-    let tedge_config = config_repository.load()?;
-    Ok(tedge_config.query(MqttPortSetting)?.into())
+/// The credentials set in the configuration, if both the username and the password are set
+fn mqtt_credentials(tedge_config: &TEdgeConfig) -> anyhow::Result<Option<MqttCredentials>> {
+    let username = tedge_config.query_optional(MqttUsernameSetting)?;
+    let password = tedge_config.query_optional(MqttPasswordSetting)?;
+    Ok(username
+        .zip(password)
+        .map(|(username, password)| MqttCredentials::new(username, password.expose_secret())))
 }
 
 fn config_repository() -> anyhow::Result<TEdgeConfigRepository> {
@@ -1,5 +1,5 @@
 use clock::WallClock;
-use mqtt_client::{Client, MqttClient};
+use mqtt_client::{Client, MqttClient, MqttCredentials};
 use std::sync::Arc;
 use thin_edge_json::group::MeasurementGrouper;
 use tracing::{instrument, log::error};
@@ -7,12 +7,14 @@ use tracing::{instrument, log::error};
 use crate::{
     batcher::{MessageBatchPublisher, MessageBatcher},
     error::DeviceMonitorError,
+    retry::RetryPublishQueue,
 };
 
 const DEFAULT_HOST: &str = "localhost";
 const DEFAULT_PORT: u16 = 1883;
 const DEFAULT_MQTT_CLIENT_ID: &str = "collectd-mapper";
 const DEFAULT_BATCHING_WINDOW: u64 = 200;
+const DEFAULT_RETRY_QUEUE_CAPACITY: usize = 1024;
 const DEFAULT_MQTT_SOURCE_TOPIC: &str = "collectd/#";
 const DEFAULT_MQTT_TARGET_TOPIC: &str = "tedge/measurements";
 
@@ -27,6 +29,7 @@ pub struct DeviceMonitorConfig {
     mqtt_source_topic: &'static str,
     mqtt_target_topic: &'static str,
     batching_window: u64,
+    credentials: Option<MqttCredentials>,
 }
 
 impl Default for DeviceMonitorConfig {
@@ -38,6 +41,7 @@ impl Default for DeviceMonitorConfig {
             mqtt_source_topic: DEFAULT_MQTT_SOURCE_TOPIC,
             mqtt_target_topic: DEFAULT_MQTT_TARGET_TOPIC,
             batching_window: DEFAULT_BATCHING_WINDOW,
+            credentials: None,
         }
     }
 }
@@ -46,6 +50,14 @@ impl DeviceMonitorConfig {
     pub fn with_port(self, port: u16) -> Self {
         Self { port, ..self }
     }
+
+    /// Authenticate with these credentials rather than with those given by the environment
+    pub fn with_credentials(self, credentials: MqttCredentials) -> Self {
+        Self {
+            credentials: Some(credentials),
+            ..self
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -67,6 +79,11 @@ impl DeviceMonitor {
             self.device_monitor_config.port,
         )
         .queue_capacity(1024);
+        let config = match self.device_monitor_config.credentials.clone() {
+            Some(credentials) => config.with_credentials(credentials),
+            None => config,
+        };
+        let config = config.with_credentials_from_env();
         let mqtt_client: Arc<dyn MqttClient> =
             Arc::new(Client::connect(self.device_monitor_config.mqtt_client_id, &config).await?);
 
@@ -90,6 +107,7 @@ impl DeviceMonitor {
             receiver,
             mqtt_client.clone(),
             Topic::new(self.device_monitor_config.mqtt_target_topic)?,
+            RetryPublishQueue::new(DEFAULT_RETRY_QUEUE_CAPACITY, Arc::new(WallClock)),
         );
         let join_handle2 = tokio::task::spawn(async move {
             message_batch_consumer.run().await;
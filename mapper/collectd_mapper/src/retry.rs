@@ -0,0 +1,272 @@
+use clock::{Clock, Timestamp};
+use mqtt_client::{Message, MqttClient};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time;
+use tracing::{info, log::warn};
+
+use crate::error::DeviceMonitorError;
+
+/// The delay after which a message not acknowledged by the broker is considered as not published
+const ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct PendingPublish {
+    message: Message,
+    failed_at: Timestamp,
+}
+
+/// The messages that failed to be published, kept to be published again
+/// once the broker is reachable.
+///
+/// A message is published only once acknowledged by the broker:
+/// handing a message over to the MQTT client succeeds even while the broker is down.
+/// A message not acknowledged within the ack timeout is queued,
+/// and might then be received twice by the broker, as with any QoS 1 message.
+///
+/// The queue is bounded: when full, the oldest message is dropped to make room for the newest.
+/// The messages are published again in the order they have been queued.
+pub struct RetryPublishQueue {
+    capacity: usize,
+    pending: VecDeque<PendingPublish>,
+    clock: Arc<dyn Clock>,
+}
+
+impl RetryPublishQueue {
+    pub fn new(capacity: usize, clock: Arc<dyn Clock>) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            capacity,
+            pending: VecDeque::with_capacity(capacity),
+            clock,
+        }
+    }
+
+    /// Keep a message whose publication has failed
+    pub fn push(&mut self, message: Message) {
+        if self.pending.len() >= self.capacity {
+            if let Some(dropped) = self.pending.pop_front() {
+                warn!(
+                    "Retry queue full: dropping a message to {}",
+                    dropped.message.topic.name
+                );
+            }
+        }
+        self.pending.push_back(PendingPublish {
+            message,
+            failed_at: self.clock.now(),
+        });
+    }
+
+    /// The number of messages waiting to be published again
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// The time elapsed since the failure of the oldest message still waiting, if any
+    pub fn oldest_pending_age(&self) -> Option<Duration> {
+        self.pending
+            .front()
+            .map(|pending| elapsed_since(self.clock.now(), pending.failed_at))
+    }
+
+    /// Publish again the pending messages, in order, stopping on the first failure.
+    ///
+    /// Returns the number of messages published.
+    pub async fn retry(
+        &mut self,
+        mqtt_client: &dyn MqttClient,
+    ) -> Result<usize, DeviceMonitorError> {
+        let mut published = 0;
+        while let Some(pending) = self.pending.front() {
+            publish_acknowledged(mqtt_client, pending.message.clone()).await?;
+
+            let lag = elapsed_since(self.clock.now(), pending.failed_at);
+            info!(
+                "Published a message to {} after a retry lag of {:?}",
+                pending.message.topic.name, lag
+            );
+            self.pending.pop_front();
+            published += 1;
+        }
+        Ok(published)
+    }
+
+    /// Publish a message, after any pending message to preserve the order,
+    /// queuing the message on failure.
+    pub async fn publish(
+        &mut self,
+        mqtt_client: &dyn MqttClient,
+        message: Message,
+    ) -> Result<(), DeviceMonitorError> {
+        if let Err(err) = self.retry(mqtt_client).await {
+            self.push(message);
+            return Err(err);
+        }
+        if let Err(err) = publish_acknowledged(mqtt_client, message.clone()).await {
+            self.push(message);
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+/// Publish a message, failing if not acknowledged by the broker within the ack timeout
+async fn publish_acknowledged(
+    mqtt_client: &dyn MqttClient,
+    message: Message,
+) -> Result<(), DeviceMonitorError> {
+    match time::timeout(ACK_TIMEOUT, mqtt_client.publish_and_wait_for_ack(message)).await {
+        Ok(acknowledged) => acknowledged.map_err(Into::into),
+        Err(_elapsed) => Err(DeviceMonitorError::PublishNotAcknowledged(ACK_TIMEOUT)),
+    }
+}
+
+fn elapsed_since(now: Timestamp, time: Timestamp) -> Duration {
+    (now - time).to_std().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::WallClock;
+    use mqtt_client::{MockMqttClient, MqttClientError, Topic};
+    use std::sync::Mutex;
+    use tokio::time::{self, Instant};
+
+    /// Any error will do to simulate a broker that cannot be reached
+    fn broker_unavailable() -> MqttClientError {
+        MqttClientError::JoinError
+    }
+
+    fn message(payload: &str) -> Message {
+        let topic = Topic::new("tedge/measurements").unwrap();
+        Message::new(&topic, payload)
+    }
+
+    /// A client whose broker is unavailable until the given time, recording the delivered payloads
+    fn client_available_from(
+        available_from: Instant,
+        delivered: Arc<Mutex<Vec<String>>>,
+    ) -> MockMqttClient {
+        let mut mqtt_client = MockMqttClient::new();
+        mqtt_client
+            .expect_publish_and_wait_for_ack()
+            .returning(move |message| {
+                if Instant::now() < available_from {
+                    return Err(broker_unavailable());
+                }
+                let payload = message.payload_str()?.to_string();
+                delivered.lock().unwrap().push(payload);
+                Ok(())
+            });
+        mqtt_client
+    }
+
+    #[tokio::test]
+    async fn queued_messages_are_delivered_once_the_broker_is_back() -> anyhow::Result<()> {
+        time::pause();
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let mqtt_client =
+            client_available_from(Instant::now() + Duration::from_secs(3), delivered.clone());
+        let mut queue = RetryPublishQueue::new(16, Arc::new(WallClock));
+
+        // A message per second, the first three being published while the broker is unavailable
+        for i in 0..5 {
+            let result = queue
+                .publish(&mqtt_client, message(&format!("{{\"count\":{}}}", i)))
+                .await;
+            assert_eq!(result.is_ok(), i >= 3);
+            time::sleep(Duration::from_secs(1)).await;
+        }
+
+        assert_eq!(queue.pending_count(), 0);
+        assert_eq!(
+            *delivered.lock().unwrap(),
+            vec![
+                r#"{"count":0}"#,
+                r#"{"count":1}"#,
+                r#"{"count":2}"#,
+                r#"{"count":3}"#,
+                r#"{"count":4}"#
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pending_messages_are_retried_in_order() -> anyhow::Result<()> {
+        time::pause();
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let mqtt_client =
+            client_available_from(Instant::now() + Duration::from_secs(10), delivered.clone());
+        let mut queue = RetryPublishQueue::new(16, Arc::new(WallClock));
+
+        queue.push(message("first"));
+        queue.push(message("second"));
+        assert!(queue.retry(&mqtt_client).await.is_err());
+        assert_eq!(queue.pending_count(), 2);
+
+        time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(queue.retry(&mqtt_client).await?, 2);
+        assert_eq!(queue.pending_count(), 0);
+        assert_eq!(*delivered.lock().unwrap(), vec!["first", "second"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn messages_handed_over_but_not_acknowledged_are_queued() {
+        let mut mqtt_client = MockMqttClient::new();
+        // Handing over a message to the client succeeds even while the broker is down
+        mqtt_client.expect_publish().times(0).returning(|_| Ok(0));
+        mqtt_client
+            .expect_publish_and_wait_for_ack()
+            .times(1)
+            .returning(|_| Err(broker_unavailable()));
+        let mut queue = RetryPublishQueue::new(16, Arc::new(WallClock));
+
+        assert!(queue.publish(&mqtt_client, message("first")).await.is_err());
+        assert_eq!(queue.pending_count(), 1);
+    }
+
+    #[test]
+    fn the_oldest_messages_are_dropped_when_the_queue_is_full() {
+        let mut queue = RetryPublishQueue::new(2, Arc::new(WallClock));
+
+        queue.push(message("first"));
+        queue.push(message("second"));
+        queue.push(message("third"));
+
+        assert_eq!(queue.pending_count(), 2);
+        let payloads: Vec<&str> = queue
+            .pending
+            .iter()
+            .map(|pending| pending.message.payload_str().unwrap())
+            .collect();
+        assert_eq!(payloads, vec!["second", "third"]);
+    }
+
+    #[test]
+    fn the_age_of_the_oldest_pending_message_is_given_by_the_clock() {
+        let now = Arc::new(Mutex::new(WallClock.now()));
+        let mut clock = clock::MockClock::new();
+        let clock_now = now.clone();
+        clock
+            .expect_now()
+            .returning(move || *clock_now.lock().unwrap());
+        let mut queue = RetryPublishQueue::new(16, Arc::new(clock));
+        assert_eq!(queue.oldest_pending_age(), None);
+
+        let advance = |secs| {
+            let mut now = now.lock().unwrap();
+            *now = *now + chrono::Duration::seconds(secs);
+        };
+
+        queue.push(message("first"));
+        advance(5);
+        queue.push(message("second"));
+        advance(2);
+
+        assert_eq!(queue.oldest_pending_age(), Some(Duration::from_secs(7)));
+    }
+}
@@ -160,6 +160,122 @@ mod tests {
         );
     }
 
+    #[test]
+    fn check_units_are_kept_in_translation() {
+        let input = r#"{
+            "temperature": {"value": 25.0, "unit": "°C"},
+            "location": {
+                "altitude": {"value": 98.6, "unit": "m"},
+                "latitude": 32.54
+            }
+        }"#;
+
+        let timestamp = FixedOffset::east(5 * 3600).ymd(2021, 4, 8).and_hms(0, 0, 0);
+
+        let output = from_thin_edge_json_with_timestamp(input, timestamp);
+
+        let expected_output = json!({
+            "type": "ThinEdgeMeasurement",
+            "time": timestamp.to_rfc3339(),
+            "temperature": {
+                "temperature": {
+                    "value": 25.0,
+                    "unit": "°C"
+                }
+            },
+            "location": {
+                "altitude": {
+                    "value": 98.6,
+                    "unit": "m"
+                },
+                "latitude": {
+                    "value": 32.54
+                }
+            }
+        });
+
+        assert_json_eq!(
+            serde_json::from_str::<serde_json::Value>(output.unwrap().as_str()).unwrap(),
+            expected_output
+        );
+    }
+
+    #[test]
+    fn check_null_values_are_dropped_in_translation() {
+        let input = r#"{
+            "temperature": null,
+            "pressure": 98.0
+        }"#;
+
+        let timestamp = FixedOffset::east(5 * 3600).ymd(2021, 4, 8).and_hms(0, 0, 0);
+
+        let output = from_thin_edge_json_with_timestamp(input, timestamp);
+
+        let expected_output = json!({
+            "type": "ThinEdgeMeasurement",
+            "time": timestamp.to_rfc3339(),
+            "pressure": {
+                "pressure": {
+                    "value": 98.0
+                }
+            }
+        });
+
+        assert_json_eq!(
+            serde_json::from_str::<serde_json::Value>(output.unwrap().as_str()).unwrap(),
+            expected_output
+        );
+    }
+
+    #[test]
+    fn check_values_with_a_quality_are_rejected() {
+        let input = r#"{
+            "temperature": {"value": 25.0, "quality": "BAD"},
+            "pressure": 98.0
+        }"#;
+
+        let timestamp = FixedOffset::east(5 * 3600).ymd(2021, 4, 8).and_hms(0, 0, 0);
+
+        let output = from_thin_edge_json_with_timestamp(input, timestamp);
+
+        assert!(matches!(
+            output,
+            Err(CumulocityJsonError::ThinEdgeJsonParserError(
+                ThinEdgeJsonParserError::VisitorError(
+                    serializer::C8yJsonSerializationError::MeasurementCollectorError(
+                        serializer::MeasurementStreamError::UnsupportedQuality { .. }
+                    )
+                )
+            ))
+        ));
+    }
+
+    #[test]
+    fn check_groups_with_a_timestamp_are_rejected() {
+        let input = r#"{
+            "time": "2013-06-22T17:03:14.000+02:00",
+            "pressure": {
+                "time": "2013-06-22T17:03:14.100+02:00",
+                "inlet": 98.0
+            }
+        }"#;
+
+        let timestamp = FixedOffset::east(5 * 3600).ymd(2021, 4, 8).and_hms(0, 0, 0);
+
+        let output = from_thin_edge_json_with_timestamp(input, timestamp);
+
+        assert!(matches!(
+            output,
+            Err(CumulocityJsonError::ThinEdgeJsonParserError(
+                ThinEdgeJsonParserError::VisitorError(
+                    serializer::C8yJsonSerializationError::MeasurementCollectorError(
+                        serializer::MeasurementStreamError::UnsupportedGroupTimestamp { .. }
+                    )
+                )
+            ))
+        ));
+    }
+
     #[test]
     fn thin_edge_json_round_tiny_number() {
         let input = r#"{
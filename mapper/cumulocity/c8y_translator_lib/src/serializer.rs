@@ -1,7 +1,11 @@
 use chrono::prelude::*;
 use json_writer::{JsonWriter, JsonWriterError};
 
-use thin_edge_json::{json::ThinEdgeJsonError, measurement::GroupedMeasurementVisitor};
+use thin_edge_json::{
+    json::ThinEdgeJsonError,
+    measurement::{GroupedMeasurementVisitor, MeasurementQuality},
+};
+use tracing::warn;
 
 pub struct C8yJsonSerializer {
     json: JsonWriter,
@@ -9,6 +13,7 @@ pub struct C8yJsonSerializer {
     needs_separator: bool,
     timestamp_present: bool,
     default_timestamp: DateTime<FixedOffset>,
+    missing_values: usize,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -42,6 +47,12 @@ pub enum MeasurementStreamError {
 
     #[error("Unexpected start of group")]
     UnexpectedStartOfGroup,
+
+    #[error("Unsupported timestamp for group {group}: a Cumulocity measurement has a single time")]
+    UnsupportedGroupTimestamp { group: String },
+
+    #[error("Unsupported quality for {name}: Cumulocity measurements have no quality")]
+    UnsupportedQuality { name: String },
 }
 
 impl C8yJsonSerializer {
@@ -59,6 +70,7 @@ impl C8yJsonSerializer {
             needs_separator: true,
             timestamp_present: false,
             default_timestamp,
+            missing_values: 0,
         }
     }
 
@@ -77,14 +89,54 @@ impl C8yJsonSerializer {
         Ok(())
     }
 
-    fn write_value_obj(&mut self, value: f64) -> Result<(), C8yJsonSerializationError> {
+    fn write_value_obj(
+        &mut self,
+        value: f64,
+        unit: Option<&str>,
+    ) -> Result<(), C8yJsonSerializationError> {
         self.json.write_open_obj();
         self.json.write_key("value")?;
         self.json.write_f64(value)?;
+        if let Some(unit) = unit {
+            self.json.write_separator();
+            self.json.write_key("unit")?;
+            self.json.write_str(unit)?;
+        }
         self.json.write_close_obj();
         Ok(())
     }
 
+    fn write_measurement(
+        &mut self,
+        key: &str,
+        value: f64,
+        unit: Option<&str>,
+    ) -> Result<(), C8yJsonSerializationError> {
+        if self.needs_separator {
+            self.json.write_separator();
+        } else {
+            self.needs_separator = true;
+        }
+
+        self.json.write_key(key)?;
+
+        if self.is_within_group {
+            self.write_value_obj(value, unit)?;
+        } else {
+            self.json.write_open_obj();
+            self.json.write_key(key)?;
+            self.write_value_obj(value, unit)?;
+            self.json.write_close_obj();
+        }
+        Ok(())
+    }
+
+    /// The number of measurements dropped for having no value,
+    /// a Cumulocity measurement value being a number
+    pub fn missing_values(&self) -> usize {
+        self.missing_values
+    }
+
     pub fn into_string(&mut self) -> Result<String, C8yJsonSerializationError> {
         self.end()?;
         Ok(self.json.clone().into_string()?)
@@ -112,23 +164,37 @@ impl GroupedMeasurementVisitor for C8yJsonSerializer {
     }
 
     fn measurement(&mut self, key: &str, value: f64) -> Result<(), Self::Error> {
-        if self.needs_separator {
-            self.json.write_separator();
-        } else {
-            self.needs_separator = true;
-        }
+        self.write_measurement(key, value, None)
+    }
 
-        self.json.write_key(key)?;
+    fn measurement_with_unit(
+        &mut self,
+        key: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.write_measurement(key, value, Some(unit))
+    }
 
-        if self.is_within_group {
-            self.write_value_obj(value)?;
-        } else {
-            self.json.write_open_obj();
-            self.json.write_key(key)?;
-            self.write_value_obj(value)?;
-            self.json.write_close_obj();
+    fn nullable_measurement(&mut self, key: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        match value {
+            Some(value) => self.measurement(key, value),
+            None => {
+                self.missing_values += 1;
+                warn!("Dropping the measurement {}: no value", key);
+                Ok(())
+            }
         }
-        Ok(())
+    }
+
+    /// Rejected, rather than silently dropping the quality
+    fn annotated_measurement(
+        &mut self,
+        key: &str,
+        _value: f64,
+        _quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        Err(MeasurementStreamError::UnsupportedQuality { name: key.into() }.into())
     }
 
     fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
@@ -146,6 +212,18 @@ impl GroupedMeasurementVisitor for C8yJsonSerializer {
         Ok(())
     }
 
+    /// Rejected, rather than filing the group under the time of the message
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        _timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        Err(MeasurementStreamError::UnsupportedGroupTimestamp {
+            group: group.into(),
+        }
+        .into())
+    }
+
     fn end_group(&mut self) -> Result<(), Self::Error> {
         if !self.is_within_group {
             return Err(MeasurementStreamError::UnexpectedEndOfGroup.into());
@@ -248,6 +326,125 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn serialize_measurements_with_units() -> anyhow::Result<()> {
+        let timestamp = FixedOffset::east(5 * 3600)
+            .ymd(2021, 6, 22)
+            .and_hms_nano(17, 3, 14, 123456789);
+
+        let mut serializer = C8yJsonSerializer::new(timestamp);
+        serializer.timestamp(timestamp)?;
+        serializer.measurement_with_unit("temperature", 25.5, "°C")?;
+        serializer.start_group("location")?;
+        serializer.measurement_with_unit("alti", 2100.4, "m")?;
+        serializer.measurement("longi", 2200.4)?;
+        serializer.end_group()?;
+
+        let output = serializer.into_string()?;
+
+        let expected_output = json!({
+            "type": "ThinEdgeMeasurement",
+            "time": "2021-06-22T17:03:14.123456789+05:00",
+            "temperature": {
+                "temperature": {
+                    "value": 25.5,
+                    "unit": "°C"
+                }
+            },
+            "location": {
+                "alti": {
+                    "value": 2100.4,
+                    "unit": "m"
+                },
+                "longi": {
+                    "value": 2200.4
+                }
+            }
+        });
+
+        assert_json_eq!(
+            serde_json::from_str::<serde_json::Value>(&output)?,
+            expected_output
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_measurement_with_quality_is_rejected() -> anyhow::Result<()> {
+        let timestamp = FixedOffset::east(5 * 3600)
+            .ymd(2021, 6, 22)
+            .and_hms_nano(17, 3, 14, 123456789);
+
+        let mut serializer = C8yJsonSerializer::new(timestamp);
+        serializer.timestamp(timestamp)?;
+
+        let expected_err =
+            serializer.annotated_measurement("temperature", 25.5, MeasurementQuality::Uncertain);
+
+        assert_matches!(
+            expected_err,
+            Err(C8yJsonSerializationError::MeasurementCollectorError(
+                MeasurementStreamError::UnsupportedQuality { name }
+            )) if name == "temperature"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_missing_values_are_dropped_and_counted() -> anyhow::Result<()> {
+        let timestamp = FixedOffset::east(5 * 3600)
+            .ymd(2021, 6, 22)
+            .and_hms_nano(17, 3, 14, 123456789);
+
+        let mut serializer = C8yJsonSerializer::new(timestamp);
+        serializer.timestamp(timestamp)?;
+        serializer.nullable_measurement("temperature", None)?;
+        serializer.start_group("location")?;
+        serializer.nullable_measurement("alti", Some(2100.4))?;
+        serializer.nullable_measurement("longi", None)?;
+        serializer.end_group()?;
+
+        assert_eq!(serializer.missing_values(), 2);
+
+        let output = serializer.into_string()?;
+
+        let expected_output = json!({
+            "type": "ThinEdgeMeasurement",
+            "time": "2021-06-22T17:03:14.123456789+05:00",
+            "location": {
+                "alti": {
+                    "value": 2100.4
+                }
+            }
+        });
+
+        assert_json_eq!(
+            serde_json::from_str::<serde_json::Value>(&output)?,
+            expected_output
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_group_with_timestamp_is_rejected() -> anyhow::Result<()> {
+        let timestamp = FixedOffset::east(5 * 3600)
+            .ymd(2021, 6, 22)
+            .and_hms_nano(17, 3, 14, 123456789);
+
+        let mut serializer = C8yJsonSerializer::new(timestamp);
+        serializer.timestamp(timestamp)?;
+
+        let expected_err = serializer.start_group_with_timestamp("location", timestamp);
+
+        assert_matches!(
+            expected_err,
+            Err(C8yJsonSerializationError::MeasurementCollectorError(
+                MeasurementStreamError::UnsupportedGroupTimestamp { group }
+            )) if group == "location"
+        );
+        Ok(())
+    }
+
     #[test]
     fn serialize_empty_message() -> anyhow::Result<()> {
         let timestamp = FixedOffset::east(5 * 3600)
@@ -0,0 +1,97 @@
+/// The maximum size of a batch accepted by the standard tier of Azure Event Hubs
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 1024 * 1024;
+
+/// A batch of events sent at once to an event hub, all the events sharing a partition key.
+///
+/// As the `EventDataBatch` of the Azure SDKs, a batch is bounded in size:
+/// an event is only added if the batch has room left for it,
+/// the size of a batch being the sum of the sizes of its events.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventDataBatch {
+    partition_key: String,
+    max_size_in_bytes: usize,
+    size_in_bytes: usize,
+    events: Vec<Vec<u8>>,
+}
+
+impl EventDataBatch {
+    pub fn new(partition_key: &str, max_size_in_bytes: usize) -> Self {
+        Self {
+            partition_key: partition_key.to_string(),
+            max_size_in_bytes,
+            size_in_bytes: 0,
+            events: Vec::new(),
+        }
+    }
+
+    /// Add an event to the batch, giving it back if the batch has no room left for it
+    pub fn try_add(&mut self, event: Vec<u8>) -> Result<(), Vec<u8>> {
+        let size_in_bytes = self.size_in_bytes + event.len();
+        if size_in_bytes > self.max_size_in_bytes {
+            return Err(event);
+        }
+        self.size_in_bytes = size_in_bytes;
+        self.events.push(event);
+        Ok(())
+    }
+
+    /// The key used by the event hub to assign all the events of the batch to the same partition
+    pub fn partition_key(&self) -> &str {
+        &self.partition_key
+    }
+
+    pub fn max_size_in_bytes(&self) -> usize {
+        self.max_size_in_bytes
+    }
+
+    pub fn size_in_bytes(&self) -> usize {
+        self.size_in_bytes
+    }
+
+    /// The events of the batch, in the order they have been added
+    pub fn events(&self) -> &[Vec<u8>] {
+        &self.events
+    }
+
+    pub fn into_events(self) -> Vec<Vec<u8>> {
+        self.events
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_are_added_while_the_batch_has_room_left() {
+        let mut batch = EventDataBatch::new("device-1", 10);
+
+        assert_eq!(batch.try_add(b"12345".to_vec()), Ok(()));
+        assert_eq!(batch.try_add(b"1234".to_vec()), Ok(()));
+        assert_eq!(batch.try_add(b"12".to_vec()), Err(b"12".to_vec()));
+        assert_eq!(batch.try_add(b"1".to_vec()), Ok(()));
+
+        assert_eq!(batch.size_in_bytes(), 10);
+        assert_eq!(batch.partition_key(), "device-1");
+        assert_eq!(
+            batch.into_events(),
+            vec![b"12345".to_vec(), b"1234".to_vec(), b"1".to_vec()]
+        );
+    }
+
+    #[test]
+    fn an_event_larger_than_the_max_size_is_never_added() {
+        let mut batch = EventDataBatch::new("device-1", 4);
+
+        assert_eq!(batch.try_add(b"12345".to_vec()), Err(b"12345".to_vec()));
+        assert!(batch.is_empty());
+    }
+}
@@ -0,0 +1,36 @@
+//! A sink publishing thin-edge JSON measurements to an Azure event hub.
+//!
+//! The events are sent in batches sharing the device id as partition key,
+//! using the HTTPS API of Event Hubs rather than AMQP 1.0, see `HttpsEventHubProducer`.
+//!
+//! ```no_run
+//! use eventhubs_sink::AzureEventHubsVisitor;
+//! use thin_edge_json::measurement::GroupedMeasurementVisitor;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), anyhow::Error> {
+//! let connection_string = "Endpoint=sb://factory.servicebus.windows.net/;\
+//!     SharedAccessKeyName=tedge;SharedAccessKey=<key>";
+//! let mut visitor =
+//!     AzureEventHubsVisitor::from_connection_string(connection_string, "measurements", "device-1")?;
+//!
+//! visitor.measurement("temperature", 25.5)?;
+//! visitor.flush().await?; // Adds `{"temperature":25.5}` to the current batch
+//!
+//! visitor.start_group("location")?;
+//! visitor.measurement("alti", 2100.4)?;
+//! visitor.end_group()?;
+//! visitor.flush().await?; // Adds `{"location":{"alti":2100.4}}` to the current batch
+//!
+//! // Sends both events with the partition key `device-1`
+//! visitor.send_batch().await?;
+//! # Ok(()) }
+//! ```
+
+mod batch;
+mod producer;
+mod publish;
+
+pub use batch::{EventDataBatch, DEFAULT_MAX_BATCH_SIZE};
+pub use producer::{EventHubProducer, HttpsEventHubProducer};
+pub use publish::{AzureEventHubsVisitor, EventHubsSinkError};
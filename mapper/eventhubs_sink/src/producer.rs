@@ -0,0 +1,441 @@
+use crate::batch::EventDataBatch;
+use crate::publish::EventHubsSinkError;
+use async_trait::async_trait;
+use hmac::{Hmac, Mac, NewMac};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use serde::Serialize;
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::form_urlencoded;
+use url::Url;
+
+#[cfg(test)]
+use mockall::automock;
+
+/// The maximum size of a request accepted by the standard tier of Azure Event Hubs
+const MAX_REQUEST_SIZE: usize = 1024 * 1024;
+
+/// The validity of the shared access signature given along each request
+const SIGNATURE_VALIDITY: Duration = Duration::from_secs(3600);
+
+const BATCH_CONTENT_TYPE: &str = "application/vnd.microsoft.servicebus.json";
+
+/// A client sending batches of events to an event hub.
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait EventHubProducer: Send {
+    /// Send all the events of the batch at once, to the partition assigned to its partition key
+    async fn send_batch(&mut self, batch: EventDataBatch) -> Result<(), EventHubsSinkError>;
+}
+
+/// A producer sending the events to an event hub using the HTTPS API of Event Hubs.
+///
+/// Event Hubs is also reached over AMQP 1.0, but the AMQP 1.0 clients for Rust,
+/// `azure_messaging_eventhubs` included, require a more recent compiler
+/// than the Rust 1.51 supported by thin-edge.
+/// With the HTTPS API, the events of a batch are sent in a single request,
+/// each with the partition key of the batch, unless the request would exceed 1 MiB
+/// once the events encoded as JSON strings: the events are then split over several requests.
+pub struct HttpsEventHubProducer {
+    client: reqwest::Client,
+    resource_uri: String,
+    messages_url: Url,
+    key_name: String,
+    key: String,
+}
+
+impl HttpsEventHubProducer {
+    /// Send the events to an event hub, authenticating with a shared access signature connection string,
+    /// as given by the Azure portal: `Endpoint=sb://<namespace>.servicebus.windows.net/;...`
+    pub fn from_connection_string(
+        connection_string: &str,
+        event_hub_name: &str,
+    ) -> Result<Self, EventHubsSinkError> {
+        let settings = ConnectionString::parse(connection_string)?;
+        let namespace_url = Url::parse(&format!("https://{}/", settings.host))
+            .map_err(|_| invalid_connection_string("invalid endpoint"))?;
+        HttpsEventHubProducer::new(
+            namespace_url,
+            event_hub_name,
+            &settings.key_name,
+            &settings.key,
+        )
+    }
+
+    /// Send the events to the event hub of the namespace at `namespace_url`,
+    /// as `https://<namespace>.servicebus.windows.net/`,
+    /// signing the requests with the given shared access key.
+    pub fn new(
+        namespace_url: Url,
+        event_hub_name: &str,
+        key_name: &str,
+        key: &str,
+    ) -> Result<Self, EventHubsSinkError> {
+        let resource_uri = format!(
+            "{}/{}",
+            namespace_url.as_str().trim_end_matches('/'),
+            event_hub_name
+        );
+        let messages_url = Url::parse(&format!(
+            "{}/messages?timeout=60&api-version=2014-01",
+            resource_uri
+        ))
+        .map_err(client_error)?;
+        Ok(Self {
+            client: reqwest::Client::new(),
+            resource_uri,
+            messages_url,
+            key_name: key_name.to_string(),
+            key: key.to_string(),
+        })
+    }
+
+    fn authorization(&self) -> String {
+        let expiry = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            + SIGNATURE_VALIDITY;
+        shared_access_signature(
+            &self.resource_uri,
+            &self.key_name,
+            &self.key,
+            expiry.as_secs(),
+        )
+    }
+}
+
+#[async_trait]
+impl EventHubProducer for HttpsEventHubProducer {
+    async fn send_batch(&mut self, batch: EventDataBatch) -> Result<(), EventHubsSinkError> {
+        for body in request_bodies(&batch, MAX_REQUEST_SIZE)? {
+            self.client
+                .post(self.messages_url.clone())
+                .header(AUTHORIZATION, self.authorization())
+                .header(CONTENT_TYPE, BATCH_CONTENT_TYPE)
+                .body(body)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(client_error)?;
+        }
+        Ok(())
+    }
+}
+
+/// The settings of a connection string, the other settings being ignored
+#[derive(Debug, Clone, PartialEq)]
+struct ConnectionString {
+    host: String,
+    key_name: String,
+    key: String,
+}
+
+impl ConnectionString {
+    fn parse(connection_string: &str) -> Result<Self, EventHubsSinkError> {
+        let mut endpoint = None;
+        let mut key_name = None;
+        let mut key = None;
+        for setting in connection_string
+            .split(';')
+            .filter(|s| !s.trim().is_empty())
+        {
+            let mut parts = setting.splitn(2, '=');
+            match (parts.next().map(str::trim), parts.next()) {
+                (Some("Endpoint"), Some(value)) => endpoint = Some(value),
+                (Some("SharedAccessKeyName"), Some(value)) => key_name = Some(value),
+                (Some("SharedAccessKey"), Some(value)) => key = Some(value),
+                (_, Some(_)) => {}
+                (_, None) => return Err(invalid_connection_string("expected <key>=<value>")),
+            }
+        }
+
+        let endpoint = endpoint.ok_or_else(|| invalid_connection_string("missing Endpoint"))?;
+        let host = endpoint
+            .trim_start_matches("sb://")
+            .trim_end_matches('/')
+            .to_string();
+        if host.is_empty() || host.contains('/') {
+            return Err(invalid_connection_string("invalid endpoint"));
+        }
+        Ok(ConnectionString {
+            host,
+            key_name: key_name
+                .ok_or_else(|| invalid_connection_string("missing SharedAccessKeyName"))?
+                .to_string(),
+            key: key
+                .ok_or_else(|| invalid_connection_string("missing SharedAccessKey"))?
+                .to_string(),
+        })
+    }
+}
+
+/// A shared access signature granting access to the resource till `expiry`, in seconds since the epoch
+fn shared_access_signature(resource_uri: &str, key_name: &str, key: &str, expiry: u64) -> String {
+    let resource = url_encode(resource_uri);
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(format!("{}\n{}", resource, expiry).as_bytes());
+    let signature = base64::encode(mac.finalize().into_bytes());
+    format!(
+        "SharedAccessSignature sr={}&sig={}&se={}&skn={}",
+        resource,
+        url_encode(&signature),
+        expiry,
+        key_name
+    )
+}
+
+fn url_encode(value: &str) -> String {
+    form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
+
+/// An event as sent in a batch, its body being a JSON string
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct BatchedEvent<'a> {
+    body: &'a str,
+    broker_properties: BrokerProperties<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct BrokerProperties<'a> {
+    partition_key: &'a str,
+}
+
+/// The bodies of the requests sending the events of a batch, in order,
+/// each body being a JSON array of at most `max_size` bytes.
+fn request_bodies(
+    batch: &EventDataBatch,
+    max_size: usize,
+) -> Result<Vec<Vec<u8>>, EventHubsSinkError> {
+    let mut bodies = Vec::new();
+    let mut body = Vec::new();
+    for event in batch.events() {
+        let entry = serde_json::to_vec(&BatchedEvent {
+            body: &String::from_utf8_lossy(event),
+            broker_properties: BrokerProperties {
+                partition_key: batch.partition_key(),
+            },
+        })
+        .map_err(client_error)?;
+        if entry.len() + 2 > max_size {
+            return Err(EventHubsSinkError::EventTooLarge {
+                size: entry.len(),
+                max_size,
+            });
+        }
+        if !body.is_empty() && body.len() + entry.len() + 2 > max_size {
+            body.push(b']');
+            bodies.push(std::mem::take(&mut body));
+        }
+        body.push(if body.is_empty() { b'[' } else { b',' });
+        body.extend_from_slice(&entry);
+    }
+    if !body.is_empty() {
+        body.push(b']');
+        bodies.push(body);
+    }
+    Ok(bodies)
+}
+
+fn invalid_connection_string(reason: &str) -> EventHubsSinkError {
+    EventHubsSinkError::InvalidConnectionString(reason.to_string())
+}
+
+fn client_error(err: impl std::fmt::Display) -> EventHubsSinkError {
+    EventHubsSinkError::ClientError(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    const CONNECTION_STRING: &str = "Endpoint=sb://factory.servicebus.windows.net/;\
+        SharedAccessKeyName=tedge;SharedAccessKey=c2VjcmV0LWtleQ==;EntityPath=measurements";
+
+    fn batch(partition_key: &str, events: &[&str]) -> EventDataBatch {
+        let mut batch = EventDataBatch::new(partition_key, 1024);
+        for event in events {
+            batch.try_add(event.as_bytes().to_vec()).unwrap();
+        }
+        batch
+    }
+
+    #[test]
+    fn connection_strings_are_parsed() -> anyhow::Result<()> {
+        assert_eq!(
+            ConnectionString::parse(CONNECTION_STRING)?,
+            ConnectionString {
+                host: "factory.servicebus.windows.net".into(),
+                key_name: "tedge".into(),
+                key: "c2VjcmV0LWtleQ==".into(),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn connection_strings_with_missing_settings_are_rejected() {
+        for connection_string in [
+            "SharedAccessKeyName=tedge;SharedAccessKey=c2VjcmV0LWtleQ==",
+            "Endpoint=sb://factory.servicebus.windows.net/;SharedAccessKey=c2VjcmV0LWtleQ==",
+            "Endpoint=sb://factory.servicebus.windows.net/;SharedAccessKeyName=tedge",
+            "Endpoint=sb://factory.servicebus.windows.net/;tedge",
+        ]
+        .iter()
+        {
+            assert_matches!(
+                ConnectionString::parse(connection_string),
+                Err(EventHubsSinkError::InvalidConnectionString(_))
+            );
+        }
+    }
+
+    #[test]
+    fn requests_are_signed_with_the_shared_access_key() {
+        let signature = shared_access_signature(
+            "https://factory.servicebus.windows.net/measurements",
+            "tedge",
+            "c2VjcmV0LWtleQ==",
+            1619795000,
+        );
+
+        assert_eq!(
+            signature,
+            "SharedAccessSignature \
+            sr=https%3A%2F%2Ffactory.servicebus.windows.net%2Fmeasurements\
+            &sig=XBvYt%2FvHh9a%2FWFcPWxWqxRYCcaMz7XGGCYzMUOj9bQo%3D\
+            &se=1619795000&skn=tedge"
+        );
+    }
+
+    #[test]
+    fn events_are_sent_as_strings_with_the_partition_key() -> anyhow::Result<()> {
+        let batch = batch(
+            "device-1",
+            &[r#"{"temperature":25.5}"#, r#"{"temperature":26.5}"#],
+        );
+
+        let bodies = request_bodies(&batch, MAX_REQUEST_SIZE)?;
+
+        assert_eq!(bodies.len(), 1);
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&bodies[0])?,
+            json!([
+                {"Body": r#"{"temperature":25.5}"#, "BrokerProperties": {"PartitionKey": "device-1"}},
+                {"Body": r#"{"temperature":26.5}"#, "BrokerProperties": {"PartitionKey": "device-1"}},
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn events_are_split_over_several_requests_when_too_large() -> anyhow::Result<()> {
+        let batch = batch("device-1", &["1", "2", "3"]);
+        let entry_size = r#"{"Body":"1","BrokerProperties":{"PartitionKey":"device-1"}}"#.len();
+
+        // Room for two events per request, along the brackets and the separator
+        let bodies = request_bodies(&batch, 2 * entry_size + 3)?;
+
+        let events: Vec<usize> = bodies
+            .iter()
+            .map(|body| serde_json::from_slice::<Vec<serde_json::Value>>(body).map(|e| e.len()))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(events, vec![2, 1]);
+        assert!(bodies.iter().all(|body| body.len() <= 2 * entry_size + 3));
+
+        assert_matches!(
+            request_bodies(&batch, entry_size),
+            Err(EventHubsSinkError::EventTooLarge { .. })
+        );
+        Ok(())
+    }
+
+    /// An event hub accepting a single request, and returning it as received
+    async fn mock_event_hub() -> anyhow::Result<(Url, tokio::task::JoinHandle<String>)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let namespace_url = Url::parse(&format!("http://{}/", listener.local_addr()?))?;
+        let request = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.expect("A request");
+            let request = read_request(&mut stream).await.expect("A valid request");
+            let response = "HTTP/1.1 201 Created\r\ncontent-length: 0\r\nconnection: close\r\n\r\n";
+            stream.write_all(response.as_bytes()).await.expect("Sent");
+            request
+        });
+        Ok((namespace_url, request))
+    }
+
+    async fn read_request(stream: &mut TcpStream) -> anyhow::Result<String> {
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        loop {
+            let n = stream.read(&mut buffer).await?;
+            request.extend_from_slice(&buffer[..n]);
+            let text = String::from_utf8_lossy(&request).to_string();
+            if let Some(head_end) = text.find("\r\n\r\n") {
+                let content_length = text[..head_end]
+                    .lines()
+                    .filter_map(|line| line.strip_prefix("content-length: "))
+                    .find_map(|length| length.parse::<usize>().ok())
+                    .unwrap_or(0);
+                if request.len() >= head_end + 4 + content_length || n == 0 {
+                    return Ok(text);
+                }
+            } else if n == 0 {
+                return Ok(text);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn batches_are_posted_to_the_event_hub() -> anyhow::Result<()> {
+        let (namespace_url, request) = mock_event_hub().await?;
+        let mut producer =
+            HttpsEventHubProducer::new(namespace_url, "measurements", "tedge", "c2VjcmV0LWtleQ==")?;
+
+        producer
+            .send_batch(batch("device-1", &[r#"{"temperature":25.5}"#]))
+            .await?;
+
+        let request = request.await?;
+        let (head, body) = request.split_at(request.find("\r\n\r\n").unwrap_or(0));
+        assert!(head.starts_with(
+            "POST /measurements/messages?timeout=60&api-version=2014-01 HTTP/1.1\r\n"
+        ));
+        assert!(head.contains("content-type: application/vnd.microsoft.servicebus.json\r\n"));
+        assert!(head.contains("authorization: SharedAccessSignature sr=http%3A%2F%2F"));
+        assert!(head.contains("&skn=tedge"));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(body.trim())?,
+            json!([{"Body": r#"{"temperature":25.5}"#, "BrokerProperties": {"PartitionKey": "device-1"}}])
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejected_batches_are_reported() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let namespace_url = Url::parse(&format!("http://{}/", listener.local_addr()?))?;
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.expect("A request");
+            let _ = read_request(&mut stream).await;
+            let response =
+                "HTTP/1.1 401 Unauthorized\r\ncontent-length: 0\r\nconnection: close\r\n\r\n";
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+        let mut producer =
+            HttpsEventHubProducer::new(namespace_url, "measurements", "tedge", "wrong-key")?;
+
+        let result = producer
+            .send_batch(batch("device-1", &[r#"{"temperature":25.5}"#]))
+            .await;
+
+        assert_matches!(result, Err(EventHubsSinkError::ClientError(_)));
+        Ok(())
+    }
+}
@@ -0,0 +1,373 @@
+use crate::batch::{EventDataBatch, DEFAULT_MAX_BATCH_SIZE};
+use crate::producer::{EventHubProducer, HttpsEventHubProducer};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use thin_edge_json::buffer::MeasurementBuffer;
+use thin_edge_json::context::MeasurementContext;
+use thin_edge_json::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use thin_edge_json::serialize::{
+    MeasurementStreamError, ThinEdgeJsonSerializationError, ThinEdgeJsonSerializer,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum EventHubsSinkError {
+    #[error("Event Hubs error: {0}")]
+    ClientError(String),
+
+    #[error("Invalid Event Hubs connection string: {0}")]
+    InvalidConnectionString(String),
+
+    #[error("An event of {size} bytes exceeds the maximum batch size of {max_size} bytes")]
+    EventTooLarge { size: usize, max_size: usize },
+
+    #[error(transparent)]
+    SerializationError(#[from] ThinEdgeJsonSerializationError),
+}
+
+/// A visitor that publishes the measurements as thin-edge JSON events to an Azure event hub.
+///
+/// The measurements are gathered and, on `flush()`, added as a single event to a batch.
+/// The batches are only sent when full or on `send_batch()`,
+/// so the number of requests to the event hub is kept low.
+///
+/// * The device id is the partition key of all the events,
+///   so the events of a device are all assigned to the same partition and read in order.
+/// * The size of a batch is bounded by 1 MiB, the maximum accepted by the standard tier.
+/// * A batch that cannot be sent is discarded, along with its events.
+pub struct AzureEventHubsVisitor<P> {
+    producer: P,
+    device_id: String,
+    batch: EventDataBatch,
+    buffer: MeasurementBuffer,
+}
+
+impl AzureEventHubsVisitor<HttpsEventHubProducer> {
+    /// Publish the measurements of the given device to an event hub.
+    ///
+    /// See `HttpsEventHubProducer::from_connection_string()` for the expected connection string.
+    pub fn from_connection_string(
+        connection_string: &str,
+        event_hub_name: &str,
+        device_id: &str,
+    ) -> Result<Self, EventHubsSinkError> {
+        let producer =
+            HttpsEventHubProducer::from_connection_string(connection_string, event_hub_name)?;
+        Ok(AzureEventHubsVisitor::new(producer, device_id))
+    }
+}
+
+impl<P: EventHubProducer> AzureEventHubsVisitor<P> {
+    pub fn new(producer: P, device_id: &str) -> Self {
+        Self {
+            producer,
+            device_id: device_id.to_string(),
+            batch: EventDataBatch::new(device_id, DEFAULT_MAX_BATCH_SIZE),
+            buffer: MeasurementBuffer::new(),
+        }
+    }
+
+    /// Set the maximum size of a batch in bytes, 1 MiB by default.
+    ///
+    /// The events added to the current batch are discarded.
+    pub fn with_max_batch_size(self, max_batch_size: usize) -> Self {
+        Self {
+            batch: EventDataBatch::new(&self.device_id, max_batch_size),
+            ..self
+        }
+    }
+
+    /// The batch of the events not sent yet
+    pub fn pending_batch(&self) -> &EventDataBatch {
+        &self.batch
+    }
+
+    /// Add the measurements gathered since the previous flush as an event to the current batch,
+    /// sending this batch first if it has no room left for the new event.
+    ///
+    /// Nothing is added if no measurements have been gathered.
+    pub async fn flush(&mut self) -> Result<(), EventHubsSinkError> {
+        let buffer = std::mem::take(&mut self.buffer);
+        if !buffer.has_measurements() {
+            return Ok(());
+        }
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        buffer.replay(&mut serializer)?;
+        let event = serializer.bytes()?;
+
+        let event = match self.batch.try_add(event) {
+            Ok(()) => return Ok(()),
+            Err(event) if self.batch.is_empty() => return Err(self.event_too_large(&event)),
+            Err(event) => event,
+        };
+        self.send_batch().await?;
+        self.batch
+            .try_add(event)
+            .map_err(|event| self.event_too_large(&event))
+    }
+
+    /// Send the current batch, even if not full.
+    ///
+    /// Nothing is sent if the batch is empty.
+    pub async fn send_batch(&mut self) -> Result<(), EventHubsSinkError> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let max_batch_size = self.batch.max_size_in_bytes();
+        let batch = std::mem::replace(
+            &mut self.batch,
+            EventDataBatch::new(&self.device_id, max_batch_size),
+        );
+        self.producer.send_batch(batch).await
+    }
+
+    fn event_too_large(&self, event: &[u8]) -> EventHubsSinkError {
+        EventHubsSinkError::EventTooLarge {
+            size: event.len(),
+            max_size: self.batch.max_size_in_bytes(),
+        }
+    }
+}
+
+impl<P> GroupedMeasurementVisitor for AzureEventHubsVisitor<P> {
+    type Error = MeasurementStreamError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        Ok(self.buffer.timestamp(value)?)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        Ok(self.buffer.measurement(name, value)?)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        Ok(self.buffer.start_group(group)?)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        Ok(self.buffer.end_group()?)
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        Ok(self.buffer.measurement_with_unit(name, value, unit)?)
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        value: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        Ok(self.buffer.start_group_with_timestamp(group, value)?)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        Ok(self.buffer.nullable_measurement(name, value)?)
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        Ok(self.buffer.complex_measurement(name, real, imaginary)?)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        Ok(self.buffer.annotated_measurement(name, value, quality)?)
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        Ok(self.buffer.set_context(context)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::producer::MockEventHubProducer;
+    use assert_matches::assert_matches;
+    use std::sync::{Arc, Mutex};
+
+    /// A producer recording the batches it is asked to send
+    fn producer() -> (MockEventHubProducer, Arc<Mutex<Vec<EventDataBatch>>>) {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let batches = sent.clone();
+        let mut producer = MockEventHubProducer::new();
+        producer.expect_send_batch().returning(move |batch| {
+            batches.lock().unwrap().push(batch);
+            Ok(())
+        });
+        (producer, sent)
+    }
+
+    fn events(batch: &EventDataBatch) -> Vec<&str> {
+        batch
+            .events()
+            .iter()
+            .map(|event| std::str::from_utf8(event).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn events_are_batched_until_the_batch_is_full() -> anyhow::Result<()> {
+        let (producer, sent) = producer();
+        // Room for two events of 20 bytes, as `{"temperature":25.5}`
+        let mut visitor = AzureEventHubsVisitor::new(producer, "device-1").with_max_batch_size(45);
+
+        for value in [25.5, 26.5, 27.5].iter() {
+            visitor.measurement("temperature", *value)?;
+            visitor.flush().await?;
+        }
+
+        {
+            let sent = sent.lock().unwrap();
+            assert_eq!(sent.len(), 1);
+            assert_eq!(
+                events(&sent[0]),
+                vec![r#"{"temperature":25.5}"#, r#"{"temperature":26.5}"#]
+            );
+        }
+        assert_eq!(
+            events(visitor.pending_batch()),
+            vec![r#"{"temperature":27.5}"#]
+        );
+
+        visitor.send_batch().await?;
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(events(&sent[1]), vec![r#"{"temperature":27.5}"#]);
+        assert!(visitor.pending_batch().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn the_device_id_is_the_partition_key_of_all_the_batches() -> anyhow::Result<()> {
+        let (producer, sent) = producer();
+        let mut visitor = AzureEventHubsVisitor::new(producer, "device-1").with_max_batch_size(40);
+
+        for _ in 0..3 {
+            visitor.start_group("location")?;
+            visitor.measurement("alti", 2100.4)?;
+            visitor.end_group()?;
+            visitor.flush().await?;
+        }
+        visitor.send_batch().await?;
+
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 3);
+        for batch in sent.iter() {
+            assert_eq!(batch.partition_key(), "device-1");
+            assert_eq!(events(batch), vec![r#"{"location":{"alti":2100.4}}"#]);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn measurements_gathered_between_two_flushes_make_one_event() -> anyhow::Result<()> {
+        let (producer, _) = producer();
+        let mut visitor = AzureEventHubsVisitor::new(producer, "device-1");
+
+        visitor.timestamp(DateTime::parse_from_rfc3339("2021-04-30T17:03:14+02:00")?)?;
+        visitor.measurement("temperature", 25.5)?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.end_group()?;
+        visitor.flush().await?;
+
+        assert_eq!(
+            events(visitor.pending_batch()),
+            vec![
+                r#"{"time":"2021-04-30T17:03:14+02:00","temperature":25.5,"location":{"alti":2100.4}}"#
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn all_kinds_of_measurements_make_an_event() -> anyhow::Result<()> {
+        let (producer, _) = producer();
+        let mut visitor = AzureEventHubsVisitor::new(producer, "device-1");
+
+        visitor.set_context(MeasurementContext::new([0x4b; 16], [0xf0; 8]))?;
+        visitor.nullable_measurement("temperature", None)?;
+        visitor.annotated_measurement("pressure", 98.0, MeasurementQuality::Bad)?;
+        visitor.start_group_with_timestamp(
+            "engine",
+            DateTime::parse_from_rfc3339("2021-04-30T17:03:14+02:00")?,
+        )?;
+        visitor.complex_measurement("current", 1.5, -0.5)?;
+        visitor.end_group()?;
+        visitor.flush().await?;
+
+        assert_eq!(
+            events(visitor.pending_batch()),
+            vec![concat!(
+                r#"{"_traceId":"4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b","_spanId":"f0f0f0f0f0f0f0f0","#,
+                r#""temperature":null,"pressure":{"value":98.0,"quality":"BAD"},"#,
+                r#""engine":{"time":"2021-04-30T17:03:14+02:00","current":{"re":1.5,"im":-0.5}}}"#
+            )]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn nothing_is_sent_when_there_is_no_measurements() -> anyhow::Result<()> {
+        let mut producer = MockEventHubProducer::new();
+        producer.expect_send_batch().never();
+        let mut visitor = AzureEventHubsVisitor::new(producer, "device-1");
+
+        visitor.flush().await?;
+        visitor.set_context(MeasurementContext::new([0x4b; 16], [0xf0; 8]))?;
+        visitor.timestamp(DateTime::parse_from_rfc3339("2021-04-30T17:03:14+02:00")?)?;
+        visitor.flush().await?;
+        visitor.send_batch().await?;
+
+        assert!(visitor.pending_batch().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn events_larger_than_a_batch_are_rejected() -> anyhow::Result<()> {
+        let (producer, sent) = producer();
+        let mut visitor = AzureEventHubsVisitor::new(producer, "device-1").with_max_batch_size(10);
+
+        visitor.measurement("temperature", 25.5)?;
+        let result = visitor.flush().await;
+
+        assert_matches!(
+            result,
+            Err(EventHubsSinkError::EventTooLarge {
+                size: 20,
+                max_size: 10
+            })
+        );
+        assert!(sent.lock().unwrap().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_errors_are_reported() -> anyhow::Result<()> {
+        let mut producer = MockEventHubProducer::new();
+        producer
+            .expect_send_batch()
+            .returning(|_| Err(EventHubsSinkError::ClientError("connection lost".into())));
+        let mut visitor = AzureEventHubsVisitor::new(producer, "device-1");
+
+        visitor.measurement("temperature", 25.5)?;
+        visitor.flush().await?;
+        let result = visitor.send_batch().await;
+
+        assert_matches!(result, Err(EventHubsSinkError::ClientError(_)));
+        Ok(())
+    }
+}
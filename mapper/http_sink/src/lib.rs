@@ -0,0 +1,31 @@
+//! A sink posting thin-edge JSON measurements to an HTTP endpoint.
+//!
+//! The measurements can also be sent as entities to a FIWARE NGSI v2 broker,
+//! using the [`FiwareNgsiV2Visitor`], as device states to the Losant REST API,
+//! using the [`LosantStateVisitor`], or as log lines to Grafana Loki,
+//! using the [`LokiSinkVisitor`].
+//!
+//! ```no_run
+//! use http_sink::HttpPostVisitor;
+//! use reqwest::Url;
+//! use thin_edge_json::measurement::GroupedMeasurementVisitor;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), anyhow::Error> {
+//! let url = Url::parse("https://example.com/measurements")?;
+//! let mut visitor = HttpPostVisitor::new(url).with_header("Authorization", "Bearer token")?;
+//!
+//! visitor.measurement("temperature", 25.5)?;
+//! visitor.flush().await?; // POSTs `{"temperature":25.5}`
+//! # Ok(()) }
+//! ```
+
+mod loki;
+mod losant;
+mod ngsi;
+mod post;
+
+pub use loki::{LokiError, LokiSinkVisitor};
+pub use losant::{LosantError, LosantStateVisitor};
+pub use ngsi::{FiwareNgsiV2Visitor, NgsiError};
+pub use post::{HttpPostVisitor, HttpSinkError};
@@ -0,0 +1,449 @@
+use crate::post::HttpSinkError;
+use chrono::offset::FixedOffset;
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use reqwest::Url;
+use serde_json::{json, Map, Value};
+use std::borrow::Cow;
+use thin_edge_json::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+
+const DEVICE_LABEL: &str = "device";
+const GROUP_LABEL: &str = "metric_group";
+
+#[derive(thiserror::Error, Debug)]
+pub enum LokiError {
+    #[error("Unexpected time stamp within a group")]
+    UnexpectedTimestamp,
+
+    #[error("Unexpected start of group")]
+    UnexpectedStartOfGroup,
+
+    #[error("Unexpected end of group")]
+    UnexpectedEndOfGroup,
+
+    #[error("Invalid label name {name:?}: only ASCII letters, digits and underscores are allowed")]
+    InvalidLabelName { name: String },
+}
+
+/// A log line, as gathered from a measurement
+struct LogEntry {
+    group: Option<String>,
+    timestamp: Option<DateTime<FixedOffset>>,
+    line: String,
+}
+
+/// A visitor that pushes the measurements as log lines to Grafana Loki.
+///
+/// Each measurement is a log line, formatted as logfmt:
+///
+/// ```text
+/// level=info metric_group=location metric_name=alti value=2100.4
+/// ```
+///
+/// * The `metric_group` field is omitted for the measurements of the root object.
+/// * The unit and the quality of a measurement, if any, are given as `unit` and `quality` fields.
+/// * A complex measurement is given by `value_re` and `value_im` fields,
+///   and a null measurement by `value=null`.
+///
+/// The log lines are labelled with the device id, as `device`,
+/// and with the group name, as `metric_group`, hence pushed in one stream per group,
+/// plus a stream for the root measurements.
+/// Static labels, as `job`, can be added to all the streams.
+///
+/// The log lines are gathered and pushed on `flush()`,
+/// by a single `POST /loki/api/v1/push` request.
+/// A log line is timestamped with the timestamp of its measurement,
+/// or with the time of the flush if none.
+/// A failed request is not retried, the log lines being discarded.
+pub struct LokiSinkVisitor {
+    client: reqwest::Client,
+    url: Url,
+    headers: HeaderMap,
+    labels: Map<String, Value>,
+    timestamp: Option<DateTime<FixedOffset>>,
+    group: Option<(String, Option<DateTime<FixedOffset>>)>,
+    entries: Vec<LogEntry>,
+}
+
+impl LokiSinkVisitor {
+    /// Push the log lines of a device to the Loki server at the given URL, as `http://loki:3100`.
+    pub fn new(loki_url: Url, device_id: &str) -> Self {
+        let mut url = loki_url;
+        let path = format!("{}/loki/api/v1/push", url.path().trim_end_matches('/'));
+        url.set_path(&path);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+        let mut labels = Map::new();
+        labels.insert(DEVICE_LABEL.into(), Value::from(device_id));
+
+        Self {
+            client: reqwest::Client::new(),
+            url,
+            headers,
+            labels,
+            timestamp: None,
+            group: None,
+            entries: vec![],
+        }
+    }
+
+    /// Add a static label to all the streams, as `job="tedge"`.
+    pub fn with_label(mut self, name: &str, value: &str) -> Result<Self, LokiError> {
+        if !is_label_name(name) {
+            return Err(LokiError::InvalidLabelName { name: name.into() });
+        }
+        self.labels.insert(name.into(), Value::from(value));
+        Ok(self)
+    }
+
+    /// Set the tenant of the log lines, as the `X-Scope-OrgID` header of a multi-tenant Loki.
+    pub fn with_tenant(mut self, tenant: &str) -> Result<Self, HttpSinkError> {
+        self.headers.insert(
+            HeaderName::from_static("x-scope-orgid"),
+            HeaderValue::from_str(tenant)?,
+        );
+        Ok(self)
+    }
+
+    /// Push the log lines gathered since the previous flush.
+    ///
+    /// Nothing is sent if no measurements have been gathered.
+    pub async fn flush(&mut self) -> Result<(), HttpSinkError> {
+        let body = match self.take_push(Utc::now()) {
+            Some(body) => body,
+            None => return Ok(()),
+        };
+
+        let response = self
+            .client
+            .post(self.url.clone())
+            .headers(self.headers.clone())
+            .body(body.to_string())
+            .send()
+            .await?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(HttpSinkError::ServerError { status })
+        }
+    }
+
+    /// The push request for the log lines gathered so far, if any,
+    /// the lines with no timestamp being timestamped with `now`
+    fn take_push(&mut self, now: DateTime<Utc>) -> Option<Value> {
+        self.group = None;
+        let timestamp = self.timestamp.take();
+        let entries = std::mem::take(&mut self.entries);
+        if entries.is_empty() {
+            return None;
+        }
+
+        let mut streams: Vec<(Option<String>, Vec<(i64, String)>)> = vec![];
+        for entry in entries {
+            let nanos = entry
+                .timestamp
+                .or(timestamp)
+                .map(|timestamp| timestamp.timestamp_nanos())
+                .unwrap_or_else(|| now.timestamp_nanos());
+            let value = (nanos, entry.line);
+            match streams.iter_mut().find(|(group, _)| *group == entry.group) {
+                Some((_, values)) => values.push(value),
+                None => streams.push((entry.group, vec![value])),
+            }
+        }
+
+        let streams: Vec<Value> = streams
+            .into_iter()
+            .map(|(group, mut values)| {
+                // Loki expects the lines of a stream in chronological order
+                values.sort_by_key(|(nanos, _)| *nanos);
+                let mut labels = self.labels.clone();
+                if let Some(group) = group {
+                    labels.insert(GROUP_LABEL.into(), Value::from(group));
+                }
+                let values: Vec<Value> = values
+                    .into_iter()
+                    .map(|(nanos, line)| json!([nanos.to_string(), line]))
+                    .collect();
+                json!({"stream": labels, "values": values})
+            })
+            .collect();
+        Some(json!({ "streams": streams }))
+    }
+
+    fn add_line(&mut self, name: &str, fields: &str) {
+        let (group, timestamp) = match self.group.as_ref() {
+            Some((group, timestamp)) => (Some(group.clone()), *timestamp),
+            None => (None, None),
+        };
+        let line = match group.as_ref() {
+            Some(group) => format!(
+                "level=info metric_group={} metric_name={} {}",
+                logfmt(group),
+                logfmt(name),
+                fields
+            ),
+            None => format!("level=info metric_name={} {}", logfmt(name), fields),
+        };
+        self.entries.push(LogEntry {
+            group,
+            timestamp,
+            line,
+        });
+    }
+
+    fn start_stream(
+        &mut self,
+        group: &str,
+        timestamp: Option<DateTime<FixedOffset>>,
+    ) -> Result<(), LokiError> {
+        if self.group.is_some() {
+            return Err(LokiError::UnexpectedStartOfGroup);
+        }
+        self.group = Some((group.to_string(), timestamp));
+        Ok(())
+    }
+}
+
+fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// A logfmt value, quoted if made of several words or empty
+fn logfmt(value: &str) -> Cow<str> {
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '=' || c == '"') {
+        Cow::Owned(format!("{:?}", value))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+impl GroupedMeasurementVisitor for LokiSinkVisitor {
+    type Error = LokiError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        if self.group.is_some() {
+            return Err(LokiError::UnexpectedTimestamp);
+        }
+        self.timestamp = Some(value);
+        Ok(())
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.add_line(name, &format!("value={}", value));
+        Ok(())
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.start_stream(group, None)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.group.take().ok_or(LokiError::UnexpectedEndOfGroup)?;
+        Ok(())
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.add_line(name, &format!("value={} unit={}", value, logfmt(unit)));
+        Ok(())
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.start_stream(group, Some(timestamp))
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        match value {
+            Some(value) => self.measurement(name, value),
+            None => {
+                self.add_line(name, "value=null");
+                Ok(())
+            }
+        }
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.add_line(name, &format!("value_re={} value_im={}", real, imaginary));
+        Ok(())
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.add_line(name, &format!("value={} quality={}", value, quality));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use chrono::TimeZone;
+    use wiremock::matchers::{body_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn visitor(server: &MockServer) -> anyhow::Result<LokiSinkVisitor> {
+        Ok(LokiSinkVisitor::new(Url::parse(&server.uri())?, "device-1"))
+    }
+
+    fn push() -> wiremock::MockBuilder {
+        Mock::given(method("POST"))
+            .and(path("/loki/api/v1/push"))
+            .and(header("content-type", "application/json"))
+    }
+
+    #[tokio::test]
+    async fn measurements_are_pushed_as_log_lines() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        push()
+            .and(header("x-scope-orgid", "plant-1"))
+            .and(body_json(json!({
+                "streams": [
+                    {
+                        "stream": {"device": "device-1", "job": "tedge"},
+                        "values": [
+                            ["1617876000000000000", "level=info metric_name=temperature value=25.5 unit=°C"],
+                            ["1617876000000000000", "level=info metric_name=pressure value=null"],
+                        ],
+                    },
+                    {
+                        "stream": {"device": "device-1", "job": "tedge", "metric_group": "location"},
+                        "values": [
+                            ["1617876000000000000", "level=info metric_group=location metric_name=alti value=2100.4"],
+                            ["1617876000000000000", "level=info metric_group=location metric_name=longi value=2200.4"],
+                        ],
+                    },
+                ],
+            })))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut visitor = visitor(&server)?
+            .with_label("job", "tedge")?
+            .with_tenant("plant-1")?;
+        visitor.timestamp(FixedOffset::east(0).ymd(2021, 4, 8).and_hms(10, 0, 0))?;
+        visitor.measurement_with_unit("temperature", 25.5, "°C")?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.measurement("longi", 2200.4)?;
+        visitor.end_group()?;
+        visitor.nullable_measurement("pressure", None)?;
+        visitor.flush().await?;
+
+        // Nothing is sent when there is no measurements
+        visitor.flush().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn the_lines_of_a_stream_are_sorted_by_time() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        push()
+            .and(body_json(json!({
+                "streams": [
+                    {
+                        "stream": {"device": "device-1", "metric_group": "engine"},
+                        "values": [
+                            ["1617868800000000000", "level=info metric_group=engine metric_name=speed value=3000 quality=UNCERTAIN"],
+                            ["1617876000000000000", "level=info metric_group=engine metric_name=current value_re=1.5 value_im=-0.5"],
+                        ],
+                    },
+                ],
+            })))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut visitor = visitor(&server)?;
+        visitor.timestamp(FixedOffset::east(0).ymd(2021, 4, 8).and_hms(10, 0, 0))?;
+        visitor.start_group("engine")?;
+        visitor.complex_measurement("current", 1.5, -0.5)?;
+        visitor.end_group()?;
+        visitor.start_group_with_timestamp(
+            "engine",
+            FixedOffset::east(0).ymd(2021, 4, 8).and_hms(8, 0, 0),
+        )?;
+        visitor.annotated_measurement("speed", 3000.0, MeasurementQuality::Uncertain)?;
+        visitor.end_group()?;
+        visitor.flush().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejected_pushes_are_reported() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        push()
+            .respond_with(ResponseTemplate::new(400))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut visitor = visitor(&server)?;
+        visitor.measurement("temperature", 25.5)?;
+
+        assert_matches!(
+            visitor.flush().await,
+            Err(HttpSinkError::ServerError { status }) if status.as_u16() == 400
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn lines_with_no_timestamp_are_pushed_at_the_flush_time() -> anyhow::Result<()> {
+        let mut visitor = LokiSinkVisitor::new(Url::parse("http://loki:3100")?, "my device");
+        visitor.measurement_with_unit("wind speed", 12.5, "m/s")?;
+
+        assert_eq!(
+            visitor.take_push(Utc.ymd(2021, 4, 8).and_hms(10, 0, 0)),
+            Some(json!({
+                "streams": [{
+                    "stream": {"device": "my device"},
+                    "values": [["1617876000000000000", r#"level=info metric_name="wind speed" value=12.5 unit=m/s"#]],
+                }],
+            }))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn label_names_are_checked() -> anyhow::Result<()> {
+        let visitor = LokiSinkVisitor::new(Url::parse("http://loki:3100")?, "device-1");
+
+        assert_matches!(
+            visitor.with_label("service-name", "tedge"),
+            Err(LokiError::InvalidLabelName { name }) if name == "service-name"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,404 @@
+use crate::post::HttpSinkError;
+use chrono::offset::FixedOffset;
+use chrono::{DateTime, SecondsFormat, Utc};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use reqwest::Url;
+use serde_json::{json, Map, Value};
+use thin_edge_json::measurement::GroupedMeasurementVisitor;
+
+const DEFAULT_API_URL: &str = "https://api.losant.com";
+
+#[derive(thiserror::Error, Debug)]
+pub enum LosantError {
+    #[error("Unexpected time stamp within a group")]
+    UnexpectedTimestamp,
+
+    #[error("Unexpected start of group")]
+    UnexpectedStartOfGroup,
+
+    #[error("Unexpected end of group")]
+    UnexpectedEndOfGroup,
+
+    #[error("Invalid value for {name:?}: Losant numbers must be finite")]
+    InvalidValue { name: String },
+}
+
+/// The attributes of a device state, as gathered from the measurements
+struct State {
+    timestamp: Option<DateTime<FixedOffset>>,
+    data: Map<String, Value>,
+}
+
+/// A visitor that POSTs the measurements as device states to the Losant REST API.
+///
+/// The measurements are the attributes of the `data` of a state:
+///
+/// ```json
+/// {"data":{"temperature":25.5,"location_alti":2100.4},"time":"2021-04-08T10:00:00.000Z"}
+/// ```
+///
+/// * The measurements of a group are given as `<group>_<name>` attributes,
+///   Losant attributes being flat.
+/// * A complex measurement is given as two attributes, `<name>_re` and `<name>_im`.
+/// * The units and the qualities are not sent, nor the null measurements.
+/// * The time of a state is the timestamp of the measurements, in UTC,
+///   and is omitted when the measurements have no timestamp, Losant then using the reception time.
+/// * The measurements of a group with a timestamp of its own are sent as a state of their own,
+///   several states being sent as an array.
+///
+/// The states are sent on `flush()` by a single `POST /applications/<app>/devices/<device>/state`
+/// request, authenticated by a device or application API token.
+/// The HTTP client, and hence its pool of connections, is kept from one flush to the next.
+pub struct LosantStateVisitor {
+    client: reqwest::Client,
+    url: Url,
+    headers: HeaderMap,
+    application_id: String,
+    device_id: String,
+    timestamp: Option<DateTime<FixedOffset>>,
+    data: Map<String, Value>,
+    group: Option<(String, Option<State>)>,
+    group_states: Vec<State>,
+}
+
+impl LosantStateVisitor {
+    /// Send the states of a device to the Losant cloud, authenticated by the given API token.
+    pub fn new(
+        application_id: &str,
+        device_id: &str,
+        api_token: &str,
+    ) -> Result<Self, HttpSinkError> {
+        let mut authorization = HeaderValue::from_str(&format!("Bearer {}", api_token))?;
+        authorization.set_sensitive(true);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert(AUTHORIZATION, authorization);
+
+        let api_url = Url::parse(DEFAULT_API_URL).expect("A valid URL");
+        Ok(Self {
+            client: reqwest::Client::new(),
+            url: state_url(api_url, application_id, device_id),
+            headers,
+            application_id: application_id.to_string(),
+            device_id: device_id.to_string(),
+            timestamp: None,
+            data: Map::new(),
+            group: None,
+            group_states: vec![],
+        })
+    }
+
+    /// Set the URL of the Losant API, `https://api.losant.com` by default.
+    pub fn with_api_url(self, api_url: Url) -> Self {
+        let url = state_url(api_url, &self.application_id, &self.device_id);
+        Self { url, ..self }
+    }
+
+    /// Use the given HTTP client, sharing its pool of connections with other visitors.
+    pub fn with_client(self, client: reqwest::Client) -> Self {
+        Self { client, ..self }
+    }
+
+    /// Send the states gathered since the previous flush.
+    ///
+    /// Nothing is sent if no measurements have been gathered.
+    /// A failed request is not retried, the states being discarded.
+    pub async fn flush(&mut self) -> Result<(), HttpSinkError> {
+        let body = match self.take_states() {
+            Some(body) => body,
+            None => return Ok(()),
+        };
+
+        let response = self
+            .client
+            .post(self.url.clone())
+            .headers(self.headers.clone())
+            .body(body.to_string())
+            .send()
+            .await?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(HttpSinkError::ServerError { status })
+        }
+    }
+
+    /// The states gathered so far, if any, as a single state or as an array of states
+    fn take_states(&mut self) -> Option<Value> {
+        self.group = None;
+        let state = State {
+            timestamp: self.timestamp.take(),
+            data: std::mem::take(&mut self.data),
+        };
+        let group_states = std::mem::take(&mut self.group_states);
+
+        let mut states: Vec<Value> = std::iter::once(state)
+            .chain(group_states)
+            .filter(|state| !state.data.is_empty())
+            .map(|state| {
+                let mut body = Map::new();
+                body.insert("data".into(), Value::Object(state.data));
+                if let Some(timestamp) = state.timestamp {
+                    body.insert("time".into(), Value::from(utc_time(timestamp)));
+                }
+                Value::Object(body)
+            })
+            .collect();
+
+        match states.len() {
+            0 => None,
+            1 => states.pop(),
+            _ => Some(Value::Array(states)),
+        }
+    }
+
+    fn add_attribute(&mut self, name: &str, value: f64) -> Result<(), LosantError> {
+        if !value.is_finite() {
+            return Err(LosantError::InvalidValue { name: name.into() });
+        }
+        let (name, data) = match self.group.as_mut() {
+            Some((group, Some(state))) => (format!("{}_{}", group, name), &mut state.data),
+            Some((group, None)) => (format!("{}_{}", group, name), &mut self.data),
+            None => (name.to_string(), &mut self.data),
+        };
+        data.insert(name, json!(value));
+        Ok(())
+    }
+
+    fn start_state(
+        &mut self,
+        group: &str,
+        timestamp: Option<DateTime<FixedOffset>>,
+    ) -> Result<(), LosantError> {
+        if self.group.is_some() {
+            return Err(LosantError::UnexpectedStartOfGroup);
+        }
+        let state = timestamp.map(|timestamp| State {
+            timestamp: Some(timestamp),
+            data: Map::new(),
+        });
+        self.group = Some((group.to_string(), state));
+        Ok(())
+    }
+}
+
+fn state_url(api_url: Url, application_id: &str, device_id: &str) -> Url {
+    let mut url = api_url;
+    let path = format!(
+        "{}/applications/{}/devices/{}/state",
+        url.path().trim_end_matches('/'),
+        application_id,
+        device_id
+    );
+    url.set_path(&path);
+    url
+}
+
+fn utc_time(timestamp: DateTime<FixedOffset>) -> String {
+    timestamp
+        .with_timezone(&Utc)
+        .to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+impl GroupedMeasurementVisitor for LosantStateVisitor {
+    type Error = LosantError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        if self.group.is_some() {
+            return Err(LosantError::UnexpectedTimestamp);
+        }
+        self.timestamp = Some(value);
+        Ok(())
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.add_attribute(name, value)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.start_state(group, None)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        match self.group.take() {
+            Some((_, Some(state))) => self.group_states.push(state),
+            Some((_, None)) => {}
+            None => return Err(LosantError::UnexpectedEndOfGroup),
+        }
+        Ok(())
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        _unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.add_attribute(name, value)
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.start_state(group, Some(timestamp))
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        match value {
+            Some(value) => self.add_attribute(name, value),
+            None => Ok(()),
+        }
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.add_attribute(&format!("{}_re", name), real)?;
+        self.add_attribute(&format!("{}_im", name), imaginary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use chrono::TimeZone;
+    use thin_edge_json::measurement::MeasurementQuality;
+    use wiremock::matchers::{body_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn visitor(server: &MockServer) -> anyhow::Result<LosantStateVisitor> {
+        Ok(
+            LosantStateVisitor::new("app-1", "device-1", "secret-token")?
+                .with_api_url(Url::parse(&server.uri())?),
+        )
+    }
+
+    fn device_state() -> wiremock::MockBuilder {
+        Mock::given(method("POST"))
+            .and(path("/applications/app-1/devices/device-1/state"))
+            .and(header("content-type", "application/json"))
+            .and(header("authorization", "Bearer secret-token"))
+    }
+
+    #[tokio::test]
+    async fn measurements_are_sent_as_a_device_state() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        device_state()
+            .and(body_json(json!({
+                "data": {
+                    "temperature": 25.5,
+                    "location_alti": 2100.4,
+                    "location_longi": 2200.4,
+                    "current_re": 1.5,
+                    "current_im": -0.5,
+                    "speed": 3000.0,
+                },
+                "time": "2021-04-08T08:00:00.000Z",
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut visitor = visitor(&server)?;
+        visitor.timestamp(
+            FixedOffset::east(2 * 3600)
+                .ymd(2021, 4, 8)
+                .and_hms(10, 0, 0),
+        )?;
+        visitor.measurement_with_unit("temperature", 25.5, "°C")?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.measurement("longi", 2200.4)?;
+        visitor.end_group()?;
+        visitor.complex_measurement("current", 1.5, -0.5)?;
+        visitor.annotated_measurement("speed", 3000.0, MeasurementQuality::Good)?;
+        visitor.nullable_measurement("pressure", None)?;
+        visitor.flush().await?;
+
+        // Nothing is sent when there is no measurements
+        visitor.flush().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn groups_with_a_timestamp_are_states_of_their_own() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        device_state()
+            .and(body_json(json!([
+                {"data": {"temperature": 25.5}},
+                {"data": {"engine_speed": 3000.0}, "time": "2021-04-08T10:00:00.000Z"},
+            ])))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut visitor = visitor(&server)?;
+        visitor.measurement("temperature", 25.5)?;
+        visitor.start_group_with_timestamp(
+            "engine",
+            FixedOffset::east(0).ymd(2021, 4, 8).and_hms(10, 0, 0),
+        )?;
+        visitor.measurement("speed", 3000.0)?;
+        visitor.end_group()?;
+        visitor.flush().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejected_states_are_reported() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        device_state()
+            .respond_with(ResponseTemplate::new(401))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut visitor = visitor(&server)?;
+        visitor.measurement("temperature", 25.5)?;
+
+        assert_matches!(
+            visitor.flush().await,
+            Err(HttpSinkError::ServerError { status }) if status.as_u16() == 401
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn states_are_posted_to_the_losant_api_by_default() -> anyhow::Result<()> {
+        let visitor = LosantStateVisitor::new("app-1", "device-1", "secret-token")?;
+
+        assert_eq!(
+            visitor.url.as_str(),
+            "https://api.losant.com/applications/app-1/devices/device-1/state"
+        );
+        assert!(visitor.headers[AUTHORIZATION].is_sensitive());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_input_is_rejected() -> anyhow::Result<()> {
+        assert_matches!(
+            LosantStateVisitor::new("app-1", "device-1", "secret\ntoken"),
+            Err(HttpSinkError::InvalidHeaderValue(_))
+        );
+
+        let mut visitor = LosantStateVisitor::new("app-1", "device-1", "secret-token")?;
+        assert_matches!(
+            visitor.measurement("temperature", f64::NAN),
+            Err(LosantError::InvalidValue { name }) if name == "temperature"
+        );
+        assert_matches!(visitor.end_group(), Err(LosantError::UnexpectedEndOfGroup));
+        Ok(())
+    }
+}
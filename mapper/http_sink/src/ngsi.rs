@@ -0,0 +1,455 @@
+use crate::post::HttpSinkError;
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use reqwest::Url;
+use serde_json::{json, Map, Value};
+use thin_edge_json::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+
+const DEFAULT_ENTITY_TYPE: &str = "Device";
+
+/// The attribute giving the time of the measurements of an entity, as by the FIWARE IoT agents
+const TIMESTAMP_ATTRIBUTE: &str = "TimeInstant";
+
+#[derive(thiserror::Error, Debug)]
+pub enum NgsiError {
+    #[error("Unexpected time stamp within a group")]
+    UnexpectedTimestamp,
+
+    #[error("Unexpected start of group")]
+    UnexpectedStartOfGroup,
+
+    #[error("Unexpected end of group")]
+    UnexpectedEndOfGroup,
+
+    #[error("Invalid value for {name:?}: NGSI numbers must be finite")]
+    InvalidValue { name: String },
+}
+
+/// The attributes of an entity, as gathered from the measurements
+struct Entity {
+    entity_type: String,
+    timestamp: Option<DateTime<FixedOffset>>,
+    attributes: Map<String, Value>,
+}
+
+/// A visitor that POSTs the measurements as entities to the batch update API of a FIWARE NGSI v2 broker.
+///
+/// * The top-level measurements are the attributes of the entity whose id is the device id,
+///   and whose type is `Device` unless set otherwise.
+/// * The measurements of a group are the attributes of the entity
+///   having the same id, but the group name as type.
+/// * A measurement is an attribute of type `Number`, the unit and the quality being given
+///   as `unitCode` and `quality` metadata. A complex value is a `StructuredValue`.
+/// * The timestamp of the measurements is given by a `TimeInstant` attribute.
+///
+/// The entities are sent on `flush()` by a single `POST /v2/op/update` request,
+/// with `append` as action type, i.e. creating the entities and attributes as needed.
+pub struct FiwareNgsiV2Visitor {
+    client: reqwest::Client,
+    url: Url,
+    headers: HeaderMap,
+    device_id: String,
+    entity_type: String,
+    timestamp: Option<DateTime<FixedOffset>>,
+    attributes: Map<String, Value>,
+    groups: Vec<Entity>,
+    group: Option<Entity>,
+}
+
+impl FiwareNgsiV2Visitor {
+    /// Send the entities of a device to the NGSI v2 broker at the given URL,
+    /// as `http://orion:1026`.
+    pub fn new(broker_url: Url, device_id: &str) -> Self {
+        let mut url = broker_url;
+        let path = format!("{}/v2/op/update", url.path().trim_end_matches('/'));
+        url.set_path(&path);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+        Self {
+            client: reqwest::Client::new(),
+            url,
+            headers,
+            device_id: device_id.to_string(),
+            entity_type: DEFAULT_ENTITY_TYPE.to_string(),
+            timestamp: None,
+            attributes: Map::new(),
+            groups: vec![],
+            group: None,
+        }
+    }
+
+    /// Set the type of the entity holding the top-level measurements, `Device` by default.
+    pub fn with_entity_type(self, entity_type: &str) -> Self {
+        Self {
+            entity_type: entity_type.to_string(),
+            ..self
+        }
+    }
+
+    /// Set the tenant and the service path of the entities,
+    /// as the `Fiware-Service` and `Fiware-ServicePath` headers.
+    pub fn with_service(
+        mut self,
+        service: &str,
+        service_path: &str,
+    ) -> Result<Self, HttpSinkError> {
+        self.headers.insert(
+            HeaderName::from_static("fiware-service"),
+            HeaderValue::from_str(service)?,
+        );
+        self.headers.insert(
+            HeaderName::from_static("fiware-servicepath"),
+            HeaderValue::from_str(service_path)?,
+        );
+        Ok(self)
+    }
+
+    /// Send the entities gathered since the previous flush.
+    ///
+    /// Nothing is sent if no measurements have been gathered.
+    /// A failed request is not retried, the entities being discarded.
+    pub async fn flush(&mut self) -> Result<(), HttpSinkError> {
+        let body = self.take_batch();
+        let body = match body {
+            Some(body) => body,
+            None => return Ok(()),
+        };
+
+        let response = self
+            .client
+            .post(self.url.clone())
+            .headers(self.headers.clone())
+            .body(body.to_string())
+            .send()
+            .await?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(HttpSinkError::ServerError { status })
+        }
+    }
+
+    /// The batch update request for the entities gathered so far, if any
+    fn take_batch(&mut self) -> Option<Value> {
+        self.group = None;
+        let device = Entity {
+            entity_type: self.entity_type.clone(),
+            timestamp: None,
+            attributes: std::mem::take(&mut self.attributes),
+        };
+        let groups = std::mem::take(&mut self.groups);
+        let timestamp = self.timestamp.take();
+
+        let entities: Vec<Value> = std::iter::once(device)
+            .chain(groups)
+            .filter(|entity| !entity.attributes.is_empty())
+            .map(|entity| {
+                let mut body = Map::new();
+                body.insert("id".into(), Value::from(self.device_id.as_str()));
+                body.insert("type".into(), Value::from(entity.entity_type));
+                body.extend(entity.attributes);
+                if let Some(timestamp) = entity.timestamp.or(timestamp) {
+                    body.insert(
+                        TIMESTAMP_ATTRIBUTE.into(),
+                        json!({"type": "DateTime", "value": timestamp.to_rfc3339()}),
+                    );
+                }
+                Value::Object(body)
+            })
+            .collect();
+
+        if entities.is_empty() {
+            None
+        } else {
+            Some(json!({"actionType": "append", "entities": entities}))
+        }
+    }
+
+    fn add_attribute(&mut self, name: &str, attribute: Value) {
+        let attributes = match self.group.as_mut() {
+            Some(group) => &mut group.attributes,
+            None => &mut self.attributes,
+        };
+        attributes.insert(name.to_string(), attribute);
+    }
+
+    fn start_entity(
+        &mut self,
+        group: &str,
+        timestamp: Option<DateTime<FixedOffset>>,
+    ) -> Result<(), NgsiError> {
+        if self.group.is_some() {
+            return Err(NgsiError::UnexpectedStartOfGroup);
+        }
+        self.group = Some(Entity {
+            entity_type: group.to_string(),
+            timestamp,
+            attributes: Map::new(),
+        });
+        Ok(())
+    }
+}
+
+fn number(name: &str, value: f64) -> Result<Value, NgsiError> {
+    if value.is_finite() {
+        Ok(Value::from(value))
+    } else {
+        Err(NgsiError::InvalidValue { name: name.into() })
+    }
+}
+
+impl GroupedMeasurementVisitor for FiwareNgsiV2Visitor {
+    type Error = NgsiError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        if self.group.is_some() {
+            return Err(NgsiError::UnexpectedTimestamp);
+        }
+        self.timestamp = Some(value);
+        Ok(())
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        let value = number(name, value)?;
+        self.add_attribute(name, json!({"type": "Number", "value": value}));
+        Ok(())
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.start_entity(group, None)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        let group = self.group.take().ok_or(NgsiError::UnexpectedEndOfGroup)?;
+        self.groups.push(group);
+        Ok(())
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        let value = number(name, value)?;
+        self.add_attribute(
+            name,
+            json!({
+                "type": "Number",
+                "value": value,
+                "metadata": {"unitCode": {"type": "Text", "value": unit}},
+            }),
+        );
+        Ok(())
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.start_entity(group, Some(timestamp))
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        let value = match value {
+            Some(value) => number(name, value)?,
+            None => Value::Null,
+        };
+        self.add_attribute(name, json!({"type": "Number", "value": value}));
+        Ok(())
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        let value = json!({"re": number(name, real)?, "im": number(name, imaginary)?});
+        self.add_attribute(name, json!({"type": "StructuredValue", "value": value}));
+        Ok(())
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        let value = number(name, value)?;
+        self.add_attribute(
+            name,
+            json!({
+                "type": "Number",
+                "value": value,
+                "metadata": {"quality": {"type": "Text", "value": quality.as_str()}},
+            }),
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use chrono::TimeZone;
+    use wiremock::matchers::{body_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn visitor(server: &MockServer) -> anyhow::Result<FiwareNgsiV2Visitor> {
+        Ok(FiwareNgsiV2Visitor::new(
+            Url::parse(&server.uri())?,
+            "device-1",
+        ))
+    }
+
+    fn batch_update() -> wiremock::MockBuilder {
+        Mock::given(method("POST"))
+            .and(path("/v2/op/update"))
+            .and(header("content-type", "application/json"))
+    }
+
+    #[tokio::test]
+    async fn measurements_are_sent_as_entity_attributes() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        batch_update()
+            .and(header("fiware-service", "smartcity"))
+            .and(header("fiware-servicepath", "/plant"))
+            .and(body_json(json!({
+                "actionType": "append",
+                "entities": [
+                    {
+                        "id": "device-1",
+                        "type": "Device",
+                        "temperature": {
+                            "type": "Number",
+                            "value": 25.5,
+                            "metadata": {"unitCode": {"type": "Text", "value": "°C"}},
+                        },
+                        "pressure": {"type": "Number", "value": null},
+                        "TimeInstant": {"type": "DateTime", "value": "2021-04-08T10:00:00+00:00"},
+                    },
+                    {
+                        "id": "device-1",
+                        "type": "location",
+                        "alti": {"type": "Number", "value": 2100.4},
+                        "TimeInstant": {"type": "DateTime", "value": "2021-04-08T10:00:00+00:00"},
+                    },
+                ],
+            })))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut visitor = visitor(&server)?.with_service("smartcity", "/plant")?;
+        visitor.timestamp(FixedOffset::east(0).ymd(2021, 4, 8).and_hms(10, 0, 0))?;
+        visitor.measurement_with_unit("temperature", 25.5, "°C")?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.end_group()?;
+        visitor.nullable_measurement("pressure", None)?;
+        visitor.flush().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn groups_are_entities_of_their_own_type() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        batch_update()
+            .and(body_json(json!({
+                "actionType": "append",
+                "entities": [
+                    {
+                        "id": "device-1",
+                        "type": "engine",
+                        "current": {"type": "StructuredValue", "value": {"re": 1.5, "im": -0.5}},
+                        "speed": {
+                            "type": "Number",
+                            "value": 3000.0,
+                            "metadata": {"quality": {"type": "Text", "value": "UNCERTAIN"}},
+                        },
+                        "TimeInstant": {"type": "DateTime", "value": "2021-04-08T12:00:00+02:00"},
+                    },
+                ],
+            })))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut visitor = visitor(&server)?;
+        visitor.start_group_with_timestamp(
+            "engine",
+            FixedOffset::east(2 * 3600)
+                .ymd(2021, 4, 8)
+                .and_hms(12, 0, 0),
+        )?;
+        visitor.complex_measurement("current", 1.5, -0.5)?;
+        visitor.annotated_measurement("speed", 3000.0, MeasurementQuality::Uncertain)?;
+        visitor.end_group()?;
+        visitor.flush().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn the_entity_type_of_the_device_can_be_set() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        batch_update()
+            .and(body_json(json!({
+                "actionType": "append",
+                "entities": [
+                    {"id": "device-1", "type": "WeatherObserved", "temperature": {"type": "Number", "value": 25.5}},
+                ],
+            })))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut visitor = visitor(&server)?.with_entity_type("WeatherObserved");
+        visitor.measurement("temperature", 25.5)?;
+        visitor.flush().await?;
+
+        // Nothing is sent when there is no measurements
+        visitor.flush().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejected_batches_are_reported() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        batch_update()
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut visitor = visitor(&server)?;
+        visitor.measurement("temperature", 25.5)?;
+
+        assert_matches!(
+            visitor.flush().await,
+            Err(HttpSinkError::ServerError { status }) if status.as_u16() == 422
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn non_finite_values_are_rejected() -> anyhow::Result<()> {
+        let mut visitor = FiwareNgsiV2Visitor::new(Url::parse("http://orion:1026")?, "device-1");
+
+        assert_matches!(
+            visitor.measurement("temperature", f64::NAN),
+            Err(NgsiError::InvalidValue { name }) if name == "temperature"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,332 @@
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use log::warn;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use reqwest::{StatusCode, Url};
+use std::time::Duration;
+use thin_edge_json::context::MeasurementContext;
+use thin_edge_json::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use thin_edge_json::serialize::{ThinEdgeJsonSerializationError, ThinEdgeJsonSerializer};
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(thiserror::Error, Debug)]
+pub enum HttpSinkError {
+    #[error(transparent)]
+    InvalidHeaderName(#[from] reqwest::header::InvalidHeaderName),
+
+    #[error(transparent)]
+    InvalidHeaderValue(#[from] reqwest::header::InvalidHeaderValue),
+
+    #[error("Failed to post the measurements: {0}")]
+    RequestError(#[from] reqwest::Error),
+
+    #[error("The server failed to process the measurements: {status}")]
+    ServerError { status: StatusCode },
+
+    #[error(transparent)]
+    SerializationError(#[from] ThinEdgeJsonSerializationError),
+}
+
+/// A visitor that POSTs the measurements as thin-edge JSON to an HTTP endpoint.
+///
+/// The measurements are gathered into a thin-edge JSON payload which is sent on `flush()`.
+///
+/// * A request failing with a 5xx status or with no response at all is retried,
+///   up to 3 attempts, waiting twice longer before each new attempt.
+/// * A request rejected with a 4xx status is not retried:
+///   the error is logged and the payload discarded.
+pub struct HttpPostVisitor {
+    client: reqwest::Client,
+    url: Url,
+    headers: HeaderMap,
+    max_attempts: u32,
+    initial_backoff: Duration,
+    serializer: ThinEdgeJsonSerializer,
+    is_empty: bool,
+}
+
+impl HttpPostVisitor {
+    pub fn new(url: Url) -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+        Self {
+            client: reqwest::Client::new(),
+            url,
+            headers,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            serializer: ThinEdgeJsonSerializer::new(),
+            is_empty: true,
+        }
+    }
+
+    /// Add a header to all the requests, as `Authorization: Bearer <token>`.
+    ///
+    /// The value of an `Authorization` header is never displayed.
+    pub fn with_header(mut self, name: &str, value: &str) -> Result<Self, HttpSinkError> {
+        let name = HeaderName::from_bytes(name.as_bytes())?;
+        let mut value = HeaderValue::from_str(value)?;
+        if name == AUTHORIZATION {
+            value.set_sensitive(true);
+        }
+        self.headers.insert(name, value);
+        Ok(self)
+    }
+
+    /// Set the delay before the first retry, the delay being doubled for each subsequent retry.
+    pub fn with_initial_backoff(self, initial_backoff: Duration) -> Self {
+        Self {
+            initial_backoff,
+            ..self
+        }
+    }
+
+    /// Set the maximum number of attempts to post a payload.
+    pub fn with_max_attempts(self, max_attempts: u32) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            ..self
+        }
+    }
+
+    /// Post the measurements gathered since the previous flush.
+    ///
+    /// Nothing is sent if no measurements have been gathered.
+    pub async fn flush(&mut self) -> Result<(), HttpSinkError> {
+        let mut serializer = std::mem::take(&mut self.serializer);
+        let is_empty = std::mem::replace(&mut self.is_empty, true);
+        let payload = serializer.into_string()?;
+        if is_empty {
+            return Ok(());
+        }
+
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            match self.post(&payload).await {
+                Ok(()) => return Ok(()),
+                Err(HttpSinkError::ServerError { status }) if status.is_client_error() => {
+                    warn!("Measurements rejected by {}: {}", self.url, status);
+                    return Ok(());
+                }
+                Err(err) if attempt < self.max_attempts => {
+                    warn!(
+                        "Attempt {} to post measurements to {} failed: {}",
+                        attempt, self.url, err
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    async fn post(&self, payload: &str) -> Result<(), HttpSinkError> {
+        let response = self
+            .client
+            .post(self.url.clone())
+            .headers(self.headers.clone())
+            .body(payload.to_string())
+            .send()
+            .await?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(HttpSinkError::ServerError { status })
+        }
+    }
+}
+
+impl GroupedMeasurementVisitor for HttpPostVisitor {
+    type Error = ThinEdgeJsonSerializationError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.serializer.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.measurement(name, value)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.serializer.start_group(group)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.serializer.end_group()
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.measurement_with_unit(name, value, unit)
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.serializer.start_group_with_timestamp(group, timestamp)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.nullable_measurement(name, value)
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.complex_measurement(name, real, imaginary)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.annotated_measurement(name, value, quality)
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.serializer.set_context(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use wiremock::matchers::{body_string, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn visitor(server: &MockServer) -> anyhow::Result<HttpPostVisitor> {
+        let url = Url::parse(&format!("{}/measurements", server.uri()))?;
+        Ok(HttpPostVisitor::new(url).with_initial_backoff(Duration::from_millis(10)))
+    }
+
+    fn post_measurements() -> wiremock::MockBuilder {
+        Mock::given(method("POST"))
+            .and(path("/measurements"))
+            .and(header("content-type", "application/json"))
+    }
+
+    #[tokio::test]
+    async fn measurements_are_posted_as_thin_edge_json() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        post_measurements()
+            .and(header("authorization", "Bearer secret-token"))
+            .and(body_string(
+                r#"{"temperature":25.5,"location":{"alti":2100.4}}"#,
+            ))
+            .respond_with(ResponseTemplate::new(201))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut visitor = visitor(&server)?.with_header("Authorization", "Bearer secret-token")?;
+        visitor.measurement("temperature", 25.5)?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.end_group()?;
+        visitor.flush().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        post_measurements()
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&server)
+            .await;
+        post_measurements()
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut visitor = visitor(&server)?;
+        visitor.measurement("temperature", 25.5)?;
+        visitor.flush().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retries_are_abandoned_after_three_attempts() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        post_measurements()
+            .respond_with(ResponseTemplate::new(500))
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let mut visitor = visitor(&server)?;
+        visitor.measurement("temperature", 25.5)?;
+
+        assert_matches!(
+            visitor.flush().await,
+            Err(HttpSinkError::ServerError { status }) if status == StatusCode::INTERNAL_SERVER_ERROR
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        post_measurements()
+            .respond_with(ResponseTemplate::new(400))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut visitor = visitor(&server)?;
+        visitor.measurement("temperature", 25.5)?;
+        visitor.flush().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn nothing_is_posted_when_there_is_no_measurements() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        post_measurements()
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let mut visitor = visitor(&server)?;
+        visitor.flush().await?;
+        Ok(())
+    }
+
+    #[test]
+    fn authorization_headers_are_not_displayed() -> anyhow::Result<()> {
+        let visitor = HttpPostVisitor::new(Url::parse("http://localhost/measurements")?)
+            .with_header("Authorization", "Bearer secret-token")?;
+
+        assert!(!format!("{:?}", visitor.headers).contains("secret-token"));
+        Ok(())
+    }
+}
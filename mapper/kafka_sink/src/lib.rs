@@ -0,0 +1,25 @@
+//! A sink producing thin-edge JSON measurements to Apache Kafka topics.
+//!
+//! ```no_run
+//! use kafka_sink::{Acks, Compression, KafkaSinkVisitor};
+//! use thin_edge_json::measurement::GroupedMeasurementVisitor;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), anyhow::Error> {
+//! let mut visitor = KafkaSinkVisitor::new("localhost:9092", "device-1")
+//!     .with_topic(|group, device_id| format!("tedge.{}.{}", device_id, group))
+//!     .with_acks(Acks::All)
+//!     .with_compression(Compression::Lz4);
+//!
+//! visitor.start_group("location")?;
+//! visitor.measurement("alti", 2100.4)?;
+//! visitor.end_group()?;
+//! visitor.flush().await?; // Produces `{"location":{"alti":2100.4}}` to `tedge.device-1.location`
+//!
+//! visitor.wait_for_deliveries().await?;
+//! # Ok(()) }
+//! ```
+
+mod produce;
+
+pub use produce::{default_topic, Acks, Compression, KafkaSinkError, KafkaSinkVisitor};
@@ -0,0 +1,416 @@
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use log::warn;
+use rdkafka::config::ClientConfig;
+use rdkafka::error::KafkaError;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::util::Timeout;
+use std::sync::{Arc, Mutex};
+use thin_edge_json::buffer::MeasurementBuffer;
+use thin_edge_json::context::MeasurementContext;
+use thin_edge_json::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use thin_edge_json::serialize::{
+    MeasurementStreamError, ThinEdgeJsonSerializationError, ThinEdgeJsonSerializer,
+};
+use tokio::sync::{AcquireError, Semaphore};
+
+const DEFAULT_MAX_IN_FLIGHT: u32 = 100;
+const DEFAULT_TOPIC: &str = "tedge.measurements";
+
+#[derive(thiserror::Error, Debug)]
+pub enum KafkaSinkError {
+    #[error("Failed to create the Kafka producer: {0}")]
+    ProducerError(#[from] KafkaError),
+
+    #[error("Failed to deliver the measurements to the topic {topic:?}: {error}")]
+    DeliveryError { topic: String, error: KafkaError },
+
+    #[error(transparent)]
+    SerializationError(#[from] ThinEdgeJsonSerializationError),
+
+    #[error("The Kafka sink is closed")]
+    Closed(#[from] AcquireError),
+}
+
+/// The number of acknowledgments the partition leader must have received
+/// before a message is considered delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Acks {
+    /// No acknowledgment is awaited
+    None,
+
+    /// The leader acknowledges the message once written to its local log
+    Leader,
+
+    /// The leader acknowledges the message once replicated to all the in-sync replicas
+    All,
+}
+
+impl Acks {
+    fn as_config(&self) -> &'static str {
+        match self {
+            Acks::None => "0",
+            Acks::Leader => "1",
+            Acks::All => "all",
+        }
+    }
+}
+
+/// The codec used to compress the batches of messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Snappy,
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    fn as_config(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Snappy => "snappy",
+            Compression::Lz4 => "lz4",
+            Compression::Zstd => "zstd",
+        }
+    }
+}
+
+/// The topic used by default: `tedge.measurements`, whatever the group and the device.
+pub fn default_topic(_group: &str, _device_id: &str) -> String {
+    DEFAULT_TOPIC.to_string()
+}
+
+/// A visitor that produces the measurements as thin-edge JSON messages to Kafka topics.
+///
+/// The measurements are gathered and produced on `flush()`,
+/// one message for the top-level measurements and one message per group,
+/// all the messages sharing the timestamp and the trace context of the measurements, if any.
+///
+/// * The topic of each message is given by a function of the group and of the device id,
+///   the group being empty for the top-level measurements.
+///   All the messages are produced to `tedge.measurements` unless set with `with_topic()`.
+/// * The messages are keyed by the device id,
+///   so the messages of a device are kept in order on a partition.
+/// * `flush()` returns as soon as the messages are handed over to the producer.
+///   The number of messages waiting for a delivery report is bounded:
+///   when the bound is reached, `flush()` waits for the delivery of previous messages.
+/// * The delivery failures are reported by `wait_for_deliveries()`,
+///   which waits for all the messages in flight to be delivered.
+///
+/// The Kafka producer is created on the first flush.
+/// Any [producer config](https://github.com/edenhill/librdkafka/blob/master/CONFIGURATION.md)
+/// can be set with `with_producer_config()`.
+pub struct KafkaSinkVisitor {
+    config: ClientConfig,
+    device_id: String,
+    topic: Box<dyn Fn(&str, &str) -> String + Send + Sync>,
+    producer: Option<FutureProducer>,
+    max_in_flight: u32,
+    in_flight: Arc<Semaphore>,
+    failures: Arc<Mutex<Vec<KafkaSinkError>>>,
+    buffer: MeasurementBuffer,
+}
+
+impl KafkaSinkVisitor {
+    /// Produce the measurements of a device to a Kafka cluster,
+    /// the bootstrap servers being given as a comma-separated list of `host:port`.
+    pub fn new(bootstrap_servers: &str, device_id: &str) -> Self {
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", bootstrap_servers);
+
+        Self {
+            config,
+            device_id: device_id.to_string(),
+            topic: Box::new(default_topic),
+            producer: None,
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            in_flight: Arc::new(Semaphore::new(DEFAULT_MAX_IN_FLIGHT as usize)),
+            failures: Arc::new(Mutex::new(Vec::new())),
+            buffer: MeasurementBuffer::new(),
+        }
+    }
+
+    /// Set the function giving the topic of a message from its group and the device id.
+    pub fn with_topic<F>(self, topic: F) -> Self
+    where
+        F: Fn(&str, &str) -> String + Send + Sync + 'static,
+    {
+        Self {
+            topic: Box::new(topic),
+            ..self
+        }
+    }
+
+    /// Set the acknowledgments required for a message to be delivered (`acks`).
+    pub fn with_acks(self, acks: Acks) -> Self {
+        self.with_producer_config("acks", acks.as_config())
+    }
+
+    /// Set the compression codec of the message batches (`compression.type`).
+    pub fn with_compression(self, compression: Compression) -> Self {
+        self.with_producer_config("compression.type", compression.as_config())
+    }
+
+    /// Set the maximum size in bytes of a message batch (`batch.size`).
+    pub fn with_batch_size(self, batch_size: usize) -> Self {
+        self.with_producer_config("batch.size", &batch_size.to_string())
+    }
+
+    /// Set any librdkafka producer config, as `linger.ms` or `message.timeout.ms`.
+    pub fn with_producer_config(mut self, key: &str, value: &str) -> Self {
+        self.config.set(key, value);
+        self
+    }
+
+    /// Set the maximum number of messages waiting for a delivery report, 100 by default.
+    pub fn with_max_in_flight(self, max_in_flight: u32) -> Self {
+        let max_in_flight = max_in_flight.max(1);
+        Self {
+            max_in_flight,
+            in_flight: Arc::new(Semaphore::new(max_in_flight as usize)),
+            ..self
+        }
+    }
+
+    /// Produce the measurements gathered since the previous flush.
+    ///
+    /// Nothing is sent if no measurements have been gathered.
+    pub async fn flush(&mut self) -> Result<(), KafkaSinkError> {
+        let buffer = std::mem::take(&mut self.buffer);
+        let producer = self.producer()?;
+
+        for (group, payload) in split_by_group(&buffer)? {
+            let topic = (self.topic)(group.as_deref().unwrap_or(""), &self.device_id);
+
+            let permit = self.in_flight.clone().acquire_owned().await?;
+            let producer = producer.clone();
+            let key = self.device_id.clone();
+            let failures = self.failures.clone();
+            tokio::spawn(async move {
+                let record = FutureRecord::to(&topic).key(&key).payload(&payload);
+                if let Err((error, _)) = producer.send(record, Timeout::Never).await {
+                    warn!("Failed to deliver measurements to {}: {}", topic, error);
+                    if let Ok(mut failures) = failures.lock() {
+                        failures.push(KafkaSinkError::DeliveryError { topic, error });
+                    }
+                }
+                drop(permit);
+            });
+        }
+        Ok(())
+    }
+
+    /// Wait for all the messages in flight to be delivered.
+    ///
+    /// Returns the first delivery failure since the previous call, if any.
+    pub async fn wait_for_deliveries(&mut self) -> Result<(), KafkaSinkError> {
+        let _all_permits = self.in_flight.acquire_many(self.max_in_flight).await?;
+
+        let mut failures = match self.failures.lock() {
+            Ok(mut failures) => std::mem::take(&mut *failures),
+            Err(_) => Vec::new(),
+        };
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures.swap_remove(0))
+        }
+    }
+
+    fn producer(&mut self) -> Result<FutureProducer, KafkaSinkError> {
+        match &self.producer {
+            Some(producer) => Ok(producer.clone()),
+            None => {
+                let producer: FutureProducer = self.config.create()?;
+                self.producer = Some(producer.clone());
+                Ok(producer)
+            }
+        }
+    }
+}
+
+/// Split the measurements into one thin-edge JSON payload per group,
+/// the top-level measurements being gathered into a payload of their own.
+fn split_by_group(
+    buffer: &MeasurementBuffer,
+) -> Result<Vec<(Option<String>, String)>, ThinEdgeJsonSerializationError> {
+    buffer
+        .split_by_group()?
+        .into_iter()
+        .map(|(group, message)| {
+            let mut serializer = ThinEdgeJsonSerializer::new();
+            message.replay(&mut serializer)?;
+            Ok((group.map(String::from), serializer.into_string()?))
+        })
+        .collect()
+}
+
+impl GroupedMeasurementVisitor for KafkaSinkVisitor {
+    type Error = MeasurementStreamError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        Ok(self.buffer.timestamp(value)?)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        Ok(self.buffer.measurement(name, value)?)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        Ok(self.buffer.start_group(group)?)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        Ok(self.buffer.end_group()?)
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        Ok(self.buffer.measurement_with_unit(name, value, unit)?)
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        value: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        Ok(self.buffer.start_group_with_timestamp(group, value)?)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        Ok(self.buffer.nullable_measurement(name, value)?)
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        Ok(self.buffer.complex_measurement(name, real, imaginary)?)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        Ok(self.buffer.annotated_measurement(name, value, quality)?)
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        Ok(self.buffer.set_context(context)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn producer_configs_are_set() {
+        let visitor = KafkaSinkVisitor::new("kafka-1:9092,kafka-2:9092", "device-1")
+            .with_acks(Acks::All)
+            .with_compression(Compression::Zstd)
+            .with_batch_size(32768)
+            .with_producer_config("linger.ms", "10");
+
+        assert_eq!(
+            visitor.config.get("bootstrap.servers"),
+            Some("kafka-1:9092,kafka-2:9092")
+        );
+        assert_eq!(visitor.config.get("acks"), Some("all"));
+        assert_eq!(visitor.config.get("compression.type"), Some("zstd"));
+        assert_eq!(visitor.config.get("batch.size"), Some("32768"));
+        assert_eq!(visitor.config.get("linger.ms"), Some("10"));
+    }
+
+    #[test]
+    fn topics_are_given_by_the_routing_function() {
+        let visitor = KafkaSinkVisitor::new("localhost:9092", "device-1")
+            .with_topic(|group, device_id| format!("{}.{}", device_id, group));
+
+        assert_eq!((visitor.topic)("location", "device-1"), "device-1.location");
+        assert_eq!(default_topic("location", "device-1"), "tedge.measurements");
+    }
+
+    #[tokio::test]
+    async fn in_flight_messages_are_bounded() -> anyhow::Result<()> {
+        let visitor = KafkaSinkVisitor::new("localhost:9092", "device-1").with_max_in_flight(2);
+
+        let _first = visitor.in_flight.clone().acquire_owned().await?;
+        let _second = visitor.in_flight.clone().acquire_owned().await?;
+        assert!(visitor.in_flight.clone().try_acquire_owned().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn all_kinds_of_measurements_are_produced() -> anyhow::Result<()> {
+        let timestamp = DateTime::parse_from_rfc3339("2021-04-30T17:03:14+02:00")?;
+        let mut visitor = KafkaSinkVisitor::new("localhost:9092", "device-1");
+        visitor.set_context(MeasurementContext::new([0x4b; 16], [0xf0; 8]))?;
+        visitor.timestamp(timestamp)?;
+        visitor.nullable_measurement("temperature", None)?;
+        visitor.start_group("engine")?;
+        visitor.annotated_measurement("pressure", 98.0, MeasurementQuality::Good)?;
+        visitor.end_group()?;
+        visitor.start_group_with_timestamp("phase", timestamp)?;
+        visitor.complex_measurement("current", 1.5, -0.5)?;
+        visitor.end_group()?;
+
+        assert_eq!(
+            split_by_group(&visitor.buffer)?,
+            vec![
+                (
+                    None,
+                    concat!(
+                        r#"{"time":"2021-04-30T17:03:14+02:00","#,
+                        r#""_traceId":"4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b","_spanId":"f0f0f0f0f0f0f0f0","#,
+                        r#""temperature":null}"#
+                    )
+                    .to_string()
+                ),
+                (
+                    Some("engine".to_string()),
+                    concat!(
+                        r#"{"time":"2021-04-30T17:03:14+02:00","#,
+                        r#""_traceId":"4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b","_spanId":"f0f0f0f0f0f0f0f0","#,
+                        r#""engine":{"pressure":{"value":98.0,"quality":"GOOD"}}}"#
+                    )
+                    .to_string()
+                ),
+                (
+                    Some("phase".to_string()),
+                    concat!(
+                        r#"{"time":"2021-04-30T17:03:14+02:00","#,
+                        r#""_traceId":"4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b","_spanId":"f0f0f0f0f0f0f0f0","#,
+                        r#""phase":{"time":"2021-04-30T17:03:14+02:00","current":{"re":1.5,"im":-0.5}}}"#
+                    )
+                    .to_string()
+                ),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn nothing_is_produced_when_there_is_no_measurements() -> anyhow::Result<()> {
+        let mut visitor = KafkaSinkVisitor::new("localhost:9092", "device-1");
+
+        visitor.flush().await?;
+        visitor.wait_for_deliveries().await?;
+        assert_eq!(
+            visitor.in_flight.available_permits(),
+            DEFAULT_MAX_IN_FLIGHT as usize
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+#![cfg(feature = "integration-test")]
+// These tests require a docker daemon to start a Kafka broker.
+// Run them by calling 'cargo test --features integration-test' from the base path of the crate
+
+use kafka_sink::{Acks, KafkaSinkVisitor};
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::Message;
+use std::collections::HashSet;
+use std::time::Duration;
+use testcontainers::images::generic::{GenericImage, WaitFor};
+use testcontainers::{clients, Docker, RunArgs};
+use thin_edge_json::measurement::GroupedMeasurementVisitor;
+use tokio::time::timeout;
+
+/// A single-node Kafka broker, listening on a fixed host port.
+///
+/// The port is fixed, as it has to be given to the broker for the clients to be redirected to it.
+/// Each test must then use its own port.
+fn kafka(
+    docker: &clients::Cli,
+    port: u16,
+) -> testcontainers::Container<clients::Cli, GenericImage> {
+    let image = GenericImage::new("bitnami/kafka:3.1")
+        .with_env_var("KAFKA_ENABLE_KRAFT", "yes")
+        .with_env_var("KAFKA_CFG_NODE_ID", "1")
+        .with_env_var("KAFKA_BROKER_ID", "1")
+        .with_env_var("KAFKA_CFG_PROCESS_ROLES", "broker,controller")
+        .with_env_var("KAFKA_CFG_CONTROLLER_LISTENER_NAMES", "CONTROLLER")
+        .with_env_var("KAFKA_CFG_CONTROLLER_QUORUM_VOTERS", "1@127.0.0.1:9093")
+        .with_env_var(
+            "KAFKA_CFG_LISTENERS",
+            "PLAINTEXT://:9092,CONTROLLER://:9093",
+        )
+        .with_env_var(
+            "KAFKA_CFG_ADVERTISED_LISTENERS",
+            format!("PLAINTEXT://localhost:{}", port),
+        )
+        .with_env_var("KAFKA_CFG_AUTO_CREATE_TOPICS_ENABLE", "true")
+        .with_env_var("ALLOW_PLAINTEXT_LISTENER", "yes")
+        .with_wait_for(WaitFor::message_on_stdout("Kafka Server started"));
+
+    docker.run_with_args(image, RunArgs::default().with_mapped_port((port, 9092)))
+}
+
+fn consumer(bootstrap_servers: &str, topic: &str) -> anyhow::Result<StreamConsumer> {
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("bootstrap.servers", bootstrap_servers)
+        .set("group.id", "integration-test")
+        .set("auto.offset.reset", "earliest")
+        .create()?;
+    consumer.subscribe(&[topic])?;
+    Ok(consumer)
+}
+
+async fn next_payload(consumer: &StreamConsumer) -> anyhow::Result<(String, String)> {
+    let message = timeout(Duration::from_secs(10), consumer.recv()).await??;
+    let key = String::from_utf8(message.key().unwrap_or_default().to_vec())?;
+    let payload = String::from_utf8(message.payload().unwrap_or_default().to_vec())?;
+    Ok((key, payload))
+}
+
+#[tokio::test]
+async fn all_the_measurements_are_delivered() -> anyhow::Result<()> {
+    let docker = clients::Cli::default();
+    let _broker = kafka(&docker, 19092);
+    let bootstrap_servers = "localhost:19092";
+
+    let mut visitor = KafkaSinkVisitor::new(bootstrap_servers, "device-1")
+        .with_acks(Acks::All)
+        .with_max_in_flight(10);
+    for i in 0..100 {
+        visitor.measurement("counter", i as f64)?;
+        visitor.flush().await?;
+    }
+    visitor.wait_for_deliveries().await?;
+
+    let consumer = consumer(bootstrap_servers, "tedge.measurements")?;
+    let mut received = HashSet::new();
+    for _ in 0..100 {
+        let (key, payload) = next_payload(&consumer).await?;
+        assert_eq!(key, "device-1");
+        received.insert(payload);
+    }
+
+    let expected: HashSet<String> = (0..100)
+        .map(|i| format!(r#"{{"counter":{:?}}}"#, i as f64))
+        .collect();
+    assert_eq!(received, expected);
+    Ok(())
+}
+
+#[tokio::test]
+async fn groups_are_routed_to_their_topics() -> anyhow::Result<()> {
+    let docker = clients::Cli::default();
+    let _broker = kafka(&docker, 19093);
+    let bootstrap_servers = "localhost:19093";
+
+    let mut visitor = KafkaSinkVisitor::new(bootstrap_servers, "device-1")
+        .with_topic(|group, device_id| format!("tedge.{}.{}", device_id, group));
+    visitor.measurement("temperature", 25.5)?;
+    visitor.start_group("location")?;
+    visitor.measurement("alti", 2100.4)?;
+    visitor.end_group()?;
+    visitor.flush().await?;
+    visitor.wait_for_deliveries().await?;
+
+    let consumer = consumer(bootstrap_servers, "tedge.device-1.location")?;
+    let (_, payload) = next_payload(&consumer).await?;
+    assert_eq!(payload, r#"{"location":{"alti":2100.4}}"#);
+    Ok(())
+}
+
+#[tokio::test]
+async fn delivery_failures_are_reported() -> anyhow::Result<()> {
+    // No broker is listening on this port
+    let mut visitor = KafkaSinkVisitor::new("localhost:19094", "device-1")
+        .with_producer_config("message.timeout.ms", "1000");
+    visitor.measurement("temperature", 25.5)?;
+    visitor.flush().await?;
+
+    assert!(visitor.wait_for_deliveries().await.is_err());
+    Ok(())
+}
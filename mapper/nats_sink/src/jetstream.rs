@@ -0,0 +1,184 @@
+//! The subset of the JetStream API used by the sink.
+//!
+//! JetStream is driven by requests on the `$JS.API` subjects and by plain NATS requests
+//! on the subjects captured by a stream, the server replying with JSON documents.
+//! This module builds these requests on top of the core NATS client.
+
+use async_nats::Connection;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The JetStream error code returned when a stream doesn't exist
+const STREAM_NOT_FOUND: u16 = 10059;
+
+#[derive(thiserror::Error, Debug)]
+pub enum JetStreamError {
+    #[error("No response from the server: {0}")]
+    RequestError(#[from] std::io::Error),
+
+    #[error("Invalid response from the server: {0}")]
+    InvalidResponse(#[from] serde_json::Error),
+
+    #[error("Request rejected by the server with the error {err_code}: {description}")]
+    ApiError { err_code: u16, description: String },
+}
+
+/// The storage of the messages of a stream, only file storage being used by the sink
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageType {
+    File,
+}
+
+/// The configuration of a stream, all the settings not given here being set by the server
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct StreamConfig {
+    pub name: String,
+    pub subjects: Vec<String>,
+    pub storage: StorageType,
+}
+
+/// The acknowledgement of a message stored by a stream
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct PubAck {
+    pub stream: String,
+    #[serde(rename = "seq")]
+    pub sequence: u64,
+    #[serde(default)]
+    pub duplicate: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiError {
+    err_code: u16,
+    description: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum ApiResponse<T> {
+    Err { error: ApiError },
+    Ok(T),
+}
+
+/// A JetStream context, sending its requests over a NATS connection
+pub struct JetStream {
+    connection: Connection,
+    timeout: Duration,
+}
+
+impl JetStream {
+    /// Send the requests over `connection`, waiting at most `timeout` for each response
+    pub fn new(connection: Connection, timeout: Duration) -> Self {
+        Self {
+            connection,
+            timeout,
+        }
+    }
+
+    /// Create the stream unless a stream with the same name already exists
+    pub async fn get_or_create_stream(&self, config: &StreamConfig) -> Result<(), JetStreamError> {
+        let info_subject = format!("$JS.API.STREAM.INFO.{}", config.name);
+        let info = self.request::<serde_json::Value>(&info_subject, Vec::new());
+        match info.await {
+            Err(JetStreamError::ApiError { err_code, .. }) if err_code == STREAM_NOT_FOUND => {
+                let create_subject = format!("$JS.API.STREAM.CREATE.{}", config.name);
+                let request = serde_json::to_vec(config)?;
+                self.request::<serde_json::Value>(&create_subject, request)
+                    .await?;
+                Ok(())
+            }
+            Err(err) => Err(err),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    /// Publish a message and wait for its acknowledgement by the stream capturing the subject
+    pub async fn publish(&self, subject: &str, payload: Vec<u8>) -> Result<PubAck, JetStreamError> {
+        self.request(subject, payload).await
+    }
+
+    async fn request<T: DeserializeOwned>(
+        &self,
+        subject: &str,
+        payload: Vec<u8>,
+    ) -> Result<T, JetStreamError> {
+        let response = self
+            .connection
+            .request_timeout(subject, payload, self.timeout)
+            .await?;
+        parse_response(&response.data)
+    }
+}
+
+fn parse_response<T: DeserializeOwned>(response: &[u8]) -> Result<T, JetStreamError> {
+    match serde_json::from_slice(response)? {
+        ApiResponse::Ok(value) => Ok(value),
+        ApiResponse::Err { error } => Err(JetStreamError::ApiError {
+            err_code: error.err_code,
+            description: error.description,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    #[test]
+    fn stream_configs_are_given_in_json() -> anyhow::Result<()> {
+        let config = StreamConfig {
+            name: "MEASUREMENTS".into(),
+            subjects: vec!["factory.measurements.>".into()],
+            storage: StorageType::File,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&config)?,
+            r#"{"name":"MEASUREMENTS","subjects":["factory.measurements.>"],"storage":"file"}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn acknowledgements_are_parsed() -> anyhow::Result<()> {
+        let ack: PubAck = parse_response(br#"{"stream":"MEASUREMENTS","seq":42}"#)?;
+
+        assert_eq!(
+            ack,
+            PubAck {
+                stream: "MEASUREMENTS".into(),
+                sequence: 42,
+                duplicate: false,
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn api_errors_are_reported() {
+        let response = br#"{
+            "type": "io.nats.jetstream.api.v1.stream_info_response",
+            "error": {"code": 404, "err_code": 10059, "description": "stream not found"}
+        }"#;
+
+        let result = parse_response::<serde_json::Value>(response);
+        assert_matches!(
+            result,
+            Err(JetStreamError::ApiError {
+                err_code: STREAM_NOT_FOUND,
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn empty_responses_are_rejected() {
+        assert_matches!(
+            parse_response::<PubAck>(b""),
+            Err(JetStreamError::InvalidResponse(_))
+        );
+    }
+}
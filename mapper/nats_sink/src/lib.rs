@@ -0,0 +1,29 @@
+//! A sink publishing thin-edge JSON measurements to a NATS JetStream stream.
+//!
+//! ```no_run
+//! use nats_sink::NatsJetStreamVisitor;
+//! use thin_edge_json::measurement::GroupedMeasurementVisitor;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), anyhow::Error> {
+//! let mut visitor = NatsJetStreamVisitor::connect("nats://localhost:4222", "device-1")
+//!     .await?
+//!     .with_stream_name("MEASUREMENTS")
+//!     .with_subject_prefix("factory.measurements");
+//!
+//! visitor.measurement("temperature", 25.5)?;
+//! visitor.start_group("location")?;
+//! visitor.measurement("alti", 2100.4)?;
+//! visitor.end_group()?;
+//!
+//! // Publishes `{"temperature":25.5}` on `factory.measurements.device-1`
+//! // and `{"location":{"alti":2100.4}}` on `factory.measurements.device-1.location`
+//! visitor.flush().await?;
+//! # Ok(()) }
+//! ```
+
+mod jetstream;
+mod publish;
+
+pub use jetstream::{JetStreamError, PubAck};
+pub use publish::{subject, NatsJetStreamVisitor, NatsSinkError};
@@ -0,0 +1,369 @@
+use crate::jetstream::{JetStream, JetStreamError, StorageType, StreamConfig};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use log::{debug, warn};
+use std::time::Duration;
+use thin_edge_json::buffer::MeasurementBuffer;
+use thin_edge_json::context::MeasurementContext;
+use thin_edge_json::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use thin_edge_json::serialize::{
+    MeasurementStreamError, ThinEdgeJsonSerializationError, ThinEdgeJsonSerializer,
+};
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_STREAM_NAME: &str = "TEDGE_MEASUREMENTS";
+const DEFAULT_SUBJECT_PREFIX: &str = "tedge.measurements";
+
+#[derive(thiserror::Error, Debug)]
+pub enum NatsSinkError {
+    #[error("Failed to connect to the NATS server: {0}")]
+    ConnectionError(#[source] std::io::Error),
+
+    #[error("Failed to create the JetStream stream {stream:?}: {error}")]
+    StreamError {
+        stream: String,
+        error: JetStreamError,
+    },
+
+    #[error("Failed to publish the measurements on {subject:?}: {error}")]
+    PublishError {
+        subject: String,
+        error: JetStreamError,
+    },
+
+    #[error(transparent)]
+    SerializationError(#[from] ThinEdgeJsonSerializationError),
+}
+
+/// The subject of a message: `<prefix>.<device_id>` for the top-level measurements
+/// and `<prefix>.<device_id>.<group>` for the measurements of a group.
+pub fn subject(prefix: &str, device_id: &str, group: Option<&str>) -> String {
+    match group {
+        None => format!("{}.{}", prefix, device_id),
+        Some(group) => format!("{}.{}.{}", prefix, device_id, group),
+    }
+}
+
+/// A visitor that publishes the measurements as thin-edge JSON messages to a JetStream stream.
+///
+/// The measurements are gathered and published on `flush()`,
+/// one message for the top-level measurements and one message per group,
+/// all the messages sharing the timestamp and the trace context of the measurements, if any.
+/// The subject of each message is derived from the device id and the group, see `subject()`.
+///
+/// * The stream is created on the first flush if it doesn't exist,
+///   capturing all the subjects under the subject prefix and storing the messages on file.
+/// * A message is published only once acknowledged by the JetStream server,
+///   the acknowledgement being awaited up to 5 seconds.
+/// * A failed publication is retried, up to 3 attempts, waiting twice longer before each attempt,
+///   while the client re-establishes the connection to the server in the background.
+///   The messages not published when the retries are exhausted are discarded.
+///   A message whose acknowledgement is lost can then be stored twice by the stream.
+pub struct NatsJetStreamVisitor {
+    jetstream: JetStream,
+    device_id: String,
+    stream_name: String,
+    subject_prefix: String,
+    stream_created: bool,
+    max_attempts: u32,
+    initial_backoff: Duration,
+    buffer: MeasurementBuffer,
+}
+
+impl NatsJetStreamVisitor {
+    /// Connect to the NATS server at `url`, as `nats://localhost:4222`,
+    /// to publish the measurements of the given device.
+    pub async fn connect(url: &str, device_id: &str) -> Result<Self, NatsSinkError> {
+        let connection = async_nats::connect(url)
+            .await
+            .map_err(NatsSinkError::ConnectionError)?;
+        Ok(Self {
+            jetstream: JetStream::new(connection, DEFAULT_REQUEST_TIMEOUT),
+            device_id: device_id.to_string(),
+            stream_name: DEFAULT_STREAM_NAME.to_string(),
+            subject_prefix: DEFAULT_SUBJECT_PREFIX.to_string(),
+            stream_created: false,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            buffer: MeasurementBuffer::new(),
+        })
+    }
+
+    /// Set the name of the stream storing the messages, `TEDGE_MEASUREMENTS` by default.
+    pub fn with_stream_name(self, stream_name: &str) -> Self {
+        Self {
+            stream_name: stream_name.to_string(),
+            ..self
+        }
+    }
+
+    /// Set the prefix of the message subjects, `tedge.measurements` by default.
+    pub fn with_subject_prefix(self, subject_prefix: &str) -> Self {
+        Self {
+            subject_prefix: subject_prefix.to_string(),
+            ..self
+        }
+    }
+
+    /// Set the delay before the first retry, the delay being doubled for each subsequent retry.
+    pub fn with_initial_backoff(self, initial_backoff: Duration) -> Self {
+        Self {
+            initial_backoff,
+            ..self
+        }
+    }
+
+    /// Set the maximum number of attempts to publish a message.
+    pub fn with_max_attempts(self, max_attempts: u32) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            ..self
+        }
+    }
+
+    /// Publish the measurements gathered since the previous flush.
+    ///
+    /// Nothing is sent if no measurements have been gathered.
+    pub async fn flush(&mut self) -> Result<(), NatsSinkError> {
+        let buffer = std::mem::take(&mut self.buffer);
+        for (group, payload) in split_by_group(&buffer)? {
+            let subject = subject(&self.subject_prefix, &self.device_id, group.as_deref());
+            self.publish_with_retries(subject, payload).await?;
+        }
+        Ok(())
+    }
+
+    async fn publish_with_retries(
+        &mut self,
+        subject: String,
+        payload: String,
+    ) -> Result<(), NatsSinkError> {
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            match self.publish(&subject, payload.as_bytes().to_vec()).await {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt < self.max_attempts => {
+                    warn!(
+                        "Attempt {} to publish measurements on {} failed: {}",
+                        attempt, subject, err
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Publish a message and wait for its acknowledgement, creating the stream if not done yet.
+    async fn publish(&mut self, subject: &str, payload: Vec<u8>) -> Result<(), NatsSinkError> {
+        if !self.stream_created {
+            self.create_stream().await?;
+        }
+
+        let ack = self
+            .jetstream
+            .publish(subject, payload)
+            .await
+            .map_err(|error| NatsSinkError::PublishError {
+                subject: subject.to_string(),
+                error,
+            })?;
+        debug!(
+            "Measurements on {} stored by {} with the sequence number {}",
+            subject, ack.stream, ack.sequence
+        );
+        Ok(())
+    }
+
+    async fn create_stream(&mut self) -> Result<(), NatsSinkError> {
+        self.jetstream
+            .get_or_create_stream(&stream_config(&self.stream_name, &self.subject_prefix))
+            .await
+            .map_err(|error| NatsSinkError::StreamError {
+                stream: self.stream_name.clone(),
+                error,
+            })?;
+        self.stream_created = true;
+        Ok(())
+    }
+}
+
+fn stream_config(stream_name: &str, subject_prefix: &str) -> StreamConfig {
+    StreamConfig {
+        name: stream_name.to_string(),
+        subjects: vec![format!("{}.>", subject_prefix)],
+        storage: StorageType::File,
+    }
+}
+
+/// Split the measurements into one thin-edge JSON payload per group,
+/// the top-level measurements being gathered into a payload of their own.
+fn split_by_group(
+    buffer: &MeasurementBuffer,
+) -> Result<Vec<(Option<String>, String)>, ThinEdgeJsonSerializationError> {
+    buffer
+        .split_by_group()?
+        .into_iter()
+        .map(|(group, message)| {
+            let mut serializer = ThinEdgeJsonSerializer::new();
+            message.replay(&mut serializer)?;
+            Ok((group.map(String::from), serializer.into_string()?))
+        })
+        .collect()
+}
+
+impl GroupedMeasurementVisitor for NatsJetStreamVisitor {
+    type Error = MeasurementStreamError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        Ok(self.buffer.timestamp(value)?)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        Ok(self.buffer.measurement(name, value)?)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        Ok(self.buffer.start_group(group)?)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        Ok(self.buffer.end_group()?)
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        Ok(self.buffer.measurement_with_unit(name, value, unit)?)
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        value: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        Ok(self.buffer.start_group_with_timestamp(group, value)?)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        Ok(self.buffer.nullable_measurement(name, value)?)
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        Ok(self.buffer.complex_measurement(name, real, imaginary)?)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        Ok(self.buffer.annotated_measurement(name, value, quality)?)
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        Ok(self.buffer.set_context(context)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use thin_edge_json::json::parse_str;
+
+    fn split(input: &str) -> anyhow::Result<Vec<(Option<String>, String)>> {
+        let mut buffer = MeasurementBuffer::new();
+        parse_str(input, &mut buffer)?;
+        Ok(split_by_group(&buffer)?)
+    }
+
+    #[test]
+    fn measurements_are_split_by_group() -> anyhow::Result<()> {
+        let messages = split(
+            r#"{"time":"2021-04-30T17:03:14+02:00","temperature":25.5,"location":{"alti":2100.4}}"#,
+        )?;
+
+        assert_eq!(
+            messages,
+            vec![
+                (
+                    None,
+                    r#"{"time":"2021-04-30T17:03:14+02:00","temperature":25.5}"#.to_string()
+                ),
+                (
+                    Some("location".to_string()),
+                    r#"{"time":"2021-04-30T17:03:14+02:00","location":{"alti":2100.4}}"#
+                        .to_string()
+                ),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn all_kinds_of_measurements_are_published() -> anyhow::Result<()> {
+        let messages = split(concat!(
+            r#"{"_traceId":"4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b","_spanId":"f0f0f0f0f0f0f0f0","#,
+            r#""temperature":null,"pressure":{"value":98.0,"quality":"UNCERTAIN"},"#,
+            r#""engine":{"time":"2021-04-30T17:03:14+02:00","speed":{"value":3000.0,"unit":"rpm"}}}"#
+        ))?;
+
+        assert_eq!(
+            messages,
+            vec![
+                (
+                    None,
+                    concat!(
+                        r#"{"_traceId":"4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b","_spanId":"f0f0f0f0f0f0f0f0","#,
+                        r#""temperature":null,"pressure":{"value":98.0,"quality":"UNCERTAIN"}}"#
+                    )
+                    .to_string()
+                ),
+                (
+                    Some("engine".to_string()),
+                    concat!(
+                        r#"{"_traceId":"4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b","_spanId":"f0f0f0f0f0f0f0f0","#,
+                        r#""engine":{"time":"2021-04-30T17:03:14+02:00","speed":{"value":3000.0,"unit":"rpm"}}}"#
+                    )
+                    .to_string()
+                ),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn subjects_are_derived_from_the_device_and_the_groups() {
+        assert_eq!(
+            subject("tedge.measurements", "device-1", None),
+            "tedge.measurements.device-1"
+        );
+        assert_eq!(
+            subject("tedge.measurements", "device-1", Some("location")),
+            "tedge.measurements.device-1.location"
+        );
+    }
+
+    #[test]
+    fn the_stream_captures_all_the_subjects_under_the_prefix() {
+        let config = stream_config("MEASUREMENTS", "factory.measurements");
+
+        assert_eq!(config.name, "MEASUREMENTS");
+        assert_eq!(config.subjects, vec!["factory.measurements.>".to_string()]);
+        assert_eq!(config.storage, StorageType::File);
+    }
+}
@@ -0,0 +1,193 @@
+#![cfg(feature = "integration-test")]
+// These tests require a docker daemon to start a NATS server with JetStream enabled.
+// Run them by calling 'cargo test --features integration-test' from the base path of the crate
+
+use async_nats::{Connection, Message};
+use nats_sink::NatsJetStreamVisitor;
+use std::process::Command;
+use std::time::Duration;
+use testcontainers::images::generic::{GenericImage, WaitFor};
+use testcontainers::{clients, Container, Docker, Image};
+use thin_edge_json::measurement::GroupedMeasurementVisitor;
+use tokio::time::timeout;
+
+const STREAM: &str = "TEDGE_MEASUREMENTS";
+const CONSUMER: &str = "test_consumer";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn nats() -> GenericImage {
+    GenericImage::new("nats:2.9")
+        .with_args(vec!["-js".into()])
+        .with_wait_for(WaitFor::message_on_stderr("Server is ready"))
+}
+
+fn server_url(server: &Container<clients::Cli, GenericImage>) -> String {
+    let port = server
+        .get_host_port(4222)
+        .expect("The server port is exposed");
+    format!("nats://localhost:{}", port)
+}
+
+/// A durable pull consumer of all the messages stored by the stream,
+/// which are redelivered if not acked within `ack_wait`
+async fn consumer(url: &str, ack_wait: Duration) -> anyhow::Result<Connection> {
+    let connection = async_nats::connect(url).await?;
+    let request = serde_json::json!({
+        "stream_name": STREAM,
+        "config": {
+            "durable_name": CONSUMER,
+            "ack_policy": "explicit",
+            "ack_wait": ack_wait.as_nanos() as u64,
+        }
+    });
+    let subject = format!("$JS.API.CONSUMER.DURABLE.CREATE.{}.{}", STREAM, CONSUMER);
+    let response = connection
+        .request_timeout(&subject, serde_json::to_vec(&request)?, REQUEST_TIMEOUT)
+        .await?;
+    let response: serde_json::Value = serde_json::from_slice(&response.data)?;
+    anyhow::ensure!(
+        response.get("error").is_none(),
+        "Failed to create the consumer: {}",
+        response
+    );
+    Ok(connection)
+}
+
+/// Fetch up to `count` messages from the consumer,
+/// the batch being ended by a status message with no payload if fewer messages are available
+async fn fetch(consumer: &Connection, count: usize) -> anyhow::Result<Vec<Message>> {
+    let inbox = consumer.new_inbox();
+    let subscription = consumer.subscribe(&inbox).await?;
+    let request = serde_json::json!({
+        "batch": count,
+        "expires": REQUEST_TIMEOUT.as_nanos() as u64,
+    });
+    let subject = format!("$JS.API.CONSUMER.MSG.NEXT.{}.{}", STREAM, CONSUMER);
+    consumer
+        .publish_request(&subject, &inbox, serde_json::to_vec(&request)?)
+        .await?;
+
+    let mut fetched = Vec::new();
+    while fetched.len() < count {
+        match timeout(Duration::from_secs(10), subscription.next()).await? {
+            Some(message) if !message.data.is_empty() => fetched.push(message),
+            _ => break,
+        }
+    }
+    subscription.unsubscribe().await?;
+    Ok(fetched)
+}
+
+fn subject_and_payload(message: &Message) -> (String, String) {
+    (
+        message.subject.clone(),
+        String::from_utf8_lossy(&message.data).to_string(),
+    )
+}
+
+#[tokio::test]
+async fn measurements_are_stored_by_the_stream() -> anyhow::Result<()> {
+    let docker = clients::Cli::default();
+    let server = docker.run(nats());
+    let url = server_url(&server);
+
+    let mut visitor = NatsJetStreamVisitor::connect(&url, "device-1").await?;
+    visitor.measurement("temperature", 25.5)?;
+    visitor.start_group("location")?;
+    visitor.measurement("alti", 2100.4)?;
+    visitor.end_group()?;
+    visitor.flush().await?;
+
+    // The consumer is created once the messages have been published
+    let consumer = consumer(&url, Duration::from_secs(30)).await?;
+    let messages = fetch(&consumer, 2).await?;
+
+    assert_eq!(
+        messages.iter().map(subject_and_payload).collect::<Vec<_>>(),
+        vec![
+            (
+                "tedge.measurements.device-1".to_string(),
+                r#"{"temperature":25.5}"#.to_string()
+            ),
+            (
+                "tedge.measurements.device-1.location".to_string(),
+                r#"{"location":{"alti":2100.4}}"#.to_string()
+            ),
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn messages_not_acknowledged_by_a_consumer_are_redelivered() -> anyhow::Result<()> {
+    let docker = clients::Cli::default();
+    let server = docker.run(nats());
+    let url = server_url(&server);
+
+    let mut visitor = NatsJetStreamVisitor::connect(&url, "device-1")
+        .await?
+        .with_subject_prefix("factory.measurements");
+    visitor.measurement("temperature", 25.5)?;
+    visitor.flush().await?;
+
+    let consumer = consumer(&url, Duration::from_secs(1)).await?;
+    let first_delivery = fetch(&consumer, 1).await?;
+    assert_eq!(first_delivery.len(), 1);
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let redelivery = fetch(&consumer, 1).await?;
+    assert_eq!(
+        redelivery
+            .iter()
+            .map(subject_and_payload)
+            .collect::<Vec<_>>(),
+        vec![(
+            "factory.measurements.device-1".to_string(),
+            r#"{"temperature":25.5}"#.to_string()
+        )]
+    );
+    redelivery[0].respond("+ACK").await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn publications_are_retried_while_the_server_is_unavailable() -> anyhow::Result<()> {
+    let docker = clients::Cli::default();
+    let server = docker.run(nats());
+    let url = server_url(&server);
+
+    let mut visitor = NatsJetStreamVisitor::connect(&url, "device-1")
+        .await?
+        .with_initial_backoff(Duration::from_millis(500))
+        .with_max_attempts(5);
+
+    let status = Command::new("docker")
+        .args(&["pause", server.id()])
+        .status()?;
+    assert!(status.success());
+    let container_id = server.id().to_string();
+    let unpause = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_secs(3));
+        Command::new("docker")
+            .args(&["unpause", &container_id])
+            .status()
+    });
+
+    visitor.measurement("temperature", 25.5)?;
+    visitor.flush().await?;
+    assert!(unpause
+        .join()
+        .expect("The unpause thread completes")?
+        .success());
+
+    let consumer = consumer(&url, Duration::from_secs(30)).await?;
+    let messages = fetch(&consumer, 1).await?;
+    assert_eq!(
+        messages.iter().map(subject_and_payload).collect::<Vec<_>>(),
+        vec![(
+            "tedge.measurements.device-1".to_string(),
+            r#"{"temperature":25.5}"#.to_string()
+        )]
+    );
+    Ok(())
+}
@@ -0,0 +1,360 @@
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use log::warn;
+use ndarray::{Array2, CowArray};
+use ort::{Environment, Session, SessionBuilder, Value};
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use thin_edge_json::context::MeasurementContext;
+use thin_edge_json::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+
+/// The suffix of the measurements injected with the anomaly score of a measurement
+pub const ANOMALY_SCORE_SUFFIX: &str = "_anomaly_score";
+
+const DEFAULT_THRESHOLD: f64 = 0.5;
+
+#[derive(thiserror::Error, Debug)]
+pub enum OnnxAnomalyError {
+    #[error("Invalid window size: a window must hold at least one value")]
+    InvalidWindowSize,
+
+    #[error(transparent)]
+    ModelError(#[from] ort::OrtError),
+
+    #[error("Invalid window: {0}")]
+    InvalidWindow(#[from] ndarray::ShapeError),
+
+    #[error("The model returned no anomaly score")]
+    MissingScore,
+}
+
+/// A visitor that scores the recent values of each measurement with an ONNX model,
+/// injecting the score of the anomalous values into the stream.
+///
+/// * The model is given the last `window_size` values of a measurement,
+///   oldest first, as a `[1, window_size]` tensor of `f32`,
+///   and returns an anomaly score as the first value of its first output.
+/// * A measurement is scored each time a new value is received, once its window is full.
+/// * The measurements are forwarded as is, followed, when their score is above the threshold,
+///   by a `<name>_anomaly_score` measurement in the same group.
+/// * A measurement of a group is tracked independently of the measurements
+///   with the same name in other groups.
+/// * The null values are forwarded but not scored, nor the complex measurements.
+///
+/// A failure to run the model is logged, the measurement being forwarded with no score.
+pub struct OnnxAnomalyDetectorVisitor<V> {
+    session: Session,
+    window_size: usize,
+    threshold: f64,
+    windows: HashMap<String, VecDeque<f32>>,
+    group: Option<String>,
+    inner: V,
+}
+
+impl<V> OnnxAnomalyDetectorVisitor<V> {
+    /// Load the ONNX model scoring windows of `window_size` values
+    pub fn new(
+        model_path: impl AsRef<Path>,
+        window_size: usize,
+        inner: V,
+    ) -> Result<Self, OnnxAnomalyError> {
+        if window_size == 0 {
+            return Err(OnnxAnomalyError::InvalidWindowSize);
+        }
+        let environment = Environment::builder()
+            .with_name("onnx_anomaly")
+            .build()?
+            .into_arc();
+        let session = SessionBuilder::new(&environment)?.with_model_from_file(model_path)?;
+
+        Ok(Self {
+            session,
+            window_size,
+            threshold: DEFAULT_THRESHOLD,
+            windows: HashMap::new(),
+            group: None,
+            inner,
+        })
+    }
+
+    /// Set the score above which a value is anomalous, 0.5 by default.
+    pub fn with_threshold(self, threshold: f64) -> Self {
+        Self { threshold, ..self }
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut V {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> V {
+        self.inner
+    }
+
+    /// Add a value to the window of a measurement, returning its score if anomalous
+    fn track(&mut self, name: &str, value: f64) -> Option<f64> {
+        if !value.is_finite() {
+            return None;
+        }
+        let key = match self.group.as_ref() {
+            Some(group) => format!("{}.{}", group, name),
+            None => name.to_string(),
+        };
+        let window_size = self.window_size;
+        let window = self
+            .windows
+            .entry(key)
+            .or_insert_with(|| VecDeque::with_capacity(window_size));
+        if window.len() == window_size {
+            window.pop_front();
+        }
+        window.push_back(value as f32);
+        if window.len() < window_size {
+            return None;
+        }
+
+        let values: Vec<f32> = window.iter().copied().collect();
+        match self.score(values) {
+            Ok(score) if score > self.threshold => Some(score),
+            Ok(_) => None,
+            Err(err) => {
+                warn!("Failed to score {}: {}", name, err);
+                None
+            }
+        }
+    }
+
+    /// Run the model on a window of values
+    fn score(&self, values: Vec<f32>) -> Result<f64, OnnxAnomalyError> {
+        let input = CowArray::from(Array2::from_shape_vec((1, values.len()), values)?).into_dyn();
+        let inputs = vec![Value::from_array(self.session.allocator(), &input)?];
+        let outputs: Vec<Value> = self.session.run(inputs)?;
+
+        let output = outputs.get(0).ok_or(OnnxAnomalyError::MissingScore)?;
+        let scores = output.try_extract::<f32>()?;
+        let score = scores.view().iter().next().copied();
+        score.map(f64::from).ok_or(OnnxAnomalyError::MissingScore)
+    }
+}
+
+impl<V> OnnxAnomalyDetectorVisitor<V>
+where
+    V: GroupedMeasurementVisitor,
+{
+    fn inject_score(&mut self, name: &str, score: Option<f64>) -> Result<(), V::Error> {
+        match score {
+            Some(score) => self
+                .inner
+                .measurement(&format!("{}{}", name, ANOMALY_SCORE_SUFFIX), score),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<V> GroupedMeasurementVisitor for OnnxAnomalyDetectorVisitor<V>
+where
+    V: GroupedMeasurementVisitor,
+{
+    type Error = V::Error;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.inner.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.inner.measurement(name, value)?;
+        let score = self.track(name, value);
+        self.inject_score(name, score)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.inner.start_group(group)?;
+        self.group = Some(group.to_string());
+        Ok(())
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.group = None;
+        self.inner.end_group()
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.inner.measurement_with_unit(name, value, unit)?;
+        let score = self.track(name, value);
+        self.inject_score(name, score)
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.inner.start_group_with_timestamp(group, timestamp)?;
+        self.group = Some(group.to_string());
+        Ok(())
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        self.inner.nullable_measurement(name, value)?;
+        let score = value.and_then(|value| self.track(name, value));
+        self.inject_score(name, score)
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.inner.complex_measurement(name, real, imaginary)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.inner.annotated_measurement(name, value, quality)?;
+        let score = self.track(name, value);
+        self.inject_score(name, score)
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.inner.set_context(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+
+    /// A linear model scoring a window of 4 values by the deviation of the latest value
+    /// from the window mean, i.e. `|x . [-0.25, -0.25, -0.25, 0.75]|`.
+    ///
+    /// See `tests/fixtures/linear_anomaly.py` for how it is generated.
+    const MODEL: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/linear_anomaly.onnx"
+    );
+
+    fn detector(threshold: f64) -> OnnxAnomalyDetectorVisitor<ThinEdgeJsonSerializer> {
+        OnnxAnomalyDetectorVisitor::new(MODEL, 4, ThinEdgeJsonSerializer::new())
+            .expect("A valid model")
+            .with_threshold(threshold)
+    }
+
+    /// Send each value as a message of its own, returning the messages
+    fn send(
+        visitor: &mut OnnxAnomalyDetectorVisitor<ThinEdgeJsonSerializer>,
+        values: &[f64],
+    ) -> anyhow::Result<Vec<String>> {
+        let mut messages = vec![];
+        for value in values.iter() {
+            visitor.measurement("temperature", *value)?;
+            messages.push(String::from_utf8(visitor.inner_mut().take_bytes()?)?);
+        }
+        Ok(messages)
+    }
+
+    #[test]
+    fn anomalous_values_are_followed_by_their_score() -> anyhow::Result<()> {
+        let mut visitor = detector(5.0);
+
+        let messages = send(&mut visitor, &[10.0, 10.0, 10.0, 20.0, 20.0, 11.0])?;
+
+        assert_eq!(
+            messages,
+            vec![
+                r#"{"temperature":10.0}"#,
+                r#"{"temperature":10.0}"#,
+                r#"{"temperature":10.0}"#,
+                r#"{"temperature":20.0,"temperature_anomaly_score":7.5}"#,
+                // Deviation of 5.0 from the mean of [10, 10, 20, 20]: not above the threshold
+                r#"{"temperature":20.0}"#,
+                // Deviation of 4.25 from the mean of [10, 20, 20, 11]
+                r#"{"temperature":11.0}"#,
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn values_are_only_scored_once_the_window_is_full() -> anyhow::Result<()> {
+        let mut visitor = detector(0.0);
+
+        let messages = send(&mut visitor, &[10.0, 100.0, 1000.0])?;
+
+        assert_eq!(
+            messages,
+            vec![
+                r#"{"temperature":10.0}"#,
+                r#"{"temperature":100.0}"#,
+                r#"{"temperature":1000.0}"#,
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn measurements_are_scored_per_group() -> anyhow::Result<()> {
+        let mut visitor = detector(1.0);
+
+        for value in [10.0, 10.0, 10.0].iter() {
+            visitor.measurement("temperature", 50.0)?;
+            visitor.start_group("engine")?;
+            visitor.measurement_with_unit("temperature", *value, "°C")?;
+            visitor.end_group()?;
+            let _ = visitor.inner_mut().take_bytes()?;
+        }
+        visitor.measurement("temperature", 50.0)?;
+        visitor.start_group("engine")?;
+        visitor.annotated_measurement("temperature", 14.0, MeasurementQuality::Good)?;
+        visitor.end_group()?;
+
+        assert_eq!(
+            visitor.into_inner().into_string()?,
+            r#"{"temperature":50.0,"engine":{"temperature":{"value":14.0,"quality":"GOOD"},"temperature_anomaly_score":3.0}}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn null_values_are_not_scored() -> anyhow::Result<()> {
+        let mut visitor = detector(1.0);
+
+        for value in [Some(10.0), None, Some(10.0), None, Some(10.0)].iter() {
+            visitor.nullable_measurement("temperature", *value)?;
+            let _ = visitor.inner_mut().take_bytes()?;
+        }
+        visitor.nullable_measurement("temperature", Some(14.0))?;
+
+        assert_eq!(
+            visitor.into_inner().into_string()?,
+            r#"{"temperature":14.0,"temperature_anomaly_score":3.0}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        assert_matches!(
+            OnnxAnomalyDetectorVisitor::new(MODEL, 0, ThinEdgeJsonSerializer::new()),
+            Err(OnnxAnomalyError::InvalidWindowSize)
+        );
+        assert_matches!(
+            OnnxAnomalyDetectorVisitor::new("missing.onnx", 4, ThinEdgeJsonSerializer::new()),
+            Err(OnnxAnomalyError::ModelError(_))
+        );
+    }
+}
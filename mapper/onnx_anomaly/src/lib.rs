@@ -0,0 +1,21 @@
+//! A visitor detecting anomalies in measurement streams, using an ONNX model run by ONNX Runtime.
+//!
+//! ```no_run
+//! use onnx_anomaly::OnnxAnomalyDetectorVisitor;
+//! use thin_edge_json::measurement::GroupedMeasurementVisitor;
+//! use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+//!
+//! # fn main() -> Result<(), anyhow::Error> {
+//! let mut visitor =
+//!     OnnxAnomalyDetectorVisitor::new("anomaly.onnx", 16, ThinEdgeJsonSerializer::new())?
+//!         .with_threshold(0.8);
+//!
+//! // Forwards `"temperature":25.5`, followed by `"temperature_anomaly_score":<score>`
+//! // if the score given by the model to the last 16 temperatures is above 0.8
+//! visitor.measurement("temperature", 25.5)?;
+//! # Ok(()) }
+//! ```
+
+mod detector;
+
+pub use detector::{OnnxAnomalyDetectorVisitor, OnnxAnomalyError, ANOMALY_SCORE_SUFFIX};
@@ -0,0 +1,83 @@
+#!/usr/bin/env python3
+"""Generate linear_anomaly.onnx, the model used by the tests of the onnx_anomaly crate.
+
+The model scores a window of 4 values by the deviation of the latest value from the window mean:
+
+    score = |window . [-0.25, -0.25, -0.25, 0.75]|
+
+The model is encoded by hand, so no ONNX package is required:
+    python3 linear_anomaly.py > linear_anomaly.onnx
+"""
+
+import struct
+import sys
+
+WINDOW_SIZE = 4
+WEIGHTS = [-0.25, -0.25, -0.25, 0.75]
+FLOAT = 1
+
+
+def varint(value):
+    out = b""
+    while True:
+        byte = value & 0x7F
+        value >>= 7
+        if value:
+            out += bytes([byte | 0x80])
+        else:
+            return out + bytes([byte])
+
+
+def int_field(number, value):
+    return varint(number << 3) + varint(value)
+
+
+def bytes_field(number, value):
+    if isinstance(value, str):
+        value = value.encode()
+    return varint(number << 3 | 2) + varint(len(value)) + value
+
+
+def tensor_type(*dims):
+    shape = b"".join(bytes_field(1, int_field(1, dim)) for dim in dims)
+    return bytes_field(1, int_field(1, FLOAT) + bytes_field(2, shape))
+
+
+def value_info(name, *dims):
+    return bytes_field(1, name) + bytes_field(2, tensor_type(*dims))
+
+
+def node(name, op_type, inputs, outputs):
+    return (
+        b"".join(bytes_field(1, i) for i in inputs)
+        + b"".join(bytes_field(2, o) for o in outputs)
+        + bytes_field(3, name)
+        + bytes_field(4, op_type)
+    )
+
+
+weights = (
+    int_field(1, WINDOW_SIZE)
+    + int_field(1, 1)
+    + int_field(2, FLOAT)
+    + bytes_field(8, "weights")
+    + bytes_field(9, struct.pack("<4f", *WEIGHTS))
+)
+
+graph = (
+    bytes_field(1, node("deviation", "MatMul", ["window", "weights"], ["deviation"]))
+    + bytes_field(1, node("abs", "Abs", ["deviation"], ["score"]))
+    + bytes_field(2, "linear_anomaly")
+    + bytes_field(5, weights)
+    + bytes_field(11, value_info("window", 1, WINDOW_SIZE))
+    + bytes_field(12, value_info("score", 1, 1))
+)
+
+model = (
+    int_field(1, 7)
+    + bytes_field(2, "thin-edge")
+    + bytes_field(7, graph)
+    + bytes_field(8, bytes_field(1, "") + int_field(2, 13))
+)
+
+sys.stdout.buffer.write(model)
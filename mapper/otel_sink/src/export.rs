@@ -0,0 +1,272 @@
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use futures::channel::mpsc;
+use futures::stream::{self, Stream};
+use log::debug;
+use opentelemetry::metrics::{Meter, MeterProvider as _, MetricsError, ValueObserver};
+use opentelemetry::sdk::metrics::PushController;
+use opentelemetry_otlp::ExporterConfig;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thin_edge_json::context::MeasurementContext;
+use thin_edge_json::measurement::GroupedMeasurementVisitor;
+use thin_edge_json::serialize::MeasurementStreamError;
+use thin_edge_json::series::FlatMeasurementSeries;
+use tokio::time::{interval_at, Instant};
+
+const DEFAULT_METER_NAME: &str = "thin-edge";
+
+/// The period of the background exports, the measurements being also exported on `flush()`
+const DEFAULT_EXPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(thiserror::Error, Debug)]
+pub enum OtelSinkError {
+    #[error("Failed to export the measurements to OpenTelemetry: {0}")]
+    MetricsError(#[from] MetricsError),
+
+    #[error("The background exports have been stopped")]
+    ExportStopped,
+}
+
+/// The last value of a measurement, as observed by its gauge on each export
+struct Gauge {
+    value: Arc<Mutex<f64>>,
+    _instrument: ValueObserver<f64>,
+}
+
+/// A value to be recorded by the gauge of a meter
+#[derive(Debug, Clone, PartialEq)]
+struct GaugePoint {
+    meter: String,
+    instrument: String,
+    value: f64,
+}
+
+/// A visitor that exports the measurements as OpenTelemetry gauges,
+/// to an OTLP collector listening for gRPC requests.
+///
+/// Each group is mapped to a meter, named after the group,
+/// and each measurement to a gauge of that meter, named after the measurement.
+/// The measurements which are not attached to a group are given to a default meter,
+/// named `thin-edge` unless set otherwise.
+///
+/// The measurements are gathered and recorded on `flush()`, which triggers an export of the gauges.
+/// The exports are run in the background: `flush()` returns without waiting for the collector.
+/// As a gauge reports the last value recorded, the gauges are also exported periodically,
+/// every 60 seconds.
+///
+/// The timestamps and units of the measurements are not exported:
+/// the data points are stamped with the time of their export.
+/// Nor is their trace context, a gauge being observed long after the values have been recorded.
+pub struct ThinEdgeToOtelExporter {
+    controller: PushController,
+    flush_requests: mpsc::UnboundedSender<()>,
+    default_meter: String,
+    meters: HashMap<String, Meter>,
+    gauges: HashMap<(String, String), Gauge>,
+    series: FlatMeasurementSeries,
+}
+
+impl ThinEdgeToOtelExporter {
+    /// Export the measurements to the OTLP collector at `endpoint`, as `http://localhost:4317`.
+    ///
+    /// This must be called from a tokio runtime, which runs the exports.
+    pub fn new(endpoint: &str) -> Result<Self, OtelSinkError> {
+        let (flush_requests, flush_receiver) = mpsc::unbounded();
+        let flush_receiver = Mutex::new(Some(flush_receiver));
+        let controller = opentelemetry_otlp::new_metrics_pipeline(tokio::spawn, move |period| {
+            let flush_receiver = flush_receiver
+                .lock()
+                .expect("The flush lock is not poisoned")
+                .take()
+                .expect("The export ticks are created once");
+            export_ticks(period, flush_receiver)
+        })
+        .with_export_config(ExporterConfig {
+            endpoint: endpoint.to_string(),
+            ..ExporterConfig::default()
+        })
+        .with_period(DEFAULT_EXPORT_INTERVAL)
+        .build()?;
+        Ok(Self {
+            controller,
+            flush_requests,
+            default_meter: DEFAULT_METER_NAME.to_string(),
+            meters: HashMap::new(),
+            gauges: HashMap::new(),
+            series: FlatMeasurementSeries::new(),
+        })
+    }
+
+    /// Set the meter of the measurements which are not attached to a group, `thin-edge` by default.
+    pub fn with_default_meter(self, meter: &str) -> Self {
+        Self {
+            default_meter: meter.to_string(),
+            ..self
+        }
+    }
+
+    /// Record the measurements gathered since the previous flush and trigger an export of all the gauges.
+    pub fn flush(&mut self) -> Result<(), OtelSinkError> {
+        self.record_series()?;
+        self.flush_requests
+            .unbounded_send(())
+            .map_err(|_| OtelSinkError::ExportStopped)
+    }
+
+    /// Record the measurements, then stop the background exports after a last export.
+    pub fn shutdown(mut self) -> Result<(), OtelSinkError> {
+        self.record_series()?;
+        // Dropping the controller stops the background task, which exports the gauges on exit
+        drop(self.controller);
+        Ok(())
+    }
+
+    fn record_series(&mut self) -> Result<(), OtelSinkError> {
+        let series = std::mem::take(&mut self.series);
+        for point in gauge_points(series, &self.default_meter) {
+            self.record(point)?;
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, point: GaugePoint) -> Result<(), OtelSinkError> {
+        let key = (point.meter, point.instrument);
+        if let Some(gauge) = self.gauges.get(&key) {
+            *gauge.value.lock().expect("The gauge lock is not poisoned") = point.value;
+            return Ok(());
+        }
+
+        let (meter_name, instrument_name) = key;
+        let provider = self.controller.provider();
+        let meter = self.meters.entry(meter_name.clone()).or_insert_with(|| {
+            // The meters are created once per name and kept until the exporter is dropped
+            let static_name: &'static str = Box::leak(meter_name.clone().into_boxed_str());
+            provider.meter(static_name, None)
+        });
+
+        let value = Arc::new(Mutex::new(point.value));
+        let observed_value = value.clone();
+        let instrument = meter
+            .f64_value_observer(instrument_name.clone(), move |gauge| {
+                let value = *observed_value
+                    .lock()
+                    .expect("The gauge lock is not poisoned");
+                gauge.observe(value, &[]);
+            })
+            .try_init()?;
+        debug!(
+            "Created the gauge {} of the meter {}",
+            instrument_name, meter_name
+        );
+
+        self.gauges.insert(
+            (meter_name, instrument_name),
+            Gauge {
+                value,
+                _instrument: instrument,
+            },
+        );
+        Ok(())
+    }
+}
+
+/// The ticks of the exports: every `period`, and on each flush request.
+fn export_ticks(
+    period: Duration,
+    flush_requests: mpsc::UnboundedReceiver<()>,
+) -> impl Stream<Item = ()> + Send + 'static {
+    let periodic = stream::unfold(
+        interval_at(Instant::now() + period, period),
+        |mut interval| async move {
+            interval.tick().await;
+            Some(((), interval))
+        },
+    );
+    stream::select(periodic, flush_requests)
+}
+
+/// Map the measurements to gauges: the group giving the meter and the name the instrument.
+fn gauge_points(series: FlatMeasurementSeries, default_meter: &str) -> Vec<GaugePoint> {
+    series
+        .measurements
+        .into_iter()
+        .map(|measurement| GaugePoint {
+            meter: measurement
+                .group
+                .unwrap_or_else(|| default_meter.to_string()),
+            instrument: measurement.name,
+            value: measurement.value,
+        })
+        .collect()
+}
+
+impl GroupedMeasurementVisitor for ThinEdgeToOtelExporter {
+    type Error = MeasurementStreamError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.series.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.series.measurement(name, value)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.series.start_group(group)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.series.end_group()
+    }
+
+    fn set_context(&mut self, _context: MeasurementContext) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(meter: &str, instrument: &str, value: f64) -> GaugePoint {
+        GaugePoint {
+            meter: meter.to_string(),
+            instrument: instrument.to_string(),
+            value,
+        }
+    }
+
+    #[test]
+    fn groups_are_mapped_to_meters() -> anyhow::Result<()> {
+        let mut series = FlatMeasurementSeries::new();
+        series.measurement("temperature", 25.5)?;
+        series.start_group("location")?;
+        series.measurement("alti", 2100.4)?;
+        series.measurement("longi", 2200.4)?;
+        series.end_group()?;
+
+        assert_eq!(
+            gauge_points(series, "thin-edge"),
+            vec![
+                point("thin-edge", "temperature", 25.5),
+                point("location", "alti", 2100.4),
+                point("location", "longi", 2200.4),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn top_level_measurements_are_given_to_the_default_meter() -> anyhow::Result<()> {
+        let mut series = FlatMeasurementSeries::new();
+        series.measurement("temperature", 25.5)?;
+
+        assert_eq!(
+            gauge_points(series, "device-1"),
+            vec![point("device-1", "temperature", 25.5)]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,26 @@
+//! A sink exporting thin-edge JSON measurements as OpenTelemetry gauges, using OTLP over gRPC.
+//!
+//! ```no_run
+//! use otel_sink::ThinEdgeToOtelExporter;
+//! use thin_edge_json::measurement::GroupedMeasurementVisitor;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), anyhow::Error> {
+//! let mut visitor = ThinEdgeToOtelExporter::new("http://localhost:4317")?;
+//!
+//! visitor.measurement("temperature", 25.5)?;
+//! visitor.start_group("location")?;
+//! visitor.measurement("alti", 2100.4)?;
+//! visitor.end_group()?;
+//!
+//! // Triggers the export of the gauge `temperature` of the meter `thin-edge`
+//! // and the gauge `alti` of the meter `location`
+//! visitor.flush()?;
+//!
+//! visitor.shutdown()?;
+//! # Ok(()) }
+//! ```
+
+mod export;
+
+pub use export::{OtelSinkError, ThinEdgeToOtelExporter};
@@ -0,0 +1,108 @@
+#![cfg(feature = "integration-test")]
+// These tests require a docker daemon to start an OpenTelemetry collector.
+// Run them by calling 'cargo test --features integration-test' from the base path of the crate
+
+use otel_sink::ThinEdgeToOtelExporter;
+use std::time::Duration;
+use testcontainers::images::generic::{GenericImage, WaitFor};
+use testcontainers::{clients, Container, Docker, Image};
+use thin_edge_json::measurement::GroupedMeasurementVisitor;
+
+const OTLP_GRPC_PORT: u16 = 4317;
+
+/// The collector exposes the metrics it receives to Prometheus on this port,
+/// which is one of the ports exposed by the collector image.
+const PROMETHEUS_PORT: u16 = 55679;
+
+fn collector() -> GenericImage {
+    GenericImage::new("otel/opentelemetry-collector:0.88.0")
+        .with_args(vec![
+            "--config=yaml:receivers::otlp::protocols::grpc::endpoint: 0.0.0.0:4317".into(),
+            "--config=yaml:exporters::prometheus::endpoint: 0.0.0.0:55679".into(),
+            "--config=yaml:service::pipelines::metrics::receivers: [otlp]".into(),
+            "--config=yaml:service::pipelines::metrics::exporters: [prometheus]".into(),
+        ])
+        .with_wait_for(WaitFor::message_on_stderr("Everything is ready"))
+}
+
+fn host_port(collector: &Container<clients::Cli, GenericImage>, port: u16) -> u16 {
+    collector
+        .get_host_port(port)
+        .expect("The collector port is exposed")
+}
+
+/// The values of the gauges exposed by the collector, as `(meter, instrument, value)`
+async fn scrape(prometheus_port: u16) -> anyhow::Result<Vec<(String, String, f64)>> {
+    let url = format!("http://localhost:{}/metrics", prometheus_port);
+    let body = reqwest::get(&url).await?.error_for_status()?.text().await?;
+
+    let mut gauges = Vec::new();
+    for line in body.lines().filter(|line| !line.starts_with('#')) {
+        let (series, value) = match line.rfind(' ') {
+            Some(index) => (&line[..index], &line[index + 1..]),
+            None => continue,
+        };
+        let instrument = series.split('{').next().unwrap_or(series);
+        let meter = series
+            .split("otel_scope_name=\"")
+            .nth(1)
+            .and_then(|labels| labels.split('"').next());
+        if let Some(meter) = meter {
+            gauges.push((meter.to_string(), instrument.to_string(), value.parse()?));
+        }
+    }
+    gauges.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+    Ok(gauges)
+}
+
+fn gauge(meter: &str, instrument: &str, value: f64) -> (String, String, f64) {
+    (meter.to_string(), instrument.to_string(), value)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn measurements_are_exported_as_gauges() -> anyhow::Result<()> {
+    let docker = clients::Cli::default();
+    let collector = docker.run(collector());
+    let endpoint = format!("http://localhost:{}", host_port(&collector, OTLP_GRPC_PORT));
+
+    let mut visitor = ThinEdgeToOtelExporter::new(&endpoint)?;
+    visitor.measurement("temperature", 25.5)?;
+    visitor.start_group("location")?;
+    visitor.measurement("alti", 2100.4)?;
+    visitor.measurement("longi", 2200.4)?;
+    visitor.end_group()?;
+    visitor.flush()?;
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert_eq!(
+        scrape(host_port(&collector, PROMETHEUS_PORT)).await?,
+        vec![
+            gauge("location", "alti", 2100.4),
+            gauge("location", "longi", 2200.4),
+            gauge("thin-edge", "temperature", 25.5),
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn gauges_report_the_last_value() -> anyhow::Result<()> {
+    let docker = clients::Cli::default();
+    let collector = docker.run(collector());
+    let endpoint = format!("http://localhost:{}", host_port(&collector, OTLP_GRPC_PORT));
+
+    let mut visitor = ThinEdgeToOtelExporter::new(&endpoint)?.with_default_meter("device-1");
+    visitor.measurement("temperature", 25.5)?;
+    visitor.flush()?;
+    visitor.measurement("temperature", 26.0)?;
+    visitor.flush()?;
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert_eq!(
+        scrape(host_port(&collector, PROMETHEUS_PORT)).await?,
+        vec![gauge("device-1", "temperature", 26.0)]
+    );
+
+    visitor.shutdown()?;
+    Ok(())
+}
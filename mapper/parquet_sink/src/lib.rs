@@ -0,0 +1,22 @@
+//! A sink writing thin-edge JSON measurements to Apache Parquet files.
+//!
+//! ```no_run
+//! use parquet_sink::ParquetSinkVisitor;
+//! use thin_edge_json::measurement::GroupedMeasurementVisitor;
+//!
+//! # fn main() -> Result<(), anyhow::Error> {
+//! let mut visitor = ParquetSinkVisitor::new("/var/tedge/measurements").with_max_rows(10_000);
+//!
+//! visitor.measurement("temperature", 25.5)?;
+//! visitor.start_group("location")?;
+//! visitor.measurement("alti", 2100.4)?;
+//! visitor.end_group()?;
+//! visitor.flush()?; // Buffers 2 rows, written to a file once 10000 rows are buffered
+//!
+//! visitor.write_file()?; // Writes the buffered rows, e.g. before the process exits
+//! # Ok(()) }
+//! ```
+
+mod write;
+
+pub use write::{measurement_schema, ParquetSinkError, ParquetSinkVisitor};
@@ -0,0 +1,514 @@
+use arrow2::array::{Array, Float64Array, Int64Array, Utf8Array};
+use arrow2::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow2::error::ArrowError;
+use arrow2::io::parquet::write::{
+    write_file, Compression, Encoding, RowGroupIterator, Version, WriteOptions,
+};
+use arrow2::record_batch::RecordBatch;
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use clock::{Clock, WallClock};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use thin_edge_json::context::MeasurementContext;
+use thin_edge_json::measurement::GroupedMeasurementVisitor;
+use thin_edge_json::serialize::MeasurementStreamError;
+use thin_edge_json::series::FlatMeasurementSeries;
+
+const DEFAULT_MAX_ROWS: usize = 100_000;
+const DEFAULT_MAX_BYTES: usize = 64 * 1024 * 1024;
+const DEFAULT_FILE_PREFIX: &str = "measurements";
+
+/// The fixed size of a row in memory: the timestamp and the value
+const ROW_FIXED_BYTES: usize = 16;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ParquetSinkError {
+    #[error("Failed to write the Parquet file {path:?}: {from}")]
+    IoError { path: PathBuf, from: std::io::Error },
+
+    #[error("Failed to encode the measurements as Parquet: {0}")]
+    EncodingError(#[from] ArrowError),
+}
+
+/// The schema of the Parquet files, with one row per measurement:
+///
+/// * `timestamp`: the time of the measurement, in microseconds since the epoch, UTC
+/// * `group`: the group of the measurement, null for a top-level measurement
+/// * `metric_name`: the name of the measurement
+/// * `value`: the value of the measurement
+/// * `trace_id`, `span_id`: the trace context of the measurement, in hexadecimal, null if none
+pub fn measurement_schema() -> Schema {
+    Schema::new(vec![
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Microsecond, Some("+00:00".to_string())),
+            false,
+        ),
+        Field::new("group", DataType::Utf8, true),
+        Field::new("metric_name", DataType::Utf8, false),
+        Field::new("value", DataType::Float64, false),
+        Field::new("trace_id", DataType::Utf8, true),
+        Field::new("span_id", DataType::Utf8, true),
+    ])
+}
+
+struct Row {
+    timestamp: i64,
+    group: Option<String>,
+    metric_name: String,
+    value: f64,
+    trace_id: Option<String>,
+    span_id: Option<String>,
+}
+
+impl Row {
+    /// An estimate of the size of the row in memory
+    fn size(&self) -> usize {
+        ROW_FIXED_BYTES
+            + self.group.as_ref().map_or(0, String::len)
+            + self.metric_name.len()
+            + self.trace_id.as_ref().map_or(0, String::len)
+            + self.span_id.as_ref().map_or(0, String::len)
+    }
+}
+
+/// A visitor that writes the measurements to Parquet files,
+/// with one row per measurement as defined by `measurement_schema()`.
+///
+/// The measurements are gathered and added as rows to an in-memory buffer on `flush()`,
+/// all the rows of a flush sharing the timestamp of the measurements,
+/// or the time of the flush if no timestamp has been given.
+///
+/// The buffered rows are written to a new file in the output directory
+/// as soon as the buffer has `max_rows` rows or an estimated size of `max_bytes`.
+/// The files are named `<prefix>-<UTC time of writing>-<sequence number>.parquet`,
+/// as `measurements-20210430T150314Z-000001.parquet`,
+/// the sequence number making the names unique even if several files are written in a second.
+pub struct ParquetSinkVisitor {
+    directory: PathBuf,
+    file_prefix: String,
+    max_rows: usize,
+    max_bytes: usize,
+    clock: Arc<dyn Clock>,
+    sequence_number: u64,
+    rows: Vec<Row>,
+    buffered_bytes: usize,
+    series: FlatMeasurementSeries,
+    context: Option<MeasurementContext>,
+}
+
+impl ParquetSinkVisitor {
+    /// Write the Parquet files in the given directory, which must exist
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            file_prefix: DEFAULT_FILE_PREFIX.to_string(),
+            max_rows: DEFAULT_MAX_ROWS,
+            max_bytes: DEFAULT_MAX_BYTES,
+            clock: Arc::new(WallClock),
+            sequence_number: 0,
+            rows: Vec::new(),
+            buffered_bytes: 0,
+            series: FlatMeasurementSeries::new(),
+            context: None,
+        }
+    }
+
+    /// Set the prefix of the file names, `measurements` by default.
+    pub fn with_file_prefix(self, file_prefix: &str) -> Self {
+        Self {
+            file_prefix: file_prefix.to_string(),
+            ..self
+        }
+    }
+
+    /// Set the number of rows triggering the writing of a file, 100000 by default.
+    pub fn with_max_rows(self, max_rows: usize) -> Self {
+        Self {
+            max_rows: max_rows.max(1),
+            ..self
+        }
+    }
+
+    /// Set the estimated size of the buffered rows triggering the writing of a file, 64 MiB by default.
+    pub fn with_max_bytes(self, max_bytes: usize) -> Self {
+        Self { max_bytes, ..self }
+    }
+
+    /// Set the clock used to timestamp the measurements given with no timestamp
+    /// and to name the files.
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self { clock, ..self }
+    }
+
+    /// The number of rows waiting to be written
+    pub fn buffered_rows(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Add the measurements gathered since the previous flush to the buffered rows,
+    /// writing a file if the buffer is full.
+    ///
+    /// Returns the path of the file written, if any.
+    pub fn flush(&mut self) -> Result<Option<PathBuf>, ParquetSinkError> {
+        let series = std::mem::take(&mut self.series);
+        let timestamp = series.timestamp.unwrap_or_else(|| self.clock.now());
+        let timestamp =
+            timestamp.timestamp() * 1_000_000 + timestamp.timestamp_subsec_micros() as i64;
+        let context = self.context.take();
+        let trace_id = context.as_ref().map(MeasurementContext::trace_id_hex);
+        let span_id = context.as_ref().map(MeasurementContext::span_id_hex);
+
+        for measurement in series.measurements {
+            let row = Row {
+                timestamp,
+                group: measurement.group,
+                metric_name: measurement.name,
+                value: measurement.value,
+                trace_id: trace_id.clone(),
+                span_id: span_id.clone(),
+            };
+            self.buffered_bytes += row.size();
+            self.rows.push(row);
+        }
+
+        if self.rows.len() >= self.max_rows || self.buffered_bytes >= self.max_bytes {
+            self.write_file()
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Write all the buffered rows to a new file, whatever the size of the buffer.
+    ///
+    /// Returns the path of the file written, `None` if there were no rows to write.
+    pub fn write_file(&mut self) -> Result<Option<PathBuf>, ParquetSinkError> {
+        if self.rows.is_empty() {
+            return Ok(None);
+        }
+
+        self.sequence_number += 1;
+        let path = self.directory.join(format!(
+            "{}-{}-{:06}.parquet",
+            self.file_prefix,
+            self.clock.now().naive_utc().format("%Y%m%dT%H%M%SZ"),
+            self.sequence_number
+        ));
+
+        let rows = std::mem::take(&mut self.rows);
+        self.buffered_bytes = 0;
+        write_rows(&path, rows)?;
+        Ok(Some(path))
+    }
+}
+
+fn write_rows(path: &Path, rows: Vec<Row>) -> Result<(), ParquetSinkError> {
+    let schema = measurement_schema();
+    let timestamp_type = schema.field(0).data_type().clone();
+
+    let timestamps: Vec<i64> = rows.iter().map(|row| row.timestamp).collect();
+    let groups: Vec<Option<&str>> = rows.iter().map(|row| row.group.as_deref()).collect();
+    let names: Vec<&str> = rows.iter().map(|row| row.metric_name.as_str()).collect();
+    let values: Vec<f64> = rows.iter().map(|row| row.value).collect();
+    let trace_ids: Vec<Option<&str>> = rows.iter().map(|row| row.trace_id.as_deref()).collect();
+    let span_ids: Vec<Option<&str>> = rows.iter().map(|row| row.span_id.as_deref()).collect();
+    let columns: Vec<Arc<dyn Array>> = vec![
+        Arc::new(Int64Array::from_vec(timestamps).to(timestamp_type)),
+        Arc::new(Utf8Array::<i32>::from(&groups)),
+        Arc::new(Utf8Array::<i32>::from_slice(&names)),
+        Arc::new(Float64Array::from_vec(values)),
+        Arc::new(Utf8Array::<i32>::from(&trace_ids)),
+        Arc::new(Utf8Array::<i32>::from(&span_ids)),
+    ];
+    let batch = RecordBatch::try_new(Arc::new(schema.clone()), columns)?;
+
+    let options = WriteOptions {
+        write_statistics: true,
+        compression: Compression::Snappy,
+        version: Version::V2,
+    };
+    let encodings = schema.fields().iter().map(|_| Encoding::Plain).collect();
+    let row_groups =
+        RowGroupIterator::try_new(vec![Ok(batch)].into_iter(), &schema, options, encodings)?;
+    let parquet_schema = row_groups.parquet_schema().clone();
+
+    let io_error = |from| ParquetSinkError::IoError {
+        path: path.to_path_buf(),
+        from,
+    };
+    let mut file = File::create(path).map_err(io_error)?;
+    write_file(
+        &mut file,
+        row_groups,
+        &schema,
+        parquet_schema,
+        options,
+        None,
+    )?;
+    file.sync_all().map_err(io_error)?;
+    Ok(())
+}
+
+impl GroupedMeasurementVisitor for ParquetSinkVisitor {
+    type Error = MeasurementStreamError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.series.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.series.measurement(name, value)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.series.start_group(group)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.series.end_group()
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.context = Some(context);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow2::io::parquet::read::RecordReader;
+    use chrono::TimeZone;
+    use clock::MockClock;
+    use tempfile::TempDir;
+
+    fn clock() -> Arc<dyn Clock> {
+        let mut clock = MockClock::new();
+        clock.expect_now().returning(|| {
+            FixedOffset::east(2 * 3600)
+                .ymd(2021, 4, 30)
+                .and_hms(17, 3, 14)
+        });
+        Arc::new(clock)
+    }
+
+    fn visitor(directory: &TempDir) -> ParquetSinkVisitor {
+        ParquetSinkVisitor::new(directory.path()).with_clock(clock())
+    }
+
+    /// Read back a Parquet file as `(timestamp, group, metric_name, value)` rows
+    fn read_rows(path: &Path) -> anyhow::Result<Vec<(i64, Option<String>, String, f64)>> {
+        let reader = RecordReader::try_new(File::open(path)?, None, None, None, None)?;
+        let mut rows = Vec::new();
+        for batch in reader {
+            let batch = batch?;
+            let timestamps = batch
+                .column(0)
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .unwrap();
+            let groups = batch
+                .column(1)
+                .as_any()
+                .downcast_ref::<Utf8Array<i32>>()
+                .unwrap();
+            let names = batch
+                .column(2)
+                .as_any()
+                .downcast_ref::<Utf8Array<i32>>()
+                .unwrap();
+            let values = batch
+                .column(3)
+                .as_any()
+                .downcast_ref::<Float64Array>()
+                .unwrap();
+            for i in 0..batch.num_rows() {
+                let group = if groups.is_null(i) {
+                    None
+                } else {
+                    Some(groups.value(i).to_string())
+                };
+                rows.push((
+                    timestamps.value(i),
+                    group,
+                    names.value(i).to_string(),
+                    values.value(i),
+                ));
+            }
+        }
+        Ok(rows)
+    }
+
+    #[test]
+    fn measurements_are_written_as_rows() -> anyhow::Result<()> {
+        let directory = TempDir::new()?;
+        let mut visitor = visitor(&directory);
+
+        visitor.timestamp(
+            FixedOffset::east(0)
+                .ymd(2021, 4, 30)
+                .and_hms_micro(15, 0, 0, 500),
+        )?;
+        visitor.measurement("temperature", 25.5)?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.end_group()?;
+        assert_eq!(visitor.flush()?, None);
+        assert_eq!(visitor.buffered_rows(), 2);
+
+        let path = visitor.write_file()?.expect("A file is written");
+        let timestamp = 1_619_794_800_000_500;
+        assert_eq!(
+            read_rows(&path)?,
+            vec![
+                (timestamp, None, "temperature".to_string(), 25.5),
+                (
+                    timestamp,
+                    Some("location".to_string()),
+                    "alti".to_string(),
+                    2100.4
+                ),
+            ]
+        );
+        assert_eq!(visitor.buffered_rows(), 0);
+        Ok(())
+    }
+
+    /// Read back the trace context of the rows of a Parquet file, as `(trace_id, span_id)`
+    fn read_contexts(path: &Path) -> anyhow::Result<Vec<(Option<String>, Option<String>)>> {
+        let reader = RecordReader::try_new(File::open(path)?, None, None, None, None)?;
+        let mut contexts = Vec::new();
+        for batch in reader {
+            let batch = batch?;
+            let column = |index: usize| {
+                batch
+                    .column(index)
+                    .as_any()
+                    .downcast_ref::<Utf8Array<i32>>()
+                    .unwrap()
+            };
+            let (trace_ids, span_ids) = (column(4), column(5));
+            for i in 0..batch.num_rows() {
+                let value = |column: &Utf8Array<i32>| {
+                    if column.is_null(i) {
+                        None
+                    } else {
+                        Some(column.value(i).to_string())
+                    }
+                };
+                contexts.push((value(trace_ids), value(span_ids)));
+            }
+        }
+        Ok(contexts)
+    }
+
+    #[test]
+    fn the_trace_context_is_written_along_the_rows_it_has_been_set_for() -> anyhow::Result<()> {
+        let directory = TempDir::new()?;
+        let mut visitor = visitor(&directory);
+
+        visitor.set_context(MeasurementContext::new([0x4b; 16], [0xf0; 8]))?;
+        visitor.measurement("temperature", 25.5)?;
+        visitor.measurement("pressure", 98.0)?;
+        visitor.flush()?;
+        visitor.measurement("temperature", 26.0)?;
+        visitor.flush()?;
+
+        let path = visitor.write_file()?.expect("A file is written");
+        let traced = (
+            Some("4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b".to_string()),
+            Some("f0f0f0f0f0f0f0f0".to_string()),
+        );
+        assert_eq!(
+            read_contexts(&path)?,
+            vec![traced.clone(), traced, (None, None)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn measurements_with_no_timestamp_are_timestamped_on_flush() -> anyhow::Result<()> {
+        let directory = TempDir::new()?;
+        let mut visitor = visitor(&directory);
+
+        visitor.measurement("temperature", 25.5)?;
+        visitor.flush()?;
+
+        let path = visitor.write_file()?.expect("A file is written");
+        assert_eq!(read_rows(&path)?[0].0, 1_619_794_994_000_000);
+        Ok(())
+    }
+
+    #[test]
+    fn a_file_is_written_when_max_rows_is_reached() -> anyhow::Result<()> {
+        let directory = TempDir::new()?;
+        let mut visitor = visitor(&directory).with_max_rows(3);
+
+        for i in 0..2 {
+            visitor.measurement("counter", i as f64)?;
+            assert_eq!(visitor.flush()?, None);
+        }
+        visitor.measurement("counter", 2.0)?;
+        let path = visitor.flush()?.expect("A file is written");
+
+        let values: Vec<f64> = read_rows(&path)?.into_iter().map(|row| row.3).collect();
+        assert_eq!(values, vec![0.0, 1.0, 2.0]);
+        assert_eq!(visitor.buffered_rows(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn a_file_is_written_when_max_bytes_is_reached() -> anyhow::Result<()> {
+        let directory = TempDir::new()?;
+        // Each "counter" row is estimated to 16 + 7 bytes
+        let mut visitor = visitor(&directory).with_max_bytes(40);
+
+        visitor.measurement("counter", 1.0)?;
+        assert_eq!(visitor.flush()?, None);
+        visitor.measurement("counter", 2.0)?;
+        let path = visitor.flush()?.expect("A file is written");
+
+        assert_eq!(read_rows(&path)?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn files_are_named_with_a_timestamp_and_a_sequence_number() -> anyhow::Result<()> {
+        let directory = TempDir::new()?;
+        let mut visitor = visitor(&directory)
+            .with_file_prefix("engine")
+            .with_max_rows(1);
+
+        visitor.measurement("speed", 3000.0)?;
+        let first = visitor.flush()?.expect("A file is written");
+        visitor.measurement("speed", 3100.0)?;
+        let second = visitor.flush()?.expect("A file is written");
+
+        assert_eq!(
+            first,
+            directory
+                .path()
+                .join("engine-20210430T150314Z-000001.parquet")
+        );
+        assert_eq!(
+            second,
+            directory
+                .path()
+                .join("engine-20210430T150314Z-000002.parquet")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn no_file_is_written_when_there_is_no_rows() -> anyhow::Result<()> {
+        let directory = TempDir::new()?;
+        let mut visitor = visitor(&directory);
+
+        assert_eq!(visitor.flush()?, None);
+        assert_eq!(visitor.write_file()?, None);
+        assert_eq!(std::fs::read_dir(directory.path())?.count(), 0);
+        Ok(())
+    }
+}
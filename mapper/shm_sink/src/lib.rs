@@ -0,0 +1,26 @@
+//! A sink writing thin-edge JSON measurements to a shared memory ring buffer,
+//! to be consumed by another process, as the mapper.
+//!
+//! ```no_run
+//! use shm_sink::{SharedMemoryReader, SharedMemoryVisitor};
+//! use thin_edge_json::measurement::GroupedMeasurementVisitor;
+//!
+//! # fn main() -> Result<(), anyhow::Error> {
+//! // In the acquisition process
+//! let mut visitor = SharedMemoryVisitor::create("tedge_measurements", 64 * 1024)?;
+//! visitor.measurement("temperature", 25.5)?;
+//! visitor.flush()?;
+//!
+//! // In the mapper process
+//! let mut reader = SharedMemoryReader::open("tedge_measurements")?;
+//! assert_eq!(reader.try_read()?, Some(r#"{"temperature":25.5}"#.to_string()));
+//! # Ok(()) }
+//! ```
+
+mod reader;
+mod ring;
+mod visitor;
+
+pub use reader::SharedMemoryReader;
+pub use ring::SharedMemoryError;
+pub use visitor::SharedMemoryVisitor;
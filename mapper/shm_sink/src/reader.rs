@@ -0,0 +1,124 @@
+use crate::ring::{RingBuffer, SharedMemoryError};
+
+/// The consumer of the thin-edge JSON payloads written by a `SharedMemoryVisitor`
+/// to a shared memory segment.
+pub struct SharedMemoryReader {
+    ring: RingBuffer,
+}
+
+impl SharedMemoryReader {
+    /// Open the shared memory segment with the given name,
+    /// which must have been created by a `SharedMemoryVisitor`.
+    pub fn open(name: &str) -> Result<Self, SharedMemoryError> {
+        Ok(Self {
+            ring: RingBuffer::open(name)?,
+        })
+    }
+
+    /// The oldest payload not read yet, if any.
+    ///
+    /// This never blocks: `None` is returned when the producer has nothing new.
+    pub fn try_read(&mut self) -> Result<Option<String>, SharedMemoryError> {
+        match self.ring.pop()? {
+            Some(payload) => Ok(Some(String::from_utf8(payload)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::visitor::SharedMemoryVisitor;
+    use assert_matches::assert_matches;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use thin_edge_json::measurement::GroupedMeasurementVisitor;
+
+    const MESSAGE_COUNT: usize = 1000;
+
+    fn segment_name(test: &str) -> String {
+        format!("tedge_shm_{}_{}", std::process::id(), test)
+    }
+
+    #[test]
+    fn all_the_messages_are_received_across_threads() -> anyhow::Result<()> {
+        let name = segment_name("threads");
+        let (ready_sender, ready) = mpsc::channel();
+        let (done_sender, done) = mpsc::channel::<()>();
+
+        let producer_name = name.clone();
+        let producer = thread::spawn(move || -> anyhow::Result<()> {
+            // A small buffer, so the producer has to wait for the consumer
+            let mut visitor = SharedMemoryVisitor::create(&producer_name, 256)?;
+            ready_sender.send(())?;
+
+            for i in 0..MESSAGE_COUNT {
+                visitor.measurement("counter", i as f64)?;
+                loop {
+                    match visitor.flush() {
+                        Ok(()) => break,
+                        Err(SharedMemoryError::BufferFull) => {
+                            // The payload has been discarded: produce it again
+                            visitor.measurement("counter", i as f64)?;
+                            thread::yield_now();
+                        }
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+            }
+
+            // Keep the segment till the consumer is done
+            let _ = done.recv();
+            Ok(())
+        });
+
+        ready.recv()?;
+        let mut reader = SharedMemoryReader::open(&name)?;
+        let mut received = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while received.len() < MESSAGE_COUNT && Instant::now() < deadline {
+            match reader.try_read()? {
+                Some(payload) => received.push(payload),
+                None => thread::yield_now(),
+            }
+        }
+        done_sender.send(())?;
+        producer.join().expect("The producer panicked")?;
+
+        let expected: Vec<String> = (0..MESSAGE_COUNT)
+            .map(|i| format!(r#"{{"counter":{:.1}}}"#, i as f64))
+            .collect();
+        assert_eq!(received, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn nothing_is_written_without_measurements() -> anyhow::Result<()> {
+        let name = segment_name("empty");
+        let mut visitor = SharedMemoryVisitor::create(&name, 64)?;
+        let mut reader = SharedMemoryReader::open(&name)?;
+
+        visitor.flush()?;
+        assert_eq!(reader.try_read()?, None);
+
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.end_group()?;
+        visitor.flush()?;
+        assert_eq!(
+            reader.try_read()?,
+            Some(r#"{"location":{"alti":2100.4}}"#.to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn only_ring_buffer_segments_can_be_opened() {
+        assert_matches!(
+            SharedMemoryReader::open(&segment_name("missing")),
+            Err(SharedMemoryError::ShmemError(_))
+        );
+    }
+}
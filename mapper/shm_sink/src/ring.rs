@@ -0,0 +1,270 @@
+use shared_memory::{Shmem, ShmemConf, ShmemError};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Marks a segment initialized as a ring buffer, with this layout version
+const MAGIC: u64 = u64::from_le_bytes(*b"TEDGERB1");
+
+// The header of the segment, the head and the tail being on cache lines of their own
+const MAGIC_OFFSET: usize = 0;
+const CAPACITY_OFFSET: usize = 8;
+const HEAD_OFFSET: usize = 64;
+const TAIL_OFFSET: usize = 128;
+const DATA_OFFSET: usize = 192;
+
+/// Each payload is prefixed by its length, as a little-endian `u32`
+const LENGTH_PREFIX: usize = 4;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SharedMemoryError {
+    #[error(transparent)]
+    ShmemError(#[from] ShmemError),
+
+    #[error("The shared memory segment {name:?} is not a thin-edge ring buffer")]
+    InvalidSegment { name: String },
+
+    #[error("The ring buffer capacity must be larger than {} bytes", LENGTH_PREFIX)]
+    CapacityTooSmall,
+
+    #[error("A payload of {size} bytes cannot fit a ring buffer of {capacity} bytes")]
+    PayloadTooLarge { size: usize, capacity: usize },
+
+    #[error("The ring buffer is full: the consumer is late")]
+    BufferFull,
+
+    #[error("The ring buffer is corrupted")]
+    CorruptedBuffer,
+
+    #[error("The payload is not UTF-8 encoded")]
+    InvalidUtf8(#[from] std::string::FromUtf8Error),
+
+    #[error(transparent)]
+    SerializationError(#[from] thin_edge_json::serialize::ThinEdgeJsonSerializationError),
+}
+
+/// A single-producer single-consumer ring buffer of payloads, in a named shared memory segment.
+///
+/// The segment starts with a header giving the capacity of the buffer
+/// along with two monotonic byte counters, the head moved by the producer only
+/// and the tail moved by the consumer only. No lock is taken:
+/// a payload is published by moving the head past it once written,
+/// and its space released by moving the tail past it once read.
+pub(crate) struct RingBuffer {
+    shmem: Shmem,
+    capacity: u64,
+}
+
+impl RingBuffer {
+    /// Create a new segment, failing if a segment with this name already exists
+    pub fn create(name: &str, capacity: usize) -> Result<Self, SharedMemoryError> {
+        if capacity <= LENGTH_PREFIX {
+            return Err(SharedMemoryError::CapacityTooSmall);
+        }
+        let shmem = ShmemConf::new()
+            .size(DATA_OFFSET + capacity)
+            .os_id(name)
+            .create()?;
+
+        let ring = Self {
+            shmem,
+            capacity: capacity as u64,
+        };
+        ring.counter(CAPACITY_OFFSET)
+            .store(capacity as u64, Ordering::Relaxed);
+        ring.counter(HEAD_OFFSET).store(0, Ordering::Relaxed);
+        ring.counter(TAIL_OFFSET).store(0, Ordering::Relaxed);
+        // Written last, so a reader never sees a partially initialized header
+        ring.counter(MAGIC_OFFSET).store(MAGIC, Ordering::Release);
+        Ok(ring)
+    }
+
+    /// Open an existing segment, created by `RingBuffer::create`
+    pub fn open(name: &str) -> Result<Self, SharedMemoryError> {
+        let shmem = ShmemConf::new().os_id(name).open()?;
+        let invalid_segment = || SharedMemoryError::InvalidSegment {
+            name: name.to_string(),
+        };
+        if shmem.len() <= DATA_OFFSET {
+            return Err(invalid_segment());
+        }
+
+        let mut ring = Self { shmem, capacity: 0 };
+        if ring.counter(MAGIC_OFFSET).load(Ordering::Acquire) != MAGIC {
+            return Err(invalid_segment());
+        }
+        let capacity = ring.counter(CAPACITY_OFFSET).load(Ordering::Relaxed);
+        if capacity <= LENGTH_PREFIX as u64 {
+            return Err(SharedMemoryError::CapacityTooSmall);
+        }
+        if capacity as usize > ring.shmem.len() - DATA_OFFSET {
+            return Err(invalid_segment());
+        }
+        ring.capacity = capacity;
+        Ok(ring)
+    }
+
+    /// Append a payload, failing if there is not enough free space
+    pub fn push(&self, payload: &[u8]) -> Result<(), SharedMemoryError> {
+        let size = (LENGTH_PREFIX + payload.len()) as u64;
+        if size > self.capacity || payload.len() > u32::MAX as usize {
+            return Err(SharedMemoryError::PayloadTooLarge {
+                size: payload.len(),
+                capacity: self.capacity as usize,
+            });
+        }
+
+        let head = self.counter(HEAD_OFFSET).load(Ordering::Relaxed);
+        let tail = self.counter(TAIL_OFFSET).load(Ordering::Acquire);
+        if head < tail || head - tail > self.capacity {
+            return Err(SharedMemoryError::CorruptedBuffer);
+        }
+        if head - tail + size > self.capacity {
+            return Err(SharedMemoryError::BufferFull);
+        }
+
+        self.write_at(head, &(payload.len() as u32).to_le_bytes());
+        self.write_at(head + LENGTH_PREFIX as u64, payload);
+        self.counter(HEAD_OFFSET)
+            .store(head + size, Ordering::Release);
+        Ok(())
+    }
+
+    /// Remove the oldest payload, if any
+    pub fn pop(&self) -> Result<Option<Vec<u8>>, SharedMemoryError> {
+        let tail = self.counter(TAIL_OFFSET).load(Ordering::Relaxed);
+        let head = self.counter(HEAD_OFFSET).load(Ordering::Acquire);
+        if head == tail {
+            return Ok(None);
+        }
+
+        let mut length = [0u8; LENGTH_PREFIX];
+        self.read_at(tail, &mut length);
+        let size = (LENGTH_PREFIX + u32::from_le_bytes(length) as usize) as u64;
+        if head < tail || size > head - tail {
+            return Err(SharedMemoryError::CorruptedBuffer);
+        }
+
+        let mut payload = vec![0u8; size as usize - LENGTH_PREFIX];
+        self.read_at(tail + LENGTH_PREFIX as u64, &mut payload);
+        self.counter(TAIL_OFFSET)
+            .store(tail + size, Ordering::Release);
+        Ok(Some(payload))
+    }
+
+    fn counter(&self, offset: usize) -> &AtomicU64 {
+        // The mapping is page-aligned and the offsets multiple of 8
+        unsafe { &*(self.shmem.as_ptr().add(offset) as *const AtomicU64) }
+    }
+
+    /// Copy bytes to the buffer, wrapping around its end
+    fn write_at(&self, position: u64, bytes: &[u8]) {
+        let start = (position % self.capacity) as usize;
+        let first = bytes.len().min(self.capacity as usize - start);
+        unsafe {
+            let data = self.shmem.as_ptr().add(DATA_OFFSET);
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), data.add(start), first);
+            std::ptr::copy_nonoverlapping(bytes.as_ptr().add(first), data, bytes.len() - first);
+        }
+    }
+
+    /// Copy bytes from the buffer, wrapping around its end
+    fn read_at(&self, position: u64, bytes: &mut [u8]) {
+        let start = (position % self.capacity) as usize;
+        let first = bytes.len().min(self.capacity as usize - start);
+        unsafe {
+            let data = self.shmem.as_ptr().add(DATA_OFFSET);
+            std::ptr::copy_nonoverlapping(data.add(start), bytes.as_mut_ptr(), first);
+            std::ptr::copy_nonoverlapping(data, bytes.as_mut_ptr().add(first), bytes.len() - first);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    fn segment_name(test: &str) -> String {
+        format!("tedge_ring_{}_{}", std::process::id(), test)
+    }
+
+    #[test]
+    fn payloads_wrap_around_the_end_of_the_buffer() -> anyhow::Result<()> {
+        let ring = RingBuffer::create(&segment_name("wrap"), 16)?;
+
+        for i in 0..10 {
+            let payload = format!("payload-{}", i);
+            ring.push(payload.as_bytes())?;
+            assert_eq!(ring.pop()?, Some(payload.into_bytes()));
+        }
+        assert_eq!(ring.pop()?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn a_full_buffer_rejects_payloads() -> anyhow::Result<()> {
+        let ring = RingBuffer::create(&segment_name("full"), 16)?;
+
+        ring.push(b"12345678")?;
+        assert_matches!(ring.push(b"1234"), Err(SharedMemoryError::BufferFull));
+        assert_matches!(
+            ring.push(&[0u8; 13]),
+            Err(SharedMemoryError::PayloadTooLarge { size: 13, .. })
+        );
+
+        assert_eq!(ring.pop()?, Some(b"12345678".to_vec()));
+        ring.push(b"1234")?;
+        Ok(())
+    }
+
+    #[test]
+    fn a_corrupted_buffer_is_detected_on_push() -> anyhow::Result<()> {
+        let ring = RingBuffer::create(&segment_name("corrupted"), 16)?;
+        ring.push(b"1234")?;
+
+        // The consumer is ahead of the producer
+        ring.counter(TAIL_OFFSET).store(16, Ordering::Release);
+        assert_matches!(ring.push(b"1234"), Err(SharedMemoryError::CorruptedBuffer));
+        assert_matches!(ring.pop(), Err(SharedMemoryError::CorruptedBuffer));
+
+        // The producer is ahead of the consumer by more than the capacity
+        ring.counter(TAIL_OFFSET).store(0, Ordering::Release);
+        ring.counter(HEAD_OFFSET).store(32, Ordering::Release);
+        assert_matches!(ring.push(b"1234"), Err(SharedMemoryError::CorruptedBuffer));
+        Ok(())
+    }
+
+    #[test]
+    fn a_capacity_not_larger_than_the_length_prefix_is_rejected() -> anyhow::Result<()> {
+        for capacity in [0, LENGTH_PREFIX].iter() {
+            assert_matches!(
+                RingBuffer::create(&segment_name("too_small"), *capacity),
+                Err(SharedMemoryError::CapacityTooSmall)
+            );
+        }
+
+        // A segment whose header gives a capacity too small to hold a payload
+        let name = segment_name("header_too_small");
+        let ring = RingBuffer::create(&name, 16)?;
+        for capacity in [0, LENGTH_PREFIX as u64].iter() {
+            ring.counter(CAPACITY_OFFSET)
+                .store(*capacity, Ordering::Relaxed);
+            assert_matches!(
+                RingBuffer::open(&name),
+                Err(SharedMemoryError::CapacityTooSmall)
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn a_segment_is_shared_by_name() -> anyhow::Result<()> {
+        let name = segment_name("shared");
+        let producer = RingBuffer::create(&name, 64)?;
+        let consumer = RingBuffer::open(&name)?;
+
+        producer.push(b"hello")?;
+        assert_eq!(consumer.pop()?, Some(b"hello".to_vec()));
+        assert_eq!(producer.pop()?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use crate::ring::{RingBuffer, SharedMemoryError};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use thin_edge_json::context::MeasurementContext;
+use thin_edge_json::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use thin_edge_json::serialize::{ThinEdgeJsonSerializationError, ThinEdgeJsonSerializer};
+
+/// A visitor that writes thin-edge JSON payloads to a ring buffer in shared memory,
+/// to be consumed by another process with a `SharedMemoryReader`.
+///
+/// The measurements are gathered into a thin-edge JSON payload which is written on `flush()`.
+///
+/// The visitor owns the shared memory segment, which is removed when the visitor is dropped.
+/// There must be a single reader, the ring buffer being designed for a single consumer.
+pub struct SharedMemoryVisitor {
+    ring: RingBuffer,
+    serializer: ThinEdgeJsonSerializer,
+    is_empty: bool,
+}
+
+impl SharedMemoryVisitor {
+    /// Create a shared memory segment with the given name,
+    /// holding a ring buffer of `capacity` bytes.
+    ///
+    /// Each payload takes 4 bytes more than its length in the buffer.
+    pub fn create(name: &str, capacity: usize) -> Result<Self, SharedMemoryError> {
+        Ok(Self {
+            ring: RingBuffer::create(name, capacity)?,
+            serializer: ThinEdgeJsonSerializer::new(),
+            is_empty: true,
+        })
+    }
+
+    /// Write the measurements gathered since the previous flush.
+    ///
+    /// Nothing is written if no measurements have been gathered.
+    /// The payload is discarded with a `BufferFull` error if the reader is too late.
+    pub fn flush(&mut self) -> Result<(), SharedMemoryError> {
+        let mut serializer = std::mem::take(&mut self.serializer);
+        let is_empty = std::mem::replace(&mut self.is_empty, true);
+        let payload = serializer.into_string()?;
+        if is_empty {
+            return Ok(());
+        }
+
+        self.ring.push(payload.as_bytes())
+    }
+}
+
+impl GroupedMeasurementVisitor for SharedMemoryVisitor {
+    type Error = ThinEdgeJsonSerializationError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.serializer.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.measurement(name, value)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.serializer.start_group(group)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.serializer.end_group()
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.measurement_with_unit(name, value, unit)
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.serializer.start_group_with_timestamp(group, timestamp)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.nullable_measurement(name, value)
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.complex_measurement(name, real, imaginary)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.annotated_measurement(name, value, quality)
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.serializer.set_context(context)
+    }
+}
@@ -0,0 +1,593 @@
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use futures_util::{SinkExt, StreamExt};
+use log::warn;
+use reqwest::Url;
+use serde::Deserialize;
+use std::time::Duration;
+use thin_edge_json::context::MeasurementContext;
+use thin_edge_json::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use thin_edge_json::serialize::{ThinEdgeJsonSerializationError, ThinEdgeJsonSerializer};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::{interval_at, Instant};
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+/// The terminator of each message of the SignalR JSON protocol
+const RECORD_SEPARATOR: char = '\u{1e}';
+
+const HANDSHAKE_REQUEST: &str = "{\"protocol\":\"json\",\"version\":1}\u{1e}";
+const PING_MESSAGE: &str = "{\"type\":6}\u{1e}";
+
+/// The type of the message sent by a hub closing the connection
+const CLOSE_MESSAGE_TYPE: u8 = 7;
+
+const DEFAULT_TARGET: &str = "ReceiveMeasurements";
+
+/// How often a ping is sent, a hub closing idle connections after 30 seconds by default
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+type HubConnection = WebSocketStream<MaybeTlsStream<TcpStream>>;
+type Invocation = (String, oneshot::Sender<Result<(), tungstenite::Error>>);
+
+#[derive(thiserror::Error, Debug)]
+pub enum SignalRSinkError {
+    #[error("Invalid SignalR hub URL: {url}")]
+    InvalidHubUrl { url: String },
+
+    #[error("Failed to negotiate the connection with the SignalR hub: {0}")]
+    NegotiationError(#[from] reqwest::Error),
+
+    #[error("The SignalR hub refused the connection: {reason}")]
+    NegotiationRefused { reason: String },
+
+    #[error("The SignalR hub doesn't accept WebSocket connections")]
+    NoWebSocketTransport,
+
+    #[error(transparent)]
+    WebSocketError(#[from] tungstenite::Error),
+
+    #[error("The SignalR handshake failed: {reason}")]
+    HandshakeError { reason: String },
+
+    #[error("The connection to the SignalR hub is closed")]
+    ConnectionClosed,
+
+    #[error(transparent)]
+    SerializationError(#[from] ThinEdgeJsonSerializationError),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NegotiateResponse {
+    connection_id: Option<String>,
+    connection_token: Option<String>,
+    #[serde(default)]
+    available_transports: Vec<AvailableTransport>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AvailableTransport {
+    transport: String,
+}
+
+#[derive(Deserialize)]
+struct HandshakeResponse {
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct HubMessage {
+    #[serde(rename = "type")]
+    message_type: Option<u8>,
+    error: Option<String>,
+}
+
+/// A visitor that sends the measurements as thin-edge JSON to an ASP.NET Core SignalR hub.
+///
+/// The connection is established with the SignalR protocol, using the JSON hub protocol:
+///
+/// * the connection is first negotiated by a POST request on `<hub url>/negotiate`,
+/// * a WebSocket is then opened on the hub URL, with the connection token as `id`,
+/// * and the JSON protocol is selected by a handshake.
+///
+/// The measurements are gathered into a thin-edge JSON payload which is sent on `flush()`,
+/// as the single argument of a non-blocking invocation of a hub method,
+/// `ReceiveMeasurements` unless set otherwise.
+///
+/// The connection is kept alive by a ping sent every 15 seconds.
+/// A connection closed by the hub is not re-established:
+/// the following flushes fail with a `ConnectionClosed` error.
+pub struct SignalRSinkVisitor {
+    target: String,
+    invocations: mpsc::Sender<Invocation>,
+    connection: JoinHandle<()>,
+    serializer: ThinEdgeJsonSerializer,
+    is_empty: bool,
+}
+
+impl SignalRSinkVisitor {
+    /// Connect to the SignalR hub at the given URL, as `http://localhost:5000/hubs/measurements`.
+    ///
+    /// The connection runs on the current tokio runtime until the visitor is dropped.
+    pub async fn connect(hub_url: Url) -> Result<Self, SignalRSinkError> {
+        let token = negotiate(&hub_url).await?;
+        let (mut hub, _) =
+            tokio_tungstenite::connect_async(websocket_url(&hub_url, &token)?.as_str()).await?;
+        handshake(&mut hub).await?;
+
+        let (invocations, requests) = mpsc::channel(1);
+        let connection = tokio::spawn(run_connection(hub, requests));
+
+        Ok(Self {
+            target: DEFAULT_TARGET.to_string(),
+            invocations,
+            connection,
+            serializer: ThinEdgeJsonSerializer::new(),
+            is_empty: true,
+        })
+    }
+
+    /// Set the hub method invoked with the measurements, `ReceiveMeasurements` by default.
+    pub fn with_target(self, target: &str) -> Self {
+        Self {
+            target: target.to_string(),
+            ..self
+        }
+    }
+
+    /// Send the measurements gathered since the previous flush.
+    ///
+    /// Nothing is sent if no measurements have been gathered.
+    pub async fn flush(&mut self) -> Result<(), SignalRSinkError> {
+        let mut serializer = std::mem::take(&mut self.serializer);
+        let is_empty = std::mem::replace(&mut self.is_empty, true);
+        let payload = serializer.into_string()?;
+        if is_empty {
+            return Ok(());
+        }
+
+        let (sent, result) = oneshot::channel();
+        self.invocations
+            .send((invocation(&self.target, &payload), sent))
+            .await
+            .map_err(|_| SignalRSinkError::ConnectionClosed)?;
+        result
+            .await
+            .map_err(|_| SignalRSinkError::ConnectionClosed)??;
+        Ok(())
+    }
+}
+
+impl Drop for SignalRSinkVisitor {
+    fn drop(&mut self) {
+        self.connection.abort();
+    }
+}
+
+/// The URL of the negotiate endpoint of a hub, using the version 1 of the negotiation
+fn negotiate_url(hub_url: &Url) -> Url {
+    let mut url = hub_url.clone();
+    let path = format!("{}/negotiate", url.path().trim_end_matches('/'));
+    url.set_path(&path);
+    url.query_pairs_mut().append_pair("negotiateVersion", "1");
+    url
+}
+
+/// The URL of the WebSocket of a hub, for the connection with the given token
+fn websocket_url(hub_url: &Url, token: &str) -> Result<Url, SignalRSinkError> {
+    let invalid_url = || SignalRSinkError::InvalidHubUrl {
+        url: hub_url.to_string(),
+    };
+    let scheme = match hub_url.scheme() {
+        "http" => "ws",
+        "https" => "wss",
+        _ => return Err(invalid_url()),
+    };
+
+    let mut url = hub_url.clone();
+    url.set_scheme(scheme).map_err(|()| invalid_url())?;
+    url.query_pairs_mut().append_pair("id", token);
+    Ok(url)
+}
+
+/// Negotiate a connection, returning the token identifying this connection
+async fn negotiate(hub_url: &Url) -> Result<String, SignalRSinkError> {
+    let response: NegotiateResponse = reqwest::Client::new()
+        .post(negotiate_url(hub_url))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    if let Some(reason) = response.error {
+        return Err(SignalRSinkError::NegotiationRefused { reason });
+    }
+    if !response
+        .available_transports
+        .iter()
+        .any(|available| available.transport == "WebSockets")
+    {
+        return Err(SignalRSinkError::NoWebSocketTransport);
+    }
+    // The version 0 of the negotiation only gives a connection id
+    response
+        .connection_token
+        .or(response.connection_id)
+        .ok_or_else(|| SignalRSinkError::NegotiationRefused {
+            reason: "no connection token".to_string(),
+        })
+}
+
+/// Select the JSON hub protocol, waiting for the hub to accept it
+async fn handshake(hub: &mut HubConnection) -> Result<(), SignalRSinkError> {
+    hub.send(Message::Text(HANDSHAKE_REQUEST.to_string()))
+        .await?;
+
+    loop {
+        match hub.next().await {
+            Some(Ok(Message::Text(text))) => {
+                // The handshake response might be followed by other messages in the same frame
+                let response = text.split(RECORD_SEPARATOR).next().unwrap_or_default();
+                let response: HandshakeResponse =
+                    serde_json::from_str(response).map_err(|err| {
+                        SignalRSinkError::HandshakeError {
+                            reason: err.to_string(),
+                        }
+                    })?;
+                return match response.error {
+                    Some(reason) => Err(SignalRSinkError::HandshakeError { reason }),
+                    None => Ok(()),
+                };
+            }
+            Some(Ok(Message::Close(_))) | None => return Err(SignalRSinkError::ConnectionClosed),
+            Some(Ok(_)) => {}
+            Some(Err(err)) => return Err(err.into()),
+        }
+    }
+}
+
+/// A non-blocking invocation of a hub method, with a thin-edge JSON payload as argument
+fn invocation(target: &str, payload: &str) -> String {
+    format!(
+        "{{\"type\":1,\"target\":{},\"arguments\":[{}]}}{}",
+        serde_json::Value::from(target),
+        payload,
+        RECORD_SEPARATOR
+    )
+}
+
+/// Whether a frame sent by the hub holds a close message
+fn is_closed_by_hub(frame: &str) -> bool {
+    frame
+        .split(RECORD_SEPARATOR)
+        .filter(|message| !message.is_empty())
+        .filter_map(|message| serde_json::from_str::<HubMessage>(message).ok())
+        .any(|message| {
+            if message.message_type != Some(CLOSE_MESSAGE_TYPE) {
+                return false;
+            }
+            if let Some(error) = message.error {
+                warn!("The SignalR hub closed the connection: {}", error);
+            }
+            true
+        })
+}
+
+/// Send the invocations and the pings to the hub, until the connection is closed
+async fn run_connection(mut hub: HubConnection, mut invocations: mpsc::Receiver<Invocation>) {
+    let mut keep_alive = interval_at(Instant::now() + KEEP_ALIVE_INTERVAL, KEEP_ALIVE_INTERVAL);
+    loop {
+        tokio::select! {
+            invocation = invocations.recv() => match invocation {
+                Some((message, sent)) => {
+                    let result = hub.send(Message::Text(message)).await;
+                    let failed = result.is_err();
+                    let _ = sent.send(result);
+                    if failed {
+                        break;
+                    }
+                }
+                None => {
+                    let _ = hub.close(None).await;
+                    break;
+                }
+            },
+            _ = keep_alive.tick() => {
+                if let Err(err) = hub.send(Message::Text(PING_MESSAGE.to_string())).await {
+                    warn!("Failed to ping the SignalR hub: {}", err);
+                    break;
+                }
+            },
+            incoming = hub.next() => match incoming {
+                Some(Ok(Message::Text(frame))) if is_closed_by_hub(&frame) => break,
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => {}
+                Some(Err(err)) => {
+                    warn!("The connection to the SignalR hub failed: {}", err);
+                    break;
+                }
+            },
+        }
+    }
+}
+
+impl GroupedMeasurementVisitor for SignalRSinkVisitor {
+    type Error = ThinEdgeJsonSerializationError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.serializer.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.measurement(name, value)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.serializer.start_group(group)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.serializer.end_group()
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.measurement_with_unit(name, value, unit)
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.serializer.start_group_with_timestamp(group, timestamp)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.nullable_measurement(name, value)
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.complex_measurement(name, real, imaginary)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.annotated_measurement(name, value, quality)
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.serializer.set_context(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::time::timeout;
+    use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+
+    const NEGOTIATE_RESPONSE: &str = r#"{
+        "connectionId": "connection-1",
+        "connectionToken": "token-1",
+        "negotiateVersion": 1,
+        "availableTransports": [
+            {"transport": "WebSockets", "transferFormats": ["Text", "Binary"]},
+            {"transport": "LongPolling", "transferFormats": ["Text", "Binary"]}
+        ]
+    }"#;
+
+    const HANDSHAKE_ACCEPTED: &str = "{}\u{1e}";
+
+    /// A hub answering a negotiate request then accepting a WebSocket connection,
+    /// reporting the requests and the frames received
+    async fn mock_hub(
+        negotiate_response: &'static str,
+        handshake_response: &'static str,
+    ) -> anyhow::Result<(Url, mpsc::UnboundedReceiver<String>)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let hub_url = Url::parse(&format!(
+            "http://{}/hubs/measurements",
+            listener.local_addr()?
+        ))?;
+        let (events, received) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            if let Err(err) = serve(listener, negotiate_response, handshake_response, events).await
+            {
+                eprintln!("The mock hub failed: {}", err);
+            }
+        });
+        Ok((hub_url, received))
+    }
+
+    async fn serve(
+        listener: TcpListener,
+        negotiate_response: &str,
+        handshake_response: &str,
+        events: mpsc::UnboundedSender<String>,
+    ) -> anyhow::Result<()> {
+        let (mut stream, _) = listener.accept().await?;
+        let request = read_request_head(&mut stream).await?;
+        events.send(request.lines().next().unwrap_or_default().to_string())?;
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            negotiate_response.len(),
+            negotiate_response
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await?;
+
+        let (stream, _) = listener.accept().await?;
+        let upgrade_events = events.clone();
+        let mut ws = tokio_tungstenite::accept_hdr_async(
+            stream,
+            move |request: &Request, response: Response| {
+                let _ = upgrade_events.send(format!("GET {}", request.uri()));
+                Ok::<Response, ErrorResponse>(response)
+            },
+        )
+        .await?;
+
+        let mut handshake_done = false;
+        while let Some(message) = ws.next().await {
+            if let Message::Text(frame) = message? {
+                events.send(frame)?;
+                if !handshake_done {
+                    ws.send(Message::Text(handshake_response.to_string()))
+                        .await?;
+                    handshake_done = true;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn read_request_head(stream: &mut TcpStream) -> anyhow::Result<String> {
+        let mut head = Vec::new();
+        let mut buffer = [0; 1024];
+        while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+            let count = stream.read(&mut buffer).await?;
+            anyhow::ensure!(count > 0, "Incomplete request");
+            head.extend_from_slice(&buffer[..count]);
+        }
+        Ok(String::from_utf8(head)?)
+    }
+
+    async fn next_event(events: &mut mpsc::UnboundedReceiver<String>) -> anyhow::Result<String> {
+        match timeout(Duration::from_secs(2), events.recv()).await? {
+            Some(event) => Ok(event),
+            None => anyhow::bail!("The mock hub stopped"),
+        }
+    }
+
+    #[tokio::test]
+    async fn measurements_are_sent_to_the_hub_after_the_handshake() -> anyhow::Result<()> {
+        let (hub_url, mut events) = mock_hub(NEGOTIATE_RESPONSE, HANDSHAKE_ACCEPTED).await?;
+
+        let mut visitor = SignalRSinkVisitor::connect(hub_url).await?;
+        visitor.measurement("temperature", 25.5)?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.end_group()?;
+        visitor.flush().await?;
+
+        assert!(next_event(&mut events)
+            .await?
+            .starts_with("POST /hubs/measurements/negotiate?negotiateVersion=1 "));
+        assert_eq!(
+            next_event(&mut events).await?,
+            "GET /hubs/measurements?id=token-1"
+        );
+        assert_eq!(
+            next_event(&mut events).await?,
+            "{\"protocol\":\"json\",\"version\":1}\u{1e}"
+        );
+        assert_eq!(
+            next_event(&mut events).await?,
+            "{\"type\":1,\"target\":\"ReceiveMeasurements\",\"arguments\":[{\"temperature\":25.5,\"location\":{\"alti\":2100.4}}]}\u{1e}"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn the_hub_method_can_be_set() -> anyhow::Result<()> {
+        let (hub_url, mut events) = mock_hub(NEGOTIATE_RESPONSE, HANDSHAKE_ACCEPTED).await?;
+
+        let mut visitor = SignalRSinkVisitor::connect(hub_url)
+            .await?
+            .with_target("Broadcast");
+        visitor.flush().await?;
+        visitor.measurement("temperature", 25.5)?;
+        visitor.flush().await?;
+
+        // The negotiate request, the WebSocket upgrade and the handshake
+        for _ in 0..3 {
+            next_event(&mut events).await?;
+        }
+        // Nothing is sent on the first flush
+        assert_eq!(
+            next_event(&mut events).await?,
+            "{\"type\":1,\"target\":\"Broadcast\",\"arguments\":[{\"temperature\":25.5}]}\u{1e}"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_failed_handshake_is_reported() -> anyhow::Result<()> {
+        let (hub_url, _events) = mock_hub(
+            NEGOTIATE_RESPONSE,
+            "{\"error\":\"Requested protocol 'json' is not available.\"}\u{1e}",
+        )
+        .await?;
+
+        assert_matches!(
+            SignalRSinkVisitor::connect(hub_url).await,
+            Err(SignalRSinkError::HandshakeError { reason }) if reason.contains("not available")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hubs_without_websocket_transport_are_rejected() -> anyhow::Result<()> {
+        let (hub_url, _events) = mock_hub(
+            r#"{"connectionToken":"token-1","availableTransports":[{"transport":"LongPolling"}]}"#,
+            HANDSHAKE_ACCEPTED,
+        )
+        .await?;
+
+        assert_matches!(
+            SignalRSinkVisitor::connect(hub_url).await,
+            Err(SignalRSinkError::NoWebSocketTransport)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn websocket_urls_are_derived_from_the_hub_url() -> anyhow::Result<()> {
+        let hub_url = Url::parse("https://example.com/hubs/measurements/")?;
+
+        assert_eq!(
+            negotiate_url(&hub_url).as_str(),
+            "https://example.com/hubs/measurements/negotiate?negotiateVersion=1"
+        );
+        assert_eq!(
+            websocket_url(&hub_url, "a b")?.as_str(),
+            "wss://example.com/hubs/measurements/?id=a+b"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn close_messages_are_detected() {
+        assert!(is_closed_by_hub(
+            "{\"type\":6}\u{1e}{\"type\":7,\"error\":\"Shutdown\"}\u{1e}"
+        ));
+        assert!(!is_closed_by_hub("{\"type\":6}\u{1e}"));
+    }
+}
@@ -0,0 +1,23 @@
+//! A sink invoking an ASP.NET Core SignalR hub with thin-edge JSON measurements,
+//! as used by real-time dashboards.
+//!
+//! ```no_run
+//! use reqwest::Url;
+//! use signalr_sink::SignalRSinkVisitor;
+//! use thin_edge_json::measurement::GroupedMeasurementVisitor;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), anyhow::Error> {
+//! let hub_url = Url::parse("http://dashboard.local/hubs/measurements")?;
+//! let mut visitor = SignalRSinkVisitor::connect(hub_url)
+//!     .await?
+//!     .with_target("ReceiveMeasurements");
+//!
+//! visitor.measurement("temperature", 25.5)?;
+//! visitor.flush().await?; // Invokes `ReceiveMeasurements({"temperature":25.5})` on the hub
+//! # Ok(()) }
+//! ```
+
+mod hub;
+
+pub use hub::{SignalRSinkError, SignalRSinkVisitor};
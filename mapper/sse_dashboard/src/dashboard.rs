@@ -0,0 +1,392 @@
+use bytes::Bytes;
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use futures_util::stream;
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::{error, warn};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use thin_edge_json::context::MeasurementContext;
+use thin_edge_json::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use thin_edge_json::serialize::{ThinEdgeJsonSerializationError, ThinEdgeJsonSerializer};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::JoinHandle;
+
+/// The path of the event stream
+const EVENTS_PATH: &str = "/events";
+
+const DEFAULT_MAX_CLIENTS: usize = 100;
+
+/// The number of events a slow client can be late of before being dropped
+const DEFAULT_BUFFER_SIZE: usize = 64;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SseDashboardError {
+    #[error("Failed to listen on {addr}: {from}")]
+    BindError {
+        addr: SocketAddr,
+        from: hyper::Error,
+    },
+
+    #[error(transparent)]
+    SerializationError(#[from] ThinEdgeJsonSerializationError),
+}
+
+/// A visitor that streams thin-edge JSON payloads to browsers, as Server-Sent Events.
+///
+/// The clients subscribe with a `GET /events` request, as done by an `EventSource`,
+/// and then receive each payload as a `data: {...}` event.
+/// The measurements are gathered into a thin-edge JSON payload
+/// which is sent to all the subscribed clients on `flush()`.
+///
+/// * At most 100 clients can be subscribed at once,
+///   any further subscription being rejected with a `503 Service Unavailable` status.
+/// * Each client has a buffer of 64 events:
+///   a client too slow to keep up is dropped when its buffer overflows,
+///   its event stream being ended once the buffered events have been sent.
+pub struct SseDashboardVisitor {
+    hub: Arc<Hub>,
+    local_addr: SocketAddr,
+    server: JoinHandle<()>,
+    serializer: ThinEdgeJsonSerializer,
+    is_empty: bool,
+}
+
+impl SseDashboardVisitor {
+    /// Start to serve the event stream on the given address.
+    ///
+    /// The server runs on the current tokio runtime until the visitor is dropped.
+    pub fn bind(addr: SocketAddr) -> Result<Self, SseDashboardError> {
+        let hub = Arc::new(Hub::new());
+        let service_hub = hub.clone();
+        let make_service = make_service_fn(move |_connection| {
+            let hub = service_hub.clone();
+            let service = service_fn(move |request| serve(request, hub.clone()));
+            async move { Ok::<_, Infallible>(service) }
+        });
+
+        let server = Server::try_bind(&addr)
+            .map_err(|from| SseDashboardError::BindError { addr, from })?
+            .serve(make_service);
+        let local_addr = server.local_addr();
+        let server = tokio::spawn(async move {
+            if let Err(err) = server.await {
+                error!("The SSE dashboard server failed: {}", err);
+            }
+        });
+
+        Ok(Self {
+            hub,
+            local_addr,
+            server,
+            serializer: ThinEdgeJsonSerializer::new(),
+            is_empty: true,
+        })
+    }
+
+    /// Set the maximum number of clients subscribed at once.
+    pub fn with_max_clients(self, max_clients: usize) -> Self {
+        self.hub.clients().max_clients = max_clients;
+        self
+    }
+
+    /// Set the number of events a slow client can be late of before being dropped,
+    /// for the clients subscribing from now on.
+    pub fn with_buffer_size(self, buffer_size: usize) -> Self {
+        self.hub.clients().buffer_size = buffer_size.max(1);
+        self
+    }
+
+    /// The address the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// The number of clients currently subscribed
+    pub fn client_count(&self) -> usize {
+        self.hub.client_count()
+    }
+
+    /// Send the measurements gathered since the previous flush.
+    ///
+    /// Nothing is sent if no measurements have been gathered.
+    pub fn flush(&mut self) -> Result<(), ThinEdgeJsonSerializationError> {
+        let mut serializer = std::mem::take(&mut self.serializer);
+        let is_empty = std::mem::replace(&mut self.is_empty, true);
+        let payload = serializer.into_string()?;
+        if is_empty {
+            return Ok(());
+        }
+
+        self.hub
+            .publish(Bytes::from(format!("data: {}\n\n", payload)));
+        Ok(())
+    }
+}
+
+impl Drop for SseDashboardVisitor {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+impl GroupedMeasurementVisitor for SseDashboardVisitor {
+    type Error = ThinEdgeJsonSerializationError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.serializer.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.measurement(name, value)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.serializer.start_group(group)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.serializer.end_group()
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.measurement_with_unit(name, value, unit)
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.serializer.start_group_with_timestamp(group, timestamp)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.nullable_measurement(name, value)
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.complex_measurement(name, real, imaginary)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.annotated_measurement(name, value, quality)
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.serializer.set_context(context)
+    }
+}
+
+/// The subscribed clients, shared by the visitor and the server
+struct Hub {
+    clients: Mutex<Clients>,
+}
+
+struct Clients {
+    senders: Vec<mpsc::Sender<Bytes>>,
+    max_clients: usize,
+    buffer_size: usize,
+}
+
+impl Hub {
+    fn new() -> Self {
+        Self {
+            clients: Mutex::new(Clients {
+                senders: Vec::new(),
+                max_clients: DEFAULT_MAX_CLIENTS,
+                buffer_size: DEFAULT_BUFFER_SIZE,
+            }),
+        }
+    }
+
+    fn clients(&self) -> MutexGuard<Clients> {
+        self.clients.lock().expect("Poisoned lock")
+    }
+
+    fn client_count(&self) -> usize {
+        let mut clients = self.clients();
+        clients.senders.retain(|sender| !sender.is_closed());
+        clients.senders.len()
+    }
+
+    /// A receiver of the events to come, unless the maximum number of clients is reached
+    fn subscribe(&self) -> Option<mpsc::Receiver<Bytes>> {
+        let mut clients = self.clients();
+        clients.senders.retain(|sender| !sender.is_closed());
+        if clients.senders.len() >= clients.max_clients {
+            return None;
+        }
+
+        let (sender, receiver) = mpsc::channel(clients.buffer_size);
+        clients.senders.push(sender);
+        Some(receiver)
+    }
+
+    /// Send an event to all the clients, dropping those whose buffer is full
+    fn publish(&self, event: Bytes) {
+        let mut clients = self.clients();
+        clients
+            .senders
+            .retain(|sender| match sender.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!("Dropping an SSE client too slow to keep up with the events");
+                    false
+                }
+                Err(TrySendError::Closed(_)) => false,
+            });
+    }
+}
+
+async fn serve(request: Request<Body>, hub: Arc<Hub>) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::GET || request.uri().path() != EVENTS_PATH {
+        return Ok(status(StatusCode::NOT_FOUND));
+    }
+
+    let receiver = match hub.subscribe() {
+        Some(receiver) => receiver,
+        None => return Ok(status(StatusCode::SERVICE_UNAVAILABLE)),
+    };
+    let events = stream::unfold(receiver, |mut receiver| async move {
+        let event = receiver.recv().await?;
+        Some((Ok::<_, Infallible>(event), receiver))
+    });
+
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        .body(Body::wrap_stream(events))
+        .expect("A valid response"))
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::body::HttpBody;
+    use hyper::Client;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    fn start_server() -> anyhow::Result<SseDashboardVisitor> {
+        Ok(SseDashboardVisitor::bind("127.0.0.1:0".parse()?)?)
+    }
+
+    async fn subscribe(
+        visitor: &SseDashboardVisitor,
+        path: &str,
+    ) -> anyhow::Result<Response<Body>> {
+        let uri = format!("http://{}{}", visitor.local_addr(), path).parse()?;
+        Ok(Client::new().get(uri).await?)
+    }
+
+    async fn next_event(response: &mut Response<Body>) -> anyhow::Result<String> {
+        match timeout(Duration::from_secs(2), response.body_mut().data()).await? {
+            Some(chunk) => Ok(String::from_utf8(chunk?.to_vec())?),
+            None => anyhow::bail!("The event stream is closed"),
+        }
+    }
+
+    #[tokio::test]
+    async fn a_client_receives_the_next_payloads() -> anyhow::Result<()> {
+        let mut visitor = start_server()?;
+        let mut response = subscribe(&visitor, "/events").await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/event-stream");
+        assert_eq!(visitor.client_count(), 1);
+
+        visitor.measurement("temperature", 25.5)?;
+        visitor.flush()?;
+        assert_eq!(
+            next_event(&mut response).await?,
+            "data: {\"temperature\":25.5}\n\n"
+        );
+
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.end_group()?;
+        visitor.flush()?;
+        assert_eq!(
+            next_event(&mut response).await?,
+            "data: {\"location\":{\"alti\":2100.4}}\n\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn subscriptions_beyond_the_maximum_are_rejected() -> anyhow::Result<()> {
+        let visitor = start_server()?.with_max_clients(1);
+
+        let first = subscribe(&visitor, "/events").await?;
+        let second = subscribe(&visitor, "/events").await?;
+
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(second.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(visitor.client_count(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn only_the_event_stream_is_served() -> anyhow::Result<()> {
+        let visitor = start_server()?;
+
+        let response = subscribe(&visitor, "/").await?;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(visitor.client_count(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn slow_clients_are_dropped_when_their_buffer_overflows() {
+        let hub = Hub::new();
+        hub.clients().buffer_size = 2;
+        let mut slow = hub.subscribe().expect("A subscription");
+
+        for i in 0..3 {
+            hub.publish(Bytes::from(format!("data: {{\"counter\":{}}}\n\n", i)));
+        }
+
+        assert_eq!(hub.client_count(), 0);
+        // The buffered events are still delivered before the end of the stream
+        assert_eq!(
+            slow.recv().await,
+            Some(Bytes::from("data: {\"counter\":0}\n\n"))
+        );
+        assert_eq!(
+            slow.recv().await,
+            Some(Bytes::from("data: {\"counter\":1}\n\n"))
+        );
+        assert_eq!(slow.recv().await, None);
+    }
+}
@@ -0,0 +1,20 @@
+//! A live dashboard endpoint streaming thin-edge JSON measurements
+//! to browsers as Server-Sent Events.
+//!
+//! ```no_run
+//! use sse_dashboard::SseDashboardVisitor;
+//! use thin_edge_json::measurement::GroupedMeasurementVisitor;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), anyhow::Error> {
+//! // Browsers subscribe with `new EventSource("http://127.0.0.1:8080/events")`
+//! let mut visitor = SseDashboardVisitor::bind("127.0.0.1:8080".parse()?)?;
+//!
+//! visitor.measurement("temperature", 25.5)?;
+//! visitor.flush()?; // Sends `data: {"temperature":25.5}` to all the subscribed clients
+//! # Ok(()) }
+//! ```
+
+mod dashboard;
+
+pub use dashboard::{SseDashboardError, SseDashboardVisitor};
@@ -0,0 +1,251 @@
+use mqtt_client::{Message, MqttClient, MqttClientError, Topic};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use thin_edge_json::version::SchemaVersion;
+
+const CAPABILITIES_TOPIC_PREFIX: &str = "te/capabilities";
+
+/// The prefix of the format names exchanged on negotiation, followed by the major version
+const FORMAT_NAME_PREFIX: &str = "te/v";
+
+#[derive(Debug, thiserror::Error)]
+pub enum NegotiationError {
+    #[error(transparent)]
+    MqttClientError(#[from] MqttClientError),
+
+    #[error("No acknowledgement of the capabilities received within {timeout:?}")]
+    Timeout { timeout: Duration },
+
+    #[error("Invalid acknowledgement of the capabilities: {reason}")]
+    InvalidAcknowledgement { reason: String },
+
+    #[error("No thin-edge JSON format is supported by both sides")]
+    NoCommonFormat,
+}
+
+/// The payload of the capabilities and of their acknowledgement,
+/// as `{"capabilities":{"formats":["te/v1","te/v2"]}}`.
+#[derive(Debug, Serialize, Deserialize)]
+struct CapabilitiesMessage {
+    capabilities: Capabilities,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Capabilities {
+    formats: Vec<String>,
+}
+
+/// Negotiate the version of the thin-edge JSON format used over an MQTT session.
+///
+/// The formats supported by the mapper are published on `te/capabilities/<device_id>`.
+/// The peer acknowledges on `te/capabilities/<device_id>/ack`,
+/// listing the formats supported by both sides, the latest of these formats being selected.
+/// Only the major versions are exchanged, as `te/v2`,
+/// the minor versions of a format being backward compatible.
+/// The negotiation fails if no acknowledgement is received within the given timeout.
+pub struct CapabilityNegotiator<'a> {
+    client: &'a dyn MqttClient,
+    device_id: String,
+    formats: Vec<SchemaVersion>,
+    timeout: Duration,
+}
+
+impl<'a> CapabilityNegotiator<'a> {
+    pub fn new(
+        client: &'a dyn MqttClient,
+        device_id: &str,
+        formats: Vec<SchemaVersion>,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            client,
+            device_id: device_id.to_string(),
+            formats,
+            timeout,
+        }
+    }
+
+    pub async fn negotiate(&self) -> Result<SchemaVersion, NegotiationError> {
+        let topic = Topic::new(&format!("{}/{}", CAPABILITIES_TOPIC_PREFIX, self.device_id))?;
+        let ack_topic = Topic::new(&format!("{}/ack", topic.name))?;
+
+        // Subscribe first, not to miss an acknowledgement sent right after the capabilities
+        let mut acks = self.client.subscribe(ack_topic.filter()).await?;
+        self.client
+            .publish(Message::new(&topic, self.capabilities_payload()))
+            .await?;
+
+        let timeout = self.timeout;
+        match tokio::time::timeout(timeout, acks.next()).await {
+            Ok(Some(ack)) => self.select_format(ack.payload_raw()),
+            Ok(None) | Err(_) => Err(NegotiationError::Timeout { timeout }),
+        }
+    }
+
+    fn capabilities_payload(&self) -> String {
+        let message = CapabilitiesMessage {
+            capabilities: Capabilities {
+                formats: self
+                    .formats
+                    .iter()
+                    .map(|format| format_name(*format))
+                    .collect(),
+            },
+        };
+        serde_json::to_string(&message).expect("Capabilities are serializable")
+    }
+
+    /// Select the latest of the acknowledged formats that is supported by the mapper
+    fn select_format(&self, ack: &[u8]) -> Result<SchemaVersion, NegotiationError> {
+        let ack: CapabilitiesMessage = serde_json::from_slice(ack).map_err(|err| {
+            NegotiationError::InvalidAcknowledgement {
+                reason: err.to_string(),
+            }
+        })?;
+
+        let acknowledged: Vec<u8> = ack
+            .capabilities
+            .formats
+            .iter()
+            .filter_map(|format| parse_format_name(format))
+            .collect();
+        self.formats
+            .iter()
+            .filter(|format| acknowledged.contains(&format.major))
+            .max()
+            .copied()
+            .ok_or(NegotiationError::NoCommonFormat)
+    }
+}
+
+/// The name of a format as exchanged on negotiation, as `te/v2`
+fn format_name(format: SchemaVersion) -> String {
+    format!("{}{}", FORMAT_NAME_PREFIX, format.major)
+}
+
+/// The major version of a format named as `te/v2`
+fn parse_format_name(name: &str) -> Option<u8> {
+    name.strip_prefix(FORMAT_NAME_PREFIX)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use mqtt_client::{MockMqttClient, MockMqttMessageStream};
+    use std::future::{pending, ready};
+
+    const DEVICE_ID: &str = "device-1";
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    fn supported_formats() -> Vec<SchemaVersion> {
+        vec![SchemaVersion::new(1, 0), SchemaVersion::new(2, 0)]
+    }
+
+    /// A broker on which the peer acknowledges the capabilities with the given payload
+    fn mock_broker(ack: &'static str) -> MockMqttClient {
+        let mut broker = MockMqttClient::new();
+        broker.expect_subscribe().times(1).returning(move |filter| {
+            assert_eq!(filter.pattern, "te/capabilities/device-1/ack");
+            let mut acks = MockMqttMessageStream::default();
+            acks.expect_next().returning(move || {
+                let topic = Topic::new("te/capabilities/device-1/ack").unwrap();
+                Box::pin(ready(Some(Message::new(&topic, ack))))
+            });
+            Ok(Box::new(acks))
+        });
+        broker.expect_publish().times(1).returning(|message| {
+            assert_eq!(message.topic.name, "te/capabilities/device-1");
+            assert_eq!(
+                message.payload_str().unwrap(),
+                r#"{"capabilities":{"formats":["te/v1","te/v2"]}}"#
+            );
+            Ok(1)
+        });
+        broker
+    }
+
+    #[tokio::test]
+    async fn the_latest_common_format_is_selected() -> anyhow::Result<()> {
+        let broker = mock_broker(r#"{"capabilities":{"formats":["te/v1","te/v2","te/v3"]}}"#);
+        let negotiator =
+            CapabilityNegotiator::new(&broker, DEVICE_ID, supported_formats(), TIMEOUT);
+
+        assert_eq!(negotiator.negotiate().await?, SchemaVersion::new(2, 0));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn an_older_format_is_selected_if_the_latest_is_not_acknowledged() -> anyhow::Result<()> {
+        let broker = mock_broker(r#"{"capabilities":{"formats":["te/v1"]}}"#);
+        let negotiator =
+            CapabilityNegotiator::new(&broker, DEVICE_ID, supported_formats(), TIMEOUT);
+
+        assert_eq!(negotiator.negotiate().await?, SchemaVersion::new(1, 0));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn the_negotiation_fails_without_common_format() {
+        let broker = mock_broker(r#"{"capabilities":{"formats":["te/v3","v4","te/2.0"]}}"#);
+        let negotiator =
+            CapabilityNegotiator::new(&broker, DEVICE_ID, supported_formats(), TIMEOUT);
+
+        assert_matches!(
+            negotiator.negotiate().await,
+            Err(NegotiationError::NoCommonFormat)
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_acknowledgements_are_rejected() {
+        let broker = mock_broker(r#"{"formats":["te/v1"]}"#);
+        let negotiator =
+            CapabilityNegotiator::new(&broker, DEVICE_ID, supported_formats(), TIMEOUT);
+
+        assert_matches!(
+            negotiator.negotiate().await,
+            Err(NegotiationError::InvalidAcknowledgement { .. })
+        );
+    }
+
+    #[tokio::test]
+    async fn the_supported_minor_version_is_selected() -> anyhow::Result<()> {
+        let broker = mock_broker(r#"{"capabilities":{"formats":["te/v1","te/v2"]}}"#);
+        let formats = vec![SchemaVersion::new(1, 0), SchemaVersion::new(2, 1)];
+        let negotiator = CapabilityNegotiator::new(&broker, DEVICE_ID, formats, TIMEOUT);
+
+        assert_eq!(negotiator.negotiate().await?, SchemaVersion::new(2, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn formats_are_named_after_their_major_version() {
+        assert_eq!(format_name(SchemaVersion::new(2, 1)), "te/v2");
+        assert_eq!(parse_format_name("te/v2"), Some(2));
+        assert_eq!(parse_format_name("te/2.0"), None);
+        assert_eq!(parse_format_name("v2"), None);
+    }
+
+    #[tokio::test]
+    async fn the_negotiation_times_out_without_acknowledgement() {
+        let mut broker = MockMqttClient::new();
+        broker.expect_subscribe().returning(|_| {
+            let mut acks = MockMqttMessageStream::default();
+            acks.expect_next().returning(|| Box::pin(pending()));
+            Ok(Box::new(acks))
+        });
+        broker.expect_publish().returning(|_| Ok(1));
+        let negotiator = CapabilityNegotiator::new(
+            &broker,
+            DEVICE_ID,
+            supported_formats(),
+            Duration::from_millis(10),
+        );
+
+        assert_matches!(
+            negotiator.negotiate().await,
+            Err(NegotiationError::Timeout { .. })
+        );
+    }
+}
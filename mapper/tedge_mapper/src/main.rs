@@ -11,6 +11,7 @@ mod az_converter;
 mod az_mapper;
 mod c8y_converter;
 mod c8y_mapper;
+mod capabilities;
 mod component;
 mod converter;
 mod error;
@@ -1,11 +1,27 @@
+use crate::capabilities::CapabilityNegotiator;
 use crate::converter::*;
 use crate::error::*;
 
 use flockfile::{Flockfile, FlockfileError};
-use mqtt_client::{Client, MqttClient, MqttClientError, Topic};
-use tedge_config::{ConfigSettingAccessor, MqttPortSetting, TEdgeConfig};
+use mqtt_client::{Client, MqttClient, MqttClientError, MqttCredentials, Topic};
+use std::time::Duration;
+use tedge_config::{
+    ConfigSettingAccessor, ConfigSettingError, DeviceIdSetting, MqttNegotiateFormatSetting,
+    MqttPasswordSetting, MqttPortSetting, MqttUsernameSetting, TEdgeConfig,
+};
+use thin_edge_json::expiry::ThinEdgeJsonExpiryFilter;
+use thin_edge_json::version::SchemaVersion;
 use tokio::task::JoinHandle;
-use tracing::{debug, error, info, instrument};
+use tracing::{debug, error, info, instrument, warn};
+
+/// The versions of the thin-edge JSON format the mappers can process.
+///
+/// Only the formats the converters actually parse and produce are advertised,
+/// so that whatever the peer selects, the mapper processes it unchanged.
+const SUPPORTED_FORMATS: [SchemaVersion; 1] = [SchemaVersion { major: 1, minor: 0 }];
+
+/// How long a mapper waits for the acknowledgement of its capabilities
+const NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(2);
 
 #[derive(Debug)]
 pub struct MapperConfig {
@@ -30,12 +46,59 @@ pub async fn create_mapper<'a>(
 
     let mqtt_config = mqtt_config(tedge_config)?;
     let mqtt_client = Client::connect(app_name, &mqtt_config).await?;
+    let negotiate: bool = tedge_config.query(MqttNegotiateFormatSetting)?.into();
+    if negotiate {
+        negotiate_format(tedge_config, &mqtt_client).await;
+    }
 
     Ok(Mapper::new(mqtt_client, mapper_config, converter, flock))
 }
 
+/// The credentials set in the configuration are used if any,
+/// falling back to those given by the environment.
 fn mqtt_config(tedge_config: &TEdgeConfig) -> Result<mqtt_client::Config, anyhow::Error> {
-    Ok(mqtt_client::Config::default().with_port(tedge_config.query(MqttPortSetting)?.into()))
+    let config =
+        mqtt_client::Config::default().with_port(tedge_config.query(MqttPortSetting)?.into());
+    let config = match mqtt_credentials(tedge_config)? {
+        Some(credentials) => config.with_credentials(credentials),
+        None => config,
+    };
+    Ok(config.with_credentials_from_env())
+}
+
+/// The credentials set in the configuration, if both the username and the password are set
+fn mqtt_credentials(
+    tedge_config: &TEdgeConfig,
+) -> Result<Option<MqttCredentials>, ConfigSettingError> {
+    let username = tedge_config.query_optional(MqttUsernameSetting)?;
+    let password = tedge_config.query_optional(MqttPasswordSetting)?;
+    Ok(username
+        .zip(password)
+        .map(|(username, password)| MqttCredentials::new(username, password.expose_secret())))
+}
+
+/// Negotiate the thin-edge JSON format with the peers of the device, if any.
+///
+/// This is only done when `mqtt.negotiate_format` is set,
+/// as it delays the start of the mapper till a peer acknowledges or the negotiation times out.
+/// A failed negotiation is not fatal: the mapper keeps processing the messages it receives.
+async fn negotiate_format(tedge_config: &TEdgeConfig, client: &dyn MqttClient) {
+    let device_id = match tedge_config.query(DeviceIdSetting) {
+        Ok(device_id) => device_id,
+        Err(err) => {
+            debug!("No capability negotiation without device id: {}", err);
+            return;
+        }
+    };
+
+    let formats = SUPPORTED_FORMATS.to_vec();
+    match CapabilityNegotiator::new(client, &device_id, formats, NEGOTIATION_TIMEOUT)
+        .negotiate()
+        .await
+    {
+        Ok(format) => info!("Negotiated thin-edge JSON format: {}", format),
+        Err(err) => warn!("Capability negotiation failed: {}", err),
+    }
 }
 
 fn check_another_instance_is_not_running(app_name: &str) -> Result<Flockfile, FlockfileError> {
@@ -94,6 +157,10 @@ impl Mapper {
     async fn subscribe_messages(&self) -> Result<(), MqttClientError> {
         let mut messages = self.client.subscribe(self.config.in_topic.filter()).await?;
         while let Some(message) = messages.next().await {
+            if ThinEdgeJsonExpiryFilter::is_expired(message.payload_raw()) {
+                debug!("Dropping expired message {:?}", message.payload_str());
+                continue;
+            }
             debug!("Mapping {:?}", message.payload_str());
             match self.converter.convert(message.payload_str()?) {
                 Ok(mapped) => {
@@ -0,0 +1,119 @@
+use chrono::offset::FixedOffset;
+use chrono::{DateTime, TimeZone};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use thin_edge_json::buffer::MeasurementBuffer;
+use thin_edge_json::json::parse_str;
+use thin_edge_json::measurement::GroupedMeasurementVisitor;
+use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    serialize_single_measurement(c);
+    serialize_10_measurements_group(c);
+    serialize_10x10_multi_measurements(c);
+    serialize_with_default_timestamp(c);
+    compare_into_string_and_bytes(c);
+    serialize_then_parse(c);
+}
+
+fn serialize_single_measurement(c: &mut Criterion) {
+    c.bench_function("single measurement", |b| {
+        b.iter(|| {
+            let mut serializer = ThinEdgeJsonSerializer::new();
+            serializer
+                .measurement("temperature", black_box(25.5))
+                .expect("Expect a valid measurement");
+            serializer
+                .into_string()
+                .expect("Expect a complete series of measurements")
+        })
+    });
+}
+
+fn serialize_10_measurements_group(c: &mut Criterion) {
+    c.bench_function("10 measurements in a group", |b| {
+        b.iter(|| to_string(ThinEdgeJsonSerializer::new(), 1, 10))
+    });
+}
+
+fn serialize_10x10_multi_measurements(c: &mut Criterion) {
+    c.bench_function("10x10 multi-measurements", |b| {
+        b.iter(|| to_string(ThinEdgeJsonSerializer::new(), 10, 10))
+    });
+}
+
+fn serialize_with_default_timestamp(c: &mut Criterion) {
+    let timestamp = test_timestamp();
+    c.bench_function("10 measurements with a default timestamp", |b| {
+        b.iter(|| {
+            to_string(
+                ThinEdgeJsonSerializer::new_with_timestamp(Some(timestamp)),
+                1,
+                10,
+            )
+        })
+    });
+}
+
+fn compare_into_string_and_bytes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("10x10 multi-measurements output");
+    group.bench_function("into_string", |b| {
+        b.iter(|| {
+            let mut serializer = ThinEdgeJsonSerializer::new();
+            produce(&mut serializer, 10, 10);
+            serializer
+                .into_string()
+                .expect("Expect a complete series of measurements")
+        })
+    });
+    group.bench_function("bytes", |b| {
+        b.iter(|| {
+            let mut serializer = ThinEdgeJsonSerializer::new();
+            produce(&mut serializer, 10, 10);
+            serializer
+                .bytes()
+                .expect("Expect a complete series of measurements")
+        })
+    });
+    group.finish();
+}
+
+fn serialize_then_parse(c: &mut Criterion) {
+    c.bench_function("10x10 multi-measurements round-trip", |b| {
+        b.iter(|| {
+            let json = to_string(ThinEdgeJsonSerializer::new(), 10, 10);
+            let mut buffer = MeasurementBuffer::new();
+            parse_str(&json, &mut buffer).expect("Expect a valid thin-edge-json message");
+            buffer
+        })
+    });
+}
+
+fn to_string(mut serializer: ThinEdgeJsonSerializer, n_grp: u64, n_per_grp: u64) -> String {
+    produce(&mut serializer, n_grp, n_per_grp);
+    serializer
+        .into_string()
+        .expect("Expect a complete series of measurements")
+}
+
+fn produce(serializer: &mut ThinEdgeJsonSerializer, n_grp: u64, n_per_grp: u64) {
+    for i in 0..n_grp {
+        serializer
+            .start_group(&format!("group_{}", i))
+            .expect("Expect a valid group");
+        for j in 0..n_per_grp {
+            serializer
+                .measurement(&format!("measurement_{}", j), black_box((i * j) as f64))
+                .expect("Expect a valid measurement");
+        }
+        serializer.end_group().expect("Expect a valid group");
+    }
+}
+
+fn test_timestamp() -> DateTime<FixedOffset> {
+    FixedOffset::east(5 * 3600)
+        .ymd(2021, 6, 22)
+        .and_hms_nano(17, 3, 14, 123456789)
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,113 @@
+//! Drive a `ThinEdgeJsonSerializer` with arbitrary sequences of visitor calls.
+//!
+//! The serializer must never panic, and must produce valid JSON
+//! whenever all the calls and the final `into_string()` succeed.
+//! A serializer that returned an error is not used any further,
+//! as a producer would discard the message.
+//!
+//! Run with: `cargo fuzz run serializer_sequence` from `mapper/thin_edge_json`.
+#![no_main]
+
+use chrono::{FixedOffset, TimeZone};
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+use thin_edge_json::trace::VisitorCall;
+
+/// An arbitrary call on a serializer, turned into a `VisitorCall` when possible
+#[derive(Debug, Arbitrary)]
+enum FuzzedCall {
+    Timestamp {
+        secs: i64,
+        offset: i32,
+    },
+    Measurement {
+        name: String,
+        value: f64,
+    },
+    MeasurementWithUnit {
+        name: String,
+        value: f64,
+        unit: String,
+    },
+    NullableMeasurement {
+        name: String,
+        value: Option<f64>,
+    },
+    StartGroup {
+        group: String,
+    },
+    StartGroupWithTimestamp {
+        group: String,
+        secs: i64,
+        offset: i32,
+    },
+    EndGroup,
+    AddMetadata {
+        key: String,
+        value: String,
+    },
+}
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    detect_duplicates: bool,
+    calls: Vec<FuzzedCall>,
+}
+
+impl FuzzedCall {
+    fn apply(self, serializer: &mut ThinEdgeJsonSerializer) -> Result<(), ()> {
+        let call = match self {
+            FuzzedCall::AddMetadata { key, value } => {
+                // Metadata keys are prefixed, as otherwise rejected
+                let key = format!("_{}", key);
+                return serializer.add_metadata(&key, &value).map_err(|_| ());
+            }
+            FuzzedCall::Timestamp { secs, offset } => VisitorCall::Timestamp {
+                value: timestamp(secs, offset).ok_or(())?,
+            },
+            FuzzedCall::Measurement { name, value } => VisitorCall::Measurement { name, value },
+            FuzzedCall::MeasurementWithUnit { name, value, unit } => {
+                VisitorCall::MeasurementWithUnit { name, value, unit }
+            }
+            FuzzedCall::NullableMeasurement { name, value } => {
+                VisitorCall::NullableMeasurement { name, value }
+            }
+            FuzzedCall::StartGroup { group } => VisitorCall::StartGroup { group },
+            FuzzedCall::StartGroupWithTimestamp {
+                group,
+                secs,
+                offset,
+            } => VisitorCall::StartGroupWithTimestamp {
+                group,
+                value: timestamp(secs, offset).ok_or(())?,
+            },
+            FuzzedCall::EndGroup => VisitorCall::EndGroup,
+        };
+        call.apply(serializer).map_err(|_| ())
+    }
+}
+
+/// A timestamp, if the arbitrary seconds and offset are in range
+fn timestamp(secs: i64, offset: i32) -> Option<chrono::DateTime<FixedOffset>> {
+    FixedOffset::east_opt(offset)?
+        .timestamp_opt(secs, 0)
+        .single()
+}
+
+fuzz_target!(|input: Input| {
+    let mut serializer =
+        ThinEdgeJsonSerializer::new().with_duplicate_detection(input.detect_duplicates);
+
+    for call in input.calls {
+        if call.apply(&mut serializer).is_err() {
+            return;
+        }
+    }
+
+    if let Ok(output) = serializer.into_string() {
+        if let Err(err) = serde_json::from_str::<serde_json::Value>(&output) {
+            panic!("Invalid JSON: {} in {:?}", err, output);
+        }
+    }
+});
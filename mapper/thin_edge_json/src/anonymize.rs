@@ -0,0 +1,258 @@
+use crate::context::MeasurementContext;
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use hmac::{Hmac, Mac, NewMac};
+use regex::Regex;
+use sha2::Sha256;
+use std::borrow::Cow;
+
+/// The prefix of the pseudonyms, making them valid identifiers even when starting with a digit
+const PSEUDONYM_PREFIX: &str = "anon_";
+
+/// The number of bytes of the HMAC kept in a pseudonym, i.e. 64 bits
+const PSEUDONYM_BYTES: usize = 8;
+
+/// A visitor that pseudonymizes the names of the measurements and groups before forwarding them.
+///
+/// A name matching any of the patterns is replaced by a pseudonym derived from the HMAC-SHA256
+/// of the name keyed with a secret: the same name and key always give the same pseudonym,
+/// but the name cannot be recovered from the pseudonym without the key.
+/// The pseudonym is the prefix `anon_` followed by the first 8 bytes of the HMAC,
+/// written as 16 lower-case hexadecimal digits.
+///
+/// ```
+/// use regex::Regex;
+/// use thin_edge_json::anonymize::AnonymizingVisitor;
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let patterns = vec![Regex::new("^serial_")?];
+/// let mut visitor = AnonymizingVisitor::new(b"secret", patterns, ThinEdgeJsonSerializer::new());
+///
+/// visitor.measurement("serial_4711", 1.0)?;
+/// visitor.measurement("temperature", 25.5)?;
+///
+/// let pseudonym = visitor.pseudonym("serial_4711");
+/// assert_eq!(
+///     visitor.into_inner().into_string()?,
+///     format!(r#"{{"{}":1.0,"temperature":25.5}}"#, pseudonym)
+/// );
+/// # Ok(()) }
+/// ```
+pub struct AnonymizingVisitor<V> {
+    mac: Hmac<Sha256>,
+    patterns: Vec<Regex>,
+    inner: V,
+}
+
+impl<V> AnonymizingVisitor<V> {
+    pub fn new(key: &[u8], patterns: Vec<Regex>, inner: V) -> Self {
+        Self {
+            mac: Hmac::new_from_slice(key).expect("HMAC accepts keys of any size"),
+            patterns,
+            inner,
+        }
+    }
+
+    /// The pseudonym of a name, whether the name matches the patterns or not
+    pub fn pseudonym(&self, name: &str) -> String {
+        let mut mac = self.mac.clone();
+        mac.update(name.as_bytes());
+        let digest = mac.finalize().into_bytes();
+
+        let mut pseudonym = String::from(PSEUDONYM_PREFIX);
+        for byte in digest.iter().take(PSEUDONYM_BYTES) {
+            pseudonym.push_str(&format!("{:02x}", byte));
+        }
+        pseudonym
+    }
+
+    /// The name to be forwarded: the pseudonym of the name if matching any pattern
+    pub fn anonymize<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        if self.patterns.iter().any(|pattern| pattern.is_match(name)) {
+            Cow::Owned(self.pseudonym(name))
+        } else {
+            Cow::Borrowed(name)
+        }
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut V {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> V {
+        self.inner
+    }
+}
+
+impl<V> GroupedMeasurementVisitor for AnonymizingVisitor<V>
+where
+    V: GroupedMeasurementVisitor,
+{
+    type Error = V::Error;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.inner.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        let name = self.anonymize(name);
+        self.inner.measurement(&name, value)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        let group = self.anonymize(group);
+        self.inner.start_group(&group)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.inner.end_group()
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        let name = self.anonymize(name);
+        self.inner.measurement_with_unit(&name, value, unit)
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        let group = self.anonymize(group);
+        self.inner.start_group_with_timestamp(&group, timestamp)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        let name = self.anonymize(name);
+        self.inner.nullable_measurement(&name, value)
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        let name = self.anonymize(name);
+        self.inner.complex_measurement(&name, real, imaginary)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        let name = self.anonymize(name);
+        self.inner.annotated_measurement(&name, value, quality)
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.inner.set_context(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::ThinEdgeJsonSerializer;
+
+    fn anonymizer(key: &[u8], patterns: &[&str]) -> AnonymizingVisitor<ThinEdgeJsonSerializer> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern).unwrap())
+            .collect();
+        AnonymizingVisitor::new(key, patterns, ThinEdgeJsonSerializer::new())
+    }
+
+    #[test]
+    fn pseudonyms_are_reproducible() {
+        let first = anonymizer(b"secret", &[]);
+        let second = anonymizer(b"secret", &[]);
+
+        assert_eq!(
+            first.pseudonym("serial_4711"),
+            first.pseudonym("serial_4711")
+        );
+        assert_eq!(
+            first.pseudonym("serial_4711"),
+            second.pseudonym("serial_4711")
+        );
+        assert_ne!(
+            first.pseudonym("serial_4711"),
+            first.pseudonym("serial_4712")
+        );
+    }
+
+    #[test]
+    fn pseudonyms_depend_on_the_key() {
+        let first = anonymizer(b"secret", &[]);
+        let second = anonymizer(b"another secret", &[]);
+
+        assert_ne!(
+            first.pseudonym("serial_4711"),
+            second.pseudonym("serial_4711")
+        );
+    }
+
+    #[test]
+    fn pseudonyms_are_truncated_hmac_sha256() {
+        // HMAC-SHA256 test case 2 of RFC 4231:
+        // 5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843
+        let anonymizer = anonymizer(b"Jefe", &[]);
+
+        assert_eq!(
+            anonymizer.pseudonym("what do ya want for nothing?"),
+            "anon_5bdcc146bf60754e"
+        );
+    }
+
+    #[test]
+    fn pseudonyms_are_valid_identifiers() {
+        let anonymizer = anonymizer(b"secret", &[]);
+
+        for name in ["temperature", "serial_4711", "", "°C"].iter() {
+            let pseudonym = anonymizer.pseudonym(name);
+            assert_eq!(
+                pseudonym.len(),
+                PSEUDONYM_PREFIX.len() + 2 * PSEUDONYM_BYTES
+            );
+            assert!(pseudonym.starts_with(|c: char| c.is_ascii_alphabetic()));
+            assert!(pseudonym
+                .chars()
+                .all(|c| c == '_' || c.is_ascii_digit() || c.is_ascii_lowercase()));
+        }
+    }
+
+    #[test]
+    fn only_the_matching_names_are_anonymized() -> anyhow::Result<()> {
+        let mut visitor = anonymizer(b"secret", &["^serial_", "^site$"]);
+        let serial = visitor.pseudonym("serial_4711");
+        let site = visitor.pseudonym("site");
+
+        visitor.measurement("temperature", 25.5)?;
+        visitor.measurement_with_unit("serial_4711", 1.0, "id")?;
+        visitor.start_group("site")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.end_group()?;
+
+        let expected_output = format!(
+            r#"{{"temperature":25.5,"{}":{{"value":1.0,"unit":"id"}},"{}":{{"alti":2100.4}}}}"#,
+            serial, site
+        );
+        assert_eq!(visitor.into_inner().into_string()?, expected_output);
+        Ok(())
+    }
+}
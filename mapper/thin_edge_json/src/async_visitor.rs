@@ -0,0 +1,321 @@
+use crate::context::MeasurementContext;
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use async_trait::async_trait;
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+
+/// The asynchronous counterpart of `GroupedMeasurementVisitor`.
+///
+/// This trait is to be implemented by consumers of measurements that perform I/O,
+/// as writing to a database or publishing over MQTT,
+/// so a producer can forward measurements without blocking the calling thread.
+///
+/// A synchronous visitor can be used where an asynchronous one is expected
+/// using a `SyncToAsyncVisitor` adapter.
+#[async_trait]
+pub trait AsyncGroupedMeasurementVisitor: Send {
+    /// Error type specific to this way of collecting measurements
+    type Error: std::error::Error + std::fmt::Debug + Send;
+
+    /// Set the timestamp shared by all the measurements of this serie
+    async fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error>;
+
+    /// Add a new measurement, attached to the current group if any
+    async fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error>;
+
+    /// Start to gather measurements for a group
+    async fn start_group(&mut self, group: &str) -> Result<(), Self::Error>;
+
+    /// Definitely end to gather measurements for the current group
+    async fn end_group(&mut self) -> Result<(), Self::Error>;
+
+    /// Add a new measurement given with its unit, attached to the current group if any
+    ///
+    /// By default, the unit is ignored and the value is added as a plain measurement.
+    async fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        _unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.measurement(name, value).await
+    }
+
+    /// Start to gather measurements for a group having its own timestamp
+    ///
+    /// By default, the timestamp is ignored and a plain group is started.
+    async fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        _timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.start_group(group).await
+    }
+
+    /// Add a new measurement which value might be missing, attached to the current group if any
+    ///
+    /// By default, a missing value is ignored and a present one is added as a plain measurement.
+    async fn nullable_measurement(
+        &mut self,
+        name: &str,
+        value: Option<f64>,
+    ) -> Result<(), Self::Error> {
+        match value {
+            Some(value) => self.measurement(name, value).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Add a new complex measurement, given by its real and imaginary parts
+    ///
+    /// By default, the value is added as a group of two measurements `re` and `im`,
+    /// as given in thin-edge JSON; hence a complex measurement cannot be added within a group.
+    async fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.start_group(name).await?;
+        self.measurement("re", real).await?;
+        self.measurement("im", imaginary).await?;
+        self.end_group().await
+    }
+
+    /// Add a new measurement annotated with the quality of its value,
+    /// attached to the current group if any
+    ///
+    /// By default, the quality is ignored and the value is added as a plain measurement.
+    async fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        _quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.measurement(name, value).await
+    }
+
+    /// Set the trace context of the measurements of this serie
+    ///
+    /// By default, the context is ignored.
+    async fn set_context(&mut self, _context: MeasurementContext) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Adapt a synchronous `GroupedMeasurementVisitor` into an `AsyncGroupedMeasurementVisitor`.
+///
+/// ```
+/// use thin_edge_json::async_visitor::{AsyncGroupedMeasurementVisitor, SyncToAsyncVisitor};
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), anyhow::Error> {
+/// let mut visitor = SyncToAsyncVisitor::new(ThinEdgeJsonSerializer::new());
+/// visitor.measurement("temperature", 25.5).await?;
+///
+/// assert_eq!(visitor.into_inner().into_string()?, r#"{"temperature":25.5}"#);
+/// # Ok(()) }
+/// ```
+pub struct SyncToAsyncVisitor<V> {
+    inner: V,
+}
+
+impl<V> SyncToAsyncVisitor<V> {
+    pub fn new(inner: V) -> Self {
+        Self { inner }
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut V {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> V {
+        self.inner
+    }
+}
+
+#[async_trait]
+impl<V> AsyncGroupedMeasurementVisitor for SyncToAsyncVisitor<V>
+where
+    V: GroupedMeasurementVisitor + Send,
+    V::Error: Send,
+{
+    type Error = V::Error;
+
+    async fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.inner.timestamp(value)
+    }
+
+    async fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.inner.measurement(name, value)
+    }
+
+    async fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.inner.start_group(group)
+    }
+
+    async fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.inner.end_group()
+    }
+
+    async fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.inner.measurement_with_unit(name, value, unit)
+    }
+
+    async fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.inner.start_group_with_timestamp(group, timestamp)
+    }
+
+    async fn nullable_measurement(
+        &mut self,
+        name: &str,
+        value: Option<f64>,
+    ) -> Result<(), Self::Error> {
+        self.inner.nullable_measurement(name, value)
+    }
+
+    async fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.inner.complex_measurement(name, real, imaginary)
+    }
+
+    async fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.inner.annotated_measurement(name, value, quality)
+    }
+
+    async fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.inner.set_context(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::ThinEdgeJsonSerializer;
+    use chrono::TimeZone;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    #[derive(thiserror::Error, Debug)]
+    pub enum TestError {
+        #[error("Unexpected end of group")]
+        UnexpectedEndOfGroup,
+    }
+
+    /// A sink that takes time to store each measurement.
+    struct SlowSink {
+        latency: Duration,
+        group: Option<String>,
+        stored: Vec<String>,
+    }
+
+    #[async_trait]
+    impl AsyncGroupedMeasurementVisitor for SlowSink {
+        type Error = TestError;
+
+        async fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+            tokio::time::sleep(self.latency).await;
+            self.stored.push(format!("time = {}", value.to_rfc3339()));
+            Ok(())
+        }
+
+        async fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+            tokio::time::sleep(self.latency).await;
+            match self.group.as_ref() {
+                Some(group) => self.stored.push(format!("{}.{} = {}", group, name, value)),
+                None => self.stored.push(format!("{} = {}", name, value)),
+            }
+            Ok(())
+        }
+
+        async fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+            self.group = Some(group.to_string());
+            Ok(())
+        }
+
+        async fn end_group(&mut self) -> Result<(), Self::Error> {
+            self.group
+                .take()
+                .map(|_| ())
+                .ok_or(TestError::UnexpectedEndOfGroup)
+        }
+    }
+
+    #[tokio::test]
+    async fn drive_a_long_running_async_sink() -> anyhow::Result<()> {
+        let latency = Duration::from_millis(20);
+        let mut sink = SlowSink {
+            latency,
+            group: None,
+            stored: vec![],
+        };
+
+        let start = Instant::now();
+        sink.timestamp(FixedOffset::east(0).ymd(2021, 4, 30).and_hms(17, 3, 14))
+            .await?;
+        sink.measurement("temperature", 25.5).await?;
+        sink.start_group("location").await?;
+        sink.measurement_with_unit("alti", 2100.4, "m").await?;
+        sink.end_group().await?;
+
+        assert!(start.elapsed() >= 3 * latency);
+        assert_eq!(
+            sink.stored,
+            vec![
+                "time = 2021-04-30T17:03:14+00:00",
+                "temperature = 25.5",
+                "location.alti = 2100.4",
+            ]
+        );
+        assert!(sink.end_group().await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn drive_a_sync_visitor_asynchronously() -> anyhow::Result<()> {
+        let mut visitor = SyncToAsyncVisitor::new(ThinEdgeJsonSerializer::new());
+
+        visitor.measurement("temperature", 25.5).await?;
+        visitor.start_group("location").await?;
+        visitor.measurement_with_unit("alti", 2100.4, "m").await?;
+        visitor.end_group().await?;
+
+        assert_eq!(
+            visitor.into_inner().into_string()?,
+            r#"{"temperature":25.5,"location":{"alti":{"value":2100.4,"unit":"m"}}}"#
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sync_visitor_errors_are_forwarded() {
+        let mut visitor = SyncToAsyncVisitor::new(ThinEdgeJsonSerializer::new());
+
+        let result = visitor.end_group().await;
+
+        assert_eq!(result.unwrap_err().to_string(), "Unexpected end of group");
+    }
+}
@@ -0,0 +1,474 @@
+use crate::context::MeasurementContext;
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use crate::serialize::MeasurementStreamError;
+use crate::trace::VisitorCall;
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use std::convert::Infallible;
+
+/// A visitor that records all the calls it receives, so they can be replayed later.
+///
+/// A buffer can be replayed any number of times and to any visitor,
+/// e.g. to fan-out a series of measurements to several sinks,
+/// or to keep an audit log of what has been produced.
+///
+/// The calls are recorded as is, with no checks: any error,
+/// as an unbalanced group, is raised by the visitor the buffer is replayed to.
+///
+/// ```
+/// use thin_edge_json::buffer::MeasurementBuffer;
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let mut buffer = MeasurementBuffer::new();
+/// buffer.measurement("temperature", 25.5)?;
+///
+/// let mut first = ThinEdgeJsonSerializer::new();
+/// let mut second = ThinEdgeJsonSerializer::new();
+/// buffer.replay(&mut first)?;
+/// buffer.replay(&mut second)?;
+///
+/// assert_eq!(first.into_string()?, r#"{"temperature":25.5}"#);
+/// assert_eq!(second.into_string()?, r#"{"temperature":25.5}"#);
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MeasurementBuffer {
+    events: Vec<VisitorCall>,
+}
+
+impl MeasurementBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The calls recorded so far, in order
+    pub fn events(&self) -> &[VisitorCall] {
+        &self.events
+    }
+
+    pub fn into_events(self) -> Vec<VisitorCall> {
+        self.events
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear()
+    }
+
+    /// Whether at least one measurement has been recorded
+    pub fn has_measurements(&self) -> bool {
+        self.events.iter().any(VisitorCall::is_measurement)
+    }
+
+    /// Split the recorded calls into one buffer per group, plus one for the top-level measurements,
+    /// in the order the groups first appear.
+    ///
+    /// Each buffer starts with the timestamp and the context of this buffer, if any,
+    /// the measurements of a group given in several parts being gathered into a single group.
+    /// Groups with no measurements are dropped.
+    ///
+    /// In contrast to `replay()`, the calls are checked: unbalanced groups are rejected.
+    pub fn split_by_group(
+        &self,
+    ) -> Result<Vec<(Option<&str>, MeasurementBuffer)>, MeasurementStreamError> {
+        let header: Vec<VisitorCall> = self
+            .events
+            .iter()
+            .filter(|event| {
+                matches!(
+                    event,
+                    VisitorCall::Timestamp { .. } | VisitorCall::SetContext { .. }
+                )
+            })
+            .cloned()
+            .collect();
+
+        let mut buffers: Vec<(Option<&str>, MeasurementBuffer)> = Vec::new();
+        // The name of the current group along with the call starting this group
+        let mut current: Option<(&str, &VisitorCall)> = None;
+        for event in self.events.iter() {
+            match event {
+                VisitorCall::Timestamp { .. } if current.is_some() => {
+                    return Err(MeasurementStreamError::UnexpectedTimestamp)
+                }
+                VisitorCall::Timestamp { .. } | VisitorCall::SetContext { .. } => {}
+                VisitorCall::StartGroup { group }
+                | VisitorCall::StartGroupWithTimestamp { group, .. } => {
+                    if current.is_some() {
+                        return Err(MeasurementStreamError::UnexpectedStartOfGroup);
+                    }
+                    current = Some((group.as_str(), event));
+                }
+                VisitorCall::EndGroup => {
+                    if current.take().is_none() {
+                        return Err(MeasurementStreamError::UnexpectedEndOfGroup);
+                    }
+                }
+                measurement => {
+                    let group = current.map(|(group, _)| group);
+                    let index = match buffers.iter().position(|(name, _)| *name == group) {
+                        Some(index) => index,
+                        None => {
+                            let mut events = header.clone();
+                            events
+                                .extend(current.map(|(_, start_of_group)| start_of_group.clone()));
+                            buffers.push((group, MeasurementBuffer::from(events)));
+                            buffers.len() - 1
+                        }
+                    };
+                    buffers[index].1.events.push(measurement.clone());
+                }
+            }
+        }
+        if current.is_some() {
+            return Err(MeasurementStreamError::UnexpectedEndOfData);
+        }
+
+        for (group, buffer) in buffers.iter_mut() {
+            if group.is_some() {
+                buffer.events.push(VisitorCall::EndGroup);
+            }
+        }
+        Ok(buffers)
+    }
+
+    /// Call on the given visitor all the recorded calls, in order
+    pub fn replay<V>(&self, visitor: &mut V) -> Result<(), V::Error>
+    where
+        V: GroupedMeasurementVisitor,
+    {
+        for event in self.events.iter() {
+            event.apply(visitor)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Vec<VisitorCall>> for MeasurementBuffer {
+    fn from(events: Vec<VisitorCall>) -> Self {
+        MeasurementBuffer { events }
+    }
+}
+
+impl GroupedMeasurementVisitor for MeasurementBuffer {
+    type Error = Infallible;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.events.push(VisitorCall::Timestamp { value });
+        Ok(())
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.events.push(VisitorCall::Measurement {
+            name: name.into(),
+            value,
+        });
+        Ok(())
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.events.push(VisitorCall::StartGroup {
+            group: group.into(),
+        });
+        Ok(())
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.events.push(VisitorCall::EndGroup);
+        Ok(())
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.events.push(VisitorCall::MeasurementWithUnit {
+            name: name.into(),
+            value,
+            unit: unit.into(),
+        });
+        Ok(())
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        value: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.events.push(VisitorCall::StartGroupWithTimestamp {
+            group: group.into(),
+            value,
+        });
+        Ok(())
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        self.events.push(VisitorCall::NullableMeasurement {
+            name: name.into(),
+            value,
+        });
+        Ok(())
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.events.push(VisitorCall::ComplexMeasurement {
+            name: name.into(),
+            real,
+            imaginary,
+        });
+        Ok(())
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.events.push(VisitorCall::AnnotatedMeasurement {
+            name: name.into(),
+            value,
+            quality,
+        });
+        Ok(())
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.events.push(VisitorCall::SetContext { context });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::parse_str;
+    use crate::serialize::{ThinEdgeJsonSerializationError, ThinEdgeJsonSerializer};
+    use assert_matches::assert_matches;
+    use chrono::TimeZone;
+
+    fn produce<V: GroupedMeasurementVisitor>(visitor: &mut V) -> Result<(), V::Error> {
+        visitor.timestamp(
+            FixedOffset::east(2 * 3600)
+                .ymd(2021, 4, 30)
+                .and_hms(17, 3, 14),
+        )?;
+        visitor.measurement("temperature", 25.5)?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.measurement_with_unit("longi", 2200.4, "deg")?;
+        visitor.end_group()?;
+        visitor.measurement_with_unit("pressure", 98.0, "kPa")?;
+        Ok(())
+    }
+
+    #[test]
+    fn replay_produces_the_same_output_as_the_original_calls() -> anyhow::Result<()> {
+        let mut direct = ThinEdgeJsonSerializer::new();
+        produce(&mut direct)?;
+
+        let mut buffer = MeasurementBuffer::new();
+        produce(&mut buffer)?;
+        let mut replayed = ThinEdgeJsonSerializer::new();
+        buffer.replay(&mut replayed)?;
+
+        assert_eq!(replayed.into_string()?, direct.into_string()?);
+        Ok(())
+    }
+
+    #[test]
+    fn a_buffer_records_a_parsed_payload() -> anyhow::Result<()> {
+        let input = r#"{"temperature":25.5,"location":{"alti":2100.4}}"#;
+
+        let mut buffer = MeasurementBuffer::new();
+        parse_str(input, &mut buffer)?;
+
+        assert_eq!(buffer.len(), 4);
+        assert_eq!(
+            buffer.events()[1],
+            VisitorCall::StartGroup {
+                group: "location".into()
+            }
+        );
+
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        buffer.replay(&mut serializer)?;
+        assert_eq!(serializer.into_string()?, input);
+        Ok(())
+    }
+
+    #[test]
+    fn errors_are_raised_on_replay() -> anyhow::Result<()> {
+        let mut buffer = MeasurementBuffer::new();
+        buffer.end_group()?;
+
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        assert_matches!(
+            buffer.replay(&mut serializer),
+            Err(ThinEdgeJsonSerializationError::MeasurementCollectorError(
+                MeasurementStreamError::UnexpectedEndOfGroup
+            ))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn a_buffer_is_split_by_group() -> anyhow::Result<()> {
+        let input = r#"{"temperature":25.5,"location":{"alti":2100.4},"pressure":98.0}"#;
+        let mut buffer = MeasurementBuffer::new();
+        parse_str(input, &mut buffer)?;
+
+        let mut payloads = Vec::new();
+        for (group, split) in buffer.split_by_group()? {
+            let mut serializer = ThinEdgeJsonSerializer::new();
+            split.replay(&mut serializer)?;
+            payloads.push((group.map(String::from), serializer.into_string()?));
+        }
+
+        assert_eq!(
+            payloads,
+            vec![
+                (None, r#"{"temperature":25.5,"pressure":98.0}"#.to_string()),
+                (
+                    Some("location".to_string()),
+                    r#"{"location":{"alti":2100.4}}"#.to_string()
+                ),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn the_timestamp_and_the_context_are_shared_by_all_the_splits() -> anyhow::Result<()> {
+        let timestamp = FixedOffset::east(0).ymd(2021, 4, 30).and_hms(17, 3, 14);
+        let context = MeasurementContext::new([0x4b; 16], [0xf0; 8]);
+        let mut buffer = MeasurementBuffer::new();
+        buffer.measurement("temperature", 25.5)?;
+        buffer.start_group("location")?;
+        buffer.measurement("alti", 2100.4)?;
+        buffer.end_group()?;
+        buffer.set_context(context.clone())?;
+        buffer.timestamp(timestamp)?;
+
+        let splits = buffer.split_by_group()?;
+
+        assert_eq!(splits.len(), 2);
+        for (_, split) in splits.iter() {
+            assert_eq!(
+                split.events()[..2].to_vec(),
+                vec![
+                    VisitorCall::SetContext {
+                        context: context.clone()
+                    },
+                    VisitorCall::Timestamp { value: timestamp },
+                ]
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn the_parts_of_a_group_are_gathered_with_all_kinds_of_measurements() -> anyhow::Result<()> {
+        let timestamp = FixedOffset::east(0).ymd(2021, 4, 30).and_hms(17, 3, 14);
+        let mut buffer = MeasurementBuffer::new();
+        buffer.start_group_with_timestamp("engine", timestamp)?;
+        buffer.nullable_measurement("speed", None)?;
+        buffer.end_group()?;
+        buffer.annotated_measurement("temperature", 25.5, MeasurementQuality::Uncertain)?;
+        buffer.start_group("engine")?;
+        buffer.complex_measurement("current", 1.0, -2.0)?;
+        buffer.end_group()?;
+        buffer.start_group("empty")?;
+        buffer.end_group()?;
+
+        let splits = buffer.split_by_group()?;
+
+        assert_eq!(
+            splits,
+            vec![
+                (
+                    Some("engine"),
+                    MeasurementBuffer::from(vec![
+                        VisitorCall::StartGroupWithTimestamp {
+                            group: "engine".into(),
+                            value: timestamp
+                        },
+                        VisitorCall::NullableMeasurement {
+                            name: "speed".into(),
+                            value: None
+                        },
+                        VisitorCall::ComplexMeasurement {
+                            name: "current".into(),
+                            real: 1.0,
+                            imaginary: -2.0
+                        },
+                        VisitorCall::EndGroup,
+                    ])
+                ),
+                (
+                    None,
+                    MeasurementBuffer::from(vec![VisitorCall::AnnotatedMeasurement {
+                        name: "temperature".into(),
+                        value: 25.5,
+                        quality: MeasurementQuality::Uncertain
+                    }])
+                ),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unbalanced_groups_are_rejected_on_split() -> anyhow::Result<()> {
+        let mut buffer = MeasurementBuffer::new();
+        buffer.start_group("location")?;
+        buffer.measurement("alti", 2100.4)?;
+        assert_matches!(
+            buffer.split_by_group(),
+            Err(MeasurementStreamError::UnexpectedEndOfData)
+        );
+
+        buffer.timestamp(FixedOffset::east(0).ymd(2021, 4, 30).and_hms(17, 3, 14))?;
+        assert_matches!(
+            buffer.split_by_group(),
+            Err(MeasurementStreamError::UnexpectedTimestamp)
+        );
+
+        let mut buffer = MeasurementBuffer::new();
+        buffer.end_group()?;
+        assert_matches!(
+            buffer.split_by_group(),
+            Err(MeasurementStreamError::UnexpectedEndOfGroup)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn only_measurements_count_as_measurements() -> anyhow::Result<()> {
+        let mut buffer = MeasurementBuffer::new();
+        buffer.timestamp(FixedOffset::east(0).ymd(2021, 4, 30).and_hms(17, 3, 14))?;
+        buffer.start_group("location")?;
+        buffer.end_group()?;
+        assert!(!buffer.has_measurements());
+
+        buffer.nullable_measurement("temperature", None)?;
+        assert!(buffer.has_measurements());
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{Number, Value};
+
+const TIME_FIELD: &str = "time";
+
+/// The times are converted in the payload (depth 0) and its groups (depth 1)
+const MAX_TIME_DEPTH: usize = 1;
+
+/// Above this magnitude, an integral float is no longer written as an integer
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+#[derive(thiserror::Error, Debug)]
+pub enum CanonicalizationError {
+    #[error("Invalid JSON: {0}")]
+    InvalidJson(#[from] serde_json::Error),
+
+    #[error("A thin-edge JSON payload must be an object")]
+    NotAnObject,
+
+    #[error("Invalid time: {value} is not an RFC 3339 time")]
+    InvalidTimestamp { value: Value },
+}
+
+/// Rewrite thin-edge JSON payloads into a canonical form,
+/// so semantically identical payloads are byte-for-byte identical, e.g. to be hashed.
+///
+/// The canonical form is compact JSON where:
+/// * the keys of all the objects are sorted,
+/// * the numbers are written with no trailing zeros, integral numbers as integers,
+/// * the times, of the payload and of its groups, are given in UTC.
+///
+/// All the numbers are taken as 64-bit floats, as the measurements of thin-edge JSON.
+///
+/// ```
+/// use thin_edge_json::canonical::ThinEdgeJsonCanonicalizer;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let payload = br#"{
+///     "time": "2021-04-30T17:03:14+02:00",
+///     "temperature": 25.50
+/// }"#;
+///
+/// assert_eq!(
+///     ThinEdgeJsonCanonicalizer::canonicalize(payload)?,
+///     br#"{"temperature":25.5,"time":"2021-04-30T15:03:14Z"}"#.to_vec()
+/// );
+/// # Ok(()) }
+/// ```
+pub struct ThinEdgeJsonCanonicalizer;
+
+impl ThinEdgeJsonCanonicalizer {
+    pub fn canonicalize(input: &[u8]) -> Result<Vec<u8>, CanonicalizationError> {
+        let payload: Value = serde_json::from_slice(input)?;
+        if !payload.is_object() {
+            return Err(CanonicalizationError::NotAnObject);
+        }
+
+        let mut output = String::new();
+        write_value(&mut output, &payload, 0)?;
+        Ok(output.into_bytes())
+    }
+}
+
+fn write_value(
+    output: &mut String,
+    value: &Value,
+    depth: usize,
+) -> Result<(), CanonicalizationError> {
+    match value {
+        Value::Object(object) => {
+            let mut fields: Vec<_> = object.iter().collect();
+            fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+            output.push('{');
+            for (i, (key, value)) in fields.into_iter().enumerate() {
+                if i > 0 {
+                    output.push(',');
+                }
+                write_string(output, key);
+                output.push(':');
+                if depth <= MAX_TIME_DEPTH && key == TIME_FIELD {
+                    write_string(output, &utc_time(value)?);
+                } else {
+                    write_value(output, value, depth + 1)?;
+                }
+            }
+            output.push('}');
+        }
+        Value::Array(values) => {
+            output.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    output.push(',');
+                }
+                write_value(output, value, depth + 1)?;
+            }
+            output.push(']');
+        }
+        Value::String(string) => write_string(output, string),
+        Value::Number(number) => write_number(output, number),
+        Value::Bool(flag) => output.push_str(if *flag { "true" } else { "false" }),
+        Value::Null => output.push_str("null"),
+    }
+    Ok(())
+}
+
+fn write_string(output: &mut String, string: &str) {
+    output.push_str(&Value::String(string.to_string()).to_string());
+}
+
+fn write_number(output: &mut String, number: &Number) {
+    // A JSON number always fits a float, though possibly with a loss of precision
+    let value = number.as_f64().unwrap_or_default();
+    if value.fract() == 0.0 && value.abs() < MAX_SAFE_INTEGER {
+        // Also turns -0 into 0
+        output.push_str(&(value as i64).to_string());
+    } else {
+        // The shortest representation that reads back as the same float,
+        // as written by the JSON serializer rather than by `Display`
+        match Number::from_f64(value) {
+            Some(number) => output.push_str(&Value::Number(number).to_string()),
+            None => output.push_str("null"),
+        }
+    }
+}
+
+fn utc_time(value: &Value) -> Result<String, CanonicalizationError> {
+    let time = value
+        .as_str()
+        .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+        .ok_or_else(|| CanonicalizationError::InvalidTimestamp {
+            value: value.clone(),
+        })?;
+    Ok(time
+        .with_timezone(&Utc)
+        .to_rfc3339_opts(SecondsFormat::AutoSi, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    fn canonical(input: &str) -> String {
+        String::from_utf8(ThinEdgeJsonCanonicalizer::canonicalize(input.as_bytes()).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn identical_payloads_have_the_same_canonical_form() {
+        let compact = r#"{"time":"2021-04-30T15:03:14Z","temperature":25.5,"location":{"longi":2200.4,"alti":2100}}"#;
+        let formatted = r#"{
+            "location": {
+                "alti": 2.1e3,
+                "longi": 2200.40
+            },
+            "temperature": 25.50,
+            "time": "2021-04-30T17:03:14.000+02:00"
+        }"#;
+
+        assert_eq!(canonical(compact), canonical(formatted));
+        assert_eq!(
+            canonical(compact),
+            r#"{"location":{"alti":2100,"longi":2200.4},"temperature":25.5,"time":"2021-04-30T15:03:14Z"}"#
+        );
+    }
+
+    #[test]
+    fn group_times_are_given_in_utc() {
+        assert_eq!(
+            canonical(r#"{"engine":{"time":"2021-04-30T17:03:14.123-01:00","rpm":1500.0}}"#),
+            r#"{"engine":{"rpm":1500,"time":"2021-04-30T18:03:14.123Z"}}"#
+        );
+    }
+
+    #[test]
+    fn numbers_are_written_in_their_shortest_form() {
+        assert_eq!(
+            canonical(r#"{"a":-0.0,"b":1e-7,"c":1e300,"d":0.10,"e":-42.000}"#),
+            r#"{"a":0,"b":1e-7,"c":1e300,"d":0.1,"e":-42}"#
+        );
+    }
+
+    #[test]
+    fn strings_and_metadata_are_kept() {
+        assert_eq!(
+            canonical(r#"{ "_schema" : "te/1.0", "_interpolated": true, "source": "col\"lectd" }"#),
+            r#"{"_interpolated":true,"_schema":"te/1.0","source":"col\"lectd"}"#
+        );
+    }
+
+    #[test]
+    fn invalid_payloads_are_rejected() {
+        assert_matches!(
+            ThinEdgeJsonCanonicalizer::canonicalize(br#"{"temperature":"#),
+            Err(CanonicalizationError::InvalidJson(_))
+        );
+        assert_matches!(
+            ThinEdgeJsonCanonicalizer::canonicalize(b"[25.5]"),
+            Err(CanonicalizationError::NotAnObject)
+        );
+        assert_matches!(
+            ThinEdgeJsonCanonicalizer::canonicalize(br#"{"time":"now"}"#),
+            Err(CanonicalizationError::InvalidTimestamp { .. })
+        );
+    }
+}
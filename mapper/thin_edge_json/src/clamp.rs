@@ -0,0 +1,289 @@
+use crate::context::MeasurementContext;
+use crate::filter::PendingGroup;
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use std::collections::HashMap;
+
+/// What to do with a value out of the range of its measurement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClampBehavior {
+    /// Replace the value by the nearest boundary of the range
+    Clamp,
+
+    /// Replace the value by the last value in range of the same measurement,
+    /// dropping the value if there is none
+    LastValid,
+
+    /// Drop the value
+    Drop,
+}
+
+/// A visitor that checks the measurements against the physical range of their sensor.
+///
+/// The ranges are given as `(min, max)` per measurement, identified by its name
+/// or by `<group>.<name>` for grouped measurements, the boundaries being included.
+/// A value out of range is clamped, replaced or dropped according to the `ClampBehavior`.
+/// A NaN value is out of any range, and is dropped when clamping, having no nearest boundary.
+///
+/// The measurements with no range, the missing values and the complex measurements
+/// are forwarded unchanged.
+/// A group is only forwarded if at least one of its measurements is.
+///
+/// ```
+/// use std::collections::HashMap;
+/// use thin_edge_json::clamp::{ClampBehavior, ClampingVisitor};
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let mut ranges = HashMap::new();
+/// ranges.insert("humidity".to_string(), (0.0, 100.0));
+/// let mut visitor =
+///     ClampingVisitor::new(ranges, ClampBehavior::Clamp, ThinEdgeJsonSerializer::new());
+///
+/// visitor.measurement("humidity", 100.3)?;
+///
+/// assert_eq!(
+///     visitor.into_inner().into_string()?,
+///     r#"{"humidity":100.0}"#
+/// );
+/// # Ok(()) }
+/// ```
+pub struct ClampingVisitor<V> {
+    ranges: HashMap<String, (f64, f64)>,
+    behavior: ClampBehavior,
+    last_valid: HashMap<String, f64>,
+    group: PendingGroup,
+    inner: V,
+}
+
+impl<V> ClampingVisitor<V> {
+    pub fn new(ranges: HashMap<String, (f64, f64)>, behavior: ClampBehavior, inner: V) -> Self {
+        Self {
+            ranges,
+            behavior,
+            last_valid: HashMap::new(),
+            group: PendingGroup::default(),
+            inner,
+        }
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut V {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> V {
+        self.inner
+    }
+
+    /// The value to be forwarded for the measurement, if any
+    fn check(&mut self, name: &str, value: f64) -> Option<f64> {
+        let key = self.group.key(name);
+        let (min, max) = match self.ranges.get(&key) {
+            Some(range) => *range,
+            None => return Some(value),
+        };
+
+        if min <= value && value <= max {
+            self.last_valid.insert(key, value);
+            return Some(value);
+        }
+        match self.behavior {
+            ClampBehavior::Clamp if value < min => Some(min),
+            ClampBehavior::Clamp if value > max => Some(max),
+            ClampBehavior::Clamp => None,
+            ClampBehavior::LastValid => self.last_valid.get(&key).cloned(),
+            ClampBehavior::Drop => None,
+        }
+    }
+}
+
+impl<V> GroupedMeasurementVisitor for ClampingVisitor<V>
+where
+    V: GroupedMeasurementVisitor,
+{
+    type Error = V::Error;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.inner.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        if let Some(value) = self.check(name, value) {
+            self.group.forward_start(&mut self.inner)?;
+            self.inner.measurement(name, value)?;
+        }
+        Ok(())
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.group.start(group, &mut self.inner)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.group.end(&mut self.inner)
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        if let Some(value) = self.check(name, value) {
+            self.group.forward_start(&mut self.inner)?;
+            self.inner.measurement_with_unit(name, value, unit)?;
+        }
+        Ok(())
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.group
+            .start_with_timestamp(group, timestamp, &mut self.inner)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        let value = match value {
+            Some(value) => match self.check(name, value) {
+                Some(value) => Some(value),
+                None => return Ok(()),
+            },
+            None => None,
+        };
+        self.group.forward_start(&mut self.inner)?;
+        self.inner.nullable_measurement(name, value)
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.group.forward_start(&mut self.inner)?;
+        self.inner.complex_measurement(name, real, imaginary)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        if let Some(value) = self.check(name, value) {
+            self.group.forward_start(&mut self.inner)?;
+            self.inner.annotated_measurement(name, value, quality)?;
+        }
+        Ok(())
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.inner.set_context(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::ThinEdgeJsonSerializer;
+
+    fn clamping_visitor(behavior: ClampBehavior) -> ClampingVisitor<ThinEdgeJsonSerializer> {
+        let mut ranges = HashMap::new();
+        ranges.insert("humidity".to_string(), (0.0, 100.0));
+        ranges.insert("engine.temperature".to_string(), (-40.0, 150.0));
+        ClampingVisitor::new(ranges, behavior, ThinEdgeJsonSerializer::new())
+    }
+
+    fn next_series(
+        visitor: &mut ClampingVisitor<ThinEdgeJsonSerializer>,
+    ) -> anyhow::Result<String> {
+        let mut serializer = std::mem::replace(visitor.inner_mut(), ThinEdgeJsonSerializer::new());
+        Ok(serializer.into_string()?)
+    }
+
+    /// Send a valid humidity, then out-of-range values, returning the series forwarded for each
+    fn visit(behavior: ClampBehavior) -> anyhow::Result<Vec<String>> {
+        let mut visitor = clamping_visitor(behavior);
+        let mut series = Vec::new();
+
+        visitor.measurement("humidity", 100.0)?;
+        series.push(next_series(&mut visitor)?);
+
+        visitor.measurement("humidity", -3.5)?;
+        series.push(next_series(&mut visitor)?);
+
+        visitor.nullable_measurement("humidity", Some(f64::NAN))?;
+        series.push(next_series(&mut visitor)?);
+
+        visitor.start_group("engine")?;
+        visitor.measurement_with_unit("temperature", 180.0, "°C")?;
+        visitor.end_group()?;
+        series.push(next_series(&mut visitor)?);
+
+        Ok(series)
+    }
+
+    #[test]
+    fn out_of_range_values_are_clamped() -> anyhow::Result<()> {
+        assert_eq!(
+            visit(ClampBehavior::Clamp)?,
+            vec![
+                r#"{"humidity":100.0}"#,
+                r#"{"humidity":0.0}"#,
+                "{}",
+                r#"{"engine":{"temperature":{"value":150.0,"unit":"°C"}}}"#,
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn out_of_range_values_are_replaced_by_the_last_valid_value() -> anyhow::Result<()> {
+        // There is no valid engine temperature to replace the invalid one
+        assert_eq!(
+            visit(ClampBehavior::LastValid)?,
+            vec![
+                r#"{"humidity":100.0}"#,
+                r#"{"humidity":100.0}"#,
+                r#"{"humidity":100.0}"#,
+                "{}",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn out_of_range_values_are_dropped() -> anyhow::Result<()> {
+        assert_eq!(
+            visit(ClampBehavior::Drop)?,
+            vec![r#"{"humidity":100.0}"#, "{}", "{}", "{}"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn measurements_with_no_range_are_forwarded_unchanged() -> anyhow::Result<()> {
+        let mut visitor = clamping_visitor(ClampBehavior::Drop);
+
+        visitor.measurement("temperature", 1000.0)?;
+        visitor.start_group("location")?;
+        visitor.measurement("humidity", 120.0)?;
+        visitor.end_group()?;
+        visitor.nullable_measurement("humidity", None)?;
+
+        assert_eq!(
+            visitor.into_inner().into_string()?,
+            r#"{"temperature":1000.0,"location":{"humidity":120.0},"humidity":null}"#
+        );
+        Ok(())
+    }
+}
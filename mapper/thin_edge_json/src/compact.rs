@@ -0,0 +1,214 @@
+use crate::context::MeasurementContext;
+use crate::json::{parse_str, ThinEdgeJsonError, ThinEdgeJsonParserError};
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use crate::serialize::{ThinEdgeJsonSerializationError, ThinEdgeJsonSerializer};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+
+#[derive(thiserror::Error, Debug)]
+pub enum CompactError {
+    #[error(transparent)]
+    InvalidPayload(#[from] ThinEdgeJsonError),
+
+    #[error(transparent)]
+    SerializationError(#[from] ThinEdgeJsonSerializationError),
+}
+
+impl From<ThinEdgeJsonParserError<ThinEdgeJsonSerializationError>> for CompactError {
+    fn from(error: ThinEdgeJsonParserError<ThinEdgeJsonSerializationError>) -> Self {
+        match error {
+            ThinEdgeJsonParserError::ThinEdgeJsonError(err) => CompactError::InvalidPayload(err),
+            ThinEdgeJsonParserError::VisitorError(err) => CompactError::SerializationError(err),
+        }
+    }
+}
+
+/// Shrink thin-edge JSON payloads by removing the floating-point noise of the measurements,
+/// as in `25.500000000000004`.
+///
+/// ```
+/// use thin_edge_json::compact::ThinEdgeJsonCompactor;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let payload = br#"{"temperature": 25.500000000000004, "location": {"alti": 2100.4444}}"#;
+/// let compacted = ThinEdgeJsonCompactor::compact(payload, 2)?;
+///
+/// assert_eq!(compacted, br#"{"temperature":25.5,"location":{"alti":2100.44}}"#);
+/// # Ok(()) }
+/// ```
+pub struct ThinEdgeJsonCompactor;
+
+impl ThinEdgeJsonCompactor {
+    /// Round all the measurements of a payload to the given number of decimal places.
+    ///
+    /// The payload is parsed and re-serialized, hence any whitespace is removed too.
+    /// The timestamp is re-serialized in RFC 3339, keeping its offset and precision.
+    pub fn compact(payload: &[u8], decimal_places: u8) -> Result<Vec<u8>, CompactError> {
+        let input = std::str::from_utf8(payload)
+            .map_err(|err| ThinEdgeJsonError::new_invalid_utf8(payload, err))?;
+
+        let mut visitor = RoundingVisitor {
+            factor: 10f64.powi(decimal_places.into()),
+            inner: ThinEdgeJsonSerializer::new(),
+        };
+        parse_str(input, &mut visitor)?;
+        Ok(visitor.inner.bytes()?)
+    }
+}
+
+/// A visitor rounding the measurements before forwarding them
+struct RoundingVisitor<V> {
+    factor: f64,
+    inner: V,
+}
+
+impl<V> RoundingVisitor<V> {
+    fn round(&self, value: f64) -> f64 {
+        let scaled = value * self.factor;
+        if scaled.is_finite() {
+            scaled.round() / self.factor
+        } else {
+            // Too large to be scaled, hence too large to have decimal places to round
+            value
+        }
+    }
+}
+
+impl<V> GroupedMeasurementVisitor for RoundingVisitor<V>
+where
+    V: GroupedMeasurementVisitor,
+{
+    type Error = V::Error;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.inner.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        let value = self.round(value);
+        self.inner.measurement(name, value)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.inner.start_group(group)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.inner.end_group()
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        let value = self.round(value);
+        self.inner.measurement_with_unit(name, value, unit)
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.inner.start_group_with_timestamp(group, timestamp)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        let value = value.map(|value| self.round(value));
+        self.inner.nullable_measurement(name, value)
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        let real = self.round(real);
+        let imaginary = self.round(imaginary);
+        self.inner.complex_measurement(name, real, imaginary)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        let value = self.round(value);
+        self.inner.annotated_measurement(name, value, quality)
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.inner.set_context(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    fn compact(payload: &str, decimal_places: u8) -> Result<String, CompactError> {
+        let compacted = ThinEdgeJsonCompactor::compact(payload.as_bytes(), decimal_places)?;
+        Ok(String::from_utf8(compacted).expect("UTF8 output"))
+    }
+
+    #[test]
+    fn floating_point_noise_is_removed() -> anyhow::Result<()> {
+        assert_eq!(
+            compact(r#"{"temperature":25.500000000000004}"#, 2)?,
+            r#"{"temperature":25.5}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn measurements_are_rounded_to_the_given_decimal_places() -> anyhow::Result<()> {
+        let input = r#"{"temperature":25.456,"location":{"alti":2100.4449,"longi":-2200.556}}"#;
+
+        assert_eq!(
+            compact(input, 2)?,
+            r#"{"temperature":25.46,"location":{"alti":2100.44,"longi":-2200.56}}"#
+        );
+        assert_eq!(
+            compact(input, 0)?,
+            r#"{"temperature":25.0,"location":{"alti":2100.0,"longi":-2201.0}}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn the_timestamp_is_not_modified() -> anyhow::Result<()> {
+        let input =
+            r#"{"time":"2021-04-30T17:03:14.123456+02:00","temperature":25.500000000000004}"#;
+
+        assert_eq!(
+            compact(input, 2)?,
+            r#"{"time":"2021-04-30T17:03:14.123456+02:00","temperature":25.5}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn large_values_are_kept_unchanged() -> anyhow::Result<()> {
+        assert_eq!(compact(r#"{"big":1e300}"#, 10)?, r#"{"big":1e300}"#);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_payloads_are_rejected() {
+        assert_matches!(
+            compact(r#"{"temperature":"hot"}"#, 2),
+            Err(CompactError::InvalidPayload(_))
+        );
+        assert_matches!(
+            ThinEdgeJsonCompactor::compact(&[0xff, 0xfe], 2),
+            Err(CompactError::InvalidPayload(
+                ThinEdgeJsonError::InvalidUtf8 { .. }
+            ))
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use crate::dedup::DeduplicatingVisitor;
+use crate::filter::{FilteringVisitor, MeasurementPredicate};
+use crate::measurement::GroupedMeasurementVisitor;
+use crate::rate_limit::RateLimitingVisitor;
+use crate::tee::TeeVisitor;
+use crate::units::{UnitConversions, UnitConvertingVisitor};
+use std::time::Duration;
+
+/// Methods wrapping a visitor into another visitor, to build visitor chains with no boxing.
+///
+/// Each method returns a visitor that processes the calls before forwarding them to `self`,
+/// so the visitor built last is the first to receive the calls.
+/// The type of the chain is fully known at compile time,
+/// as `RateLimitingVisitor<FilteringVisitor<ThinEdgeJsonSerializer, _>>`,
+/// letting the compiler inline the calls along the chain.
+/// See `PipelineConfig` for chains defined at runtime.
+///
+/// ```
+/// use std::time::Duration;
+/// use thin_edge_json::compose::VisitorExt;
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// // The measurements are first rate-limited, then filtered, then deduplicated
+/// let mut visitor = ThinEdgeJsonSerializer::new()
+///     .deduplicate(0.1)
+///     .filter(|key: &str| key.starts_with("temp"))
+///     .rate_limit(Duration::from_secs(60));
+///
+/// visitor.measurement("temperature", 25.5)?;
+/// visitor.measurement("pressure", 98.0)?;
+///
+/// let mut serializer = visitor.into_inner().into_inner().into_inner();
+/// assert_eq!(serializer.into_string()?, r#"{"temperature":25.5}"#);
+/// # Ok(()) }
+/// ```
+pub trait VisitorExt: GroupedMeasurementVisitor + Sized {
+    /// Only forward the measurements accepted by the predicate, see `FilteringVisitor`.
+    fn filter<P>(self, predicate: P) -> FilteringVisitor<Self, P>
+    where
+        P: MeasurementPredicate,
+    {
+        FilteringVisitor::with_predicate(predicate, self)
+    }
+
+    /// Convert the units of the measurements, see `UnitConvertingVisitor`.
+    fn convert_units(self, conversions: UnitConversions) -> UnitConvertingVisitor<Self> {
+        UnitConvertingVisitor::new(conversions, self)
+    }
+
+    /// Drop the measurements that haven't changed by more than `epsilon`, see `DeduplicatingVisitor`.
+    fn deduplicate(self, epsilon: f64) -> DeduplicatingVisitor<Self> {
+        DeduplicatingVisitor::new(epsilon, self)
+    }
+
+    /// Forward each measurement at most once per interval, see `RateLimitingVisitor`.
+    fn rate_limit(self, interval: Duration) -> RateLimitingVisitor<Self> {
+        RateLimitingVisitor::new(interval, self)
+    }
+
+    /// Also forward all the calls to another visitor, see `TeeVisitor`.
+    fn tee<W>(self, other: W) -> TeeVisitor<Self, W>
+    where
+        W: GroupedMeasurementVisitor,
+    {
+        TeeVisitor::new(self, other)
+    }
+}
+
+impl<V> VisitorExt for V where V: GroupedMeasurementVisitor {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::MeasurementBuffer;
+    use crate::serialize::ThinEdgeJsonSerializer;
+    use crate::units::UnitConversion;
+
+    fn is_temperature(key: &str) -> bool {
+        key.starts_with("temp")
+    }
+
+    #[test]
+    fn a_chain_is_a_plain_nesting_of_visitors() -> anyhow::Result<()> {
+        // The type of the chain is checked at compile time, with no boxed visitor
+        let mut visitor: RateLimitingVisitor<
+            FilteringVisitor<
+                TeeVisitor<DeduplicatingVisitor<ThinEdgeJsonSerializer>, MeasurementBuffer>,
+                fn(&str) -> bool,
+            >,
+        > = ThinEdgeJsonSerializer::new()
+            .deduplicate(0.0)
+            .tee(MeasurementBuffer::new())
+            .filter(is_temperature as fn(&str) -> bool)
+            .rate_limit(Duration::from_secs(60));
+
+        visitor.measurement("temperature", 25.5)?;
+        visitor.measurement("pressure", 98.0)?;
+
+        let (dedup, buffer) = visitor.into_inner().into_inner().into_inner();
+        assert_eq!(dedup.into_inner().into_string()?, r#"{"temperature":25.5}"#);
+        assert_eq!(buffer.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn units_are_converted_along_the_chain() -> anyhow::Result<()> {
+        let fahrenheit = UnitConversion {
+            factor: 1.8,
+            offset: 32.0,
+            unit: Some("°F".into()),
+        };
+        let conversions = UnitConversions::new().with_conversion("temperature", fahrenheit);
+        let mut visitor = ThinEdgeJsonSerializer::new()
+            .convert_units(conversions)
+            .deduplicate(0.0);
+
+        visitor.measurement("temperature", 25.0)?;
+        visitor.measurement("temperature", 25.0)?;
+
+        assert_eq!(
+            visitor.into_inner().into_inner().into_string()?,
+            r#"{"temperature":{"value":77.0,"unit":"°F"}}"#
+        );
+        Ok(())
+    }
+}
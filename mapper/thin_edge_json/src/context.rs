@@ -0,0 +1,283 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The metadata key of the trace id, when written along the measurements
+pub const TRACE_ID_KEY: &str = "_traceId";
+
+/// The metadata key of the span id, when written along the measurements
+pub const SPAN_ID_KEY: &str = "_spanId";
+
+/// The trace context of a serie of measurements, propagated from device to device
+/// as the trace context and the baggage of OpenTelemetry.
+///
+/// ```
+/// use thin_edge_json::context::MeasurementContext;
+///
+/// let context = MeasurementContext::new([0x4b; 16], [0xf0; 8]).with_baggage("site", "plant-7");
+///
+/// assert_eq!(context.trace_id_hex(), "4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b");
+/// assert_eq!(context.span_id_hex(), "f0f0f0f0f0f0f0f0");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MeasurementContext {
+    #[serde(with = "hex_id")]
+    pub trace_id: [u8; 16],
+
+    #[serde(with = "hex_id")]
+    pub span_id: [u8; 8],
+
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub baggage: HashMap<String, String>,
+}
+
+impl MeasurementContext {
+    pub fn new(trace_id: [u8; 16], span_id: [u8; 8]) -> Self {
+        Self {
+            trace_id,
+            span_id,
+            baggage: HashMap::new(),
+        }
+    }
+
+    /// Add a key-value pair to the baggage, replacing any value previously given for that key
+    pub fn with_baggage(mut self, key: &str, value: &str) -> Self {
+        self.baggage.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// The context with the given ids in hexadecimal, as in a W3C `traceparent` header,
+    /// or `None` if these are not 32 and 16 hexadecimal digits.
+    pub fn from_hex(trace_id: &str, span_id: &str) -> Option<Self> {
+        let mut context = MeasurementContext::default();
+        from_hex(trace_id, &mut context.trace_id)?;
+        from_hex(span_id, &mut context.span_id)?;
+        Some(context)
+    }
+
+    /// The trace id as 32 lower-case hexadecimal digits, as in a W3C `traceparent` header
+    pub fn trace_id_hex(&self) -> String {
+        to_hex(&self.trace_id)
+    }
+
+    /// The span id as 16 lower-case hexadecimal digits, as in a W3C `traceparent` header
+    pub fn span_id_hex(&self) -> String {
+        to_hex(&self.span_id)
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str, bytes: &mut [u8]) -> Option<()> {
+    if hex.len() != 2 * bytes.len() || !hex.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+        return None;
+    }
+    for (byte, digits) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        let digits = std::str::from_utf8(digits).ok()?;
+        *byte = u8::from_str_radix(digits, 16).ok()?;
+    }
+    Some(())
+}
+
+/// The ids are (de)serialized in hexadecimal
+mod hex_id {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S, const N: usize>(id: &[u8; N], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&super::to_hex(id))
+    }
+
+    pub fn deserialize<'de, D, const N: usize>(deserializer: D) -> Result<[u8; N], D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let hex = String::deserialize(deserializer)?;
+        let mut id = [0; N];
+        super::from_hex(&hex, &mut id)
+            .ok_or_else(|| D::Error::custom(format!("Invalid hexadecimal id: {:?}", hex)))?;
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::anonymize::AnonymizingVisitor;
+    use crate::clamp::{ClampBehavior, ClampingVisitor};
+    use crate::dedup::DeduplicatingVisitor;
+    use crate::filter::FilteringVisitor;
+    use crate::histogram::HistogramVisitor;
+    use crate::interpolate::InterpolatingVisitor;
+    use crate::measurement::{GroupedMeasurementVisitor, MetadataVisitor};
+    use crate::mqtt5::Mqtt5UserPropertiesVisitor;
+    use crate::rate_limit::RateLimitingVisitor;
+    use crate::recording::RecordingVisitor;
+    use crate::remap::MeasurementNameRemapper;
+    use crate::serialize::MeasurementStreamError;
+    use crate::statistics::StatisticsInjectingVisitor;
+    use crate::tee::TeeVisitor;
+    use crate::throttle::ThrottlingVisitor;
+    use crate::time_window::TimeWindowVisitor;
+    use crate::transform::{DslTransformVisitor, TransformConfig};
+    use crate::units::{
+        MeasurementTypeRegistry, UnitAnnotatingVisitor, UnitConversions, UnitConvertingVisitor,
+    };
+    use chrono::offset::FixedOffset;
+    use chrono::DateTime;
+    use std::time::Duration;
+
+    /// A visitor recording the contexts it is given
+    #[derive(Default)]
+    struct ContextRecorder {
+        contexts: Vec<MeasurementContext>,
+    }
+
+    impl GroupedMeasurementVisitor for ContextRecorder {
+        type Error = MeasurementStreamError;
+
+        fn timestamp(&mut self, _value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn measurement(&mut self, _name: &str, _value: f64) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn start_group(&mut self, _group: &str) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn end_group(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+            self.contexts.push(context);
+            Ok(())
+        }
+    }
+
+    impl MetadataVisitor for ContextRecorder {
+        fn metadata(&mut self, _key: &str, _value: &str) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn metadata_flag(&mut self, _key: &str, _value: bool) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    fn test_context() -> MeasurementContext {
+        MeasurementContext::new([0x4b; 16], [0xf0; 8]).with_baggage("site", "plant-7")
+    }
+
+    /// Check that the context given to a wrapper reaches the inner recorder,
+    /// the wrapper being built around `&mut recorder` and flushed, if need be, by `$flush`.
+    macro_rules! assert_context_forwarded {
+        ($recorder:ident => $wrapper:expr) => {
+            assert_context_forwarded!($recorder => $wrapper, |_: &mut _| Ok::<(), MeasurementStreamError>(()))
+        };
+        ($recorder:ident => $wrapper:expr, $flush:expr) => {{
+            let mut $recorder = ContextRecorder::default();
+            {
+                let mut wrapper = $wrapper;
+                wrapper.set_context(test_context())?;
+                wrapper.measurement("temperature", 25.5)?;
+                $flush(&mut wrapper)?;
+            }
+            assert_eq!(
+                $recorder.contexts,
+                vec![test_context()],
+                "{}",
+                stringify!($wrapper)
+            );
+        }};
+    }
+
+    #[test]
+    fn contexts_are_forwarded_by_the_wrappers() -> anyhow::Result<()> {
+        assert_context_forwarded!(recorder => &mut recorder);
+        assert_context_forwarded!(recorder => AnonymizingVisitor::new(b"key", vec![], &mut recorder));
+        assert_context_forwarded!(recorder => ClampingVisitor::new(Default::default(), ClampBehavior::Clamp, &mut recorder));
+        assert_context_forwarded!(recorder => DeduplicatingVisitor::new(0.1, &mut recorder));
+        assert_context_forwarded!(recorder => FilteringVisitor::new("*", &mut recorder));
+        assert_context_forwarded!(
+            recorder => HistogramVisitor::new(&[0.0, 50.0], &mut recorder),
+            |histograms: &mut HistogramVisitor<_>| histograms.flush()
+        );
+        assert_context_forwarded!(recorder => InterpolatingVisitor::new(Duration::from_secs(60), Duration::from_secs(10), &mut recorder));
+        assert_context_forwarded!(recorder => MeasurementNameRemapper::new(&mut recorder));
+        assert_context_forwarded!(recorder => Mqtt5UserPropertiesVisitor::new(&mut recorder));
+        assert_context_forwarded!(recorder => RateLimitingVisitor::new(Duration::from_secs(1), &mut recorder));
+        assert_context_forwarded!(recorder => RecordingVisitor::new(&mut recorder));
+        assert_context_forwarded!(recorder => StatisticsInjectingVisitor::new(10, &mut recorder));
+        assert_context_forwarded!(recorder => TeeVisitor::new(&mut recorder, ContextRecorder::default()));
+        assert_context_forwarded!(
+            recorder => ThrottlingVisitor::new(Duration::from_secs(1), &mut recorder),
+            |throttle: &mut ThrottlingVisitor<_>| throttle.flush()
+        );
+        assert_context_forwarded!(recorder => TimeWindowVisitor::new(vec![], &mut recorder));
+        assert_context_forwarded!(recorder => DslTransformVisitor::new(TransformConfig::from_json_str(r#"{"rules":[]}"#)?.compile()?, &mut recorder));
+        assert_context_forwarded!(recorder => UnitAnnotatingVisitor::new(MeasurementTypeRegistry::new(), &mut recorder));
+        assert_context_forwarded!(recorder => UnitConvertingVisitor::new(UnitConversions::new(), &mut recorder));
+        Ok(())
+    }
+
+    #[test]
+    fn ids_are_hex_encoded_with_leading_zeros() {
+        let context = MeasurementContext::new(
+            [
+                0x0a, 0xf7, 0x65, 0x19, 0x16, 0xcd, 0x43, 0xdd, 0x84, 0x48, 0xeb, 0x21, 0x1c, 0x80,
+                0x31, 0x9c,
+            ],
+            [0x00, 0xf0, 0x67, 0xaa, 0x0b, 0xa9, 0x02, 0xb7],
+        );
+
+        assert_eq!(context.trace_id_hex(), "0af7651916cd43dd8448eb211c80319c");
+        assert_eq!(context.span_id_hex(), "00f067aa0ba902b7");
+    }
+
+    #[test]
+    fn ids_are_parsed_from_hex() {
+        let context =
+            MeasurementContext::from_hex("0af7651916cd43dd8448eb211c80319c", "00f067aa0ba902b7");
+
+        assert_eq!(
+            context.map(|context| (context.trace_id_hex(), context.span_id_hex())),
+            Some((
+                "0af7651916cd43dd8448eb211c80319c".to_string(),
+                "00f067aa0ba902b7".to_string()
+            ))
+        );
+        assert_eq!(
+            MeasurementContext::from_hex("0af765", "00f067aa0ba902b7"),
+            None
+        );
+        assert_eq!(
+            MeasurementContext::from_hex("0af7651916cd43dd8448eb211c80319c", "+0f067aa0ba902b7"),
+            None
+        );
+    }
+
+    #[test]
+    fn contexts_are_serialized_with_hex_ids() -> anyhow::Result<()> {
+        let context = MeasurementContext::new([0x4b; 16], [0xf0; 8]);
+
+        let json = serde_json::to_value(&context)?;
+
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "trace_id": "4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b",
+                "span_id": "f0f0f0f0f0f0f0f0",
+            })
+        );
+        assert_eq!(serde_json::from_value::<MeasurementContext>(json)?, context);
+        Ok(())
+    }
+}
@@ -0,0 +1,303 @@
+use crate::series::FlatMeasurementSeries;
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+
+const CSV_HEADER: [&str; 5] = ["time", "group", "name", "value", "unit"];
+
+/// Convert measurements from and to CSV.
+///
+/// A CSV document starts with a `time,group,name,value,unit` header,
+/// followed by one row per measurement.
+/// The `time`, `group` and `unit` cells are left empty when not applicable.
+/// All the rows share the same time, if any, given as an RFC 3339 timestamp.
+///
+/// ```
+/// use thin_edge_json::csv::CsvConverter;
+/// use thin_edge_json::json::parse_str;
+/// use thin_edge_json::series::FlatMeasurementSeries;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let mut series = FlatMeasurementSeries::new();
+/// parse_str(r#"{"temperature": 25.5, "location": {"alti": 2100.4}}"#, &mut series)?;
+///
+/// assert_eq!(
+///     CsvConverter::to_csv(&series),
+///     "time,group,name,value,unit\n\
+///      ,,temperature,25.5,\n\
+///      ,location,alti,2100.4,\n"
+/// );
+/// # Ok(()) }
+/// ```
+pub struct CsvConverter;
+
+#[derive(thiserror::Error, Debug)]
+pub enum CsvError {
+    #[error("Invalid CSV header: expected {expected:?}, found {found:?}")]
+    InvalidHeader { expected: String, found: String },
+
+    #[error("Invalid CSV row {row}: expected 5 cells, found {found}")]
+    InvalidRowLength { row: usize, found: usize },
+
+    #[error("Invalid CSV row {row}: {value:?} is not a number")]
+    InvalidValue { row: usize, value: String },
+
+    #[error("Invalid CSV row {row}: {value:?} is not an RFC 3339 timestamp")]
+    InvalidTimestamp { row: usize, value: String },
+
+    #[error("Invalid CSV row {row}: the measurement name is missing")]
+    MissingName { row: usize },
+
+    #[error("Invalid CSV row {row}: all the rows must have the same time")]
+    MultipleTimestamps { row: usize },
+
+    #[error("Invalid CSV: unterminated quoted cell")]
+    UnterminatedQuote,
+}
+
+impl CsvConverter {
+    pub fn to_csv(series: &FlatMeasurementSeries) -> String {
+        let time = series
+            .timestamp
+            .map(|timestamp| timestamp.to_rfc3339())
+            .unwrap_or_default();
+
+        let mut csv = String::new();
+        write_row(&mut csv, &CSV_HEADER);
+        for measurement in series.measurements.iter() {
+            write_row(
+                &mut csv,
+                &[
+                    &time,
+                    measurement.group.as_deref().unwrap_or_default(),
+                    &measurement.name,
+                    &measurement.value.to_string(),
+                    measurement.unit.as_deref().unwrap_or_default(),
+                ],
+            );
+        }
+        csv
+    }
+
+    pub fn from_csv(input: &str) -> Result<FlatMeasurementSeries, CsvError> {
+        let mut rows = parse_rows(input)?.into_iter();
+
+        let header = rows.next().unwrap_or_default();
+        if header != CSV_HEADER {
+            return Err(CsvError::InvalidHeader {
+                expected: CSV_HEADER.join(","),
+                found: header.join(","),
+            });
+        }
+
+        let mut series = FlatMeasurementSeries::new();
+        for (index, cells) in rows.enumerate() {
+            let row = index + 2;
+            if let [time, group, name, value, unit] = cells.as_slice() {
+                if !time.is_empty() {
+                    let timestamp =
+                        DateTime::<FixedOffset>::parse_from_rfc3339(time).map_err(|_| {
+                            CsvError::InvalidTimestamp {
+                                row,
+                                value: time.clone(),
+                            }
+                        })?;
+                    match series.timestamp {
+                        Some(previous) if previous != timestamp => {
+                            return Err(CsvError::MultipleTimestamps { row })
+                        }
+                        _ => series.timestamp = Some(timestamp),
+                    }
+                }
+                if name.is_empty() {
+                    return Err(CsvError::MissingName { row });
+                }
+                let value = value.parse().map_err(|_| CsvError::InvalidValue {
+                    row,
+                    value: value.clone(),
+                })?;
+                series.push(non_empty(group), name, value, non_empty(unit));
+            } else {
+                return Err(CsvError::InvalidRowLength {
+                    row,
+                    found: cells.len(),
+                });
+            }
+        }
+        Ok(series)
+    }
+}
+
+fn non_empty(cell: &str) -> Option<&str> {
+    Some(cell).filter(|cell| !cell.is_empty())
+}
+
+fn write_row(csv: &mut String, cells: &[&str]) {
+    let cells: Vec<String> = cells.iter().map(|cell| quote(cell)).collect();
+    csv.push_str(&cells.join(","));
+    csv.push('\n');
+}
+
+/// Quote a cell as specified by RFC 4180, only when required
+fn quote(cell: &str) -> String {
+    if cell.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_string()
+    }
+}
+
+/// Split a CSV document into rows of unquoted cells, ignoring empty lines
+fn parse_rows(input: &str) -> Result<Vec<Vec<String>>, CsvError> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut cell = String::new();
+    let mut chars = input.chars().peekable();
+    let mut quoted = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    cell.push('"');
+                    chars.next();
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if cell.is_empty() => quoted = true,
+            ',' if !quoted => row.push(std::mem::take(&mut cell)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' if !quoted => {
+                if !row.is_empty() || !cell.is_empty() {
+                    row.push(std::mem::take(&mut cell));
+                    rows.push(std::mem::take(&mut row));
+                }
+            }
+            c => cell.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(CsvError::UnterminatedQuote);
+    }
+    if !row.is_empty() || !cell.is_empty() {
+        row.push(cell);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::parse_str;
+    use crate::serialize::ThinEdgeJsonSerializer;
+    use assert_matches::assert_matches;
+
+    fn json_to_csv(input: &str) -> anyhow::Result<String> {
+        let mut series = FlatMeasurementSeries::new();
+        parse_str(input, &mut series)?;
+        Ok(CsvConverter::to_csv(&series))
+    }
+
+    fn csv_to_json(input: &str) -> anyhow::Result<String> {
+        let series = CsvConverter::from_csv(input)?;
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        series.visit(&mut serializer)?;
+        Ok(serializer.into_string()?)
+    }
+
+    #[test]
+    fn convert_thin_edge_json_to_csv() -> anyhow::Result<()> {
+        let input = r#"{
+            "time": "2021-04-30T17:03:14+02:00",
+            "temperature": 25.5,
+            "location": {"alti": 2100.4}
+        }"#;
+
+        assert_eq!(
+            json_to_csv(input)?,
+            "time,group,name,value,unit\n\
+             2021-04-30T17:03:14+02:00,,temperature,25.5,\n\
+             2021-04-30T17:03:14+02:00,location,alti,2100.4,\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn units_are_given_in_their_own_column() -> anyhow::Result<()> {
+        let mut series = FlatMeasurementSeries::new();
+        series.push(None, "temperature", 25.5, Some("°C"));
+
+        assert_eq!(
+            CsvConverter::to_csv(&series),
+            "time,group,name,value,unit\n,,temperature,25.5,°C\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn convert_csv_to_thin_edge_json() -> anyhow::Result<()> {
+        let input = "time,group,name,value,unit\r\n\
+                     2021-04-30T17:03:14+02:00,location,alti,2100.4,m\r\n\
+                     ,,temperature,25,\r\n\
+                     2021-04-30T17:03:14+02:00,location,longi,2200.4,\r\n";
+
+        assert_eq!(
+            csv_to_json(input)?,
+            r#"{"time":"2021-04-30T17:03:14+02:00","location":{"alti":{"value":2100.4,"unit":"m"},"longi":2200.4},"temperature":25.0}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn special_characters_are_quoted() -> anyhow::Result<()> {
+        let mut series = FlatMeasurementSeries::new();
+        series.push(Some("a,b"), "say \"hi\"", 1.0, None);
+
+        let csv = CsvConverter::to_csv(&series);
+
+        assert_eq!(
+            csv,
+            "time,group,name,value,unit\n,\"a,b\",\"say \"\"hi\"\"\",1,\n"
+        );
+        assert_eq!(CsvConverter::from_csv(&csv)?, series);
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_csv() {
+        assert_matches!(
+            CsvConverter::from_csv("name,value\ntemperature,25.5\n"),
+            Err(CsvError::InvalidHeader { .. })
+        );
+        assert_matches!(
+            CsvConverter::from_csv("time,group,name,value,unit\n,,temperature,hot,\n"),
+            Err(CsvError::InvalidValue { row: 2, .. })
+        );
+        assert_matches!(
+            CsvConverter::from_csv("time,group,name,value,unit\n,,temperature,25.5\n"),
+            Err(CsvError::InvalidRowLength { row: 2, found: 4 })
+        );
+        assert_matches!(
+            CsvConverter::from_csv("time,group,name,value,unit\n,,,25.5,\n"),
+            Err(CsvError::MissingName { row: 2 })
+        );
+        assert_matches!(
+            CsvConverter::from_csv("time,group,name,value,unit\n,\"location,alti,2100.4,\n"),
+            Err(CsvError::UnterminatedQuote)
+        );
+    }
+
+    #[test]
+    fn reject_rows_with_different_times() {
+        let input = "time,group,name,value,unit\n\
+                     2021-04-30T17:03:14+02:00,,temperature,25.5,\n\
+                     2021-04-30T17:03:15+02:00,,pressure,98.0,\n";
+
+        assert_matches!(
+            CsvConverter::from_csv(input),
+            Err(CsvError::MultipleTimestamps { row: 3 })
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use crate::context::MeasurementContext;
+use crate::filter::PendingGroup;
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use std::collections::HashMap;
+
+/// A visitor that drops the measurements that have not changed since last forwarded.
+///
+/// A measurement, identified by its name or by `<group>.<name>` for grouped measurements,
+/// is dropped when its value differs by at most `epsilon` from the value last forwarded
+/// for the same measurement. A missing value is only dropped if the last value was missing too.
+/// The complex measurements are always forwarded.
+///
+/// A group is only forwarded if at least one of its measurements is.
+///
+/// ```
+/// use thin_edge_json::dedup::DeduplicatingVisitor;
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let mut visitor = DeduplicatingVisitor::new(0.1, ThinEdgeJsonSerializer::new());
+///
+/// visitor.measurement("temperature", 25.0)?;
+/// let first = std::mem::replace(visitor.inner_mut(), ThinEdgeJsonSerializer::new());
+///
+/// visitor.measurement("temperature", 25.05)?;
+/// let second = std::mem::replace(visitor.inner_mut(), ThinEdgeJsonSerializer::new());
+///
+/// assert_eq!(first.bytes()?, br#"{"temperature":25.0}"#);
+/// assert_eq!(second.bytes()?, b"{}");
+/// # Ok(()) }
+/// ```
+pub struct DeduplicatingVisitor<V> {
+    epsilon: f64,
+    last_forwarded: HashMap<String, Option<f64>>,
+    group: PendingGroup,
+    inner: V,
+}
+
+impl<V> DeduplicatingVisitor<V> {
+    pub fn new(epsilon: f64, inner: V) -> Self {
+        Self {
+            epsilon: epsilon.abs(),
+            last_forwarded: HashMap::new(),
+            group: PendingGroup::default(),
+            inner,
+        }
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut V {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> V {
+        self.inner
+    }
+
+    /// Tell if the value has changed since last forwarded, recording the value if so
+    fn accept(&mut self, name: &str, value: Option<f64>) -> bool {
+        let key = self.group.key(name);
+        let epsilon = self.epsilon;
+        let unchanged = |last: &Option<f64>| match (last, value) {
+            (Some(last), Some(value)) => (value - last).abs() <= epsilon,
+            (None, None) => true,
+            _ => false,
+        };
+
+        if self.last_forwarded.get(&key).map_or(false, unchanged) {
+            false
+        } else {
+            self.last_forwarded.insert(key, value);
+            true
+        }
+    }
+}
+
+impl<V> GroupedMeasurementVisitor for DeduplicatingVisitor<V>
+where
+    V: GroupedMeasurementVisitor,
+{
+    type Error = V::Error;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.inner.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        if self.accept(name, Some(value)) {
+            self.group.forward_start(&mut self.inner)?;
+            self.inner.measurement(name, value)?;
+        }
+        Ok(())
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.group.start(group, &mut self.inner)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.group.end(&mut self.inner)
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        if self.accept(name, Some(value)) {
+            self.group.forward_start(&mut self.inner)?;
+            self.inner.measurement_with_unit(name, value, unit)?;
+        }
+        Ok(())
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.group
+            .start_with_timestamp(group, timestamp, &mut self.inner)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        if self.accept(name, value) {
+            self.group.forward_start(&mut self.inner)?;
+            self.inner.nullable_measurement(name, value)?;
+        }
+        Ok(())
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.group.forward_start(&mut self.inner)?;
+        self.inner.complex_measurement(name, real, imaginary)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        if self.accept(name, Some(value)) {
+            self.group.forward_start(&mut self.inner)?;
+            self.inner.annotated_measurement(name, value, quality)?;
+        }
+        Ok(())
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.inner.set_context(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::ThinEdgeJsonSerializer;
+
+    fn next_series(
+        visitor: &mut DeduplicatingVisitor<ThinEdgeJsonSerializer>,
+    ) -> anyhow::Result<String> {
+        let mut serializer = std::mem::replace(visitor.inner_mut(), ThinEdgeJsonSerializer::new());
+        Ok(serializer.into_string()?)
+    }
+
+    #[test]
+    fn values_within_epsilon_of_the_last_forwarded_one_are_dropped() -> anyhow::Result<()> {
+        let mut visitor = DeduplicatingVisitor::new(0.5, ThinEdgeJsonSerializer::new());
+
+        visitor.measurement("temperature", 20.0)?;
+        visitor.measurement("pressure", 98.0)?;
+        assert_eq!(
+            next_series(&mut visitor)?,
+            r#"{"temperature":20.0,"pressure":98.0}"#
+        );
+
+        visitor.measurement("temperature", 20.5)?;
+        visitor.measurement("pressure", 98.75)?;
+        assert_eq!(next_series(&mut visitor)?, r#"{"pressure":98.75}"#);
+
+        // The reference is the last forwarded value, not the last received one
+        visitor.measurement("temperature", 20.75)?;
+        assert_eq!(next_series(&mut visitor)?, r#"{"temperature":20.75}"#);
+        Ok(())
+    }
+
+    #[test]
+    fn missing_values_are_deduplicated() -> anyhow::Result<()> {
+        let mut visitor = DeduplicatingVisitor::new(0.0, ThinEdgeJsonSerializer::new());
+
+        visitor.nullable_measurement("humidity", None)?;
+        assert_eq!(next_series(&mut visitor)?, r#"{"humidity":null}"#);
+
+        visitor.nullable_measurement("humidity", None)?;
+        assert_eq!(next_series(&mut visitor)?, "{}");
+
+        visitor.nullable_measurement("humidity", Some(0.0))?;
+        assert_eq!(next_series(&mut visitor)?, r#"{"humidity":0.0}"#);
+        Ok(())
+    }
+
+    #[test]
+    fn groups_with_no_changed_measurements_are_dropped() -> anyhow::Result<()> {
+        let mut visitor = DeduplicatingVisitor::new(0.0, ThinEdgeJsonSerializer::new());
+
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.end_group()?;
+        assert_eq!(
+            next_series(&mut visitor)?,
+            r#"{"location":{"alti":2100.4}}"#
+        );
+
+        visitor.measurement("alti", 2100.4)?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.end_group()?;
+        assert_eq!(next_series(&mut visitor)?, r#"{"alti":2100.4}"#);
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+use crate::json::{ThinEdgeJson, ThinEdgeJsonError, ThinEdgeJsonParserError, ThinEdgeValue};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Compute what changed between two thin-edge JSON payloads.
+///
+/// ```
+/// use thin_edge_json::diff::ThinEdgeJsonDiff;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let before = br#"{"temperature": 20.0, "pressure": 98.0}"#;
+/// let after = br#"{"temperature": 25.0, "location": {"alti": 2100.4}}"#;
+///
+/// let diff = ThinEdgeJsonDiff::compute(before, after)?;
+///
+/// assert_eq!(diff.added.get("location.alti"), Some(&2100.4));
+/// assert_eq!(diff.removed.get("pressure"), Some(&98.0));
+/// assert_eq!(diff.changed.get("temperature").map(|c| c.delta), Some(5.0));
+/// # Ok(()) }
+/// ```
+pub struct ThinEdgeJsonDiff;
+
+/// The measurements added, removed and changed between two thin-edge JSON payloads.
+///
+/// A measurement is identified by its name, or by `<group>.<name>` when attached to a group.
+/// The timestamps of the payloads are not compared.
+///
+/// Serialized as JSON, a diff has the following schema:
+///
+/// ```json
+/// {
+///     "added": { "<measurement>": <value>, ... },
+///     "removed": { "<measurement>": <value>, ... },
+///     "changed": {
+///         "<measurement>": {
+///             "old": <value>,
+///             "new": <value>,
+///             "delta": <new - old>,
+///             "percentage": <100 * delta / |old|, or null when old is 0>
+///         },
+///         ...
+///     }
+/// }
+/// ```
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct MeasurementDiff {
+    pub added: BTreeMap<String, f64>,
+    pub removed: BTreeMap<String, f64>,
+    pub changed: BTreeMap<String, ChangedValue>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ChangedValue {
+    pub old: f64,
+    pub new: f64,
+    pub delta: f64,
+    pub percentage: Option<f64>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum DiffError {
+    #[error("Invalid 'before' payload: {0}")]
+    InvalidBeforePayload(ThinEdgeJsonParserError<ThinEdgeJsonError>),
+
+    #[error("Invalid 'after' payload: {0}")]
+    InvalidAfterPayload(ThinEdgeJsonParserError<ThinEdgeJsonError>),
+}
+
+impl ThinEdgeJsonDiff {
+    // The values are compared exactly: any change of a value in the payloads is reported
+    #[allow(clippy::float_cmp)]
+    pub fn compute(before: &[u8], after: &[u8]) -> Result<MeasurementDiff, DiffError> {
+        let before = ThinEdgeJson::from_utf8(before).map_err(DiffError::InvalidBeforePayload)?;
+        let after = ThinEdgeJson::from_utf8(after).map_err(DiffError::InvalidAfterPayload)?;
+
+        let before = flatten(&before);
+        let mut after = flatten(&after);
+        let mut diff = MeasurementDiff::default();
+
+        for (name, old) in before.into_iter() {
+            match after.remove(&name) {
+                None => {
+                    diff.removed.insert(name, old);
+                }
+                Some(new) if new != old => {
+                    diff.changed.insert(name, ChangedValue::new(old, new));
+                }
+                Some(_) => {}
+            }
+        }
+        diff.added = after;
+
+        Ok(diff)
+    }
+}
+
+impl MeasurementDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl ChangedValue {
+    fn new(old: f64, new: f64) -> Self {
+        let delta = new - old;
+        let percentage = if old == 0.0 {
+            None
+        } else {
+            Some(100.0 * delta / old.abs())
+        };
+
+        Self {
+            old,
+            new,
+            delta,
+            percentage,
+        }
+    }
+}
+
+fn flatten(thin_edge_json: &ThinEdgeJson) -> BTreeMap<String, f64> {
+    let mut measurements = BTreeMap::new();
+    for value in thin_edge_json.values.iter() {
+        match value {
+            ThinEdgeValue::Single(measurement) => {
+                measurements.insert(measurement.name.clone(), measurement.value);
+            }
+            ThinEdgeValue::Multi(group) => {
+                for measurement in group.values.iter() {
+                    let name = format!("{}.{}", group.name, measurement.name);
+                    measurements.insert(name, measurement.value);
+                }
+            }
+        }
+    }
+    measurements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use serde_json::json;
+
+    #[test]
+    fn identical_payloads_have_no_diff() -> anyhow::Result<()> {
+        let payload = br#"{"temperature": 25.5, "location": {"alti": 2100.4}}"#;
+
+        let diff = ThinEdgeJsonDiff::compute(payload, payload)?;
+
+        assert!(diff.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn timestamps_are_not_compared() -> anyhow::Result<()> {
+        let before = br#"{"time": "2021-04-30T17:03:14+02:00", "temperature": 25.5}"#;
+        let after = br#"{"time": "2021-04-30T17:04:14+02:00", "temperature": 25.5}"#;
+
+        let diff = ThinEdgeJsonDiff::compute(before, after)?;
+
+        assert!(diff.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn diff_added_removed_and_changed_measurements() -> anyhow::Result<()> {
+        let before = br#"{
+            "temperature": 20.0,
+            "pressure": 98.0,
+            "location": {"alti": 2100.0, "longi": 12.5}
+        }"#;
+        let after = br#"{
+            "temperature": 25.0,
+            "humidity": 40.0,
+            "location": {"alti": 2000.0, "lati": 48.1, "longi": 12.5}
+        }"#;
+
+        let diff = ThinEdgeJsonDiff::compute(before, after)?;
+
+        assert_eq!(diff.added.len(), 2);
+        assert_eq!(diff.added.get("humidity"), Some(&40.0));
+        assert_eq!(diff.added.get("location.lati"), Some(&48.1));
+
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed.get("pressure"), Some(&98.0));
+
+        assert_eq!(diff.changed.len(), 2);
+        assert_eq!(
+            diff.changed.get("temperature"),
+            Some(&ChangedValue {
+                old: 20.0,
+                new: 25.0,
+                delta: 5.0,
+                percentage: Some(25.0),
+            })
+        );
+        assert_eq!(
+            diff.changed.get("location.alti"),
+            Some(&ChangedValue {
+                old: 2100.0,
+                new: 2000.0,
+                delta: -100.0,
+                percentage: Some(100.0 * -100.0 / 2100.0),
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn percentage_is_undefined_for_a_change_from_zero() -> anyhow::Result<()> {
+        let before = br#"{"temperature": 0}"#;
+        let after = br#"{"temperature": 5}"#;
+
+        let diff = ThinEdgeJsonDiff::compute(before, after)?;
+
+        let change = diff.changed.get("temperature").unwrap();
+        assert_eq!(change.delta, 5.0);
+        assert_eq!(change.percentage, None);
+        Ok(())
+    }
+
+    #[test]
+    fn diff_serializes_to_the_documented_json_schema() -> anyhow::Result<()> {
+        let before = br#"{"temperature": 20.0, "pressure": 98.0}"#;
+        let after = br#"{"temperature": 0.0, "location": {"alti": 2100.4}}"#;
+
+        let diff = ThinEdgeJsonDiff::compute(before, after)?;
+
+        assert_eq!(
+            serde_json::to_value(&diff)?,
+            json!({
+                "added": { "location.alti": 2100.4 },
+                "removed": { "pressure": 98.0 },
+                "changed": {
+                    "temperature": {
+                        "old": 20.0,
+                        "new": 0.0,
+                        "delta": -20.0,
+                        "percentage": -100.0
+                    }
+                }
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_payloads_are_reported() {
+        let valid = br#"{"temperature": 20.0}"#;
+        let invalid = br#"{"temperature": "hot"}"#;
+
+        assert_matches!(
+            ThinEdgeJsonDiff::compute(invalid, valid),
+            Err(DiffError::InvalidBeforePayload(_))
+        );
+        assert_matches!(
+            ThinEdgeJsonDiff::compute(valid, invalid),
+            Err(DiffError::InvalidAfterPayload(_))
+        );
+    }
+}
@@ -0,0 +1,348 @@
+use crate::json::{parse_str, ThinEdgeJsonParserError};
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+const DEFAULT_FEATURE_ID: &str = "measurements";
+
+/// A Ditto protocol message modifying a property of a feature of the twin of a thing.
+///
+/// Serialized as documented by the
+/// [Ditto protocol](https://www.eclipse.org/ditto/protocol-specification.html):
+///
+/// ```json
+/// {
+///   "topic": "<namespace>/<thing_name>/things/twin/commands/modify",
+///   "headers": {"response-required": false},
+///   "path": "/features/<feature_id>/properties/<property_path>",
+///   "value": 25.5
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DittoCommand {
+    pub topic: String,
+    pub headers: DittoHeaders,
+    pub path: String,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DittoHeaders {
+    /// No response is expected for telemetry
+    #[serde(rename = "response-required")]
+    pub response_required: bool,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum DittoError {
+    #[error("Invalid Ditto thing id: {thing_id:?} must be given as <namespace>:<name>")]
+    InvalidThingId { thing_id: String },
+
+    #[error("Invalid Ditto feature id: {feature_id:?} must not be empty nor contain '/'")]
+    InvalidFeatureId { feature_id: String },
+
+    #[error("Invalid Ditto property: {name:?} must not be empty")]
+    InvalidPropertyName { name: String },
+
+    #[error("Invalid value for {name}: {value} has no JSON representation")]
+    InvalidValue { name: String, value: f64 },
+
+    #[error("Unexpected end of group")]
+    UnexpectedEndOfGroup,
+
+    #[error("Unexpected start of group")]
+    UnexpectedStartOfGroup,
+}
+
+/// Convert thin-edge JSON measurements into [Eclipse Ditto](https://www.eclipse.org/ditto/)
+/// commands modifying the twin of a thing, one `modify` command per measurement.
+///
+/// Each group is mapped to a Ditto feature, named after the group,
+/// and each measurement to a property of that feature, named after the measurement.
+/// The measurements which are not attached to a group are mapped to the properties
+/// of a default feature, named `measurements` unless set otherwise.
+///
+/// The measurements with a unit are given as `{"value":<value>,"unit":<unit>}`,
+/// the complex measurements as `{"re":<real>,"im":<imaginary>}`, as in thin-edge JSON.
+/// The timestamps, which are not part of a Ditto command, are ignored.
+///
+/// ```
+/// use thin_edge_json::ditto::DittoTwinVisitor;
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let mut visitor = DittoTwinVisitor::new("org.eclipse.ditto:fancy-car")?;
+/// visitor.start_group("engine")?;
+/// visitor.measurement("speed", 3000.0)?;
+/// visitor.end_group()?;
+///
+/// let commands = visitor.into_commands();
+/// assert_eq!(
+///     serde_json::to_string(&commands[0])?,
+///     r#"{"topic":"org.eclipse.ditto/fancy-car/things/twin/commands/modify","headers":{"response-required":false},"path":"/features/engine/properties/speed","value":3000.0}"#
+/// );
+/// # Ok(()) }
+/// ```
+#[derive(Debug)]
+pub struct DittoTwinVisitor {
+    topic: String,
+    default_feature: String,
+    group: Option<String>,
+    commands: Vec<DittoCommand>,
+}
+
+impl DittoTwinVisitor {
+    /// Create a visitor for the thing with the given id, given as `<namespace>:<name>`
+    pub fn new(thing_id: &str) -> Result<Self, DittoError> {
+        let invalid_thing_id = || DittoError::InvalidThingId {
+            thing_id: thing_id.into(),
+        };
+        let separator = thing_id.find(':').ok_or_else(invalid_thing_id)?;
+        let (namespace, name) = (&thing_id[..separator], &thing_id[separator + 1..]);
+        if name.is_empty() || name.contains('/') || namespace.contains('/') {
+            return Err(invalid_thing_id());
+        }
+
+        Ok(Self {
+            topic: format!("{}/{}/things/twin/commands/modify", namespace, name),
+            default_feature: DEFAULT_FEATURE_ID.into(),
+            group: None,
+            commands: Vec::new(),
+        })
+    }
+
+    /// Set the feature of the measurements which are not attached to a group
+    pub fn with_default_feature(self, feature_id: &str) -> Result<Self, DittoError> {
+        check_feature_id(feature_id)?;
+        Ok(Self {
+            default_feature: feature_id.into(),
+            ..self
+        })
+    }
+
+    /// Convert a thin-edge JSON string into Ditto commands for the given thing
+    pub fn convert(
+        thin_edge_json: &str,
+        thing_id: &str,
+    ) -> Result<Vec<DittoCommand>, ThinEdgeJsonParserError<DittoError>> {
+        let mut visitor =
+            DittoTwinVisitor::new(thing_id).map_err(ThinEdgeJsonParserError::VisitorError)?;
+        let () = parse_str(thin_edge_json, &mut visitor)?;
+        Ok(visitor.into_commands())
+    }
+
+    /// The commands built so far, one per measurement, in order
+    pub fn into_commands(self) -> Vec<DittoCommand> {
+        self.commands
+    }
+
+    fn modify(&mut self, name: &str, value: Value) -> Result<(), DittoError> {
+        if name.is_empty() {
+            return Err(DittoError::InvalidPropertyName { name: name.into() });
+        }
+        let feature = self.group.as_ref().unwrap_or(&self.default_feature);
+        let path = format!("/features/{}/properties/{}", feature, name);
+        self.commands.push(DittoCommand {
+            topic: self.topic.clone(),
+            headers: DittoHeaders {
+                response_required: false,
+            },
+            path,
+            value,
+        });
+        Ok(())
+    }
+}
+
+fn check_feature_id(feature_id: &str) -> Result<(), DittoError> {
+    if feature_id.is_empty() || feature_id.contains('/') {
+        return Err(DittoError::InvalidFeatureId {
+            feature_id: feature_id.into(),
+        });
+    }
+    Ok(())
+}
+
+fn json_number(name: &str, value: f64) -> Result<Value, DittoError> {
+    serde_json::Number::from_f64(value)
+        .map(Value::Number)
+        .ok_or_else(|| DittoError::InvalidValue {
+            name: name.into(),
+            value,
+        })
+}
+
+impl GroupedMeasurementVisitor for DittoTwinVisitor {
+    type Error = DittoError;
+
+    fn timestamp(&mut self, _value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        let value = json_number(name, value)?;
+        self.modify(name, value)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        if self.group.is_some() {
+            return Err(DittoError::UnexpectedStartOfGroup);
+        }
+        check_feature_id(group)?;
+        self.group = Some(group.into());
+        Ok(())
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        if self.group.take().is_none() {
+            return Err(DittoError::UnexpectedEndOfGroup);
+        }
+        Ok(())
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        let value = json_number(name, value)?;
+        self.modify(name, json!({"value": value, "unit": unit}))
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        let value = match value {
+            Some(value) => json_number(name, value)?,
+            None => Value::Null,
+        };
+        self.modify(name, value)
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        let real = json_number(name, real)?;
+        let imaginary = json_number(name, imaginary)?;
+        self.modify(name, json!({"re": real, "im": imaginary}))
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        let value = json_number(name, value)?;
+        self.modify(name, json!({"value": value, "quality": quality}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    const TOPIC: &str = "org.eclipse.ditto/fancy-car/things/twin/commands/modify";
+
+    fn envelope(path: &str, value: Value) -> Value {
+        json!({
+            "topic": TOPIC,
+            "headers": {"response-required": false},
+            "path": path,
+            "value": value,
+        })
+    }
+
+    fn envelopes(commands: &[DittoCommand]) -> Vec<Value> {
+        commands
+            .iter()
+            .map(|command| serde_json::to_value(command).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn each_measurement_is_a_modify_command() -> anyhow::Result<()> {
+        let input = r#"{
+            "time": "2021-04-30T17:03:14+02:00",
+            "temperature": 25.5,
+            "location": {"alti": 2100.4, "longi": 2200.4}
+        }"#;
+
+        let commands = DittoTwinVisitor::convert(input, "org.eclipse.ditto:fancy-car")?;
+
+        assert_eq!(
+            envelopes(&commands),
+            vec![
+                envelope("/features/measurements/properties/temperature", json!(25.5)),
+                envelope("/features/location/properties/alti", json!(2100.4)),
+                envelope("/features/location/properties/longi", json!(2200.4)),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn units_complex_and_missing_values_are_given_as_json_values() -> anyhow::Result<()> {
+        let mut visitor = DittoTwinVisitor::new("org.eclipse.ditto:fancy-car")?
+            .with_default_feature("sensors")?;
+        visitor.measurement_with_unit("temperature", 25.5, "°C")?;
+        visitor.complex_measurement("impedance", 50.0, -10.0)?;
+        visitor.nullable_measurement("pressure", None)?;
+
+        assert_eq!(
+            envelopes(&visitor.into_commands()),
+            vec![
+                envelope(
+                    "/features/sensors/properties/temperature",
+                    json!({"value": 25.5, "unit": "°C"})
+                ),
+                envelope(
+                    "/features/sensors/properties/impedance",
+                    json!({"re": 50.0, "im": -10.0})
+                ),
+                envelope("/features/sensors/properties/pressure", Value::Null),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn thing_ids_are_given_with_a_namespace() {
+        assert_matches!(
+            DittoTwinVisitor::new("fancy-car"),
+            Err(DittoError::InvalidThingId { .. })
+        );
+        assert_matches!(
+            DittoTwinVisitor::new("org.eclipse.ditto:"),
+            Err(DittoError::InvalidThingId { .. })
+        );
+        assert_matches!(DittoTwinVisitor::new(":fancy-car"), Ok(_));
+    }
+
+    #[test]
+    fn feature_ids_cannot_contain_slashes() -> anyhow::Result<()> {
+        let mut visitor = DittoTwinVisitor::new("org.eclipse.ditto:fancy-car")?;
+
+        assert_matches!(
+            visitor.start_group("engine/1"),
+            Err(DittoError::InvalidFeatureId { .. })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn special_values_are_rejected() -> anyhow::Result<()> {
+        let mut visitor = DittoTwinVisitor::new("org.eclipse.ditto:fancy-car")?;
+
+        assert_matches!(
+            visitor.measurement("temperature", f64::NAN),
+            Err(DittoError::InvalidValue { .. })
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,309 @@
+use crate::context::MeasurementContext;
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+
+/// The error type of a `DynGroupedMeasurementVisitor`
+pub type BoxedError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// An object-safe version of `GroupedMeasurementVisitor`.
+///
+/// The associated error type of a `GroupedMeasurementVisitor` prevents its use as a trait object.
+/// This trait is the same, except that all errors are boxed.
+///
+/// It is implemented by any `GroupedMeasurementVisitor` with a thread-safe error type,
+/// making possible to choose at runtime the visitors a chain is made of.
+///
+/// ```
+/// use thin_edge_json::dyn_visitor::{BoxedError, DynGroupedMeasurementVisitor};
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+///
+/// # fn main() -> Result<(), BoxedError> {
+/// let mut visitor: Box<dyn DynGroupedMeasurementVisitor> = Box::new(ThinEdgeJsonSerializer::new());
+/// visitor.measurement("temperature", 25.5)?;
+/// # Ok(()) }
+/// ```
+pub trait DynGroupedMeasurementVisitor {
+    /// Set the timestamp shared by all the measurements of this serie
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), BoxedError>;
+
+    /// Add a new measurement, attached to the current group if any
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), BoxedError>;
+
+    /// Start to gather measurements for a group
+    fn start_group(&mut self, group: &str) -> Result<(), BoxedError>;
+
+    /// Definitely end to gather measurements for the current group
+    fn end_group(&mut self) -> Result<(), BoxedError>;
+
+    /// Add a new measurement given with its unit, attached to the current group if any
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), BoxedError>;
+
+    /// Start to gather measurements for a group having its own timestamp
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), BoxedError>;
+
+    /// Add a new measurement which value might be missing, attached to the current group if any
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), BoxedError>;
+
+    /// Add a new complex measurement, given by its real and imaginary parts
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), BoxedError>;
+
+    /// Add a new measurement annotated with the quality of its value,
+    /// attached to the current group if any
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), BoxedError>;
+
+    /// Set the trace context of the measurements of this serie
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), BoxedError>;
+}
+
+impl<V> DynGroupedMeasurementVisitor for V
+where
+    V: GroupedMeasurementVisitor,
+    V::Error: std::error::Error + Send + Sync + 'static,
+{
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), BoxedError> {
+        Ok(GroupedMeasurementVisitor::timestamp(self, value)?)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), BoxedError> {
+        Ok(GroupedMeasurementVisitor::measurement(self, name, value)?)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), BoxedError> {
+        Ok(GroupedMeasurementVisitor::start_group(self, group)?)
+    }
+
+    fn end_group(&mut self) -> Result<(), BoxedError> {
+        Ok(GroupedMeasurementVisitor::end_group(self)?)
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), BoxedError> {
+        Ok(GroupedMeasurementVisitor::measurement_with_unit(
+            self, name, value, unit,
+        )?)
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), BoxedError> {
+        Ok(GroupedMeasurementVisitor::start_group_with_timestamp(
+            self, group, timestamp,
+        )?)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), BoxedError> {
+        Ok(GroupedMeasurementVisitor::nullable_measurement(
+            self, name, value,
+        )?)
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), BoxedError> {
+        Ok(GroupedMeasurementVisitor::complex_measurement(
+            self, name, real, imaginary,
+        )?)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), BoxedError> {
+        Ok(GroupedMeasurementVisitor::annotated_measurement(
+            self, name, value, quality,
+        )?)
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), BoxedError> {
+        Ok(GroupedMeasurementVisitor::set_context(self, context)?)
+    }
+}
+
+/// The error returned by a boxed `DynGroupedMeasurementVisitor` used as a `GroupedMeasurementVisitor`
+#[derive(Debug)]
+pub struct DynVisitorError(pub BoxedError);
+
+impl std::fmt::Display for DynVisitorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for DynVisitorError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+/// A boxed visitor can be wrapped by any generic visitor adapter,
+/// as the `UnitAnnotatingVisitor`, to build chains at runtime.
+impl<'a> GroupedMeasurementVisitor for Box<dyn DynGroupedMeasurementVisitor + 'a> {
+    type Error = DynVisitorError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        (**self).timestamp(value).map_err(DynVisitorError)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        (**self).measurement(name, value).map_err(DynVisitorError)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        (**self).start_group(group).map_err(DynVisitorError)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        (**self).end_group().map_err(DynVisitorError)
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        (**self)
+            .measurement_with_unit(name, value, unit)
+            .map_err(DynVisitorError)
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        (**self)
+            .start_group_with_timestamp(group, timestamp)
+            .map_err(DynVisitorError)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        (**self)
+            .nullable_measurement(name, value)
+            .map_err(DynVisitorError)
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        (**self)
+            .complex_measurement(name, real, imaginary)
+            .map_err(DynVisitorError)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        (**self)
+            .annotated_measurement(name, value, quality)
+            .map_err(DynVisitorError)
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        (**self).set_context(context).map_err(DynVisitorError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::ThinEdgeJsonSerializer;
+    use crate::units::{MeasurementTypeRegistry, UnitAnnotatingVisitor};
+    use chrono::TimeZone;
+
+    fn drive(visitor: &mut dyn DynGroupedMeasurementVisitor) -> Result<(), BoxedError> {
+        visitor.timestamp(FixedOffset::east(0).ymd(2021, 4, 30).and_hms(17, 3, 14))?;
+        visitor.measurement("temperature", 25.5)?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.end_group()?;
+        Ok(())
+    }
+
+    #[test]
+    fn drive_a_vec_of_boxed_visitors() -> Result<(), BoxedError> {
+        let registry = MeasurementTypeRegistry::new().with_unit(None, "temperature", "°C");
+
+        let mut plain = ThinEdgeJsonSerializer::new();
+        let mut annotated = UnitAnnotatingVisitor::new(registry, ThinEdgeJsonSerializer::new());
+
+        {
+            let mut visitors: Vec<Box<dyn DynGroupedMeasurementVisitor + '_>> =
+                vec![Box::new(&mut plain), Box::new(&mut annotated)];
+
+            for visitor in visitors.iter_mut() {
+                drive(visitor.as_mut())?;
+            }
+        }
+
+        assert_eq!(
+            plain.into_string()?,
+            r#"{"time":"2021-04-30T17:03:14+00:00","temperature":25.5,"location":{"alti":2100.4}}"#
+        );
+        assert_eq!(
+            annotated.into_inner().into_string()?,
+            r#"{"time":"2021-04-30T17:03:14+00:00","temperature":{"value":25.5,"unit":"°C"},"location":{"alti":2100.4}}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn visitor_errors_are_boxed() {
+        let mut visitor: Box<dyn DynGroupedMeasurementVisitor> =
+            Box::new(ThinEdgeJsonSerializer::new());
+
+        let error = visitor.as_mut().end_group().unwrap_err();
+
+        assert_eq!(error.to_string(), "Unexpected end of group");
+    }
+
+    #[test]
+    fn boxed_visitors_can_be_wrapped_by_generic_adapters() -> Result<(), BoxedError> {
+        let registry = MeasurementTypeRegistry::new().with_unit(None, "temperature", "°C");
+        let boxed: Box<dyn DynGroupedMeasurementVisitor> = Box::new(ThinEdgeJsonSerializer::new());
+        let mut chain = UnitAnnotatingVisitor::new(registry, boxed);
+
+        drive(&mut chain)?;
+        let error = GroupedMeasurementVisitor::end_group(&mut chain).unwrap_err();
+
+        assert_eq!(error.to_string(), "Unexpected end of group");
+        Ok(())
+    }
+}
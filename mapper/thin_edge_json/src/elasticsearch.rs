@@ -0,0 +1,174 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Map, Value};
+
+const TIME_FIELD: &str = "time";
+const TIMESTAMP_FIELD: &str = "@timestamp";
+
+#[derive(thiserror::Error, Debug)]
+pub enum ElasticsearchBulkError {
+    #[error("Invalid JSON payload at index {index}: {from}")]
+    InvalidJson {
+        index: usize,
+        from: serde_json::Error,
+    },
+
+    #[error("Invalid payload at index {index}: a thin-edge JSON payload must be an object")]
+    NotAnObject { index: usize },
+
+    #[error("Invalid payload at index {index}: {value} is not an RFC 3339 time")]
+    InvalidTimestamp { index: usize, value: Value },
+}
+
+/// Convert thin-edge JSON payloads into the body of an
+/// [Elasticsearch bulk request](https://www.elastic.co/guide/en/elasticsearch/reference/current/docs-bulk.html),
+/// indexing one document per payload.
+///
+/// Each document is the payload with its `time` field replaced by an `@timestamp` field,
+/// the time being given in UTC, as expected by Elasticsearch data streams and Kibana.
+/// A payload with no time is indexed with no `@timestamp`.
+/// The groups, units and metadata of the payloads are kept unchanged.
+///
+/// ```
+/// use thin_edge_json::elasticsearch::ThinEdgeToElasticsearchBulkConverter;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let converter = ThinEdgeToElasticsearchBulkConverter::new("measurements");
+/// let payloads = [
+///     r#"{"time":"2021-04-30T17:03:14+02:00","temperature":25.5}"#,
+///     r#"{"location":{"alti":2100.4}}"#,
+/// ];
+///
+/// assert_eq!(
+///     converter.convert(&payloads)?,
+///     "{\"index\":{\"_index\":\"measurements\"}}\n\
+///      {\"@timestamp\":\"2021-04-30T15:03:14Z\",\"temperature\":25.5}\n\
+///      {\"index\":{\"_index\":\"measurements\"}}\n\
+///      {\"location\":{\"alti\":2100.4}}\n"
+/// );
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ThinEdgeToElasticsearchBulkConverter {
+    pub index_name: String,
+}
+
+impl ThinEdgeToElasticsearchBulkConverter {
+    pub fn new(index_name: &str) -> Self {
+        Self {
+            index_name: index_name.to_string(),
+        }
+    }
+
+    /// Convert the payloads into an action and a document line each,
+    /// every line being terminated by a newline as required by the bulk API.
+    pub fn convert<S>(&self, payloads: &[S]) -> Result<String, ElasticsearchBulkError>
+    where
+        S: AsRef<str>,
+    {
+        let action = json!({"index": {"_index": self.index_name}}).to_string();
+        let mut bulk = String::new();
+        for (index, payload) in payloads.iter().enumerate() {
+            let document = to_document(index, payload.as_ref())?;
+            bulk.push_str(&action);
+            bulk.push('\n');
+            bulk.push_str(&Value::Object(document).to_string());
+            bulk.push('\n');
+        }
+        Ok(bulk)
+    }
+}
+
+fn to_document(index: usize, payload: &str) -> Result<Map<String, Value>, ElasticsearchBulkError> {
+    let payload: Value = serde_json::from_str(payload)
+        .map_err(|from| ElasticsearchBulkError::InvalidJson { index, from })?;
+    let mut payload = match payload {
+        Value::Object(payload) => payload,
+        _ => return Err(ElasticsearchBulkError::NotAnObject { index }),
+    };
+
+    let mut document = Map::new();
+    if let Some(time) = payload.remove(TIME_FIELD) {
+        let timestamp = time
+            .as_str()
+            .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+            .ok_or_else(|| ElasticsearchBulkError::InvalidTimestamp {
+                index,
+                value: time.clone(),
+            })?;
+        let timestamp = timestamp
+            .with_timezone(&Utc)
+            .to_rfc3339_opts(SecondsFormat::AutoSi, true);
+        document.insert(TIMESTAMP_FIELD.into(), Value::String(timestamp));
+    }
+    document.extend(payload);
+    Ok(document)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    fn lines(bulk: &str) -> Vec<Value> {
+        bulk.lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn each_payload_is_indexed_as_a_document() -> anyhow::Result<()> {
+        let converter = ThinEdgeToElasticsearchBulkConverter::new("tedge-measurements");
+        let payloads = vec![
+            r#"{"time":"2021-04-30T17:03:14.123+02:00","temperature":{"value":25.5,"unit":"°C"}}"#
+                .to_string(),
+            r#"{"_schema":"te/1.0","location":{"alti":2100.4,"longi":2200.4}}"#.to_string(),
+        ];
+
+        let bulk = converter.convert(&payloads)?;
+
+        assert!(bulk.ends_with('\n'));
+        assert_eq!(
+            lines(&bulk),
+            vec![
+                json!({"index": {"_index": "tedge-measurements"}}),
+                json!({
+                    "@timestamp": "2021-04-30T15:03:14.123Z",
+                    "temperature": {"value": 25.5, "unit": "°C"},
+                }),
+                json!({"index": {"_index": "tedge-measurements"}}),
+                json!({
+                    "_schema": "te/1.0",
+                    "location": {"alti": 2100.4, "longi": 2200.4},
+                }),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn no_payloads_make_an_empty_body() -> anyhow::Result<()> {
+        let converter = ThinEdgeToElasticsearchBulkConverter::new("measurements");
+        let payloads: [&str; 0] = [];
+
+        assert_eq!(converter.convert(&payloads)?, "");
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_payloads_are_reported_with_their_index() {
+        let converter = ThinEdgeToElasticsearchBulkConverter::new("measurements");
+
+        assert_matches!(
+            converter.convert(&[r#"{"temperature":25.5}"#, r#"{"temperature":"#]),
+            Err(ElasticsearchBulkError::InvalidJson { index: 1, .. })
+        );
+        assert_matches!(
+            converter.convert(&["[25.5]"]),
+            Err(ElasticsearchBulkError::NotAnObject { index: 0 })
+        );
+        assert_matches!(
+            converter.convert(&[r#"{"time":"yesterday","temperature":25.5}"#]),
+            Err(ElasticsearchBulkError::InvalidTimestamp { index: 0, .. })
+        );
+    }
+}
@@ -0,0 +1,221 @@
+/// The longest RFC 3339 timestamp, with nanoseconds, a time-zone offset and a 6-digit signed year
+const MAX_TIMESTAMP_LEN: usize = 38;
+
+/// The longest number written for a finite `f64`, as `-2.2250738585072014e-308`
+const MAX_F64_LEN: usize = 24;
+
+/// Estimate the size of a thin-edge JSON payload, without serializing it.
+///
+/// The estimate is an upper bound of the size of the payload written by `ThinEdgeJsonSerializer`,
+/// so it can be used to pre-allocate a buffer or to check a payload against a broker limit.
+/// The names are taken into account exactly, escaped characters included,
+/// while the timestamp and the values are counted with their longest possible representation.
+///
+/// The structure is given as a list of groups with their measurements,
+/// the measurements that are not attached to a group being given with an empty group name.
+///
+/// ```
+/// use thin_edge_json::estimate::ThinEdgeJsonSizeEstimator;
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let estimate = ThinEdgeJsonSizeEstimator::estimate(
+///     &[("", &[("temperature", 25.5)]), ("location", &[("alti", 2100.4)])],
+///     false,
+/// );
+///
+/// let mut serializer = ThinEdgeJsonSerializer::new();
+/// serializer.measurement("temperature", 25.5)?;
+/// serializer.start_group("location")?;
+/// serializer.measurement("alti", 2100.4)?;
+/// serializer.end_group()?;
+///
+/// assert!(estimate >= serializer.bytes()?.len());
+/// # Ok(()) }
+/// ```
+pub struct ThinEdgeJsonSizeEstimator;
+
+impl ThinEdgeJsonSizeEstimator {
+    pub fn estimate(groups: &[(&str, &[(&str, f64)])], has_timestamp: bool) -> usize {
+        // The enclosing braces
+        let mut size = 2;
+        let mut entries = 0;
+
+        if has_timestamp {
+            size += key_size("time") + MAX_TIMESTAMP_LEN + 2;
+            entries += 1;
+        }
+
+        for (group, measurements) in groups.iter() {
+            if group.is_empty() {
+                size += measurements_size(measurements);
+                entries += measurements.len();
+            } else {
+                size += key_size(group) + 2 + measurements_size(measurements);
+                size += separators(measurements.len());
+                entries += 1;
+            }
+        }
+
+        size + separators(entries)
+    }
+}
+
+/// The size of the measurements, not counting the separators between them
+fn measurements_size(measurements: &[(&str, f64)]) -> usize {
+    measurements
+        .iter()
+        .map(|(name, _)| key_size(name) + MAX_F64_LEN)
+        .sum()
+}
+
+fn separators(entries: usize) -> usize {
+    entries.saturating_sub(1)
+}
+
+/// The size of `"<key>":`, with the key escaped as done by `serde_json`
+fn key_size(key: &str) -> usize {
+    let escaped: usize = key
+        .bytes()
+        .map(|byte| match byte {
+            b'"' | b'\\' | b'\n' | b'\r' | b'\t' | 0x08 | 0x0c => 2,
+            0x00..=0x1f => 6,
+            _ => 1,
+        })
+        .sum();
+    escaped + 3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measurement::GroupedMeasurementVisitor;
+    use crate::serialize::ThinEdgeJsonSerializer;
+    use chrono::{DateTime, FixedOffset, TimeZone};
+    use proptest::prelude::*;
+
+    fn serialize(
+        groups: &[(&str, &[(&str, f64)])],
+        timestamp: Option<DateTime<FixedOffset>>,
+    ) -> anyhow::Result<String> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        if let Some(timestamp) = timestamp {
+            serializer.timestamp(timestamp)?;
+        }
+        for (group, measurements) in groups.iter() {
+            if !group.is_empty() {
+                serializer.start_group(group)?;
+            }
+            for (name, value) in measurements.iter() {
+                serializer.measurement(name, *value)?;
+            }
+            if !group.is_empty() {
+                serializer.end_group()?;
+            }
+        }
+        Ok(serializer.into_string()?)
+    }
+
+    #[test]
+    fn keys_are_counted_with_their_escaped_characters() {
+        assert_eq!(key_size("alti"), r#""alti":"#.len());
+        assert_eq!(
+            key_size("a\"b\\c\nd"),
+            serde_json::to_string("a\"b\\c\nd").unwrap().len() + 1
+        );
+        assert_eq!(
+            key_size("\u{1}°C"),
+            serde_json::to_string("\u{1}°C").unwrap().len() + 1
+        );
+    }
+
+    #[test]
+    fn the_estimate_is_exact_for_the_longest_values() -> anyhow::Result<()> {
+        let longest = -2.2250738585072014e-308;
+        let groups: &[(&str, &[(&str, f64)])] = &[
+            ("", &[("min", longest), ("max", longest)]),
+            ("location", &[("lo", longest)]),
+        ];
+        let timestamp =
+            FixedOffset::east(3600)
+                .ymd(-100_000, 4, 30)
+                .and_hms_nano(17, 3, 14, 123_456_789);
+
+        assert_eq!(
+            ThinEdgeJsonSizeEstimator::estimate(groups, true),
+            serialize(groups, Some(timestamp))?.len()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn empty_payloads() -> anyhow::Result<()> {
+        assert_eq!(ThinEdgeJsonSizeEstimator::estimate(&[], false), 2);
+        assert_eq!(
+            ThinEdgeJsonSizeEstimator::estimate(&[("", &[])], false),
+            serialize(&[], None)?.len()
+        );
+        Ok(())
+    }
+
+    fn names() -> impl Strategy<Value = Vec<String>> {
+        // Unique names, as duplicated keys are rejected
+        prop::collection::vec("\\PC{0,12}", 0..8).prop_map(|names| {
+            names
+                .into_iter()
+                .enumerate()
+                .map(|(i, name)| format!("m{}{}", i, name))
+                .collect()
+        })
+    }
+
+    fn borrowed(measurements: &[(String, f64)]) -> Vec<(&str, f64)> {
+        measurements
+            .iter()
+            .map(|(name, value)| (name.as_str(), *value))
+            .collect()
+    }
+
+    fn values() -> impl Strategy<Value = Vec<f64>> {
+        prop::collection::vec(
+            any::<f64>().prop_filter("A finite value", |value| value.is_finite()),
+            8,
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn the_estimate_is_an_upper_bound(
+            top_level in names(),
+            groups in prop::collection::vec(names(), 0..4),
+            values in values(),
+            with_timestamp in any::<bool>(),
+            secs in 0i64..4_102_444_800,
+        ) {
+            let measurements = |names: &[String]| -> Vec<(String, f64)> {
+                names.iter().cloned().zip(values.iter().cloned()).collect()
+            };
+            let top_level = measurements(&top_level);
+            let groups: Vec<(String, Vec<(String, f64)>)> = groups
+                .into_iter()
+                .enumerate()
+                .map(|(i, names)| (format!("g{}", i), measurements(&names)))
+                .collect();
+
+            let top_level = borrowed(&top_level);
+            let group_measurements: Vec<Vec<(&str, f64)>> =
+                groups.iter().map(|(_, measurements)| borrowed(measurements)).collect();
+            let mut structure: Vec<(&str, &[(&str, f64)])> = vec![("", top_level.as_slice())];
+            for ((group, _), measurements) in groups.iter().zip(group_measurements.iter()) {
+                structure.push((group.as_str(), measurements.as_slice()));
+            }
+
+            let timestamp = Some(FixedOffset::east(0).timestamp(secs, 0)).filter(|_| with_timestamp);
+            let output = serialize(&structure, timestamp).unwrap();
+            let estimate = ThinEdgeJsonSizeEstimator::estimate(&structure, with_timestamp);
+
+            prop_assert!(estimate >= output.len(), "{} < {}: {}", estimate, output.len(), output);
+        }
+    }
+}
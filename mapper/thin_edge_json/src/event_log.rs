@@ -0,0 +1,634 @@
+use crate::context::MeasurementContext;
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use crate::trace::VisitorCall;
+use chrono::offset::FixedOffset;
+use chrono::{DateTime, Utc};
+use clock::{Clock, Timestamp, WallClock};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_MAX_SEGMENT_SIZE: u64 = 16 * 1024 * 1024;
+const SEGMENT_PREFIX: &str = "events-";
+const SEGMENT_EXTENSION: &str = "log";
+
+#[derive(thiserror::Error, Debug)]
+pub enum EventLogError {
+    #[error("I/O error on the event log {path:?}: {from}")]
+    IoError { path: PathBuf, from: std::io::Error },
+
+    #[error("Invalid event in the event log {path:?}: {from}")]
+    InvalidEvent {
+        path: PathBuf,
+        from: serde_cbor::Error,
+    },
+
+    #[error("Failed to encode an event: {0}")]
+    EncodingError(#[source] serde_cbor::Error),
+
+    #[error("The event is too large to be logged: {size} bytes")]
+    EventTooLarge { size: usize },
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ReplayError<E: std::error::Error> {
+    #[error(transparent)]
+    LogError(#[from] EventLogError),
+
+    #[error("The event at offset {offset} has been rejected by the visitor: {error}")]
+    VisitorError { offset: u64, error: E },
+}
+
+/// An event of the log, i.e. a visitor call with its offset and the time it has been logged
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct LogEntry {
+    offset: u64,
+    logged_at: Timestamp,
+    call: VisitorCall,
+}
+
+/// An append-only log persisting all the calls received as a visitor,
+/// so these calls can be replayed later, from any offset.
+///
+/// Each call is given an offset, starting at 0 and increasing by one for each call.
+/// The calls are stored in segment files under a directory, `events-<first offset>.log`,
+/// a new segment being started when the current one exceeds the maximum segment size.
+/// Each call is stored as a frame: the length of the call, on 4 bytes big-endian,
+/// followed by the call encoded in CBOR along its offset and the time it has been logged.
+///
+/// A frame only partially written, e.g. when the device has been powered off,
+/// is discarded when the log is open again.
+///
+/// ```
+/// use thin_edge_json::event_log::MeasurementEventLog;
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// # let dir = tempfile::tempdir()?;
+/// let mut log = MeasurementEventLog::open(dir.path())?;
+/// log.measurement("temperature", 25.5)?;
+/// log.measurement("pressure", 98.0)?;
+///
+/// let mut serializer = ThinEdgeJsonSerializer::new();
+/// log.replay_from(1, &mut serializer)?;
+/// assert_eq!(serializer.into_string()?, r#"{"pressure":98.0}"#);
+/// # Ok(()) }
+/// ```
+pub struct MeasurementEventLog {
+    dir: PathBuf,
+    clock: Box<dyn Clock>,
+    max_segment_size: u64,
+    next_offset: u64,
+    current_segment: Option<Segment>,
+}
+
+/// The segment events are appended to
+struct Segment {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl MeasurementEventLog {
+    /// Open the log stored under the given directory, creating the directory if needed.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, EventLogError> {
+        Self::open_with_clock(dir, Box::new(WallClock))
+    }
+
+    pub fn open_with_clock(
+        dir: impl AsRef<Path>,
+        clock: Box<dyn Clock>,
+    ) -> Result<Self, EventLogError> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir).map_err(|from| io_error(&dir, from))?;
+
+        let mut log = Self {
+            dir,
+            clock,
+            max_segment_size: DEFAULT_MAX_SEGMENT_SIZE,
+            next_offset: 0,
+            current_segment: None,
+        };
+
+        if let Some((first_offset, path)) = log.segments()?.pop() {
+            let (entries, valid_size) = read_segment(&path)?;
+            let file = OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .map_err(|from| io_error(&path, from))?;
+            // Discard a frame partially written
+            file.set_len(valid_size)
+                .map_err(|from| io_error(&path, from))?;
+
+            log.next_offset = entries
+                .last()
+                .map_or(first_offset, |entry| entry.offset + 1);
+            log.current_segment = Some(Segment {
+                path,
+                file,
+                size: valid_size,
+            });
+        }
+        Ok(log)
+    }
+
+    /// Set the size in bytes above which a new segment file is started, 16 MiB by default.
+    pub fn with_max_segment_size(self, max_segment_size: u64) -> Self {
+        Self {
+            max_segment_size,
+            ..self
+        }
+    }
+
+    /// The offset that will be given to the next logged call
+    pub fn next_offset(&self) -> u64 {
+        self.next_offset
+    }
+
+    /// Append a call to the log, returning its offset.
+    pub fn append(&mut self, call: VisitorCall) -> Result<u64, EventLogError> {
+        let offset = self.next_offset;
+        let entry = LogEntry {
+            offset,
+            logged_at: self.clock.now(),
+            call,
+        };
+        let frame = encode_frame(&entry)?;
+
+        let segment = self.segment_for(frame.len() as u64)?;
+        segment
+            .file
+            .write_all(&frame)
+            .map_err(|from| io_error(&segment.path, from))?;
+        segment.size += frame.len() as u64;
+
+        self.next_offset += 1;
+        Ok(offset)
+    }
+
+    /// Make sure all the logged calls are written to disk.
+    pub fn sync(&self) -> Result<(), EventLogError> {
+        match self.current_segment.as_ref() {
+            Some(segment) => segment
+                .file
+                .sync_data()
+                .map_err(|from| io_error(&segment.path, from)),
+            None => Ok(()),
+        }
+    }
+
+    /// Call on the given visitor all the logged calls from the given offset, in order.
+    ///
+    /// Returns the offset following the last replayed call,
+    /// i.e. the offset to replay from to get only the calls logged after this replay.
+    /// The calls removed by a compaction are skipped.
+    pub fn replay_from<V>(&self, offset: u64, visitor: &mut V) -> Result<u64, ReplayError<V::Error>>
+    where
+        V: GroupedMeasurementVisitor,
+    {
+        let segments = self.segments()?;
+        let mut next_offset = offset;
+        for (i, (_, path)) in segments.iter().enumerate() {
+            let ends_before_offset = segments
+                .get(i + 1)
+                .map_or(false, |(next_first_offset, _)| *next_first_offset <= offset);
+            if ends_before_offset {
+                continue;
+            }
+
+            let (entries, _) = read_segment(path)?;
+            for entry in entries.into_iter().filter(|entry| entry.offset >= offset) {
+                entry
+                    .call
+                    .apply(visitor)
+                    .map_err(|error| ReplayError::VisitorError {
+                        offset: entry.offset,
+                        error,
+                    })?;
+                next_offset = entry.offset + 1;
+            }
+        }
+        Ok(next_offset)
+    }
+
+    /// Remove all the calls logged before the given time.
+    ///
+    /// The offsets of the remaining calls are left unchanged,
+    /// and the calls logged after the compaction are appended to a new segment.
+    pub fn compact(&mut self, keep_since: DateTime<Utc>) -> Result<(), EventLogError> {
+        self.current_segment = None;
+
+        for (_, path) in self.segments()? {
+            let (entries, _) = read_segment(&path)?;
+            let kept: Vec<&LogEntry> = entries
+                .iter()
+                .filter(|entry| entry.logged_at >= keep_since)
+                .collect();
+
+            if kept.len() == entries.len() {
+                // The calls are logged in order, so the following ones are kept too
+                break;
+            }
+
+            std::fs::remove_file(&path).map_err(|from| io_error(&path, from))?;
+            if let Some(first) = kept.first() {
+                let mut frames = Vec::new();
+                for entry in kept.iter() {
+                    frames.extend(encode_frame(entry)?);
+                }
+                let compacted = self.segment_path(first.offset);
+                std::fs::write(&compacted, frames).map_err(|from| io_error(&compacted, from))?;
+                break;
+            }
+        }
+
+        // Keep track of the next offset, even if all the segments have been removed
+        if self.segments()?.is_empty() {
+            self.new_segment()?;
+        }
+        Ok(())
+    }
+
+    /// The segment the next frame of the given size has to be appended to
+    fn segment_for(&mut self, frame_size: u64) -> Result<&mut Segment, EventLogError> {
+        let max_segment_size = self.max_segment_size;
+        let is_full =
+            |segment: &Segment| segment.size > 0 && segment.size + frame_size > max_segment_size;
+        if self.current_segment.as_ref().map_or(true, is_full) {
+            self.new_segment()?;
+        }
+        Ok(self
+            .current_segment
+            .as_mut()
+            .expect("A segment has just been open"))
+    }
+
+    fn new_segment(&mut self) -> Result<(), EventLogError> {
+        let path = self.segment_path(self.next_offset);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|from| io_error(&path, from))?;
+        let size = file.metadata().map_err(|from| io_error(&path, from))?.len();
+        self.current_segment = Some(Segment { path, file, size });
+        Ok(())
+    }
+
+    fn segment_path(&self, first_offset: u64) -> PathBuf {
+        self.dir.join(format!(
+            "{}{:020}.{}",
+            SEGMENT_PREFIX, first_offset, SEGMENT_EXTENSION
+        ))
+    }
+
+    /// The segment files, with their first offset, in order
+    fn segments(&self) -> Result<Vec<(u64, PathBuf)>, EventLogError> {
+        let mut segments = Vec::new();
+        for dir_entry in std::fs::read_dir(&self.dir).map_err(|from| io_error(&self.dir, from))? {
+            let path = dir_entry.map_err(|from| io_error(&self.dir, from))?.path();
+            if let Some(first_offset) = segment_first_offset(&path) {
+                segments.push((first_offset, path));
+            }
+        }
+        segments.sort();
+        Ok(segments)
+    }
+}
+
+fn segment_first_offset(path: &Path) -> Option<u64> {
+    if path.extension()?.to_str()? != SEGMENT_EXTENSION {
+        return None;
+    }
+    let stem = path.file_stem()?.to_str()?;
+    stem.strip_prefix(SEGMENT_PREFIX)?.parse().ok()
+}
+
+fn encode_frame(entry: &LogEntry) -> Result<Vec<u8>, EventLogError> {
+    let event = serde_cbor::to_vec(entry).map_err(EventLogError::EncodingError)?;
+    let len = u32::try_from(event.len())
+        .map_err(|_| EventLogError::EventTooLarge { size: event.len() })?;
+
+    let mut frame = Vec::with_capacity(4 + event.len());
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend(event);
+    Ok(frame)
+}
+
+/// Read all the entries of a segment,
+/// along the size of the segment up to the last frame that is complete.
+fn read_segment(path: &Path) -> Result<(Vec<LogEntry>, u64), EventLogError> {
+    let bytes = std::fs::read(path).map_err(|from| io_error(path, from))?;
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    while bytes.len() >= pos + 4 {
+        let mut len = [0; 4];
+        len.copy_from_slice(&bytes[pos..pos + 4]);
+        let end = pos + 4 + u32::from_be_bytes(len) as usize;
+        if end > bytes.len() {
+            break;
+        }
+
+        let entry = serde_cbor::from_slice(&bytes[pos + 4..end]).map_err(|from| {
+            EventLogError::InvalidEvent {
+                path: path.to_path_buf(),
+                from,
+            }
+        })?;
+        entries.push(entry);
+        pos = end;
+    }
+    Ok((entries, pos as u64))
+}
+
+fn io_error(path: &Path, from: std::io::Error) -> EventLogError {
+    EventLogError::IoError {
+        path: path.to_path_buf(),
+        from,
+    }
+}
+
+impl GroupedMeasurementVisitor for MeasurementEventLog {
+    type Error = EventLogError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.append(VisitorCall::Timestamp { value })?;
+        Ok(())
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.append(VisitorCall::Measurement {
+            name: name.into(),
+            value,
+        })?;
+        Ok(())
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.append(VisitorCall::StartGroup {
+            group: group.into(),
+        })?;
+        Ok(())
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.append(VisitorCall::EndGroup)?;
+        Ok(())
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.append(VisitorCall::MeasurementWithUnit {
+            name: name.into(),
+            value,
+            unit: unit.into(),
+        })?;
+        Ok(())
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.append(VisitorCall::StartGroupWithTimestamp {
+            group: group.into(),
+            value: timestamp,
+        })?;
+        Ok(())
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        self.append(VisitorCall::NullableMeasurement {
+            name: name.into(),
+            value,
+        })?;
+        Ok(())
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.append(VisitorCall::ComplexMeasurement {
+            name: name.into(),
+            real,
+            imaginary,
+        })?;
+        Ok(())
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.append(VisitorCall::AnnotatedMeasurement {
+            name: name.into(),
+            value,
+            quality,
+        })?;
+        Ok(())
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.append(VisitorCall::SetContext { context })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::MeasurementBuffer;
+    use crate::serialize::ThinEdgeJsonSerializer;
+    use chrono::TimeZone;
+    use clock::MockClock;
+
+    fn at(minutes: i64) -> Timestamp {
+        FixedOffset::east(0).ymd(2021, 4, 30).and_hms(17, 0, 0) + chrono::Duration::minutes(minutes)
+    }
+
+    /// A clock moving one minute forward each time it's read
+    fn ticking_clock() -> Box<dyn Clock> {
+        let mut clock = MockClock::new();
+        let mut minutes = 0;
+        clock.expect_now().returning(move || {
+            let now = at(minutes);
+            minutes += 1;
+            now
+        });
+        Box::new(clock)
+    }
+
+    fn measurements(count: usize) -> Vec<VisitorCall> {
+        (0..count)
+            .map(|i| VisitorCall::Measurement {
+                name: format!("m{}", i),
+                value: i as f64,
+            })
+            .collect()
+    }
+
+    fn replayed(log: &MeasurementEventLog, offset: u64) -> anyhow::Result<Vec<VisitorCall>> {
+        let mut buffer = MeasurementBuffer::new();
+        log.replay_from(offset, &mut buffer)?;
+        Ok(buffer.into_events())
+    }
+
+    fn segment_count(dir: &Path) -> anyhow::Result<usize> {
+        Ok(std::fs::read_dir(dir)?.count())
+    }
+
+    #[test]
+    fn all_kinds_of_calls_are_replayed() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut log = MeasurementEventLog::open(dir.path())?;
+
+        log.set_context(MeasurementContext::new([0x4b; 16], [0xf0; 8]))?;
+        log.timestamp(at(0))?;
+        log.measurement_with_unit("temperature", 25.5, "°C")?;
+        log.nullable_measurement("humidity", None)?;
+        log.start_group_with_timestamp("location", at(1))?;
+        log.measurement("alti", 2100.4)?;
+        log.complex_measurement("impedance", 3.0, 4.0)?;
+        log.end_group()?;
+
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        assert_eq!(log.replay_from(0, &mut serializer)?, 8);
+        assert_eq!(
+            serializer.into_string()?,
+            r#"{"time":"2021-04-30T17:00:00+00:00","_traceId":"4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b","_spanId":"f0f0f0f0f0f0f0f0","temperature":{"value":25.5,"unit":"°C"},"humidity":null,"location":{"time":"2021-04-30T17:01:00+00:00","alti":2100.4,"impedance":{"re":3.0,"im":4.0}}}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn replay_across_segment_files() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut log = MeasurementEventLog::open(dir.path())?.with_max_segment_size(100);
+        let calls = measurements(20);
+        for call in calls.iter() {
+            log.append(call.clone())?;
+        }
+
+        assert!(segment_count(dir.path())? > 2);
+        assert_eq!(replayed(&log, 0)?, calls);
+        assert_eq!(replayed(&log, 7)?, calls[7..].to_vec());
+        assert_eq!(replayed(&log, 19)?, calls[19..].to_vec());
+        assert_eq!(replayed(&log, 20)?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn offsets_go_on_when_the_log_is_reopen() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let calls = measurements(6);
+        {
+            let mut log = MeasurementEventLog::open(dir.path())?.with_max_segment_size(100);
+            for call in calls[..4].iter() {
+                log.append(call.clone())?;
+            }
+            log.sync()?;
+        }
+
+        let mut log = MeasurementEventLog::open(dir.path())?.with_max_segment_size(100);
+        assert_eq!(log.next_offset(), 4);
+        assert_eq!(log.append(calls[4].clone())?, 4);
+        assert_eq!(log.append(calls[5].clone())?, 5);
+        assert_eq!(replayed(&log, 3)?, calls[3..].to_vec());
+        Ok(())
+    }
+
+    #[test]
+    fn a_partially_written_frame_is_discarded() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let calls = measurements(3);
+        {
+            let mut log = MeasurementEventLog::open(dir.path())?;
+            for call in calls[..2].iter() {
+                log.append(call.clone())?;
+            }
+        }
+        let segment = std::fs::read_dir(dir.path())?.next().unwrap()?.path();
+        let mut file = OpenOptions::new().append(true).open(&segment)?;
+        file.write_all(&[0, 0, 0, 42, 1, 2])?;
+
+        let mut log = MeasurementEventLog::open(dir.path())?;
+        assert_eq!(log.append(calls[2].clone())?, 2);
+        assert_eq!(replayed(&log, 0)?, calls);
+        Ok(())
+    }
+
+    #[test]
+    fn compaction_removes_the_old_calls() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut log = MeasurementEventLog::open_with_clock(dir.path(), ticking_clock())?
+            .with_max_segment_size(100);
+        // The call at offset i is logged at minute i
+        let calls = measurements(12);
+        for call in calls.iter() {
+            log.append(call.clone())?;
+        }
+        let segments_before = segment_count(dir.path())?;
+
+        log.compact(at(5).with_timezone(&Utc))?;
+
+        assert!(segment_count(dir.path())? < segments_before);
+        assert_eq!(replayed(&log, 0)?, calls[5..].to_vec());
+        assert_eq!(replayed(&log, 8)?, calls[8..].to_vec());
+
+        // New calls are appended after the compacted ones
+        assert_eq!(log.append(calls[0].clone())?, 12);
+        let mut expected = calls[10..].to_vec();
+        expected.push(calls[0].clone());
+        assert_eq!(replayed(&log, 10)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn offsets_are_kept_when_all_the_calls_are_compacted() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let calls = measurements(3);
+        {
+            let mut log = MeasurementEventLog::open_with_clock(dir.path(), ticking_clock())?;
+            for call in calls.iter() {
+                log.append(call.clone())?;
+            }
+            log.compact(at(60).with_timezone(&Utc))?;
+            assert_eq!(replayed(&log, 0)?, vec![]);
+        }
+
+        let mut log = MeasurementEventLog::open(dir.path())?;
+        assert_eq!(log.next_offset(), 3);
+        assert_eq!(log.append(calls[0].clone())?, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn replay_stops_on_the_first_error_of_the_visitor() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut log = MeasurementEventLog::open(dir.path())?;
+        log.measurement("temperature", 25.5)?;
+        log.end_group()?;
+
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        let result = log.replay_from(0, &mut serializer);
+
+        assert!(matches!(
+            result,
+            Err(ReplayError::VisitorError { offset: 1, .. })
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+/// The key of the field giving the time from which a thin-edge JSON message is obsolete.
+pub const EXPIRES_AT_KEY: &str = "_expires_at";
+
+/// Check the `"_expires_at"` field of thin-edge JSON messages,
+/// so a consumer can drop the expired messages rather than processing or forwarding them.
+///
+/// A message is expired from its expiry time onwards.
+/// A message with no expiry time never expires,
+/// and so does a message whose expiry time is not an RFC 3339 time.
+///
+/// ```
+/// use std::time::Duration;
+/// use thin_edge_json::expiry::ThinEdgeJsonExpiryFilter;
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let mut serializer = ThinEdgeJsonSerializer::new().with_ttl(Duration::from_secs(60));
+/// serializer.measurement("temperature", 25.5)?;
+/// let payload = serializer.bytes()?;
+///
+/// assert!(!ThinEdgeJsonExpiryFilter::is_expired(&payload));
+/// assert!(!ThinEdgeJsonExpiryFilter::is_expired(br#"{"temperature":25.5}"#));
+/// assert!(ThinEdgeJsonExpiryFilter::is_expired(
+///     br#"{"_expires_at":"2021-04-30T15:03:14Z","temperature":25.5}"#
+/// ));
+/// # Ok(()) }
+/// ```
+pub struct ThinEdgeJsonExpiryFilter;
+
+impl ThinEdgeJsonExpiryFilter {
+    /// Check if a message is expired now
+    pub fn is_expired(payload: &[u8]) -> bool {
+        Self::is_expired_at(payload, Utc::now())
+    }
+
+    /// Check if a message is expired at the given time
+    pub fn is_expired_at(payload: &[u8], now: DateTime<Utc>) -> bool {
+        match Self::expires_at(payload) {
+            Some(expires_at) => now >= expires_at,
+            None => false,
+        }
+    }
+
+    /// The expiry time of a message, if any
+    pub fn expires_at(payload: &[u8]) -> Option<DateTime<Utc>> {
+        #[derive(Deserialize)]
+        struct Expiring {
+            #[serde(rename = "_expires_at")]
+            expires_at: Option<String>,
+        }
+
+        // Most messages have no expiry time: don't parse them twice, here and then by the mapper
+        if !contains(payload, EXPIRES_AT_KEY.as_bytes()) {
+            return None;
+        }
+
+        let expiring: Expiring = serde_json::from_slice(payload).ok()?;
+        let expires_at = DateTime::parse_from_rfc3339(&expiring.expires_at?).ok()?;
+        Some(expires_at.with_timezone(&Utc))
+    }
+}
+
+fn contains(payload: &[u8], key: &[u8]) -> bool {
+    payload.windows(key.len()).any(|window| window == key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    const PAYLOAD: &[u8] = br#"{"_expires_at":"2021-04-30T17:03:14+02:00","temperature":25.5}"#;
+
+    fn expiry_time() -> DateTime<Utc> {
+        Utc.ymd(2021, 4, 30).and_hms(15, 3, 14)
+    }
+
+    #[test]
+    fn a_message_expires_at_its_expiry_time() {
+        assert_eq!(
+            ThinEdgeJsonExpiryFilter::expires_at(PAYLOAD),
+            Some(expiry_time())
+        );
+
+        let before = expiry_time() - Duration::seconds(1);
+        let just_before = expiry_time() - Duration::milliseconds(1);
+        let after = expiry_time() + Duration::seconds(1);
+        assert!(!ThinEdgeJsonExpiryFilter::is_expired_at(PAYLOAD, before));
+        assert!(!ThinEdgeJsonExpiryFilter::is_expired_at(
+            PAYLOAD,
+            just_before
+        ));
+        assert!(ThinEdgeJsonExpiryFilter::is_expired_at(
+            PAYLOAD,
+            expiry_time()
+        ));
+        assert!(ThinEdgeJsonExpiryFilter::is_expired_at(PAYLOAD, after));
+    }
+
+    #[test]
+    fn messages_with_no_expiry_time_never_expire() {
+        let far_future = Utc.ymd(9999, 12, 31).and_hms(23, 59, 59);
+
+        for payload in [
+            &br#"{"temperature":25.5}"#[..],
+            &br#"{"_expires_at":"tomorrow","temperature":25.5}"#[..],
+            &br#"{"_expires_at":null}"#[..],
+            &br#"{"temperature":25.5,"expires_at":"2021-04-30T15:03:14Z"}"#[..],
+            &b"not a JSON payload"[..],
+        ]
+        .iter()
+        {
+            assert_eq!(ThinEdgeJsonExpiryFilter::expires_at(payload), None);
+            assert!(!ThinEdgeJsonExpiryFilter::is_expired_at(
+                payload, far_future
+            ));
+        }
+    }
+}
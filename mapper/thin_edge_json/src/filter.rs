@@ -0,0 +1,387 @@
+use crate::context::MeasurementContext;
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use regex::Regex;
+
+/// A visitor that only forwards the measurements with a name matching a glob pattern.
+///
+/// The pattern is matched against the measurement name,
+/// or against `<group>.<name>` for a measurement attached to a group.
+/// In a pattern, `*` matches any sequence of characters and `?` any single character.
+///
+/// A group is only forwarded if at least one of its measurements is.
+///
+/// Rather than with a glob pattern, the measurements can be selected by any predicate,
+/// using `FilteringVisitor::with_predicate()`.
+///
+/// ```
+/// use thin_edge_json::filter::FilteringVisitor;
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let mut visitor = FilteringVisitor::new("temp_*", ThinEdgeJsonSerializer::new());
+///
+/// visitor.measurement("temp_engine", 80.0)?;
+/// visitor.measurement("pressure", 98.0)?;
+///
+/// assert_eq!(visitor.into_inner().into_string()?, r#"{"temp_engine":80.0}"#);
+/// # Ok(()) }
+/// ```
+pub struct FilteringVisitor<V, P = GlobPattern> {
+    predicate: P,
+    group: PendingGroup,
+    inner: V,
+}
+
+/// Tell if a measurement, given as `<name>` or as `<group>.<name>`, has to be forwarded
+pub trait MeasurementPredicate {
+    fn accept(&self, key: &str) -> bool;
+}
+
+impl<F> MeasurementPredicate for F
+where
+    F: Fn(&str) -> bool,
+{
+    fn accept(&self, key: &str) -> bool {
+        self(key)
+    }
+}
+
+/// A glob pattern, where `*` matches any sequence of characters and `?` any single character
+#[derive(Debug, Clone)]
+pub struct GlobPattern {
+    regex: Regex,
+}
+
+impl GlobPattern {
+    pub fn new(pattern: &str) -> Self {
+        let regex = Regex::new(&glob_to_regex(pattern))
+            .expect("A glob pattern is translated into a valid regex");
+        Self { regex }
+    }
+}
+
+impl MeasurementPredicate for GlobPattern {
+    fn accept(&self, key: &str) -> bool {
+        self.regex.is_match(key)
+    }
+}
+
+impl<V> FilteringVisitor<V, GlobPattern> {
+    pub fn new(pattern: &str, inner: V) -> Self {
+        Self::with_predicate(GlobPattern::new(pattern), inner)
+    }
+}
+
+impl<V, P> FilteringVisitor<V, P> {
+    pub fn with_predicate(predicate: P, inner: V) -> Self {
+        Self {
+            predicate,
+            group: PendingGroup::default(),
+            inner,
+        }
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut V {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> V {
+        self.inner
+    }
+
+    fn accept(&self, name: &str) -> bool
+    where
+        P: MeasurementPredicate,
+    {
+        self.predicate.accept(&self.group.key(name))
+    }
+}
+
+impl<V, P> GroupedMeasurementVisitor for FilteringVisitor<V, P>
+where
+    V: GroupedMeasurementVisitor,
+    P: MeasurementPredicate,
+{
+    type Error = V::Error;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.inner.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        if self.accept(name) {
+            self.group.forward_start(&mut self.inner)?;
+            self.inner.measurement(name, value)?;
+        }
+        Ok(())
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.group.start(group, &mut self.inner)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.group.end(&mut self.inner)
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        if self.accept(name) {
+            self.group.forward_start(&mut self.inner)?;
+            self.inner.measurement_with_unit(name, value, unit)?;
+        }
+        Ok(())
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.group
+            .start_with_timestamp(group, timestamp, &mut self.inner)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        if self.accept(name) {
+            self.group.forward_start(&mut self.inner)?;
+            self.inner.nullable_measurement(name, value)?;
+        }
+        Ok(())
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        if self.accept(name) {
+            self.group.forward_start(&mut self.inner)?;
+            self.inner.complex_measurement(name, real, imaginary)?;
+        }
+        Ok(())
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        if self.accept(name) {
+            self.group.forward_start(&mut self.inner)?;
+            self.inner.annotated_measurement(name, value, quality)?;
+        }
+        Ok(())
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.inner.set_context(context)
+    }
+}
+
+/// A group which start is only forwarded along its first forwarded measurement,
+/// for the visitors that might drop all the measurements of a group.
+#[derive(Debug, Default)]
+pub(crate) struct PendingGroup {
+    name: Option<String>,
+    timestamp: Option<DateTime<FixedOffset>>,
+    forwarded: bool,
+}
+
+impl PendingGroup {
+    /// The name of a measurement, prefixed by the current group if any
+    pub(crate) fn key(&self, name: &str) -> String {
+        match self.name.as_ref() {
+            Some(group) => format!("{}.{}", group, name),
+            None => name.to_string(),
+        }
+    }
+
+    /// The timestamp of the current group, if any
+    pub(crate) fn timestamp(&self) -> Option<DateTime<FixedOffset>> {
+        self.name.as_ref().and(self.timestamp)
+    }
+
+    pub(crate) fn start<V>(&mut self, group: &str, inner: &mut V) -> Result<(), V::Error>
+    where
+        V: GroupedMeasurementVisitor,
+    {
+        if self.name.is_some() {
+            // Let the inner visitor report the error
+            return inner.start_group(group);
+        }
+        self.name = Some(group.to_string());
+        self.timestamp = None;
+        self.forwarded = false;
+        Ok(())
+    }
+
+    pub(crate) fn start_with_timestamp<V>(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+        inner: &mut V,
+    ) -> Result<(), V::Error>
+    where
+        V: GroupedMeasurementVisitor,
+    {
+        if self.name.is_some() {
+            // Let the inner visitor report the error
+            return inner.start_group_with_timestamp(group, timestamp);
+        }
+        self.name = Some(group.to_string());
+        self.timestamp = Some(timestamp);
+        self.forwarded = false;
+        Ok(())
+    }
+
+    pub(crate) fn forward_start<V>(&mut self, inner: &mut V) -> Result<(), V::Error>
+    where
+        V: GroupedMeasurementVisitor,
+    {
+        if let Some(group) = self.name.as_ref() {
+            if !self.forwarded {
+                match self.timestamp {
+                    Some(timestamp) => inner.start_group_with_timestamp(group, timestamp)?,
+                    None => inner.start_group(group)?,
+                }
+                self.forwarded = true;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn end<V>(&mut self, inner: &mut V) -> Result<(), V::Error>
+    where
+        V: GroupedMeasurementVisitor,
+    {
+        match self.name.take() {
+            Some(_) if !self.forwarded => Ok(()),
+            _ => {
+                self.forwarded = false;
+                inner.end_group()
+            }
+        }
+    }
+}
+
+fn glob_to_regex(pattern: &str) -> String {
+    let mut regex = String::with_capacity(pattern.len() + 2);
+    regex.push('^');
+    for c in pattern.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::ThinEdgeJsonSerializer;
+    use chrono::TimeZone;
+
+    #[test]
+    fn glob_patterns() {
+        let visitor = FilteringVisitor::new("temp_?.*", ());
+
+        assert!(visitor.accept("temp_1.max"));
+        assert!(visitor.accept("temp_1."));
+        assert!(!visitor.accept("temp_12.max"));
+        assert!(!visitor.accept("temp_1"));
+        assert!(!visitor.accept("xtemp_1.max"));
+    }
+
+    #[test]
+    fn measurements_can_be_selected_by_a_predicate() -> anyhow::Result<()> {
+        let mut visitor = FilteringVisitor::with_predicate(
+            |key: &str| !key.ends_with("_raw"),
+            ThinEdgeJsonSerializer::new(),
+        );
+
+        visitor.measurement("temperature", 25.5)?;
+        visitor.measurement("temperature_raw", 2551.0)?;
+        visitor.start_group("engine")?;
+        visitor.measurement("speed_raw", 3001.0)?;
+        visitor.end_group()?;
+
+        assert_eq!(
+            visitor.into_inner().into_string()?,
+            r#"{"temperature":25.5}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn groups_without_matching_measurements_are_dropped() -> anyhow::Result<()> {
+        let mut visitor = FilteringVisitor::new("*temp*", ThinEdgeJsonSerializer::new());
+
+        visitor.measurement("temperature", 25.5)?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.end_group()?;
+        visitor.start_group("engine")?;
+        visitor.measurement("temp", 80.0)?;
+        visitor.measurement("speed", 3000.0)?;
+        visitor.end_group()?;
+
+        let expected_output = r#"{"temperature":25.5,"engine":{"temp":80.0}}"#;
+        assert_eq!(visitor.into_inner().into_string()?, expected_output);
+        Ok(())
+    }
+
+    #[test]
+    fn grouped_measurements_are_matched_with_their_group() -> anyhow::Result<()> {
+        let mut visitor = FilteringVisitor::new("location.*", ThinEdgeJsonSerializer::new());
+
+        visitor.measurement("alti", 10.0)?;
+        visitor.start_group("location")?;
+        visitor.measurement_with_unit("alti", 2100.4, "m")?;
+        visitor.end_group()?;
+
+        let expected_output = r#"{"location":{"alti":{"value":2100.4,"unit":"m"}}}"#;
+        assert_eq!(visitor.into_inner().into_string()?, expected_output);
+        Ok(())
+    }
+
+    #[test]
+    fn unbalanced_groups_are_reported_by_the_inner_visitor() {
+        let mut visitor = FilteringVisitor::new("*", ThinEdgeJsonSerializer::new());
+
+        assert!(visitor.end_group().is_err());
+    }
+
+    #[test]
+    fn group_timestamps_are_forwarded_along_the_group() -> anyhow::Result<()> {
+        let mut visitor = FilteringVisitor::new("*.temp", ThinEdgeJsonSerializer::new());
+        let timestamp = FixedOffset::east(0).ymd(2021, 4, 30).and_hms(17, 3, 14);
+
+        visitor.start_group_with_timestamp("location", timestamp)?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.end_group()?;
+        visitor.start_group_with_timestamp("engine", timestamp)?;
+        visitor.measurement("temp", 80.0)?;
+        visitor.end_group()?;
+
+        let expected_output = r#"{"engine":{"time":"2021-04-30T17:03:14+00:00","temp":80.0}}"#;
+        assert_eq!(visitor.into_inner().into_string()?, expected_output);
+        Ok(())
+    }
+}
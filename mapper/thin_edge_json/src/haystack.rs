@@ -0,0 +1,444 @@
+use crate::json::{parse_str, ThinEdgeJsonParserError};
+use crate::measurement::GroupedMeasurementVisitor;
+use chrono::offset::FixedOffset;
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::collections::HashMap;
+
+const ZINC_VERSION: &str = "3.0";
+const TIMESTAMP_COLUMN: &str = "ts";
+const GROUP_COLUMN: &str = "group";
+
+/// Convert thin-edge JSON measurements into a [Project Haystack](https://project-haystack.org) grid,
+/// encoded using the [ZINC](https://project-haystack.org/doc/Zinc) format.
+///
+/// The measurements which are not attached to a group are gathered in a first record,
+/// followed by one record per group.
+/// The measurement names are used as column names and must be valid Haystack tag names,
+/// i.e. start with a lower case ASCII letter followed by ASCII letters, digits or underscores.
+/// The values are encoded as Haystack numbers, along with their units if any.
+///
+/// When the thin-edge JSON input has a timestamp, this timestamp is given, in UTC,
+/// by a `ts` column; and when the input has groups, the group of each record
+/// is given by a `group` column.
+///
+/// ```
+/// use thin_edge_json::haystack::ThinEdgeToHaystackConverter;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let input = r#"{
+///     "time": "2021-04-30T17:03:14+02:00",
+///     "temperature": 25.5,
+///     "location": {"alti": 2100.4}
+/// }"#;
+///
+/// let zinc = ThinEdgeToHaystackConverter::convert(input)?;
+///
+/// assert_eq!(
+///     zinc,
+///     "ver:\"3.0\"\n\
+///      ts,group,temperature,alti\n\
+///      2021-04-30T15:03:14Z UTC,,25.5,\n\
+///      2021-04-30T15:03:14Z UTC,\"location\",,2100.4\n"
+/// );
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Default)]
+pub struct ThinEdgeToHaystackConverter {
+    timestamp: Option<DateTime<FixedOffset>>,
+    columns: Vec<String>,
+    top_level: HaystackRecord,
+    groups: Vec<HaystackRecord>,
+    within_group: bool,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum HaystackError {
+    #[error("Invalid Haystack tag name: {name:?} must start with a lower case letter followed by letters, digits or underscores")]
+    InvalidTagName { name: String },
+
+    #[error("Invalid Haystack column name: {name:?} is a reserved word.")]
+    ReservedColumnName { name: String },
+
+    #[error("Invalid Haystack unit: {unit:?}")]
+    InvalidUnit { unit: String },
+
+    #[error("Duplicated measurement: {name}")]
+    DuplicatedMeasurement { name: String },
+
+    #[error("Unexpected end of group")]
+    UnexpectedEndOfGroup,
+
+    #[error("Unexpected start of group")]
+    UnexpectedStartOfGroup,
+
+    #[error("Unexpected time stamp within a group")]
+    UnexpectedTimestamp,
+}
+
+#[derive(Debug, Default)]
+struct HaystackRecord {
+    group: Option<String>,
+    cells: HashMap<String, HaystackNumber>,
+}
+
+#[derive(Debug)]
+struct HaystackNumber {
+    value: f64,
+    unit: Option<String>,
+}
+
+impl ThinEdgeToHaystackConverter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Convert a thin-edge JSON string into a ZINC grid
+    pub fn convert(thin_edge_json: &str) -> Result<String, ThinEdgeJsonParserError<HaystackError>> {
+        let mut converter = ThinEdgeToHaystackConverter::new();
+        let () = parse_str(thin_edge_json, &mut converter)?;
+        converter
+            .into_zinc()
+            .map_err(ThinEdgeJsonParserError::VisitorError)
+    }
+
+    /// Encode the measurements collected so far as a ZINC grid
+    pub fn into_zinc(self) -> Result<String, HaystackError> {
+        if self.within_group {
+            return Err(HaystackError::UnexpectedStartOfGroup);
+        }
+
+        let timestamp = self.timestamp.map(zinc_date_time);
+        let with_groups = !self.groups.is_empty();
+
+        let mut columns = Vec::new();
+        if timestamp.is_some() {
+            columns.push(TIMESTAMP_COLUMN.to_string());
+        }
+        if with_groups {
+            columns.push(GROUP_COLUMN.to_string());
+        }
+        columns.extend(self.columns.iter().cloned());
+
+        let mut zinc = format!("ver:{}\n", zinc_str(ZINC_VERSION));
+        zinc.push_str(&columns.join(","));
+        zinc.push('\n');
+
+        let top_level = Some(&self.top_level).filter(|record| !record.cells.is_empty());
+        for record in top_level.into_iter().chain(self.groups.iter()) {
+            let mut cells = Vec::new();
+            if let Some(timestamp) = timestamp.as_ref() {
+                cells.push(timestamp.clone());
+            }
+            if with_groups {
+                cells.push(record.group.as_deref().map(zinc_str).unwrap_or_default());
+            }
+            for column in self.columns.iter() {
+                cells.push(
+                    record
+                        .cells
+                        .get(column)
+                        .map(HaystackNumber::to_zinc)
+                        .unwrap_or_default(),
+                );
+            }
+            zinc.push_str(&cells.join(","));
+            zinc.push('\n');
+        }
+
+        Ok(zinc)
+    }
+
+    fn add_cell(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: Option<&str>,
+    ) -> Result<(), HaystackError> {
+        check_tag_name(name)?;
+        if name == TIMESTAMP_COLUMN || name == GROUP_COLUMN {
+            return Err(HaystackError::ReservedColumnName { name: name.into() });
+        }
+        if let Some(unit) = unit {
+            check_unit(unit)?;
+        }
+
+        let record = if self.within_group {
+            self.groups
+                .last_mut()
+                .expect("A group record is pushed on start of group")
+        } else {
+            &mut self.top_level
+        };
+
+        if record.cells.contains_key(name) {
+            let name = match record.group.as_ref() {
+                Some(group) => format!("{}.{}", group, name),
+                None => name.to_string(),
+            };
+            return Err(HaystackError::DuplicatedMeasurement { name });
+        }
+        record.cells.insert(
+            name.to_string(),
+            HaystackNumber {
+                value,
+                unit: unit.map(String::from),
+            },
+        );
+
+        if !self.columns.iter().any(|column| column == name) {
+            self.columns.push(name.to_string());
+        }
+        Ok(())
+    }
+}
+
+impl GroupedMeasurementVisitor for ThinEdgeToHaystackConverter {
+    type Error = HaystackError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        if self.within_group {
+            return Err(HaystackError::UnexpectedTimestamp);
+        }
+        self.timestamp = Some(value);
+        Ok(())
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.add_cell(name, value, None)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        if self.within_group {
+            return Err(HaystackError::UnexpectedStartOfGroup);
+        }
+        self.within_group = true;
+        self.groups.push(HaystackRecord {
+            group: Some(group.to_string()),
+            cells: HashMap::new(),
+        });
+        Ok(())
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        if !self.within_group {
+            return Err(HaystackError::UnexpectedEndOfGroup);
+        }
+        self.within_group = false;
+        Ok(())
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.add_cell(name, value, Some(unit))
+    }
+}
+
+impl HaystackNumber {
+    fn to_zinc(&self) -> String {
+        let value = if self.value.is_nan() {
+            "NaN".to_string()
+        } else if self.value == f64::INFINITY {
+            "INF".to_string()
+        } else if self.value == f64::NEG_INFINITY {
+            "-INF".to_string()
+        } else {
+            self.value.to_string()
+        };
+
+        match self.unit.as_ref() {
+            Some(unit) if self.value.is_finite() => format!("{}{}", value, unit),
+            _ => value,
+        }
+    }
+}
+
+fn check_tag_name(name: &str) -> Result<(), HaystackError> {
+    let mut chars = name.chars();
+    let valid = chars.next().map_or(false, |c| c.is_ascii_lowercase())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(HaystackError::InvalidTagName { name: name.into() })
+    }
+}
+
+fn check_unit(unit: &str) -> Result<(), HaystackError> {
+    let valid = !unit.is_empty()
+        && unit
+            .chars()
+            .all(|c| c.is_ascii_alphabetic() || "%_/$".contains(c) || c as u32 > 127);
+    if valid {
+        Ok(())
+    } else {
+        Err(HaystackError::InvalidUnit { unit: unit.into() })
+    }
+}
+
+fn zinc_str(value: &str) -> String {
+    let mut zinc = String::with_capacity(value.len() + 2);
+    zinc.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => zinc.push_str("\\\""),
+            '\\' => zinc.push_str("\\\\"),
+            '$' => zinc.push_str("\\$"),
+            '\n' => zinc.push_str("\\n"),
+            '\r' => zinc.push_str("\\r"),
+            '\t' => zinc.push_str("\\t"),
+            c if (c as u32) < 0x20 => zinc.push_str(&format!("\\u{:04x}", c as u32)),
+            c => zinc.push(c),
+        }
+    }
+    zinc.push('"');
+    zinc
+}
+
+fn zinc_date_time(timestamp: DateTime<FixedOffset>) -> String {
+    let utc = timestamp.with_timezone(&Utc);
+    format!("{} UTC", utc.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use regex::Regex;
+
+    const ZINC_TAG: &str = r"[a-z][a-zA-Z0-9_]*";
+    const ZINC_CELL: &str = r#"(?:N|INF|-INF|NaN|"(?:[^"\\$\x00-\x1f]|\\[bfnrt"\\$]|\\u[0-9a-fA-F]{4})*"|-?\d+(?:\.\d+)?(?:[eE][+-]?\d+)?(?:[a-zA-Z%_/$]|[^\x00-\x7f])*|\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}(?:\.\d+)?(?:Z|[+-]\d{2}:\d{2}) [a-zA-Z0-9_+-]+)?"#;
+
+    /// Check a grid against the subset of the ZINC grammar used by the converter:
+    /// no grid nor column meta data, and scalar cells only.
+    fn assert_valid_zinc_grid(zinc: &str) {
+        let lines: Vec<&str> = zinc.lines().collect();
+        assert!(zinc.ends_with('\n'), "missing final new line");
+        assert_eq!(lines[0], r#"ver:"3.0""#);
+
+        let cols = Regex::new(&format!("^{tag}(?:,{tag})*$", tag = ZINC_TAG)).unwrap();
+        assert!(cols.is_match(lines[1]), "invalid columns: {}", lines[1]);
+
+        let col_count = lines[1].split(',').count();
+        let row = Regex::new(&format!(
+            "^{cell}(?:,{cell}){{{n}}}$",
+            cell = ZINC_CELL,
+            n = col_count - 1
+        ))
+        .unwrap();
+        for line in lines[2..].iter() {
+            assert!(row.is_match(line), "invalid row: {}", line);
+        }
+    }
+
+    #[test]
+    fn convert_measurements_and_groups() -> anyhow::Result<()> {
+        let input = r#"{
+            "time": "2021-04-30T17:03:14.123+02:00",
+            "temperature": 25.5,
+            "pressure": 98,
+            "location": {"alti": 2100.4, "longi": -2200.4},
+            "wind": {"speed": 12, "alti": 10}
+        }"#;
+
+        let zinc = ThinEdgeToHaystackConverter::convert(input)?;
+
+        assert_valid_zinc_grid(&zinc);
+        assert_eq!(
+            zinc,
+            r#"ver:"3.0"
+ts,group,temperature,pressure,alti,longi,speed
+2021-04-30T15:03:14.123Z UTC,,25.5,98,,,
+2021-04-30T15:03:14.123Z UTC,"location",,,2100.4,-2200.4,
+2021-04-30T15:03:14.123Z UTC,"wind",,,10,,12
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn convert_without_timestamp_nor_groups() -> anyhow::Result<()> {
+        let zinc = ThinEdgeToHaystackConverter::convert(r#"{"temperature": 25.5}"#)?;
+
+        assert_valid_zinc_grid(&zinc);
+        assert_eq!(zinc, "ver:\"3.0\"\ntemperature\n25.5\n");
+        Ok(())
+    }
+
+    #[test]
+    fn numbers_have_units() -> anyhow::Result<()> {
+        let mut converter = ThinEdgeToHaystackConverter::new();
+        converter.measurement_with_unit("temperature", 25.5, "°C")?;
+        converter.measurement_with_unit("speed", 12.0, "m/s")?;
+        converter.measurement_with_unit("humidity", 40.0, "%")?;
+
+        let zinc = converter.into_zinc()?;
+
+        assert_valid_zinc_grid(&zinc);
+        assert_eq!(
+            zinc,
+            "ver:\"3.0\"\ntemperature,speed,humidity\n25.5°C,12m/s,40%\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn group_names_are_escaped() -> anyhow::Result<()> {
+        let mut converter = ThinEdgeToHaystackConverter::new();
+        converter.start_group("a \"quoted\", $priced\\group")?;
+        converter.measurement("value", 1.0)?;
+        converter.end_group()?;
+
+        let zinc = converter.into_zinc()?;
+
+        assert_valid_zinc_grid(&zinc);
+        assert!(zinc.contains(r#""a \"quoted\", \$priced\\group""#));
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_tag_names() {
+        let mut converter = ThinEdgeToHaystackConverter::new();
+
+        assert_matches!(
+            converter.measurement("Temperature", 25.5),
+            Err(HaystackError::InvalidTagName { .. })
+        );
+        assert_matches!(
+            converter.measurement("2nd_sensor", 25.5),
+            Err(HaystackError::InvalidTagName { .. })
+        );
+        assert_matches!(
+            converter.measurement("air-quality", 25.5),
+            Err(HaystackError::InvalidTagName { .. })
+        );
+        assert_matches!(
+            converter.measurement("ts", 25.5),
+            Err(HaystackError::ReservedColumnName { .. })
+        );
+    }
+
+    #[test]
+    fn reject_invalid_units() {
+        let mut converter = ThinEdgeToHaystackConverter::new();
+
+        assert_matches!(
+            converter.measurement_with_unit("speed", 12.0, "km h"),
+            Err(HaystackError::InvalidUnit { .. })
+        );
+    }
+
+    #[test]
+    fn reject_duplicated_measurements() {
+        let mut converter = ThinEdgeToHaystackConverter::new();
+        converter.measurement("temperature", 25.5).unwrap();
+
+        assert_matches!(
+            converter.measurement("temperature", 25.5),
+            Err(HaystackError::DuplicatedMeasurement { .. })
+        );
+    }
+}
@@ -0,0 +1,379 @@
+use crate::context::MeasurementContext;
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use std::collections::{BTreeMap, HashMap};
+
+/// The counts of the values of a measurement falling into each bucket.
+///
+/// Given the boundaries `b0 < b1 < ... < bn`, the buckets are
+/// `[-inf, b0)`, `[b0, b1)`, ..., `[bn, inf]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    boundaries: Vec<f64>,
+    counts: Vec<u64>,
+}
+
+impl Histogram {
+    /// Create an empty histogram, ignoring the boundaries that are not finite.
+    pub fn new(boundaries: &[f64]) -> Self {
+        let mut boundaries: Vec<f64> = boundaries
+            .iter()
+            .cloned()
+            .filter(|boundary| boundary.is_finite())
+            .collect();
+        boundaries.sort_by(|a, b| a.partial_cmp(b).expect("Finite values are ordered"));
+        boundaries.dedup();
+        let counts = vec![0; boundaries.len() + 1];
+        Self { boundaries, counts }
+    }
+
+    /// Count a value into its bucket, a NaN being ignored.
+    pub fn record(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        let index = self
+            .boundaries
+            .iter()
+            .take_while(|boundary| **boundary <= value)
+            .count();
+        self.counts[index] += 1;
+    }
+
+    /// The total number of recorded values
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The `(lower, upper, count)` of each bucket, in increasing order
+    pub fn buckets(&self) -> impl Iterator<Item = (f64, f64, u64)> + '_ {
+        let n = self.boundaries.len();
+        self.counts.iter().enumerate().map(move |(i, count)| {
+            let lower = if i == 0 {
+                f64::NEG_INFINITY
+            } else {
+                self.boundaries[i - 1]
+            };
+            let upper = if i == n {
+                f64::INFINITY
+            } else {
+                self.boundaries[i]
+            };
+            (lower, upper, *count)
+        })
+    }
+}
+
+/// A visitor that counts the measurements into histograms, rather than forwarding them.
+///
+/// Each measurement has its own histogram, the measurements of a group being counted apart
+/// from the top-level measurements with the same name.
+/// The bucket boundaries are the same for all the measurements,
+/// unless set specifically for a measurement with `with_boundaries()`.
+///
+/// On `flush()`, the histograms of the measurements received since the previous flush
+/// are forwarded to the inner visitor, one measurement `<name>_bucket_<lower>_<upper>` per bucket,
+/// the bucket counts of the grouped measurements being forwarded within their group.
+/// The last timestamp received since the previous flush, if any, is forwarded first.
+///
+/// ```
+/// use thin_edge_json::histogram::HistogramVisitor;
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let mut visitor = HistogramVisitor::new(&[0.0, 10.0], ThinEdgeJsonSerializer::new());
+///
+/// visitor.measurement("temperature", 5.0)?;
+/// visitor.measurement("temperature", 12.5)?;
+/// visitor.measurement("temperature", 7.0)?;
+/// visitor.flush()?;
+///
+/// assert_eq!(
+///     visitor.into_inner().into_string()?,
+///     r#"{"temperature_bucket_-inf_0":0.0,"temperature_bucket_0_10":2.0,"temperature_bucket_10_inf":1.0}"#
+/// );
+/// # Ok(()) }
+/// ```
+pub struct HistogramVisitor<V> {
+    boundaries: Vec<f64>,
+    specific_boundaries: HashMap<String, Vec<f64>>,
+    histograms: BTreeMap<(Option<String>, String), Histogram>,
+    timestamp: Option<DateTime<FixedOffset>>,
+    context: Option<MeasurementContext>,
+    group: Option<String>,
+    inner: V,
+}
+
+impl<V> HistogramVisitor<V> {
+    pub fn new(boundaries: &[f64], inner: V) -> Self {
+        Self {
+            boundaries: boundaries.to_vec(),
+            specific_boundaries: HashMap::new(),
+            histograms: BTreeMap::new(),
+            timestamp: None,
+            context: None,
+            group: None,
+            inner,
+        }
+    }
+
+    /// Use specific bucket boundaries for a measurement,
+    /// given as `<name>` or as `<group>.<name>` for a grouped measurement.
+    pub fn with_boundaries(mut self, measurement: &str, boundaries: &[f64]) -> Self {
+        self.specific_boundaries
+            .insert(measurement.to_string(), boundaries.to_vec());
+        self
+    }
+
+    /// The histogram of a measurement since the previous flush
+    pub fn histogram(&self, group: Option<&str>, name: &str) -> Option<&Histogram> {
+        self.histograms
+            .get(&(group.map(String::from), name.to_string()))
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut V {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> V {
+        self.inner
+    }
+
+    fn record(&mut self, name: &str, value: f64) {
+        let key = (self.group.clone(), name.to_string());
+        let boundaries = self
+            .specific_boundaries
+            .get(&measurement_key(self.group.as_deref(), name))
+            .unwrap_or(&self.boundaries);
+        self.histograms
+            .entry(key)
+            .or_insert_with(|| Histogram::new(boundaries))
+            .record(value);
+    }
+}
+
+impl<V> HistogramVisitor<V>
+where
+    V: GroupedMeasurementVisitor,
+{
+    /// Forward the histograms of the measurements received since the previous flush,
+    /// then reset them.
+    ///
+    /// Nothing is forwarded if no measurements have been received.
+    pub fn flush(&mut self) -> Result<(), V::Error> {
+        let histograms = std::mem::take(&mut self.histograms);
+        if let Some(context) = self.context.take() {
+            self.inner.set_context(context)?;
+        }
+        if let Some(timestamp) = self.timestamp.take() {
+            self.inner.timestamp(timestamp)?;
+        }
+
+        let mut current_group: Option<String> = None;
+        for ((group, name), histogram) in histograms {
+            if group != current_group {
+                if current_group.is_some() {
+                    self.inner.end_group()?;
+                }
+                if let Some(group) = group.as_deref() {
+                    self.inner.start_group(group)?;
+                }
+                current_group = group;
+            }
+            for (lower, upper, count) in histogram.buckets() {
+                let bucket = format!("{}_bucket_{}_{}", name, lower, upper);
+                self.inner.measurement(&bucket, count as f64)?;
+            }
+        }
+        if current_group.is_some() {
+            self.inner.end_group()?;
+        }
+        Ok(())
+    }
+}
+
+impl<V> GroupedMeasurementVisitor for HistogramVisitor<V>
+where
+    V: GroupedMeasurementVisitor,
+{
+    type Error = V::Error;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.timestamp = Some(value);
+        Ok(())
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.record(name, value);
+        Ok(())
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.group = Some(group.to_string());
+        Ok(())
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.timestamp = Some(timestamp);
+        self.start_group(group)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        if let Some(value) = value {
+            self.record(name, value);
+        }
+        Ok(())
+    }
+
+    /// The complex measurements are not counted
+    fn complex_measurement(
+        &mut self,
+        _name: &str,
+        _real: f64,
+        _imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        _quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.record(name, value);
+        Ok(())
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.group = None;
+        Ok(())
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        _unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.record(name, value);
+        Ok(())
+    }
+
+    /// The latest context is forwarded along the histograms
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.context = Some(context);
+        Ok(())
+    }
+}
+
+fn measurement_key(group: Option<&str>, name: &str) -> String {
+    match group {
+        Some(group) => format!("{}.{}", group, name),
+        None => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::ThinEdgeJsonSerializer;
+
+    fn counts(histogram: &Histogram) -> Vec<u64> {
+        histogram.buckets().map(|(_, _, count)| count).collect()
+    }
+
+    #[test]
+    fn values_are_counted_into_their_bucket() {
+        let mut histogram = Histogram::new(&[10.0, 0.0, 20.0]);
+        for value in [-5.0, 0.0, 9.99, 10.0, 19.0, 20.0, 150.0, f64::NAN].iter() {
+            histogram.record(*value);
+        }
+
+        assert_eq!(counts(&histogram), vec![1, 2, 2, 2]);
+        assert_eq!(histogram.count(), 7);
+        assert_eq!(
+            histogram
+                .buckets()
+                .map(|(lower, upper, _)| (lower, upper))
+                .collect::<Vec<_>>(),
+            vec![
+                (f64::NEG_INFINITY, 0.0),
+                (0.0, 10.0),
+                (10.0, 20.0),
+                (20.0, f64::INFINITY)
+            ]
+        );
+    }
+
+    #[test]
+    fn infinite_values_fall_into_the_outer_buckets() {
+        let mut histogram = Histogram::new(&[0.0, f64::NAN, f64::INFINITY]);
+        histogram.record(f64::NEG_INFINITY);
+        histogram.record(f64::INFINITY);
+
+        assert_eq!(counts(&histogram), vec![1, 1]);
+    }
+
+    #[test]
+    fn histograms_are_kept_per_measurement_and_group() -> anyhow::Result<()> {
+        let mut visitor = HistogramVisitor::new(&[0.0, 10.0], ThinEdgeJsonSerializer::new())
+            .with_boundaries("engine.speed", &[1000.0]);
+
+        visitor.measurement("temperature", 5.0)?;
+        visitor.start_group("engine")?;
+        visitor.measurement("temperature", 50.0)?;
+        visitor.measurement_with_unit("speed", 3000.0, "rpm")?;
+        visitor.end_group()?;
+        visitor.nullable_measurement("temperature", None)?;
+
+        assert_eq!(
+            visitor.histogram(None, "temperature").map(counts),
+            Some(vec![0, 1, 0])
+        );
+        assert_eq!(
+            visitor.histogram(Some("engine"), "temperature").map(counts),
+            Some(vec![0, 0, 1])
+        );
+        assert_eq!(
+            visitor.histogram(Some("engine"), "speed").map(counts),
+            Some(vec![0, 1])
+        );
+        assert_eq!(visitor.histogram(None, "speed"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn flush_forwards_the_bucket_counts() -> anyhow::Result<()> {
+        let mut visitor = HistogramVisitor::new(&[0.0, 10.0], ThinEdgeJsonSerializer::new());
+        let timestamp = DateTime::parse_from_rfc3339("2021-04-30T17:03:14+02:00")?;
+
+        visitor.timestamp(timestamp)?;
+        visitor.measurement("temperature", 5.0)?;
+        visitor.start_group("engine")?;
+        visitor.measurement("speed", -1.0)?;
+        visitor.end_group()?;
+        visitor.measurement("temperature", 12.0)?;
+        visitor.flush()?;
+
+        let expected_output = r#"{"time":"2021-04-30T17:03:14+02:00","temperature_bucket_-inf_0":0.0,"temperature_bucket_0_10":1.0,"temperature_bucket_10_inf":1.0,"engine":{"speed_bucket_-inf_0":1.0,"speed_bucket_0_10":0.0,"speed_bucket_10_inf":0.0}}"#;
+        let first = std::mem::replace(visitor.inner_mut(), ThinEdgeJsonSerializer::new());
+        assert_eq!(first.bytes()?, expected_output.as_bytes());
+
+        // The histograms are reset by a flush
+        visitor.flush()?;
+        assert!(visitor.histogram(None, "temperature").is_none());
+        assert_eq!(visitor.into_inner().into_string()?, "{}");
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+use crate::series::FlatMeasurementSeries;
+use chrono::offset::FixedOffset;
+use chrono::{TimeZone, Utc};
+
+/// The InfluxDB measurement used for the thin-edge measurements which are not attached to a group
+pub const TOP_LEVEL_MEASUREMENT: &str = "thin_edge";
+
+/// Convert measurements from and to the
+/// [InfluxDB line protocol](https://docs.influxdata.com/influxdb/v2.0/reference/syntax/line-protocol/).
+///
+/// The measurements which are not attached to a group are given as the fields
+/// of a first line for the `thin_edge` InfluxDB measurement,
+/// followed by one line per group, using the group name as InfluxDB measurement.
+/// The timestamp, if any, is given in nanoseconds since the UNIX epoch.
+///
+/// The line protocol having no notion of unit nor time zone,
+/// the units are dropped and the timestamps read from line protocol are in UTC.
+/// When reading line protocol, the tags are ignored
+/// and only float and integer fields are supported.
+///
+/// ```
+/// use thin_edge_json::influxdb::InfluxDbConverter;
+/// use thin_edge_json::json::parse_str;
+/// use thin_edge_json::series::FlatMeasurementSeries;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let mut series = FlatMeasurementSeries::new();
+/// parse_str(r#"{"temperature": 25.5, "location": {"alti": 2100.4}}"#, &mut series)?;
+///
+/// assert_eq!(
+///     InfluxDbConverter::to_line_protocol(&series),
+///     "thin_edge temperature=25.5\n\
+///      location alti=2100.4\n"
+/// );
+/// # Ok(()) }
+/// ```
+pub struct InfluxDbConverter;
+
+#[derive(thiserror::Error, Debug)]
+pub enum InfluxDbError {
+    #[error("Invalid line protocol at line {line}: {reason}")]
+    InvalidLine { line: usize, reason: String },
+
+    #[error("Invalid line protocol at line {line}: the field {field:?} is not a number")]
+    UnsupportedFieldValue { line: usize, field: String },
+
+    #[error("Invalid line protocol at line {line}: {value:?} is not a valid timestamp")]
+    InvalidTimestamp { line: usize, value: String },
+
+    #[error("Invalid line protocol at line {line}: all the lines must have the same timestamp")]
+    MultipleTimestamps { line: usize },
+}
+
+impl InfluxDbConverter {
+    pub fn to_line_protocol(series: &FlatMeasurementSeries) -> String {
+        let timestamp = series
+            .timestamp
+            .map(|timestamp| format!(" {}", timestamp.timestamp_nanos()))
+            .unwrap_or_default();
+
+        // Lines in order of first appearance, each with its fields
+        let mut lines: Vec<(&str, Vec<String>)> = Vec::new();
+        for measurement in series.measurements.iter() {
+            let name = measurement
+                .group
+                .as_deref()
+                .unwrap_or(TOP_LEVEL_MEASUREMENT);
+            let field = format!(
+                "{}={}",
+                escape(&measurement.name, &[',', '=', ' ']),
+                measurement.value
+            );
+            match lines.iter_mut().find(|(line_name, _)| *line_name == name) {
+                Some((_, fields)) => fields.push(field),
+                None => lines.push((name, vec![field])),
+            }
+        }
+
+        let mut line_protocol = String::new();
+        for (name, fields) in lines {
+            line_protocol.push_str(&escape(name, &[',', ' ']));
+            line_protocol.push(' ');
+            line_protocol.push_str(&fields.join(","));
+            line_protocol.push_str(&timestamp);
+            line_protocol.push('\n');
+        }
+        line_protocol
+    }
+
+    pub fn from_line_protocol(input: &str) -> Result<FlatMeasurementSeries, InfluxDbError> {
+        let mut series = FlatMeasurementSeries::new();
+
+        for (index, text) in input.lines().enumerate() {
+            let line = index + 1;
+            let text = text.trim();
+            if text.is_empty() || text.starts_with('#') {
+                continue;
+            }
+            let invalid_line = |reason: &str| InfluxDbError::InvalidLine {
+                line,
+                reason: reason.to_string(),
+            };
+
+            let sections = split_unescaped(text, ' ');
+            let (key, fields, timestamp) = match sections.as_slice() {
+                [key, fields] => (key, fields, None),
+                [key, fields, timestamp] => (key, fields, Some(timestamp)),
+                _ => {
+                    return Err(invalid_line(
+                        "expected a measurement, fields and a timestamp",
+                    ))
+                }
+            };
+
+            // The tags, following the measurement name, are ignored
+            let name = unescape(&split_unescaped(key, ',')[0]);
+            if name.is_empty() {
+                return Err(invalid_line("the measurement name is missing"));
+            }
+            let group = Some(name.as_str()).filter(|name| *name != TOP_LEVEL_MEASUREMENT);
+
+            for field in split_unescaped(fields, ',') {
+                let (field_name, value) = match split_unescaped(&field, '=').as_slice() {
+                    [field_name, value] => (unescape(field_name), value.clone()),
+                    _ => return Err(invalid_line("expected fields as key=value pairs")),
+                };
+                let value = parse_field_value(&value).ok_or_else(|| {
+                    InfluxDbError::UnsupportedFieldValue {
+                        line,
+                        field: field_name.clone(),
+                    }
+                })?;
+                series.push(group, &field_name, value, None);
+            }
+
+            if let Some(value) = timestamp {
+                let timestamp = value
+                    .parse::<i64>()
+                    .ok()
+                    .map(|nanos| {
+                        Utc.timestamp_nanos(nanos)
+                            .with_timezone(&FixedOffset::east(0))
+                    })
+                    .ok_or_else(|| InfluxDbError::InvalidTimestamp {
+                        line,
+                        value: value.to_string(),
+                    })?;
+                match series.timestamp {
+                    Some(previous) if previous != timestamp => {
+                        return Err(InfluxDbError::MultipleTimestamps { line })
+                    }
+                    _ => series.timestamp = Some(timestamp),
+                }
+            }
+        }
+        Ok(series)
+    }
+}
+
+/// Parse a float or an integer field value, as `25.5`, `-3i` or `7u`
+fn parse_field_value(value: &str) -> Option<f64> {
+    let number = value
+        .strip_suffix('i')
+        .or_else(|| value.strip_suffix('u'))
+        .unwrap_or(value);
+    number.parse::<f64>().ok().filter(|value| value.is_finite())
+}
+
+fn escape(text: &str, special_chars: &[char]) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c == '\\' || special_chars.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Split a text on the separators which are not escaped, keeping the escape sequences
+fn split_unescaped(text: &str, separator: char) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                part.push(c);
+                part.extend(chars.next());
+            }
+            c if c == separator => parts.push(std::mem::take(&mut part)),
+            c => part.push(c),
+        }
+    }
+    parts.push(part);
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::parse_str;
+    use crate::serialize::ThinEdgeJsonSerializer;
+    use assert_matches::assert_matches;
+
+    fn json_to_line_protocol(input: &str) -> anyhow::Result<String> {
+        let mut series = FlatMeasurementSeries::new();
+        parse_str(input, &mut series)?;
+        Ok(InfluxDbConverter::to_line_protocol(&series))
+    }
+
+    fn line_protocol_to_json(input: &str) -> anyhow::Result<String> {
+        let series = InfluxDbConverter::from_line_protocol(input)?;
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        series.visit(&mut serializer)?;
+        Ok(serializer.into_string()?)
+    }
+
+    #[test]
+    fn convert_thin_edge_json_to_line_protocol() -> anyhow::Result<()> {
+        let input = r#"{
+            "time": "2021-04-30T17:03:14.5+02:00",
+            "temperature": 25.5,
+            "location": {"alti": 2100.4, "longi": 2200.4},
+            "pressure": 98
+        }"#;
+
+        assert_eq!(
+            json_to_line_protocol(input)?,
+            "thin_edge temperature=25.5,pressure=98 1619794994500000000\n\
+             location alti=2100.4,longi=2200.4 1619794994500000000\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn convert_line_protocol_to_thin_edge_json() -> anyhow::Result<()> {
+        let input = "# Comments and empty lines are ignored\n\
+                     \n\
+                     location,device=pi alti=2100.4,longi=2200 1619794994000000000\n\
+                     thin_edge temperature=25.5,count=3i 1619794994000000000\n";
+
+        assert_eq!(
+            line_protocol_to_json(input)?,
+            r#"{"time":"2021-04-30T15:03:14+00:00","location":{"alti":2100.4,"longi":2200.0},"temperature":25.5,"count":3.0}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn units_are_dropped() {
+        let mut series = FlatMeasurementSeries::new();
+        series.push(None, "temperature", 25.5, Some("°C"));
+
+        assert_eq!(
+            InfluxDbConverter::to_line_protocol(&series),
+            "thin_edge temperature=25.5\n"
+        );
+    }
+
+    #[test]
+    fn special_characters_are_escaped() -> anyhow::Result<()> {
+        let mut series = FlatMeasurementSeries::new();
+        series.push(Some("engine room"), "temp,max=1", 80.0, None);
+
+        let line_protocol = InfluxDbConverter::to_line_protocol(&series);
+
+        assert_eq!(line_protocol, "engine\\ room temp\\,max\\=1=80\n");
+        assert_eq!(
+            InfluxDbConverter::from_line_protocol(&line_protocol)?,
+            series
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_line_protocol() {
+        assert_matches!(
+            InfluxDbConverter::from_line_protocol("thin_edge\n"),
+            Err(InfluxDbError::InvalidLine { line: 1, .. })
+        );
+        assert_matches!(
+            InfluxDbConverter::from_line_protocol("thin_edge status=\"on\"\n"),
+            Err(InfluxDbError::UnsupportedFieldValue { line: 1, .. })
+        );
+        assert_matches!(
+            InfluxDbConverter::from_line_protocol("thin_edge temperature=25.5 yesterday\n"),
+            Err(InfluxDbError::InvalidTimestamp { line: 1, .. })
+        );
+        assert_matches!(
+            InfluxDbConverter::from_line_protocol("a x=1 1000\nb y=2 2000\n"),
+            Err(InfluxDbError::MultipleTimestamps { line: 2 })
+        );
+    }
+}
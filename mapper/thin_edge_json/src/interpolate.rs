@@ -0,0 +1,455 @@
+use crate::context::MeasurementContext;
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality, MetadataVisitor};
+use crate::series::FlatMeasurementSeries;
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use clock::{Clock, Timestamp, WallClock};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// The metadata key tagging the interpolated messages
+pub const INTERPOLATED_KEY: &str = "_interpolated";
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    timestamp: Timestamp,
+    value: f64,
+}
+
+/// A visitor that fills the gaps of the measurement time series with interpolated values.
+///
+/// The measurements are forwarded unchanged to the inner visitor,
+/// while the last value and time of each measurement,
+/// identified by its group and name, are tracked.
+/// When a measurement is received more than `max_gap` after its previous value,
+/// synthetic values are computed by linear interpolation,
+/// every `interpolation_interval` from the previous value.
+///
+/// These synthetic values are gathered into messages, one per instant,
+/// to be retrieved with `take_interpolated()` and forwarded to a sink,
+/// either as is or tagged with `"_interpolated":true` using `visit_tagged()`.
+///
+/// The time of a measurement is given by the timestamp of its group, if any,
+/// else by the latest timestamp received by the visitor,
+/// else by the clock when the measurement is received.
+///
+/// ```
+/// use chrono::{FixedOffset, TimeZone};
+/// use std::time::Duration;
+/// use thin_edge_json::interpolate::{visit_tagged, InterpolatingVisitor};
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let mut visitor = InterpolatingVisitor::new(
+///     Duration::from_secs(90),
+///     Duration::from_secs(60),
+///     ThinEdgeJsonSerializer::new(),
+/// );
+///
+/// let start = FixedOffset::east(0).ymd(2021, 4, 30).and_hms(17, 0, 0);
+/// visitor.timestamp(start)?;
+/// visitor.measurement("temperature", 20.0)?;
+///
+/// // The next message, sent a couple of minutes later
+/// *visitor.inner_mut() = ThinEdgeJsonSerializer::new();
+/// visitor.timestamp(start + chrono::Duration::minutes(2))?;
+/// visitor.measurement("temperature", 22.0)?;
+///
+/// let interpolated = visitor.take_interpolated();
+/// let mut serializer = ThinEdgeJsonSerializer::new();
+/// visit_tagged(&interpolated[0], &mut serializer)?;
+///
+/// assert_eq!(
+///     serializer.into_string()?,
+///     r#"{"time":"2021-04-30T17:01:00+00:00","_interpolated":true,"temperature":21.0}"#
+/// );
+/// # Ok(()) }
+/// ```
+pub struct InterpolatingVisitor<V> {
+    max_gap: Duration,
+    interpolation_interval: Duration,
+    clock: Box<dyn Clock>,
+    timestamp: Option<Timestamp>,
+    group: Option<String>,
+    group_timestamp: Option<Timestamp>,
+    last_seen: HashMap<(Option<String>, String), Sample>,
+    interpolated: Vec<FlatMeasurementSeries>,
+    inner: V,
+}
+
+impl<V> InterpolatingVisitor<V> {
+    pub fn new(max_gap: Duration, interpolation_interval: Duration, inner: V) -> Self {
+        Self::with_clock(max_gap, interpolation_interval, Box::new(WallClock), inner)
+    }
+
+    pub fn with_clock(
+        max_gap: Duration,
+        interpolation_interval: Duration,
+        clock: Box<dyn Clock>,
+        inner: V,
+    ) -> Self {
+        Self {
+            max_gap,
+            interpolation_interval,
+            clock,
+            timestamp: None,
+            group: None,
+            group_timestamp: None,
+            last_seen: HashMap::new(),
+            interpolated: Vec::new(),
+            inner,
+        }
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut V {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> V {
+        self.inner
+    }
+
+    /// The messages of interpolated values produced since the previous call,
+    /// ordered by timestamp.
+    pub fn take_interpolated(&mut self) -> Vec<FlatMeasurementSeries> {
+        let mut interpolated = std::mem::take(&mut self.interpolated);
+        interpolated.sort_by_key(|message| message.timestamp);
+        interpolated
+    }
+
+    /// Record the value of a measurement, interpolating the values missing since the previous one
+    fn track(&mut self, name: &str, value: f64, unit: Option<&str>) {
+        let timestamp = match self.group_timestamp.or(self.timestamp) {
+            Some(timestamp) => timestamp,
+            None => self.clock.now(),
+        };
+        let sample = Sample { timestamp, value };
+        let key = (self.group.clone(), name.to_string());
+
+        if let Some(previous) = self.last_seen.insert(key, sample) {
+            let group = self.group.clone();
+            self.interpolate(group.as_deref(), name, unit, previous, sample);
+        }
+    }
+
+    fn interpolate(
+        &mut self,
+        group: Option<&str>,
+        name: &str,
+        unit: Option<&str>,
+        from: Sample,
+        to: Sample,
+    ) {
+        let gap = match (to.timestamp - from.timestamp).to_std() {
+            Ok(gap) => gap,
+            // The measurements are received out of order
+            Err(_) => return,
+        };
+        if gap <= self.max_gap || self.interpolation_interval == Duration::from_secs(0) {
+            return;
+        }
+
+        let mut elapsed = self.interpolation_interval;
+        while elapsed < gap {
+            let offset = match chrono::Duration::from_std(elapsed) {
+                Ok(offset) => offset,
+                Err(_) => return,
+            };
+            let ratio = elapsed.as_secs_f64() / gap.as_secs_f64();
+            let value = from.value + (to.value - from.value) * ratio;
+            self.message_at(from.timestamp + offset)
+                .push(group, name, value, unit);
+            elapsed += self.interpolation_interval;
+        }
+    }
+
+    /// The interpolated message for the given instant
+    fn message_at(&mut self, timestamp: Timestamp) -> &mut FlatMeasurementSeries {
+        let index = match self
+            .interpolated
+            .iter()
+            .position(|message| message.timestamp == Some(timestamp))
+        {
+            Some(index) => index,
+            None => {
+                let mut message = FlatMeasurementSeries::new();
+                message.timestamp = Some(timestamp);
+                self.interpolated.push(message);
+                self.interpolated.len() - 1
+            }
+        };
+        &mut self.interpolated[index]
+    }
+}
+
+/// Forward an interpolated message to a visitor, tagged with `"_interpolated":true`
+pub fn visit_tagged<W>(message: &FlatMeasurementSeries, visitor: &mut W) -> Result<(), W::Error>
+where
+    W: MetadataVisitor,
+{
+    visitor.metadata_flag(INTERPOLATED_KEY, true)?;
+    message.visit(visitor)
+}
+
+impl<V> GroupedMeasurementVisitor for InterpolatingVisitor<V>
+where
+    V: GroupedMeasurementVisitor,
+{
+    type Error = V::Error;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.timestamp = Some(value);
+        self.inner.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.track(name, value, None);
+        self.inner.measurement(name, value)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.group = Some(group.to_string());
+        self.group_timestamp = None;
+        self.inner.start_group(group)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.group = None;
+        self.group_timestamp = None;
+        self.inner.end_group()
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.track(name, value, Some(unit));
+        self.inner.measurement_with_unit(name, value, unit)
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.group = Some(group.to_string());
+        self.group_timestamp = Some(timestamp);
+        self.inner.start_group_with_timestamp(group, timestamp)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        if let Some(value) = value {
+            self.track(name, value, None);
+        }
+        self.inner.nullable_measurement(name, value)
+    }
+
+    /// The complex measurements are forwarded with no interpolation
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.inner.complex_measurement(name, real, imaginary)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.track(name, value, None);
+        self.inner.annotated_measurement(name, value, quality)
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.inner.set_context(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::ThinEdgeJsonSerializer;
+    use chrono::TimeZone;
+    use clock::MockClock;
+
+    fn at(minutes: i64) -> Timestamp {
+        FixedOffset::east(0).ymd(2021, 4, 30).and_hms(17, 0, 0) + chrono::Duration::minutes(minutes)
+    }
+
+    fn visitor() -> InterpolatingVisitor<ThinEdgeJsonSerializer> {
+        InterpolatingVisitor::new(
+            Duration::from_secs(90),
+            Duration::from_secs(60),
+            ThinEdgeJsonSerializer::new(),
+        )
+    }
+
+    /// The inner serializer is given one message, hence replaced before the next one
+    fn next_message(visitor: &mut InterpolatingVisitor<ThinEdgeJsonSerializer>) {
+        *visitor.inner_mut() = ThinEdgeJsonSerializer::new();
+    }
+
+    fn serialize(messages: &[FlatMeasurementSeries]) -> anyhow::Result<Vec<String>> {
+        let mut payloads = Vec::new();
+        for message in messages {
+            let mut serializer = ThinEdgeJsonSerializer::new();
+            message.visit(&mut serializer)?;
+            payloads.push(serializer.into_string()?);
+        }
+        Ok(payloads)
+    }
+
+    #[test]
+    fn measurements_are_forwarded_unchanged() -> anyhow::Result<()> {
+        let mut visitor = visitor();
+        visitor.timestamp(at(0))?;
+        visitor.measurement("temperature", 20.0)?;
+        visitor.start_group("location")?;
+        visitor.measurement_with_unit("alti", 2100.4, "m")?;
+        visitor.end_group()?;
+
+        assert_eq!(
+            visitor.into_inner().into_string()?,
+            r#"{"time":"2021-04-30T17:00:00+00:00","temperature":20.0,"location":{"alti":{"value":2100.4,"unit":"m"}}}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn no_values_are_interpolated_within_the_max_gap() -> anyhow::Result<()> {
+        let mut visitor = visitor();
+        visitor.timestamp(at(0))?;
+        visitor.measurement("temperature", 20.0)?;
+        next_message(&mut visitor);
+        visitor.timestamp(at(1))?;
+        visitor.measurement("temperature", 21.0)?;
+
+        assert!(visitor.take_interpolated().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn gaps_are_filled_by_linear_interpolation() -> anyhow::Result<()> {
+        let mut visitor = visitor();
+        visitor.timestamp(at(0))?;
+        visitor.measurement("temperature", 10.0)?;
+        next_message(&mut visitor);
+        visitor.timestamp(at(4))?;
+        visitor.measurement("temperature", 50.0)?;
+
+        assert_eq!(
+            serialize(&visitor.take_interpolated())?,
+            vec![
+                r#"{"time":"2021-04-30T17:01:00+00:00","temperature":20.0}"#,
+                r#"{"time":"2021-04-30T17:02:00+00:00","temperature":30.0}"#,
+                r#"{"time":"2021-04-30T17:03:00+00:00","temperature":40.0}"#,
+            ]
+        );
+        assert!(visitor.take_interpolated().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn interpolated_values_are_gathered_by_instant() -> anyhow::Result<()> {
+        let mut visitor = visitor();
+        visitor.timestamp(at(0))?;
+        visitor.measurement("temperature", 20.0)?;
+        visitor.start_group("location")?;
+        visitor.measurement_with_unit("alti", 2100.0, "m")?;
+        visitor.end_group()?;
+
+        next_message(&mut visitor);
+        visitor.timestamp(at(4))?;
+        visitor.start_group("location")?;
+        visitor.measurement_with_unit("alti", 2140.0, "m")?;
+        visitor.end_group()?;
+        visitor.measurement("temperature", 24.0)?;
+
+        assert_eq!(
+            serialize(&visitor.take_interpolated())?,
+            vec![
+                r#"{"time":"2021-04-30T17:01:00+00:00","location":{"alti":{"value":2110.0,"unit":"m"}},"temperature":21.0}"#,
+                r#"{"time":"2021-04-30T17:02:00+00:00","location":{"alti":{"value":2120.0,"unit":"m"}},"temperature":22.0}"#,
+                r#"{"time":"2021-04-30T17:03:00+00:00","location":{"alti":{"value":2130.0,"unit":"m"}},"temperature":23.0}"#,
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn group_timestamps_take_precedence() -> anyhow::Result<()> {
+        let mut visitor = visitor();
+        visitor.timestamp(at(10))?;
+        visitor.start_group_with_timestamp("engine", at(0))?;
+        visitor.measurement("speed", 1000.0)?;
+        visitor.end_group()?;
+        next_message(&mut visitor);
+        visitor.start_group("engine")?;
+        visitor.measurement("speed", 3000.0)?;
+        visitor.end_group()?;
+
+        let interpolated = visitor.take_interpolated();
+        assert_eq!(interpolated.len(), 9);
+        assert_eq!(interpolated[0].timestamp, Some(at(1)));
+        assert_eq!(interpolated[0].measurements[0].value, 1200.0);
+        Ok(())
+    }
+
+    #[test]
+    fn measurements_with_no_timestamp_are_timed_by_the_clock() -> anyhow::Result<()> {
+        let mut clock = MockClock::new();
+        let mut minutes = 0;
+        clock.expect_now().returning(move || {
+            let now = at(minutes);
+            minutes += 2;
+            now
+        });
+        let mut visitor = InterpolatingVisitor::with_clock(
+            Duration::from_secs(90),
+            Duration::from_secs(60),
+            Box::new(clock),
+            ThinEdgeJsonSerializer::new(),
+        );
+
+        visitor.measurement("temperature", 20.0)?;
+        next_message(&mut visitor);
+        visitor.measurement("temperature", 22.0)?;
+
+        assert_eq!(
+            serialize(&visitor.take_interpolated())?,
+            vec![r#"{"time":"2021-04-30T17:01:00+00:00","temperature":21.0}"#]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn interpolated_messages_can_be_tagged() -> anyhow::Result<()> {
+        let mut visitor = visitor();
+        visitor.timestamp(at(0))?;
+        visitor.measurement("temperature", 20.0)?;
+        next_message(&mut visitor);
+        visitor.timestamp(at(2))?;
+        visitor.measurement("temperature", 22.0)?;
+
+        let interpolated = visitor.take_interpolated();
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        visit_tagged(&interpolated[0], &mut serializer)?;
+
+        assert_eq!(
+            serializer.into_string()?,
+            r#"{"time":"2021-04-30T17:01:00+00:00","_interpolated":true,"temperature":21.0}"#
+        );
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
-use crate::measurement::GroupedMeasurementVisitor;
+use crate::context::{MeasurementContext, SPAN_ID_KEY, TRACE_ID_KEY};
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality, METADATA_KEY_PREFIX};
 use chrono::{format::ParseError, prelude::*};
 use json::JsonValue;
 
@@ -140,6 +141,12 @@ pub fn parse_str<T: GroupedMeasurementVisitor>(
 
     match &thin_edge_obj {
         JsonValue::Object(thin_edge_obj) => {
+            if let Some(context) = parse_context(thin_edge_obj) {
+                let () = visitor
+                    .set_context(context)
+                    .map_err(ThinEdgeJsonParserError::VisitorError)?;
+            }
+
             for (key, value) in thin_edge_obj.iter() {
                 if key.contains('\\') {
                     return Err(ThinEdgeJsonError::InvalidThinEdgeJsonKey {
@@ -152,6 +159,11 @@ pub fn parse_str<T: GroupedMeasurementVisitor>(
                         name: String::from(key),
                     }
                     .into());
+                } else if key.starts_with(METADATA_KEY_PREFIX)
+                    && (value.is_string() || value.is_boolean())
+                {
+                    // The metadata fields, as the version of the format, are not measurements
+                    continue;
                 } else if key.eq("time") {
                     let () = visitor
                         .timestamp(parse_from_rfc3339(
@@ -161,52 +173,19 @@ pub fn parse_str<T: GroupedMeasurementVisitor>(
                         )?)
                         .map_err(ThinEdgeJsonParserError::VisitorError)?;
                 } else {
-                    match value {
-                        // Single Value object
-                        JsonValue::Number(num) => {
-                            let () = visitor
-                                .measurement(key, (*num).into())
-                                .map_err(ThinEdgeJsonParserError::VisitorError)?;
-                        }
-                        // Multi value object
-                        JsonValue::Object(multi_value_thin_edge_object) => {
-                            let () = visitor
-                                .start_group(key)
-                                .map_err(ThinEdgeJsonParserError::VisitorError)?;
-
-                            for (k, v) in multi_value_thin_edge_object.iter() {
-                                match v {
-                                    JsonValue::Number(num) => {
-                                        // Single Value object
-                                        let () = visitor
-                                            .measurement(k, (*num).into())
-                                            .map_err(ThinEdgeJsonParserError::VisitorError)?;
-                                    }
-                                    JsonValue::Object(_object) => {
-                                        return Err(ThinEdgeJsonError::InvalidThinEdgeHierarchy {
-                                            name: k.into(),
-                                        }
-                                        .into());
-                                    }
-                                    value => {
-                                        return Err(ThinEdgeJsonError::new_invalid_json_value(
-                                            k, value,
-                                        )
-                                        .into());
-                                    }
-                                }
+                    match visit_measurement(key, value, visitor) {
+                        Some(result) => result.map_err(ThinEdgeJsonParserError::VisitorError)?,
+                        None => match value {
+                            // Multi value object
+                            JsonValue::Object(multi_value_thin_edge_object) => {
+                                parse_group(key, multi_value_thin_edge_object, visitor)?;
                             }
-
-                            let () = visitor
-                                .end_group()
-                                .map_err(ThinEdgeJsonParserError::VisitorError)?;
-                        }
-
-                        _ => {
-                            return Err(
-                                ThinEdgeJsonError::new_invalid_json_value(key, value).into()
-                            );
-                        }
+                            _ => {
+                                return Err(
+                                    ThinEdgeJsonError::new_invalid_json_value(key, value).into()
+                                );
+                            }
+                        },
                     }
                 }
             }
@@ -217,6 +196,85 @@ pub fn parse_str<T: GroupedMeasurementVisitor>(
     Ok(())
 }
 
+/// The trace context given by the `_traceId` and `_spanId` metadata fields, if both are valid
+fn parse_context(object: &json::object::Object) -> Option<MeasurementContext> {
+    let trace_id = object.get(TRACE_ID_KEY)?.as_str()?;
+    let span_id = object.get(SPAN_ID_KEY)?.as_str()?;
+    MeasurementContext::from_hex(trace_id, span_id)
+}
+
+fn parse_group<T: GroupedMeasurementVisitor>(
+    group: &str,
+    object: &json::object::Object,
+    visitor: &mut T,
+) -> Result<(), ThinEdgeJsonParserError<T::Error>> {
+    // A group can be given its own timestamp, while a time number is a plain measurement
+    let timestamp = object.get("time").and_then(JsonValue::as_str);
+    match timestamp {
+        Some(timestamp) => {
+            visitor.start_group_with_timestamp(group, parse_from_rfc3339(timestamp)?)
+        }
+        None => visitor.start_group(group),
+    }
+    .map_err(ThinEdgeJsonParserError::VisitorError)?;
+
+    for (k, v) in object.iter() {
+        match visit_measurement(k, v, visitor) {
+            Some(result) => result.map_err(ThinEdgeJsonParserError::VisitorError)?,
+            None if k == "time" && timestamp.is_some() => continue,
+            None => match v {
+                JsonValue::Object(_object) => {
+                    return Err(
+                        ThinEdgeJsonError::InvalidThinEdgeHierarchy { name: k.into() }.into(),
+                    );
+                }
+                value => {
+                    return Err(ThinEdgeJsonError::new_invalid_json_value(k, value).into());
+                }
+            },
+        }
+    }
+
+    visitor
+        .end_group()
+        .map_err(ThinEdgeJsonParserError::VisitorError)
+}
+
+/// Visit a single measurement, returning `None` if the value is not one:
+/// i.e. neither a number, nor `null` which stands for a missing value,
+/// nor an object with just a number `value` and either a string `unit` or a `quality`.
+fn visit_measurement<T: GroupedMeasurementVisitor>(
+    name: &str,
+    value: &JsonValue,
+    visitor: &mut T,
+) -> Option<Result<(), T::Error>> {
+    match value {
+        JsonValue::Number(num) => Some(visitor.measurement(name, (*num).into())),
+        JsonValue::Null => Some(visitor.nullable_measurement(name, None)),
+        JsonValue::Object(object) if object.len() == 2 => {
+            let value = object.get("value").and_then(JsonValue::as_f64)?;
+            if let Some(unit) = object.get("unit").and_then(JsonValue::as_str) {
+                return Some(visitor.measurement_with_unit(name, value, unit));
+            }
+            let quality = object.get("quality").and_then(JsonValue::as_str)?;
+            let quality = parse_quality(quality)?;
+            Some(visitor.annotated_measurement(name, value, quality))
+        }
+        _ => None,
+    }
+}
+
+fn parse_quality(quality: &str) -> Option<MeasurementQuality> {
+    [
+        MeasurementQuality::Good,
+        MeasurementQuality::Bad,
+        MeasurementQuality::Uncertain,
+    ]
+    .iter()
+    .copied()
+    .find(|known| known.as_str() == quality)
+}
+
 fn parse_from_rfc3339(timestamp: &str) -> Result<DateTime<FixedOffset>, ThinEdgeJsonError> {
     let time = DateTime::parse_from_rfc3339(&timestamp).map_err(|err| {
         ThinEdgeJsonError::InvalidTimestamp {
@@ -236,6 +294,14 @@ impl ThinEdgeJson {
         Ok(builder.done()?)
     }
 
+    pub fn from_utf8(
+        input: &[u8],
+    ) -> Result<ThinEdgeJson, ThinEdgeJsonParserError<ThinEdgeJsonError>> {
+        let json_string = std::str::from_utf8(input)
+            .map_err(|err| ThinEdgeJsonError::new_invalid_utf8(input, err))?;
+        ThinEdgeJson::from_str(json_string)
+    }
+
     pub fn has_timestamp(&self) -> bool {
         self.timestamp.is_some()
     }
@@ -323,13 +389,23 @@ pub enum ThinEdgeJsonError {
 impl ThinEdgeJsonError {
     const MAX_LEN: usize = 80;
 
-    fn new_invalid_json(input: &str, from: json::JsonError) -> ThinEdgeJsonError {
+    pub(crate) fn new_invalid_json(input: &str, from: json::JsonError) -> ThinEdgeJsonError {
         ThinEdgeJsonError::InvalidJson {
             input_excerpt: input_prefix(input, ThinEdgeJsonError::MAX_LEN),
             from,
         }
     }
 
+    pub(crate) fn new_invalid_utf8(input: &[u8], from: std::str::Utf8Error) -> ThinEdgeJsonError {
+        ThinEdgeJsonError::InvalidUtf8 {
+            input_excerpt: input_prefix(
+                &String::from_utf8_lossy(input),
+                ThinEdgeJsonError::MAX_LEN,
+            ),
+            from,
+        }
+    }
+
     fn new_invalid_json_root(json: &JsonValue) -> ThinEdgeJsonError {
         ThinEdgeJsonError::InvalidThinEdgeJsonRoot {
             json_excerpt: input_prefix(&json.to_string(), ThinEdgeJsonError::MAX_LEN),
@@ -365,6 +441,9 @@ impl ThinEdgeJsonError {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::buffer::MeasurementBuffer;
+    use crate::serialize::ThinEdgeJsonSerializer;
+    use crate::trace::VisitorCall;
 
     #[test]
     fn test_str_with_invalid_timestamp() {
@@ -484,6 +563,94 @@ mod tests {
         assert_eq!(expected_error, error.to_string());
     }
 
+    #[test]
+    fn thin_edge_json_accept_schema_version() {
+        let input = r#"{
+           "_schema" : "te/1.0",
+           "temperature": 50
+          }"#;
+
+        let output = ThinEdgeJson::from_str(input).unwrap();
+        assert_eq!(output.values.len(), 1);
+    }
+
+    #[test]
+    fn thin_edge_json_accept_expiry_time() {
+        let input = r#"{
+           "_expires_at" : "2013-06-22T17:04:14Z",
+           "temperature": 50
+          }"#;
+
+        let output = ThinEdgeJson::from_str(input).unwrap();
+        assert_eq!(output.values.len(), 1);
+    }
+
+    #[test]
+    fn thin_edge_json_accept_metadata() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new().with_schema_version(1, 0);
+        serializer.add_metadata("_site", "plant-7")?;
+        serializer.add_metadata_flag("_interpolated", true)?;
+        serializer.measurement("temperature", 25.5)?;
+        let input = serializer.into_string()?;
+
+        let mut buffer = MeasurementBuffer::new();
+        parse_str(&input, &mut buffer)?;
+
+        assert_eq!(
+            buffer.into_events(),
+            vec![VisitorCall::Measurement {
+                name: "temperature".into(),
+                value: 25.5
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn thin_edge_json_accept_trace_context() -> anyhow::Result<()> {
+        let calls = vec![
+            VisitorCall::SetContext {
+                context: MeasurementContext::new([0x4b; 16], [0xf0; 8]),
+            },
+            VisitorCall::Timestamp {
+                value: test_timestamp(),
+            },
+            VisitorCall::Measurement {
+                name: "temperature".into(),
+                value: 25.5,
+            },
+        ];
+
+        assert_eq!(round_trip(&calls)?, calls);
+        Ok(())
+    }
+
+    #[test]
+    fn thin_edge_json_ignore_invalid_trace_context() -> anyhow::Result<()> {
+        let input = r#"{"_traceId": "4b4b", "_spanId": "f0f0f0f0f0f0f0f0", "temperature": 25.5}"#;
+
+        let mut buffer = MeasurementBuffer::new();
+        parse_str(input, &mut buffer)?;
+
+        assert_eq!(
+            buffer.into_events(),
+            vec![VisitorCall::Measurement {
+                name: "temperature".into(),
+                value: 25.5
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn thin_edge_json_reject_string_value_without_metadata_prefix() {
+        let input = r#"{"site": "plant-7", "temperature": 25.5}"#;
+        let expected_error = r#"Not a number: the "site" value must be a number, not a string."#;
+
+        let error = ThinEdgeJson::from_str(input).unwrap_err();
+        assert_eq!(expected_error, error.to_string());
+    }
+
     #[test]
     fn thin_edge_json_reject_string_value() {
         let input = r#"{
@@ -705,6 +872,188 @@ mod tests {
         assert_eq!(expected_error, error.to_string());
     }
 
+    #[test]
+    fn thin_edge_json_reject_invalid_utf8() {
+        let input = b"{\"temperature\": 25, \"\xc3\x28\": 32}";
+
+        let output = ThinEdgeJson::from_utf8(input);
+
+        let error = output.unwrap_err();
+        assert!(error.to_string().starts_with("Invalid UTF8: "));
+    }
+
+    /// Serialize the calls and parse back the payload, returning the calls made by the parser
+    fn round_trip(calls: &[VisitorCall]) -> anyhow::Result<Vec<VisitorCall>> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        for call in calls.iter() {
+            call.apply(&mut serializer)?;
+        }
+        let payload = serializer.into_string()?;
+
+        let mut buffer = MeasurementBuffer::new();
+        parse_str(&payload, &mut buffer)?;
+        Ok(buffer.into_events())
+    }
+
+    fn test_timestamp() -> DateTime<FixedOffset> {
+        FixedOffset::east(2 * 3600)
+            .ymd(2021, 4, 30)
+            .and_hms(17, 3, 14)
+    }
+
+    #[test]
+    fn thin_edge_json_accept_group_timestamp() -> anyhow::Result<()> {
+        let calls = vec![
+            VisitorCall::Timestamp {
+                value: test_timestamp(),
+            },
+            VisitorCall::StartGroupWithTimestamp {
+                group: "engine".into(),
+                value: test_timestamp() + chrono::Duration::seconds(1),
+            },
+            VisitorCall::Measurement {
+                name: "speed".into(),
+                value: 3000.0,
+            },
+            VisitorCall::EndGroup,
+        ];
+
+        assert_eq!(round_trip(&calls)?, calls);
+        Ok(())
+    }
+
+    #[test]
+    fn thin_edge_json_accept_time_measurement_within_a_group() -> anyhow::Result<()> {
+        let input = r#"{"engine": {"time": 12.5, "speed": 3000}}"#;
+
+        let mut buffer = MeasurementBuffer::new();
+        parse_str(input, &mut buffer)?;
+
+        assert_eq!(
+            buffer.into_events(),
+            vec![
+                VisitorCall::StartGroup {
+                    group: "engine".into()
+                },
+                VisitorCall::Measurement {
+                    name: "time".into(),
+                    value: 12.5
+                },
+                VisitorCall::Measurement {
+                    name: "speed".into(),
+                    value: 3000.0
+                },
+                VisitorCall::EndGroup,
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn thin_edge_json_accept_missing_values() -> anyhow::Result<()> {
+        let calls = vec![
+            VisitorCall::NullableMeasurement {
+                name: "temperature".into(),
+                value: None,
+            },
+            VisitorCall::StartGroup {
+                group: "location".into(),
+            },
+            VisitorCall::Measurement {
+                name: "alti".into(),
+                value: 2100.4,
+            },
+            VisitorCall::NullableMeasurement {
+                name: "longi".into(),
+                value: None,
+            },
+            VisitorCall::EndGroup,
+        ];
+
+        assert_eq!(round_trip(&calls)?, calls);
+        Ok(())
+    }
+
+    #[test]
+    fn thin_edge_json_accept_values_with_units() -> anyhow::Result<()> {
+        let calls = vec![
+            VisitorCall::MeasurementWithUnit {
+                name: "temperature".into(),
+                value: 25.5,
+                unit: "°C".into(),
+            },
+            VisitorCall::StartGroup {
+                group: "location".into(),
+            },
+            VisitorCall::MeasurementWithUnit {
+                name: "alti".into(),
+                value: 2100.4,
+                unit: "m".into(),
+            },
+            VisitorCall::EndGroup,
+        ];
+
+        assert_eq!(round_trip(&calls)?, calls);
+        Ok(())
+    }
+
+    #[test]
+    fn thin_edge_json_reject_values_with_invalid_units() {
+        let input = r#"{"location": {"alti": {"value": 2100.4, "unit": true}}}"#;
+        let expected_error =
+            r#"More than 2 nested levels: the record for "alti" must be flattened."#;
+
+        let error = ThinEdgeJson::from_str(input).unwrap_err();
+        assert_eq!(expected_error, error.to_string());
+    }
+
+    #[test]
+    fn thin_edge_json_accept_values_with_a_quality() -> anyhow::Result<()> {
+        let calls = vec![
+            VisitorCall::AnnotatedMeasurement {
+                name: "temperature".into(),
+                value: 25.5,
+                quality: MeasurementQuality::Good,
+            },
+            VisitorCall::StartGroup {
+                group: "location".into(),
+            },
+            VisitorCall::AnnotatedMeasurement {
+                name: "alti".into(),
+                value: 2100.4,
+                quality: MeasurementQuality::Uncertain,
+            },
+            VisitorCall::AnnotatedMeasurement {
+                name: "longi".into(),
+                value: 2200.4,
+                quality: MeasurementQuality::Bad,
+            },
+            VisitorCall::EndGroup,
+        ];
+
+        assert_eq!(round_trip(&calls)?, calls);
+        Ok(())
+    }
+
+    #[test]
+    fn thin_edge_json_reject_unknown_qualities() {
+        let input = r#"{"temperature": {"value": 25.5, "quality": "FAIR"}}"#;
+        let expected_error = r#"Not a number: the "quality" value must be a number, not a string."#;
+
+        let error = ThinEdgeJson::from_str(input).unwrap_err();
+        assert_eq!(expected_error, error.to_string());
+    }
+
+    #[test]
+    fn thin_edge_json_reject_invalid_group_timestamp() {
+        let input = r#"{"engine": {"time": "2021-04-30 17:03:14", "speed": 3000}}"#;
+
+        let expected_error = r#"Invalid ISO8601 timestamp (expected YYYY-MM-DDThh:mm:ss.sss.±hh:mm): "2021-04-30 17:03:14""#;
+        let error = ThinEdgeJson::from_str(input).unwrap_err();
+
+        assert!(error.to_string().starts_with(expected_error));
+    }
+
     use proptest::prelude::*;
 
     proptest! {
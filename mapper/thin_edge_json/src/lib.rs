@@ -1,7 +1,54 @@
 //! A library to create [ThinEdgeJson][1] from bytes of json data by validating it.
 //! [1]: https://github.com/thin-edge/thin-edge.io/blob/main/docs/src/architecture/thin-edge-json.md
 
+pub mod anonymize;
+pub mod async_visitor;
+pub mod buffer;
+pub mod canonical;
+pub mod clamp;
+pub mod compact;
+pub mod compose;
+pub mod context;
+pub mod csv;
+pub mod dedup;
+pub mod diff;
+pub mod ditto;
+pub mod dyn_visitor;
+pub mod elasticsearch;
+pub mod estimate;
+pub mod event_log;
+pub mod expiry;
+pub mod filter;
 pub mod group;
+pub mod haystack;
+pub mod histogram;
+pub mod influxdb;
+pub mod interpolate;
 pub mod json;
+pub mod lint;
 pub mod measurement;
+pub mod merge_patch;
+pub mod middleware;
+pub mod mqtt5;
+pub mod pipeline;
+pub mod rate_limit;
+pub mod recording;
+pub mod remap;
+pub mod replayer;
+pub mod schema;
+pub mod senml;
+pub mod serde_bridge;
 pub mod serialize;
+pub mod series;
+pub mod signalk;
+pub mod sparkplug;
+pub mod statistics;
+pub mod tee;
+pub mod throttle;
+pub mod time_window;
+pub mod timeout_flush;
+pub mod trace;
+pub mod transform;
+pub mod typed;
+pub mod units;
+pub mod version;
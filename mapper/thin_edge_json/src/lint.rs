@@ -0,0 +1,352 @@
+use crate::buffer::MeasurementBuffer;
+use crate::json::parse_str;
+use crate::trace::VisitorCall;
+use chrono::offset::FixedOffset;
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::collections::BTreeMap;
+
+/// The maximum number of characters of a measurement or group name not to be warned about
+pub const MAX_NAME_LENGTH: usize = 64;
+
+/// The characters separating the words of a name, as in `engine_speed`
+const NAME_SEPARATORS: &[char] = &['_', '.', '-'];
+
+/// A non-fatal warning about a thin-edge JSON payload, suggesting how to improve it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintWarning {
+    /// The rule raising the warning, as `non-utc-timestamp`
+    pub code: &'static str,
+    pub message: String,
+    pub suggestion: String,
+}
+
+/// Check thin-edge JSON payloads against some good practices, warning about:
+///
+/// * `invalid-payload`: the payload is not valid thin-edge JSON, all the other rules being skipped;
+/// * `non-utc-timestamp`: a timestamp is not given in UTC;
+/// * `long-name`: a measurement or group name is longer than 64 characters;
+/// * `ungrouped-measurement`: a measurement in the root object is named after a group,
+///   as `location_alti` along a `location` group;
+/// * `common-prefix`: several measurements in the root object share a prefix,
+///   as `engine_speed` and `engine_temp`, and could be grouped.
+///
+/// ```
+/// use thin_edge_json::lint::ThinEdgeJsonLinter;
+///
+/// let payload = br#"{"time":"2021-04-30T17:03:14+02:00","temperature":25.5}"#;
+/// let warnings = ThinEdgeJsonLinter::lint(payload);
+///
+/// assert_eq!(warnings.len(), 1);
+/// assert_eq!(warnings[0].code, "non-utc-timestamp");
+/// assert_eq!(warnings[0].suggestion, "Use \"2021-04-30T15:03:14Z\"");
+/// ```
+pub struct ThinEdgeJsonLinter;
+
+impl ThinEdgeJsonLinter {
+    /// The warnings raised by a payload, rule by rule, none if the payload follows all the rules
+    pub fn lint(payload: &[u8]) -> Vec<LintWarning> {
+        let outline = match Outline::parse(payload) {
+            Ok(outline) => outline,
+            Err(error) => {
+                return vec![LintWarning {
+                    code: "invalid-payload",
+                    message: format!("The payload is not valid thin-edge JSON: {}", error),
+                    suggestion: "Fix the payload, which would be rejected".into(),
+                }]
+            }
+        };
+
+        let mut warnings = Vec::new();
+        outline.check_timestamps(&mut warnings);
+        outline.check_name_lengths(&mut warnings);
+        outline.check_ungrouped_measurements(&mut warnings);
+        outline.check_common_prefixes(&mut warnings);
+        warnings
+    }
+}
+
+/// The names and timestamps of a payload, i.e. all that the rules are about
+#[derive(Debug, Default)]
+struct Outline {
+    timestamps: Vec<DateTime<FixedOffset>>,
+    measurements: Vec<String>,
+    groups: Vec<(String, Vec<String>)>,
+}
+
+impl Outline {
+    fn parse(payload: &[u8]) -> Result<Self, String> {
+        let payload = std::str::from_utf8(payload).map_err(|err| err.to_string())?;
+        let mut buffer = MeasurementBuffer::new();
+        parse_str(payload, &mut buffer).map_err(|err| err.to_string())?;
+
+        let mut outline = Outline::default();
+        let mut group: Option<(String, Vec<String>)> = None;
+        for call in buffer.into_events() {
+            let name = match call {
+                VisitorCall::Timestamp { value } => {
+                    outline.timestamps.push(value);
+                    continue;
+                }
+                VisitorCall::StartGroup { group: name } => {
+                    group = Some((name, vec![]));
+                    continue;
+                }
+                VisitorCall::StartGroupWithTimestamp { group: name, value } => {
+                    outline.timestamps.push(value);
+                    group = Some((name, vec![]));
+                    continue;
+                }
+                VisitorCall::EndGroup => {
+                    outline.groups.extend(group.take());
+                    continue;
+                }
+                VisitorCall::SetContext { .. } => continue,
+                VisitorCall::Measurement { name, .. }
+                | VisitorCall::MeasurementWithUnit { name, .. }
+                | VisitorCall::NullableMeasurement { name, .. }
+                | VisitorCall::ComplexMeasurement { name, .. }
+                | VisitorCall::AnnotatedMeasurement { name, .. } => name,
+            };
+            match group.as_mut() {
+                Some((_, measurements)) => measurements.push(name),
+                None => outline.measurements.push(name),
+            }
+        }
+        Ok(outline)
+    }
+
+    fn check_timestamps(&self, warnings: &mut Vec<LintWarning>) {
+        for timestamp in self.timestamps.iter() {
+            if timestamp.offset().local_minus_utc() != 0 {
+                let utc = timestamp
+                    .with_timezone(&Utc)
+                    .to_rfc3339_opts(SecondsFormat::AutoSi, true);
+                warnings.push(LintWarning {
+                    code: "non-utc-timestamp",
+                    message: format!(
+                        "The timestamp {:?} is not given in UTC",
+                        timestamp.to_rfc3339()
+                    ),
+                    suggestion: format!("Use {:?}", utc),
+                });
+            }
+        }
+    }
+
+    fn check_name_lengths(&self, warnings: &mut Vec<LintWarning>) {
+        let group_names = self.groups.iter().map(|(group, _)| group.as_str());
+        let group_measurements = self
+            .groups
+            .iter()
+            .flat_map(|(_, measurements)| measurements.iter().map(String::as_str));
+        let names = self
+            .measurements
+            .iter()
+            .map(String::as_str)
+            .chain(group_names)
+            .chain(group_measurements);
+
+        for name in names {
+            let length = name.chars().count();
+            if length > MAX_NAME_LENGTH {
+                warnings.push(LintWarning {
+                    code: "long-name",
+                    message: format!(
+                        "The name {:?} is {} characters long, more than {}",
+                        name, length, MAX_NAME_LENGTH
+                    ),
+                    suggestion: format!(
+                        "Use a name of at most {} characters, possibly grouping measurements",
+                        MAX_NAME_LENGTH
+                    ),
+                });
+            }
+        }
+    }
+
+    fn check_ungrouped_measurements(&self, warnings: &mut Vec<LintWarning>) {
+        for name in self.measurements.iter() {
+            if let Some((prefix, rest)) = split_prefix(name) {
+                if self.groups.iter().any(|(group, _)| group == prefix) {
+                    warnings.push(LintWarning {
+                        code: "ungrouped-measurement",
+                        message: format!(
+                            "The measurement {:?} is named after the group {:?}",
+                            name, prefix
+                        ),
+                        suggestion: format!("Move it into the group {:?}, as {:?}", prefix, rest),
+                    });
+                }
+            }
+        }
+    }
+
+    fn check_common_prefixes(&self, warnings: &mut Vec<LintWarning>) {
+        let mut prefixes: BTreeMap<&str, Vec<(&str, &str)>> = BTreeMap::new();
+        for name in self.measurements.iter() {
+            if let Some((prefix, rest)) = split_prefix(name) {
+                if !self.groups.iter().any(|(group, _)| group == prefix) {
+                    prefixes
+                        .entry(prefix)
+                        .or_default()
+                        .push((name.as_str(), rest));
+                }
+            }
+        }
+
+        for (prefix, names) in prefixes.into_iter().filter(|(_, names)| names.len() > 1) {
+            let (names, rests): (Vec<&str>, Vec<&str>) = names.into_iter().unzip();
+            warnings.push(LintWarning {
+                code: "common-prefix",
+                message: format!(
+                    "The measurements {} share the prefix {:?}",
+                    quoted(&names),
+                    prefix
+                ),
+                suggestion: format!("Move them into a group {:?}, as {}", prefix, quoted(&rests)),
+            });
+        }
+    }
+}
+
+/// Split a name on its first word separator, as `engine_speed` into `engine` and `speed`
+fn split_prefix(name: &str) -> Option<(&str, &str)> {
+    let index = name.find(NAME_SEPARATORS)?;
+    let (prefix, rest) = (&name[..index], &name[index + 1..]);
+    if prefix.is_empty() || rest.is_empty() {
+        None
+    } else {
+        Some((prefix, rest))
+    }
+}
+
+fn quoted(names: &[&str]) -> String {
+    names
+        .iter()
+        .map(|name| format!("{:?}", name))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(payload: &str) -> Vec<&'static str> {
+        ThinEdgeJsonLinter::lint(payload.as_bytes())
+            .into_iter()
+            .map(|warning| warning.code)
+            .collect()
+    }
+
+    #[test]
+    fn well_formed_payloads_raise_no_warnings() {
+        let payload = r#"{
+            "time": "2021-04-30T15:03:14Z",
+            "temperature": 25.5,
+            "location": {"alti": 2100.4, "longi": 2200.4},
+            "engine_speed": 3000
+        }"#;
+
+        assert_eq!(ThinEdgeJsonLinter::lint(payload.as_bytes()), vec![]);
+    }
+
+    #[test]
+    fn invalid_payloads_are_reported() {
+        assert_eq!(codes(r#"{"temperature":"hot"}"#), vec!["invalid-payload"]);
+        assert_eq!(codes("not json"), vec!["invalid-payload"]);
+        assert_eq!(
+            ThinEdgeJsonLinter::lint(&[0xff, 0xfe])[0].code,
+            "invalid-payload"
+        );
+    }
+
+    #[test]
+    fn timestamps_should_be_given_in_utc() {
+        assert_eq!(
+            ThinEdgeJsonLinter::lint(
+                br#"{"time":"2021-04-30T17:03:14.123+02:00","temperature":25.5}"#
+            ),
+            vec![LintWarning {
+                code: "non-utc-timestamp",
+                message: r#"The timestamp "2021-04-30T17:03:14.123+02:00" is not given in UTC"#
+                    .into(),
+                suggestion: r#"Use "2021-04-30T15:03:14.123Z""#.into(),
+            }]
+        );
+        assert_eq!(
+            codes(r#"{"time":"2021-04-30T10:03:14-05:00","temperature":25.5}"#),
+            vec!["non-utc-timestamp"]
+        );
+        assert_eq!(
+            codes(r#"{"time":"2021-04-30T15:03:14+00:00","temperature":25.5}"#),
+            Vec::<&str>::new()
+        );
+        assert_eq!(
+            codes(r#"{"engine":{"time":"2021-04-30T17:03:14+02:00","speed":3000}}"#),
+            vec!["non-utc-timestamp"]
+        );
+    }
+
+    #[test]
+    fn names_should_not_be_too_long() {
+        let long_name = "a".repeat(65);
+        let payload = format!(
+            r#"{{"{max}":1,"{long}":2,"{long}_group":{{"value":3}},"group":{{"{long}":4}}}}"#,
+            max = "a".repeat(64),
+            long = long_name
+        );
+
+        let warnings = ThinEdgeJsonLinter::lint(payload.as_bytes());
+
+        assert_eq!(
+            warnings.iter().map(|w| w.code).collect::<Vec<_>>(),
+            vec!["long-name", "long-name", "long-name"]
+        );
+        assert_eq!(
+            warnings[0].message,
+            format!(
+                "The name {:?} is 65 characters long, more than 64",
+                long_name
+            )
+        );
+    }
+
+    #[test]
+    fn root_measurements_named_after_a_group_should_be_moved_into_that_group() {
+        let payload = r#"{"location": {"alti": 2100.4}, "location_longi": 2200.4}"#;
+
+        assert_eq!(
+            ThinEdgeJsonLinter::lint(payload.as_bytes()),
+            vec![LintWarning {
+                code: "ungrouped-measurement",
+                message: r#"The measurement "location_longi" is named after the group "location""#
+                    .into(),
+                suggestion: r#"Move it into the group "location", as "longi""#.into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn root_measurements_sharing_a_prefix_should_be_grouped() {
+        let payload = r#"{
+            "engine_speed": 3000,
+            "engine.temp": 80,
+            "pressure": 98,
+            "wind_speed": 12.5,
+            "_private": 1,
+            "engine_": 2
+        }"#;
+
+        assert_eq!(
+            ThinEdgeJsonLinter::lint(payload.as_bytes()),
+            vec![LintWarning {
+                code: "common-prefix",
+                message:
+                    r#"The measurements "engine_speed", "engine.temp" share the prefix "engine""#
+                        .into(),
+                suggestion: r#"Move them into a group "engine", as "speed", "temp""#.into(),
+            }]
+        );
+    }
+}
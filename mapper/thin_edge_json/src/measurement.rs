@@ -1,5 +1,8 @@
+use crate::context::MeasurementContext;
 use chrono::offset::FixedOffset;
 use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 
 /// The `FlatMeasurementVisitor` trait represents the capability to collect/visit a series of measurements.
 ///
@@ -97,6 +100,40 @@ pub trait FlatMeasurementVisitor {
     ) -> Result<(), Self::Error>;
 }
 
+/// The quality of a measured value, as reported by its source.
+///
+/// New qualities might be added, hence a match on a quality must have a wildcard arm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+#[non_exhaustive]
+pub enum MeasurementQuality {
+    /// The value can be trusted
+    Good,
+
+    /// The value is known to be wrong, as when the sensor is out of order
+    Bad,
+
+    /// The value might be wrong, as when the sensor is out of its calibration range
+    Uncertain,
+}
+
+impl MeasurementQuality {
+    /// The name of the quality, as written in thin-edge JSON: `GOOD`, `BAD` or `UNCERTAIN`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MeasurementQuality::Good => "GOOD",
+            MeasurementQuality::Bad => "BAD",
+            MeasurementQuality::Uncertain => "UNCERTAIN",
+        }
+    }
+}
+
+impl fmt::Display for MeasurementQuality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 /// The `GroupedMeasurementVisitor` trait represents the capability
 /// to collect/visit a series of measurements that have *already* been arranged in groups.
 ///
@@ -179,4 +216,167 @@ pub trait GroupedMeasurementVisitor {
 
     /// Add a new measurement, attached to the current group if any
     fn end_group(&mut self) -> Result<(), Self::Error>;
+
+    /// Add a new measurement given with its unit, attached to the current group if any
+    ///
+    /// By default, the unit is ignored and the value is added as a plain measurement.
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        _unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.measurement(name, value)
+    }
+
+    /// Start to gather measurements for a group having its own timestamp
+    ///
+    /// By default, the timestamp is ignored and a plain group is started.
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        _timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.start_group(group)
+    }
+
+    /// Add a new measurement which value might be missing, attached to the current group if any
+    ///
+    /// By default, a missing value is ignored and a present one is added as a plain measurement.
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        match value {
+            Some(value) => self.measurement(name, value),
+            None => Ok(()),
+        }
+    }
+
+    /// Add a new complex measurement, given by its real and imaginary parts
+    ///
+    /// By default, the value is added as a group of two measurements `re` and `im`,
+    /// as given in thin-edge JSON; hence a complex measurement cannot be added within a group.
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.start_group(name)?;
+        self.measurement("re", real)?;
+        self.measurement("im", imaginary)?;
+        self.end_group()
+    }
+
+    /// Add a new measurement annotated with the quality of its value,
+    /// attached to the current group if any
+    ///
+    /// By default, the quality is ignored and the value is added as a plain measurement.
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        _quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.measurement(name, value)
+    }
+
+    /// Set the trace context of the measurements of this serie
+    ///
+    /// By default, the context is ignored.
+    fn set_context(&mut self, _context: MeasurementContext) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<V> GroupedMeasurementVisitor for &mut V
+where
+    V: GroupedMeasurementVisitor + ?Sized,
+{
+    type Error = V::Error;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        (**self).timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        (**self).measurement(name, value)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        (**self).start_group(group)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        (**self).end_group()
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        (**self).measurement_with_unit(name, value, unit)
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        (**self).start_group_with_timestamp(group, timestamp)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        (**self).nullable_measurement(name, value)
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        (**self).complex_measurement(name, real, imaginary)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        (**self).annotated_measurement(name, value, quality)
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        (**self).set_context(context)
+    }
+}
+
+/// The prefix of the metadata keys, as `_schema` or `_interpolated`,
+/// which tells the metadata fields of a message from its measurements.
+pub const METADATA_KEY_PREFIX: &str = "_";
+
+/// A visitor accepting metadata fields, giving some context to the measurements of a message.
+///
+/// The metadata keys are expected to start with `METADATA_KEY_PREFIX`.
+pub trait MetadataVisitor: GroupedMeasurementVisitor {
+    /// Add a string metadata field to the current message
+    fn metadata(&mut self, key: &str, value: &str) -> Result<(), Self::Error>;
+
+    /// Add a boolean metadata field to the current message
+    fn metadata_flag(&mut self, key: &str, value: bool) -> Result<(), Self::Error>;
+}
+
+impl<V> MetadataVisitor for &mut V
+where
+    V: MetadataVisitor + ?Sized,
+{
+    fn metadata(&mut self, key: &str, value: &str) -> Result<(), Self::Error> {
+        (**self).metadata(key, value)
+    }
+
+    fn metadata_flag(&mut self, key: &str, value: bool) -> Result<(), Self::Error> {
+        (**self).metadata_flag(key, value)
+    }
 }
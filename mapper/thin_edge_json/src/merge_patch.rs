@@ -0,0 +1,190 @@
+use crate::json::{parse_str, ThinEdgeJson, ThinEdgeJsonError, ThinEdgeJsonParserError};
+use crate::serialize::{ThinEdgeJsonSerializationError, ThinEdgeJsonSerializer};
+use json::object::Object;
+use json::JsonValue;
+
+/// Apply a JSON merge patch ([RFC 7396](https://tools.ietf.org/html/rfc7396))
+/// to a thin-edge JSON payload.
+///
+/// The patch adds the measurements and groups that are not in the base payload,
+/// overwrites those that are, and removes those set to `null` in the patch.
+/// Both the base payload and the patched payload must be valid thin-edge JSON.
+///
+/// ```
+/// use thin_edge_json::merge_patch::ThinEdgeJsonMergePatch;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let base = br#"{"temperature": 20.0, "pressure": 98.0, "location": {"alti": 2100.4}}"#;
+/// let patch = br#"{"temperature": 25.0, "pressure": null, "location": {"longi": 2200.4}}"#;
+///
+/// let patched = ThinEdgeJsonMergePatch::apply(base, patch)?;
+///
+/// assert_eq!(
+///     patched,
+///     br#"{"temperature":25.0,"location":{"alti":2100.4,"longi":2200.4}}"#.to_vec()
+/// );
+/// # Ok(()) }
+/// ```
+pub struct ThinEdgeJsonMergePatch;
+
+#[derive(thiserror::Error, Debug)]
+pub enum MergePatchError {
+    #[error("Invalid base payload: {0}")]
+    InvalidBasePayload(ThinEdgeJsonParserError<ThinEdgeJsonError>),
+
+    #[error("Invalid patch: {0}")]
+    InvalidPatch(ThinEdgeJsonError),
+
+    #[error("Invalid patched payload: {0}")]
+    InvalidPatchedPayload(ThinEdgeJsonParserError<ThinEdgeJsonError>),
+
+    #[error(transparent)]
+    SerializationError(#[from] ThinEdgeJsonParserError<ThinEdgeJsonSerializationError>),
+}
+
+impl ThinEdgeJsonMergePatch {
+    pub fn apply(base: &[u8], patch: &[u8]) -> Result<Vec<u8>, MergePatchError> {
+        let base = std::str::from_utf8(base)
+            .map_err(|err| ThinEdgeJsonError::new_invalid_utf8(base, err))
+            .map_err(|err| MergePatchError::InvalidBasePayload(err.into()))?;
+        let _ = ThinEdgeJson::from_str(base).map_err(MergePatchError::InvalidBasePayload)?;
+        let base = json::parse(base).map_err(|err| {
+            MergePatchError::InvalidBasePayload(
+                ThinEdgeJsonError::new_invalid_json(base, err).into(),
+            )
+        })?;
+
+        let patch = std::str::from_utf8(patch)
+            .map_err(|err| ThinEdgeJsonError::new_invalid_utf8(patch, err))
+            .map_err(MergePatchError::InvalidPatch)?;
+        let patch = json::parse(patch)
+            .map_err(|err| ThinEdgeJsonError::new_invalid_json(patch, err))
+            .map_err(MergePatchError::InvalidPatch)?;
+
+        let patched = merge(base, patch).dump();
+        let _ = ThinEdgeJson::from_str(&patched).map_err(MergePatchError::InvalidPatchedPayload)?;
+
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        let () = parse_str(&patched, &mut serializer)?;
+        let bytes = serializer
+            .bytes()
+            .map_err(ThinEdgeJsonParserError::VisitorError)?;
+        Ok(bytes)
+    }
+}
+
+/// The MergePatch algorithm of RFC 7396, preserving the order of the keys of the target
+fn merge(target: JsonValue, patch: JsonValue) -> JsonValue {
+    match patch {
+        JsonValue::Object(patch) => {
+            let mut target = match target {
+                JsonValue::Object(target) => target,
+                _ => Object::new(),
+            };
+            for (key, value) in patch.iter() {
+                if value.is_null() {
+                    let _ = target.remove(key);
+                } else if let Some(current) = target.get_mut(key) {
+                    *current = merge(current.take(), value.clone());
+                } else {
+                    target.insert(key, merge(JsonValue::Null, value.clone()));
+                }
+            }
+            JsonValue::Object(target)
+        }
+        patch => patch,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    fn apply(base: &str, patch: &str) -> Result<String, MergePatchError> {
+        let patched = ThinEdgeJsonMergePatch::apply(base.as_bytes(), patch.as_bytes())?;
+        Ok(String::from_utf8(patched).expect("The serializer produces UTF-8"))
+    }
+
+    #[test]
+    fn new_measurements_are_added() -> anyhow::Result<()> {
+        let base = r#"{"time":"2021-04-30T17:03:14+02:00","temperature":25.5}"#;
+        let patch = r#"{"pressure":98,"location":{"alti":2100.4}}"#;
+
+        assert_eq!(
+            apply(base, patch)?,
+            r#"{"time":"2021-04-30T17:03:14+02:00","temperature":25.5,"pressure":98.0,"location":{"alti":2100.4}}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn existing_values_are_overwritten_in_place() -> anyhow::Result<()> {
+        let base =
+            r#"{"temperature":25.5,"location":{"alti":2100.4,"longi":2200.4},"pressure":98}"#;
+        let patch =
+            r#"{"temperature":30,"location":{"longi":10},"time":"2021-04-30T17:03:14+02:00"}"#;
+
+        assert_eq!(
+            apply(base, patch)?,
+            r#"{"temperature":30.0,"location":{"alti":2100.4,"longi":10.0},"pressure":98.0,"time":"2021-04-30T17:03:14+02:00"}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn null_values_remove_keys() -> anyhow::Result<()> {
+        let base = r#"{"time":"2021-04-30T17:03:14+02:00","temperature":25.5,"location":{"alti":2100.4,"longi":2200.4},"pressure":98}"#;
+        let patch = r#"{"time":null,"pressure":null,"location":{"longi":null},"unknown":null}"#;
+
+        assert_eq!(
+            apply(base, patch)?,
+            r#"{"temperature":25.5,"location":{"alti":2100.4}}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn a_group_can_replace_a_measurement() -> anyhow::Result<()> {
+        let base = r#"{"temperature":25.5}"#;
+        let patch = r#"{"temperature":{"engine":80}}"#;
+
+        assert_eq!(apply(base, patch)?, r#"{"temperature":{"engine":80.0}}"#);
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_base_payloads() {
+        assert_matches!(
+            apply(r#"{"temperature":"hot"}"#, r#"{}"#),
+            Err(MergePatchError::InvalidBasePayload(_))
+        );
+    }
+
+    #[test]
+    fn reject_invalid_patches() {
+        assert_matches!(
+            apply(r#"{"temperature":25.5}"#, r#"{"temperature":"#),
+            Err(MergePatchError::InvalidPatch(_))
+        );
+    }
+
+    #[test]
+    fn reject_patches_producing_invalid_payloads() {
+        assert_matches!(
+            apply(r#"{"temperature":25.5}"#, r#"{"temperature":null}"#),
+            Err(MergePatchError::InvalidPatchedPayload(_))
+        );
+        assert_matches!(
+            apply(r#"{"temperature":25.5}"#, r#"{"pressure":"high"}"#),
+            Err(MergePatchError::InvalidPatchedPayload(_))
+        );
+        assert_matches!(
+            apply(
+                r#"{"location":{"alti":2100.4}}"#,
+                r#"{"location":{"alti":null}}"#
+            ),
+            Err(MergePatchError::InvalidPatchedPayload(_))
+        );
+    }
+}
@@ -0,0 +1,320 @@
+use crate::context::MeasurementContext;
+use crate::filter::PendingGroup;
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+
+/// Hooks called by a `MiddlewareVisitor` around each measurement it forwards.
+///
+/// A measurement is given to the hooks by its name,
+/// or by `<group>.<name>` for a measurement attached to a group.
+/// The hooks are called for the measurements with a value, i.e. not for missing values
+/// nor for complex measurements, which are forwarded as is.
+pub trait VisitorMiddleware {
+    /// Called before a measurement is forwarded, the measurement being dropped if `false` is returned.
+    fn before_measurement(&mut self, _name: &str, _value: f64) -> bool {
+        true
+    }
+
+    /// Called after a measurement has been forwarded, with the outcome of the inner visitor,
+    /// even when this outcome is an error.
+    ///
+    /// The returned result is the one returned by the `MiddlewareVisitor`,
+    /// by default the outcome of the inner visitor.
+    fn after_measurement<E>(
+        &mut self,
+        _name: &str,
+        _value: f64,
+        result: Result<(), E>,
+    ) -> Result<(), E>
+    where
+        E: std::error::Error,
+    {
+        result
+    }
+}
+
+/// A visitor that calls the hooks of a `VisitorMiddleware` around each measurement.
+///
+/// A group is only forwarded if at least one of its measurements is.
+///
+/// ```
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::middleware::{MiddlewareVisitor, VisitorMiddleware};
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+///
+/// /// Drop the negative values
+/// struct Positive;
+///
+/// impl VisitorMiddleware for Positive {
+///     fn before_measurement(&mut self, _name: &str, value: f64) -> bool {
+///         value >= 0.0
+///     }
+/// }
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let mut visitor = MiddlewareVisitor::new(Positive, ThinEdgeJsonSerializer::new());
+///
+/// visitor.measurement("temperature", 25.0)?;
+/// visitor.measurement("pressure", -1.0)?;
+///
+/// assert_eq!(visitor.into_inner().into_string()?, r#"{"temperature":25.0}"#);
+/// # Ok(()) }
+/// ```
+pub struct MiddlewareVisitor<M, V> {
+    middleware: M,
+    group: PendingGroup,
+    inner: V,
+}
+
+impl<M, V> MiddlewareVisitor<M, V> {
+    pub fn new(middleware: M, inner: V) -> Self {
+        Self {
+            middleware,
+            group: PendingGroup::default(),
+            inner,
+        }
+    }
+
+    pub fn middleware(&self) -> &M {
+        &self.middleware
+    }
+
+    pub fn middleware_mut(&mut self) -> &mut M {
+        &mut self.middleware
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut V {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> V {
+        self.inner
+    }
+}
+
+impl<M, V> MiddlewareVisitor<M, V>
+where
+    M: VisitorMiddleware,
+    V: GroupedMeasurementVisitor,
+{
+    fn around<F>(&mut self, name: &str, value: f64, forward: F) -> Result<(), V::Error>
+    where
+        F: FnOnce(&mut V) -> Result<(), V::Error>,
+    {
+        let key = self.group.key(name);
+        if !self.middleware.before_measurement(&key, value) {
+            return Ok(());
+        }
+
+        let result = match self.group.forward_start(&mut self.inner) {
+            Ok(()) => forward(&mut self.inner),
+            Err(err) => Err(err),
+        };
+        self.middleware.after_measurement(&key, value, result)
+    }
+}
+
+impl<M, V> GroupedMeasurementVisitor for MiddlewareVisitor<M, V>
+where
+    M: VisitorMiddleware,
+    V: GroupedMeasurementVisitor,
+{
+    type Error = V::Error;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.inner.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.around(name, value, |inner| inner.measurement(name, value))
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.group.start(group, &mut self.inner)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.group.end(&mut self.inner)
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.around(name, value, |inner| {
+            inner.measurement_with_unit(name, value, unit)
+        })
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.group
+            .start_with_timestamp(group, timestamp, &mut self.inner)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        match value {
+            Some(value) => self.around(name, value, |inner| {
+                inner.nullable_measurement(name, Some(value))
+            }),
+            None => {
+                self.group.forward_start(&mut self.inner)?;
+                self.inner.nullable_measurement(name, None)
+            }
+        }
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.group.forward_start(&mut self.inner)?;
+        self.inner.complex_measurement(name, real, imaginary)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.around(name, value, |inner| {
+            inner.annotated_measurement(name, value, quality)
+        })
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.inner.set_context(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::ThinEdgeJsonSerializer;
+
+    /// Drop the measurements with a name starting with `_`, in a group or not,
+    /// and record the outcome of the others
+    #[derive(Default)]
+    struct Recorder {
+        before: Vec<String>,
+        after: Vec<(String, bool)>,
+    }
+
+    impl VisitorMiddleware for Recorder {
+        fn before_measurement(&mut self, name: &str, _value: f64) -> bool {
+            self.before.push(name.to_string());
+            let local_name = name.rsplit('.').next().unwrap_or(name);
+            !local_name.starts_with('_')
+        }
+
+        fn after_measurement<E>(
+            &mut self,
+            name: &str,
+            _value: f64,
+            result: Result<(), E>,
+        ) -> Result<(), E>
+        where
+            E: std::error::Error,
+        {
+            self.after.push((name.to_string(), result.is_ok()));
+            result
+        }
+    }
+
+    #[test]
+    fn measurements_rejected_before_are_not_forwarded() -> anyhow::Result<()> {
+        let mut visitor =
+            MiddlewareVisitor::new(Recorder::default(), ThinEdgeJsonSerializer::new());
+
+        visitor.measurement("temperature", 25.0)?;
+        visitor.measurement("_debug", 1.0)?;
+        visitor.start_group("location")?;
+        visitor.measurement_with_unit("alti", 2100.4, "m")?;
+        visitor.end_group()?;
+        visitor.start_group("internal")?;
+        visitor.measurement("_counter", 42.0)?;
+        visitor.end_group()?;
+
+        assert_eq!(
+            visitor.middleware().before,
+            vec![
+                "temperature",
+                "_debug",
+                "location.alti",
+                "internal._counter"
+            ]
+        );
+        assert_eq!(
+            visitor.middleware().after,
+            vec![
+                ("temperature".to_string(), true),
+                ("location.alti".to_string(), true)
+            ]
+        );
+        assert_eq!(
+            visitor.into_inner().into_string()?,
+            r#"{"temperature":25.0,"location":{"alti":{"value":2100.4,"unit":"m"}}}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn after_is_called_when_the_inner_visitor_fails() {
+        let mut visitor = MiddlewareVisitor::new(
+            Recorder::default(),
+            ThinEdgeJsonSerializer::new().with_duplicate_detection(true),
+        );
+
+        assert!(visitor.measurement("temperature", 25.0).is_ok());
+        assert!(visitor.measurement("temperature", 26.0).is_err());
+
+        assert_eq!(
+            visitor.middleware().after,
+            vec![
+                ("temperature".to_string(), true),
+                ("temperature".to_string(), false)
+            ]
+        );
+    }
+
+    #[test]
+    fn after_can_change_the_outcome() -> anyhow::Result<()> {
+        /// Ignore the errors of the inner visitor
+        struct Lenient;
+
+        impl VisitorMiddleware for Lenient {
+            fn after_measurement<E>(&mut self, _: &str, _: f64, _: Result<(), E>) -> Result<(), E>
+            where
+                E: std::error::Error,
+            {
+                Ok(())
+            }
+        }
+
+        let mut visitor = MiddlewareVisitor::new(
+            Lenient,
+            ThinEdgeJsonSerializer::new().with_duplicate_detection(true),
+        );
+        visitor.measurement("temperature", 25.0)?;
+        visitor.measurement("temperature", 26.0)?;
+
+        assert_eq!(
+            visitor.into_inner().into_string()?,
+            r#"{"temperature":25.0}"#
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,481 @@
+use crate::context::MeasurementContext;
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality, MetadataVisitor};
+use crate::serialize::{ThinEdgeJsonSerializationError, ThinEdgeJsonSerializer};
+use crate::version::SCHEMA_VERSION_KEY;
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+
+/// The metadata fields moved to MQTT 5.0 user properties, with the name of their property
+const USER_PROPERTIES: [(&str, &str); 3] = [
+    ("device_id", "device_id"),
+    (SCHEMA_VERSION_KEY, "schema_version"),
+    ("group_name", "group_name"),
+];
+
+/// The content type of thin-edge JSON payloads
+pub const THIN_EDGE_JSON_CONTENT_TYPE: &str = "application/vnd.thin-edge+json";
+
+/// The content type of thin-edge JSON payloads, for the consumers only aware of plain JSON
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// The specification of thin-edge JSON, given as correlation data of the MQTT 5.0 messages
+pub const THIN_EDGE_JSON_SCHEMA_URL: &str =
+    "https://github.com/thin-edge/thin-edge.io/blob/main/docs/src/architecture/thin-edge-json.md";
+
+/// A visitor that moves the `device_id`, `_schema` and `group_name` metadata fields
+/// out of the payload, to be set as MQTT 5.0 user properties on the publish call.
+///
+/// The user properties are named `device_id`, `schema_version` and `group_name`,
+/// and are given as `(name, value)` pairs, as the `user_properties` of the publish properties
+/// of `rumqttc::v5`. The other metadata fields and all the measurements are forwarded
+/// unchanged to the inner visitor, which is then left with the measurement data only.
+///
+/// ```
+/// use thin_edge_json::measurement::{GroupedMeasurementVisitor, MetadataVisitor};
+/// use thin_edge_json::mqtt5::Mqtt5UserPropertiesVisitor;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let mut visitor = Mqtt5UserPropertiesVisitor::new(ThinEdgeJsonSerializer::new());
+///
+/// visitor.metadata("device_id", "device-1")?;
+/// visitor.measurement("temperature", 25.5)?;
+///
+/// assert_eq!(
+///     visitor.take_user_properties(),
+///     vec![("device_id".to_string(), "device-1".to_string())]
+/// );
+/// assert_eq!(visitor.into_inner().into_string()?, r#"{"temperature":25.5}"#);
+/// # Ok(()) }
+/// ```
+pub struct Mqtt5UserPropertiesVisitor<V> {
+    user_properties: Vec<(String, String)>,
+    inner: V,
+}
+
+impl<V> Mqtt5UserPropertiesVisitor<V> {
+    pub fn new(inner: V) -> Self {
+        Self {
+            user_properties: Vec::new(),
+            inner,
+        }
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut V {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> V {
+        self.inner
+    }
+
+    /// The user properties extracted so far, in order
+    pub fn user_properties(&self) -> &[(String, String)] {
+        &self.user_properties
+    }
+
+    /// Take the user properties extracted so far, to be set on the publish call of the payload
+    pub fn take_user_properties(&mut self) -> Vec<(String, String)> {
+        std::mem::take(&mut self.user_properties)
+    }
+}
+
+impl<V> GroupedMeasurementVisitor for Mqtt5UserPropertiesVisitor<V>
+where
+    V: GroupedMeasurementVisitor,
+{
+    type Error = V::Error;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.inner.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.inner.measurement(name, value)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.inner.start_group(group)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.inner.end_group()
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.inner.measurement_with_unit(name, value, unit)
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.inner.start_group_with_timestamp(group, timestamp)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        self.inner.nullable_measurement(name, value)
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.inner.complex_measurement(name, real, imaginary)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.inner.annotated_measurement(name, value, quality)
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.inner.set_context(context)
+    }
+}
+
+impl<V> MetadataVisitor for Mqtt5UserPropertiesVisitor<V>
+where
+    V: MetadataVisitor,
+{
+    fn metadata(&mut self, key: &str, value: &str) -> Result<(), Self::Error> {
+        match USER_PROPERTIES
+            .iter()
+            .find(|(metadata_key, _)| *metadata_key == key)
+        {
+            Some((_, property)) => {
+                self.user_properties
+                    .push((property.to_string(), value.to_string()));
+                Ok(())
+            }
+            None => self.inner.metadata(key, value),
+        }
+    }
+
+    fn metadata_flag(&mut self, key: &str, value: bool) -> Result<(), Self::Error> {
+        self.inner.metadata_flag(key, value)
+    }
+}
+
+/// The MQTT 5.0 properties of a publish packet,
+/// as the `PublishProperties` of `rumqttc::v5`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Mqtt5PublishProperties {
+    pub content_type: Option<String>,
+    pub correlation_data: Option<Vec<u8>>,
+    pub user_properties: Vec<(String, String)>,
+}
+
+/// An MQTT 5.0 publish packet, to be sent by an MQTT 5.0 client.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mqtt5Publish {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub properties: Mqtt5PublishProperties,
+}
+
+/// A visitor that gathers measurements into thin-edge JSON MQTT 5.0 publish packets,
+/// whose content type is given as a publish property.
+///
+/// The content type is `application/vnd.thin-edge+json`,
+/// or `application/json` in compatibility mode, for consumers only aware of plain JSON.
+/// The URL of the thin-edge JSON specification is given as correlation data.
+///
+/// ```
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::mqtt5::{ContentTypedMqttVisitor, THIN_EDGE_JSON_CONTENT_TYPE};
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let mut visitor = ContentTypedMqttVisitor::new("tedge/measurements");
+///
+/// visitor.measurement("temperature", 25.5)?;
+/// let publish = visitor.flush()?.expect("A packet to publish");
+///
+/// assert_eq!(publish.topic, "tedge/measurements");
+/// assert_eq!(publish.payload, br#"{"temperature":25.5}"#.to_vec());
+/// assert_eq!(
+///     publish.properties.content_type.as_deref(),
+///     Some(THIN_EDGE_JSON_CONTENT_TYPE)
+/// );
+/// # Ok(()) }
+/// ```
+pub struct ContentTypedMqttVisitor {
+    topic: String,
+    content_type: &'static str,
+    serializer: ThinEdgeJsonSerializer,
+    is_empty: bool,
+}
+
+impl ContentTypedMqttVisitor {
+    pub fn new(topic: &str) -> Self {
+        Self {
+            topic: topic.to_string(),
+            content_type: THIN_EDGE_JSON_CONTENT_TYPE,
+            serializer: ThinEdgeJsonSerializer::new(),
+            is_empty: true,
+        }
+    }
+
+    /// Publish the payloads with the `application/json` content type.
+    pub fn with_compatibility_mode(self) -> Self {
+        Self {
+            content_type: JSON_CONTENT_TYPE,
+            ..self
+        }
+    }
+
+    /// The packet to publish the measurements gathered since the previous flush.
+    ///
+    /// `None` is returned if no measurements have been gathered.
+    pub fn flush(&mut self) -> Result<Option<Mqtt5Publish>, ThinEdgeJsonSerializationError> {
+        let mut serializer = std::mem::take(&mut self.serializer);
+        let is_empty = std::mem::replace(&mut self.is_empty, true);
+        let payload = serializer.into_string()?;
+        if is_empty {
+            return Ok(None);
+        }
+
+        Ok(Some(Mqtt5Publish {
+            topic: self.topic.clone(),
+            payload: payload.into_bytes(),
+            properties: Mqtt5PublishProperties {
+                content_type: Some(self.content_type.to_string()),
+                correlation_data: Some(THIN_EDGE_JSON_SCHEMA_URL.as_bytes().to_vec()),
+                user_properties: Vec::new(),
+            },
+        }))
+    }
+}
+
+impl GroupedMeasurementVisitor for ContentTypedMqttVisitor {
+    type Error = ThinEdgeJsonSerializationError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.serializer.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.measurement(name, value)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.serializer.start_group(group)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.serializer.end_group()
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.measurement_with_unit(name, value, unit)
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.serializer.start_group_with_timestamp(group, timestamp)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.nullable_measurement(name, value)
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.complex_measurement(name, real, imaginary)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.is_empty = false;
+        self.serializer.annotated_measurement(name, value, quality)
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.serializer.set_context(context)
+    }
+}
+
+impl MetadataVisitor for ContentTypedMqttVisitor {
+    fn metadata(&mut self, key: &str, value: &str) -> Result<(), Self::Error> {
+        self.serializer.metadata(key, value)
+    }
+
+    fn metadata_flag(&mut self, key: &str, value: bool) -> Result<(), Self::Error> {
+        self.serializer.metadata_flag(key, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn property(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn metadata_fields_are_moved_to_user_properties() -> anyhow::Result<()> {
+        let mut visitor = Mqtt5UserPropertiesVisitor::new(ThinEdgeJsonSerializer::new());
+
+        visitor.metadata("device_id", "device-1")?;
+        visitor.metadata(SCHEMA_VERSION_KEY, "te/1.2")?;
+        visitor.metadata("group_name", "engine")?;
+        visitor.measurement("temperature", 25.5)?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.end_group()?;
+
+        assert_eq!(
+            visitor.user_properties(),
+            &[
+                property("device_id", "device-1"),
+                property("schema_version", "te/1.2"),
+                property("group_name", "engine"),
+            ]
+        );
+        assert_eq!(
+            visitor.into_inner().into_string()?,
+            r#"{"temperature":25.5,"location":{"alti":2100.4}}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn other_metadata_fields_are_kept_in_the_payload() -> anyhow::Result<()> {
+        let mut visitor = Mqtt5UserPropertiesVisitor::new(ThinEdgeJsonSerializer::new());
+
+        visitor.metadata("device_id", "device-1")?;
+        visitor.metadata("_source", "collectd")?;
+        visitor.metadata_flag("_interpolated", true)?;
+        visitor.measurement("temperature", 25.5)?;
+
+        assert_eq!(
+            visitor.take_user_properties(),
+            vec![property("device_id", "device-1")]
+        );
+        assert!(visitor.user_properties().is_empty());
+        assert_eq!(
+            visitor.into_inner().into_string()?,
+            r#"{"_source":"collectd","_interpolated":true,"temperature":25.5}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn the_schema_version_of_the_serializer_is_not_a_user_property() -> anyhow::Result<()> {
+        // Only the metadata given to the visitor can be moved, not the fields added by the sink
+        let mut visitor = Mqtt5UserPropertiesVisitor::new(
+            ThinEdgeJsonSerializer::new().with_schema_version(1, 2),
+        );
+
+        visitor.measurement("temperature", 25.5)?;
+
+        assert!(visitor.user_properties().is_empty());
+        assert_eq!(
+            visitor.into_inner().into_string()?,
+            r#"{"_schema":"te/1.2","temperature":25.5}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn the_content_type_and_schema_url_are_set_on_each_publish() -> anyhow::Result<()> {
+        let mut visitor = ContentTypedMqttVisitor::new("tedge/measurements");
+
+        visitor.measurement("temperature", 25.5)?;
+        let first = visitor.flush()?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.end_group()?;
+        let second = visitor.flush()?;
+
+        let expected_properties = Mqtt5PublishProperties {
+            content_type: Some("application/vnd.thin-edge+json".to_string()),
+            correlation_data: Some(THIN_EDGE_JSON_SCHEMA_URL.as_bytes().to_vec()),
+            user_properties: vec![],
+        };
+        assert_eq!(
+            first,
+            Some(Mqtt5Publish {
+                topic: "tedge/measurements".to_string(),
+                payload: br#"{"temperature":25.5}"#.to_vec(),
+                properties: expected_properties.clone(),
+            })
+        );
+        assert_eq!(
+            second,
+            Some(Mqtt5Publish {
+                topic: "tedge/measurements".to_string(),
+                payload: br#"{"location":{"alti":2100.4}}"#.to_vec(),
+                properties: expected_properties,
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn plain_json_is_the_content_type_in_compatibility_mode() -> anyhow::Result<()> {
+        let mut visitor =
+            ContentTypedMqttVisitor::new("tedge/measurements").with_compatibility_mode();
+
+        visitor.measurement("temperature", 25.5)?;
+        let publish = visitor.flush()?.expect("A packet to publish");
+
+        assert_eq!(
+            publish.properties.content_type.as_deref(),
+            Some("application/json")
+        );
+        assert_eq!(
+            publish.properties.correlation_data.as_deref(),
+            Some(THIN_EDGE_JSON_SCHEMA_URL.as_bytes())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn nothing_is_published_without_measurements() -> anyhow::Result<()> {
+        let mut visitor = ContentTypedMqttVisitor::new("tedge/measurements");
+
+        assert_eq!(visitor.flush()?, None);
+
+        visitor.metadata("_device_id", "device-1")?;
+        assert_eq!(visitor.flush()?, None);
+        Ok(())
+    }
+}
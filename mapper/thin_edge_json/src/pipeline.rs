@@ -0,0 +1,251 @@
+use crate::dyn_visitor::DynGroupedMeasurementVisitor;
+use crate::filter::FilteringVisitor;
+use crate::rate_limit::RateLimitingVisitor;
+use crate::units::{UnitConversions, UnitConvertingVisitor};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The stages measurements go through before reaching a sink.
+///
+/// A pipeline is described in TOML as a list of stages,
+/// the first stage being the first to process the measurements:
+///
+/// ```toml
+/// stages = [
+///     { type = "filter", pattern = "temp_*" },
+///     { type = "unit_convert", file = "units.toml" },
+///     { type = "rate_limit", interval_ms = 1000 },
+/// ]
+/// ```
+///
+/// See `UnitConversions` for the format of the unit conversion files.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineConfig {
+    #[serde(default)]
+    pub stages: Vec<StageConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum StageConfig {
+    /// Only forward the measurements matching a glob pattern
+    Filter { pattern: String },
+
+    /// Convert the measurements using the conversions defined in a TOML file
+    UnitConvert { file: PathBuf },
+
+    /// Forward each measurement at most once per interval
+    RateLimit { interval_ms: u64 },
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum PipelineConfigError {
+    #[error("Failed to read {path:?}: {from}")]
+    FileReadError { path: PathBuf, from: std::io::Error },
+
+    #[error("Invalid pipeline configuration: {0}")]
+    InvalidPipelineConfig(#[source] toml::de::Error),
+
+    #[error("Invalid unit conversions in {path:?}: {from}")]
+    InvalidUnitConversions {
+        path: PathBuf,
+        from: toml::de::Error,
+    },
+}
+
+impl PipelineConfig {
+    pub fn from_toml_str(config: &str) -> Result<Self, PipelineConfigError> {
+        toml::from_str(config).map_err(PipelineConfigError::InvalidPipelineConfig)
+    }
+
+    /// Load a pipeline configuration file.
+    ///
+    /// The relative paths of the unit conversion files are resolved
+    /// from the directory of the pipeline configuration file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, PipelineConfigError> {
+        let path = path.as_ref();
+        let mut config = PipelineConfig::from_toml_str(&read_file(path)?)?;
+
+        if let Some(dir) = path.parent() {
+            for stage in config.stages.iter_mut() {
+                if let StageConfig::UnitConvert { file } = stage {
+                    if file.is_relative() {
+                        *file = dir.join(&file);
+                    }
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    /// Chain the stages of this pipeline in front of the given sink.
+    pub fn build_pipeline<'a>(
+        &self,
+        sink: Box<dyn DynGroupedMeasurementVisitor + 'a>,
+    ) -> Result<Box<dyn DynGroupedMeasurementVisitor + 'a>, PipelineConfigError> {
+        let mut pipeline = sink;
+        for stage in self.stages.iter().rev() {
+            pipeline = match stage {
+                StageConfig::Filter { pattern } => {
+                    Box::new(FilteringVisitor::new(pattern, pipeline))
+                }
+                StageConfig::UnitConvert { file } => {
+                    let conversions = load_unit_conversions(file)?;
+                    Box::new(UnitConvertingVisitor::new(conversions, pipeline))
+                }
+                StageConfig::RateLimit { interval_ms } => {
+                    let interval = Duration::from_millis(*interval_ms);
+                    Box::new(RateLimitingVisitor::new(interval, pipeline))
+                }
+            };
+        }
+        Ok(pipeline)
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, PipelineConfigError> {
+    std::fs::read_to_string(path).map_err(|from| PipelineConfigError::FileReadError {
+        path: path.into(),
+        from,
+    })
+}
+
+fn load_unit_conversions(path: &Path) -> Result<UnitConversions, PipelineConfigError> {
+    toml::from_str(&read_file(path)?).map_err(|from| PipelineConfigError::InvalidUnitConversions {
+        path: path.into(),
+        from,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dyn_visitor::BoxedError;
+    use crate::serialize::ThinEdgeJsonSerializer;
+    use assert_matches::assert_matches;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    const PIPELINE: &str = r#"
+        stages = [
+            { type = "filter", pattern = "*temp*" },
+            { type = "unit_convert", file = "units.toml" },
+            { type = "rate_limit", interval_ms = 60000 },
+        ]
+    "#;
+
+    const UNITS: &str = r#"
+        [temperature]
+        factor = 1.8
+        offset = 32
+        unit = "°F"
+
+        ["engine.temp"]
+        offset = -273
+        unit = "°C"
+    "#;
+
+    fn write_file(dir: &TempDir, name: &str, content: &str) -> PathBuf {
+        let path = dir.path().join(name);
+        let mut file = std::fs::File::create(&path).unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        path
+    }
+
+    #[test]
+    fn parse_pipeline_config() -> anyhow::Result<()> {
+        let config = PipelineConfig::from_toml_str(PIPELINE)?;
+
+        assert_eq!(
+            config.stages,
+            vec![
+                StageConfig::Filter {
+                    pattern: "*temp*".into()
+                },
+                StageConfig::UnitConvert {
+                    file: "units.toml".into()
+                },
+                StageConfig::RateLimit { interval_ms: 60000 },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reject_unknown_stages() {
+        let config = r#"stages = [ { type = "compress", level = 9 } ]"#;
+
+        assert_matches!(
+            PipelineConfig::from_toml_str(config),
+            Err(PipelineConfigError::InvalidPipelineConfig(_))
+        );
+    }
+
+    #[test]
+    fn unit_files_are_resolved_from_the_config_dir() -> anyhow::Result<()> {
+        let dir = TempDir::new()?;
+        let path = write_file(&dir, "pipeline.toml", PIPELINE);
+
+        let config = PipelineConfig::from_file(&path)?;
+
+        assert_eq!(
+            config.stages[1],
+            StageConfig::UnitConvert {
+                file: dir.path().join("units.toml")
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn the_pipeline_applies_each_stage() -> Result<(), BoxedError> {
+        let dir = TempDir::new()?;
+        write_file(&dir, "units.toml", UNITS);
+        let config = PipelineConfig::from_file(write_file(&dir, "pipeline.toml", PIPELINE))?;
+
+        let mut first = ThinEdgeJsonSerializer::new();
+        let mut second = ThinEdgeJsonSerializer::new();
+        {
+            let mut pipeline = config.build_pipeline(Box::new(&mut first))?;
+            pipeline.measurement("temperature", 25.0)?;
+            pipeline.measurement("pressure", 98.0)?;
+            pipeline.start_group("engine")?;
+            pipeline.measurement("temp", 353.0)?;
+            pipeline.measurement("speed", 3000.0)?;
+            pipeline.end_group()?;
+        }
+        {
+            // A new pipeline comes with a new rate limiter
+            let mut pipeline = config.build_pipeline(Box::new(&mut second))?;
+            pipeline.measurement("temperature", 25.0)?;
+            pipeline.measurement("temperature", 26.0)?;
+        }
+
+        assert_eq!(
+            first.into_string()?,
+            r#"{"temperature":{"value":77.0,"unit":"°F"},"engine":{"temp":{"value":80.0,"unit":"°C"}}}"#
+        );
+        assert_eq!(
+            second.into_string()?,
+            r#"{"temperature":{"value":77.0,"unit":"°F"}}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn missing_unit_files_are_reported() -> anyhow::Result<()> {
+        let config = PipelineConfig::from_toml_str(
+            r#"stages = [ { type = "unit_convert", file = "/does/not/exist.toml" } ]"#,
+        )?;
+
+        let result = config.build_pipeline(Box::new(ThinEdgeJsonSerializer::new()));
+
+        assert!(matches!(
+            result,
+            Err(PipelineConfigError::FileReadError { .. })
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+use crate::context::MeasurementContext;
+use crate::filter::PendingGroup;
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use clock::{Clock, Timestamp, WallClock};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// A visitor that forwards each measurement at most once per interval.
+///
+/// A measurement, identified by its name or by `<group>.<name>` for grouped measurements,
+/// is dropped when the same measurement has been forwarded less than `interval` ago.
+/// A group is only forwarded if at least one of its measurements is.
+///
+/// ```
+/// use std::time::Duration;
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::rate_limit::RateLimitingVisitor;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let mut visitor =
+///     RateLimitingVisitor::new(Duration::from_secs(60), ThinEdgeJsonSerializer::new());
+///
+/// visitor.measurement("temperature", 25.0)?;
+/// let first = std::mem::replace(visitor.inner_mut(), ThinEdgeJsonSerializer::new());
+///
+/// visitor.measurement("temperature", 26.0)?;
+/// let second = std::mem::replace(visitor.inner_mut(), ThinEdgeJsonSerializer::new());
+///
+/// assert_eq!(first.bytes()?, br#"{"temperature":25.0}"#);
+/// assert_eq!(second.bytes()?, b"{}");
+/// # Ok(()) }
+/// ```
+pub struct RateLimitingVisitor<V> {
+    interval: Duration,
+    clock: Box<dyn Clock>,
+    last_forwarded: HashMap<String, Timestamp>,
+    group: PendingGroup,
+    inner: V,
+}
+
+impl<V> RateLimitingVisitor<V> {
+    pub fn new(interval: Duration, inner: V) -> Self {
+        Self::with_clock(interval, Box::new(WallClock), inner)
+    }
+
+    pub fn with_clock(interval: Duration, clock: Box<dyn Clock>, inner: V) -> Self {
+        Self {
+            interval,
+            clock,
+            last_forwarded: HashMap::new(),
+            group: PendingGroup::default(),
+            inner,
+        }
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut V {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> V {
+        self.inner
+    }
+
+    /// Tell if the measurement can be forwarded now, recording the time if so
+    fn accept(&mut self, name: &str) -> bool {
+        let key = self.group.key(name);
+        let now = self.clock.now();
+        let interval = self.interval;
+        let elapsed = |last: &Timestamp| {
+            (now - *last)
+                .to_std()
+                .map_or(false, |elapsed| elapsed >= interval)
+        };
+
+        if self.last_forwarded.get(&key).map_or(true, elapsed) {
+            self.last_forwarded.insert(key, now);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl<V> GroupedMeasurementVisitor for RateLimitingVisitor<V>
+where
+    V: GroupedMeasurementVisitor,
+{
+    type Error = V::Error;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.inner.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        if self.accept(name) {
+            self.group.forward_start(&mut self.inner)?;
+            self.inner.measurement(name, value)?;
+        }
+        Ok(())
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.group.start(group, &mut self.inner)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.group.end(&mut self.inner)
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        if self.accept(name) {
+            self.group.forward_start(&mut self.inner)?;
+            self.inner.measurement_with_unit(name, value, unit)?;
+        }
+        Ok(())
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.group
+            .start_with_timestamp(group, timestamp, &mut self.inner)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        if self.accept(name) {
+            self.group.forward_start(&mut self.inner)?;
+            self.inner.nullable_measurement(name, value)?;
+        }
+        Ok(())
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        if self.accept(name) {
+            self.group.forward_start(&mut self.inner)?;
+            self.inner.complex_measurement(name, real, imaginary)?;
+        }
+        Ok(())
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        if self.accept(name) {
+            self.group.forward_start(&mut self.inner)?;
+            self.inner.annotated_measurement(name, value, quality)?;
+        }
+        Ok(())
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.inner.set_context(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::ThinEdgeJsonSerializer;
+    use chrono::TimeZone;
+    use std::sync::{Arc, Mutex};
+
+    /// A clock that only moves forward when told so
+    #[derive(Clone)]
+    struct TestClock {
+        now: Arc<Mutex<Timestamp>>,
+    }
+
+    impl TestClock {
+        fn new() -> Self {
+            let start = FixedOffset::east(0).ymd(2021, 4, 30).and_hms(17, 0, 0);
+            Self {
+                now: Arc::new(Mutex::new(start)),
+            }
+        }
+
+        fn advance(&self, duration: Duration) {
+            let mut now = self.now.lock().unwrap();
+            *now = *now + chrono::Duration::from_std(duration).unwrap();
+        }
+    }
+
+    impl Clock for TestClock {
+        fn now(&self) -> Timestamp {
+            *self.now.lock().unwrap()
+        }
+    }
+
+    fn next_series(
+        visitor: &mut RateLimitingVisitor<ThinEdgeJsonSerializer>,
+    ) -> anyhow::Result<String> {
+        let mut serializer = std::mem::replace(visitor.inner_mut(), ThinEdgeJsonSerializer::new());
+        Ok(serializer.into_string()?)
+    }
+
+    #[test]
+    fn measurements_are_forwarded_once_per_interval() -> anyhow::Result<()> {
+        let clock = TestClock::new();
+        let mut visitor = RateLimitingVisitor::with_clock(
+            Duration::from_secs(10),
+            Box::new(clock.clone()),
+            ThinEdgeJsonSerializer::new(),
+        );
+
+        visitor.measurement("temperature", 20.0)?;
+        assert_eq!(next_series(&mut visitor)?, r#"{"temperature":20.0}"#);
+
+        clock.advance(Duration::from_secs(5));
+        visitor.measurement("temperature", 21.0)?;
+        visitor.measurement("pressure", 98.0)?;
+        assert_eq!(next_series(&mut visitor)?, r#"{"pressure":98.0}"#);
+
+        clock.advance(Duration::from_secs(5));
+        visitor.measurement("temperature", 22.0)?;
+        visitor.measurement("pressure", 99.0)?;
+        assert_eq!(next_series(&mut visitor)?, r#"{"temperature":22.0}"#);
+        Ok(())
+    }
+
+    #[test]
+    fn groups_with_no_forwarded_measurements_are_dropped() -> anyhow::Result<()> {
+        let clock = TestClock::new();
+        let mut visitor = RateLimitingVisitor::with_clock(
+            Duration::from_secs(10),
+            Box::new(clock.clone()),
+            ThinEdgeJsonSerializer::new(),
+        );
+
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.end_group()?;
+        assert_eq!(
+            next_series(&mut visitor)?,
+            r#"{"location":{"alti":2100.4}}"#
+        );
+
+        clock.advance(Duration::from_secs(1));
+        visitor.measurement("alti", 10.0)?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.5)?;
+        visitor.end_group()?;
+        assert_eq!(next_series(&mut visitor)?, r#"{"alti":10.0}"#);
+        Ok(())
+    }
+}
@@ -0,0 +1,619 @@
+use crate::context::MeasurementContext;
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use crate::trace::VisitorCall;
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// The name of the WireMock scenario enforcing the order of the recorded calls
+const SCENARIO_NAME: &str = "thin-edge-visitor-calls";
+
+/// The initial state of any WireMock scenario
+const SCENARIO_STARTED: &str = "Started";
+
+#[derive(thiserror::Error, Debug)]
+pub enum RecordingError {
+    #[error("Failed to read {path:?}: {from}")]
+    FileReadError { path: PathBuf, from: std::io::Error },
+
+    #[error("Failed to write {path:?}: {from}")]
+    FileWriteError { path: PathBuf, from: std::io::Error },
+
+    #[error("Invalid recording {path:?}: {from}")]
+    InvalidRecording {
+        path: PathBuf,
+        from: serde_json::Error,
+    },
+}
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum StubError {
+    #[error("Unexpected call #{index}: {actual:?} while {expected:?} was recorded")]
+    UnexpectedCall {
+        index: usize,
+        expected: VisitorCall,
+        actual: VisitorCall,
+    },
+
+    #[error("Unexpected call #{index}: {actual:?} while no more calls were recorded")]
+    ExtraCall { index: usize, actual: VisitorCall },
+}
+
+/// A recording, as a list of WireMock stub mappings, one per visitor call.
+///
+/// Each call is a `POST /visitor/<call>` request with the call as JSON body,
+/// the calls being chained by a WireMock scenario so they are only matched in order:
+///
+/// ```json
+/// {
+///   "mappings": [{
+///     "request": {
+///       "method": "POST",
+///       "url": "/visitor/measurement",
+///       "bodyPatterns": [{
+///         "equalToJson": {"call": "measurement", "name": "temperature", "value": 25.5}
+///       }]
+///     },
+///     "response": {"status": 200},
+///     "scenarioName": "thin-edge-visitor-calls",
+///     "requiredScenarioState": "Started",
+///     "newScenarioState": "call-1"
+///   }]
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct WireMockRecording {
+    mappings: Vec<WireMockMapping>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WireMockMapping {
+    request: WireMockRequest,
+    response: WireMockResponse,
+    scenario_name: String,
+    required_scenario_state: String,
+    new_scenario_state: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WireMockRequest {
+    method: String,
+    url: String,
+    body_patterns: Vec<WireMockBodyPattern>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WireMockBodyPattern {
+    equal_to_json: VisitorCall,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct WireMockResponse {
+    status: u16,
+}
+
+impl WireMockRecording {
+    fn new(calls: &[VisitorCall]) -> Self {
+        let mappings = calls
+            .iter()
+            .enumerate()
+            .map(|(index, call)| WireMockMapping {
+                request: WireMockRequest {
+                    method: "POST".into(),
+                    url: format!("/visitor/{}", call_name(call)),
+                    body_patterns: vec![WireMockBodyPattern {
+                        equal_to_json: call.clone(),
+                    }],
+                },
+                response: WireMockResponse { status: 200 },
+                scenario_name: SCENARIO_NAME.into(),
+                required_scenario_state: scenario_state(index),
+                new_scenario_state: scenario_state(index + 1),
+            })
+            .collect();
+        WireMockRecording { mappings }
+    }
+
+    fn into_calls(self) -> Vec<VisitorCall> {
+        self.mappings
+            .into_iter()
+            .filter_map(|mapping| mapping.request.body_patterns.into_iter().next())
+            .map(|pattern| pattern.equal_to_json)
+            .collect()
+    }
+}
+
+/// The tag of a call, as `measurement` or `start_group`
+fn call_name(call: &VisitorCall) -> String {
+    serde_json::to_value(call)
+        .ok()
+        .and_then(|json| json.get("call")?.as_str().map(String::from))
+        .unwrap_or_default()
+}
+
+fn scenario_state(index: usize) -> String {
+    if index == 0 {
+        SCENARIO_STARTED.into()
+    } else {
+        format!("call-{}", index)
+    }
+}
+
+/// A visitor that records all the calls it receives before forwarding them,
+/// so they can be saved as WireMock stub mappings and replayed by a `StubVisitor`.
+///
+/// This enables golden-master testing: the calls made by a source are recorded once,
+/// then a `StubVisitor` checks that the same source still makes the very same calls.
+///
+/// See `StubVisitor` for an example.
+pub struct RecordingVisitor<V> {
+    calls: Vec<VisitorCall>,
+    inner: V,
+}
+
+impl<V> RecordingVisitor<V> {
+    pub fn new(inner: V) -> Self {
+        Self {
+            calls: Vec::new(),
+            inner,
+        }
+    }
+
+    /// The calls recorded so far, in order
+    pub fn calls(&self) -> &[VisitorCall] {
+        &self.calls
+    }
+
+    /// The calls recorded so far, as WireMock stub mappings
+    pub fn to_json(&self) -> String {
+        let recording = WireMockRecording::new(&self.calls);
+        serde_json::to_string_pretty(&recording).expect("A recording is serializable")
+    }
+
+    /// Save the calls recorded so far into a JSON file of WireMock stub mappings
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), RecordingError> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_json()).map_err(|from| RecordingError::FileWriteError {
+            path: path.into(),
+            from,
+        })
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut V {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> V {
+        self.inner
+    }
+}
+
+impl<V> GroupedMeasurementVisitor for RecordingVisitor<V>
+where
+    V: GroupedMeasurementVisitor,
+{
+    type Error = V::Error;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.calls.push(VisitorCall::Timestamp { value });
+        self.inner.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.calls.push(VisitorCall::Measurement {
+            name: name.into(),
+            value,
+        });
+        self.inner.measurement(name, value)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.calls.push(VisitorCall::StartGroup {
+            group: group.into(),
+        });
+        self.inner.start_group(group)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.calls.push(VisitorCall::EndGroup);
+        self.inner.end_group()
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.calls.push(VisitorCall::MeasurementWithUnit {
+            name: name.into(),
+            value,
+            unit: unit.into(),
+        });
+        self.inner.measurement_with_unit(name, value, unit)
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.calls.push(VisitorCall::StartGroupWithTimestamp {
+            group: group.into(),
+            value: timestamp,
+        });
+        self.inner.start_group_with_timestamp(group, timestamp)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        self.calls.push(VisitorCall::NullableMeasurement {
+            name: name.into(),
+            value,
+        });
+        self.inner.nullable_measurement(name, value)
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.calls.push(VisitorCall::ComplexMeasurement {
+            name: name.into(),
+            real,
+            imaginary,
+        });
+        self.inner.complex_measurement(name, real, imaginary)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.calls.push(VisitorCall::AnnotatedMeasurement {
+            name: name.into(),
+            value,
+            quality,
+        });
+        self.inner.annotated_measurement(name, value, quality)
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.calls.push(VisitorCall::SetContext {
+            context: context.clone(),
+        });
+        self.inner.set_context(context)
+    }
+}
+
+/// A visitor that only accepts, in order, the calls of a recording made by a `RecordingVisitor`.
+///
+/// Any call differing from the next recorded call is rejected,
+/// and `is_complete()` tells if all the recorded calls have been received.
+/// The recorded calls can also be replayed to any visitor with `replay()`.
+///
+/// ```
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::recording::{RecordingVisitor, StubVisitor};
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// # let dir = tempfile::TempDir::new()?;
+/// # let path = dir.path().join("recording.json");
+/// let mut recorder = RecordingVisitor::new(ThinEdgeJsonSerializer::new());
+/// recorder.measurement("temperature", 25.5)?;
+/// recorder.save(&path)?;
+///
+/// let mut stub = StubVisitor::from_recording(&path)?;
+/// stub.measurement("temperature", 25.5)?;
+/// assert!(stub.is_complete());
+/// assert!(stub.measurement("temperature", 25.5).is_err());
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct StubVisitor {
+    calls: Vec<VisitorCall>,
+    received: usize,
+}
+
+impl StubVisitor {
+    pub fn new(calls: Vec<VisitorCall>) -> Self {
+        Self { calls, received: 0 }
+    }
+
+    /// Load a recording saved by a `RecordingVisitor`
+    pub fn from_recording(path: &Path) -> Result<Self, RecordingError> {
+        let json = std::fs::read_to_string(path).map_err(|from| RecordingError::FileReadError {
+            path: path.into(),
+            from,
+        })?;
+        let recording: WireMockRecording =
+            serde_json::from_str(&json).map_err(|from| RecordingError::InvalidRecording {
+                path: path.into(),
+                from,
+            })?;
+        Ok(StubVisitor::new(recording.into_calls()))
+    }
+
+    /// The recorded calls, in order
+    pub fn calls(&self) -> &[VisitorCall] {
+        &self.calls
+    }
+
+    /// The recorded calls not received yet
+    pub fn remaining(&self) -> &[VisitorCall] {
+        &self.calls[self.received..]
+    }
+
+    /// Tell if all the recorded calls have been received
+    pub fn is_complete(&self) -> bool {
+        self.received == self.calls.len()
+    }
+
+    /// Call on the given visitor all the recorded calls, in order
+    pub fn replay<V>(&self, visitor: &mut V) -> Result<(), V::Error>
+    where
+        V: GroupedMeasurementVisitor,
+    {
+        for call in self.calls.iter() {
+            call.apply(visitor)?;
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self, actual: VisitorCall) -> Result<(), StubError> {
+        let index = self.received;
+        match self.calls.get(index) {
+            Some(expected) if *expected == actual => {
+                self.received += 1;
+                Ok(())
+            }
+            Some(expected) => Err(StubError::UnexpectedCall {
+                index,
+                expected: expected.clone(),
+                actual,
+            }),
+            None => Err(StubError::ExtraCall { index, actual }),
+        }
+    }
+}
+
+impl GroupedMeasurementVisitor for StubVisitor {
+    type Error = StubError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.receive(VisitorCall::Timestamp { value })
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.receive(VisitorCall::Measurement {
+            name: name.into(),
+            value,
+        })
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.receive(VisitorCall::StartGroup {
+            group: group.into(),
+        })
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.receive(VisitorCall::EndGroup)
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.receive(VisitorCall::MeasurementWithUnit {
+            name: name.into(),
+            value,
+            unit: unit.into(),
+        })
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.receive(VisitorCall::StartGroupWithTimestamp {
+            group: group.into(),
+            value: timestamp,
+        })
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        self.receive(VisitorCall::NullableMeasurement {
+            name: name.into(),
+            value,
+        })
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.receive(VisitorCall::ComplexMeasurement {
+            name: name.into(),
+            real,
+            imaginary,
+        })
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.receive(VisitorCall::AnnotatedMeasurement {
+            name: name.into(),
+            value,
+            quality,
+        })
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.receive(VisitorCall::SetContext { context })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::ThinEdgeJsonSerializer;
+    use assert_matches::assert_matches;
+    use chrono::TimeZone;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    /// Drive a visitor with a sequence using all the kinds of calls
+    fn drive<V: GroupedMeasurementVisitor>(visitor: &mut V) -> Result<(), V::Error> {
+        let time = FixedOffset::east(2 * 3600)
+            .ymd(2021, 4, 30)
+            .and_hms(17, 3, 14);
+        visitor.timestamp(time)?;
+        visitor.measurement_with_unit("temperature", 25.5, "°C")?;
+        visitor.nullable_measurement("pressure", None)?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.complex_measurement("current", 1.5, -0.5)?;
+        visitor.end_group()?;
+        visitor.start_group_with_timestamp("engine", time)?;
+        visitor.annotated_measurement("speed", 3000.0, MeasurementQuality::Uncertain)?;
+        visitor.end_group()
+    }
+
+    #[test]
+    fn recorded_calls_are_replayed_by_the_stub() -> anyhow::Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("recording.json");
+        let mut recorder = RecordingVisitor::new(ThinEdgeJsonSerializer::new());
+        drive(&mut recorder)?;
+        recorder.save(&path)?;
+
+        let mut stub = StubVisitor::from_recording(&path)?;
+        assert_eq!(stub.calls(), recorder.calls());
+        assert_eq!(stub.remaining().len(), 10);
+
+        drive(&mut stub)?;
+        assert!(stub.is_complete());
+        assert!(stub.remaining().is_empty());
+
+        let mut replayed = ThinEdgeJsonSerializer::new();
+        stub.replay(&mut replayed)?;
+        assert_eq!(
+            replayed.into_string()?,
+            recorder.into_inner().into_string()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn calls_differing_from_the_recording_are_rejected() -> anyhow::Result<()> {
+        let mut recorder = RecordingVisitor::new(ThinEdgeJsonSerializer::new());
+        drive(&mut recorder)?;
+        let mut stub = StubVisitor::new(recorder.calls().to_vec());
+
+        stub.timestamp(
+            FixedOffset::east(2 * 3600)
+                .ymd(2021, 4, 30)
+                .and_hms(17, 3, 14),
+        )?;
+        assert_eq!(
+            stub.measurement_with_unit("temperature", 26.0, "°C"),
+            Err(StubError::UnexpectedCall {
+                index: 1,
+                expected: VisitorCall::MeasurementWithUnit {
+                    name: "temperature".into(),
+                    value: 25.5,
+                    unit: "°C".into()
+                },
+                actual: VisitorCall::MeasurementWithUnit {
+                    name: "temperature".into(),
+                    value: 26.0,
+                    unit: "°C".into()
+                },
+            })
+        );
+        assert!(!stub.is_complete());
+
+        let mut stub = StubVisitor::new(vec![]);
+        assert_matches!(stub.end_group(), Err(StubError::ExtraCall { index: 0, .. }));
+        Ok(())
+    }
+
+    #[test]
+    fn calls_are_recorded_as_wiremock_mappings() -> anyhow::Result<()> {
+        let mut recorder = RecordingVisitor::new(ThinEdgeJsonSerializer::new());
+        recorder.start_group("location")?;
+        recorder.measurement("alti", 2100.4)?;
+
+        let json: serde_json::Value = serde_json::from_str(&recorder.to_json())?;
+
+        assert_eq!(
+            json,
+            json!({
+                "mappings": [
+                    {
+                        "request": {
+                            "method": "POST",
+                            "url": "/visitor/start_group",
+                            "bodyPatterns": [{"equalToJson": {"call": "start_group", "group": "location"}}],
+                        },
+                        "response": {"status": 200},
+                        "scenarioName": "thin-edge-visitor-calls",
+                        "requiredScenarioState": "Started",
+                        "newScenarioState": "call-1",
+                    },
+                    {
+                        "request": {
+                            "method": "POST",
+                            "url": "/visitor/measurement",
+                            "bodyPatterns": [{"equalToJson": {"call": "measurement", "name": "alti", "value": 2100.4}}],
+                        },
+                        "response": {"status": 200},
+                        "scenarioName": "thin-edge-visitor-calls",
+                        "requiredScenarioState": "call-1",
+                        "newScenarioState": "call-2",
+                    },
+                ]
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_recordings_are_reported() -> anyhow::Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("recording.json");
+        std::fs::write(&path, r#"{"mappings":[{"request":{}}]}"#)?;
+
+        assert_matches!(
+            StubVisitor::from_recording(&path),
+            Err(RecordingError::InvalidRecording { .. })
+        );
+        assert_matches!(
+            StubVisitor::from_recording(&dir.path().join("missing.json")),
+            Err(RecordingError::FileReadError { .. })
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+use crate::context::MeasurementContext;
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use regex::Regex;
+use std::borrow::Cow;
+
+/// A visitor that renames the measurements and the groups before forwarding them.
+///
+/// The rules are tried in order and the first regex matching a name renames it,
+/// the matched part being replaced by the replacement string
+/// which may refer to the capture groups of the regex (as `$1` or `$name`).
+/// The names matching no rules are forwarded unchanged.
+///
+/// ```
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::remap::MeasurementNameRemapper;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let mut visitor = MeasurementNameRemapper::new(ThinEdgeJsonSerializer::new())
+///     .with_rule("^temp$", "temperature")?;
+///
+/// visitor.measurement("temp", 25.5)?;
+/// visitor.measurement("pressure", 98.0)?;
+///
+/// assert_eq!(
+///     visitor.into_inner().into_string()?,
+///     r#"{"temperature":25.5,"pressure":98.0}"#
+/// );
+/// # Ok(()) }
+/// ```
+pub struct MeasurementNameRemapper<V> {
+    pub rules: Vec<(Regex, String)>,
+    inner: V,
+}
+
+impl<V> MeasurementNameRemapper<V> {
+    pub fn new(inner: V) -> Self {
+        Self {
+            rules: Vec::new(),
+            inner,
+        }
+    }
+
+    /// Add a rule, with a lower priority than all the rules already added
+    pub fn with_rule(mut self, pattern: &str, replacement: &str) -> Result<Self, regex::Error> {
+        let regex = Regex::new(pattern)?;
+        self.rules.push((regex, replacement.to_string()));
+        Ok(self)
+    }
+
+    /// The name given to a measurement or a group by the first matching rule
+    pub fn rename<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        self.rules
+            .iter()
+            .find(|(regex, _)| regex.is_match(name))
+            .map(|(regex, replacement)| regex.replace(name, replacement.as_str()))
+            .unwrap_or(Cow::Borrowed(name))
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut V {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> V {
+        self.inner
+    }
+}
+
+impl<V> GroupedMeasurementVisitor for MeasurementNameRemapper<V>
+where
+    V: GroupedMeasurementVisitor,
+{
+    type Error = V::Error;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.inner.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        let name = self.rename(name);
+        self.inner.measurement(&name, value)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        let group = self.rename(group);
+        self.inner.start_group(&group)
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        let group = self.rename(group);
+        self.inner.start_group_with_timestamp(&group, timestamp)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        let name = self.rename(name);
+        self.inner.nullable_measurement(&name, value)
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        let name = self.rename(name);
+        self.inner.complex_measurement(&name, real, imaginary)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        let name = self.rename(name);
+        self.inner.annotated_measurement(&name, value, quality)
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.inner.set_context(context)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.inner.end_group()
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        let name = self.rename(name);
+        self.inner.measurement_with_unit(&name, value, unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::ThinEdgeJsonSerializer;
+
+    #[test]
+    fn the_first_matching_rule_wins() -> anyhow::Result<()> {
+        let remapper = MeasurementNameRemapper::new(ThinEdgeJsonSerializer::new())
+            .with_rule("^temp_(.*)$", "temperature_$1")?
+            .with_rule("^temp", "t")?;
+
+        assert_eq!(remapper.rename("temp_engine"), "temperature_engine");
+        assert_eq!(remapper.rename("temperature"), "terature");
+        Ok(())
+    }
+
+    #[test]
+    fn names_matching_no_rules_are_unchanged() -> anyhow::Result<()> {
+        let mut visitor = MeasurementNameRemapper::new(ThinEdgeJsonSerializer::new())
+            .with_rule("^temp$", "temperature")?;
+
+        visitor.measurement("pressure", 98.0)?;
+        visitor.measurement("temp", 25.5)?;
+        visitor.measurement_with_unit("humidity", 40.0, "%")?;
+
+        let expected_output =
+            r#"{"pressure":98.0,"temperature":25.5,"humidity":{"value":40.0,"unit":"%"}}"#;
+        assert_eq!(visitor.into_inner().into_string()?, expected_output);
+        Ok(())
+    }
+
+    #[test]
+    fn groups_are_renamed_too() -> anyhow::Result<()> {
+        let mut visitor = MeasurementNameRemapper::new(ThinEdgeJsonSerializer::new())
+            .with_rule("^loc$", "location")?
+            .with_rule("^alt$", "alti")?;
+
+        visitor.start_group("loc")?;
+        visitor.measurement("alt", 2100.4)?;
+        visitor.end_group()?;
+
+        let expected_output = r#"{"location":{"alti":2100.4}}"#;
+        assert_eq!(visitor.into_inner().into_string()?, expected_output);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        let result =
+            MeasurementNameRemapper::new(ThinEdgeJsonSerializer::new()).with_rule("(", "x");
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,349 @@
+use crate::buffer::MeasurementBuffer;
+use crate::measurement::GroupedMeasurementVisitor;
+use crate::trace::VisitorCall;
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum PlaybackError {
+    #[error("Invalid playback speed {0:?}: expected a factor, as \"0.5x\", or \"fastest\"")]
+    InvalidSpeed(String),
+}
+
+/// The speed at which recorded measurements are replayed,
+/// either a multiple of the real-time speed or as fast as possible.
+///
+/// A speed can be parsed from a factor followed by an `x`, as `0.5x` or `10x`,
+/// or from `fastest`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlaybackSpeed {
+    /// `None` for the fastest speed, with no delays
+    factor: Option<f64>,
+}
+
+impl PlaybackSpeed {
+    /// Replay the measurements with their original delays
+    pub const REAL_TIME: PlaybackSpeed = PlaybackSpeed { factor: Some(1.0) };
+
+    /// Replay the measurements with no delays
+    pub const FASTEST: PlaybackSpeed = PlaybackSpeed { factor: None };
+
+    /// Replay the measurements `factor` times faster than real-time,
+    /// or slower for a factor below 1.
+    pub fn times(factor: f64) -> Result<Self, PlaybackError> {
+        if factor.is_finite() && factor > 0.0 {
+            Ok(PlaybackSpeed {
+                factor: Some(factor),
+            })
+        } else {
+            Err(PlaybackError::InvalidSpeed(format!("{}x", factor)))
+        }
+    }
+
+    /// The factor applied to the real-time speed, `None` for the fastest speed
+    pub fn factor(&self) -> Option<f64> {
+        self.factor
+    }
+
+    /// The delay to wait at this speed for a real-time delay
+    fn scale(&self, delay: Duration) -> Duration {
+        match self.factor {
+            Some(factor) => delay.div_f64(factor),
+            None => Duration::from_secs(0),
+        }
+    }
+}
+
+impl Default for PlaybackSpeed {
+    fn default() -> Self {
+        PlaybackSpeed::REAL_TIME
+    }
+}
+
+impl FromStr for PlaybackSpeed {
+    type Err = PlaybackError;
+
+    fn from_str(speed: &str) -> Result<Self, Self::Err> {
+        let speed = speed.trim();
+        if speed.eq_ignore_ascii_case("fastest") {
+            return Ok(PlaybackSpeed::FASTEST);
+        }
+        speed
+            .strip_suffix('x')
+            .and_then(|factor| factor.parse::<f64>().ok())
+            .and_then(|factor| PlaybackSpeed::times(factor).ok())
+            .ok_or_else(|| PlaybackError::InvalidSpeed(speed.to_string()))
+    }
+}
+
+/// A handle to pause and resume a `MeasurementReplayer`, possibly from another task.
+#[derive(Debug, Clone, Default)]
+pub struct PlaybackControl {
+    paused: Arc<AtomicBool>,
+    resumed: Arc<Notify>,
+}
+
+impl PlaybackControl {
+    /// Pause the replay before the next call
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Resume a paused replay
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+        self.resumed.notify_waiters();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Wait till the replay is not paused, returning for how long it has been paused
+    async fn wait_resumed(&self) -> Duration {
+        let paused_at = Instant::now();
+        loop {
+            // Registered before checking the flag, so a resume in between is not missed
+            let resumed = self.resumed.notified();
+            if !self.is_paused() {
+                return paused_at.elapsed();
+            }
+            resumed.await;
+        }
+    }
+}
+
+/// A replayer of recorded measurements, reproducing the delays between their timestamps.
+///
+/// * The calls are applied in order to a visitor,
+///   each timestamp being applied once the time elapsed since the first timestamp,
+///   scaled by the playback speed, matches the time elapsed since the start of the replay.
+/// * Only the message timestamps pace the replay, not the group timestamps.
+///   A timestamp older than the first one is applied with no delay.
+/// * The replay can be paused and resumed using the `PlaybackControl` of the replayer,
+///   a pause taking effect before the next call and delaying the rest of the replay.
+///
+/// ```
+/// use thin_edge_json::buffer::MeasurementBuffer;
+/// use thin_edge_json::replayer::MeasurementReplayer;
+/// use thin_edge_json::trace::VisitorCall;
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), anyhow::Error> {
+/// let replayer = MeasurementReplayer::new(vec![
+///     VisitorCall::Timestamp { value: "2021-04-30T17:00:00Z".parse()? },
+///     VisitorCall::Measurement { name: "temperature".into(), value: 25.5 },
+///     VisitorCall::Timestamp { value: "2021-04-30T17:00:10Z".parse()? },
+///     VisitorCall::Measurement { name: "temperature".into(), value: 26.0 },
+/// ])
+/// .with_speed("100x".parse()?);
+///
+/// // Replays 10 seconds of measurements in 100 milliseconds
+/// let mut buffer = MeasurementBuffer::new();
+/// replayer.replay(&mut buffer).await?;
+/// assert_eq!(buffer.events(), replayer.calls());
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone)]
+pub struct MeasurementReplayer {
+    calls: Vec<VisitorCall>,
+    speed: PlaybackSpeed,
+    control: PlaybackControl,
+}
+
+impl MeasurementReplayer {
+    pub fn new(calls: Vec<VisitorCall>) -> Self {
+        Self {
+            calls,
+            speed: PlaybackSpeed::REAL_TIME,
+            control: PlaybackControl::default(),
+        }
+    }
+
+    /// Set the playback speed, real-time by default.
+    pub fn with_speed(self, speed: PlaybackSpeed) -> Self {
+        Self { speed, ..self }
+    }
+
+    pub fn speed(&self) -> PlaybackSpeed {
+        self.speed
+    }
+
+    /// The handle to pause and resume this replayer
+    pub fn control(&self) -> PlaybackControl {
+        self.control.clone()
+    }
+
+    /// The calls to replay, in order
+    pub fn calls(&self) -> &[VisitorCall] {
+        &self.calls
+    }
+
+    /// Apply all the calls to the visitor, waiting for each timestamp to be due
+    pub async fn replay<V>(&self, visitor: &mut V) -> Result<(), V::Error>
+    where
+        V: GroupedMeasurementVisitor,
+    {
+        // When the replay started and the first timestamp, to which the other ones are relative
+        let mut origin: Option<(Instant, DateTime<FixedOffset>)> = None;
+
+        for call in self.calls.iter() {
+            if self.control.is_paused() {
+                let pause = self.control.wait_resumed().await;
+                if let Some((start, _)) = origin.as_mut() {
+                    *start += pause;
+                }
+            }
+
+            if let VisitorCall::Timestamp { value } = call {
+                match origin {
+                    None => origin = Some((Instant::now(), *value)),
+                    Some((start, first)) => {
+                        let elapsed = (*value - first).to_std().unwrap_or_default();
+                        tokio::time::sleep_until(start + self.speed.scale(elapsed)).await;
+                    }
+                }
+            }
+
+            call.apply(visitor)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<MeasurementBuffer> for MeasurementReplayer {
+    fn from(buffer: MeasurementBuffer) -> Self {
+        MeasurementReplayer::new(buffer.into_events())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::time::Instant as WallClock;
+
+    /// A measurement every `period`, as separate messages
+    fn recording(count: u32, period: chrono::Duration) -> Vec<VisitorCall> {
+        let start = FixedOffset::east(0).ymd(2021, 4, 30).and_hms(17, 0, 0);
+        (0..count)
+            .flat_map(|i| {
+                vec![
+                    VisitorCall::Timestamp {
+                        value: start + period * i as i32,
+                    },
+                    VisitorCall::Measurement {
+                        name: "temperature".into(),
+                        value: f64::from(i),
+                    },
+                ]
+            })
+            .collect()
+    }
+
+    /// Replay the calls, returning the wall-clock duration of the replay
+    async fn replay(replayer: &MeasurementReplayer) -> anyhow::Result<Duration> {
+        let mut buffer = MeasurementBuffer::new();
+        let start = WallClock::now();
+        replayer.replay(&mut buffer).await?;
+        let elapsed = start.elapsed();
+
+        assert_eq!(buffer.events(), replayer.calls());
+        Ok(elapsed)
+    }
+
+    fn assert_within_10_percent(actual: Duration, expected: Duration) {
+        let (actual, expected) = (actual.as_secs_f64(), expected.as_secs_f64());
+        assert!(
+            (actual - expected).abs() <= expected * 0.1,
+            "Replayed in {}s while {}s was expected",
+            actual,
+            expected
+        );
+    }
+
+    #[tokio::test]
+    async fn real_time_replay_reproduces_the_original_delays() -> anyhow::Result<()> {
+        let replayer = MeasurementReplayer::new(recording(5, chrono::Duration::milliseconds(100)));
+
+        let elapsed = replay(&replayer).await?;
+
+        assert_within_10_percent(elapsed, Duration::from_millis(400));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delays_are_scaled_by_the_playback_speed() -> anyhow::Result<()> {
+        let slow = MeasurementReplayer::new(recording(3, chrono::Duration::milliseconds(50)))
+            .with_speed("0.5x".parse()?);
+        assert_within_10_percent(replay(&slow).await?, Duration::from_millis(200));
+
+        let fast = MeasurementReplayer::new(recording(3, chrono::Duration::seconds(1)))
+            .with_speed("10x".parse()?);
+        assert_within_10_percent(replay(&fast).await?, Duration::from_millis(200));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn the_fastest_replay_has_no_delays() -> anyhow::Result<()> {
+        let replayer = MeasurementReplayer::new(recording(10, chrono::Duration::hours(1)))
+            .with_speed(PlaybackSpeed::FASTEST);
+
+        assert!(replay(&replayer).await? < Duration::from_millis(50));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn all_the_calls_are_replayed_in_order() -> anyhow::Result<()> {
+        let calls = recording(2, chrono::Duration::seconds(1));
+        let replayer = MeasurementReplayer::from(MeasurementBuffer::from(calls.clone()))
+            .with_speed(PlaybackSpeed::FASTEST);
+
+        let mut buffer = MeasurementBuffer::new();
+        replayer.replay(&mut buffer).await?;
+
+        assert_eq!(buffer.into_events(), calls);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_paused_replay_waits_to_be_resumed() -> anyhow::Result<()> {
+        let replayer = MeasurementReplayer::new(recording(3, chrono::Duration::milliseconds(100)));
+        let control = replayer.control();
+        control.pause();
+        assert!(control.is_paused());
+
+        let resume = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            control.resume();
+        });
+        let elapsed = replay(&replayer).await?;
+        resume.await?;
+
+        // The 200ms pause followed by the 200ms of the recording
+        assert_within_10_percent(elapsed, Duration::from_millis(400));
+        assert!(!replayer.control().is_paused());
+        Ok(())
+    }
+
+    #[test]
+    fn playback_speeds_are_parsed() {
+        assert_eq!("1x".parse(), Ok(PlaybackSpeed::REAL_TIME));
+        assert_eq!("0.5x".parse(), Ok(PlaybackSpeed::times(0.5).unwrap()));
+        assert_eq!("10x".parse::<PlaybackSpeed>().unwrap().factor(), Some(10.0));
+        assert_eq!("FASTEST".parse(), Ok(PlaybackSpeed::FASTEST));
+
+        for invalid in ["10", "0x", "-2x", "fastx", ""].iter() {
+            assert_eq!(
+                invalid.parse::<PlaybackSpeed>(),
+                Err(PlaybackError::InvalidSpeed(invalid.to_string()))
+            );
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use crate::context::{SPAN_ID_KEY, TRACE_ID_KEY};
+use crate::trace::VisitorCall;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+const JSON_SCHEMA_DRAFT_07: &str = "http://json-schema.org/draft-07/schema#";
+
+/// Generate the JSON Schema of thin-edge JSON payloads from sample data.
+///
+/// The schema is inferred from a trace of `GroupedMeasurementVisitor` calls,
+/// as recorded when a test payload is produced or parsed.
+/// Every measurement, group and timestamp seen in the trace is declared as required,
+/// and no other properties are allowed.
+///
+/// ```
+/// use thin_edge_json::schema::ThinEdgeJsonSchema;
+/// use thin_edge_json::trace::VisitorCall;
+///
+/// let trace = vec![
+///     VisitorCall::Measurement { name: "temperature".into(), value: 25.0 },
+/// ];
+///
+/// let schema = ThinEdgeJsonSchema::from_visitor_trace(&trace);
+///
+/// assert_eq!(schema.as_value()["properties"]["temperature"]["type"], "number");
+/// ```
+pub struct ThinEdgeJsonSchema;
+
+/// A JSON Schema (draft-07) document.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct JsonSchemaDoc(Value);
+
+impl ThinEdgeJsonSchema {
+    pub fn from_visitor_trace(calls: &[VisitorCall]) -> JsonSchemaDoc {
+        let mut root = ObjectSchema::default();
+        let mut group: Option<(String, ObjectSchema)> = None;
+
+        for call in calls.iter() {
+            match call {
+                VisitorCall::Timestamp { .. } => {
+                    root.add_property("time", json!({"type": "string", "format": "date-time"}));
+                }
+                VisitorCall::Measurement { name, .. } => {
+                    let object = match group.as_mut() {
+                        Some((_, group_schema)) => group_schema,
+                        None => &mut root,
+                    };
+                    object.add_property(name, json!({"type": "number"}));
+                }
+                VisitorCall::NullableMeasurement { name, .. } => {
+                    let object = match group.as_mut() {
+                        Some((_, group_schema)) => group_schema,
+                        None => &mut root,
+                    };
+                    object.add_property(name, json!({"type": ["number", "null"]}));
+                }
+                VisitorCall::ComplexMeasurement { name, .. } => {
+                    let object = match group.as_mut() {
+                        Some((_, group_schema)) => group_schema,
+                        None => &mut root,
+                    };
+                    let mut complex = ObjectSchema::default();
+                    complex.add_property("re", json!({"type": "number"}));
+                    complex.add_property("im", json!({"type": "number"}));
+                    object.add_property(name, complex.into_value());
+                }
+                VisitorCall::AnnotatedMeasurement { name, .. } => {
+                    let object = match group.as_mut() {
+                        Some((_, group_schema)) => group_schema,
+                        None => &mut root,
+                    };
+                    let mut annotated = ObjectSchema::default();
+                    annotated.add_property("value", json!({"type": "number"}));
+                    annotated.add_property("quality", json!({"type": "string"}));
+                    object.add_property(name, annotated.into_value());
+                }
+                VisitorCall::MeasurementWithUnit { name, unit, .. } => {
+                    let object = match group.as_mut() {
+                        Some((_, group_schema)) => group_schema,
+                        None => &mut root,
+                    };
+                    let mut value_with_unit = ObjectSchema::default();
+                    value_with_unit.add_property("value", json!({"type": "number"}));
+                    value_with_unit.add_property("unit", json!({ "const": unit }));
+                    object.add_property(name, value_with_unit.into_value());
+                }
+                VisitorCall::StartGroup { group: name } => {
+                    group = Some((name.clone(), ObjectSchema::default()));
+                }
+                VisitorCall::StartGroupWithTimestamp { group: name, .. } => {
+                    let mut group_schema = ObjectSchema::default();
+                    group_schema
+                        .add_property("time", json!({"type": "string", "format": "date-time"}));
+                    group = Some((name.clone(), group_schema));
+                }
+                VisitorCall::EndGroup => {
+                    if let Some((name, group_schema)) = group.take() {
+                        root.add_property(&name, group_schema.into_value());
+                    }
+                }
+                VisitorCall::SetContext { .. } => {
+                    let trace_id = json!({"type": "string", "pattern": "^[0-9a-f]{32}$"});
+                    let span_id = json!({"type": "string", "pattern": "^[0-9a-f]{16}$"});
+                    root.add_property(TRACE_ID_KEY, trace_id);
+                    root.add_property(SPAN_ID_KEY, span_id);
+                }
+            }
+        }
+
+        let mut schema = root.into_value();
+        schema["$schema"] = json!(JSON_SCHEMA_DRAFT_07);
+        schema["title"] = json!("thin-edge JSON measurement");
+        JsonSchemaDoc(schema)
+    }
+}
+
+impl JsonSchemaDoc {
+    pub fn as_value(&self) -> &Value {
+        &self.0
+    }
+
+    pub fn into_value(self) -> Value {
+        self.0
+    }
+
+    pub fn to_string_pretty(&self) -> String {
+        // A `serde_json::Value` can always be serialized
+        serde_json::to_string_pretty(&self.0).expect("A JSON value is serializable")
+    }
+}
+
+#[derive(Default)]
+struct ObjectSchema {
+    properties: Map<String, Value>,
+    required: Vec<String>,
+}
+
+impl ObjectSchema {
+    fn add_property(&mut self, name: &str, schema: Value) {
+        if self.properties.insert(name.into(), schema).is_none() {
+            self.required.push(name.into());
+        }
+    }
+
+    fn into_value(self) -> Value {
+        json!({
+            "type": "object",
+            "properties": self.properties,
+            "required": self.required,
+            "additionalProperties": false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::ThinEdgeJsonSerializer;
+    use chrono::{FixedOffset, TimeZone};
+    use jsonschema::JSONSchema;
+
+    fn sample_trace() -> Vec<VisitorCall> {
+        vec![
+            VisitorCall::Timestamp {
+                value: FixedOffset::east(2 * 3600)
+                    .ymd(2021, 4, 30)
+                    .and_hms(17, 3, 14),
+            },
+            VisitorCall::Measurement {
+                name: "temperature".into(),
+                value: 25.5,
+            },
+            VisitorCall::StartGroup {
+                group: "location".into(),
+            },
+            VisitorCall::Measurement {
+                name: "alti".into(),
+                value: 2100.4,
+            },
+            VisitorCall::Measurement {
+                name: "longi".into(),
+                value: 2200.4,
+            },
+            VisitorCall::EndGroup,
+        ]
+    }
+
+    fn compile(schema: &JsonSchemaDoc) -> JSONSchema {
+        JSONSchema::compile(schema.as_value()).expect("A valid JSON schema")
+    }
+
+    #[test]
+    fn generate_schema_from_trace() {
+        let schema = ThinEdgeJsonSchema::from_visitor_trace(&sample_trace());
+
+        assert_eq!(
+            schema.into_value(),
+            json!({
+                "$schema": "http://json-schema.org/draft-07/schema#",
+                "title": "thin-edge JSON measurement",
+                "type": "object",
+                "properties": {
+                    "time": {"type": "string", "format": "date-time"},
+                    "temperature": {"type": "number"},
+                    "location": {
+                        "type": "object",
+                        "properties": {
+                            "alti": {"type": "number"},
+                            "longi": {"type": "number"},
+                        },
+                        "required": ["alti", "longi"],
+                        "additionalProperties": false,
+                    },
+                },
+                "required": ["time", "temperature", "location"],
+                "additionalProperties": false,
+            })
+        );
+    }
+
+    #[test]
+    fn the_payload_of_the_trace_is_valid_against_the_generated_schema() -> anyhow::Result<()> {
+        let trace = sample_trace();
+        let schema = ThinEdgeJsonSchema::from_visitor_trace(&trace);
+
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        for call in trace.iter() {
+            call.apply(&mut serializer)?;
+        }
+        let payload: Value = serde_json::from_str(&serializer.into_string()?)?;
+
+        assert!(compile(&schema).is_valid(&payload));
+        Ok(())
+    }
+
+    #[test]
+    fn payloads_not_matching_the_trace_are_invalid_against_the_generated_schema() {
+        let schema = ThinEdgeJsonSchema::from_visitor_trace(&sample_trace());
+        let schema = compile(&schema);
+
+        let missing_measurement = json!({
+            "time": "2021-04-30T17:03:14+02:00",
+            "temperature": 25.5,
+            "location": {"alti": 2100.4}
+        });
+        let unexpected_measurement = json!({
+            "time": "2021-04-30T17:03:14+02:00",
+            "temperature": 25.5,
+            "pressure": 98.0,
+            "location": {"alti": 2100.4, "longi": 2200.4}
+        });
+        let not_a_number = json!({
+            "time": "2021-04-30T17:03:14+02:00",
+            "temperature": "hot",
+            "location": {"alti": 2100.4, "longi": 2200.4}
+        });
+
+        assert!(!schema.is_valid(&missing_measurement));
+        assert!(!schema.is_valid(&unexpected_measurement));
+        assert!(!schema.is_valid(&not_a_number));
+    }
+
+    #[test]
+    fn measurements_with_unit_are_objects_with_a_constant_unit() {
+        let trace = vec![VisitorCall::MeasurementWithUnit {
+            name: "temperature".into(),
+            value: 25.5,
+            unit: "°C".into(),
+        }];
+
+        let schema = ThinEdgeJsonSchema::from_visitor_trace(&trace);
+        let schema = compile(&schema);
+
+        assert!(schema.is_valid(&json!({"temperature": {"value": 20.0, "unit": "°C"}})));
+        assert!(!schema.is_valid(&json!({"temperature": {"value": 20.0, "unit": "K"}})));
+        assert!(!schema.is_valid(&json!({"temperature": 20.0})));
+    }
+
+    #[test]
+    fn empty_trace_generates_the_schema_of_an_empty_object() {
+        let schema = ThinEdgeJsonSchema::from_visitor_trace(&[]);
+        let schema = compile(&schema);
+
+        assert!(schema.is_valid(&json!({})));
+        assert!(!schema.is_valid(&json!({"temperature": 25.5})));
+    }
+}
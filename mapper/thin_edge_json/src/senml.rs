@@ -0,0 +1,312 @@
+use crate::series::FlatMeasurementSeries;
+use chrono::offset::FixedOffset;
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+/// The separator between a group and a measurement name in a SenML name
+const GROUP_SEPARATOR: char = '/';
+
+/// Convert measurements from and to [SenML](https://tools.ietf.org/html/rfc8428) JSON.
+///
+/// Each measurement is given by a SenML record,
+/// named after the measurement prefixed by its group if any, as in `location/alti`.
+/// The timestamp of the measurements, if any, is given as the base time of the first record,
+/// in seconds since the UNIX epoch.
+///
+/// SenML having no notion of time zone, the timestamps read from SenML are in UTC.
+/// Only numeric values are supported.
+///
+/// ```
+/// use thin_edge_json::json::parse_str;
+/// use thin_edge_json::senml::SenmlConverter;
+/// use thin_edge_json::series::FlatMeasurementSeries;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let mut series = FlatMeasurementSeries::new();
+/// parse_str(r#"{"temperature": 25.5, "location": {"alti": 2100.4}}"#, &mut series)?;
+///
+/// assert_eq!(
+///     SenmlConverter::to_senml(&series)?,
+///     r#"[{"n":"temperature","v":25.5},{"n":"location/alti","v":2100.4}]"#
+/// );
+/// # Ok(()) }
+/// ```
+pub struct SenmlConverter;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SenmlError {
+    #[error("Invalid SenML name: {name:?} must start with a letter or a digit, followed by letters, digits or '-', ':', '.', '_'")]
+    InvalidName { name: String },
+
+    #[error("Invalid SenML: {0}")]
+    InvalidSenml(#[from] serde_json::Error),
+
+    #[error("Invalid SenML record {record}: the name is missing")]
+    MissingName { record: usize },
+
+    #[error("Invalid SenML record {record}: only numeric values are supported")]
+    UnsupportedValue { record: usize },
+
+    #[error("Invalid SenML record {record}: {time} is not a valid time")]
+    InvalidTime { record: usize, time: f64 },
+
+    #[error("Invalid SenML record {record}: all the records must have the same time")]
+    MultipleTimestamps { record: usize },
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SenmlRecord {
+    #[serde(rename = "bn", default, skip_serializing_if = "Option::is_none")]
+    base_name: Option<String>,
+
+    #[serde(rename = "bt", default, skip_serializing_if = "Option::is_none")]
+    base_time: Option<f64>,
+
+    #[serde(rename = "bu", default, skip_serializing_if = "Option::is_none")]
+    base_unit: Option<String>,
+
+    #[serde(rename = "n", default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+
+    #[serde(rename = "u", default, skip_serializing_if = "Option::is_none")]
+    unit: Option<String>,
+
+    #[serde(rename = "v", default, skip_serializing_if = "Option::is_none")]
+    value: Option<f64>,
+
+    #[serde(rename = "vs", default, skip_serializing_if = "Option::is_none")]
+    string_value: Option<String>,
+
+    #[serde(rename = "vb", default, skip_serializing_if = "Option::is_none")]
+    bool_value: Option<bool>,
+
+    #[serde(rename = "vd", default, skip_serializing_if = "Option::is_none")]
+    data_value: Option<String>,
+
+    #[serde(rename = "t", default, skip_serializing_if = "Option::is_none")]
+    time: Option<f64>,
+}
+
+impl SenmlConverter {
+    pub fn to_senml(series: &FlatMeasurementSeries) -> Result<String, SenmlError> {
+        let records = SenmlConverter::records(series)?;
+        Ok(serde_json::to_string(&records)?)
+    }
+
+    pub fn to_senml_pretty(series: &FlatMeasurementSeries) -> Result<String, SenmlError> {
+        let records = SenmlConverter::records(series)?;
+        Ok(serde_json::to_string_pretty(&records)?)
+    }
+
+    pub fn from_senml(input: &str) -> Result<FlatMeasurementSeries, SenmlError> {
+        let records: Vec<SenmlRecord> = serde_json::from_str(input)?;
+
+        let mut series = FlatMeasurementSeries::new();
+        let mut base_name = String::new();
+        let mut base_time = None;
+        let mut base_unit = None;
+
+        for (record, senml) in records.into_iter().enumerate() {
+            if let Some(name) = senml.base_name {
+                base_name = name;
+            }
+            if let Some(time) = senml.base_time {
+                base_time = Some(time);
+            }
+            if let Some(unit) = senml.base_unit {
+                base_unit = Some(unit);
+            }
+
+            if senml.string_value.is_some()
+                || senml.bool_value.is_some()
+                || senml.data_value.is_some()
+            {
+                return Err(SenmlError::UnsupportedValue { record });
+            }
+            let value = match senml.value {
+                Some(value) => value,
+                // A record with no value only sets base fields
+                None if senml.name.is_none() => continue,
+                None => return Err(SenmlError::UnsupportedValue { record }),
+            };
+
+            let name = format!("{}{}", base_name, senml.name.unwrap_or_default());
+            if name.is_empty() {
+                return Err(SenmlError::MissingName { record });
+            }
+
+            if senml.time.is_some() || base_time.is_some() {
+                let time = base_time.unwrap_or_default() + senml.time.unwrap_or_default();
+                let timestamp =
+                    from_epoch_seconds(time).ok_or(SenmlError::InvalidTime { record, time })?;
+                match series.timestamp {
+                    Some(previous) if previous != timestamp => {
+                        return Err(SenmlError::MultipleTimestamps { record })
+                    }
+                    _ => series.timestamp = Some(timestamp),
+                }
+            }
+
+            let unit = senml.unit.or_else(|| base_unit.clone());
+            let mut parts = name.splitn(2, GROUP_SEPARATOR);
+            match (parts.next(), parts.next()) {
+                (Some(group), Some(name)) => series.push(Some(group), name, value, unit.as_deref()),
+                _ => series.push(None, &name, value, unit.as_deref()),
+            }
+        }
+        Ok(series)
+    }
+
+    fn records(series: &FlatMeasurementSeries) -> Result<Vec<SenmlRecord>, SenmlError> {
+        let mut records = Vec::new();
+        for measurement in series.measurements.iter() {
+            check_name(measurement.group.as_deref())?;
+            check_name(Some(&measurement.name))?;
+            let name = match measurement.group.as_ref() {
+                Some(group) => format!("{}{}{}", group, GROUP_SEPARATOR, measurement.name),
+                None => measurement.name.clone(),
+            };
+            records.push(SenmlRecord {
+                name: Some(name),
+                unit: measurement.unit.clone(),
+                value: Some(measurement.value),
+                ..SenmlRecord::default()
+            });
+        }
+
+        if let (Some(timestamp), Some(first)) = (series.timestamp, records.first_mut()) {
+            first.base_time = Some(to_epoch_seconds(timestamp));
+        }
+        Ok(records)
+    }
+}
+
+/// Check that a name contains only the characters allowed by SenML, but the group separator
+fn check_name(name: Option<&str>) -> Result<(), SenmlError> {
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(()),
+    };
+    let mut chars = name.chars();
+    let valid_first = chars.next().map_or(false, |c| c.is_ascii_alphanumeric());
+    let valid_rest = chars.all(|c| c.is_ascii_alphanumeric() || "-:._".contains(c));
+    if valid_first && valid_rest {
+        Ok(())
+    } else {
+        Err(SenmlError::InvalidName {
+            name: name.to_string(),
+        })
+    }
+}
+
+fn to_epoch_seconds(timestamp: DateTime<FixedOffset>) -> f64 {
+    timestamp.timestamp() as f64 + f64::from(timestamp.timestamp_subsec_nanos()) / 1e9
+}
+
+fn from_epoch_seconds(time: f64) -> Option<DateTime<FixedOffset>> {
+    if !time.is_finite() || time < 0.0 || time > i64::MAX as f64 {
+        return None;
+    }
+    let seconds = time.trunc();
+    let nanos = ((time - seconds) * 1e9).round().min(999_999_999.0) as u32;
+    let timestamp = Utc.timestamp_opt(seconds as i64, nanos).single()?;
+    Some(timestamp.with_timezone(&FixedOffset::east(0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::parse_str;
+    use crate::serialize::ThinEdgeJsonSerializer;
+    use assert_matches::assert_matches;
+
+    fn json_to_senml(input: &str) -> anyhow::Result<String> {
+        let mut series = FlatMeasurementSeries::new();
+        parse_str(input, &mut series)?;
+        Ok(SenmlConverter::to_senml(&series)?)
+    }
+
+    fn senml_to_json(input: &str) -> anyhow::Result<String> {
+        let series = SenmlConverter::from_senml(input)?;
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        series.visit(&mut serializer)?;
+        Ok(serializer.into_string()?)
+    }
+
+    #[test]
+    fn convert_thin_edge_json_to_senml() -> anyhow::Result<()> {
+        let input = r#"{
+            "time": "2021-04-30T17:03:14.5+02:00",
+            "temperature": 25.5,
+            "location": {"alti": 2100.4}
+        }"#;
+
+        assert_eq!(
+            json_to_senml(input)?,
+            r#"[{"bt":1619794994.5,"n":"temperature","v":25.5},{"n":"location/alti","v":2100.4}]"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn units_are_given_by_the_records() -> anyhow::Result<()> {
+        let mut series = FlatMeasurementSeries::new();
+        series.push(None, "temperature", 25.5, Some("Cel"));
+
+        assert_eq!(
+            SenmlConverter::to_senml(&series)?,
+            r#"[{"n":"temperature","u":"Cel","v":25.5}]"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn convert_senml_to_thin_edge_json() -> anyhow::Result<()> {
+        let input = r#"[
+            {"bn": "location/", "bt": 1619794994, "bu": "m", "n": "alti", "v": 2100.4},
+            {"n": "longi", "v": 2200.4, "u": "deg"},
+            {"bn": "", "n": "temperature", "u": "Cel", "v": 25, "t": 0}
+        ]"#;
+
+        assert_eq!(
+            senml_to_json(input)?,
+            r#"{"time":"2021-04-30T15:03:14+00:00","location":{"alti":{"value":2100.4,"unit":"m"},"longi":{"value":2200.4,"unit":"deg"}},"temperature":{"value":25.0,"unit":"Cel"}}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reject_names_not_allowed_by_senml() {
+        let mut with_space = FlatMeasurementSeries::new();
+        with_space.push(None, "outside temperature", 25.5, None);
+        let mut with_separator = FlatMeasurementSeries::new();
+        with_separator.push(Some("engine/1"), "temperature", 80.0, None);
+
+        assert_matches!(
+            SenmlConverter::to_senml(&with_space),
+            Err(SenmlError::InvalidName { name }) if name == "outside temperature"
+        );
+        assert_matches!(
+            SenmlConverter::to_senml(&with_separator),
+            Err(SenmlError::InvalidName { name }) if name == "engine/1"
+        );
+    }
+
+    #[test]
+    fn reject_non_numeric_values() {
+        assert_matches!(
+            SenmlConverter::from_senml(r#"[{"n": "status", "vs": "on"}]"#),
+            Err(SenmlError::UnsupportedValue { record: 0 })
+        );
+    }
+
+    #[test]
+    fn reject_records_with_different_times() {
+        assert_matches!(
+            SenmlConverter::from_senml(
+                r#"[{"n": "a", "v": 1, "t": 1619795014}, {"n": "b", "v": 2, "t": 1619795015}]"#
+            ),
+            Err(SenmlError::MultipleTimestamps { record: 1 })
+        );
+    }
+}
@@ -0,0 +1,564 @@
+use crate::measurement::GroupedMeasurementVisitor;
+use crate::serialize::{ThinEdgeJsonSerializationError, ThinEdgeJsonSerializer};
+use chrono::DateTime;
+use serde::ser::{Impossible, Serialize, SerializeMap, SerializeStruct};
+use std::fmt::Display;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SerdeBridgeError<E>
+where
+    E: std::error::Error + 'static,
+{
+    #[error(transparent)]
+    VisitorError(E),
+
+    #[error("Expected a map or a struct, found {kind}")]
+    NotAnObject { kind: &'static str },
+
+    #[error("Unsupported value for {name:?}: {kind}")]
+    UnsupportedValue { name: String, kind: &'static str },
+
+    #[error("The group {name:?} is nested into another group")]
+    NestedGroup { name: String },
+
+    #[error("Invalid key: the keys of a map must be strings")]
+    InvalidKey,
+
+    #[error("Invalid time {value:?}: {from}")]
+    InvalidTimestamp {
+        value: String,
+        from: chrono::format::ParseError,
+    },
+
+    #[error("{0}")]
+    Custom(String),
+}
+
+impl<E> serde::ser::Error for SerdeBridgeError<E>
+where
+    E: std::error::Error + 'static,
+{
+    fn custom<T: Display>(msg: T) -> Self {
+        SerdeBridgeError::Custom(msg.to_string())
+    }
+}
+
+/// Serialize a value as a thin-edge JSON payload, using a [`ThinEdgeSerdeBridge`].
+///
+/// ```
+/// use serde::Serialize;
+/// use thin_edge_json::serde_bridge::to_thin_edge_json;
+///
+/// #[derive(Serialize)]
+/// struct Location {
+///     latitude: f64,
+///     longitude: f64,
+/// }
+///
+/// #[derive(Serialize)]
+/// struct Measurements {
+///     temperature: f32,
+///     location: Location,
+/// }
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let measurements = Measurements {
+///     temperature: 25.5,
+///     location: Location { latitude: 48.5, longitude: 9.25 },
+/// };
+///
+/// assert_eq!(
+///     to_thin_edge_json(&measurements)?,
+///     r#"{"temperature":25.5,"location":{"latitude":48.5,"longitude":9.25}}"#
+/// );
+/// # Ok(()) }
+/// ```
+pub fn to_thin_edge_json<T>(
+    value: &T,
+) -> Result<String, SerdeBridgeError<ThinEdgeJsonSerializationError>>
+where
+    T: Serialize + ?Sized,
+{
+    let mut serializer = ThinEdgeJsonSerializer::new();
+    value.serialize(ThinEdgeSerdeBridge::new(&mut serializer))?;
+    serializer
+        .into_string()
+        .map_err(SerdeBridgeError::VisitorError)
+}
+
+/// Where a value is serialized in the thin-edge JSON object model
+#[derive(Debug, Clone, PartialEq)]
+enum Context {
+    /// The whole payload, which must be an object
+    Root,
+    /// A top-level field: a measurement, a group or the time
+    Field(String),
+    /// A field of a group, which must be a measurement
+    GroupField(String),
+}
+
+/// A serde serializer driving a measurement visitor,
+/// so any `serde::Serialize` value can be serialized as thin-edge JSON.
+///
+/// * The value must be serialized as a map or a struct, which fields are the measurements.
+/// * A field serialized as a number is a measurement, and one serialized as `None` a null measurement.
+/// * A field serialized as a map or a struct is a group, which fields must be measurements.
+/// * A top-level field named `time` and serialized as an RFC 3339 string is the timestamp,
+///   as for a `chrono::DateTime` field.
+///
+/// Any other value is rejected, as booleans, strings, sequences and enums.
+pub struct ThinEdgeSerdeBridge<'a, V> {
+    visitor: &'a mut V,
+    context: Context,
+}
+
+impl<'a, V> ThinEdgeSerdeBridge<'a, V>
+where
+    V: GroupedMeasurementVisitor,
+    V::Error: 'static,
+{
+    pub fn new(visitor: &'a mut V) -> Self {
+        Self {
+            visitor,
+            context: Context::Root,
+        }
+    }
+
+    fn measurement(self, value: Option<f64>) -> Result<(), SerdeBridgeError<V::Error>> {
+        match self.context {
+            Context::Root => Err(SerdeBridgeError::NotAnObject {
+                kind: if value.is_some() { "a number" } else { "null" },
+            }),
+            Context::Field(name) | Context::GroupField(name) => self
+                .visitor
+                .nullable_measurement(&name, value)
+                .map_err(SerdeBridgeError::VisitorError),
+        }
+    }
+
+    fn object(self) -> Result<ObjectSerializer<'a, V>, SerdeBridgeError<V::Error>> {
+        let is_group = match self.context {
+            Context::Root => false,
+            Context::Field(name) => {
+                self.visitor
+                    .start_group(&name)
+                    .map_err(SerdeBridgeError::VisitorError)?;
+                true
+            }
+            Context::GroupField(name) => return Err(SerdeBridgeError::NestedGroup { name }),
+        };
+        Ok(ObjectSerializer {
+            visitor: self.visitor,
+            is_group,
+            key: None,
+        })
+    }
+
+    fn unsupported(self, kind: &'static str) -> SerdeBridgeError<V::Error> {
+        match self.context {
+            Context::Root => SerdeBridgeError::NotAnObject { kind },
+            Context::Field(name) | Context::GroupField(name) => {
+                SerdeBridgeError::UnsupportedValue { name, kind }
+            }
+        }
+    }
+}
+
+impl<'a, V> serde::Serializer for ThinEdgeSerdeBridge<'a, V>
+where
+    V: GroupedMeasurementVisitor,
+    V::Error: 'static,
+{
+    type Ok = ();
+    type Error = SerdeBridgeError<V::Error>;
+    type SerializeSeq = Impossible<(), Self::Error>;
+    type SerializeTuple = Impossible<(), Self::Error>;
+    type SerializeTupleStruct = Impossible<(), Self::Error>;
+    type SerializeTupleVariant = Impossible<(), Self::Error>;
+    type SerializeMap = ObjectSerializer<'a, V>;
+    type SerializeStruct = ObjectSerializer<'a, V>;
+    type SerializeStructVariant = Impossible<(), Self::Error>;
+
+    fn serialize_bool(self, _v: bool) -> Result<(), Self::Error> {
+        Err(self.unsupported("a boolean"))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), Self::Error> {
+        self.measurement(Some(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), Self::Error> {
+        self.measurement(Some(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), Self::Error> {
+        self.measurement(Some(v.into()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), Self::Error> {
+        self.measurement(Some(v as f64))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), Self::Error> {
+        self.measurement(Some(v.into()))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), Self::Error> {
+        self.measurement(Some(v.into()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), Self::Error> {
+        self.measurement(Some(v.into()))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), Self::Error> {
+        self.measurement(Some(v as f64))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), Self::Error> {
+        // Going through the decimal representation, so 25.1f32 is 25.1 and not 25.100000381469727
+        let v: f64 = v.to_string().parse().unwrap_or_else(|_| v.into());
+        self.measurement(Some(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), Self::Error> {
+        self.measurement(Some(v))
+    }
+
+    fn serialize_char(self, _v: char) -> Result<(), Self::Error> {
+        Err(self.unsupported("a character"))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), Self::Error> {
+        if self.context != Context::Field("time".into()) {
+            return Err(self.unsupported("a string"));
+        }
+        let timestamp =
+            DateTime::parse_from_rfc3339(v).map_err(|from| SerdeBridgeError::InvalidTimestamp {
+                value: v.to_string(),
+                from,
+            })?;
+        self.visitor
+            .timestamp(timestamp)
+            .map_err(SerdeBridgeError::VisitorError)
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<(), Self::Error> {
+        Err(self.unsupported("a byte array"))
+    }
+
+    fn serialize_none(self) -> Result<(), Self::Error> {
+        self.measurement(None)
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Self::Error> {
+        self.measurement(None)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Self::Error> {
+        self.measurement(None)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<(), Self::Error> {
+        Err(self.unsupported("an enum"))
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        Err(self.unsupported("an enum"))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        Err(self.unsupported("a sequence"))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        Err(self.unsupported("a tuple"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        Err(self.unsupported("a tuple"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        Err(self.unsupported("an enum"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        self.object()
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        self.object()
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        Err(self.unsupported("an enum"))
+    }
+}
+
+/// Serialize the fields of the payload or of a group
+pub struct ObjectSerializer<'a, V> {
+    visitor: &'a mut V,
+    is_group: bool,
+    key: Option<String>,
+}
+
+impl<'a, V> ObjectSerializer<'a, V>
+where
+    V: GroupedMeasurementVisitor,
+    V::Error: 'static,
+{
+    fn field<T>(&mut self, name: String, value: &T) -> Result<(), SerdeBridgeError<V::Error>>
+    where
+        T: Serialize + ?Sized,
+    {
+        let context = if self.is_group {
+            Context::GroupField(name)
+        } else {
+            Context::Field(name)
+        };
+        value.serialize(ThinEdgeSerdeBridge {
+            visitor: &mut *self.visitor,
+            context,
+        })
+    }
+
+    fn end_object(self) -> Result<(), SerdeBridgeError<V::Error>> {
+        if self.is_group {
+            self.visitor
+                .end_group()
+                .map_err(SerdeBridgeError::VisitorError)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, V> SerializeMap for ObjectSerializer<'a, V>
+where
+    V: GroupedMeasurementVisitor,
+    V::Error: 'static,
+{
+    type Ok = ();
+    type Error = SerdeBridgeError<V::Error>;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        match serde_json::to_value(key) {
+            Ok(serde_json::Value::String(key)) => {
+                self.key = Some(key);
+                Ok(())
+            }
+            _ => Err(SerdeBridgeError::InvalidKey),
+        }
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        let name = self.key.take().ok_or(SerdeBridgeError::InvalidKey)?;
+        self.field(name, value)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        self.end_object()
+    }
+}
+
+impl<'a, V> SerializeStruct for ObjectSerializer<'a, V>
+where
+    V: GroupedMeasurementVisitor,
+    V::Error: 'static,
+{
+    type Ok = ();
+    type Error = SerdeBridgeError<V::Error>;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.field(key.to_string(), value)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        self.end_object()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use chrono::offset::FixedOffset;
+    use chrono::TimeZone;
+    use serde::Serialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize)]
+    struct Engine {
+        speed: u32,
+        temperature: f64,
+        torque: Option<f64>,
+    }
+
+    #[derive(Serialize)]
+    struct Measurements {
+        time: DateTime<FixedOffset>,
+        voltage: f64,
+        engine: Engine,
+        status: Option<i16>,
+    }
+
+    #[test]
+    fn serialize_a_measurement_struct() -> anyhow::Result<()> {
+        let measurements = Measurements {
+            time: FixedOffset::east(3600).ymd(2021, 4, 8).and_hms(10, 0, 0),
+            voltage: 230.5,
+            engine: Engine {
+                speed: 3000,
+                temperature: 90.25,
+                torque: None,
+            },
+            status: Some(-1),
+        };
+
+        assert_eq!(
+            to_thin_edge_json(&measurements)?,
+            r#"{"time":"2021-04-08T10:00:00+01:00","voltage":230.5,"engine":{"speed":3000.0,"temperature":90.25,"torque":null},"status":-1.0}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_a_map_of_groups() -> anyhow::Result<()> {
+        let mut location = BTreeMap::new();
+        location.insert("alti", 2100.4);
+        location.insert("lati", 48.5);
+        let mut measurements = BTreeMap::new();
+        measurements.insert("location", location);
+
+        assert_eq!(
+            to_thin_edge_json(&measurements)?,
+            r#"{"location":{"alti":2100.4,"lati":48.5}}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn f32_values_are_serialized_as_written() -> anyhow::Result<()> {
+        let mut measurements = BTreeMap::new();
+        measurements.insert("temperature", 25.1f32);
+
+        assert_eq!(to_thin_edge_json(&measurements)?, r#"{"temperature":25.1}"#);
+        Ok(())
+    }
+
+    #[test]
+    fn the_payload_must_be_an_object() {
+        assert_matches!(
+            to_thin_edge_json(&25.5),
+            Err(SerdeBridgeError::NotAnObject { .. })
+        );
+        assert_matches!(
+            to_thin_edge_json(&vec![1.0, 2.0]),
+            Err(SerdeBridgeError::NotAnObject { .. })
+        );
+    }
+
+    #[test]
+    fn groups_cannot_be_nested() {
+        #[derive(Serialize)]
+        struct Outer {
+            engine: BTreeMap<&'static str, BTreeMap<&'static str, f64>>,
+        }
+        let mut cylinders = BTreeMap::new();
+        cylinders.insert("cylinders", BTreeMap::new());
+
+        assert_matches!(
+            to_thin_edge_json(&Outer { engine: cylinders }),
+            Err(SerdeBridgeError::NestedGroup { name }) if name == "cylinders"
+        );
+    }
+
+    #[test]
+    fn non_numeric_values_are_rejected() {
+        #[derive(Serialize)]
+        struct Status {
+            running: bool,
+        }
+        #[derive(Serialize)]
+        struct Label {
+            name: &'static str,
+        }
+
+        assert_matches!(
+            to_thin_edge_json(&Status { running: true }),
+            Err(SerdeBridgeError::UnsupportedValue { name, .. }) if name == "running"
+        );
+        assert_matches!(
+            to_thin_edge_json(&Label { name: "engine" }),
+            Err(SerdeBridgeError::UnsupportedValue { name, .. }) if name == "name"
+        );
+    }
+
+    #[test]
+    fn the_time_must_be_rfc3339() {
+        let mut measurements = BTreeMap::new();
+        measurements.insert("time", "yesterday");
+
+        assert_matches!(
+            to_thin_edge_json(&measurements),
+            Err(SerdeBridgeError::InvalidTimestamp { value, .. }) if value == "yesterday"
+        );
+    }
+}
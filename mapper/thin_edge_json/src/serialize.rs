@@ -1,7 +1,16 @@
-use crate::measurement::GroupedMeasurementVisitor;
+use crate::context::{MeasurementContext, SPAN_ID_KEY, TRACE_ID_KEY};
+use crate::expiry::EXPIRES_AT_KEY;
+use crate::measurement::{
+    GroupedMeasurementVisitor, MeasurementQuality, MetadataVisitor, METADATA_KEY_PREFIX,
+};
+use crate::version::{SchemaVersion, SCHEMA_VERSION_KEY};
 use chrono::offset::FixedOffset;
-use chrono::DateTime;
+use chrono::{DateTime, SecondsFormat, Utc};
 use json_writer::{JsonWriter, JsonWriterError};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::convert::Infallible;
+use std::time::Duration;
 
 pub struct ThinEdgeJsonSerializer {
     json: JsonWriter,
@@ -9,6 +18,73 @@ pub struct ThinEdgeJsonSerializer {
     needs_separator: bool,
     default_timestamp: Option<DateTime<FixedOffset>>,
     timestamp_present: bool,
+    metadata: Vec<(String, FieldValue<'static>)>,
+    metadata_written: bool,
+    ttl: Option<Duration>,
+    detect_duplicates: bool,
+    keys: HashSet<String>,
+    group_keys: HashSet<String>,
+    nan_policy: NanPolicy,
+    infinity_policy: InfinityPolicy,
+    sorted_keys: bool,
+    sorted_fields: BTreeMap<String, SortedEntry>,
+    sorted_group: Option<(String, BTreeMap<String, FieldValue<'static>>)>,
+}
+
+/// How the serializer handles a NaN measurement, which has no JSON representation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NanPolicy {
+    /// Write `null` in place of the value
+    ReplaceWithNull,
+
+    /// Write `f64::MAX` in place of the value
+    ReplaceWithMax,
+
+    /// Skip the measurement
+    DropMeasurement,
+
+    /// Fail with an `InvalidF64Value` error, the default
+    ReturnError,
+}
+
+/// How the serializer handles an infinite measurement, which has no JSON representation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InfinityPolicy {
+    /// Write `null` in place of the value
+    ReplaceWithNull,
+
+    /// Write `f64::MAX` in place of `f64::INFINITY` and `f64::MIN` in place of `f64::NEG_INFINITY`
+    ReplaceWithMax,
+
+    /// Skip the measurement
+    DropMeasurement,
+
+    /// Fail with an `InvalidF64Value` error, the default
+    ReturnError,
+}
+
+/// The value of a field, as written after its key
+#[derive(Clone)]
+enum FieldValue<'a> {
+    Str(Cow<'a, str>),
+    Flag(bool),
+    Number(Option<f64>),
+    WithUnit(f64, Cow<'a, str>),
+    Complex(f64, f64),
+    Annotated(f64, MeasurementQuality),
+}
+
+/// A top-level field buffered to be written with sorted keys
+enum SortedEntry {
+    Field(FieldValue<'static>),
+    Group(BTreeMap<String, FieldValue<'static>>),
+}
+
+/// The value to be written for a measurement, once the special values handled
+enum NormalizedValue {
+    Value(f64),
+    Null,
+    Dropped,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -39,6 +115,25 @@ pub enum MeasurementStreamError {
 
     #[error("Unexpected start of group")]
     UnexpectedStartOfGroup,
+
+    #[error("Unexpected metadata after measurements")]
+    UnexpectedMetadata,
+
+    #[error("The key {0:?} is used both as metadata and measurement")]
+    MetadataKeyCollision(String),
+
+    #[error("Invalid metadata key {0:?}: a metadata key must start with an underscore")]
+    InvalidMetadataKey(String),
+
+    #[error("Duplicate measurement key: {0:?}")]
+    DuplicateMeasurementKey(String),
+}
+
+/// So visitors that cannot fail, as a `MeasurementBuffer`, can be used to collect measurements
+impl From<Infallible> for MeasurementStreamError {
+    fn from(never: Infallible) -> Self {
+        match never {}
+    }
 }
 
 impl ThinEdgeJsonSerializer {
@@ -57,7 +152,301 @@ impl ThinEdgeJsonSerializer {
             needs_separator: false,
             default_timestamp,
             timestamp_present: false,
+            metadata: Vec::new(),
+            metadata_written: false,
+            ttl: None,
+            detect_duplicates: true,
+            keys: HashSet::new(),
+            group_keys: HashSet::new(),
+            nan_policy: NanPolicy::ReturnError,
+            infinity_policy: InfinityPolicy::ReturnError,
+            sorted_keys: false,
+            sorted_fields: BTreeMap::new(),
+            sorted_group: None,
+        }
+    }
+
+    /// Enable or disable the detection of measurements and groups given twice with the same key,
+    /// which is done by default.
+    ///
+    /// Disabling this detection saves the tracking of the keys,
+    /// when the producer of the measurements is known to not repeat any key.
+    pub fn with_duplicate_detection(self, detect_duplicates: bool) -> Self {
+        Self {
+            detect_duplicates,
+            ..self
+        }
+    }
+
+    /// Set how NaN and infinite measurements are handled, these values having no JSON representation.
+    ///
+    /// By default, such a value is rejected with an `InvalidF64Value` error.
+    pub fn with_special_value_policy(self, nan: NanPolicy, inf: InfinityPolicy) -> Self {
+        Self {
+            nan_policy: nan,
+            infinity_policy: inf,
+            ..self
+        }
+    }
+
+    /// Write the fields sorted by key rather than in the order given:
+    /// `time` first, then the metadata fields, then the measurements and groups,
+    /// the keys being compared case-insensitively.
+    ///
+    /// Two messages with the same content are then serialized byte for byte the same,
+    /// whatever the order the measurements have been produced.
+    /// The fields of the groups are sorted too, as the metadata fields with the measurements.
+    ///
+    /// The fields are buffered till the end of the message.
+    pub fn with_sorted_keys(self, sorted_keys: bool) -> Self {
+        Self {
+            sorted_keys,
+            ..self
+        }
+    }
+
+    /// Add a `"_schema":"te/<major>.<minor>"` field giving the version of the format
+    /// the message conforms to.
+    ///
+    /// The version is written as the first metadata field, i.e. after the timestamp, if any.
+    pub fn with_schema_version(mut self, major: u8, minor: u8) -> Self {
+        let version = SchemaVersion::new(major, minor).to_string();
+        self.metadata.retain(|(key, _)| key != SCHEMA_VERSION_KEY);
+        self.metadata.insert(
+            0,
+            (SCHEMA_VERSION_KEY.into(), FieldValue::Str(version.into())),
+        );
+        self
+    }
+
+    /// Add an `"_expires_at"` field giving the time from which the message is obsolete,
+    /// i.e. the time the message is serialized plus the given time-to-live.
+    ///
+    /// The expiry time is given in UTC, to the second, as the last metadata field.
+    /// The consumers of the message can then drop it once expired,
+    /// see `ThinEdgeJsonExpiryFilter`.
+    pub fn with_ttl(self, ttl: Duration) -> Self {
+        Self {
+            ttl: Some(ttl),
+            ..self
+        }
+    }
+
+    /// Add a string field giving some context to the measurements,
+    /// as a device firmware version or a site id.
+    ///
+    /// The metadata fields are written after the timestamp and before the measurements,
+    /// hence must be added before any measurement or group.
+    /// A metadata key must start with `_`, as `_site`, so the parser skips the field,
+    /// and must not be used by any measurement or group of the message.
+    pub fn add_metadata(
+        &mut self,
+        key: &str,
+        value: &str,
+    ) -> Result<(), ThinEdgeJsonSerializationError> {
+        self.push_metadata(key, FieldValue::Str(value.to_string().into()))
+    }
+
+    /// Add a boolean metadata field, as `"_interpolated":true`.
+    ///
+    /// The same rules apply as for the string fields added with `add_metadata()`.
+    pub fn add_metadata_flag(
+        &mut self,
+        key: &str,
+        value: bool,
+    ) -> Result<(), ThinEdgeJsonSerializationError> {
+        self.push_metadata(key, FieldValue::Flag(value))
+    }
+
+    /// Add the magnitude `|re + i·im|` of a complex value as a plain measurement.
+    pub fn magnitude_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), ThinEdgeJsonSerializationError> {
+        self.measurement(name, real.hypot(imaginary))
+    }
+
+    /// Add the phase of a complex value as a plain measurement, in radians within `[-π, π]`.
+    pub fn phase_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), ThinEdgeJsonSerializationError> {
+        self.measurement(name, imaginary.atan2(real))
+    }
+
+    fn push_metadata(
+        &mut self,
+        key: &str,
+        value: FieldValue<'static>,
+    ) -> Result<(), ThinEdgeJsonSerializationError> {
+        if self.metadata_written || self.is_within_group {
+            return Err(MeasurementStreamError::UnexpectedMetadata.into());
+        }
+        if !key.starts_with(METADATA_KEY_PREFIX) {
+            return Err(MeasurementStreamError::InvalidMetadataKey(key.into()).into());
+        }
+        if self.is_metadata_key(key) {
+            return Err(MeasurementStreamError::MetadataKeyCollision(key.into()).into());
+        }
+
+        self.metadata.push((key.into(), value));
+        Ok(())
+    }
+
+    fn is_metadata_key(&self, key: &str) -> bool {
+        self.metadata
+            .iter()
+            .any(|(metadata_key, _)| metadata_key == key)
+    }
+
+    /// Check that a measurement or group key is not a duplicate nor clashes with a metadata key,
+    /// writing the metadata fields before the first measurement.
+    fn start_measurement_key(&mut self, key: &str) -> Result<(), ThinEdgeJsonSerializationError> {
+        if self.detect_duplicates {
+            let keys = if self.is_within_group {
+                &mut self.group_keys
+            } else {
+                &mut self.keys
+            };
+            if !keys.insert(key.into()) {
+                return Err(MeasurementStreamError::DuplicateMeasurementKey(key.into()).into());
+            }
+        }
+        if self.is_within_group {
+            return Ok(());
+        }
+        if self.is_metadata_key(key) {
+            return Err(MeasurementStreamError::MetadataKeyCollision(key.into()).into());
         }
+        self.write_metadata()
+    }
+
+    fn normalize(&self, value: f64) -> Result<NormalizedValue, ThinEdgeJsonSerializationError> {
+        let replacement = if value.is_nan() {
+            match self.nan_policy {
+                NanPolicy::ReplaceWithNull => NormalizedValue::Null,
+                NanPolicy::ReplaceWithMax => NormalizedValue::Value(f64::MAX),
+                NanPolicy::DropMeasurement => NormalizedValue::Dropped,
+                NanPolicy::ReturnError => {
+                    return Err(JsonWriterError::InvalidF64Value { value }.into())
+                }
+            }
+        } else if value.is_infinite() {
+            match self.infinity_policy {
+                InfinityPolicy::ReplaceWithNull => NormalizedValue::Null,
+                InfinityPolicy::ReplaceWithMax if value > 0.0 => NormalizedValue::Value(f64::MAX),
+                InfinityPolicy::ReplaceWithMax => NormalizedValue::Value(f64::MIN),
+                InfinityPolicy::DropMeasurement => NormalizedValue::Dropped,
+                InfinityPolicy::ReturnError => {
+                    return Err(JsonWriterError::InvalidF64Value { value }.into())
+                }
+            }
+        } else {
+            NormalizedValue::Value(value)
+        };
+        Ok(replacement)
+    }
+
+    fn write_measurement(
+        &mut self,
+        name: &str,
+        value: Option<f64>,
+    ) -> Result<(), ThinEdgeJsonSerializationError> {
+        self.start_measurement_key(name)?;
+        self.write_field(name, FieldValue::Number(value))
+    }
+
+    /// Write a field of the current object, or buffer it if the keys are sorted
+    fn write_field(
+        &mut self,
+        key: &str,
+        value: FieldValue,
+    ) -> Result<(), ThinEdgeJsonSerializationError> {
+        if self.sorted_keys {
+            let value = value.into_owned();
+            match self.sorted_group.as_mut() {
+                Some((_, fields)) => {
+                    fields.insert(key.into(), value);
+                }
+                None => {
+                    self.sorted_fields
+                        .insert(key.into(), SortedEntry::Field(value));
+                }
+            }
+            return Ok(());
+        }
+
+        if self.needs_separator {
+            self.json.write_separator();
+        }
+        self.json.write_key(key)?;
+        value.write(&mut self.json)?;
+        self.needs_separator = true;
+        Ok(())
+    }
+
+    fn write_metadata(&mut self) -> Result<(), ThinEdgeJsonSerializationError> {
+        if self.metadata_written {
+            return Ok(());
+        }
+        if let Some(expires_at) = self.expires_at() {
+            self.metadata.retain(|(key, _)| key != EXPIRES_AT_KEY);
+            self.metadata
+                .push((EXPIRES_AT_KEY.into(), FieldValue::Str(expires_at.into())));
+        }
+        for (key, value) in self.metadata.iter() {
+            if self.sorted_keys {
+                self.sorted_fields
+                    .insert(key.clone(), SortedEntry::Field(value.clone()));
+                continue;
+            }
+            if self.needs_separator {
+                self.json.write_separator();
+            }
+            self.json.write_key(key)?;
+            value.write(&mut self.json)?;
+            self.needs_separator = true;
+        }
+        self.metadata_written = true;
+        Ok(())
+    }
+
+    /// The expiry time of the message, if a time-to-live is set and can be represented
+    fn expires_at(&self) -> Option<String> {
+        let ttl = chrono::Duration::from_std(self.ttl?).ok()?;
+        let expires_at = Utc::now().checked_add_signed(ttl)?;
+        Some(expires_at.to_rfc3339_opts(SecondsFormat::Secs, true))
+    }
+
+    /// Write the buffered fields sorted by key, as given by `in_key_order()`
+    fn write_sorted_fields(&mut self) -> Result<(), ThinEdgeJsonSerializationError> {
+        let fields = std::mem::take(&mut self.sorted_fields);
+        for (key, entry) in in_key_order(fields) {
+            if self.needs_separator {
+                self.json.write_separator();
+            }
+            self.json.write_key(&key)?;
+            match entry {
+                SortedEntry::Field(value) => value.write(&mut self.json)?,
+                SortedEntry::Group(fields) => {
+                    self.json.write_open_obj();
+                    for (i, (key, value)) in in_key_order(fields).into_iter().enumerate() {
+                        if i > 0 {
+                            self.json.write_separator();
+                        }
+                        self.json.write_key(&key)?;
+                        value.write(&mut self.json)?;
+                    }
+                    self.json.write_close_obj();
+                }
+            }
+            self.needs_separator = true;
+        }
+        Ok(())
     }
 
     fn end(&mut self) -> Result<(), ThinEdgeJsonSerializationError> {
@@ -70,6 +459,10 @@ impl ThinEdgeJsonSerializer {
                 let () = self.timestamp(default_timestamp)?;
             }
         }
+        self.write_metadata()?;
+        if self.sorted_keys {
+            self.write_sorted_fields()?;
+        }
 
         self.json.write_close_obj();
         Ok(())
@@ -83,6 +476,26 @@ impl ThinEdgeJsonSerializer {
         self.end()?;
         Ok(self.json.clone().into_string()?)
     }
+
+    /// Complete the current message, returning it, and start a new one with the same settings.
+    ///
+    /// The schema version and the time-to-live are kept,
+    /// but not the other metadata fields nor the timestamp.
+    pub fn take_bytes(&mut self) -> Result<Vec<u8>, ThinEdgeJsonSerializationError> {
+        let bytes = self.into_string()?.into_bytes();
+
+        self.json = JsonWriter::with_capacity(1024);
+        self.json.write_open_obj();
+        self.needs_separator = false;
+        self.timestamp_present = false;
+        self.metadata.retain(|(key, _)| key == SCHEMA_VERSION_KEY);
+        self.metadata_written = false;
+        self.keys.clear();
+        self.group_keys.clear();
+        self.sorted_fields.clear();
+        self.sorted_group = None;
+        Ok(bytes)
+    }
 }
 
 impl Default for ThinEdgeJsonSerializer {
@@ -98,26 +511,39 @@ impl GroupedMeasurementVisitor for ThinEdgeJsonSerializer {
         if self.is_within_group {
             return Err(MeasurementStreamError::UnexpectedTimestamp.into());
         }
-
-        if self.needs_separator {
-            self.json.write_separator();
+        // The metadata follow the timestamp, hence the key is checked but no metadata written
+        if self.detect_duplicates && !self.keys.insert("time".into()) {
+            return Err(MeasurementStreamError::DuplicateMeasurementKey("time".into()).into());
         }
 
-        self.json.write_key("time")?;
-        self.json.write_str(timestamp.to_rfc3339().as_str())?;
-        self.needs_separator = true;
+        self.write_field("time", FieldValue::Str(timestamp.to_rfc3339().into()))?;
         self.timestamp_present = true;
         Ok(())
     }
 
     fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
-        if self.needs_separator {
-            self.json.write_separator();
+        match self.normalize(value)? {
+            NormalizedValue::Value(value) => self.write_measurement(name, Some(value)),
+            NormalizedValue::Null => self.write_measurement(name, None),
+            NormalizedValue::Dropped => Ok(()),
         }
-        self.json.write_key(name)?;
-        self.json.write_f64(value)?;
-        self.needs_separator = true;
-        Ok(())
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        let value = match self.normalize(value)? {
+            NormalizedValue::Value(value) => value,
+            // A missing value has no unit
+            NormalizedValue::Null => return self.write_measurement(name, None),
+            NormalizedValue::Dropped => return Ok(()),
+        };
+
+        self.start_measurement_key(name)?;
+        self.write_field(name, FieldValue::WithUnit(value, unit.into()))
     }
 
     fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
@@ -125,13 +551,19 @@ impl GroupedMeasurementVisitor for ThinEdgeJsonSerializer {
             return Err(MeasurementStreamError::UnexpectedStartOfGroup.into());
         }
 
-        if self.needs_separator {
-            self.json.write_separator();
+        self.start_measurement_key(group)?;
+        if self.sorted_keys {
+            self.sorted_group = Some((group.into(), BTreeMap::new()));
+        } else {
+            if self.needs_separator {
+                self.json.write_separator();
+            }
+            self.json.write_key(group)?;
+            self.json.write_open_obj();
+            self.needs_separator = false;
         }
-        self.json.write_key(group)?;
-        self.json.write_open_obj();
-        self.needs_separator = false;
         self.is_within_group = true;
+        self.group_keys.clear();
         Ok(())
     }
 
@@ -140,18 +572,179 @@ impl GroupedMeasurementVisitor for ThinEdgeJsonSerializer {
             return Err(MeasurementStreamError::UnexpectedEndOfGroup.into());
         }
 
-        self.json.write_close_obj();
-        self.needs_separator = true;
+        match self.sorted_group.take() {
+            Some((group, fields)) => {
+                self.sorted_fields.insert(group, SortedEntry::Group(fields));
+            }
+            None => {
+                self.json.write_close_obj();
+                self.needs_separator = true;
+            }
+        }
         self.is_within_group = false;
         Ok(())
     }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.start_group(group)?;
+        self.start_measurement_key("time")?;
+        self.write_field("time", FieldValue::Str(timestamp.to_rfc3339().into()))
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        match value {
+            Some(value) => self.measurement(name, value),
+            None => self.write_measurement(name, None),
+        }
+    }
+
+    /// Written as `"name":{"re":<real>,"im":<imaginary>}`, either within or outside a group.
+    ///
+    /// If any part is a special value, the policy applies to the whole measurement.
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        let (real, imaginary) = match (self.normalize(real)?, self.normalize(imaginary)?) {
+            (NormalizedValue::Dropped, _) | (_, NormalizedValue::Dropped) => return Ok(()),
+            (NormalizedValue::Null, _) | (_, NormalizedValue::Null) => {
+                return self.write_measurement(name, None)
+            }
+            (NormalizedValue::Value(real), NormalizedValue::Value(imaginary)) => (real, imaginary),
+        };
+
+        self.start_measurement_key(name)?;
+        self.write_field(name, FieldValue::Complex(real, imaginary))
+    }
+
+    /// Written as `"name":{"value":<value>,"quality":"GOOD"}`, either within or outside a group.
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        let value = match self.normalize(value)? {
+            NormalizedValue::Value(value) => value,
+            // A missing value has no quality
+            NormalizedValue::Null => return self.write_measurement(name, None),
+            NormalizedValue::Dropped => return Ok(()),
+        };
+
+        self.start_measurement_key(name)?;
+        self.write_field(name, FieldValue::Annotated(value, quality))
+    }
+
+    /// Written as `"_traceId"` and `"_spanId"` metadata fields, given in hexadecimal.
+    /// The baggage is not written.
+    ///
+    /// As any metadata, the context must be set before the measurements and groups.
+    /// A context set twice replaces the previous one.
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        if self.metadata_written || self.is_within_group {
+            return Err(MeasurementStreamError::UnexpectedMetadata.into());
+        }
+        self.metadata
+            .retain(|(key, _)| key != TRACE_ID_KEY && key != SPAN_ID_KEY);
+        self.push_metadata(TRACE_ID_KEY, FieldValue::Str(context.trace_id_hex().into()))?;
+        self.push_metadata(SPAN_ID_KEY, FieldValue::Str(context.span_id_hex().into()))
+    }
+}
+
+impl FieldValue<'_> {
+    fn into_owned(self) -> FieldValue<'static> {
+        match self {
+            FieldValue::Str(value) => FieldValue::Str(value.into_owned().into()),
+            FieldValue::Flag(value) => FieldValue::Flag(value),
+            FieldValue::Number(value) => FieldValue::Number(value),
+            FieldValue::WithUnit(value, unit) => {
+                FieldValue::WithUnit(value, unit.into_owned().into())
+            }
+            FieldValue::Complex(real, imaginary) => FieldValue::Complex(real, imaginary),
+            FieldValue::Annotated(value, quality) => FieldValue::Annotated(value, quality),
+        }
+    }
+
+    fn write(&self, json: &mut JsonWriter) -> Result<(), JsonWriterError> {
+        match self {
+            FieldValue::Str(value) => json.write_str(value)?,
+            FieldValue::Flag(value) => json.write_bool(*value),
+            FieldValue::Number(Some(value)) => json.write_f64(*value)?,
+            FieldValue::Number(None) => json.write_null(),
+            FieldValue::WithUnit(value, unit) => {
+                json.write_open_obj();
+                json.write_key("value")?;
+                json.write_f64(*value)?;
+                json.write_separator();
+                json.write_key("unit")?;
+                json.write_str(unit)?;
+                json.write_close_obj();
+            }
+            FieldValue::Complex(real, imaginary) => {
+                json.write_open_obj();
+                json.write_key("re")?;
+                json.write_f64(*real)?;
+                json.write_separator();
+                json.write_key("im")?;
+                json.write_f64(*imaginary)?;
+                json.write_close_obj();
+            }
+            FieldValue::Annotated(value, quality) => {
+                json.write_open_obj();
+                json.write_key("value")?;
+                json.write_f64(*value)?;
+                json.write_separator();
+                json.write_key("quality")?;
+                json.write_str(quality.as_str())?;
+                json.write_close_obj();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The fields in the order of their keys: `time` first, then the metadata fields,
+/// then the measurements and groups, the keys being compared case-insensitively.
+fn in_key_order<V>(fields: BTreeMap<String, V>) -> Vec<(String, V)> {
+    let mut fields: Vec<(String, V)> = fields.into_iter().collect();
+    fields.sort_by_cached_key(|(key, _)| sort_key(key));
+    fields
+}
+
+/// The rank of a key, then its lowercase form, ties being broken by the key itself
+fn sort_key(key: &str) -> (u8, String, String) {
+    let rank = if key == "time" {
+        0
+    } else if key.starts_with(METADATA_KEY_PREFIX) {
+        1
+    } else {
+        2
+    };
+    (rank, key.to_lowercase(), key.to_string())
+}
+
+impl MetadataVisitor for ThinEdgeJsonSerializer {
+    fn metadata(&mut self, key: &str, value: &str) -> Result<(), Self::Error> {
+        self.add_metadata(key, value)
+    }
+
+    fn metadata_flag(&mut self, key: &str, value: bool) -> Result<(), Self::Error> {
+        self.add_metadata_flag(key, value)
+    }
 }
 
 #[cfg(test)]
 
 mod tests {
     use super::*;
-    use chrono::{offset::FixedOffset, DateTime, Local};
+    use crate::expiry::ThinEdgeJsonExpiryFilter;
+    use chrono::{offset::FixedOffset, DateTime, Local, Timelike};
     fn test_timestamp() -> DateTime<FixedOffset> {
         let local_time_now: DateTime<Local> = Local::now();
         local_time_now.with_timezone(local_time_now.offset())
@@ -201,6 +794,20 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn serialize_measurement_with_unit() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        serializer.measurement_with_unit("temperature", 25.5, "°C")?;
+        serializer.start_group("location")?;
+        serializer.measurement_with_unit("alti", 2100.4, "m")?;
+        serializer.measurement("longi", 2200.4)?;
+        serializer.end_group()?;
+        let expected_output = r#"{"temperature":{"value":25.5,"unit":"°C"},"location":{"alti":{"value":2100.4,"unit":"m"},"longi":2200.4}}"#;
+        let output = serializer.into_string()?;
+        assert_eq!(expected_output, output);
+        Ok(())
+    }
+
     #[test]
     fn serialize_empty_message() -> anyhow::Result<()> {
         let mut serializer = ThinEdgeJsonSerializer::new();
@@ -266,4 +873,651 @@ mod tests {
         assert_eq!(expected_error, result.unwrap_err().to_string());
         Ok(())
     }
+
+    fn serialize_special_value(
+        nan: NanPolicy,
+        inf: InfinityPolicy,
+        value: f64,
+    ) -> Result<String, ThinEdgeJsonSerializationError> {
+        let mut serializer = ThinEdgeJsonSerializer::new().with_special_value_policy(nan, inf);
+        serializer.measurement("temperature", 25.5)?;
+        serializer.measurement("pressure", value)?;
+        serializer.into_string()
+    }
+
+    #[test]
+    fn serialize_special_values_rejected_by_default() -> anyhow::Result<()> {
+        for (value, expected_error) in [
+            (f64::NAN, "Invalid f64 value NaN"),
+            (f64::INFINITY, "Invalid f64 value inf"),
+            (f64::NEG_INFINITY, "Invalid f64 value -inf"),
+        ]
+        .iter()
+        {
+            let mut serializer = ThinEdgeJsonSerializer::new();
+            let result = serializer.measurement("pressure", *value);
+            assert_eq!(*expected_error, result.unwrap_err().to_string());
+
+            // The rejected measurement is not written at all
+            serializer.measurement("temperature", 25.5)?;
+            assert_eq!(r#"{"temperature":25.5}"#, serializer.into_string()?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_nan_with_policies() -> anyhow::Result<()> {
+        let inf = InfinityPolicy::ReturnError;
+
+        let output = serialize_special_value(NanPolicy::ReplaceWithNull, inf, f64::NAN)?;
+        assert_eq!(r#"{"temperature":25.5,"pressure":null}"#, output);
+
+        let output = serialize_special_value(NanPolicy::ReplaceWithMax, inf, f64::NAN)?;
+        assert_eq!(
+            r#"{"temperature":25.5,"pressure":1.7976931348623157e308}"#,
+            output
+        );
+
+        let output = serialize_special_value(NanPolicy::DropMeasurement, inf, f64::NAN)?;
+        assert_eq!(r#"{"temperature":25.5}"#, output);
+
+        let result = serialize_special_value(NanPolicy::ReturnError, inf, f64::NAN);
+        assert_eq!("Invalid f64 value NaN", result.unwrap_err().to_string());
+
+        // The NaN policy doesn't apply to infinite values
+        let result = serialize_special_value(NanPolicy::ReplaceWithNull, inf, f64::INFINITY);
+        assert_eq!("Invalid f64 value inf", result.unwrap_err().to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_infinity_with_policies() -> anyhow::Result<()> {
+        let nan = NanPolicy::ReturnError;
+
+        for value in [f64::INFINITY, f64::NEG_INFINITY].iter() {
+            let output = serialize_special_value(nan, InfinityPolicy::ReplaceWithNull, *value)?;
+            assert_eq!(r#"{"temperature":25.5,"pressure":null}"#, output);
+
+            let output = serialize_special_value(nan, InfinityPolicy::DropMeasurement, *value)?;
+            assert_eq!(r#"{"temperature":25.5}"#, output);
+
+            let result = serialize_special_value(nan, InfinityPolicy::ReturnError, *value);
+            assert!(result.is_err());
+        }
+
+        let output = serialize_special_value(nan, InfinityPolicy::ReplaceWithMax, f64::INFINITY)?;
+        assert_eq!(
+            r#"{"temperature":25.5,"pressure":1.7976931348623157e308}"#,
+            output
+        );
+
+        let output =
+            serialize_special_value(nan, InfinityPolicy::ReplaceWithMax, f64::NEG_INFINITY)?;
+        assert_eq!(
+            r#"{"temperature":25.5,"pressure":-1.7976931348623157e308}"#,
+            output
+        );
+
+        // The infinity policy doesn't apply to NaN
+        let result = serialize_special_value(nan, InfinityPolicy::ReplaceWithNull, f64::NAN);
+        assert_eq!("Invalid f64 value NaN", result.unwrap_err().to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_special_values_with_units_and_groups() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new()
+            .with_special_value_policy(NanPolicy::ReplaceWithNull, InfinityPolicy::DropMeasurement);
+        serializer.measurement_with_unit("temperature", f64::NAN, "°C")?;
+        serializer.start_group("location")?;
+        serializer.measurement("alti", f64::INFINITY)?;
+        serializer.nullable_measurement("longi", Some(f64::NAN))?;
+        serializer.end_group()?;
+
+        let expected_output = r#"{"temperature":null,"location":{"longi":null}}"#;
+        let output = serializer.into_string()?;
+        assert_eq!(expected_output, output);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_metadata_after_the_timestamp() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        let timestamp = test_timestamp();
+        serializer.add_metadata("_firmware", "1.2.3")?;
+        serializer.add_metadata("_site", "plant-7")?;
+        serializer.timestamp(timestamp)?;
+        serializer.measurement("temperature", 25.5)?;
+        serializer.start_group("location")?;
+        serializer.measurement("alti", 2100.4)?;
+        serializer.end_group()?;
+
+        let body = r#""_firmware":"1.2.3","_site":"plant-7","temperature":25.5,"location":{"alti":2100.4}"#;
+        let expected_output = format!(r#"{{"time":"{}",{}}}"#, timestamp.to_rfc3339(), body);
+        let output = serializer.into_string()?;
+        assert_eq!(output, expected_output);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_metadata_flags() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        serializer.add_metadata("_site", "plant-7")?;
+        serializer.add_metadata_flag("_interpolated", true)?;
+        serializer.measurement("temperature", 25.5)?;
+        let expected_output = r#"{"_site":"plant-7","_interpolated":true,"temperature":25.5}"#;
+        let output = serializer.into_string()?;
+        assert_eq!(expected_output, output);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_metadata_only_message() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        serializer.add_metadata("_reason", "scheduled")?;
+        let expected_output = r#"{"_reason":"scheduled"}"#;
+        let output = serializer.into_string()?;
+        assert_eq!(expected_output, output);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_schema_version() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new().with_schema_version(1, 0);
+        let timestamp = test_timestamp();
+        serializer.add_metadata("_site", "plant-7")?;
+        serializer.timestamp(timestamp)?;
+        serializer.measurement("temperature", 25.5)?;
+
+        let body = r#""_schema":"te/1.0","_site":"plant-7","temperature":25.5"#;
+        let expected_output = format!(r#"{{"time":"{}",{}}}"#, timestamp.to_rfc3339(), body);
+        let output = serializer.into_string()?;
+        assert_eq!(output, expected_output);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_expiry_time() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new()
+            .with_schema_version(1, 0)
+            .with_ttl(Duration::from_secs(60));
+        serializer.add_metadata("_site", "plant-7")?;
+        serializer.measurement("temperature", 25.5)?;
+
+        let before = Utc::now().with_nanosecond(0).unwrap();
+        let output = serializer.take_bytes()?;
+        let after = Utc::now();
+
+        let expires_at = ThinEdgeJsonExpiryFilter::expires_at(&output).unwrap();
+        assert!(before + chrono::Duration::seconds(60) <= expires_at);
+        assert!(expires_at <= after + chrono::Duration::seconds(60));
+        let expected_output = format!(
+            r#"{{"_schema":"te/1.0","_site":"plant-7","_expires_at":"{}","temperature":25.5}}"#,
+            expires_at.to_rfc3339_opts(SecondsFormat::Secs, true)
+        );
+        assert_eq!(String::from_utf8(output)?, expected_output);
+
+        // The time-to-live applies to the next messages too
+        serializer.measurement("temperature", 26.0)?;
+        let output = serializer.bytes()?;
+        assert!(ThinEdgeJsonExpiryFilter::expires_at(&output).unwrap() >= expires_at);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_trace_context() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        let mut trace_id = [0u8; 16];
+        trace_id[15] = 0x2a;
+        let context = MeasurementContext::new(trace_id, [0xab, 0, 0, 0, 0, 0, 0, 0x01])
+            .with_baggage("site", "plant-7");
+        serializer.set_context(MeasurementContext::new([0xff; 16], [0xff; 8]))?;
+        serializer.set_context(context)?;
+        serializer.measurement("temperature", 25.5)?;
+
+        let expected_output = r#"{"_traceId":"0000000000000000000000000000002a","_spanId":"ab00000000000001","temperature":25.5}"#;
+        assert_eq!(serializer.into_string()?, expected_output);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_trace_context_after_measurements() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        serializer.measurement("temperature", 25.5)?;
+
+        let result = serializer.set_context(MeasurementContext::default());
+
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_schema_version_set_twice() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new()
+            .with_schema_version(1, 0)
+            .with_schema_version(2, 1);
+        serializer.measurement("temperature", 25.5)?;
+        let output = serializer.bytes()?;
+        assert_eq!(output, br#"{"_schema":"te/2.1","temperature":25.5}"#);
+        assert_eq!(SchemaVersion::parse_from_payload(&output), Some((2, 1)));
+        Ok(())
+    }
+
+    #[test]
+    fn take_bytes_starts_a_new_message_with_the_same_settings() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new()
+            .with_schema_version(1, 0)
+            .with_sorted_keys(true);
+        serializer.add_metadata("_site", "plant-7")?;
+        serializer.timestamp(test_timestamp())?;
+        serializer.measurement("temperature", 25.5)?;
+        let _ = serializer.take_bytes()?;
+
+        serializer.measurement("temperature", 26.0)?;
+        serializer.measurement("pressure", 98.0)?;
+        let output = serializer.take_bytes()?;
+        assert_eq!(
+            output,
+            br#"{"_schema":"te/1.0","pressure":98.0,"temperature":26.0}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_metadata_key_clashing_with_a_measurement() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        serializer.add_metadata("_site", "plant-7")?;
+        let result = serializer.measurement("_site", 7.0);
+        let expected_error = r#"The key "_site" is used both as metadata and measurement"#;
+        assert_eq!(expected_error, result.unwrap_err().to_string());
+
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        serializer.add_metadata("_location", "north")?;
+        let result = serializer.start_group("_location");
+        assert_eq!(
+            r#"The key "_location" is used both as metadata and measurement"#,
+            result.unwrap_err().to_string()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_metadata_key_used_within_a_group() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        serializer.add_metadata("_alti", "estimated")?;
+        serializer.start_group("location")?;
+        serializer.measurement("_alti", 2100.4)?;
+        serializer.end_group()?;
+        let expected_output = r#"{"_alti":"estimated","location":{"_alti":2100.4}}"#;
+        let output = serializer.into_string()?;
+        assert_eq!(expected_output, output);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_unexpected_metadata() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        let result = serializer.add_metadata("time", "now");
+        assert_eq!(
+            r#"Invalid metadata key "time": a metadata key must start with an underscore"#,
+            result.unwrap_err().to_string()
+        );
+        let result = serializer.add_metadata_flag("interpolated", true);
+        assert_eq!(
+            r#"Invalid metadata key "interpolated": a metadata key must start with an underscore"#,
+            result.unwrap_err().to_string()
+        );
+
+        serializer.measurement("temperature", 25.5)?;
+        let result = serializer.add_metadata("_site", "plant-7");
+        let expected_error = "Unexpected metadata after measurements";
+        assert_eq!(expected_error, result.unwrap_err().to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_duplicate_measurement() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        serializer.measurement("temp", 25.0)?;
+        let result = serializer.measurement_with_unit("temp", 26.0, "°C");
+        let expected_error = r#"Duplicate measurement key: "temp""#;
+        assert_eq!(expected_error, result.unwrap_err().to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_duplicate_group() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        serializer.start_group("location")?;
+        serializer.measurement("alti", 2100.4)?;
+        let result = serializer.measurement("alti", 2100.5);
+        let expected_error = r#"Duplicate measurement key: "alti""#;
+        assert_eq!(expected_error, result.unwrap_err().to_string());
+        serializer.end_group()?;
+
+        let result = serializer.start_group("location");
+        let expected_error = r#"Duplicate measurement key: "location""#;
+        assert_eq!(expected_error, result.unwrap_err().to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_same_key_at_different_levels() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        serializer.measurement("alti", 2100.0)?;
+        serializer.start_group("location")?;
+        serializer.measurement("alti", 2100.4)?;
+        serializer.end_group()?;
+        serializer.start_group("backup")?;
+        serializer.measurement("alti", 2100.5)?;
+        serializer.end_group()?;
+        let expected_output =
+            r#"{"alti":2100.0,"location":{"alti":2100.4},"backup":{"alti":2100.5}}"#;
+        let output = serializer.into_string()?;
+        assert_eq!(expected_output, output);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_duplicates_when_detection_is_disabled() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new().with_duplicate_detection(false);
+        serializer.measurement("temp", 25.0)?;
+        serializer.measurement("temp", 26.0)?;
+        let expected_output = r#"{"temp":25.0,"temp":26.0}"#;
+        let output = serializer.into_string()?;
+        assert_eq!(expected_output, output);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_group_with_timestamp() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        let timestamp = test_timestamp();
+        let group_timestamp = timestamp + chrono::Duration::milliseconds(100);
+        serializer.timestamp(timestamp)?;
+        serializer.measurement("temperature", 25.5)?;
+        serializer.start_group_with_timestamp("pressure", group_timestamp)?;
+        serializer.measurement("inlet", 98.1)?;
+        serializer.measurement("outlet", 97.4)?;
+        serializer.end_group()?;
+        let expected_output = format!(
+            r#"{{"time":"{}","temperature":25.5,"pressure":{{"time":"{}","inlet":98.1,"outlet":97.4}}}}"#,
+            timestamp.to_rfc3339(),
+            group_timestamp.to_rfc3339()
+        );
+        let output = serializer.into_string()?;
+        assert_eq!(expected_output, output);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_group_with_timestamp_and_time_measurement() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        serializer.start_group_with_timestamp("pressure", test_timestamp())?;
+        let result = serializer.measurement("time", 1.0);
+        let expected_error = r#"Duplicate measurement key: "time""#;
+        assert_eq!(expected_error, result.unwrap_err().to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_duplicate_timestamp() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        serializer.timestamp(test_timestamp())?;
+        let result = serializer.timestamp(test_timestamp());
+        let expected_error = r#"Duplicate measurement key: "time""#;
+        assert_eq!(expected_error, result.unwrap_err().to_string());
+
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        serializer.timestamp(test_timestamp())?;
+        let result = serializer.measurement("time", 1.0);
+        assert_eq!(expected_error, result.unwrap_err().to_string());
+
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        serializer.measurement("time", 1.0)?;
+        let result = serializer.timestamp(test_timestamp());
+        assert_eq!(expected_error, result.unwrap_err().to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_missing_measurement_values() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        serializer.nullable_measurement("temperature", None)?;
+        serializer.start_group("location")?;
+        serializer.nullable_measurement("alti", Some(2100.4))?;
+        serializer.nullable_measurement("longi", None)?;
+        serializer.end_group()?;
+        let expected_output = r#"{"temperature":null,"location":{"alti":2100.4,"longi":null}}"#;
+        let output = serializer.into_string()?;
+        assert_eq!(expected_output, output);
+
+        let json: serde_json::Value = serde_json::from_str(&output)?;
+        assert_eq!(json.get("temperature"), Some(&serde_json::Value::Null));
+        assert_eq!(json.get("pressure"), None);
+        assert_eq!(
+            json["location"].get("longi"),
+            Some(&serde_json::Value::Null)
+        );
+        assert_eq!(json["location"].get("lati"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_complex_measurements() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        serializer.complex_measurement("impedance", 3.0, 4.0)?;
+        serializer.start_group("phase_a")?;
+        serializer.complex_measurement("current", -1.5, 0.0)?;
+        serializer.end_group()?;
+        let expected_output =
+            r#"{"impedance":{"re":3.0,"im":4.0},"phase_a":{"current":{"re":-1.5,"im":0.0}}}"#;
+        assert_eq!(expected_output, serializer.into_string()?);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_complex_measurement_with_special_values() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        assert!(serializer.complex_measurement("z", 3.0, f64::NAN).is_err());
+
+        let mut serializer = ThinEdgeJsonSerializer::new()
+            .with_special_value_policy(NanPolicy::ReplaceWithNull, InfinityPolicy::DropMeasurement);
+        serializer.complex_measurement("z", f64::NAN, 4.0)?;
+        serializer.complex_measurement("w", 1.0, f64::INFINITY)?;
+        assert_eq!(r#"{"z":null}"#, serializer.into_string()?);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_annotated_measurements() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        serializer.annotated_measurement("temperature", 25.5, MeasurementQuality::Good)?;
+        serializer.start_group("engine")?;
+        serializer.annotated_measurement("speed", -1.0, MeasurementQuality::Bad)?;
+        serializer.annotated_measurement("pressure", 98.0, MeasurementQuality::Uncertain)?;
+        serializer.end_group()?;
+        let expected_output = concat!(
+            r#"{"temperature":{"value":25.5,"quality":"GOOD"},"#,
+            r#""engine":{"speed":{"value":-1.0,"quality":"BAD"},"#,
+            r#""pressure":{"value":98.0,"quality":"UNCERTAIN"}}}"#
+        );
+        assert_eq!(expected_output, serializer.into_string()?);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_annotated_measurement_with_special_values() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        assert!(serializer
+            .annotated_measurement("temperature", f64::NAN, MeasurementQuality::Bad)
+            .is_err());
+
+        let mut serializer = ThinEdgeJsonSerializer::new()
+            .with_special_value_policy(NanPolicy::ReplaceWithNull, InfinityPolicy::DropMeasurement);
+        serializer.annotated_measurement("temperature", f64::NAN, MeasurementQuality::Bad)?;
+        serializer.annotated_measurement("pressure", f64::INFINITY, MeasurementQuality::Good)?;
+        assert_eq!(r#"{"temperature":null}"#, serializer.into_string()?);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_magnitude_and_phase_of_complex_values() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        serializer.magnitude_measurement("magnitude", 3.0, 4.0)?;
+        serializer.phase_measurement("phase", 0.0, 2.0)?;
+        let output = serializer.into_string()?;
+
+        let json: serde_json::Value = serde_json::from_str(&output)?;
+        assert_eq!(json["magnitude"].as_f64(), Some(5.0));
+        assert_eq!(json["phase"].as_f64(), Some(std::f64::consts::FRAC_PI_2));
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_with_sorted_keys() -> anyhow::Result<()> {
+        let timestamp = DateTime::parse_from_rfc3339("2021-04-30T17:03:14+02:00")?;
+
+        let mut first = ThinEdgeJsonSerializer::new().with_sorted_keys(true);
+        first.measurement("temperature", 25.5)?;
+        first.start_group("location")?;
+        first.measurement("longi", 2200.4)?;
+        first.measurement_with_unit("alti", 2100.4, "m")?;
+        first.end_group()?;
+        first.nullable_measurement("pressure", None)?;
+        first.timestamp(timestamp)?;
+
+        let mut second = ThinEdgeJsonSerializer::new().with_sorted_keys(true);
+        second.timestamp(timestamp)?;
+        second.nullable_measurement("pressure", None)?;
+        second.start_group("location")?;
+        second.measurement_with_unit("alti", 2100.4, "m")?;
+        second.measurement("longi", 2200.4)?;
+        second.end_group()?;
+        second.measurement("temperature", 25.5)?;
+
+        let expected_output = r#"{"time":"2021-04-30T17:03:14+02:00","location":{"alti":{"value":2100.4,"unit":"m"},"longi":2200.4},"pressure":null,"temperature":25.5}"#;
+        assert_eq!(first.into_string()?, expected_output);
+        assert_eq!(second.into_string()?, expected_output);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_with_sorted_mixed_case_keys() -> anyhow::Result<()> {
+        let timestamp = DateTime::parse_from_rfc3339("2021-04-30T17:03:14+02:00")?;
+        let mut serializer = ThinEdgeJsonSerializer::new().with_sorted_keys(true);
+
+        serializer.add_metadata("_Site", "Factory 1")?;
+        serializer.add_metadata("_line", "A")?;
+        serializer.measurement("beta", 2.0)?;
+        serializer.measurement("Zeta", 26.0)?;
+        serializer.start_group("Location")?;
+        serializer.measurement("longi", 2200.4)?;
+        serializer.measurement("Alti", 2100.4)?;
+        serializer.end_group()?;
+        serializer.measurement("alpha", 1.0)?;
+        serializer.measurement("Alpha", 1.5)?;
+        serializer.timestamp(timestamp)?;
+
+        let expected_output = r#"{"time":"2021-04-30T17:03:14+02:00","_line":"A","_Site":"Factory 1","Alpha":1.5,"alpha":1.0,"beta":2.0,"Location":{"Alti":2100.4,"longi":2200.4},"Zeta":26.0}"#;
+        assert_eq!(serializer.into_string()?, expected_output);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_with_sorted_keys_and_metadata() -> anyhow::Result<()> {
+        let timestamp = DateTime::parse_from_rfc3339("2021-04-30T17:03:14+02:00")?;
+        let mut serializer = ThinEdgeJsonSerializer::new()
+            .with_schema_version(1, 0)
+            .with_sorted_keys(true);
+
+        serializer.add_metadata("_site", "Factory 1")?;
+        serializer.complex_measurement("voltage", 230.0, -1.5)?;
+        serializer.start_group_with_timestamp("engine", timestamp)?;
+        serializer.measurement("speed", 3000.0)?;
+        serializer.end_group()?;
+        serializer.measurement("alti", 2100.4)?;
+
+        let expected_output = r#"{"_schema":"te/1.0","_site":"Factory 1","alti":2100.4,"engine":{"time":"2021-04-30T17:03:14+02:00","speed":3000.0},"voltage":{"re":230.0,"im":-1.5}}"#;
+        assert_eq!(serializer.into_string()?, expected_output);
+        Ok(())
+    }
+
+    mod properties {
+        use super::*;
+        use chrono::TimeZone;
+        use proptest::prelude::*;
+
+        fn measurements() -> impl Strategy<Value = Vec<(String, f64)>> {
+            // The time key clashes with the timestamp, when there is one
+            let name = prop_oneof![1 => Just("time".to_string()), 9 => "\\PC*"];
+            let value = any::<f64>().prop_filter("A finite value", |value| value.is_finite());
+            // Unique names, as duplicated keys are rejected
+            prop::collection::btree_map(name, value, 0..20)
+                .prop_map(|measurements| measurements.into_iter().collect())
+        }
+
+        fn timestamp() -> impl Strategy<Value = DateTime<FixedOffset>> {
+            // RFC 3339 offsets are given in minutes
+            (0i64..4_102_444_800, -720i32..840).prop_map(|(secs, offset_minutes)| {
+                FixedOffset::east(offset_minutes * 60).timestamp(secs, 0)
+            })
+        }
+
+        fn serialize(
+            measurements: &[(String, f64)],
+            timestamp: Option<DateTime<FixedOffset>>,
+        ) -> Result<String, ThinEdgeJsonSerializationError> {
+            let mut serializer = ThinEdgeJsonSerializer::new();
+            if let Some(timestamp) = timestamp {
+                serializer.timestamp(timestamp)?;
+            }
+            for (name, value) in measurements.iter() {
+                serializer.measurement(name, *value)?;
+            }
+            serializer.into_string()
+        }
+
+        proptest! {
+            #[test]
+            fn output_is_valid_json_with_all_the_measurements(
+                (measurements, with_timestamp) in (measurements(), any::<bool>()),
+                timestamp in timestamp(),
+            ) {
+                let timestamp = Some(timestamp).filter(|_| with_timestamp);
+                let result = serialize(&measurements, timestamp);
+                if with_timestamp && measurements.iter().any(|(name, _)| name == "time") {
+                    prop_assert_eq!(
+                        result.unwrap_err().to_string(),
+                        r#"Duplicate measurement key: "time""#
+                    );
+                    return Ok(());
+                }
+                let output = result.unwrap();
+
+                let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+                let object = json.as_object().unwrap();
+                prop_assert_eq!(object.len(), measurements.len() + timestamp.iter().count());
+                for (name, value) in measurements.iter() {
+                    prop_assert!(object.contains_key(name));
+
+                    // The value is given in its shortest form, from which it is parsed back exactly
+                    let number = serde_json::to_string(value).unwrap();
+                    let key = serde_json::to_string(name).unwrap();
+                    let field = format!("{}:{}", key, number);
+                    prop_assert!(output.contains(&field));
+                    prop_assert_eq!(number.parse::<f64>().unwrap().to_bits(), value.to_bits());
+                }
+            }
+
+            #[test]
+            fn timestamp_is_given_in_rfc3339(timestamp in timestamp()) {
+                let output = serialize(&[], Some(timestamp)).unwrap();
+
+                let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+                let time = json["time"].as_str().unwrap();
+                prop_assert_eq!(DateTime::parse_from_rfc3339(time).unwrap(), timestamp);
+            }
+        }
+    }
 }
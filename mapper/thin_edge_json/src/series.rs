@@ -0,0 +1,295 @@
+use crate::measurement::GroupedMeasurementVisitor;
+use crate::serialize::MeasurementStreamError;
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use std::collections::HashMap;
+
+/// A measurement attached to its group, if any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlatMeasurement {
+    pub group: Option<String>,
+    pub name: String,
+    pub value: f64,
+    pub unit: Option<String>,
+}
+
+/// A series of measurements stored as a flat list, in the order they have been produced.
+///
+/// This is the pivot used to convert thin-edge JSON from and to tabular formats,
+/// as CSV, where each measurement is given along its group.
+///
+/// * As a `GroupedMeasurementVisitor`, a `FlatMeasurementSeries` collects measurements,
+///   e.g. when parsing thin-edge JSON.
+/// * The measurements of a series can be pushed in any order, including interleaved groups,
+///   and then visited, the measurements of each group being gathered
+///   where the group first appears.
+///
+/// ```
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+/// use thin_edge_json::series::FlatMeasurementSeries;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let mut series = FlatMeasurementSeries::new();
+/// series.push(Some("location"), "alti", 2100.4, None);
+/// series.push(None, "temperature", 25.5, Some("°C"));
+/// series.push(Some("location"), "longi", 2200.4, None);
+///
+/// let mut serializer = ThinEdgeJsonSerializer::new();
+/// series.visit(&mut serializer)?;
+///
+/// assert_eq!(
+///     serializer.into_string()?,
+///     r#"{"location":{"alti":2100.4,"longi":2200.4},"temperature":{"value":25.5,"unit":"°C"}}"#
+/// );
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FlatMeasurementSeries {
+    pub timestamp: Option<DateTime<FixedOffset>>,
+    pub measurements: Vec<FlatMeasurement>,
+    group: Option<String>,
+}
+
+enum Entry<'a> {
+    Measurement(&'a FlatMeasurement),
+    Group(&'a str, Vec<&'a FlatMeasurement>),
+}
+
+impl FlatMeasurementSeries {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.measurements.is_empty()
+    }
+
+    pub fn push(&mut self, group: Option<&str>, name: &str, value: f64, unit: Option<&str>) {
+        self.measurements.push(FlatMeasurement {
+            group: group.map(String::from),
+            name: name.to_string(),
+            value,
+            unit: unit.map(String::from),
+        });
+    }
+
+    /// Visit the measurements of the series, group by group
+    pub fn visit<V>(&self, visitor: &mut V) -> Result<(), V::Error>
+    where
+        V: GroupedMeasurementVisitor,
+    {
+        if let Some(timestamp) = self.timestamp {
+            visitor.timestamp(timestamp)?;
+        }
+
+        for entry in self.entries() {
+            match entry {
+                Entry::Measurement(measurement) => visit_measurement(visitor, measurement)?,
+                Entry::Group(group, measurements) => {
+                    visitor.start_group(group)?;
+                    for measurement in measurements {
+                        visit_measurement(visitor, measurement)?;
+                    }
+                    visitor.end_group()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Split the series into one series per group, plus one for the top-level measurements,
+    /// in the order the groups first appear, all sharing the timestamp of this series.
+    pub fn split_by_group(&self) -> Vec<(Option<&str>, FlatMeasurementSeries)> {
+        let mut series: Vec<(Option<&str>, FlatMeasurementSeries)> = Vec::new();
+        for measurement in self.measurements.iter() {
+            let group = measurement.group.as_deref();
+            let index = match series.iter().position(|(name, _)| *name == group) {
+                Some(index) => index,
+                None => {
+                    series.push((
+                        group,
+                        FlatMeasurementSeries {
+                            timestamp: self.timestamp,
+                            ..FlatMeasurementSeries::default()
+                        },
+                    ));
+                    series.len() - 1
+                }
+            };
+            series[index].1.measurements.push(measurement.clone());
+        }
+        series
+    }
+
+    fn entries(&self) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        let mut group_index = HashMap::new();
+
+        for measurement in self.measurements.iter() {
+            match measurement.group.as_deref() {
+                None => entries.push(Entry::Measurement(measurement)),
+                Some(group) => {
+                    let index = *group_index.entry(group).or_insert_with(|| {
+                        entries.push(Entry::Group(group, Vec::new()));
+                        entries.len() - 1
+                    });
+                    if let Entry::Group(_, measurements) = &mut entries[index] {
+                        measurements.push(measurement);
+                    }
+                }
+            }
+        }
+        entries
+    }
+}
+
+fn visit_measurement<V>(visitor: &mut V, measurement: &FlatMeasurement) -> Result<(), V::Error>
+where
+    V: GroupedMeasurementVisitor,
+{
+    match measurement.unit.as_deref() {
+        Some(unit) => visitor.measurement_with_unit(&measurement.name, measurement.value, unit),
+        None => visitor.measurement(&measurement.name, measurement.value),
+    }
+}
+
+impl GroupedMeasurementVisitor for FlatMeasurementSeries {
+    type Error = MeasurementStreamError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        if self.group.is_some() {
+            return Err(MeasurementStreamError::UnexpectedTimestamp);
+        }
+        self.timestamp = Some(value);
+        Ok(())
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        let group = self.group.clone();
+        self.push(group.as_deref(), name, value, None);
+        Ok(())
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        if self.group.is_some() {
+            return Err(MeasurementStreamError::UnexpectedStartOfGroup);
+        }
+        self.group = Some(group.to_string());
+        Ok(())
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        if self.group.take().is_none() {
+            return Err(MeasurementStreamError::UnexpectedEndOfGroup);
+        }
+        Ok(())
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        let group = self.group.clone();
+        self.push(group.as_deref(), name, value, Some(unit));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::parse_str;
+    use crate::serialize::ThinEdgeJsonSerializer;
+    use assert_matches::assert_matches;
+
+    #[test]
+    fn a_parsed_payload_is_visited_unchanged() -> anyhow::Result<()> {
+        let input = r#"{"time":"2021-04-30T17:03:14+02:00","temperature":25.5,"location":{"alti":2100.4,"longi":2200.4},"pressure":98.0}"#;
+
+        let mut series = FlatMeasurementSeries::new();
+        parse_str(input, &mut series)?;
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        series.visit(&mut serializer)?;
+
+        assert_eq!(serializer.into_string()?, input);
+        Ok(())
+    }
+
+    #[test]
+    fn measurements_are_attached_to_their_group() -> anyhow::Result<()> {
+        let mut series = FlatMeasurementSeries::new();
+        series.measurement("temperature", 25.5)?;
+        series.start_group("location")?;
+        series.measurement_with_unit("alti", 2100.4, "m")?;
+        series.end_group()?;
+
+        assert_eq!(
+            series.measurements,
+            vec![
+                FlatMeasurement {
+                    group: None,
+                    name: "temperature".into(),
+                    value: 25.5,
+                    unit: None,
+                },
+                FlatMeasurement {
+                    group: Some("location".into()),
+                    name: "alti".into(),
+                    value: 2100.4,
+                    unit: Some("m".into()),
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn a_series_is_split_by_group() -> anyhow::Result<()> {
+        let mut series = FlatMeasurementSeries::new();
+        parse_str(
+            r#"{"time":"2021-04-30T17:03:14+02:00","temperature":25.5,"location":{"alti":2100.4},"pressure":98.0}"#,
+            &mut series,
+        )?;
+
+        let mut payloads = Vec::new();
+        for (group, series) in series.split_by_group() {
+            let mut serializer = ThinEdgeJsonSerializer::new();
+            series.visit(&mut serializer)?;
+            payloads.push((group, serializer.into_string()?));
+        }
+
+        assert_eq!(
+            payloads,
+            vec![
+                (
+                    None,
+                    r#"{"time":"2021-04-30T17:03:14+02:00","temperature":25.5,"pressure":98.0}"#
+                        .to_string()
+                ),
+                (
+                    Some("location"),
+                    r#"{"time":"2021-04-30T17:03:14+02:00","location":{"alti":2100.4}}"#
+                        .to_string()
+                ),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reject_unbalanced_groups() {
+        let mut series = FlatMeasurementSeries::new();
+
+        assert_matches!(
+            series.end_group(),
+            Err(MeasurementStreamError::UnexpectedEndOfGroup)
+        );
+        assert_matches!(series.start_group("location"), Ok(()));
+        assert_matches!(
+            series.start_group("location"),
+            Err(MeasurementStreamError::UnexpectedStartOfGroup)
+        );
+    }
+}
@@ -0,0 +1,493 @@
+use crate::json::{parse_str, ThinEdgeJsonParserError};
+use crate::measurement::GroupedMeasurementVisitor;
+use chrono::offset::FixedOffset;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+const DEFAULT_SOURCE_LABEL: &str = "thin-edge";
+
+/// A SignalK delta message, updating the values of a vessel.
+///
+/// Serialized as documented by the
+/// [SignalK specification](https://signalk.org/specification/1.7.0/doc/data_model.html):
+///
+/// ```json
+/// {
+///   "context": "vessels.urn:mrn:imo:mmsi:<mmsi>",
+///   "updates": [{
+///     "source": {"label": "thin-edge"},
+///     "timestamp": "2021-04-30T15:03:14.000Z",
+///     "values": [{"path": "<group>.<name>", "value": 25.5}]
+///   }]
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SignalKDelta {
+    pub context: String,
+    pub updates: Vec<SignalKUpdate>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SignalKUpdate {
+    pub source: SignalKSource,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+    pub values: Vec<SignalKValue>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SignalKSource {
+    pub label: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SignalKValue {
+    pub path: String,
+    pub value: Value,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SignalKError {
+    #[error("Invalid MMSI: {mmsi:?} must be given as 9 digits")]
+    InvalidMmsi { mmsi: String },
+
+    #[error("Invalid SignalK path: {name:?} must not be empty")]
+    InvalidPath { name: String },
+
+    #[error("Invalid value for {name}: {value} has no JSON representation")]
+    InvalidValue { name: String, value: f64 },
+
+    #[error("Unexpected time stamp within a group")]
+    UnexpectedTimestamp,
+
+    #[error("Unexpected end of group")]
+    UnexpectedEndOfGroup,
+
+    #[error("Unexpected start of group")]
+    UnexpectedStartOfGroup,
+}
+
+/// Convert thin-edge JSON measurements into a [SignalK](https://signalk.org) delta message,
+/// updating the values of a vessel identified by its MMSI.
+///
+/// Each measurement is mapped to a SignalK value, whose path is the name of the measurement,
+/// prefixed by the name of its group if any, as in `propulsion.rpm`.
+///
+/// * The measurements are given in a single update, timestamped with the time of the message,
+///   except the measurements of a group with a timestamp of its own,
+///   given in an update of their own.
+/// * The timestamps are given in UTC, as required by SignalK.
+/// * The units are not given, SignalK values being expressed in SI units.
+/// * The complex measurements are given as `{"re":<real>,"im":<imaginary>}` objects,
+///   and the quality of the annotated measurements is ignored.
+///
+/// ```
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::signalk::SignalKDeltaVisitor;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let mut visitor = SignalKDeltaVisitor::new("123456789")?;
+/// visitor.start_group("navigation")?;
+/// visitor.measurement("speedOverGround", 3.85)?;
+/// visitor.end_group()?;
+///
+/// assert_eq!(
+///     serde_json::to_string(&visitor.into_delta())?,
+///     r#"{"context":"vessels.urn:mrn:imo:mmsi:123456789","updates":[{"source":{"label":"thin-edge"},"values":[{"path":"navigation.speedOverGround","value":3.85}]}]}"#
+/// );
+/// # Ok(()) }
+/// ```
+#[derive(Debug)]
+pub struct SignalKDeltaVisitor {
+    context: String,
+    source_label: String,
+    timestamp: Option<DateTime<FixedOffset>>,
+    values: Vec<SignalKValue>,
+    group: Option<String>,
+    group_updates: Vec<(DateTime<FixedOffset>, Vec<SignalKValue>)>,
+    is_timestamped_group: bool,
+}
+
+impl SignalKDeltaVisitor {
+    /// Create a visitor for the vessel with the given MMSI, given as 9 digits
+    pub fn new(mmsi: &str) -> Result<Self, SignalKError> {
+        if mmsi.len() != 9 || !mmsi.chars().all(|c| c.is_ascii_digit()) {
+            return Err(SignalKError::InvalidMmsi { mmsi: mmsi.into() });
+        }
+
+        Ok(Self {
+            context: format!("vessels.urn:mrn:imo:mmsi:{}", mmsi),
+            source_label: DEFAULT_SOURCE_LABEL.into(),
+            timestamp: None,
+            values: Vec::new(),
+            group: None,
+            group_updates: Vec::new(),
+            is_timestamped_group: false,
+        })
+    }
+
+    /// Set the label of the source of the updates, `thin-edge` by default
+    pub fn with_source_label(self, label: &str) -> Self {
+        Self {
+            source_label: label.into(),
+            ..self
+        }
+    }
+
+    /// Convert a thin-edge JSON string into a SignalK delta message for the given vessel
+    pub fn convert(
+        thin_edge_json: &str,
+        mmsi: &str,
+    ) -> Result<SignalKDelta, ThinEdgeJsonParserError<SignalKError>> {
+        let mut visitor =
+            SignalKDeltaVisitor::new(mmsi).map_err(ThinEdgeJsonParserError::VisitorError)?;
+        let () = parse_str(thin_edge_json, &mut visitor)?;
+        Ok(visitor.into_delta())
+    }
+
+    /// The delta message built so far, with no update if no measurements have been given
+    pub fn into_delta(self) -> SignalKDelta {
+        let source = SignalKSource {
+            label: self.source_label,
+        };
+        let mut updates = Vec::new();
+        if !self.values.is_empty() {
+            updates.push(SignalKUpdate {
+                source: source.clone(),
+                timestamp: self.timestamp.map(utc_timestamp),
+                values: self.values,
+            });
+        }
+        for (timestamp, values) in self.group_updates {
+            updates.push(SignalKUpdate {
+                source: source.clone(),
+                timestamp: Some(utc_timestamp(timestamp)),
+                values,
+            });
+        }
+
+        SignalKDelta {
+            context: self.context,
+            updates,
+        }
+    }
+
+    fn update(&mut self, name: &str, value: Value) -> Result<(), SignalKError> {
+        if name.is_empty() {
+            return Err(SignalKError::InvalidPath { name: name.into() });
+        }
+        let path = match self.group.as_ref() {
+            Some(group) => format!("{}.{}", group, name),
+            None => name.to_string(),
+        };
+
+        let value = SignalKValue { path, value };
+        match self.group_updates.last_mut() {
+            Some((_, values)) if self.is_timestamped_group => values.push(value),
+            _ => self.values.push(value),
+        }
+        Ok(())
+    }
+}
+
+fn utc_timestamp(timestamp: DateTime<FixedOffset>) -> String {
+    timestamp
+        .with_timezone(&Utc)
+        .to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn json_number(name: &str, value: f64) -> Result<Value, SignalKError> {
+    serde_json::Number::from_f64(value)
+        .map(Value::Number)
+        .ok_or_else(|| SignalKError::InvalidValue {
+            name: name.into(),
+            value,
+        })
+}
+
+impl GroupedMeasurementVisitor for SignalKDeltaVisitor {
+    type Error = SignalKError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        if self.group.is_some() {
+            return Err(SignalKError::UnexpectedTimestamp);
+        }
+        self.timestamp = Some(value);
+        Ok(())
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        let value = json_number(name, value)?;
+        self.update(name, value)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        if self.group.is_some() {
+            return Err(SignalKError::UnexpectedStartOfGroup);
+        }
+        if group.is_empty() {
+            return Err(SignalKError::InvalidPath { name: group.into() });
+        }
+        self.group = Some(group.into());
+        Ok(())
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        if self.group.take().is_none() {
+            return Err(SignalKError::UnexpectedEndOfGroup);
+        }
+        self.is_timestamped_group = false;
+        Ok(())
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        _unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.measurement(name, value)
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.start_group(group)?;
+        self.group_updates.push((timestamp, Vec::new()));
+        self.is_timestamped_group = true;
+        Ok(())
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        let value = match value {
+            Some(value) => json_number(name, value)?,
+            None => Value::Null,
+        };
+        self.update(name, value)
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        let real = json_number(name, real)?;
+        let imaginary = json_number(name, imaginary)?;
+        self.update(name, json!({"re": real, "im": imaginary}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measurement::MeasurementQuality;
+    use assert_matches::assert_matches;
+    use chrono::TimeZone;
+    use jsonschema::JSONSchema;
+
+    /// The SignalK delta schema, reduced to the rules applying to the messages produced here
+    fn signalk_delta_schema() -> JSONSchema {
+        let schema = json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "title": "SignalK delta message",
+            "type": "object",
+            "required": ["updates"],
+            "properties": {
+                "context": {
+                    "type": "string",
+                    "pattern": "^vessels\\.urn:mrn:imo:mmsi:[0-9]{9}$"
+                },
+                "updates": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["values"],
+                        "properties": {
+                            "source": {
+                                "type": "object",
+                                "required": ["label"],
+                                "properties": {"label": {"type": "string"}}
+                            },
+                            "timestamp": {
+                                "type": "string",
+                                "pattern": "^\\d{4}-\\d{2}-\\d{2}T\\d{2}:\\d{2}:\\d{2}(\\.\\d+)?Z$"
+                            },
+                            "values": {
+                                "type": "array",
+                                "items": {
+                                    "type": "object",
+                                    "required": ["path", "value"],
+                                    "properties": {
+                                        "path": {"type": "string", "pattern": "^[^.]+(\\.[^.]+)*$"},
+                                        "value": {}
+                                    },
+                                    "additionalProperties": false
+                                }
+                            }
+                        },
+                        "additionalProperties": false
+                    }
+                }
+            },
+            "additionalProperties": false
+        });
+        JSONSchema::compile(&schema).expect("A valid JSON schema")
+    }
+
+    fn assert_valid(delta: &SignalKDelta) {
+        let delta = serde_json::to_value(delta).unwrap();
+        let schema = signalk_delta_schema();
+        if let Err(errors) = schema.validate(&delta) {
+            let errors: Vec<String> = errors.map(|error| error.to_string()).collect();
+            panic!("Invalid SignalK delta {}: {:?}", delta, errors);
+        };
+    }
+
+    fn value(path: &str, value: Value) -> SignalKValue {
+        SignalKValue {
+            path: path.into(),
+            value,
+        }
+    }
+
+    #[test]
+    fn measurements_are_values_of_a_single_update() -> anyhow::Result<()> {
+        let mut visitor = SignalKDeltaVisitor::new("123456789")?.with_source_label("tedge-agent");
+        visitor.timestamp(
+            FixedOffset::east(2 * 3600)
+                .ymd(2021, 4, 30)
+                .and_hms(17, 3, 14),
+        )?;
+        visitor.measurement_with_unit("temperature", 298.15, "K")?;
+        visitor.start_group("propulsion")?;
+        visitor.measurement("rpm", 30.5)?;
+        visitor.nullable_measurement("oilPressure", None)?;
+        visitor.end_group()?;
+
+        let delta = visitor.into_delta();
+
+        assert_valid(&delta);
+        assert_eq!(
+            delta,
+            SignalKDelta {
+                context: "vessels.urn:mrn:imo:mmsi:123456789".into(),
+                updates: vec![SignalKUpdate {
+                    source: SignalKSource {
+                        label: "tedge-agent".into()
+                    },
+                    timestamp: Some("2021-04-30T15:03:14.000Z".into()),
+                    values: vec![
+                        value("temperature", json!(298.15)),
+                        value("propulsion.rpm", json!(30.5)),
+                        value("propulsion.oilPressure", Value::Null),
+                    ],
+                }],
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn groups_with_a_timestamp_are_updates_of_their_own() -> anyhow::Result<()> {
+        let mut visitor = SignalKDeltaVisitor::new("123456789")?;
+        visitor.measurement("temperature", 298.15)?;
+        visitor.start_group_with_timestamp(
+            "navigation",
+            FixedOffset::west(3600).ymd(2021, 4, 30).and_hms(17, 3, 14),
+        )?;
+        visitor.complex_measurement("position", 60.1, 24.9)?;
+        visitor.end_group()?;
+        visitor.annotated_measurement("depth", 12.5, MeasurementQuality::Uncertain)?;
+
+        let delta = visitor.into_delta();
+
+        assert_valid(&delta);
+        let updates: Vec<(Option<String>, Vec<SignalKValue>)> = delta
+            .updates
+            .into_iter()
+            .map(|update| (update.timestamp, update.values))
+            .collect();
+        assert_eq!(
+            updates,
+            vec![
+                (
+                    None,
+                    vec![
+                        value("temperature", json!(298.15)),
+                        value("depth", json!(12.5)),
+                    ]
+                ),
+                (
+                    Some("2021-04-30T18:03:14.000Z".into()),
+                    vec![value(
+                        "navigation.position",
+                        json!({"re": 60.1, "im": 24.9})
+                    )]
+                ),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn thin_edge_json_is_converted_into_a_delta() -> anyhow::Result<()> {
+        let delta = SignalKDeltaVisitor::convert(
+            r#"{"time":"2021-04-30T17:03:14.123+02:00","environment":{"depth":12.5,"wind":18.2}}"#,
+            "230123456",
+        )?;
+
+        assert_valid(&delta);
+        assert_eq!(
+            serde_json::to_value(&delta)?,
+            json!({
+                "context": "vessels.urn:mrn:imo:mmsi:230123456",
+                "updates": [{
+                    "source": {"label": "thin-edge"},
+                    "timestamp": "2021-04-30T15:03:14.123Z",
+                    "values": [
+                        {"path": "environment.depth", "value": 12.5},
+                        {"path": "environment.wind", "value": 18.2}
+                    ]
+                }]
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn no_measurements_make_no_updates() -> anyhow::Result<()> {
+        let delta = SignalKDeltaVisitor::new("123456789")?.into_delta();
+
+        assert_valid(&delta);
+        assert!(delta.updates.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_input_is_rejected() -> anyhow::Result<()> {
+        assert_matches!(
+            SignalKDeltaVisitor::new("1234"),
+            Err(SignalKError::InvalidMmsi { .. })
+        );
+        assert_matches!(
+            SignalKDeltaVisitor::new("12345678a"),
+            Err(SignalKError::InvalidMmsi { .. })
+        );
+
+        let mut visitor = SignalKDeltaVisitor::new("123456789")?;
+        assert_matches!(
+            visitor.measurement("", 1.0),
+            Err(SignalKError::InvalidPath { .. })
+        );
+        assert_matches!(
+            visitor.measurement("speed", f64::NAN),
+            Err(SignalKError::InvalidValue { .. })
+        );
+        assert_matches!(visitor.end_group(), Err(SignalKError::UnexpectedEndOfGroup));
+        Ok(())
+    }
+}
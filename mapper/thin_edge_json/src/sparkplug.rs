@@ -0,0 +1,151 @@
+/// The namespace of the Sparkplug B topics
+pub const SPARKPLUG_NAMESPACE: &str = "spBv1.0";
+
+/// The characters that are not allowed in Sparkplug ids, being MQTT separators or wildcards
+const RESERVED_CHARS: [char; 3] = ['/', '+', '#'];
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum SparkplugTopicError {
+    #[error("Invalid Sparkplug id: an id must not be empty")]
+    EmptyId,
+
+    #[error("Invalid Sparkplug id: {id:?} must not contain '/', '+' nor '#'")]
+    ReservedCharacter { id: String },
+}
+
+/// The topics on which an edge node publishes measurements following the Sparkplug B specification.
+///
+/// The measurements of a device attached to the edge node are published on
+/// `spBv1.0/<group_id>/DDATA/<edge_node_id>/<device_id>`,
+/// and those of the edge node itself on `spBv1.0/<group_id>/NDATA/<edge_node_id>`.
+///
+/// As the ids are used as topic levels, they must not be empty,
+/// nor contain the MQTT separator `/` or the wildcards `+` and `#`, which Sparkplug forbids.
+/// Such ids are rejected rather than rewritten, as two devices could then share a topic.
+///
+/// ```
+/// use thin_edge_json::sparkplug::{SparkplugTopicError, SparkplugTopicMapper};
+///
+/// # fn main() -> Result<(), SparkplugTopicError> {
+/// let mapper = SparkplugTopicMapper::new("factory", "gateway-1");
+///
+/// assert_eq!(mapper.device_topic("sensor-1")?, "spBv1.0/factory/DDATA/gateway-1/sensor-1");
+/// assert_eq!(mapper.group_topic()?, "spBv1.0/factory/NDATA/gateway-1");
+/// assert!(mapper.device_topic("line/1").is_err());
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparkplugTopicMapper {
+    pub group_id: String,
+    pub edge_node_id: String,
+}
+
+impl SparkplugTopicMapper {
+    pub fn new(group_id: &str, edge_node_id: &str) -> Self {
+        Self {
+            group_id: group_id.to_string(),
+            edge_node_id: edge_node_id.to_string(),
+        }
+    }
+
+    /// The topic of the measurements of a device attached to the edge node
+    pub fn device_topic(&self, device_id: &str) -> Result<String, SparkplugTopicError> {
+        Ok(format!(
+            "{}/{}/DDATA/{}/{}",
+            SPARKPLUG_NAMESPACE,
+            topic_level(&self.group_id)?,
+            topic_level(&self.edge_node_id)?,
+            topic_level(device_id)?
+        ))
+    }
+
+    /// The topic of the measurements of the edge node itself
+    pub fn group_topic(&self) -> Result<String, SparkplugTopicError> {
+        Ok(format!(
+            "{}/{}/NDATA/{}",
+            SPARKPLUG_NAMESPACE,
+            topic_level(&self.group_id)?,
+            topic_level(&self.edge_node_id)?
+        ))
+    }
+}
+
+fn topic_level(id: &str) -> Result<&str, SparkplugTopicError> {
+    if id.is_empty() {
+        Err(SparkplugTopicError::EmptyId)
+    } else if id.contains(&RESERVED_CHARS[..]) {
+        Err(SparkplugTopicError::ReservedCharacter { id: id.to_string() })
+    } else {
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topics_are_built_from_the_ids() -> Result<(), SparkplugTopicError> {
+        let mapper = SparkplugTopicMapper::new("factory", "gateway-1");
+
+        assert_eq!(
+            mapper.device_topic("sensor-1")?,
+            "spBv1.0/factory/DDATA/gateway-1/sensor-1"
+        );
+        assert_eq!(mapper.group_topic()?, "spBv1.0/factory/NDATA/gateway-1");
+        Ok(())
+    }
+
+    #[test]
+    fn device_ids_with_reserved_characters_are_rejected() {
+        let mapper = SparkplugTopicMapper::new("factory", "gateway-1");
+
+        for device_id in ["line/1/sensor", "sensor+", "sensor#", "/"].iter() {
+            assert_eq!(
+                mapper.device_topic(device_id),
+                Err(SparkplugTopicError::ReservedCharacter {
+                    id: device_id.to_string()
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn empty_ids_are_rejected() {
+        let mapper = SparkplugTopicMapper::new("factory", "gateway-1");
+        assert_eq!(mapper.device_topic(""), Err(SparkplugTopicError::EmptyId));
+
+        let mapper = SparkplugTopicMapper::new("", "gateway-1");
+        assert_eq!(mapper.group_topic(), Err(SparkplugTopicError::EmptyId));
+    }
+
+    #[test]
+    fn other_characters_are_kept_in_device_ids() -> Result<(), SparkplugTopicError> {
+        let mapper = SparkplugTopicMapper::new("factory", "gateway-1");
+
+        assert_eq!(
+            mapper.device_topic("capteur température.1 $ä")?,
+            "spBv1.0/factory/DDATA/gateway-1/capteur température.1 $ä"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn group_and_node_ids_with_reserved_characters_are_rejected() {
+        let mapper = SparkplugTopicMapper::new("plant/A", "gateway-1");
+        assert_eq!(
+            mapper.device_topic("sensor-1"),
+            Err(SparkplugTopicError::ReservedCharacter {
+                id: "plant/A".to_string()
+            })
+        );
+
+        let mapper = SparkplugTopicMapper::new("factory", "gateway#1");
+        assert_eq!(
+            mapper.group_topic(),
+            Err(SparkplugTopicError::ReservedCharacter {
+                id: "gateway#1".to_string()
+            })
+        );
+    }
+}
@@ -0,0 +1,382 @@
+use crate::context::MeasurementContext;
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use std::collections::{HashMap, VecDeque};
+
+/// The group under which the statistics are injected
+pub const STATISTICS_GROUP: &str = "_stats";
+
+/// Statistics over the last `window_size` samples of a measurement.
+///
+/// The mean and the variance are updated as samples enter and leave the window
+/// using Welford's algorithm, which is numerically stable.
+#[derive(Debug, Clone)]
+pub struct RollingStatistics {
+    window_size: usize,
+    samples: VecDeque<f64>,
+    mean: f64,
+    m2: f64,
+}
+
+impl RollingStatistics {
+    /// Create statistics over a window of `window_size` samples, at least one.
+    pub fn new(window_size: usize) -> Self {
+        let window_size = window_size.max(1);
+        Self {
+            window_size,
+            samples: VecDeque::with_capacity(window_size),
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+
+    pub fn push(&mut self, value: f64) {
+        if self.samples.len() == self.window_size {
+            if let Some(oldest) = self.samples.pop_front() {
+                self.remove(oldest);
+            }
+        }
+        self.samples.push_back(value);
+        self.add(value);
+    }
+
+    fn add(&mut self, value: f64) {
+        let n = self.samples.len() as f64;
+        let delta = value - self.mean;
+        self.mean += delta / n;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    fn remove(&mut self, value: f64) {
+        let n = self.samples.len() as f64;
+        if n == 0.0 {
+            self.mean = 0.0;
+            self.m2 = 0.0;
+        } else {
+            let delta = value - self.mean;
+            self.mean -= delta / n;
+            self.m2 -= delta * (value - self.mean);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn min(&self) -> f64 {
+        self.samples.iter().cloned().fold(f64::INFINITY, f64::min)
+    }
+
+    pub fn max(&self) -> f64 {
+        self.samples
+            .iter()
+            .cloned()
+            .fold(f64::NEG_INFINITY, f64::max)
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// The population variance of the samples in the window
+    pub fn variance(&self) -> f64 {
+        if self.samples.is_empty() {
+            0.0
+        } else {
+            // Rounding errors might make m2 slightly negative when all the samples are equal
+            (self.m2 / self.samples.len() as f64).max(0.0)
+        }
+    }
+
+    /// The population standard deviation of the samples in the window
+    pub fn stddev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+}
+
+/// A visitor that computes running statistics over the measurements it forwards.
+///
+/// For each measurement, the visitor keeps the last `window_size` values,
+/// these values being collected along all the measurement series forwarded by the visitor.
+/// On `emit_statistics()`, the visitor injects into the current series a `_stats` group
+/// with the `<name>_min`, `<name>_max`, `<name>_mean` and `<name>_stddev` of all the measurements
+/// updated since the previous call, where `<name>` is `<group>.<measurement>` for grouped measurements.
+///
+/// ```
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+/// use thin_edge_json::statistics::StatisticsInjectingVisitor;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let mut visitor = StatisticsInjectingVisitor::new(10, ThinEdgeJsonSerializer::new());
+///
+/// visitor.measurement("temperature", 25.0)?;
+/// visitor.emit_statistics()?;
+///
+/// // The visitor is reused with a new serializer for the next series of measurements
+/// let first = std::mem::replace(visitor.inner_mut(), ThinEdgeJsonSerializer::new());
+/// assert_eq!(
+///     first.bytes()?,
+///     br#"{"temperature":25.0,"_stats":{"temperature_min":25.0,"temperature_max":25.0,"temperature_mean":25.0,"temperature_stddev":0.0}}"#
+/// );
+/// # Ok(()) }
+/// ```
+pub struct StatisticsInjectingVisitor<V> {
+    window_size: usize,
+    windows: HashMap<String, RollingStatistics>,
+    updated: Vec<String>,
+    group: Option<String>,
+    inner: V,
+}
+
+impl<V> StatisticsInjectingVisitor<V> {
+    pub fn new(window_size: usize, inner: V) -> Self {
+        Self {
+            window_size,
+            windows: HashMap::new(),
+            updated: Vec::new(),
+            group: None,
+            inner,
+        }
+    }
+
+    pub fn statistics(&self, group: Option<&str>, name: &str) -> Option<&RollingStatistics> {
+        self.windows.get(&statistics_key(group, name))
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut V {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> V {
+        self.inner
+    }
+
+    fn record(&mut self, name: &str, value: f64) {
+        let key = statistics_key(self.group.as_deref(), name);
+        let window_size = self.window_size;
+        self.windows
+            .entry(key.clone())
+            .or_insert_with(|| RollingStatistics::new(window_size))
+            .push(value);
+        if !self.updated.contains(&key) {
+            self.updated.push(key);
+        }
+    }
+}
+
+impl<V> StatisticsInjectingVisitor<V>
+where
+    V: GroupedMeasurementVisitor,
+{
+    /// Inject the `_stats` group for all the measurements updated since the previous call.
+    ///
+    /// Nothing is injected if no measurements have been updated.
+    pub fn emit_statistics(&mut self) -> Result<(), V::Error> {
+        if self.updated.is_empty() {
+            return Ok(());
+        }
+
+        self.inner.start_group(STATISTICS_GROUP)?;
+        for key in self.updated.drain(..) {
+            if let Some(stats) = self.windows.get(&key) {
+                self.inner
+                    .measurement(&format!("{}_min", key), stats.min())?;
+                self.inner
+                    .measurement(&format!("{}_max", key), stats.max())?;
+                self.inner
+                    .measurement(&format!("{}_mean", key), stats.mean())?;
+                self.inner
+                    .measurement(&format!("{}_stddev", key), stats.stddev())?;
+            }
+        }
+        self.inner.end_group()
+    }
+}
+
+impl<V> GroupedMeasurementVisitor for StatisticsInjectingVisitor<V>
+where
+    V: GroupedMeasurementVisitor,
+{
+    type Error = V::Error;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.inner.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.inner.measurement(name, value)?;
+        self.record(name, value);
+        Ok(())
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.inner.start_group(group)?;
+        self.group = Some(group.to_string());
+        Ok(())
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.inner.start_group_with_timestamp(group, timestamp)?;
+        self.group = Some(group.to_string());
+        Ok(())
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        self.inner.nullable_measurement(name, value)?;
+        if let Some(value) = value {
+            self.record(name, value);
+        }
+        Ok(())
+    }
+
+    /// The complex measurements are forwarded with no statistics
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.inner.complex_measurement(name, real, imaginary)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.inner.annotated_measurement(name, value, quality)?;
+        self.record(name, value);
+        Ok(())
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.inner.end_group()?;
+        self.group = None;
+        Ok(())
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.inner.measurement_with_unit(name, value, unit)?;
+        self.record(name, value);
+        Ok(())
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.inner.set_context(context)
+    }
+}
+
+fn statistics_key(group: Option<&str>, name: &str) -> String {
+    match group {
+        Some(group) => format!("{}.{}", group, name),
+        None => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::ThinEdgeJsonSerializer;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "expected {} but got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn stddev_of_a_known_data_set() {
+        let mut stats = RollingStatistics::new(8);
+        for value in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0].iter() {
+            stats.push(*value);
+        }
+
+        assert_eq!(stats.len(), 8);
+        assert_eq!(stats.min(), 2.0);
+        assert_eq!(stats.max(), 9.0);
+        assert_close(stats.mean(), 5.0);
+        assert_close(stats.stddev(), 2.0);
+    }
+
+    #[test]
+    fn stddev_is_stable_with_large_offsets() {
+        let mut stats = RollingStatistics::new(4);
+        for value in [4.0, 7.0, 13.0, 16.0].iter() {
+            stats.push(1e9 + *value);
+        }
+
+        assert_close(stats.mean(), 1e9 + 10.0);
+        assert_close(stats.variance(), 22.5);
+    }
+
+    #[test]
+    fn window_forgets_old_values() {
+        let mut stats = RollingStatistics::new(3);
+        for value in [100.0, -50.0, 1.0, 2.0, 3.0].iter() {
+            stats.push(*value);
+        }
+
+        assert_eq!(stats.len(), 3);
+        assert_eq!(stats.min(), 1.0);
+        assert_eq!(stats.max(), 3.0);
+        assert_close(stats.mean(), 2.0);
+        assert_close(stats.variance(), 2.0 / 3.0);
+    }
+
+    #[test]
+    fn inject_statistics_in_a_stats_group() -> anyhow::Result<()> {
+        let mut visitor = StatisticsInjectingVisitor::new(2, ThinEdgeJsonSerializer::new());
+
+        visitor.measurement("temperature", 20.0)?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 100.0)?;
+        visitor.end_group()?;
+        visitor.emit_statistics()?;
+        let _ = std::mem::replace(visitor.inner_mut(), ThinEdgeJsonSerializer::new());
+
+        visitor.measurement("temperature", 30.0)?;
+        visitor.emit_statistics()?;
+        let second = std::mem::replace(visitor.inner_mut(), ThinEdgeJsonSerializer::new());
+
+        let expected_output = r#"{"temperature":30.0,"_stats":{"temperature_min":20.0,"temperature_max":30.0,"temperature_mean":25.0,"temperature_stddev":5.0}}"#;
+        assert_eq!(second.bytes()?, expected_output.as_bytes());
+
+        let alti = visitor.statistics(Some("location"), "alti").unwrap();
+        assert_eq!(alti.len(), 1);
+        assert!(visitor.statistics(None, "alti").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn no_stats_group_without_measurements() -> anyhow::Result<()> {
+        let mut visitor = StatisticsInjectingVisitor::new(2, ThinEdgeJsonSerializer::new());
+
+        visitor.emit_statistics()?;
+
+        assert_eq!(visitor.into_inner().into_string()?, "{}");
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+use crate::context::MeasurementContext;
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+
+#[derive(thiserror::Error, Debug)]
+pub enum TeeError<E1: std::error::Error, E2: std::error::Error> {
+    #[error("First visitor: {0}")]
+    First(E1),
+
+    #[error("Second visitor: {0}")]
+    Second(E2),
+}
+
+/// A visitor that forwards all the calls it receives to two visitors.
+///
+/// Each call is forwarded to the first visitor then to the second one,
+/// unless the first visitor fails.
+///
+/// ```
+/// use thin_edge_json::buffer::MeasurementBuffer;
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+/// use thin_edge_json::tee::TeeVisitor;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let mut visitor = TeeVisitor::new(ThinEdgeJsonSerializer::new(), MeasurementBuffer::new());
+///
+/// visitor.measurement("temperature", 25.5)?;
+///
+/// let (mut serializer, buffer) = visitor.into_inner();
+/// assert_eq!(serializer.into_string()?, r#"{"temperature":25.5}"#);
+/// assert_eq!(buffer.len(), 1);
+/// # Ok(()) }
+/// ```
+pub struct TeeVisitor<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> TeeVisitor<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+
+    pub fn first(&self) -> &A {
+        &self.first
+    }
+
+    pub fn first_mut(&mut self) -> &mut A {
+        &mut self.first
+    }
+
+    pub fn second(&self) -> &B {
+        &self.second
+    }
+
+    pub fn second_mut(&mut self) -> &mut B {
+        &mut self.second
+    }
+
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<A, B> TeeVisitor<A, B>
+where
+    A: GroupedMeasurementVisitor,
+    B: GroupedMeasurementVisitor,
+{
+    fn forward<F, G>(&mut self, first: F, second: G) -> Result<(), TeeError<A::Error, B::Error>>
+    where
+        F: FnOnce(&mut A) -> Result<(), A::Error>,
+        G: FnOnce(&mut B) -> Result<(), B::Error>,
+    {
+        first(&mut self.first).map_err(TeeError::First)?;
+        second(&mut self.second).map_err(TeeError::Second)
+    }
+}
+
+impl<A, B> GroupedMeasurementVisitor for TeeVisitor<A, B>
+where
+    A: GroupedMeasurementVisitor,
+    B: GroupedMeasurementVisitor,
+{
+    type Error = TeeError<A::Error, B::Error>;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.forward(|a| a.timestamp(value), |b| b.timestamp(value))
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.forward(
+            |a| a.measurement(name, value),
+            |b| b.measurement(name, value),
+        )
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.forward(|a| a.start_group(group), |b| b.start_group(group))
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.forward(|a| a.end_group(), |b| b.end_group())
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.forward(
+            |a| a.measurement_with_unit(name, value, unit),
+            |b| b.measurement_with_unit(name, value, unit),
+        )
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.forward(
+            |a| a.start_group_with_timestamp(group, timestamp),
+            |b| b.start_group_with_timestamp(group, timestamp),
+        )
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        self.forward(
+            |a| a.nullable_measurement(name, value),
+            |b| b.nullable_measurement(name, value),
+        )
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.forward(
+            |a| a.complex_measurement(name, real, imaginary),
+            |b| b.complex_measurement(name, real, imaginary),
+        )
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.forward(
+            |a| a.annotated_measurement(name, value, quality),
+            |b| b.annotated_measurement(name, value, quality),
+        )
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        let first_context = context.clone();
+        self.forward(|a| a.set_context(first_context), |b| b.set_context(context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::MeasurementBuffer;
+    use crate::serialize::ThinEdgeJsonSerializer;
+    use crate::trace::VisitorCall;
+
+    #[test]
+    fn all_the_calls_are_forwarded_to_both_visitors() -> anyhow::Result<()> {
+        let mut visitor = TeeVisitor::new(ThinEdgeJsonSerializer::new(), MeasurementBuffer::new());
+
+        visitor.measurement("temperature", 25.5)?;
+        visitor.start_group("location")?;
+        visitor.measurement_with_unit("alti", 2100.4, "m")?;
+        visitor.end_group()?;
+
+        let (mut serializer, buffer) = visitor.into_inner();
+        assert_eq!(
+            serializer.into_string()?,
+            r#"{"temperature":25.5,"location":{"alti":{"value":2100.4,"unit":"m"}}}"#
+        );
+        assert_eq!(buffer.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn the_second_visitor_is_not_called_when_the_first_one_fails() {
+        let mut visitor = TeeVisitor::new(ThinEdgeJsonSerializer::new(), MeasurementBuffer::new());
+
+        assert!(matches!(visitor.end_group(), Err(TeeError::First(_))));
+        assert!(visitor.second().is_empty());
+    }
+
+    #[test]
+    fn errors_of_the_second_visitor_are_reported() {
+        let mut visitor = TeeVisitor::new(MeasurementBuffer::new(), ThinEdgeJsonSerializer::new());
+
+        assert!(matches!(visitor.end_group(), Err(TeeError::Second(_))));
+        assert_eq!(visitor.first().events(), &[VisitorCall::EndGroup]);
+    }
+}
@@ -0,0 +1,375 @@
+use crate::context::MeasurementContext;
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use clock::{Clock, Timestamp, WallClock};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// The latest value received for a measurement, kept with the visitor call that received it
+#[derive(Debug, Clone, PartialEq)]
+enum LatestValue {
+    Value(f64),
+    WithUnit(f64, String),
+    Nullable(Option<f64>),
+    Complex(f64, f64),
+    Annotated(f64, MeasurementQuality),
+}
+
+/// A visitor that forwards the measurements to the inner visitor at most once per interval.
+///
+/// Unlike the `RateLimitingVisitor`, which forwards the first value of each interval
+/// and drops the following ones, this visitor keeps the most recent value of each measurement
+/// and forwards these latest values in a single flush.
+///
+/// A window opens with the first measurement received after a flush.
+/// The window is flushed by the first measurement received once `min_interval` has elapsed,
+/// that measurement opening the next window. `flush()` can also be called to force a flush.
+/// On a flush, the last timestamp received within the window, if any, is forwarded first,
+/// followed by the latest value of each measurement, the grouped ones within their group.
+///
+/// ```
+/// use std::time::Duration;
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+/// use thin_edge_json::throttle::ThrottlingVisitor;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let mut visitor =
+///     ThrottlingVisitor::new(Duration::from_secs(1), ThinEdgeJsonSerializer::new());
+///
+/// visitor.measurement("temperature", 25.0)?;
+/// visitor.measurement("temperature", 26.0)?;
+/// visitor.flush()?;
+///
+/// assert_eq!(
+///     visitor.into_inner().into_string()?,
+///     r#"{"temperature":26.0}"#
+/// );
+/// # Ok(()) }
+/// ```
+pub struct ThrottlingVisitor<V> {
+    min_interval: Duration,
+    clock: Box<dyn Clock>,
+    window_start: Option<Timestamp>,
+    latest: BTreeMap<(Option<String>, String), LatestValue>,
+    timestamp: Option<DateTime<FixedOffset>>,
+    context: Option<MeasurementContext>,
+    group: Option<String>,
+    inner: V,
+}
+
+impl<V> ThrottlingVisitor<V> {
+    pub fn new(min_interval: Duration, inner: V) -> Self {
+        Self::with_clock(min_interval, Box::new(WallClock), inner)
+    }
+
+    pub fn with_clock(min_interval: Duration, clock: Box<dyn Clock>, inner: V) -> Self {
+        Self {
+            min_interval,
+            clock,
+            window_start: None,
+            latest: BTreeMap::new(),
+            timestamp: None,
+            context: None,
+            group: None,
+            inner,
+        }
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut V {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> V {
+        self.inner
+    }
+
+    /// Tell if the current window has lasted for at least `min_interval`
+    fn window_elapsed(&self, now: Timestamp) -> bool {
+        let min_interval = self.min_interval;
+        self.window_start.map_or(false, |start| {
+            (now - start)
+                .to_std()
+                .map_or(false, |elapsed| elapsed >= min_interval)
+        })
+    }
+}
+
+impl<V> ThrottlingVisitor<V>
+where
+    V: GroupedMeasurementVisitor,
+{
+    /// Forward the latest values received since the previous flush, then reset them.
+    ///
+    /// Nothing is forwarded if no measurements have been received.
+    pub fn flush(&mut self) -> Result<(), V::Error> {
+        self.window_start = None;
+        let latest = std::mem::take(&mut self.latest);
+        if latest.is_empty() {
+            return Ok(());
+        }
+        if let Some(context) = self.context.take() {
+            self.inner.set_context(context)?;
+        }
+        if let Some(timestamp) = self.timestamp.take() {
+            self.inner.timestamp(timestamp)?;
+        }
+
+        let mut current_group: Option<String> = None;
+        for ((group, name), value) in latest {
+            if group != current_group {
+                if current_group.is_some() {
+                    self.inner.end_group()?;
+                }
+                if let Some(group) = group.as_deref() {
+                    self.inner.start_group(group)?;
+                }
+                current_group = group;
+            }
+            match value {
+                LatestValue::Value(value) => self.inner.measurement(&name, value)?,
+                LatestValue::WithUnit(value, unit) => {
+                    self.inner.measurement_with_unit(&name, value, &unit)?
+                }
+                LatestValue::Nullable(value) => self.inner.nullable_measurement(&name, value)?,
+                LatestValue::Complex(real, imaginary) => {
+                    self.inner.complex_measurement(&name, real, imaginary)?
+                }
+                LatestValue::Annotated(value, quality) => {
+                    self.inner.annotated_measurement(&name, value, quality)?
+                }
+            }
+        }
+        if current_group.is_some() {
+            self.inner.end_group()?;
+        }
+        Ok(())
+    }
+
+    /// Keep the value as the latest one of the measurement,
+    /// after having flushed the previous window if elapsed.
+    fn record(&mut self, name: &str, value: LatestValue) -> Result<(), V::Error> {
+        let now = self.clock.now();
+        if self.window_elapsed(now) {
+            self.flush()?;
+        }
+        if self.window_start.is_none() {
+            self.window_start = Some(now);
+        }
+        self.latest
+            .insert((self.group.clone(), name.to_string()), value);
+        Ok(())
+    }
+}
+
+impl<V> GroupedMeasurementVisitor for ThrottlingVisitor<V>
+where
+    V: GroupedMeasurementVisitor,
+{
+    type Error = V::Error;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.timestamp = Some(value);
+        Ok(())
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.record(name, LatestValue::Value(value))
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.group = Some(group.to_string());
+        Ok(())
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.group = None;
+        Ok(())
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.record(name, LatestValue::WithUnit(value, unit.to_string()))
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.timestamp = Some(timestamp);
+        self.start_group(group)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        self.record(name, LatestValue::Nullable(value))
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.record(name, LatestValue::Complex(real, imaginary))
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.record(name, LatestValue::Annotated(value, quality))
+    }
+
+    /// The latest context is forwarded along the latest values
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.context = Some(context);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::ThinEdgeJsonSerializer;
+    use chrono::TimeZone;
+    use std::sync::{Arc, Mutex};
+
+    /// A clock that only moves forward when told so
+    #[derive(Clone)]
+    struct TestClock {
+        now: Arc<Mutex<Timestamp>>,
+    }
+
+    impl TestClock {
+        fn new() -> Self {
+            let start = FixedOffset::east(0).ymd(2021, 4, 30).and_hms(17, 0, 0);
+            Self {
+                now: Arc::new(Mutex::new(start)),
+            }
+        }
+
+        fn advance(&self, duration: Duration) {
+            let mut now = self.now.lock().unwrap();
+            *now = *now + chrono::Duration::from_std(duration).unwrap();
+        }
+    }
+
+    impl Clock for TestClock {
+        fn now(&self) -> Timestamp {
+            *self.now.lock().unwrap()
+        }
+    }
+
+    fn next_series(
+        visitor: &mut ThrottlingVisitor<ThinEdgeJsonSerializer>,
+    ) -> anyhow::Result<String> {
+        let mut serializer = std::mem::replace(visitor.inner_mut(), ThinEdgeJsonSerializer::new());
+        Ok(serializer.into_string()?)
+    }
+
+    fn throttling_visitor(clock: &TestClock) -> ThrottlingVisitor<ThinEdgeJsonSerializer> {
+        ThrottlingVisitor::with_clock(
+            Duration::from_secs(1),
+            Box::new(clock.clone()),
+            ThinEdgeJsonSerializer::new(),
+        )
+    }
+
+    #[test]
+    fn the_last_value_of_each_window_is_forwarded() -> anyhow::Result<()> {
+        let clock = TestClock::new();
+        let mut visitor = throttling_visitor(&clock);
+
+        for value in [20.0, 21.0, 22.0].iter() {
+            visitor.measurement("temperature", *value)?;
+            clock.advance(Duration::from_millis(300));
+        }
+        assert_eq!(next_series(&mut visitor)?, "{}");
+
+        // The window is flushed by the first measurement received once elapsed
+        clock.advance(Duration::from_millis(100));
+        visitor.measurement("temperature", 23.0)?;
+        assert_eq!(next_series(&mut visitor)?, r#"{"temperature":22.0}"#);
+
+        clock.advance(Duration::from_millis(500));
+        visitor.measurement("temperature", 24.0)?;
+        assert_eq!(next_series(&mut visitor)?, "{}");
+
+        clock.advance(Duration::from_millis(500));
+        visitor.measurement("temperature", 25.0)?;
+        assert_eq!(next_series(&mut visitor)?, r#"{"temperature":24.0}"#);
+
+        visitor.flush()?;
+        assert_eq!(next_series(&mut visitor)?, r#"{"temperature":25.0}"#);
+        Ok(())
+    }
+
+    #[test]
+    fn the_latest_value_of_each_measurement_is_kept() -> anyhow::Result<()> {
+        let clock = TestClock::new();
+        let mut visitor = throttling_visitor(&clock);
+        let timestamp = DateTime::parse_from_rfc3339("2021-04-30T17:03:14+02:00")?;
+
+        visitor.measurement_with_unit("temperature", 20.0, "°C")?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.nullable_measurement("longi", None)?;
+        visitor.end_group()?;
+        visitor.timestamp(timestamp)?;
+        visitor.measurement_with_unit("temperature", 21.0, "°C")?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.5)?;
+        visitor.end_group()?;
+        visitor.flush()?;
+
+        assert_eq!(
+            next_series(&mut visitor)?,
+            r#"{"time":"2021-04-30T17:03:14+02:00","temperature":{"value":21.0,"unit":"°C"},"location":{"alti":2100.5,"longi":null}}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn nothing_is_forwarded_when_nothing_has_been_received() -> anyhow::Result<()> {
+        let clock = TestClock::new();
+        let mut visitor = throttling_visitor(&clock);
+
+        visitor.measurement("temperature", 20.0)?;
+        visitor.flush()?;
+        assert_eq!(next_series(&mut visitor)?, r#"{"temperature":20.0}"#);
+
+        visitor.flush()?;
+        assert_eq!(next_series(&mut visitor)?, "{}");
+        Ok(())
+    }
+
+    #[test]
+    fn the_latest_context_is_forwarded_with_the_latest_values() -> anyhow::Result<()> {
+        let clock = TestClock::new();
+        let mut visitor = throttling_visitor(&clock);
+
+        visitor.set_context(MeasurementContext::new([0x01; 16], [0x01; 8]))?;
+        visitor.measurement("temperature", 20.0)?;
+        visitor.set_context(MeasurementContext::new([0x4b; 16], [0xf0; 8]))?;
+        visitor.measurement("temperature", 21.0)?;
+        visitor.flush()?;
+
+        assert_eq!(
+            next_series(&mut visitor)?,
+            r#"{"_traceId":"4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b","_spanId":"f0f0f0f0f0f0f0f0","temperature":21.0}"#
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,360 @@
+use crate::context::MeasurementContext;
+use crate::filter::PendingGroup;
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use chrono::offset::FixedOffset;
+use chrono::{DateTime, Datelike, NaiveTime, Weekday};
+use clock::{Clock, Timestamp, WallClock};
+
+/// A daily period of time, active on some days of the week.
+///
+/// The start is included and the end excluded.
+/// A window whose end is before its start spans midnight, as a night shift from 22:00 to 06:00,
+/// and is active on a day if it starts on that day, even if it ends the next day.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub days: Vec<Weekday>,
+}
+
+impl TimeWindow {
+    pub fn new(start: NaiveTime, end: NaiveTime, days: Vec<Weekday>) -> Self {
+        Self { start, end, days }
+    }
+
+    /// Tell if the window is active at the given time, as read in the time zone of the timestamp.
+    pub fn contains(&self, time: Timestamp) -> bool {
+        let day = time.weekday();
+        let time = time.time();
+        if self.start <= self.end {
+            self.start <= time && time < self.end && self.days.contains(&day)
+        } else if time >= self.start {
+            self.days.contains(&day)
+        } else {
+            time < self.end && self.days.contains(&day.pred())
+        }
+    }
+}
+
+/// A visitor that only forwards the measurements timed within one of the given windows.
+///
+/// The time of a measurement is given by the timestamp of its group, if any,
+/// else by the latest timestamp received by the visitor,
+/// else by the clock when the measurement is received.
+/// The windows are checked against the time of day read in the time zone of that time.
+///
+/// A measurement is forwarded if any window is active, windows possibly overlapping.
+/// A group is only forwarded if at least one of its measurements is.
+///
+/// ```
+/// use chrono::{FixedOffset, NaiveTime, TimeZone, Weekday};
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+/// use thin_edge_json::time_window::{TimeWindow, TimeWindowVisitor};
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let office_hours = TimeWindow::new(
+///     NaiveTime::from_hms(8, 0, 0),
+///     NaiveTime::from_hms(18, 0, 0),
+///     vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri],
+/// );
+/// let mut visitor = TimeWindowVisitor::new(vec![office_hours], ThinEdgeJsonSerializer::new());
+///
+/// // A Saturday
+/// visitor.timestamp(FixedOffset::east(0).ymd(2021, 5, 1).and_hms(10, 0, 0))?;
+/// visitor.measurement("temperature", 25.0)?;
+///
+/// assert_eq!(
+///     visitor.into_inner().into_string()?,
+///     r#"{"time":"2021-05-01T10:00:00+00:00"}"#
+/// );
+/// # Ok(()) }
+/// ```
+pub struct TimeWindowVisitor<V> {
+    windows: Vec<TimeWindow>,
+    clock: Box<dyn Clock>,
+    timestamp: Option<Timestamp>,
+    group: PendingGroup,
+    inner: V,
+}
+
+impl<V> TimeWindowVisitor<V> {
+    pub fn new(windows: Vec<TimeWindow>, inner: V) -> Self {
+        Self::with_clock(windows, Box::new(WallClock), inner)
+    }
+
+    pub fn with_clock(windows: Vec<TimeWindow>, clock: Box<dyn Clock>, inner: V) -> Self {
+        Self {
+            windows,
+            clock,
+            timestamp: None,
+            group: PendingGroup::default(),
+            inner,
+        }
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut V {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> V {
+        self.inner
+    }
+
+    fn accept(&self) -> bool {
+        let time = match self.group.timestamp().or(self.timestamp) {
+            Some(time) => time,
+            None => self.clock.now(),
+        };
+        self.windows.iter().any(|window| window.contains(time))
+    }
+}
+
+impl<V> GroupedMeasurementVisitor for TimeWindowVisitor<V>
+where
+    V: GroupedMeasurementVisitor,
+{
+    type Error = V::Error;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.timestamp = Some(value);
+        self.inner.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        if self.accept() {
+            self.group.forward_start(&mut self.inner)?;
+            self.inner.measurement(name, value)?;
+        }
+        Ok(())
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.group.start(group, &mut self.inner)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.group.end(&mut self.inner)
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        if self.accept() {
+            self.group.forward_start(&mut self.inner)?;
+            self.inner.measurement_with_unit(name, value, unit)?;
+        }
+        Ok(())
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.group
+            .start_with_timestamp(group, timestamp, &mut self.inner)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        if self.accept() {
+            self.group.forward_start(&mut self.inner)?;
+            self.inner.nullable_measurement(name, value)?;
+        }
+        Ok(())
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        if self.accept() {
+            self.group.forward_start(&mut self.inner)?;
+            self.inner.complex_measurement(name, real, imaginary)?;
+        }
+        Ok(())
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        if self.accept() {
+            self.group.forward_start(&mut self.inner)?;
+            self.inner.annotated_measurement(name, value, quality)?;
+        }
+        Ok(())
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.inner.set_context(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::ThinEdgeJsonSerializer;
+    use chrono::TimeZone;
+    use clock::MockClock;
+
+    /// 2021-05-03 is a Monday
+    fn monday_at(hour: u32, min: u32, sec: u32) -> Timestamp {
+        FixedOffset::east(0).ymd(2021, 5, 3).and_hms(hour, min, sec)
+    }
+
+    fn working_days() -> Vec<Weekday> {
+        vec![
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+        ]
+    }
+
+    fn day_shift() -> TimeWindow {
+        TimeWindow::new(
+            NaiveTime::from_hms(8, 0, 0),
+            NaiveTime::from_hms(16, 0, 0),
+            working_days(),
+        )
+    }
+
+    fn night_shift() -> TimeWindow {
+        TimeWindow::new(
+            NaiveTime::from_hms(22, 0, 0),
+            NaiveTime::from_hms(6, 0, 0),
+            vec![Weekday::Mon],
+        )
+    }
+
+    fn clock_at(now: Timestamp) -> Box<dyn Clock> {
+        let mut clock = MockClock::new();
+        clock.expect_now().returning(move || now);
+        Box::new(clock)
+    }
+
+    #[test]
+    fn the_start_of_a_window_is_included_and_the_end_excluded() {
+        let window = day_shift();
+
+        assert!(!window.contains(monday_at(7, 59, 59)));
+        assert!(window.contains(monday_at(8, 0, 0)));
+        assert!(window.contains(monday_at(15, 59, 59)));
+        assert!(!window.contains(monday_at(16, 0, 0)));
+    }
+
+    #[test]
+    fn a_window_is_only_active_on_its_days() {
+        let window = day_shift();
+        let saturday = FixedOffset::east(0).ymd(2021, 5, 1).and_hms(10, 0, 0);
+
+        assert!(window.contains(monday_at(10, 0, 0)));
+        assert!(!window.contains(saturday));
+    }
+
+    #[test]
+    fn a_window_can_span_midnight() {
+        let window = night_shift();
+        let tuesday = |hour| FixedOffset::east(0).ymd(2021, 5, 4).and_hms(hour, 0, 0);
+        let sunday = |hour| FixedOffset::east(0).ymd(2021, 5, 2).and_hms(hour, 0, 0);
+
+        assert!(window.contains(monday_at(22, 0, 0)));
+        assert!(window.contains(tuesday(5)));
+        assert!(!window.contains(tuesday(6)));
+        assert!(!window.contains(tuesday(22)));
+        assert!(!window.contains(monday_at(5, 0, 0)));
+        assert!(!window.contains(sunday(23)));
+    }
+
+    #[test]
+    fn the_time_of_day_is_read_in_the_time_zone_of_the_timestamp() {
+        let window = day_shift();
+        // 07:30 UTC is 09:30 in UTC+2
+        let time = FixedOffset::east(2 * 3600)
+            .ymd(2021, 5, 3)
+            .and_hms(9, 30, 0);
+
+        assert!(window.contains(time));
+        assert!(!window.contains(monday_at(7, 30, 0)));
+    }
+
+    #[test]
+    fn measurements_are_filtered_using_their_timestamp() -> anyhow::Result<()> {
+        let mut visitor = TimeWindowVisitor::new(
+            vec![day_shift(), night_shift()],
+            ThinEdgeJsonSerializer::new(),
+        );
+
+        visitor.timestamp(monday_at(16, 0, 0))?;
+        visitor.measurement("temperature", 25.0)?;
+        visitor.start_group_with_timestamp("engine", monday_at(15, 59, 59))?;
+        visitor.measurement("speed", 3000.0)?;
+        visitor.end_group()?;
+        visitor.start_group_with_timestamp("location", monday_at(21, 0, 0))?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.end_group()?;
+
+        assert_eq!(
+            visitor.into_inner().into_string()?,
+            r#"{"time":"2021-05-03T16:00:00+00:00","engine":{"time":"2021-05-03T15:59:59+00:00","speed":3000.0}}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn measurements_with_no_timestamp_are_timed_by_the_clock() -> anyhow::Result<()> {
+        let mut visitor = TimeWindowVisitor::with_clock(
+            vec![day_shift()],
+            clock_at(monday_at(8, 0, 0)),
+            ThinEdgeJsonSerializer::new(),
+        );
+        visitor.measurement("temperature", 25.0)?;
+        assert_eq!(
+            visitor.into_inner().into_string()?,
+            r#"{"temperature":25.0}"#
+        );
+
+        let mut visitor = TimeWindowVisitor::with_clock(
+            vec![day_shift()],
+            clock_at(monday_at(7, 59, 59)),
+            ThinEdgeJsonSerializer::new(),
+        );
+        visitor.measurement("temperature", 25.0)?;
+        assert_eq!(visitor.into_inner().into_string()?, "{}");
+        Ok(())
+    }
+
+    #[test]
+    fn overlapping_windows_are_combined() -> anyhow::Result<()> {
+        let morning = TimeWindow::new(
+            NaiveTime::from_hms(6, 0, 0),
+            NaiveTime::from_hms(9, 0, 0),
+            vec![Weekday::Mon],
+        );
+        let mut visitor = TimeWindowVisitor::with_clock(
+            vec![morning, day_shift()],
+            clock_at(monday_at(12, 0, 0)),
+            ThinEdgeJsonSerializer::new(),
+        );
+        visitor.measurement("temperature", 25.0)?;
+
+        assert_eq!(
+            visitor.into_inner().into_string()?,
+            r#"{"temperature":25.0}"#
+        );
+        Ok(())
+    }
+}
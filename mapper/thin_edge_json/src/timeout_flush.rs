@@ -0,0 +1,359 @@
+use crate::context::MeasurementContext;
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use crate::serialize::{ThinEdgeJsonSerializationError, ThinEdgeJsonSerializer};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// A visitor gathering measurements into payloads, as the `ThinEdgeJsonSerializer`
+pub trait PayloadVisitor: GroupedMeasurementVisitor {
+    /// Complete the current payload, returning it, and start a new one
+    fn take_payload(&mut self) -> Result<Vec<u8>, Self::Error>;
+}
+
+impl PayloadVisitor for ThinEdgeJsonSerializer {
+    fn take_payload(&mut self) -> Result<Vec<u8>, ThinEdgeJsonSerializationError> {
+        self.take_bytes()
+    }
+}
+
+type FlushCallback = Arc<dyn Fn(Vec<u8>) + Send + Sync>;
+
+/// The state shared by the visitor and the timer flushing its payload
+struct PendingPayload<V: PayloadVisitor> {
+    inner: V,
+    /// Incremented on each flush, so a timer only flushes the payload it has been started for
+    generation: u64,
+    has_measurements: bool,
+    is_within_group: bool,
+    /// Set when the timer fires within a group, the payload being flushed at the end of the group
+    expired: bool,
+    /// The error raised by a flush on timeout, to be returned on the next call
+    error: Option<V::Error>,
+}
+
+impl<V: PayloadVisitor> PendingPayload<V> {
+    fn flush(&mut self, on_flush: &FlushCallback) -> Result<(), V::Error> {
+        self.generation += 1;
+        self.has_measurements = false;
+        self.expired = false;
+        let payload = self.inner.take_payload()?;
+        on_flush(payload);
+        Ok(())
+    }
+}
+
+/// A visitor that flushes the payload of a serializer
+/// when this payload has not been taken within a maximum age.
+///
+/// The age of a payload is counted from its first measurement.
+/// If `take_payload()` is not called within `max_age`, the payload is taken from the serializer
+/// and given to the `on_flush` callback, the serializer starting a new payload.
+/// A payload expiring within a group is flushed at the end of the group.
+///
+/// The timer is a tokio task: the visitor must be used within a tokio runtime.
+/// An error raised by a flush on timeout is returned by the next call to the visitor.
+/// A payload still pending when the visitor is dropped is not flushed.
+///
+/// ```
+/// use std::sync::mpsc;
+/// use std::time::Duration;
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+/// use thin_edge_json::timeout_flush::TimeoutFlushingVisitor;
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), anyhow::Error> {
+/// let (flushed, payloads) = mpsc::channel();
+/// let mut visitor = TimeoutFlushingVisitor::new(
+///     Duration::from_millis(100),
+///     ThinEdgeJsonSerializer::new(),
+///     move |payload| flushed.send(payload).unwrap(),
+/// );
+///
+/// visitor.measurement("temperature", 25.5)?;
+/// tokio::time::sleep(Duration::from_millis(200)).await;
+///
+/// assert_eq!(payloads.try_recv()?, br#"{"temperature":25.5}"#);
+/// # Ok(()) }
+/// ```
+pub struct TimeoutFlushingVisitor<V: PayloadVisitor> {
+    max_age: Duration,
+    on_flush: FlushCallback,
+    pending: Arc<Mutex<PendingPayload<V>>>,
+    timer: Option<JoinHandle<()>>,
+}
+
+impl<V> TimeoutFlushingVisitor<V>
+where
+    V: PayloadVisitor + Send + 'static,
+    V::Error: Send,
+{
+    pub fn new<F>(max_age: Duration, inner: V, on_flush: F) -> Self
+    where
+        F: Fn(Vec<u8>) + Send + Sync + 'static,
+    {
+        Self {
+            max_age,
+            on_flush: Arc::new(on_flush),
+            pending: Arc::new(Mutex::new(PendingPayload {
+                inner,
+                generation: 0,
+                has_measurements: false,
+                is_within_group: false,
+                expired: false,
+                error: None,
+            })),
+            timer: None,
+        }
+    }
+
+    /// Take the current payload, cancelling its flush on timeout
+    pub fn take_payload(&mut self) -> Result<Vec<u8>, V::Error> {
+        self.cancel_timer();
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(err) = pending.error.take() {
+            return Err(err);
+        }
+        pending.generation += 1;
+        pending.has_measurements = false;
+        pending.expired = false;
+        pending.inner.take_payload()
+    }
+
+    /// Forward a call to the inner visitor, after reporting the error of a flush on timeout
+    fn forward(
+        &mut self,
+        call: impl FnOnce(&mut V) -> Result<(), V::Error>,
+    ) -> Result<(), V::Error> {
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(err) = pending.error.take() {
+            return Err(err);
+        }
+        call(&mut pending.inner)
+    }
+
+    /// Forward a measurement, starting the timer of the payload on its first measurement
+    fn forward_measurement(
+        &mut self,
+        call: impl FnOnce(&mut V) -> Result<(), V::Error>,
+    ) -> Result<(), V::Error> {
+        let generation = {
+            let mut pending = self.pending.lock().unwrap();
+            if let Some(err) = pending.error.take() {
+                return Err(err);
+            }
+            call(&mut pending.inner)?;
+            if pending.has_measurements {
+                return Ok(());
+            }
+            pending.has_measurements = true;
+            pending.generation
+        };
+        self.start_timer(generation);
+        Ok(())
+    }
+
+    fn start_timer(&mut self, generation: u64) {
+        self.cancel_timer();
+        let max_age = self.max_age;
+        let pending = self.pending.clone();
+        let on_flush = self.on_flush.clone();
+        self.timer = Some(tokio::spawn(async move {
+            tokio::time::sleep(max_age).await;
+            let mut pending = pending.lock().unwrap();
+            if pending.generation != generation {
+                return;
+            }
+            if pending.is_within_group {
+                pending.expired = true;
+            } else if let Err(err) = pending.flush(&on_flush) {
+                pending.error = Some(err);
+            }
+        }));
+    }
+
+    fn cancel_timer(&mut self) {
+        if let Some(timer) = self.timer.take() {
+            timer.abort();
+        }
+    }
+}
+
+impl<V: PayloadVisitor> Drop for TimeoutFlushingVisitor<V> {
+    fn drop(&mut self) {
+        if let Some(timer) = self.timer.take() {
+            timer.abort();
+        }
+    }
+}
+
+impl<V> GroupedMeasurementVisitor for TimeoutFlushingVisitor<V>
+where
+    V: PayloadVisitor + Send + 'static,
+    V::Error: Send,
+{
+    type Error = V::Error;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.forward(|inner| inner.timestamp(value))
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.forward_measurement(|inner| inner.measurement(name, value))
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.forward(|inner| inner.start_group(group))?;
+        self.pending.lock().unwrap().is_within_group = true;
+        Ok(())
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.forward(|inner| inner.end_group())?;
+        let on_flush = self.on_flush.clone();
+        let mut pending = self.pending.lock().unwrap();
+        pending.is_within_group = false;
+        if pending.expired {
+            pending.flush(&on_flush)?;
+        }
+        Ok(())
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.forward_measurement(|inner| inner.measurement_with_unit(name, value, unit))
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.forward(|inner| inner.start_group_with_timestamp(group, timestamp))?;
+        self.pending.lock().unwrap().is_within_group = true;
+        Ok(())
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        self.forward_measurement(|inner| inner.nullable_measurement(name, value))
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.forward_measurement(|inner| inner.complex_measurement(name, real, imaginary))
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.forward_measurement(|inner| inner.annotated_measurement(name, value, quality))
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.forward(|inner| inner.set_context(context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    const MAX_AGE: Duration = Duration::from_millis(100);
+
+    fn visitor() -> (
+        TimeoutFlushingVisitor<ThinEdgeJsonSerializer>,
+        mpsc::Receiver<String>,
+    ) {
+        let (flushed, payloads) = mpsc::channel();
+        let visitor =
+            TimeoutFlushingVisitor::new(MAX_AGE, ThinEdgeJsonSerializer::new(), move |payload| {
+                flushed.send(String::from_utf8(payload).unwrap()).unwrap()
+            });
+        (visitor, payloads)
+    }
+
+    async fn wait_max_age() {
+        tokio::time::sleep(MAX_AGE * 2).await;
+    }
+
+    #[tokio::test]
+    async fn payloads_are_flushed_after_the_max_age() -> anyhow::Result<()> {
+        let (mut visitor, payloads) = visitor();
+
+        visitor.measurement("temperature", 25.5)?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.end_group()?;
+        assert!(payloads.try_recv().is_err());
+
+        wait_max_age().await;
+        assert_eq!(
+            payloads.try_recv()?,
+            r#"{"temperature":25.5,"location":{"alti":2100.4}}"#
+        );
+
+        // The serializer is reset, the next payload being flushed on its own timeout
+        visitor.measurement("temperature", 26.0)?;
+        wait_max_age().await;
+        assert_eq!(payloads.try_recv()?, r#"{"temperature":26.0}"#);
+        assert!(payloads.try_recv().is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn payloads_taken_in_time_are_not_flushed() -> anyhow::Result<()> {
+        let (mut visitor, payloads) = visitor();
+
+        visitor.measurement("temperature", 25.5)?;
+        assert_eq!(visitor.take_payload()?, br#"{"temperature":25.5}"#);
+
+        wait_max_age().await;
+        assert!(payloads.try_recv().is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn payloads_expiring_within_a_group_are_flushed_at_the_end_of_the_group(
+    ) -> anyhow::Result<()> {
+        let (mut visitor, payloads) = visitor();
+
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        wait_max_age().await;
+        assert!(payloads.try_recv().is_err());
+
+        visitor.measurement("longi", 2200.4)?;
+        visitor.end_group()?;
+        assert_eq!(
+            payloads.try_recv()?,
+            r#"{"location":{"alti":2100.4,"longi":2200.4}}"#
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn payloads_without_measurements_are_not_flushed() -> anyhow::Result<()> {
+        let (mut visitor, payloads) = visitor();
+
+        visitor.timestamp(DateTime::parse_from_rfc3339("2021-04-08T10:00:00+02:00")?)?;
+        wait_max_age().await;
+
+        assert!(payloads.try_recv().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use crate::context::MeasurementContext;
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+
+/// A call to one of the methods of a `GroupedMeasurementVisitor`.
+///
+/// A sequence of `VisitorCall`s records how a source drove a visitor,
+/// and can be applied later to any other visitor.
+///
+/// ```
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+/// use thin_edge_json::trace::VisitorCall;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let trace = vec![
+///     VisitorCall::StartGroup { group: "location".into() },
+///     VisitorCall::Measurement { name: "alti".into(), value: 2100.4 },
+///     VisitorCall::EndGroup,
+/// ];
+///
+/// let mut serializer = ThinEdgeJsonSerializer::new();
+/// for call in trace.iter() {
+///     call.apply(&mut serializer)?;
+/// }
+///
+/// assert_eq!(serializer.into_string()?, r#"{"location":{"alti":2100.4}}"#);
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "call", rename_all = "snake_case")]
+pub enum VisitorCall {
+    Timestamp {
+        value: DateTime<FixedOffset>,
+    },
+    Measurement {
+        name: String,
+        value: f64,
+    },
+    StartGroup {
+        group: String,
+    },
+    EndGroup,
+    MeasurementWithUnit {
+        name: String,
+        value: f64,
+        unit: String,
+    },
+    StartGroupWithTimestamp {
+        group: String,
+        value: DateTime<FixedOffset>,
+    },
+    NullableMeasurement {
+        name: String,
+        value: Option<f64>,
+    },
+    ComplexMeasurement {
+        name: String,
+        real: f64,
+        imaginary: f64,
+    },
+    AnnotatedMeasurement {
+        name: String,
+        value: f64,
+        quality: MeasurementQuality,
+    },
+    SetContext {
+        context: MeasurementContext,
+    },
+}
+
+impl VisitorCall {
+    /// Call on the given visitor the method this call stands for.
+    pub fn apply<V>(&self, visitor: &mut V) -> Result<(), V::Error>
+    where
+        V: GroupedMeasurementVisitor,
+    {
+        match self {
+            VisitorCall::Timestamp { value } => visitor.timestamp(*value),
+            VisitorCall::Measurement { name, value } => visitor.measurement(name, *value),
+            VisitorCall::StartGroup { group } => visitor.start_group(group),
+            VisitorCall::EndGroup => visitor.end_group(),
+            VisitorCall::MeasurementWithUnit { name, value, unit } => {
+                visitor.measurement_with_unit(name, *value, unit)
+            }
+            VisitorCall::StartGroupWithTimestamp { group, value } => {
+                visitor.start_group_with_timestamp(group, *value)
+            }
+            VisitorCall::NullableMeasurement { name, value } => {
+                visitor.nullable_measurement(name, *value)
+            }
+            VisitorCall::ComplexMeasurement {
+                name,
+                real,
+                imaginary,
+            } => visitor.complex_measurement(name, *real, *imaginary),
+            VisitorCall::AnnotatedMeasurement {
+                name,
+                value,
+                quality,
+            } => visitor.annotated_measurement(name, *value, *quality),
+            VisitorCall::SetContext { context } => visitor.set_context(context.clone()),
+        }
+    }
+
+    /// Whether this call gives a measurement, in contrast to a timestamp, a group or a context
+    pub fn is_measurement(&self) -> bool {
+        match self {
+            VisitorCall::Measurement { .. }
+            | VisitorCall::MeasurementWithUnit { .. }
+            | VisitorCall::NullableMeasurement { .. }
+            | VisitorCall::ComplexMeasurement { .. }
+            | VisitorCall::AnnotatedMeasurement { .. } => true,
+            VisitorCall::Timestamp { .. }
+            | VisitorCall::StartGroup { .. }
+            | VisitorCall::EndGroup
+            | VisitorCall::StartGroupWithTimestamp { .. }
+            | VisitorCall::SetContext { .. } => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    #[test]
+    fn visitor_calls_are_serialized_as_tagged_objects() -> anyhow::Result<()> {
+        let trace = vec![
+            VisitorCall::Timestamp {
+                value: FixedOffset::east(2 * 3600)
+                    .ymd(2021, 4, 30)
+                    .and_hms(17, 3, 14),
+            },
+            VisitorCall::StartGroup {
+                group: "location".into(),
+            },
+            VisitorCall::Measurement {
+                name: "alti".into(),
+                value: 2100.4,
+            },
+            VisitorCall::EndGroup,
+        ];
+
+        let json = serde_json::to_value(&trace)?;
+
+        assert_eq!(
+            json,
+            json!([
+                {"call": "timestamp", "value": "2021-04-30T17:03:14+02:00"},
+                {"call": "start_group", "group": "location"},
+                {"call": "measurement", "name": "alti", "value": 2100.4},
+                {"call": "end_group"},
+            ])
+        );
+        assert_eq!(serde_json::from_value::<Vec<VisitorCall>>(json)?, trace);
+        Ok(())
+    }
+
+    #[test]
+    fn measurement_qualities_are_serialized_in_uppercase() -> anyhow::Result<()> {
+        let call = VisitorCall::AnnotatedMeasurement {
+            name: "temperature".into(),
+            value: 25.5,
+            quality: MeasurementQuality::Uncertain,
+        };
+
+        let json = serde_json::to_value(&call)?;
+
+        assert_eq!(
+            json,
+            json!({"call": "annotated_measurement", "name": "temperature", "value": 25.5, "quality": "UNCERTAIN"})
+        );
+        assert_eq!(serde_json::from_value::<VisitorCall>(json)?, call);
+        Ok(())
+    }
+
+    #[test]
+    fn contexts_are_serialized_with_hex_ids() -> anyhow::Result<()> {
+        let call = VisitorCall::SetContext {
+            context: MeasurementContext::new([0x4b; 16], [0xf0; 8]).with_baggage("site", "plant-7"),
+        };
+
+        let json = serde_json::to_value(&call)?;
+
+        assert_eq!(
+            json,
+            json!({
+                "call": "set_context",
+                "context": {
+                    "trace_id": "4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b",
+                    "span_id": "f0f0f0f0f0f0f0f0",
+                    "baggage": {"site": "plant-7"},
+                },
+            })
+        );
+        assert_eq!(serde_json::from_value::<VisitorCall>(json)?, call);
+        Ok(())
+    }
+}
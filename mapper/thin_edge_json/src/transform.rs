@@ -0,0 +1,888 @@
+use crate::context::MeasurementContext;
+use crate::filter::{GlobPattern, MeasurementPredicate, PendingGroup};
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality, MetadataVisitor};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::str::Chars;
+
+/// Transformation rules, as loaded from a JSON file.
+///
+/// The rules are applied in order to each measurement,
+/// a rule applying to the measurements with a key, `<name>` or `<group>.<name>`,
+/// matching its glob pattern:
+///
+/// ```json
+/// {
+///     "expressions": { "fahrenheit_to_celsius": "($0 - 32) * 5/9" },
+///     "rules": [
+///         { "type": "rename", "measurement": "temp_f", "to": "temperature" },
+///         { "type": "apply", "measurement": "temperature", "expression": "fahrenheit_to_celsius" },
+///         { "type": "apply", "measurement": "engine.*", "expression": "$0 * 1000" },
+///         { "type": "range", "measurement": "temperature", "min": -50, "max": 150 },
+///         { "type": "metadata", "key": "_site", "value": "plant-1" }
+///     ]
+/// }
+/// ```
+///
+/// * A `rename` rule gives a new name to the measurement, which stays in its group.
+///   The rules that follow match the new name.
+/// * An `apply` rule replaces the value of the measurement by the result of an expression,
+///   either given inline or by the name of one of the `expressions`.
+///   An expression combines `$0`, the value of the measurement, and numbers
+///   with the `+`, `-`, `*` and `/` operators and parentheses.
+/// * A `range` rule drops the measurement if its value is lower than `min` or greater than `max`,
+///   the bounds being optional and inclusive.
+/// * A `metadata` rule adds a static metadata field to the messages, its key starting with `_`.
+///
+/// The complex measurements are only renamed,
+/// and the null measurements are neither transformed nor dropped.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransformConfig {
+    #[serde(default)]
+    pub expressions: HashMap<String, String>,
+
+    #[serde(default)]
+    pub rules: Vec<TransformRuleConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TransformRuleConfig {
+    /// Rename the measurements matching a glob pattern
+    Rename { measurement: String, to: String },
+
+    /// Compute the value of the measurements matching a glob pattern
+    Apply {
+        measurement: String,
+        expression: String,
+    },
+
+    /// Drop the measurements matching a glob pattern which value is out of range
+    Range {
+        measurement: String,
+        min: Option<f64>,
+        max: Option<f64>,
+    },
+
+    /// Add a static metadata field to the messages
+    Metadata { key: String, value: String },
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum TransformConfigError {
+    #[error("Failed to read {path:?}: {from}")]
+    FileReadError { path: PathBuf, from: std::io::Error },
+
+    #[error("Invalid transformation rules: {0}")]
+    InvalidTransformConfig(#[source] serde_json::Error),
+
+    #[error("Invalid expression {expression:?}: {from}")]
+    InvalidExpression {
+        expression: String,
+        from: ExpressionError,
+    },
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum ExpressionError {
+    #[error("unexpected character {0:?}")]
+    UnexpectedCharacter(char),
+
+    #[error("unknown variable ${0}, the value of the measurement being $0")]
+    UnknownVariable(String),
+
+    #[error("invalid number {0:?}")]
+    InvalidNumber(String),
+
+    #[error("unexpected {0}")]
+    UnexpectedToken(String),
+
+    #[error("unexpected end of expression")]
+    UnexpectedEnd,
+}
+
+impl TransformConfig {
+    pub fn from_json_str(config: &str) -> Result<Self, TransformConfigError> {
+        serde_json::from_str(config).map_err(TransformConfigError::InvalidTransformConfig)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, TransformConfigError> {
+        let path = path.as_ref();
+        let config =
+            std::fs::read_to_string(path).map_err(|from| TransformConfigError::FileReadError {
+                path: path.into(),
+                from,
+            })?;
+        TransformConfig::from_json_str(&config)
+    }
+
+    /// Compile the rules, parsing all the expressions
+    pub fn compile(&self) -> Result<TransformRules, TransformConfigError> {
+        let mut rules = Vec::new();
+        let mut metadata = Vec::new();
+        for rule in self.rules.iter() {
+            match rule {
+                TransformRuleConfig::Rename { measurement, to } => {
+                    rules.push(TransformRule::Rename {
+                        pattern: GlobPattern::new(measurement),
+                        to: to.clone(),
+                    });
+                }
+                TransformRuleConfig::Apply {
+                    measurement,
+                    expression,
+                } => {
+                    let expression = self.expressions.get(expression).unwrap_or(expression);
+                    let parsed = Expression::parse(expression).map_err(|from| {
+                        TransformConfigError::InvalidExpression {
+                            expression: expression.clone(),
+                            from,
+                        }
+                    })?;
+                    rules.push(TransformRule::Apply {
+                        pattern: GlobPattern::new(measurement),
+                        expression: parsed,
+                    });
+                }
+                TransformRuleConfig::Range {
+                    measurement,
+                    min,
+                    max,
+                } => {
+                    rules.push(TransformRule::Range {
+                        pattern: GlobPattern::new(measurement),
+                        min: min.unwrap_or(f64::NEG_INFINITY),
+                        max: max.unwrap_or(f64::INFINITY),
+                    });
+                }
+                TransformRuleConfig::Metadata { key, value } => {
+                    metadata.push((key.clone(), value.clone()));
+                }
+            }
+        }
+        Ok(TransformRules { rules, metadata })
+    }
+}
+
+/// Compiled transformation rules, ready to be applied by a `DslTransformVisitor`
+#[derive(Debug, Clone, Default)]
+pub struct TransformRules {
+    rules: Vec<TransformRule>,
+    metadata: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone)]
+enum TransformRule {
+    Rename {
+        pattern: GlobPattern,
+        to: String,
+    },
+    Apply {
+        pattern: GlobPattern,
+        expression: Expression,
+    },
+    Range {
+        pattern: GlobPattern,
+        min: f64,
+        max: f64,
+    },
+}
+
+impl TransformRules {
+    /// Load and compile the rules of a JSON file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, TransformConfigError> {
+        TransformConfig::from_file(path)?.compile()
+    }
+
+    /// The new name and value of a measurement, or `None` if the measurement is dropped
+    fn transform(
+        &self,
+        group: &PendingGroup,
+        name: &str,
+        mut value: Option<f64>,
+    ) -> Option<(String, Option<f64>)> {
+        let mut name = name.to_string();
+        for rule in self.rules.iter() {
+            match rule {
+                TransformRule::Rename { pattern, to } if pattern.accept(&group.key(&name)) => {
+                    name = to.clone();
+                }
+                TransformRule::Apply {
+                    pattern,
+                    expression,
+                } if pattern.accept(&group.key(&name)) => {
+                    value = value.map(|value| expression.eval(value));
+                }
+                TransformRule::Range { pattern, min, max } if pattern.accept(&group.key(&name)) => {
+                    if let Some(value) = value {
+                        if value < *min || value > *max {
+                            return None;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Some((name, value))
+    }
+
+    /// The new name of a measurement, renamed by the rules but otherwise unchanged
+    fn rename(&self, group: &PendingGroup, name: &str) -> String {
+        let mut name = name.to_string();
+        for rule in self.rules.iter() {
+            if let TransformRule::Rename { pattern, to } = rule {
+                if pattern.accept(&group.key(&name)) {
+                    name = to.clone();
+                }
+            }
+        }
+        name
+    }
+}
+
+/// An arithmetic expression computing a new value from the value `$0` of a measurement.
+///
+/// ```
+/// use thin_edge_json::transform::Expression;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let fahrenheit_to_celsius = Expression::parse("($0 - 32) * 5/9")?;
+///
+/// assert_eq!(fahrenheit_to_celsius.eval(212.0), 100.0);
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    root: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Value,
+    Number(f64),
+    Neg(Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Value,
+    Number(f64),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Open,
+    Close,
+}
+
+impl Expression {
+    pub fn parse(expression: &str) -> Result<Self, ExpressionError> {
+        let tokens = tokenize(expression)?;
+        let mut tokens = tokens.into_iter().peekable();
+        let root = parse_sum(&mut tokens)?;
+        match tokens.next() {
+            None => Ok(Expression { root }),
+            Some(token) => Err(ExpressionError::UnexpectedToken(token.to_string())),
+        }
+    }
+
+    /// Evaluate the expression, `$0` standing for the given value
+    pub fn eval(&self, value: f64) -> f64 {
+        self.root.eval(value)
+    }
+}
+
+impl Expr {
+    fn eval(&self, value: f64) -> f64 {
+        match self {
+            Expr::Value => value,
+            Expr::Number(number) => *number,
+            Expr::Neg(expr) => -expr.eval(value),
+            Expr::Add(lhs, rhs) => lhs.eval(value) + rhs.eval(value),
+            Expr::Sub(lhs, rhs) => lhs.eval(value) - rhs.eval(value),
+            Expr::Mul(lhs, rhs) => lhs.eval(value) * rhs.eval(value),
+            Expr::Div(lhs, rhs) => lhs.eval(value) / rhs.eval(value),
+        }
+    }
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Value => write!(f, "$0"),
+            Token::Number(number) => write!(f, "{}", number),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Star => write!(f, "*"),
+            Token::Slash => write!(f, "/"),
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+        }
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, ExpressionError> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Star,
+            '/' => Token::Slash,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '$' => {
+                let variable = take_while(&mut chars, String::new(), |c| {
+                    c.is_ascii_alphanumeric() || c == '_'
+                });
+                if variable != "0" {
+                    return Err(ExpressionError::UnknownVariable(variable));
+                }
+                Token::Value
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let number = take_while(&mut chars, c.to_string(), |c| {
+                    c.is_ascii_digit() || c == '.'
+                });
+                match number.parse() {
+                    Ok(number) => Token::Number(number),
+                    Err(_) => return Err(ExpressionError::InvalidNumber(number)),
+                }
+            }
+            c => return Err(ExpressionError::UnexpectedCharacter(c)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn take_while(
+    chars: &mut Peekable<Chars>,
+    mut taken: String,
+    accept: impl Fn(char) -> bool,
+) -> String {
+    while let Some(c) = chars.peek() {
+        if !accept(*c) {
+            break;
+        }
+        taken.push(*c);
+        chars.next();
+    }
+    taken
+}
+
+type Tokens = Peekable<std::vec::IntoIter<Token>>;
+
+/// sum := product (('+' | '-') product)*
+fn parse_sum(tokens: &mut Tokens) -> Result<Expr, ExpressionError> {
+    let mut expr = parse_product(tokens)?;
+    loop {
+        expr = match tokens.peek() {
+            Some(Token::Plus) => {
+                tokens.next();
+                Expr::Add(Box::new(expr), Box::new(parse_product(tokens)?))
+            }
+            Some(Token::Minus) => {
+                tokens.next();
+                Expr::Sub(Box::new(expr), Box::new(parse_product(tokens)?))
+            }
+            _ => return Ok(expr),
+        }
+    }
+}
+
+/// product := factor (('*' | '/') factor)*
+fn parse_product(tokens: &mut Tokens) -> Result<Expr, ExpressionError> {
+    let mut expr = parse_factor(tokens)?;
+    loop {
+        expr = match tokens.peek() {
+            Some(Token::Star) => {
+                tokens.next();
+                Expr::Mul(Box::new(expr), Box::new(parse_factor(tokens)?))
+            }
+            Some(Token::Slash) => {
+                tokens.next();
+                Expr::Div(Box::new(expr), Box::new(parse_factor(tokens)?))
+            }
+            _ => return Ok(expr),
+        }
+    }
+}
+
+/// factor := '-' factor | '$0' | number | '(' sum ')'
+fn parse_factor(tokens: &mut Tokens) -> Result<Expr, ExpressionError> {
+    match tokens.next() {
+        Some(Token::Minus) => Ok(Expr::Neg(Box::new(parse_factor(tokens)?))),
+        Some(Token::Value) => Ok(Expr::Value),
+        Some(Token::Number(number)) => Ok(Expr::Number(number)),
+        Some(Token::Open) => {
+            let expr = parse_sum(tokens)?;
+            match tokens.next() {
+                Some(Token::Close) => Ok(expr),
+                Some(token) => Err(ExpressionError::UnexpectedToken(token.to_string())),
+                None => Err(ExpressionError::UnexpectedEnd),
+            }
+        }
+        Some(token) => Err(ExpressionError::UnexpectedToken(token.to_string())),
+        None => Err(ExpressionError::UnexpectedEnd),
+    }
+}
+
+/// A visitor that transforms the measurements along rules loaded from a JSON file,
+/// before forwarding them.
+///
+/// See `TransformConfig` for the rules and the format of the files.
+///
+/// A group is only forwarded if at least one of its measurements is.
+/// The metadata fields are added before the first forwarded measurement,
+/// and once again after each call to `next_message()`.
+///
+/// ```
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+/// use thin_edge_json::transform::{DslTransformVisitor, TransformConfig};
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let rules = TransformConfig::from_json_str(
+///     r#"{"rules":[{"type":"apply","measurement":"temp","expression":"($0 - 32) * 5/9"}]}"#,
+/// )?
+/// .compile()?;
+/// let mut visitor = DslTransformVisitor::new(rules, ThinEdgeJsonSerializer::new());
+///
+/// visitor.measurement("temp", 212.0)?;
+///
+/// assert_eq!(visitor.into_inner().into_string()?, r#"{"temp":100.0}"#);
+/// # Ok(()) }
+/// ```
+pub struct DslTransformVisitor<V> {
+    rules: TransformRules,
+    group: PendingGroup,
+    metadata_added: bool,
+    inner: V,
+}
+
+impl<V> DslTransformVisitor<V> {
+    pub fn new(rules: TransformRules, inner: V) -> Self {
+        Self {
+            rules,
+            group: PendingGroup::default(),
+            metadata_added: false,
+            inner,
+        }
+    }
+
+    /// Start a new message, to which the metadata fields have to be added
+    pub fn next_message(&mut self) {
+        self.metadata_added = false;
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut V {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> V {
+        self.inner
+    }
+
+    /// Add the metadata fields, if not done yet, then the pending group, if any
+    fn forward_start(&mut self) -> Result<(), V::Error>
+    where
+        V: MetadataVisitor,
+    {
+        if !self.metadata_added {
+            for (key, value) in self.rules.metadata.iter() {
+                self.inner.metadata(key, value)?;
+            }
+            self.metadata_added = true;
+        }
+        self.group.forward_start(&mut self.inner)
+    }
+}
+
+impl<V> GroupedMeasurementVisitor for DslTransformVisitor<V>
+where
+    V: MetadataVisitor,
+{
+    type Error = V::Error;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.inner.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        if let Some((name, Some(value))) = self.rules.transform(&self.group, name, Some(value)) {
+            self.forward_start()?;
+            self.inner.measurement(&name, value)?;
+        }
+        Ok(())
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.group.start(group, &mut self.inner)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.group.end(&mut self.inner)
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        if let Some((name, Some(value))) = self.rules.transform(&self.group, name, Some(value)) {
+            self.forward_start()?;
+            self.inner.measurement_with_unit(&name, value, unit)?;
+        }
+        Ok(())
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.group
+            .start_with_timestamp(group, timestamp, &mut self.inner)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        if let Some((name, value)) = self.rules.transform(&self.group, name, value) {
+            self.forward_start()?;
+            self.inner.nullable_measurement(&name, value)?;
+        }
+        Ok(())
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        let name = self.rules.rename(&self.group, name);
+        self.forward_start()?;
+        self.inner.complex_measurement(&name, real, imaginary)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        if let Some((name, Some(value))) = self.rules.transform(&self.group, name, Some(value)) {
+            self.forward_start()?;
+            self.inner.annotated_measurement(&name, value, quality)?;
+        }
+        Ok(())
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.inner.set_context(context)
+    }
+}
+
+impl<V> MetadataVisitor for DslTransformVisitor<V>
+where
+    V: MetadataVisitor,
+{
+    fn metadata(&mut self, key: &str, value: &str) -> Result<(), Self::Error> {
+        self.inner.metadata(key, value)
+    }
+
+    fn metadata_flag(&mut self, key: &str, value: bool) -> Result<(), Self::Error> {
+        self.inner.metadata_flag(key, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::ThinEdgeJsonSerializer;
+    use assert_matches::assert_matches;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    fn visitor(rules: &str) -> DslTransformVisitor<ThinEdgeJsonSerializer> {
+        let rules = TransformConfig::from_json_str(rules)
+            .expect("Valid rules")
+            .compile()
+            .expect("Valid expressions");
+        DslTransformVisitor::new(rules, ThinEdgeJsonSerializer::new())
+    }
+
+    #[test]
+    fn parse_transform_config() -> anyhow::Result<()> {
+        let config = TransformConfig::from_json_str(
+            r#"{
+                "expressions": { "fahrenheit_to_celsius": "($0 - 32) * 5/9" },
+                "rules": [
+                    { "type": "rename", "measurement": "temp_f", "to": "temperature" },
+                    { "type": "apply", "measurement": "temperature", "expression": "fahrenheit_to_celsius" },
+                    { "type": "range", "measurement": "temperature", "max": 150 },
+                    { "type": "metadata", "key": "_site", "value": "plant-1" }
+                ]
+            }"#,
+        )?;
+
+        assert_eq!(
+            config.rules,
+            vec![
+                TransformRuleConfig::Rename {
+                    measurement: "temp_f".into(),
+                    to: "temperature".into()
+                },
+                TransformRuleConfig::Apply {
+                    measurement: "temperature".into(),
+                    expression: "fahrenheit_to_celsius".into()
+                },
+                TransformRuleConfig::Range {
+                    measurement: "temperature".into(),
+                    min: None,
+                    max: Some(150.0)
+                },
+                TransformRuleConfig::Metadata {
+                    key: "_site".into(),
+                    value: "plant-1".into()
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_rules() {
+        assert_matches!(
+            TransformConfig::from_json_str(r#"{"rules":[{"type":"compress","level":9}]}"#),
+            Err(TransformConfigError::InvalidTransformConfig(_))
+        );
+        assert_matches!(
+            TransformConfig::from_json_str(
+                r#"{"rules":[{"type":"apply","measurement":"temp","expression":"$1 * 2"}]}"#
+            )
+            .unwrap()
+            .compile(),
+            Err(TransformConfigError::InvalidExpression {
+                from: ExpressionError::UnknownVariable(_),
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn evaluate_expressions() -> anyhow::Result<()> {
+        for (expression, value, expected) in [
+            ("$0", 21.5, 21.5),
+            ("42", 21.5, 42.0),
+            ("$0 * 1000", 1.5, 1500.0),
+            ("($0 - 32) * 5/9", 212.0, 100.0),
+            ("$0 - 32 * 5 / 8", 100.0, 80.0),
+            ("-$0 + 2", 3.0, -1.0),
+            ("-(-$0)", 3.0, 3.0),
+            ("10 - 4 - 3", 0.0, 3.0),
+            ("12 / 3 / 2", 0.0, 2.0),
+            (" ( ( $0 ) ) ", 7.0, 7.0),
+            ("0.5 * $0", 3.0, 1.5),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                Expression::parse(expression)?.eval(*value),
+                *expected,
+                "{}",
+                expression
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_expressions() {
+        for (expression, error) in [
+            ("", ExpressionError::UnexpectedEnd),
+            ("$0 +", ExpressionError::UnexpectedEnd),
+            ("($0 - 32", ExpressionError::UnexpectedEnd),
+            ("$0 - 32)", ExpressionError::UnexpectedToken(")".into())),
+            ("$0 $0", ExpressionError::UnexpectedToken("$0".into())),
+            ("* 2", ExpressionError::UnexpectedToken("*".into())),
+            (
+                "$value * 2",
+                ExpressionError::UnknownVariable("value".into()),
+            ),
+            ("$12", ExpressionError::UnknownVariable("12".into())),
+            ("1.2.3", ExpressionError::InvalidNumber("1.2.3".into())),
+            ("$0 ^ 2", ExpressionError::UnexpectedCharacter('^')),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                Expression::parse(expression),
+                Err(error.clone()),
+                "{}",
+                expression
+            );
+        }
+    }
+
+    #[test]
+    fn rename_measurements() -> anyhow::Result<()> {
+        let mut visitor = visitor(
+            r#"{"rules":[
+                {"type":"rename","measurement":"*temp_*","to":"temperature"},
+                {"type":"rename","measurement":"engine.rpm","to":"speed"}
+            ]}"#,
+        );
+
+        visitor.measurement("temp_f", 77.0)?;
+        visitor.start_group("engine")?;
+        visitor.measurement_with_unit("rpm", 3000.0, "rpm")?;
+        visitor.complex_measurement("temp_coil", 1.0, 2.0)?;
+        visitor.end_group()?;
+
+        assert_eq!(
+            visitor.into_inner().into_string()?,
+            r#"{"temperature":77.0,"engine":{"speed":{"value":3000.0,"unit":"rpm"},"temperature":{"re":1.0,"im":2.0}}}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn apply_expressions() -> anyhow::Result<()> {
+        let mut visitor = visitor(
+            r#"{
+                "expressions": { "fahrenheit_to_celsius": "($0 - 32) * 5/9" },
+                "rules": [
+                    {"type":"rename","measurement":"temp_f","to":"temperature"},
+                    {"type":"apply","measurement":"temperature","expression":"fahrenheit_to_celsius"},
+                    {"type":"apply","measurement":"engine.*","expression":"$0 * 1000"}
+                ]
+            }"#,
+        );
+
+        visitor.measurement("temp_f", 212.0)?;
+        visitor.measurement("pressure", 98.0)?;
+        visitor.start_group("engine")?;
+        visitor.annotated_measurement("power", 1.5, MeasurementQuality::Good)?;
+        visitor.nullable_measurement("torque", None)?;
+        visitor.end_group()?;
+
+        assert_eq!(
+            visitor.into_inner().into_string()?,
+            r#"{"temperature":100.0,"pressure":98.0,"engine":{"power":{"value":1500.0,"quality":"GOOD"},"torque":null}}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn filter_by_value_range() -> anyhow::Result<()> {
+        let mut visitor = visitor(
+            r#"{"rules":[
+                {"type":"range","measurement":"temperature","min":-50,"max":150},
+                {"type":"range","measurement":"engine.*","min":0}
+            ]}"#,
+        );
+
+        visitor.measurement("temperature", 150.0)?;
+        visitor.measurement("temperature", 150.5)?;
+        visitor.measurement("temperature", -51.0)?;
+        visitor.start_group("engine")?;
+        visitor.measurement("speed", -1.0)?;
+        visitor.nullable_measurement("torque", None)?;
+        visitor.end_group()?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", -10.0)?;
+        visitor.end_group()?;
+
+        assert_eq!(
+            visitor.into_inner().into_string()?,
+            r#"{"temperature":150.0,"engine":{"torque":null},"location":{"alti":-10.0}}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn groups_with_no_forwarded_measurements_are_dropped() -> anyhow::Result<()> {
+        let mut visitor =
+            visitor(r#"{"rules":[{"type":"range","measurement":"engine.*","max":100}]}"#);
+
+        visitor.measurement("temperature", 25.0)?;
+        visitor.start_group("engine")?;
+        visitor.measurement("speed", 3000.0)?;
+        visitor.end_group()?;
+
+        assert_eq!(
+            visitor.into_inner().into_string()?,
+            r#"{"temperature":25.0}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn inject_metadata_fields() -> anyhow::Result<()> {
+        let mut visitor = visitor(
+            r#"{"rules":[
+                {"type":"metadata","key":"_site","value":"plant-1"},
+                {"type":"metadata","key":"_line","value":"2"}
+            ]}"#,
+        );
+
+        visitor.measurement("temperature", 25.0)?;
+        visitor.measurement("pressure", 98.0)?;
+        let first = visitor.inner_mut().take_bytes()?;
+        visitor.next_message();
+        visitor.measurement("temperature", 26.0)?;
+        let second = visitor.inner_mut().take_bytes()?;
+
+        assert_eq!(
+            String::from_utf8(first)?,
+            r#"{"_site":"plant-1","_line":"2","temperature":25.0,"pressure":98.0}"#
+        );
+        assert_eq!(
+            String::from_utf8(second)?,
+            r#"{"_site":"plant-1","_line":"2","temperature":26.0}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn load_rules_from_a_file() -> anyhow::Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("rules.json");
+        std::fs::File::create(&path)?.write_all(
+            br#"{"rules":[{"type":"apply","measurement":"*","expression":"$0 + 1"}]}"#,
+        )?;
+
+        let mut visitor = DslTransformVisitor::new(
+            TransformRules::from_file(&path)?,
+            ThinEdgeJsonSerializer::new(),
+        );
+        visitor.measurement("temperature", 25.0)?;
+
+        assert_eq!(
+            visitor.into_inner().into_string()?,
+            r#"{"temperature":26.0}"#
+        );
+        assert_matches!(
+            TransformRules::from_file(dir.path().join("missing.json")),
+            Err(TransformConfigError::FileReadError { .. })
+        );
+        Ok(())
+    }
+}
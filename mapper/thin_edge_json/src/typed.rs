@@ -0,0 +1,28 @@
+/// The error raised when a thin-edge JSON payload doesn't match a typed measurement schema,
+/// as defined with the `define_measurement_schema!` macro of the `thin_edge_schema_macro` crate.
+///
+/// The name of a measurement attached to a group is given as `<group>.<name>`.
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum TypedMeasurementError {
+    #[error("Missing measurement: {name}")]
+    MissingMeasurement { name: String },
+
+    #[error("Missing group: {name}")]
+    MissingGroup { name: String },
+
+    #[error("Unexpected measurement: {name}")]
+    UnexpectedMeasurement { name: String },
+
+    #[error("Unexpected group: {name}")]
+    UnexpectedGroup { name: String },
+}
+
+impl TypedMeasurementError {
+    /// The key of a measurement, prefixed by its group if any
+    pub fn key(group: Option<&str>, name: &str) -> String {
+        match group {
+            Some(group) => format!("{}.{}", group, name),
+            None => name.to_string(),
+        }
+    }
+}
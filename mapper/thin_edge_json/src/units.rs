@@ -0,0 +1,471 @@
+use crate::context::MeasurementContext;
+use crate::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Registry of the units of the measurements, indexed by group and measurement name.
+///
+/// A measurement which is not attached to a group is registered with a `None` group.
+#[derive(Debug, Clone, Default)]
+pub struct MeasurementTypeRegistry {
+    pub units: HashMap<(Option<String>, String), String>,
+}
+
+impl MeasurementTypeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_unit(mut self, group: Option<&str>, name: &str, unit: &str) -> Self {
+        self.add_unit(group, name, unit);
+        self
+    }
+
+    pub fn add_unit(&mut self, group: Option<&str>, name: &str, unit: &str) {
+        let key = (group.map(String::from), name.to_string());
+        self.units.insert(key, unit.to_string());
+    }
+
+    pub fn unit(&self, group: Option<&str>, name: &str) -> Option<&str> {
+        let key = (group.map(String::from), name.to_string());
+        self.units.get(&key).map(String::as_str)
+    }
+}
+
+/// A visitor that annotates the measurements with their units before forwarding them.
+///
+/// The measurements with a unit registered in the `MeasurementTypeRegistry`
+/// are forwarded using `measurement_with_unit`, all the others are forwarded unchanged.
+///
+/// ```
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+/// use thin_edge_json::units::{MeasurementTypeRegistry, UnitAnnotatingVisitor};
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let registry = MeasurementTypeRegistry::new().with_unit(None, "temperature", "°C");
+/// let mut visitor = UnitAnnotatingVisitor::new(registry, ThinEdgeJsonSerializer::new());
+///
+/// visitor.measurement("temperature", 25.5)?;
+/// visitor.measurement("pressure", 98.0)?;
+///
+/// assert_eq!(
+///     visitor.into_inner().into_string()?,
+///     r#"{"temperature":{"value":25.5,"unit":"°C"},"pressure":98.0}"#
+/// );
+/// # Ok(()) }
+/// ```
+pub struct UnitAnnotatingVisitor<V> {
+    registry: MeasurementTypeRegistry,
+    group: Option<String>,
+    inner: V,
+}
+
+impl<V> UnitAnnotatingVisitor<V> {
+    pub fn new(registry: MeasurementTypeRegistry, inner: V) -> Self {
+        Self {
+            registry,
+            group: None,
+            inner,
+        }
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut V {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> V {
+        self.inner
+    }
+}
+
+impl<V> GroupedMeasurementVisitor for UnitAnnotatingVisitor<V>
+where
+    V: GroupedMeasurementVisitor,
+{
+    type Error = V::Error;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.inner.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        match self.registry.unit(self.group.as_deref(), name) {
+            Some(unit) => self.inner.measurement_with_unit(name, value, unit),
+            None => self.inner.measurement(name, value),
+        }
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.inner.start_group(group)?;
+        self.group = Some(group.to_string());
+        Ok(())
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.inner.start_group_with_timestamp(group, timestamp)?;
+        self.group = Some(group.to_string());
+        Ok(())
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        match value {
+            Some(value) => self.measurement(name, value),
+            None => self.inner.nullable_measurement(name, None),
+        }
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.inner.complex_measurement(name, real, imaginary)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.inner.annotated_measurement(name, value, quality)
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.inner.set_context(context)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.inner.end_group()?;
+        self.group = None;
+        Ok(())
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.inner.measurement_with_unit(name, value, unit)
+    }
+}
+
+/// A linear conversion of the values of a measurement: `value * factor + offset`.
+///
+/// When a unit is given, the converted values are annotated with this unit.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct UnitConversion {
+    #[serde(default = "UnitConversion::default_factor")]
+    pub factor: f64,
+
+    #[serde(default)]
+    pub offset: f64,
+
+    pub unit: Option<String>,
+}
+
+impl UnitConversion {
+    fn default_factor() -> f64 {
+        1.0
+    }
+
+    pub fn apply(&self, value: f64) -> f64 {
+        value * self.factor + self.offset
+    }
+}
+
+/// The conversions to apply to the measurements,
+/// indexed by measurement name or by `<group>.<name>` for grouped measurements.
+///
+/// These conversions can be loaded from TOML:
+///
+/// ```toml
+/// [temperature]
+/// factor = 1.8
+/// offset = 32
+/// unit = "°F"
+///
+/// ["location.alti"]
+/// factor = 0.001
+/// unit = "km"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct UnitConversions {
+    pub conversions: HashMap<String, UnitConversion>,
+}
+
+impl UnitConversions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_conversion(mut self, key: &str, conversion: UnitConversion) -> Self {
+        self.conversions.insert(key.to_string(), conversion);
+        self
+    }
+
+    pub fn conversion(&self, group: Option<&str>, name: &str) -> Option<&UnitConversion> {
+        match group {
+            Some(group) => self.conversions.get(&format!("{}.{}", group, name)),
+            None => self.conversions.get(name),
+        }
+    }
+}
+
+/// A visitor that converts the measurement values before forwarding them.
+///
+/// The measurements with no registered `UnitConversion` are forwarded unchanged.
+///
+/// ```
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+/// use thin_edge_json::units::{UnitConversion, UnitConversions, UnitConvertingVisitor};
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let fahrenheit = UnitConversion { factor: 1.8, offset: 32.0, unit: Some("°F".into()) };
+/// let conversions = UnitConversions::new().with_conversion("temperature", fahrenheit);
+/// let mut visitor = UnitConvertingVisitor::new(conversions, ThinEdgeJsonSerializer::new());
+///
+/// visitor.measurement("temperature", 25.0)?;
+///
+/// assert_eq!(
+///     visitor.into_inner().into_string()?,
+///     r#"{"temperature":{"value":77.0,"unit":"°F"}}"#
+/// );
+/// # Ok(()) }
+/// ```
+pub struct UnitConvertingVisitor<V> {
+    conversions: UnitConversions,
+    group: Option<String>,
+    inner: V,
+}
+
+impl<V> UnitConvertingVisitor<V> {
+    pub fn new(conversions: UnitConversions, inner: V) -> Self {
+        Self {
+            conversions,
+            group: None,
+            inner,
+        }
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut V {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> V {
+        self.inner
+    }
+}
+
+impl<V> GroupedMeasurementVisitor for UnitConvertingVisitor<V>
+where
+    V: GroupedMeasurementVisitor,
+{
+    type Error = V::Error;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.inner.timestamp(value)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        match self.conversions.conversion(self.group.as_deref(), name) {
+            Some(conversion) => {
+                let value = conversion.apply(value);
+                match conversion.unit.as_ref() {
+                    Some(unit) => self.inner.measurement_with_unit(name, value, unit),
+                    None => self.inner.measurement(name, value),
+                }
+            }
+            None => self.inner.measurement(name, value),
+        }
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.inner.start_group(group)?;
+        self.group = Some(group.to_string());
+        Ok(())
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.inner.start_group_with_timestamp(group, timestamp)?;
+        self.group = Some(group.to_string());
+        Ok(())
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        match value {
+            Some(value) => self.measurement(name, value),
+            None => self.inner.nullable_measurement(name, None),
+        }
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.inner.complex_measurement(name, real, imaginary)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        let value = match self.conversions.conversion(self.group.as_deref(), name) {
+            Some(conversion) => conversion.apply(value),
+            None => value,
+        };
+        self.inner.annotated_measurement(name, value, quality)
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.inner.set_context(context)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.inner.end_group()?;
+        self.group = None;
+        Ok(())
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        match self.conversions.conversion(self.group.as_deref(), name) {
+            Some(conversion) => {
+                let value = conversion.apply(value);
+                let unit = conversion.unit.as_deref().unwrap_or(unit);
+                self.inner.measurement_with_unit(name, value, unit)
+            }
+            None => self.inner.measurement_with_unit(name, value, unit),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::ThinEdgeJsonSerializer;
+
+    fn registry() -> MeasurementTypeRegistry {
+        MeasurementTypeRegistry::new()
+            .with_unit(None, "temperature", "°C")
+            .with_unit(None, "pressure", "hPa")
+            .with_unit(Some("wind"), "speed", "m/s")
+    }
+
+    #[test]
+    fn registry_distinguishes_groups() {
+        let registry = registry()
+            .with_unit(Some("engine"), "temperature", "K")
+            .with_unit(None, "speed", "km/h");
+
+        assert_eq!(registry.unit(None, "temperature"), Some("°C"));
+        assert_eq!(registry.unit(Some("engine"), "temperature"), Some("K"));
+        assert_eq!(registry.unit(Some("wind"), "speed"), Some("m/s"));
+        assert_eq!(registry.unit(None, "speed"), Some("km/h"));
+        assert_eq!(registry.unit(Some("wind"), "temperature"), None);
+    }
+
+    #[test]
+    fn measurements_with_a_known_unit_are_annotated() -> anyhow::Result<()> {
+        let mut visitor = UnitAnnotatingVisitor::new(registry(), ThinEdgeJsonSerializer::new());
+
+        visitor.measurement("temperature", 25.5)?;
+        visitor.start_group("wind")?;
+        visitor.measurement("speed", 12.0)?;
+        visitor.end_group()?;
+
+        let expected_output = r#"{"temperature":{"value":25.5,"unit":"°C"},"wind":{"speed":{"value":12.0,"unit":"m/s"}}}"#;
+        assert_eq!(visitor.into_inner().into_string()?, expected_output);
+        Ok(())
+    }
+
+    #[test]
+    fn measurements_with_an_unknown_unit_are_left_plain() -> anyhow::Result<()> {
+        let mut visitor = UnitAnnotatingVisitor::new(registry(), ThinEdgeJsonSerializer::new());
+
+        visitor.measurement("humidity", 40.0)?;
+        visitor.start_group("location")?;
+        visitor.measurement("pressure", 98.0)?;
+        visitor.end_group()?;
+        visitor.measurement("speed", 12.0)?;
+
+        let expected_output = r#"{"humidity":40.0,"location":{"pressure":98.0},"speed":12.0}"#;
+        assert_eq!(visitor.into_inner().into_string()?, expected_output);
+        Ok(())
+    }
+
+    #[test]
+    fn missing_values_are_forwarded_unannotated() -> anyhow::Result<()> {
+        let mut visitor = UnitAnnotatingVisitor::new(registry(), ThinEdgeJsonSerializer::new());
+
+        visitor.nullable_measurement("temperature", Some(25.5))?;
+        visitor.nullable_measurement("pressure", None)?;
+
+        let expected_output = r#"{"temperature":{"value":25.5,"unit":"°C"},"pressure":null}"#;
+        assert_eq!(visitor.into_inner().into_string()?, expected_output);
+        Ok(())
+    }
+
+    #[test]
+    fn measurements_are_converted() -> anyhow::Result<()> {
+        let conversions = UnitConversions::new()
+            .with_conversion(
+                "location.alti",
+                UnitConversion {
+                    factor: 0.001,
+                    offset: 0.0,
+                    unit: Some("km".into()),
+                },
+            )
+            .with_conversion(
+                "pressure",
+                UnitConversion {
+                    factor: 10.0,
+                    offset: 0.0,
+                    unit: None,
+                },
+            );
+        let mut visitor = UnitConvertingVisitor::new(conversions, ThinEdgeJsonSerializer::new());
+
+        visitor.measurement("alti", 1000.0)?;
+        visitor.measurement_with_unit("pressure", 98.0, "hPa")?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2000.0)?;
+        visitor.end_group()?;
+
+        let expected_output = r#"{"alti":1000.0,"pressure":{"value":980.0,"unit":"hPa"},"location":{"alti":{"value":2.0,"unit":"km"}}}"#;
+        assert_eq!(visitor.into_inner().into_string()?, expected_output);
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use serde::Deserialize;
+use std::fmt;
+
+/// The key of the field giving the version of the format a thin-edge JSON message conforms to.
+pub const SCHEMA_VERSION_KEY: &str = "_schema";
+
+const SCHEMA_VERSION_PREFIX: &str = "te/";
+
+/// The version of the thin-edge JSON format, given as `"_schema":"te/<major>.<minor>"`.
+///
+/// A consumer can check the version of a message before processing it:
+/// a change of the minor version is expected to be backward compatible,
+/// while a change of the major version is not.
+///
+/// ```
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+/// use thin_edge_json::version::SchemaVersion;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let mut serializer = ThinEdgeJsonSerializer::new().with_schema_version(1, 2);
+/// serializer.measurement("temperature", 25.5)?;
+/// let payload = serializer.bytes()?;
+///
+/// assert_eq!(payload, br#"{"_schema":"te/1.2","temperature":25.5}"#);
+/// assert_eq!(SchemaVersion::parse_from_payload(&payload), Some((1, 2)));
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SchemaVersion {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl SchemaVersion {
+    pub fn new(major: u8, minor: u8) -> Self {
+        Self { major, minor }
+    }
+
+    /// Extract the version of a thin-edge JSON payload.
+    ///
+    /// Returns `None` if the payload is not a JSON object
+    /// or has no `_schema` field of the form `te/<major>.<minor>`.
+    pub fn parse_from_payload(payload: &[u8]) -> Option<(u8, u8)> {
+        #[derive(Deserialize)]
+        struct Versioned {
+            #[serde(rename = "_schema")]
+            schema: Option<String>,
+        }
+
+        let versioned: Versioned = serde_json::from_slice(payload).ok()?;
+        let version = SchemaVersion::parse(&versioned.schema?)?;
+        Some((version.major, version.minor))
+    }
+
+    /// Parse a version given as `te/<major>.<minor>`
+    pub fn parse(version: &str) -> Option<SchemaVersion> {
+        let version = version.strip_prefix(SCHEMA_VERSION_PREFIX)?;
+        let mut numbers = version.splitn(2, '.');
+        let major = numbers.next()?.parse().ok()?;
+        let minor = numbers.next()?.parse().ok()?;
+        Some(SchemaVersion { major, minor })
+    }
+}
+
+impl fmt::Display for SchemaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}.{}", SCHEMA_VERSION_PREFIX, self.major, self.minor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_formatted_with_the_te_prefix() {
+        assert_eq!(SchemaVersion::new(1, 0).to_string(), "te/1.0");
+        assert_eq!(SchemaVersion::new(2, 13).to_string(), "te/2.13");
+    }
+
+    #[test]
+    fn versions_are_parsed_from_payloads() {
+        assert_eq!(
+            SchemaVersion::parse_from_payload(br#"{"_schema":"te/1.2","temperature":25.5}"#),
+            Some((1, 2))
+        );
+        assert_eq!(
+            SchemaVersion::parse_from_payload(
+                br#"{"time":"2021-04-30T17:03:14+02:00","location":{"alti":2100.4},"_schema":"te/255.0"}"#
+            ),
+            Some((255, 0))
+        );
+    }
+
+    #[test]
+    fn payloads_with_no_valid_version_have_none() {
+        for payload in [
+            r#"{"temperature":25.5}"#,
+            r#"{"_schema":"te/1"}"#,
+            r#"{"_schema":"te/1.2.3"}"#,
+            r#"{"_schema":"te/256.0"}"#,
+            r#"{"_schema":"1.0"}"#,
+            r#"{"_schema":1.0}"#,
+            r#"[1.0]"#,
+            r#"{"_schema":"te/1.0""#,
+        ]
+        .iter()
+        {
+            assert_eq!(
+                SchemaVersion::parse_from_payload(payload.as_bytes()),
+                None,
+                "for {}",
+                payload
+            );
+        }
+    }
+}
@@ -0,0 +1,109 @@
+#![cfg(feature = "integration-test")]
+// These tests require a docker daemon to start an Elasticsearch node.
+// Run them by calling 'cargo test --features integration-test' from the base path of the crate
+
+use serde_json::{json, Value};
+use testcontainers::images::generic::{GenericImage, WaitFor};
+use testcontainers::{clients, Docker};
+use thin_edge_json::elasticsearch::ThinEdgeToElasticsearchBulkConverter;
+
+const INDEX_NAME: &str = "tedge-measurements";
+
+fn elasticsearch() -> GenericImage {
+    GenericImage::new("docker.elastic.co/elasticsearch/elasticsearch:7.12.1")
+        .with_env_var("discovery.type", "single-node")
+        .with_env_var("ES_JAVA_OPTS", "-Xms512m -Xmx512m")
+        .with_wait_for(WaitFor::message_on_stdout("\"message\": \"started\""))
+}
+
+/// Send a bulk request, refreshing the index so the documents are searchable on return
+async fn bulk_index(port: u16, body: String) -> anyhow::Result<Value> {
+    let response = reqwest::Client::new()
+        .post(&format!("http://localhost:{}/_bulk?refresh=true", port))
+        .header("Content-Type", "application/x-ndjson")
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(response.json().await?)
+}
+
+async fn search(port: u16, query: Value) -> anyhow::Result<Vec<Value>> {
+    let response: Value = reqwest::Client::new()
+        .post(&format!("http://localhost:{}/{}/_search", port, INDEX_NAME))
+        .json(&json!({ "query": query, "sort": ["@timestamp"] }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let hits = response["hits"]["hits"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    Ok(hits.into_iter().map(|hit| hit["_source"].clone()).collect())
+}
+
+#[tokio::test]
+async fn converted_payloads_are_indexed_by_elasticsearch() -> anyhow::Result<()> {
+    let docker = clients::Cli::default();
+    let node = docker.run(elasticsearch());
+    let port = node
+        .get_host_port(9200)
+        .expect("The Elasticsearch port is exposed");
+
+    let converter = ThinEdgeToElasticsearchBulkConverter::new(INDEX_NAME);
+    let payloads = [
+        r#"{"time":"2021-04-30T17:03:14.123+02:00","temperature":25.5}"#,
+        r#"{"time":"2021-04-30T17:04:14+02:00","temperature":26.0,"location":{"alti":2100.4}}"#,
+    ];
+
+    let response = bulk_index(port, converter.convert(&payloads)?).await?;
+
+    assert_eq!(response["errors"], json!(false), "{}", response);
+    assert_eq!(response["items"].as_array().map(Vec::len), Some(2));
+    assert_eq!(
+        search(port, json!({ "match_all": {} })).await?,
+        vec![
+            json!({"@timestamp": "2021-04-30T15:03:14.123Z", "temperature": 25.5}),
+            json!({
+                "@timestamp": "2021-04-30T15:04:14Z",
+                "temperature": 26.0,
+                "location": {"alti": 2100.4},
+            }),
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn the_timestamps_are_indexed_as_dates() -> anyhow::Result<()> {
+    let docker = clients::Cli::default();
+    let node = docker.run(elasticsearch());
+    let port = node
+        .get_host_port(9200)
+        .expect("The Elasticsearch port is exposed");
+
+    let converter = ThinEdgeToElasticsearchBulkConverter::new(INDEX_NAME);
+    let payloads = [
+        r#"{"time":"2021-04-30T17:03:14+02:00","temperature":25.5}"#,
+        r#"{"time":"2021-04-30T19:03:14+02:00","temperature":26.0}"#,
+    ];
+    let response = bulk_index(port, converter.convert(&payloads)?).await?;
+    assert_eq!(response["errors"], json!(false), "{}", response);
+
+    // A range query on UTC dates only matches if the time zone has been applied
+    let query = json!({
+        "range": {
+            "@timestamp": {
+                "gte": "2021-04-30T16:00:00Z",
+                "lt": "2021-04-30T18:00:00Z",
+            }
+        }
+    });
+    assert_eq!(
+        search(port, query).await?,
+        vec![json!({"@timestamp": "2021-04-30T17:03:14Z", "temperature": 26.0})]
+    );
+    Ok(())
+}
@@ -0,0 +1,33 @@
+use structopt::StructOpt;
+use thin_edge_json_tools::convert::{convert, Format};
+use thin_edge_json_tools::input::read_input;
+
+/// Convert measurements between thin-edge JSON and other formats.
+///
+/// The measurements are read from stdin and written to stdout.
+/// The supported formats are: thin_edge_json, csv, senml and influxdb_line_proto.
+#[derive(StructOpt, Debug)]
+#[structopt(name = "tedge-convert")]
+struct ConvertOpt {
+    /// The format of the input
+    #[structopt(long, default_value = "thin_edge_json")]
+    from: Format,
+
+    /// The format of the output
+    #[structopt(long)]
+    to: Format,
+
+    /// Indent the JSON output
+    #[structopt(long)]
+    pretty: bool,
+}
+
+fn main() -> anyhow::Result<()> {
+    let opt = ConvertOpt::from_args();
+
+    let input = read_input(None)?;
+    let output = convert(&input, opt.from, opt.to, opt.pretty)?;
+
+    print!("{}", output);
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use structopt::StructOpt;
+use thin_edge_json_tools::generate::{MeasurementSpec, ThinEdgeJsonGenerator};
+
+/// Generate thin-edge JSON payloads for manual testing.
+///
+/// The payloads are written to stdout, one per line.
+#[derive(StructOpt, Debug)]
+#[structopt(name = "tedge-gen")]
+struct GenerateOpt {
+    /// The measurements of the payloads, as `<name>=<value>,...`
+    #[structopt(long, required = true, use_delimiter = true)]
+    measurements: Vec<MeasurementSpec>,
+
+    /// Put the measurements in this group
+    #[structopt(long)]
+    group: Option<String>,
+
+    /// The timestamp of the payloads, in RFC 3339 format
+    #[structopt(long, parse(try_from_str = DateTime::parse_from_rfc3339))]
+    timestamp: Option<DateTime<FixedOffset>>,
+
+    /// The number of payloads
+    #[structopt(long, default_value = "1")]
+    count: usize,
+
+    /// The increment of the values from one payload to the next
+    #[structopt(long, default_value = "1.0", allow_hyphen_values = true)]
+    step: f64,
+}
+
+fn main() -> anyhow::Result<()> {
+    let opt = GenerateOpt::from_args();
+
+    let mut generator = ThinEdgeJsonGenerator::new(opt.measurements).with_step(opt.step);
+    if let Some(group) = &opt.group {
+        generator = generator.with_group(group);
+    }
+    if let Some(timestamp) = opt.timestamp {
+        generator = generator.with_timestamp(timestamp);
+    }
+
+    for index in 0..opt.count {
+        println!("{}", generator.payload(index)?);
+    }
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use std::path::PathBuf;
+use structopt::StructOpt;
+use thin_edge_json_tools::input::read_input;
+use thin_edge_json_tools::validate::{SchemaValidator, ThinEdgeJsonValidator};
+
+/// Check that a payload is valid thin-edge JSON.
+///
+/// The errors are reported on stderr and the command fails with exit code 1.
+#[derive(StructOpt, Debug)]
+#[structopt(name = "tedge-validate")]
+struct ValidateOpt {
+    /// The file to validate, stdin being read if none is given
+    #[structopt(parse(from_os_str))]
+    input: Option<PathBuf>,
+
+    /// Also check that the payload matches this JSON schema
+    #[structopt(long, parse(from_os_str))]
+    schema: Option<PathBuf>,
+
+    /// Print the canonical form of a valid payload
+    #[structopt(long)]
+    pretty: bool,
+}
+
+fn main() -> anyhow::Result<()> {
+    let opt = ValidateOpt::from_args();
+
+    let input = read_input(opt.input.as_deref())?;
+    let canonical = ThinEdgeJsonValidator::validate(&input)?;
+
+    if let Some(schema) = opt.schema {
+        SchemaValidator::from_file(&schema)?.validate(&canonical)?;
+    }
+
+    if opt.pretty {
+        println!("{}", ThinEdgeJsonValidator::pretty_print(&canonical));
+    }
+    Ok(())
+}
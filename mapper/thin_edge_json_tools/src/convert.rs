@@ -0,0 +1,155 @@
+use crate::validate::ThinEdgeJsonValidator;
+use std::str::FromStr;
+use thin_edge_json::csv::{CsvConverter, CsvError};
+use thin_edge_json::influxdb::{InfluxDbConverter, InfluxDbError};
+use thin_edge_json::json::{parse_str, ThinEdgeJsonParserError};
+use thin_edge_json::senml::{SenmlConverter, SenmlError};
+use thin_edge_json::serialize::{
+    MeasurementStreamError, ThinEdgeJsonSerializationError, ThinEdgeJsonSerializer,
+};
+use thin_edge_json::series::FlatMeasurementSeries;
+
+/// The formats measurements can be converted from and to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    ThinEdgeJson,
+    Csv,
+    Senml,
+    InfluxDbLineProtocol,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConversionError {
+    #[error("Unknown format {format:?}: expected one of thin_edge_json, csv, senml or influxdb_line_proto")]
+    UnknownFormat { format: String },
+
+    #[error("Invalid thin-edge JSON: {0}")]
+    InvalidThinEdgeJson(#[from] ThinEdgeJsonParserError<MeasurementStreamError>),
+
+    #[error(transparent)]
+    CsvError(#[from] CsvError),
+
+    #[error(transparent)]
+    SenmlError(#[from] SenmlError),
+
+    #[error(transparent)]
+    InfluxDbError(#[from] InfluxDbError),
+
+    #[error(transparent)]
+    SerializationError(#[from] ThinEdgeJsonSerializationError),
+}
+
+impl FromStr for Format {
+    type Err = ConversionError;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "thin_edge_json" => Ok(Format::ThinEdgeJson),
+            "csv" => Ok(Format::Csv),
+            "senml" => Ok(Format::Senml),
+            "influxdb_line_proto" => Ok(Format::InfluxDbLineProtocol),
+            _ => Err(ConversionError::UnknownFormat {
+                format: format.to_string(),
+            }),
+        }
+    }
+}
+
+impl Format {
+    pub fn parse(self, input: &str) -> Result<FlatMeasurementSeries, ConversionError> {
+        match self {
+            Format::ThinEdgeJson => {
+                let mut series = FlatMeasurementSeries::new();
+                let () = parse_str(input, &mut series)?;
+                Ok(series)
+            }
+            Format::Csv => Ok(CsvConverter::from_csv(input)?),
+            Format::Senml => Ok(SenmlConverter::from_senml(input)?),
+            Format::InfluxDbLineProtocol => Ok(InfluxDbConverter::from_line_protocol(input)?),
+        }
+    }
+
+    /// Format a series of measurements, the JSON formats being indented when `pretty` is set.
+    ///
+    /// The output always ends with a new line.
+    pub fn format(
+        self,
+        series: &FlatMeasurementSeries,
+        pretty: bool,
+    ) -> Result<String, ConversionError> {
+        match self {
+            Format::ThinEdgeJson => {
+                let mut serializer = ThinEdgeJsonSerializer::new();
+                series.visit(&mut serializer)?;
+                let json = serializer.into_string()?;
+                if pretty {
+                    Ok(ThinEdgeJsonValidator::pretty_print(&json) + "\n")
+                } else {
+                    Ok(json + "\n")
+                }
+            }
+            Format::Csv => Ok(CsvConverter::to_csv(series)),
+            Format::Senml if pretty => Ok(SenmlConverter::to_senml_pretty(series)? + "\n"),
+            Format::Senml => Ok(SenmlConverter::to_senml(series)? + "\n"),
+            Format::InfluxDbLineProtocol => Ok(InfluxDbConverter::to_line_protocol(series)),
+        }
+    }
+}
+
+/// Convert measurements from one format to another
+pub fn convert(
+    input: &str,
+    from: Format,
+    to: Format,
+    pretty: bool,
+) -> Result<String, ConversionError> {
+    let series = from.parse(input)?;
+    to.format(&series, pretty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    #[test]
+    fn parse_format_names() {
+        assert_matches!("thin_edge_json".parse(), Ok(Format::ThinEdgeJson));
+        assert_matches!("csv".parse(), Ok(Format::Csv));
+        assert_matches!("senml".parse(), Ok(Format::Senml));
+        assert_matches!(
+            "influxdb_line_proto".parse(),
+            Ok(Format::InfluxDbLineProtocol)
+        );
+        assert_matches!(
+            "xml".parse::<Format>(),
+            Err(ConversionError::UnknownFormat { .. })
+        );
+    }
+
+    #[test]
+    fn convert_between_non_json_formats() -> anyhow::Result<()> {
+        let csv = "time,group,name,value,unit\n,location,alti,2100.4,m\n";
+
+        assert_eq!(
+            convert(csv, Format::Csv, Format::InfluxDbLineProtocol, false)?,
+            "location alti=2100.4\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn json_output_can_be_pretty_printed() -> anyhow::Result<()> {
+        let input = r#"{"temperature":25.5}"#;
+
+        assert_eq!(
+            convert(input, Format::ThinEdgeJson, Format::ThinEdgeJson, true)?,
+            "{\n  \"temperature\": 25.5\n}\n"
+        );
+        assert_eq!(
+            convert(input, Format::ThinEdgeJson, Format::Senml, true)?,
+            "[\n  {\n    \"n\": \"temperature\",\n    \"v\": 25.5\n  }\n]\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use std::str::FromStr;
+use thin_edge_json::measurement::GroupedMeasurementVisitor;
+use thin_edge_json::serialize::{ThinEdgeJsonSerializationError, ThinEdgeJsonSerializer};
+
+#[derive(thiserror::Error, Debug)]
+pub enum GenerationError {
+    #[error("Invalid measurement {measurement:?}: expected <name>=<value>")]
+    InvalidMeasurement { measurement: String },
+
+    #[error("Invalid value for the measurement {name:?}: {value:?} is not a number")]
+    InvalidValue { name: String, value: String },
+
+    #[error(transparent)]
+    SerializationError(#[from] ThinEdgeJsonSerializationError),
+}
+
+/// A measurement given on the command line as `<name>=<value>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeasurementSpec {
+    pub name: String,
+    pub value: f64,
+}
+
+impl FromStr for MeasurementSpec {
+    type Err = GenerationError;
+
+    fn from_str(measurement: &str) -> Result<Self, Self::Err> {
+        let mut parts = measurement.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) if !name.trim().is_empty() => {
+                let name = name.trim().to_string();
+                let value = value.trim();
+                match value.parse::<f64>() {
+                    Ok(parsed) if parsed.is_finite() => Ok(MeasurementSpec {
+                        name,
+                        value: parsed,
+                    }),
+                    _ => Err(GenerationError::InvalidValue {
+                        name,
+                        value: value.to_string(),
+                    }),
+                }
+            }
+            _ => Err(GenerationError::InvalidMeasurement {
+                measurement: measurement.to_string(),
+            }),
+        }
+    }
+}
+
+/// Generate thin-edge JSON payloads from a set of measurements,
+/// the values being incremented by a step from one payload to the next.
+///
+/// ```
+/// use thin_edge_json_tools::generate::ThinEdgeJsonGenerator;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let generator = ThinEdgeJsonGenerator::new(vec!["temperature=25.5".parse()?])
+///     .with_group("engine")
+///     .with_step(0.5);
+///
+/// assert_eq!(
+///     generator.generate(2)?,
+///     vec![
+///         r#"{"engine":{"temperature":25.5}}"#,
+///         r#"{"engine":{"temperature":26.0}}"#,
+///     ]
+/// );
+/// # Ok(()) }
+/// ```
+pub struct ThinEdgeJsonGenerator {
+    measurements: Vec<MeasurementSpec>,
+    group: Option<String>,
+    timestamp: Option<DateTime<FixedOffset>>,
+    step: f64,
+}
+
+impl ThinEdgeJsonGenerator {
+    pub fn new(measurements: Vec<MeasurementSpec>) -> Self {
+        Self {
+            measurements,
+            group: None,
+            timestamp: None,
+            step: 1.0,
+        }
+    }
+
+    /// Put all the measurements in the given group
+    pub fn with_group(self, group: &str) -> Self {
+        Self {
+            group: Some(group.to_string()),
+            ..self
+        }
+    }
+
+    /// Set the timestamp of all the payloads
+    pub fn with_timestamp(self, timestamp: DateTime<FixedOffset>) -> Self {
+        Self {
+            timestamp: Some(timestamp),
+            ..self
+        }
+    }
+
+    /// Set the increment of the values from one payload to the next, 1.0 by default
+    pub fn with_step(self, step: f64) -> Self {
+        Self { step, ..self }
+    }
+
+    /// The payload at the given position, starting at 0 with the initial values
+    pub fn payload(&self, index: usize) -> Result<String, GenerationError> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        if let Some(timestamp) = self.timestamp {
+            serializer.timestamp(timestamp)?;
+        }
+        if let Some(group) = &self.group {
+            serializer.start_group(group)?;
+        }
+        for measurement in self.measurements.iter() {
+            let value = measurement.value + self.step * index as f64;
+            serializer.measurement(&measurement.name, value)?;
+        }
+        if self.group.is_some() {
+            serializer.end_group()?;
+        }
+        Ok(serializer.into_string()?)
+    }
+
+    /// The first `count` payloads
+    pub fn generate(&self, count: usize) -> Result<Vec<String>, GenerationError> {
+        (0..count).map(|index| self.payload(index)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    #[test]
+    fn parse_measurements() {
+        assert_matches!(
+            "temperature=25.5".parse::<MeasurementSpec>(),
+            Ok(MeasurementSpec { name, value }) if name == "temperature" && value == 25.5
+        );
+        assert_matches!(
+            "temperature".parse::<MeasurementSpec>(),
+            Err(GenerationError::InvalidMeasurement { .. })
+        );
+        assert_matches!(
+            "=25.5".parse::<MeasurementSpec>(),
+            Err(GenerationError::InvalidMeasurement { .. })
+        );
+        assert_matches!(
+            "temperature=hot".parse::<MeasurementSpec>(),
+            Err(GenerationError::InvalidValue { .. })
+        );
+        assert_matches!(
+            "temperature=NaN".parse::<MeasurementSpec>(),
+            Err(GenerationError::InvalidValue { .. })
+        );
+    }
+
+    #[test]
+    fn values_are_incremented_by_the_step() -> anyhow::Result<()> {
+        let generator =
+            ThinEdgeJsonGenerator::new(vec!["temperature=25.5".parse()?, "pressure=98".parse()?])
+                .with_step(-0.5);
+
+        assert_eq!(
+            generator.generate(3)?,
+            vec![
+                r#"{"temperature":25.5,"pressure":98.0}"#,
+                r#"{"temperature":25.0,"pressure":97.5}"#,
+                r#"{"temperature":24.5,"pressure":97.0}"#,
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn payloads_can_be_timestamped() -> anyhow::Result<()> {
+        let timestamp = DateTime::parse_from_rfc3339("2021-04-08T10:00:00+02:00")?;
+        let generator =
+            ThinEdgeJsonGenerator::new(vec!["alti=2100.5".parse()?]).with_timestamp(timestamp);
+
+        assert_eq!(
+            generator.payload(1)?,
+            r#"{"time":"2021-04-08T10:00:00+02:00","alti":2101.5}"#
+        );
+        assert!(generator.generate(0)?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,28 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+#[derive(thiserror::Error, Debug)]
+pub enum InputError {
+    #[error("Failed to read {path:?}: {from}")]
+    FileReadError { path: PathBuf, from: std::io::Error },
+
+    #[error("Failed to read stdin: {0}")]
+    StdinReadError(#[source] std::io::Error),
+}
+
+/// Read the whole content of the given file, or of stdin if no file is given.
+pub fn read_input(path: Option<&Path>) -> Result<String, InputError> {
+    match path {
+        Some(path) => std::fs::read_to_string(path).map_err(|from| InputError::FileReadError {
+            path: path.into(),
+            from,
+        }),
+        None => {
+            let mut input = String::new();
+            std::io::stdin()
+                .read_to_string(&mut input)
+                .map_err(InputError::StdinReadError)?;
+            Ok(input)
+        }
+    }
+}
@@ -0,0 +1,10 @@
+//! Command line tools to process thin-edge JSON payloads.
+//!
+//! * `tedge-validate` checks that a payload is valid thin-edge JSON.
+//! * `tedge-convert` converts measurements between thin-edge JSON and other formats.
+//! * `tedge-gen` generates thin-edge JSON payloads for manual testing.
+
+pub mod convert;
+pub mod generate;
+pub mod input;
+pub mod validate;
@@ -0,0 +1,197 @@
+use crate::input::{read_input, InputError};
+use jsonschema::JSONSchema;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use thin_edge_json::json::{parse_str, ThinEdgeJson, ThinEdgeJsonError, ThinEdgeJsonParserError};
+use thin_edge_json::serialize::{ThinEdgeJsonSerializationError, ThinEdgeJsonSerializer};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ValidationError {
+    #[error("Invalid thin-edge JSON: {0}")]
+    InvalidThinEdgeJson(#[from] ThinEdgeJsonParserError<ThinEdgeJsonError>),
+
+    #[error(transparent)]
+    SerializationError(#[from] ThinEdgeJsonParserError<ThinEdgeJsonSerializationError>),
+
+    #[error(transparent)]
+    SchemaReadError(#[from] InputError),
+
+    #[error("Invalid JSON schema {path:?}: {reason}")]
+    InvalidSchema { path: PathBuf, reason: String },
+
+    #[error("The payload doesn't match the schema {path:?}:\n{}", .errors.join("\n"))]
+    SchemaMismatch { path: PathBuf, errors: Vec<String> },
+}
+
+/// Check that payloads are valid thin-edge JSON.
+pub struct ThinEdgeJsonValidator;
+
+impl ThinEdgeJsonValidator {
+    /// Validate a thin-edge JSON payload, returning its canonical form.
+    ///
+    /// The canonical form is the compact payload produced by the `ThinEdgeJsonSerializer`,
+    /// with the measurements in the order of the input and all the numbers as floats.
+    ///
+    /// ```
+    /// use thin_edge_json_tools::validate::ThinEdgeJsonValidator;
+    ///
+    /// # fn main() -> Result<(), anyhow::Error> {
+    /// let canonical = ThinEdgeJsonValidator::validate(r#"{ "temperature": 25 }"#)?;
+    ///
+    /// assert_eq!(canonical, r#"{"temperature":25.0}"#);
+    /// assert!(ThinEdgeJsonValidator::validate(r#"{ "temperature": "hot" }"#).is_err());
+    /// # Ok(()) }
+    /// ```
+    pub fn validate(input: &str) -> Result<String, ValidationError> {
+        let _ = ThinEdgeJson::from_str(input)?;
+
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        let () = parse_str(input, &mut serializer)?;
+        let canonical = serializer
+            .into_string()
+            .map_err(ThinEdgeJsonParserError::VisitorError)?;
+        Ok(canonical)
+    }
+
+    /// Indent a canonical payload, keeping the measurements in order
+    pub fn pretty_print(canonical: &str) -> String {
+        json::parse(canonical)
+            .expect("A canonical payload is valid JSON")
+            .pretty(2)
+    }
+}
+
+/// Check that thin-edge JSON payloads match a JSON schema,
+/// as those produced by `ThinEdgeJsonSchema`.
+pub struct SchemaValidator {
+    path: PathBuf,
+    schema: Value,
+}
+
+impl SchemaValidator {
+    pub fn from_file(path: &Path) -> Result<Self, ValidationError> {
+        let invalid_schema = |reason: String| ValidationError::InvalidSchema {
+            path: path.into(),
+            reason,
+        };
+
+        let schema: Value = serde_json::from_str(&read_input(Some(path))?)
+            .map_err(|err| invalid_schema(err.to_string()))?;
+        let _ = JSONSchema::compile(&schema).map_err(|err| invalid_schema(err.to_string()))?;
+
+        Ok(SchemaValidator {
+            path: path.into(),
+            schema,
+        })
+    }
+
+    /// Validate a payload, which is expected to be valid JSON, against the schema
+    pub fn validate(&self, payload: &str) -> Result<(), ValidationError> {
+        let payload: Value =
+            serde_json::from_str(payload).map_err(|err| ValidationError::SchemaMismatch {
+                path: self.path.clone(),
+                errors: vec![err.to_string()],
+            })?;
+        let schema = JSONSchema::compile(&self.schema).expect("A schema checked on load");
+
+        let result = schema.validate(&payload);
+        if let Err(errors) = result {
+            let errors = errors.map(|err| err.to_string()).collect();
+            return Err(ValidationError::SchemaMismatch {
+                path: self.path.clone(),
+                errors,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    const SCHEMA: &str = r#"{
+        "type": "object",
+        "properties": { "temperature": { "type": "number", "maximum": 100 } },
+        "required": ["temperature"]
+    }"#;
+
+    fn schema_file(content: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn canonical_form_uses_floats_and_no_spaces() -> anyhow::Result<()> {
+        let input = r#"{
+            "time": "2021-04-30T17:03:14+02:00",
+            "temperature": 25,
+            "location": { "alti": 2100.4 }
+        }"#;
+
+        assert_eq!(
+            ThinEdgeJsonValidator::validate(input)?,
+            r#"{"time":"2021-04-30T17:03:14+02:00","temperature":25.0,"location":{"alti":2100.4}}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_payloads() {
+        assert_matches!(
+            ThinEdgeJsonValidator::validate(r#"{"temperature":"hot"}"#),
+            Err(ValidationError::InvalidThinEdgeJson(_))
+        );
+        assert_matches!(
+            ThinEdgeJsonValidator::validate(r#"{}"#),
+            Err(ValidationError::InvalidThinEdgeJson(_))
+        );
+        assert_matches!(
+            ThinEdgeJsonValidator::validate(r#"{"temperature":"#),
+            Err(ValidationError::InvalidThinEdgeJson(_))
+        );
+    }
+
+    #[test]
+    fn pretty_print_keeps_the_measurement_order() {
+        let pretty = ThinEdgeJsonValidator::pretty_print(r#"{"temperature":25.0,"alti":2100.4}"#);
+
+        assert_eq!(pretty, "{\n  \"temperature\": 25.0,\n  \"alti\": 2100.4\n}");
+    }
+
+    #[test]
+    fn payloads_are_checked_against_the_schema() -> anyhow::Result<()> {
+        let file = schema_file(SCHEMA);
+        let validator = SchemaValidator::from_file(file.path())?;
+
+        assert_matches!(validator.validate(r#"{"temperature":25.0}"#), Ok(()));
+        assert_matches!(
+            validator.validate(r#"{"temperature":125.0}"#),
+            Err(ValidationError::SchemaMismatch { errors, .. }) if errors.len() == 1
+        );
+        assert_matches!(
+            validator.validate(r#"{"pressure":98.0}"#),
+            Err(ValidationError::SchemaMismatch { .. })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_schemas() {
+        let not_json = schema_file(r#"{"type":"#);
+        let not_a_schema = schema_file(r#"{"type":"not-a-type"}"#);
+
+        assert_matches!(
+            SchemaValidator::from_file(not_json.path()),
+            Err(ValidationError::InvalidSchema { .. })
+        );
+        assert_matches!(
+            SchemaValidator::from_file(not_a_schema.path()),
+            Err(ValidationError::InvalidSchema { .. })
+        );
+    }
+}
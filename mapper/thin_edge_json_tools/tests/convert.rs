@@ -0,0 +1,79 @@
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::path::PathBuf;
+
+const FORMATS: [(&str, &str); 3] = [
+    ("csv", "measurements.csv"),
+    ("senml", "measurements.senml.json"),
+    ("influxdb_line_proto", "measurements.influx"),
+];
+
+fn fixture(name: &str) -> String {
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "fixtures", name]
+        .iter()
+        .collect();
+    std::fs::read_to_string(&path).unwrap_or_else(|_| panic!("Missing fixture {:?}", path))
+}
+
+fn tedge_convert(from: &str, to: &str) -> Result<Command, Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("tedge-convert")?;
+    cmd.args(&["--from", from, "--to", to]);
+    Ok(cmd)
+}
+
+#[test]
+fn convert_thin_edge_json_to_each_format() -> Result<(), Box<dyn std::error::Error>> {
+    for (format, expected) in FORMATS.iter() {
+        tedge_convert("thin_edge_json", format)?
+            .write_stdin(fixture("measurements.json"))
+            .assert()
+            .success()
+            .stdout(fixture(expected));
+    }
+    Ok(())
+}
+
+#[test]
+fn convert_each_format_to_thin_edge_json() -> Result<(), Box<dyn std::error::Error>> {
+    for (format, input) in FORMATS.iter() {
+        tedge_convert(format, "thin_edge_json")?
+            .write_stdin(fixture(input))
+            .assert()
+            .success()
+            .stdout(fixture("measurements.json"));
+    }
+    Ok(())
+}
+
+#[test]
+fn json_output_can_be_pretty_printed() -> Result<(), Box<dyn std::error::Error>> {
+    tedge_convert("csv", "thin_edge_json")?
+        .arg("--pretty")
+        .write_stdin("time,group,name,value,unit\n,,temperature,25.5,\n")
+        .assert()
+        .success()
+        .stdout("{\n  \"temperature\": 25.5\n}\n");
+    Ok(())
+}
+
+#[test]
+fn unknown_formats_are_rejected() -> Result<(), Box<dyn std::error::Error>> {
+    tedge_convert("thin_edge_json", "xml")?
+        .write_stdin(fixture("measurements.json"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Unknown format \"xml\""));
+    Ok(())
+}
+
+#[test]
+fn invalid_inputs_are_reported() -> Result<(), Box<dyn std::error::Error>> {
+    tedge_convert("senml", "csv")?
+        .write_stdin(r#"[{"n": "status", "vs": "on"}]"#)
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "only numeric values are supported",
+        ));
+    Ok(())
+}
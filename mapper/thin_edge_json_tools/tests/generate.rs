@@ -0,0 +1,71 @@
+use assert_cmd::Command;
+use predicates::prelude::*;
+use thin_edge_json_tools::validate::ThinEdgeJsonValidator;
+
+fn tedge_gen(args: &[&str]) -> Result<Command, Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("tedge-gen")?;
+    cmd.args(args);
+    Ok(cmd)
+}
+
+#[test]
+fn generated_payloads_are_valid_thin_edge_json() -> Result<(), Box<dyn std::error::Error>> {
+    let output = tedge_gen(&[
+        "--measurements",
+        "temperature=25.5,pressure=98",
+        "--group",
+        "engine",
+        "--timestamp",
+        "2021-04-08T10:00:00+02:00",
+        "--count",
+        "3",
+        "--step",
+        "0.5",
+    ])?
+    .output()?;
+
+    assert!(output.status.success());
+    let payloads: Vec<String> = String::from_utf8(output.stdout)?
+        .lines()
+        .map(|line| line.to_string())
+        .collect();
+    assert_eq!(payloads.len(), 3);
+    for payload in payloads.iter() {
+        assert_eq!(&ThinEdgeJsonValidator::validate(payload)?, payload);
+    }
+    assert_eq!(
+        payloads[2],
+        r#"{"time":"2021-04-08T10:00:00+02:00","engine":{"temperature":26.5,"pressure":99.0}}"#
+    );
+    Ok(())
+}
+
+#[test]
+fn a_single_payload_is_generated_by_default() -> Result<(), Box<dyn std::error::Error>> {
+    tedge_gen(&["--measurements", "temperature=25.5"])?
+        .assert()
+        .success()
+        .stdout("{\"temperature\":25.5}\n");
+    Ok(())
+}
+
+#[test]
+fn no_payload_is_generated_for_a_zero_count() -> Result<(), Box<dyn std::error::Error>> {
+    tedge_gen(&["--measurements", "temperature=25.5", "--count", "0"])?
+        .assert()
+        .success()
+        .stdout("")
+        .stderr("");
+    Ok(())
+}
+
+#[test]
+fn invalid_measurements_are_rejected() -> Result<(), Box<dyn std::error::Error>> {
+    tedge_gen(&["--measurements", "temperature=hot"])?
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            r#"Invalid value for the measurement "temperature""#,
+        ));
+    Ok(())
+}
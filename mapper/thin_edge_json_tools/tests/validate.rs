@@ -0,0 +1,115 @@
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::io::Write;
+use tempfile::NamedTempFile;
+
+fn tedge_validate<I, S>(args: I) -> Result<Command, Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+{
+    let mut cmd = Command::cargo_bin("tedge-validate")?;
+    cmd.args(args);
+    Ok(cmd)
+}
+
+fn temp_file(content: &str) -> Result<NamedTempFile, Box<dyn std::error::Error>> {
+    let mut file = NamedTempFile::new()?;
+    file.write_all(content.as_bytes())?;
+    Ok(file)
+}
+
+#[test]
+fn valid_payloads_are_accepted() -> Result<(), Box<dyn std::error::Error>> {
+    tedge_validate(&[] as &[&str])?
+        .write_stdin(r#"{"temperature": 25.5, "location": {"alti": 2100.4}}"#)
+        .assert()
+        .success()
+        .stdout("")
+        .stderr("");
+    Ok(())
+}
+
+#[test]
+fn valid_payloads_are_printed_in_canonical_form() -> Result<(), Box<dyn std::error::Error>> {
+    let file = temp_file(r#"{ "temperature" : 25, "pressure": 98.0 }"#)?;
+
+    tedge_validate(&[file.path().as_os_str(), "--pretty".as_ref()])?
+        .assert()
+        .success()
+        .stdout("{\n  \"temperature\": 25.0,\n  \"pressure\": 98.0\n}\n");
+    Ok(())
+}
+
+#[test]
+fn invalid_values_are_reported() -> Result<(), Box<dyn std::error::Error>> {
+    tedge_validate(&[] as &[&str])?
+        .write_stdin(r#"{"temperature": "hot"}"#)
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(predicate::str::contains(
+            r#"Not a number: the "temperature" value must be a number, not string."#,
+        ));
+    Ok(())
+}
+
+#[test]
+fn invalid_json_is_reported() -> Result<(), Box<dyn std::error::Error>> {
+    tedge_validate(&[] as &[&str])?
+        .write_stdin(r#"{"temperature": 25.5"#)
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("Invalid JSON"));
+    Ok(())
+}
+
+#[test]
+fn empty_payloads_are_reported() -> Result<(), Box<dyn std::error::Error>> {
+    tedge_validate(&[] as &[&str])?
+        .write_stdin("{}")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "Empty Thin Edge measurement: it must contain at least one measurement",
+        ));
+    Ok(())
+}
+
+#[test]
+fn missing_files_are_reported() -> Result<(), Box<dyn std::error::Error>> {
+    tedge_validate(&["/does/not/exist.json"])?
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "Failed to read \"/does/not/exist.json\"",
+        ));
+    Ok(())
+}
+
+#[test]
+fn payloads_are_checked_against_a_schema() -> Result<(), Box<dyn std::error::Error>> {
+    let schema = temp_file(
+        r#"{
+            "type": "object",
+            "properties": { "temperature": { "type": "number" } },
+            "required": ["temperature"],
+            "additionalProperties": false
+        }"#,
+    )?;
+    let schema_arg = format!("--schema={}", schema.path().display());
+
+    tedge_validate(&[&schema_arg])?
+        .write_stdin(r#"{"temperature": 25.5}"#)
+        .assert()
+        .success();
+
+    tedge_validate(&[&schema_arg])?
+        .write_stdin(r#"{"temperature": 25.5, "pressure": 98.0}"#)
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "The payload doesn't match the schema",
+        ));
+    Ok(())
+}
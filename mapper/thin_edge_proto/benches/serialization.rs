@@ -0,0 +1,104 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use thin_edge_json::json::parse_str;
+use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+use thin_edge_proto::serializer::ThinEdgeProtoSerializer;
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    serialize_reference_measurement(c);
+    serialize_50_measurements(c);
+    serialize_17x3_multi_measurements(c);
+}
+
+const REFERENCE_THIN_EDGE_JSON: &str = r#"{
+            "time": "2021-06-22T17:03:14.123456789+05:00",
+            "temperature": 25.01,
+            "location": {
+                  "latitude": 32.54,
+                  "longitude": -117.67,
+                  "altitude": 98.6
+              },
+            "pressure": 98.01
+        }"#;
+
+fn serialize_reference_measurement(c: &mut Criterion) {
+    compare("reference measurement", REFERENCE_THIN_EDGE_JSON, c);
+}
+
+fn serialize_50_measurements(c: &mut Criterion) {
+    compare("50 measurements", &flat_message(50), c);
+}
+
+fn serialize_17x3_multi_measurements(c: &mut Criterion) {
+    compare("17x3 multi-measurements", &group_message(17, 3), c);
+}
+
+/// Compare the time to serialize a message and the size of the output for both formats
+fn compare(id: &str, message: &str, c: &mut Criterion) {
+    let json_size = to_json(message).len();
+    let proto_size = to_proto(message).len();
+    println!(
+        "Serialized {}: JSON = {} bytes, ProtoBuf = {} bytes ({:.0}%)",
+        id,
+        json_size,
+        proto_size,
+        100.0 * proto_size as f64 / json_size as f64
+    );
+
+    let mut group = c.benchmark_group(id);
+    group.bench_function("JSON", |b| b.iter(|| to_json(message)));
+    group.bench_function("ProtoBuf", |b| b.iter(|| to_proto(message)));
+    group.finish();
+}
+
+fn to_json(message: &str) -> Vec<u8> {
+    let mut serializer = ThinEdgeJsonSerializer::new();
+    parse_str(message, &mut serializer).expect("Expect a valid thin-edge-json message");
+    serializer
+        .bytes()
+        .expect("Expect a complete series of measurements")
+}
+
+fn to_proto(message: &str) -> Vec<u8> {
+    let mut serializer = ThinEdgeProtoSerializer::new();
+    parse_str(message, &mut serializer).expect("Expect a valid thin-edge-json message");
+    serializer
+        .bytes()
+        .expect("Expect a complete series of measurements")
+}
+
+fn flat_message(n: u64) -> String {
+    let mut message = String::with_capacity(5000);
+    let mut sep = "{";
+    for i in 0..n {
+        message.push_str(&format!("{}\n\t\"measurement_{}\" : {}", sep, i, i * 10));
+        sep = ","
+    }
+    message.push_str("\n}");
+    message
+}
+
+fn group_message(n_grp: u64, n_per_grp: u64) -> String {
+    let mut message = String::with_capacity(5000);
+    let mut sep = "{";
+    for i in 0..n_grp {
+        message.push_str(&format!("{}\n\t\"group_{}\" : {{", sep, i));
+        sep = "";
+        for j in 0..n_per_grp {
+            message.push_str(&format!(
+                "{}\n\t\"measurement_{}_{}\" : {}",
+                sep,
+                i,
+                j,
+                i * j
+            ));
+            sep = ","
+        }
+        message.push_str("\n\t}");
+        sep = ","
+    }
+    message.push_str("\n}");
+    message
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
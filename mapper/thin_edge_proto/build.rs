@@ -0,0 +1,8 @@
+fn main() -> std::io::Result<()> {
+    println!("cargo:rerun-if-changed=thin_edge_measurements.proto");
+    println!("cargo:rerun-if-changed=sparkplug_b.proto");
+    prost_build::compile_protos(
+        &["thin_edge_measurements.proto", "sparkplug_b.proto"],
+        &["."],
+    )
+}
@@ -0,0 +1,321 @@
+use crate::proto;
+use chrono::offset::FixedOffset;
+use chrono::{DateTime, TimeZone};
+use prost::Message;
+use std::convert::TryInto;
+use thin_edge_json::context::MeasurementContext;
+use thin_edge_json::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+
+/// Decode a ProtoBuf `MeasurementSeries`, driving a `GroupedMeasurementVisitor`.
+pub struct ThinEdgeProtoDeserializer;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ThinEdgeProtoParserError<T: std::error::Error + std::fmt::Debug + 'static> {
+    #[error("Invalid ProtoBuf measurement series: {0}")]
+    InvalidProtoBuf(#[from] prost::DecodeError),
+
+    #[error(
+        "Invalid timestamp: {seconds}s and {nanos}ns with a UTC offset of {utc_offset_seconds}s"
+    )]
+    InvalidTimestamp {
+        seconds: i64,
+        nanos: u32,
+        utc_offset_seconds: i32,
+    },
+
+    #[error(
+        "Invalid trace context: a trace id has 16 bytes and a span id 8 bytes, not {trace_id_len} and {span_id_len}"
+    )]
+    InvalidContext {
+        trace_id_len: usize,
+        span_id_len: usize,
+    },
+
+    #[error(transparent)]
+    VisitorError(T),
+}
+
+impl ThinEdgeProtoDeserializer {
+    pub fn parse<V>(bytes: &[u8], visitor: &mut V) -> Result<(), ThinEdgeProtoParserError<V::Error>>
+    where
+        V: GroupedMeasurementVisitor,
+    {
+        let series = proto::MeasurementSeries::decode(bytes)?;
+        ThinEdgeProtoDeserializer::visit(&series, visitor)
+    }
+
+    pub fn visit<V>(
+        series: &proto::MeasurementSeries,
+        visitor: &mut V,
+    ) -> Result<(), ThinEdgeProtoParserError<V::Error>>
+    where
+        V: GroupedMeasurementVisitor,
+    {
+        if let Some(context) = series.context.as_ref() {
+            visitor
+                .set_context(parse_context(context)?)
+                .map_err(ThinEdgeProtoParserError::VisitorError)?;
+        }
+        if let Some(timestamp) = series.timestamp.as_ref() {
+            visitor
+                .timestamp(parse_timestamp(timestamp)?)
+                .map_err(ThinEdgeProtoParserError::VisitorError)?;
+        }
+
+        for entry in series.entries.iter().filter_map(|e| e.entry.as_ref()) {
+            match entry {
+                proto::entry::Entry::Measurement(measurement) => {
+                    visit_measurement(measurement, visitor)?;
+                }
+                proto::entry::Entry::Group(group) => {
+                    match group.timestamp.as_ref() {
+                        Some(timestamp) => visitor
+                            .start_group_with_timestamp(&group.name, parse_timestamp(timestamp)?),
+                        None => visitor.start_group(&group.name),
+                    }
+                    .map_err(ThinEdgeProtoParserError::VisitorError)?;
+                    for measurement in group.measurements.iter() {
+                        visit_measurement(measurement, visitor)?;
+                    }
+                    visitor
+                        .end_group()
+                        .map_err(ThinEdgeProtoParserError::VisitorError)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn visit_measurement<V>(
+    measurement: &proto::Measurement,
+    visitor: &mut V,
+) -> Result<(), ThinEdgeProtoParserError<V::Error>>
+where
+    V: GroupedMeasurementVisitor,
+{
+    let name = &measurement.name;
+    let value = measurement.value;
+    let quality = match measurement.quality() {
+        proto::Quality::Unspecified => None,
+        proto::Quality::Good => Some(MeasurementQuality::Good),
+        proto::Quality::Bad => Some(MeasurementQuality::Bad),
+        proto::Quality::Uncertain => Some(MeasurementQuality::Uncertain),
+    };
+
+    let result = if measurement.missing {
+        visitor.nullable_measurement(name, None)
+    } else if let Some(imaginary) = measurement.imaginary.as_ref() {
+        visitor.complex_measurement(name, value, imaginary.value)
+    } else if let Some(quality) = quality {
+        visitor.annotated_measurement(name, value, quality)
+    } else if measurement.unit.is_empty() {
+        visitor.measurement(name, value)
+    } else {
+        visitor.measurement_with_unit(name, value, &measurement.unit)
+    };
+    result.map_err(ThinEdgeProtoParserError::VisitorError)
+}
+
+fn parse_context<E>(
+    context: &proto::Context,
+) -> Result<MeasurementContext, ThinEdgeProtoParserError<E>>
+where
+    E: std::error::Error + std::fmt::Debug + 'static,
+{
+    match (
+        context.trace_id.as_slice().try_into(),
+        context.span_id.as_slice().try_into(),
+    ) {
+        (Ok(trace_id), Ok(span_id)) => Ok(MeasurementContext {
+            trace_id,
+            span_id,
+            baggage: context.baggage.clone().into_iter().collect(),
+        }),
+        _ => Err(ThinEdgeProtoParserError::InvalidContext {
+            trace_id_len: context.trace_id.len(),
+            span_id_len: context.span_id.len(),
+        }),
+    }
+}
+
+fn parse_timestamp<E>(
+    timestamp: &proto::Timestamp,
+) -> Result<DateTime<FixedOffset>, ThinEdgeProtoParserError<E>>
+where
+    E: std::error::Error + std::fmt::Debug + 'static,
+{
+    FixedOffset::east_opt(timestamp.utc_offset_seconds)
+        .and_then(|offset| {
+            offset
+                .timestamp_opt(timestamp.seconds, timestamp.nanos)
+                .single()
+        })
+        .ok_or(ThinEdgeProtoParserError::InvalidTimestamp {
+            seconds: timestamp.seconds,
+            nanos: timestamp.nanos,
+            utc_offset_seconds: timestamp.utc_offset_seconds,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serializer::ThinEdgeProtoSerializer;
+    use assert_matches::assert_matches;
+    use pretty_assertions::assert_eq;
+    use thin_edge_json::buffer::MeasurementBuffer;
+    use thin_edge_json::json::parse_str;
+    use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+    use thin_edge_json::trace::VisitorCall;
+
+    fn round_trip(thin_edge_json: &str) -> anyhow::Result<String> {
+        let mut proto = ThinEdgeProtoSerializer::new();
+        parse_str(thin_edge_json, &mut proto)?;
+        let bytes = proto.bytes()?;
+
+        let mut json = ThinEdgeJsonSerializer::new();
+        ThinEdgeProtoDeserializer::parse(&bytes, &mut json)?;
+        Ok(json.into_string()?)
+    }
+
+    #[test]
+    fn round_trip_measurements_and_groups() -> anyhow::Result<()> {
+        let input = r#"{"time":"2021-04-30T17:03:14.123+02:00","temperature":25.5,"location":{"alti":2100.4,"longi":-2200.4},"pressure":98.0}"#;
+
+        assert_eq!(round_trip(input)?, input);
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip_units() -> anyhow::Result<()> {
+        let mut proto = ThinEdgeProtoSerializer::new();
+        proto.measurement_with_unit("temperature", 25.5, "°C")?;
+        let bytes = proto.bytes()?;
+
+        let mut json = ThinEdgeJsonSerializer::new();
+        ThinEdgeProtoDeserializer::parse(&bytes, &mut json)?;
+
+        assert_eq!(
+            json.into_string()?,
+            r#"{"temperature":{"value":25.5,"unit":"°C"}}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip_all_kinds_of_measurements() -> anyhow::Result<()> {
+        let calls = vec![
+            VisitorCall::NullableMeasurement {
+                name: "temperature".into(),
+                value: None,
+            },
+            VisitorCall::AnnotatedMeasurement {
+                name: "pressure".into(),
+                value: 98.0,
+                quality: MeasurementQuality::Bad,
+            },
+            VisitorCall::StartGroupWithTimestamp {
+                group: "phase".into(),
+                value: FixedOffset::east(2 * 3600)
+                    .ymd(2021, 4, 30)
+                    .and_hms(17, 3, 14),
+            },
+            VisitorCall::ComplexMeasurement {
+                name: "current".into(),
+                real: 1.5,
+                imaginary: -0.5,
+            },
+            VisitorCall::EndGroup,
+        ];
+
+        let mut proto = ThinEdgeProtoSerializer::new();
+        for call in calls.iter() {
+            call.apply(&mut proto)?;
+        }
+        let bytes = proto.bytes()?;
+
+        let mut buffer = MeasurementBuffer::new();
+        ThinEdgeProtoDeserializer::parse(&bytes, &mut buffer)?;
+
+        assert_eq!(buffer.into_events(), calls);
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip_trace_context() -> anyhow::Result<()> {
+        let context =
+            MeasurementContext::new([0x4b; 16], [0xf0; 8]).with_baggage("site", "plant-7");
+        let mut proto = ThinEdgeProtoSerializer::new();
+        proto.set_context(context.clone())?;
+        proto.measurement("temperature", 25.5)?;
+        let bytes = proto.bytes()?;
+
+        let mut buffer = MeasurementBuffer::new();
+        ThinEdgeProtoDeserializer::parse(&bytes, &mut buffer)?;
+
+        assert_eq!(
+            buffer.into_events(),
+            vec![
+                VisitorCall::SetContext { context },
+                VisitorCall::Measurement {
+                    name: "temperature".into(),
+                    value: 25.5
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_trace_context() {
+        let series = proto::MeasurementSeries {
+            context: Some(proto::Context {
+                trace_id: vec![0x4b; 8],
+                span_id: vec![0xf0; 8],
+                ..proto::Context::default()
+            }),
+            ..proto::MeasurementSeries::default()
+        };
+        let mut json = ThinEdgeJsonSerializer::new();
+
+        let result = ThinEdgeProtoDeserializer::visit(&series, &mut json);
+
+        assert_matches!(
+            result,
+            Err(ThinEdgeProtoParserError::InvalidContext {
+                trace_id_len: 8,
+                span_id_len: 8
+            })
+        );
+    }
+
+    #[test]
+    fn reject_invalid_protobuf() {
+        let mut json = ThinEdgeJsonSerializer::new();
+
+        let result = ThinEdgeProtoDeserializer::parse(&[0xff, 0xff, 0xff], &mut json);
+
+        assert_matches!(result, Err(ThinEdgeProtoParserError::InvalidProtoBuf(_)));
+    }
+
+    #[test]
+    fn reject_invalid_utc_offsets() {
+        let series = proto::MeasurementSeries {
+            timestamp: Some(proto::Timestamp {
+                seconds: 0,
+                nanos: 0,
+                utc_offset_seconds: 48 * 3600,
+            }),
+            ..proto::MeasurementSeries::default()
+        };
+        let mut json = ThinEdgeJsonSerializer::new();
+
+        let result = ThinEdgeProtoDeserializer::visit(&series, &mut json);
+
+        assert_matches!(
+            result,
+            Err(ThinEdgeProtoParserError::InvalidTimestamp { .. })
+        );
+    }
+}
@@ -0,0 +1,42 @@
+//! A ProtoBuf encoding of the thin-edge measurement model.
+//!
+//! The schema is defined by `thin_edge_measurements.proto`.
+//! Measurements are encoded with a `ThinEdgeProtoSerializer`
+//! and decoded with a `ThinEdgeProtoDeserializer`,
+//! both working with any `GroupedMeasurementVisitor`.
+//!
+//! Measurements can also be encoded as Sparkplug B payloads with a `SparkplugSerializer`.
+//!
+//! ```
+//! use thin_edge_json::json::parse_str;
+//! use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+//! use thin_edge_proto::deserializer::ThinEdgeProtoDeserializer;
+//! use thin_edge_proto::serializer::ThinEdgeProtoSerializer;
+//!
+//! # fn main() -> Result<(), anyhow::Error> {
+//! let input = r#"{"temperature":25.5,"location":{"alti":2100.4}}"#;
+//!
+//! let mut proto = ThinEdgeProtoSerializer::new();
+//! parse_str(input, &mut proto)?;
+//! let bytes = proto.bytes()?;
+//!
+//! let mut json = ThinEdgeJsonSerializer::new();
+//! ThinEdgeProtoDeserializer::parse(&bytes, &mut json)?;
+//!
+//! assert_eq!(json.into_string()?, input);
+//! # Ok(()) }
+//! ```
+
+pub mod deserializer;
+pub mod serializer;
+pub mod sparkplug;
+
+/// The types generated from `thin_edge_measurements.proto`
+pub mod proto {
+    include!(concat!(env!("OUT_DIR"), "/thin_edge.rs"));
+}
+
+/// The types generated from `sparkplug_b.proto`
+pub mod sparkplug_b {
+    include!(concat!(env!("OUT_DIR"), "/org.eclipse.tahu.protobuf.rs"));
+}
@@ -0,0 +1,310 @@
+use crate::proto;
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use prost::Message;
+use thin_edge_json::context::MeasurementContext;
+use thin_edge_json::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use thin_edge_json::serialize::MeasurementStreamError;
+
+/// Encode measurements as a ProtoBuf `MeasurementSeries`.
+#[derive(Debug, Default)]
+pub struct ThinEdgeProtoSerializer {
+    series: proto::MeasurementSeries,
+    group: Option<proto::Group>,
+}
+
+impl ThinEdgeProtoSerializer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The series of measurements collected so far
+    pub fn into_message(self) -> Result<proto::MeasurementSeries, MeasurementStreamError> {
+        if self.group.is_some() {
+            return Err(MeasurementStreamError::UnexpectedEndOfData);
+        }
+        Ok(self.series)
+    }
+
+    /// The ProtoBuf encoding of the series of measurements collected so far
+    pub fn bytes(self) -> Result<Vec<u8>, MeasurementStreamError> {
+        Ok(self.into_message()?.encode_to_vec())
+    }
+
+    fn add_measurement(&mut self, measurement: proto::Measurement) {
+        match self.group.as_mut() {
+            Some(group) => group.measurements.push(measurement),
+            None => self.series.entries.push(proto::Entry {
+                entry: Some(proto::entry::Entry::Measurement(measurement)),
+            }),
+        }
+    }
+}
+
+impl GroupedMeasurementVisitor for ThinEdgeProtoSerializer {
+    type Error = MeasurementStreamError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        if self.group.is_some() {
+            return Err(MeasurementStreamError::UnexpectedTimestamp);
+        }
+        self.series.timestamp = Some(timestamp(value));
+        Ok(())
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.add_measurement(proto::Measurement {
+            name: name.to_string(),
+            value,
+            ..proto::Measurement::default()
+        });
+        Ok(())
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        if self.group.is_some() {
+            return Err(MeasurementStreamError::UnexpectedStartOfGroup);
+        }
+        self.group = Some(proto::Group {
+            name: group.to_string(),
+            ..proto::Group::default()
+        });
+        Ok(())
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        match self.group.take() {
+            Some(group) => {
+                self.series.entries.push(proto::Entry {
+                    entry: Some(proto::entry::Entry::Group(group)),
+                });
+                Ok(())
+            }
+            None => Err(MeasurementStreamError::UnexpectedEndOfGroup),
+        }
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.add_measurement(proto::Measurement {
+            name: name.to_string(),
+            value,
+            unit: unit.to_string(),
+            ..proto::Measurement::default()
+        });
+        Ok(())
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        value: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.start_group(group)?;
+        if let Some(group) = self.group.as_mut() {
+            group.timestamp = Some(timestamp(value));
+        }
+        Ok(())
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        match value {
+            Some(value) => self.measurement(name, value),
+            None => {
+                self.add_measurement(proto::Measurement {
+                    name: name.to_string(),
+                    missing: true,
+                    ..proto::Measurement::default()
+                });
+                Ok(())
+            }
+        }
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.add_measurement(proto::Measurement {
+            name: name.to_string(),
+            value: real,
+            imaginary: Some(proto::Imaginary { value: imaginary }),
+            ..proto::Measurement::default()
+        });
+        Ok(())
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        let quality = match quality {
+            MeasurementQuality::Good => proto::Quality::Good,
+            MeasurementQuality::Bad => proto::Quality::Bad,
+            MeasurementQuality::Uncertain => proto::Quality::Uncertain,
+        };
+        self.add_measurement(proto::Measurement {
+            name: name.to_string(),
+            value,
+            quality: quality as i32,
+            ..proto::Measurement::default()
+        });
+        Ok(())
+    }
+
+    /// A context set twice replaces the previous one.
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.series.context = Some(proto::Context {
+            trace_id: context.trace_id.to_vec(),
+            span_id: context.span_id.to_vec(),
+            baggage: context.baggage.into_iter().collect(),
+        });
+        Ok(())
+    }
+}
+
+fn timestamp(value: DateTime<FixedOffset>) -> proto::Timestamp {
+    proto::Timestamp {
+        seconds: value.timestamp(),
+        nanos: value.timestamp_subsec_nanos(),
+        utc_offset_seconds: value.offset().local_minus_utc(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use chrono::TimeZone;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn serialize_measurements_and_groups() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeProtoSerializer::new();
+        let timestamp =
+            FixedOffset::east(2 * 3600)
+                .ymd(2021, 4, 30)
+                .and_hms_nano(17, 3, 14, 123_000_000);
+
+        serializer.timestamp(timestamp)?;
+        serializer.measurement("temperature", 25.5)?;
+        serializer.start_group("location")?;
+        serializer.measurement_with_unit("alti", 2100.4, "m")?;
+        serializer.end_group()?;
+
+        let expected = proto::MeasurementSeries {
+            timestamp: Some(proto::Timestamp {
+                seconds: timestamp.timestamp(),
+                nanos: 123_000_000,
+                utc_offset_seconds: 7200,
+            }),
+            context: None,
+            entries: vec![
+                proto::Entry {
+                    entry: Some(proto::entry::Entry::Measurement(proto::Measurement {
+                        name: "temperature".into(),
+                        value: 25.5,
+                        ..proto::Measurement::default()
+                    })),
+                },
+                proto::Entry {
+                    entry: Some(proto::entry::Entry::Group(proto::Group {
+                        name: "location".into(),
+                        measurements: vec![proto::Measurement {
+                            name: "alti".into(),
+                            value: 2100.4,
+                            unit: "m".into(),
+                            ..proto::Measurement::default()
+                        }],
+                        timestamp: None,
+                    })),
+                },
+            ],
+        };
+        assert_eq!(serializer.into_message()?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_all_kinds_of_measurements() -> anyhow::Result<()> {
+        let mut serializer = ThinEdgeProtoSerializer::new();
+        let timestamp = FixedOffset::east(0).ymd(2021, 4, 30).and_hms(17, 3, 14);
+
+        serializer.nullable_measurement("temperature", None)?;
+        serializer.start_group_with_timestamp("phase", timestamp)?;
+        serializer.complex_measurement("current", 1.5, -0.5)?;
+        serializer.annotated_measurement("voltage", 230.0, MeasurementQuality::Uncertain)?;
+        serializer.end_group()?;
+
+        let expected = proto::MeasurementSeries {
+            timestamp: None,
+            context: None,
+            entries: vec![
+                proto::Entry {
+                    entry: Some(proto::entry::Entry::Measurement(proto::Measurement {
+                        name: "temperature".into(),
+                        missing: true,
+                        ..proto::Measurement::default()
+                    })),
+                },
+                proto::Entry {
+                    entry: Some(proto::entry::Entry::Group(proto::Group {
+                        name: "phase".into(),
+                        measurements: vec![
+                            proto::Measurement {
+                                name: "current".into(),
+                                value: 1.5,
+                                imaginary: Some(proto::Imaginary { value: -0.5 }),
+                                ..proto::Measurement::default()
+                            },
+                            proto::Measurement {
+                                name: "voltage".into(),
+                                value: 230.0,
+                                quality: proto::Quality::Uncertain as i32,
+                                ..proto::Measurement::default()
+                            },
+                        ],
+                        timestamp: Some(proto::Timestamp {
+                            seconds: timestamp.timestamp(),
+                            nanos: 0,
+                            utc_offset_seconds: 0,
+                        }),
+                    })),
+                },
+            ],
+        };
+        assert_eq!(serializer.into_message()?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn reject_unbalanced_groups() {
+        let mut serializer = ThinEdgeProtoSerializer::new();
+        assert_matches!(
+            serializer.end_group(),
+            Err(MeasurementStreamError::UnexpectedEndOfGroup)
+        );
+
+        serializer.start_group("location").unwrap();
+        assert_matches!(
+            serializer.start_group("location"),
+            Err(MeasurementStreamError::UnexpectedStartOfGroup)
+        );
+        assert_matches!(
+            serializer.timestamp(FixedOffset::east(0).ymd(2021, 4, 30).and_hms(17, 3, 14)),
+            Err(MeasurementStreamError::UnexpectedTimestamp)
+        );
+        assert_matches!(
+            serializer.bytes(),
+            Err(MeasurementStreamError::UnexpectedEndOfData)
+        );
+    }
+}
@@ -0,0 +1,414 @@
+use crate::sparkplug_b::{payload, DataType, Payload};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use prost::Message;
+use thin_edge_json::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use thin_edge_json::serialize::MeasurementStreamError;
+use thin_edge_json::sparkplug::{SparkplugTopicError, SparkplugTopicMapper};
+
+/// The property giving the unit of a metric
+const UNIT_PROPERTY: &str = "engUnit";
+
+/// The property giving the quality of a metric, as an OPC quality code
+const QUALITY_PROPERTY: &str = "Quality";
+
+#[derive(thiserror::Error, Debug)]
+pub enum SparkplugSerializationError {
+    #[error(transparent)]
+    InvalidTopic(#[from] SparkplugTopicError),
+
+    #[error(transparent)]
+    InvalidMeasurements(#[from] MeasurementStreamError),
+}
+
+/// Encode the measurements of an edge node and of its devices as Sparkplug B payloads,
+/// each payload being returned along the topic it has to be published on.
+///
+/// * The measurements are gathered and encoded as a payload by `node_message()`,
+///   for the edge node itself, or by `device_message()`, for a device attached to the node.
+/// * The payloads are numbered from 0 to 255, then from 0 again,
+///   the sequence numbers being shared by the messages of the node and of its devices.
+/// * Each measurement is encoded as a `Double` metric, named `<group>/<name>` within a group,
+///   and timestamped with the time of its group, if any.
+///   The message timestamp is the timestamp of the payload.
+/// * A missing value is encoded as a null metric,
+///   and a complex value as two metrics, `<name>_re` and `<name>_im`.
+/// * A unit is given by an `engUnit` property,
+///   and a quality by a `Quality` property holding its OPC code:
+///   192 for good, 64 for uncertain and 0 for bad.
+///
+/// The birth and death certificates are not produced, nor is the trace context encoded.
+///
+/// ```
+/// use thin_edge_json::measurement::GroupedMeasurementVisitor;
+/// use thin_edge_json::sparkplug::SparkplugTopicMapper;
+/// use thin_edge_proto::sparkplug::SparkplugSerializer;
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let topics = SparkplugTopicMapper::new("factory", "gateway-1");
+/// let mut serializer = SparkplugSerializer::new(topics);
+///
+/// serializer.measurement("temperature", 25.5)?;
+/// let (topic, _payload) = serializer.device_message("sensor-1")?;
+/// assert_eq!(topic, "spBv1.0/factory/DDATA/gateway-1/sensor-1");
+///
+/// serializer.measurement("cpu", 12.0)?;
+/// let (topic, _payload) = serializer.node_message()?;
+/// assert_eq!(topic, "spBv1.0/factory/NDATA/gateway-1");
+/// # Ok(()) }
+/// ```
+#[derive(Debug)]
+pub struct SparkplugSerializer {
+    topics: SparkplugTopicMapper,
+    sequence_number: u8,
+    payload: Payload,
+    group: Option<Group>,
+}
+
+#[derive(Debug)]
+struct Group {
+    name: String,
+    timestamp: Option<u64>,
+}
+
+impl SparkplugSerializer {
+    pub fn new(topics: SparkplugTopicMapper) -> Self {
+        Self {
+            topics,
+            sequence_number: 0,
+            payload: Payload::default(),
+            group: None,
+        }
+    }
+
+    /// The topic and the payload of the measurements of the edge node gathered so far
+    pub fn node_message(&mut self) -> Result<(String, Vec<u8>), SparkplugSerializationError> {
+        let topic = self.topics.group_topic()?;
+        Ok((topic, self.payload()?))
+    }
+
+    /// The topic and the payload of the measurements of a device gathered so far
+    pub fn device_message(
+        &mut self,
+        device_id: &str,
+    ) -> Result<(String, Vec<u8>), SparkplugSerializationError> {
+        let topic = self.topics.device_topic(device_id)?;
+        Ok((topic, self.payload()?))
+    }
+
+    /// Encode the measurements gathered so far, the next payload having the next sequence number
+    fn payload(&mut self) -> Result<Vec<u8>, MeasurementStreamError> {
+        if self.group.is_some() {
+            return Err(MeasurementStreamError::UnexpectedEndOfData);
+        }
+        let mut payload = std::mem::take(&mut self.payload);
+        payload.seq = Some(u64::from(self.sequence_number));
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        Ok(payload.encode_to_vec())
+    }
+
+    fn add_metric(&mut self, name: &str, value: Option<f64>, properties: Vec<(&str, Property)>) {
+        let (name, timestamp) = match self.group.as_ref() {
+            Some(group) => (format!("{}/{}", group.name, name), group.timestamp),
+            None => (name.to_string(), None),
+        };
+        let properties = if properties.is_empty() {
+            None
+        } else {
+            Some(payload::PropertySet {
+                keys: properties.iter().map(|(key, _)| key.to_string()).collect(),
+                values: properties
+                    .into_iter()
+                    .map(|(_, value)| value.into())
+                    .collect(),
+            })
+        };
+        self.payload.metrics.push(payload::Metric {
+            name: Some(name),
+            timestamp,
+            datatype: Some(DataType::Double as u32),
+            is_null: value.is_none().then(|| true),
+            properties,
+            value: value.map(payload::metric::Value::DoubleValue),
+            ..payload::Metric::default()
+        });
+    }
+}
+
+enum Property {
+    Text(String),
+    Quality(u32),
+}
+
+impl From<Property> for payload::PropertyValue {
+    fn from(property: Property) -> Self {
+        let (datatype, value) = match property {
+            Property::Text(text) => (
+                DataType::String,
+                payload::property_value::Value::StringValue(text),
+            ),
+            Property::Quality(code) => (
+                DataType::Int32,
+                payload::property_value::Value::IntValue(code),
+            ),
+        };
+        payload::PropertyValue {
+            r#type: Some(datatype as u32),
+            value: Some(value),
+            ..payload::PropertyValue::default()
+        }
+    }
+}
+
+fn quality_code(quality: MeasurementQuality) -> u32 {
+    match quality {
+        MeasurementQuality::Good => 192,
+        MeasurementQuality::Uncertain => 64,
+        MeasurementQuality::Bad => 0,
+    }
+}
+
+fn timestamp(value: DateTime<FixedOffset>) -> u64 {
+    value.timestamp_millis().max(0) as u64
+}
+
+impl GroupedMeasurementVisitor for SparkplugSerializer {
+    type Error = MeasurementStreamError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        if self.group.is_some() {
+            return Err(MeasurementStreamError::UnexpectedTimestamp);
+        }
+        self.payload.timestamp = Some(timestamp(value));
+        Ok(())
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.add_metric(name, Some(value), vec![]);
+        Ok(())
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        if self.group.is_some() {
+            return Err(MeasurementStreamError::UnexpectedStartOfGroup);
+        }
+        self.group = Some(Group {
+            name: group.to_string(),
+            timestamp: None,
+        });
+        Ok(())
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        match self.group.take() {
+            Some(_) => Ok(()),
+            None => Err(MeasurementStreamError::UnexpectedEndOfGroup),
+        }
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        let unit = Property::Text(unit.to_string());
+        self.add_metric(name, Some(value), vec![(UNIT_PROPERTY, unit)]);
+        Ok(())
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        value: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.start_group(group)?;
+        if let Some(group) = self.group.as_mut() {
+            group.timestamp = Some(timestamp(value));
+        }
+        Ok(())
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        self.add_metric(name, value, vec![]);
+        Ok(())
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.add_metric(&format!("{}_re", name), Some(real), vec![]);
+        self.add_metric(&format!("{}_im", name), Some(imaginary), vec![]);
+        Ok(())
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        let quality = Property::Quality(quality_code(quality));
+        self.add_metric(name, Some(value), vec![(QUALITY_PROPERTY, quality)]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use chrono::TimeZone;
+    use pretty_assertions::assert_eq;
+
+    fn serializer() -> SparkplugSerializer {
+        SparkplugSerializer::new(SparkplugTopicMapper::new("factory", "gateway-1"))
+    }
+
+    fn metric(name: &str, value: Option<f64>) -> payload::Metric {
+        payload::Metric {
+            name: Some(name.to_string()),
+            datatype: Some(DataType::Double as u32),
+            is_null: value.is_none().then(|| true),
+            value: value.map(payload::metric::Value::DoubleValue),
+            ..payload::Metric::default()
+        }
+    }
+
+    fn properties(key: &str, property: Property) -> Option<payload::PropertySet> {
+        Some(payload::PropertySet {
+            keys: vec![key.to_string()],
+            values: vec![property.into()],
+        })
+    }
+
+    #[test]
+    fn measurements_are_encoded_as_metrics() -> anyhow::Result<()> {
+        let mut serializer = serializer();
+        let time = FixedOffset::east(2 * 3600)
+            .ymd(2021, 4, 30)
+            .and_hms_milli(17, 3, 14, 123);
+        let group_time = FixedOffset::east(0).ymd(2021, 4, 30).and_hms(15, 3, 10);
+
+        serializer.timestamp(time)?;
+        serializer.measurement("temperature", 25.5)?;
+        serializer.start_group_with_timestamp("location", group_time)?;
+        serializer.measurement_with_unit("alti", 2100.4, "m")?;
+        serializer.end_group()?;
+        let (topic, payload) = serializer.device_message("sensor-1")?;
+
+        assert_eq!(topic, "spBv1.0/factory/DDATA/gateway-1/sensor-1");
+        assert_eq!(
+            Payload::decode(&payload[..])?,
+            Payload {
+                timestamp: Some(1_619_794_994_123),
+                seq: Some(0),
+                metrics: vec![
+                    metric("temperature", Some(25.5)),
+                    payload::Metric {
+                        timestamp: Some(1_619_794_990_000),
+                        properties: properties(UNIT_PROPERTY, Property::Text("m".into())),
+                        ..metric("location/alti", Some(2100.4))
+                    },
+                ],
+                ..Payload::default()
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn all_kinds_of_measurements_are_encoded() -> anyhow::Result<()> {
+        let mut serializer = serializer();
+
+        serializer.nullable_measurement("pressure", None)?;
+        serializer.complex_measurement("current", 1.5, -0.5)?;
+        serializer.annotated_measurement("humidity", 60.0, MeasurementQuality::Uncertain)?;
+        let (topic, payload) = serializer.node_message()?;
+
+        assert_eq!(topic, "spBv1.0/factory/NDATA/gateway-1");
+        assert_eq!(
+            Payload::decode(&payload[..])?.metrics,
+            vec![
+                metric("pressure", None),
+                metric("current_re", Some(1.5)),
+                metric("current_im", Some(-0.5)),
+                payload::Metric {
+                    properties: properties(QUALITY_PROPERTY, Property::Quality(64)),
+                    ..metric("humidity", Some(60.0))
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn payloads_are_numbered_from_0_to_255() -> anyhow::Result<()> {
+        let mut serializer = serializer();
+        let mut sequence_numbers = Vec::new();
+        for i in 0..257 {
+            serializer.measurement("counter", f64::from(i))?;
+            let (_, payload) = if i % 2 == 0 {
+                serializer.node_message()?
+            } else {
+                serializer.device_message("sensor-1")?
+            };
+            sequence_numbers.push(Payload::decode(&payload[..])?.seq());
+        }
+
+        assert_eq!(sequence_numbers[..3], [0, 1, 2]);
+        assert_eq!(sequence_numbers[255..], [255, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn each_message_starts_a_new_payload() -> anyhow::Result<()> {
+        let mut serializer = serializer();
+        serializer.timestamp(FixedOffset::east(0).ymd(2021, 4, 30).and_hms(15, 0, 0))?;
+        serializer.measurement("temperature", 25.5)?;
+        serializer.node_message()?;
+
+        serializer.measurement("temperature", 26.0)?;
+        let (_, payload) = serializer.node_message()?;
+
+        let payload = Payload::decode(&payload[..])?;
+        assert_eq!(payload.timestamp, None);
+        assert_eq!(payload.metrics, vec![metric("temperature", Some(26.0))]);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_device_ids_are_rejected() {
+        let mut serializer = serializer();
+
+        assert_matches!(
+            serializer.device_message("line/1"),
+            Err(SparkplugSerializationError::InvalidTopic(
+                SparkplugTopicError::ReservedCharacter { .. }
+            ))
+        );
+        assert_matches!(
+            serializer.device_message(""),
+            Err(SparkplugSerializationError::InvalidTopic(
+                SparkplugTopicError::EmptyId
+            ))
+        );
+    }
+
+    #[test]
+    fn unclosed_groups_are_rejected() -> anyhow::Result<()> {
+        let mut serializer = serializer();
+        serializer.start_group("location")?;
+
+        assert_matches!(
+            serializer.node_message(),
+            Err(SparkplugSerializationError::InvalidMeasurements(
+                MeasurementStreamError::UnexpectedEndOfData
+            ))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,287 @@
+use crate::parse::{Entry, Group, Measurement, Schema};
+use proc_macro2::TokenStream;
+use quote::quote;
+
+impl Schema {
+    pub fn expand(&self) -> TokenStream {
+        let Schema {
+            attrs, vis, name, ..
+        } = self;
+
+        let fields = self.entries.iter().map(|entry| match entry {
+            Entry::Measurement(measurement) => measurement_field(vis, measurement),
+            Entry::Group(group) => {
+                let Group {
+                    attrs,
+                    name,
+                    struct_name,
+                    ..
+                } = group;
+                quote! {
+                    #(#attrs)*
+                    #vis #name: #struct_name
+                }
+            }
+        });
+
+        let visits = self.entries.iter().map(|entry| match entry {
+            Entry::Measurement(measurement) => visit_measurement(measurement),
+            Entry::Group(group) => {
+                let field = &group.name;
+                let key = group.key();
+                quote! {
+                    visitor.start_group(#key)?;
+                    self.#field.visit(visitor)?;
+                    visitor.end_group()?;
+                }
+            }
+        });
+
+        let names: Vec<_> = self
+            .entries
+            .iter()
+            .map(|entry| match entry {
+                Entry::Measurement(measurement) => &measurement.name,
+                Entry::Group(group) => &group.name,
+            })
+            .collect();
+        let single_keys = self.entries.iter().filter_map(|entry| match entry {
+            Entry::Measurement(measurement) => Some(measurement.key()),
+            Entry::Group(_) => None,
+        });
+        let single_fields = self.entries.iter().filter_map(|entry| match entry {
+            Entry::Measurement(measurement) => Some(&measurement.name),
+            Entry::Group(_) => None,
+        });
+        let group_keys = self.entries.iter().filter_map(|entry| match entry {
+            Entry::Measurement(_) => None,
+            Entry::Group(group) => Some(group.key()),
+        });
+        let group_fields = self.entries.iter().filter_map(|entry| match entry {
+            Entry::Measurement(_) => None,
+            Entry::Group(group) => Some(&group.name),
+        });
+        let group_struct_names = self.entries.iter().filter_map(|entry| match entry {
+            Entry::Measurement(_) => None,
+            Entry::Group(group) => Some(&group.struct_name),
+        });
+        let required = self.entries.iter().map(|entry| match entry {
+            Entry::Measurement(measurement) => required_measurement(None, measurement),
+            Entry::Group(group) => {
+                let field = &group.name;
+                let key = group.key();
+                quote! {
+                    let #field = #field.ok_or_else(|| {
+                        ::thin_edge_json::typed::TypedMeasurementError::MissingGroup {
+                            name: #key.to_string(),
+                        }
+                    })?;
+                }
+            }
+        });
+
+        let groups = self.entries.iter().filter_map(|entry| match entry {
+            Entry::Measurement(_) => None,
+            Entry::Group(group) => Some(expand_group(vis, group)),
+        });
+
+        quote! {
+            #(#attrs)*
+            #[derive(Debug, Clone, PartialEq)]
+            #vis struct #name {
+                #(#fields,)*
+            }
+
+            impl #name {
+                /// Forward the measurements to a visitor, as a thin-edge JSON message
+                #vis fn visit<V>(&self, visitor: &mut V) -> ::std::result::Result<(), V::Error>
+                where
+                    V: ::thin_edge_json::measurement::GroupedMeasurementVisitor,
+                {
+                    #(#visits)*
+                    Ok(())
+                }
+            }
+
+            impl ::std::convert::TryFrom<&::thin_edge_json::json::ThinEdgeJson> for #name {
+                type Error = ::thin_edge_json::typed::TypedMeasurementError;
+
+                fn try_from(
+                    __json: &::thin_edge_json::json::ThinEdgeJson,
+                ) -> ::std::result::Result<Self, Self::Error> {
+                    #(let mut #names = None;)*
+
+                    for __value in __json.values.iter() {
+                        match __value {
+                            ::thin_edge_json::json::ThinEdgeValue::Single(__measurement) => {
+                                match __measurement.name.as_str() {
+                                    #(#single_keys => #single_fields = Some(__measurement.value),)*
+                                    _ => {
+                                        return Err(
+                                            ::thin_edge_json::typed::TypedMeasurementError::UnexpectedMeasurement {
+                                                name: __measurement.name.clone(),
+                                            },
+                                        )
+                                    }
+                                }
+                            }
+                            ::thin_edge_json::json::ThinEdgeValue::Multi(__group) => {
+                                match __group.name.as_str() {
+                                    #(#group_keys => {
+                                        #group_fields = Some(
+                                            #group_struct_names::from_measurements(&__group.name, &__group.values)?,
+                                        )
+                                    })*
+                                    _ => {
+                                        return Err(
+                                            ::thin_edge_json::typed::TypedMeasurementError::UnexpectedGroup {
+                                                name: __group.name.clone(),
+                                            },
+                                        )
+                                    }
+                                }
+                            }
+                        }
+                    }
+
+                    #(#required)*
+                    Ok(#name { #(#names,)* })
+                }
+            }
+
+            impl ::std::convert::TryFrom<::thin_edge_json::json::ThinEdgeJson> for #name {
+                type Error = ::thin_edge_json::typed::TypedMeasurementError;
+
+                fn try_from(
+                    __json: ::thin_edge_json::json::ThinEdgeJson,
+                ) -> ::std::result::Result<Self, Self::Error> {
+                    <Self as ::std::convert::TryFrom<&::thin_edge_json::json::ThinEdgeJson>>::try_from(&__json)
+                }
+            }
+
+            #(#groups)*
+        }
+    }
+}
+
+fn expand_group(vis: &syn::Visibility, group: &Group) -> TokenStream {
+    let name = &group.struct_name;
+    let fields = group
+        .measurements
+        .iter()
+        .map(|measurement| measurement_field(vis, measurement));
+    let visits = group.measurements.iter().map(visit_measurement);
+    let names: Vec<_> = group
+        .measurements
+        .iter()
+        .map(|measurement| &measurement.name)
+        .collect();
+    let keys = group.measurements.iter().map(Measurement::key);
+    let group_key = group.key();
+    let required = group
+        .measurements
+        .iter()
+        .map(|measurement| required_measurement(Some(&group_key), measurement));
+
+    quote! {
+        #[derive(Debug, Clone, PartialEq)]
+        #vis struct #name {
+            #(#fields,)*
+        }
+
+        impl #name {
+            /// Forward the measurements of this group to a visitor,
+            /// the group being started and ended by the caller
+            #vis fn visit<V>(&self, visitor: &mut V) -> ::std::result::Result<(), V::Error>
+            where
+                V: ::thin_edge_json::measurement::GroupedMeasurementVisitor,
+            {
+                #(#visits)*
+                Ok(())
+            }
+
+            #[doc(hidden)]
+            #vis fn from_measurements(
+                __group: &str,
+                __measurements: &[::thin_edge_json::json::SingleValueMeasurement],
+            ) -> ::std::result::Result<Self, ::thin_edge_json::typed::TypedMeasurementError> {
+                #(let mut #names = None;)*
+
+                for __measurement in __measurements.iter() {
+                    match __measurement.name.as_str() {
+                        #(#keys => #names = Some(__measurement.value),)*
+                        _ => {
+                            return Err(
+                                ::thin_edge_json::typed::TypedMeasurementError::UnexpectedMeasurement {
+                                    name: ::thin_edge_json::typed::TypedMeasurementError::key(
+                                        Some(__group),
+                                        &__measurement.name,
+                                    ),
+                                },
+                            )
+                        }
+                    }
+                }
+
+                #(#required)*
+                Ok(#name { #(#names,)* })
+            }
+        }
+    }
+}
+
+fn measurement_field(vis: &syn::Visibility, measurement: &Measurement) -> TokenStream {
+    let Measurement { attrs, name, .. } = measurement;
+    if measurement.optional {
+        quote! {
+            #(#attrs)*
+            #vis #name: ::std::option::Option<f64>
+        }
+    } else {
+        quote! {
+            #(#attrs)*
+            #vis #name: f64
+        }
+    }
+}
+
+fn visit_measurement(measurement: &Measurement) -> TokenStream {
+    let field = &measurement.name;
+    let key = measurement.key();
+    match (measurement.optional, measurement.unit.as_ref()) {
+        (false, None) => quote! {
+            visitor.measurement(#key, self.#field)?;
+        },
+        (false, Some(unit)) => quote! {
+            visitor.measurement_with_unit(#key, self.#field, #unit)?;
+        },
+        (true, None) => quote! {
+            visitor.nullable_measurement(#key, self.#field)?;
+        },
+        (true, Some(unit)) => quote! {
+            match self.#field {
+                Some(value) => visitor.measurement_with_unit(#key, value, #unit)?,
+                None => visitor.nullable_measurement(#key, None)?,
+            }
+        },
+    }
+}
+
+/// Unwrap a required measurement, leaving an optional one as is
+fn required_measurement(group: Option<&str>, measurement: &Measurement) -> TokenStream {
+    let field = &measurement.name;
+    if measurement.optional {
+        return quote! {};
+    }
+    let name = match group {
+        Some(group) => format!("{}.{}", group, measurement.key()),
+        None => measurement.key(),
+    };
+    quote! {
+        let #field = #field.ok_or_else(|| {
+            ::thin_edge_json::typed::TypedMeasurementError::MissingMeasurement {
+                name: #name.to_string(),
+            }
+        })?;
+    }
+}
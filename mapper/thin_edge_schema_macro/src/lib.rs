@@ -0,0 +1,68 @@
+//! A macro to define typed thin-edge JSON measurement schemas.
+//!
+//! Using a typed schema, the names of the measurements are checked at compile time,
+//! rather than being given as strings spread over the application code.
+
+mod expand;
+mod parse;
+
+use proc_macro::TokenStream;
+use syn::parse_macro_input;
+
+/// Define a typed measurement schema, i.e. a struct with one field per measurement or group.
+///
+/// * A measurement is given as `name: f64` or `name: Option<f64>`,
+///   possibly followed by a unit, as in `temperature: f64 in "°C"`.
+/// * A group is given as `name: group StructName { <measurements> }`,
+///   `StructName` being the name of the struct generated for the group.
+///
+/// The definition is checked at compile time:
+/// groups cannot be nested, a name cannot be used twice at the same level,
+/// and the reserved `time` and `type` keys cannot be used.
+///
+/// The generated struct provides:
+/// * a `visit()` method that forwards the measurements to any `GroupedMeasurementVisitor`,
+///   the missing optional values being forwarded as `nullable_measurement(name, None)`;
+/// * `TryFrom<ThinEdgeJson>` and `TryFrom<&ThinEdgeJson>` implementations,
+///   which fail with a `TypedMeasurementError` if a required measurement is missing
+///   or if the payload has a measurement not defined by the schema.
+///   The units are not checked, as they are not given by a `ThinEdgeJson` payload.
+///
+/// ```
+/// use std::convert::TryFrom;
+/// use thin_edge_json::json::ThinEdgeJson;
+/// use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+/// use thin_edge_schema_macro::define_measurement_schema;
+///
+/// define_measurement_schema! {
+///     pub struct Environment {
+///         temperature: f64 in "°C",
+///         humidity: Option<f64>,
+///         location: group EnvironmentLocation {
+///             alti: f64 in "m",
+///             longi: f64,
+///         },
+///     }
+/// }
+///
+/// # fn main() -> Result<(), anyhow::Error> {
+/// let payload = r#"{"temperature": 25.5, "location": {"alti": 2100.4, "longi": 2200.4}}"#;
+/// let environment = Environment::try_from(ThinEdgeJson::from_str(payload)?)?;
+///
+/// assert_eq!(environment.temperature, 25.5);
+/// assert_eq!(environment.humidity, None);
+/// assert_eq!(environment.location.alti, 2100.4);
+///
+/// let mut serializer = ThinEdgeJsonSerializer::new();
+/// environment.visit(&mut serializer)?;
+/// assert_eq!(
+///     serializer.into_string()?,
+///     r#"{"temperature":{"value":25.5,"unit":"°C"},"humidity":null,"location":{"alti":{"value":2100.4,"unit":"m"},"longi":2200.4}}"#
+/// );
+/// # Ok(()) }
+/// ```
+#[proc_macro]
+pub fn define_measurement_schema(input: TokenStream) -> TokenStream {
+    let schema = parse_macro_input!(input as parse::Schema);
+    schema.expand().into()
+}
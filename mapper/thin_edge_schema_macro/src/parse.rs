@@ -0,0 +1,316 @@
+use std::collections::HashSet;
+use syn::ext::IdentExt;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{braced, Attribute, Ident, LitStr, Token, Type, Visibility};
+
+mod kw {
+    syn::custom_keyword!(group);
+}
+
+/// The keys that can't be used for a measurement or a group
+const RESERVED_NAMES: [&str; 2] = ["time", "type"];
+
+/// A schema, as given to `define_measurement_schema!`:
+///
+/// ```text
+/// pub struct Environment {
+///     temperature: f64 in "°C",
+///     humidity: Option<f64>,
+///     location: group EnvironmentLocation {
+///         alti: f64 in "m",
+///         longi: f64,
+///     },
+/// }
+/// ```
+pub struct Schema {
+    pub attrs: Vec<Attribute>,
+    pub vis: Visibility,
+    pub name: Ident,
+    pub entries: Vec<Entry>,
+}
+
+pub enum Entry {
+    Measurement(Measurement),
+    Group(Group),
+}
+
+pub struct Measurement {
+    pub attrs: Vec<Attribute>,
+    pub name: Ident,
+    pub optional: bool,
+    pub unit: Option<LitStr>,
+}
+
+pub struct Group {
+    pub attrs: Vec<Attribute>,
+    pub name: Ident,
+    pub struct_name: Ident,
+    pub measurements: Vec<Measurement>,
+}
+
+impl Measurement {
+    /// The measurement name, as given in thin-edge JSON
+    pub fn key(&self) -> String {
+        self.name.unraw().to_string()
+    }
+}
+
+impl Group {
+    /// The group name, as given in thin-edge JSON
+    pub fn key(&self) -> String {
+        self.name.unraw().to_string()
+    }
+}
+
+impl Parse for Schema {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let vis = input.parse()?;
+        let _: Token![struct] = input.parse()?;
+        let name = input.parse()?;
+
+        let content;
+        braced!(content in input);
+        let entries: Vec<Entry> = Punctuated::<Entry, Token![,]>::parse_terminated(&content)?
+            .into_iter()
+            .collect();
+
+        check_names(entries.iter().map(|entry| match entry {
+            Entry::Measurement(measurement) => &measurement.name,
+            Entry::Group(group) => &group.name,
+        }))?;
+        if entries.is_empty() {
+            return Err(syn::Error::new(
+                name.span(),
+                "a schema must define at least one measurement",
+            ));
+        }
+
+        Ok(Schema {
+            attrs,
+            vis,
+            name,
+            entries,
+        })
+    }
+}
+
+impl Parse for Entry {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let name: Ident = input.parse()?;
+        let _: Token![:] = input.parse()?;
+
+        if input.peek(kw::group) {
+            let _: kw::group = input.parse()?;
+            let struct_name = input.parse()?;
+
+            let content;
+            braced!(content in input);
+            let measurements: Vec<Measurement> =
+                Punctuated::<MeasurementBody, Token![,]>::parse_terminated(&content)?
+                    .into_iter()
+                    .map(MeasurementBody::into_measurement)
+                    .collect();
+
+            check_names(measurements.iter().map(|measurement| &measurement.name))?;
+            if measurements.is_empty() {
+                return Err(syn::Error::new(
+                    name.span(),
+                    "a group must define at least one measurement",
+                ));
+            }
+
+            Ok(Entry::Group(Group {
+                attrs,
+                name,
+                struct_name,
+                measurements,
+            }))
+        } else {
+            let (optional, unit) = parse_measurement_type(input)?;
+            Ok(Entry::Measurement(Measurement {
+                attrs,
+                name,
+                optional,
+                unit,
+            }))
+        }
+    }
+}
+
+/// A measurement of a group, where no nested group is allowed
+struct MeasurementBody(Measurement);
+
+impl MeasurementBody {
+    fn into_measurement(self) -> Measurement {
+        self.0
+    }
+}
+
+impl Parse for MeasurementBody {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let name: Ident = input.parse()?;
+        let _: Token![:] = input.parse()?;
+        if input.peek(kw::group) {
+            return Err(input.error("groups cannot be nested in thin-edge JSON"));
+        }
+        let (optional, unit) = parse_measurement_type(input)?;
+        Ok(MeasurementBody(Measurement {
+            attrs,
+            name,
+            optional,
+            unit,
+        }))
+    }
+}
+
+/// Parse `f64` or `Option<f64>`, followed by an optional unit `in "<unit>"`
+fn parse_measurement_type(input: ParseStream) -> syn::Result<(bool, Option<LitStr>)> {
+    let ty: Type = input.parse()?;
+    let optional = match quote::quote!(#ty).to_string().as_str() {
+        "f64" => false,
+        "Option < f64 >" => true,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                ty,
+                "a measurement must be of type `f64` or `Option<f64>`",
+            ))
+        }
+    };
+
+    let unit = if input.peek(Token![in]) {
+        let _: Token![in] = input.parse()?;
+        let unit: LitStr = input.parse()?;
+        if unit.value().is_empty() {
+            return Err(syn::Error::new(unit.span(), "a unit cannot be empty"));
+        }
+        Some(unit)
+    } else {
+        None
+    };
+
+    Ok((optional, unit))
+}
+
+/// Check that names are not reserved and not used twice at the same level
+fn check_names<'a>(names: impl Iterator<Item = &'a Ident>) -> syn::Result<()> {
+    let mut seen = HashSet::new();
+    for name in names {
+        let key = name.unraw().to_string();
+        if RESERVED_NAMES.contains(&key.as_str()) {
+            return Err(syn::Error::new(
+                name.span(),
+                format!("`{}` is a reserved thin-edge JSON key", key),
+            ));
+        }
+        if !seen.insert(key.clone()) {
+            return Err(syn::Error::new(
+                name.span(),
+                format!("`{}` is defined twice", key),
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(input: &str) -> String {
+        match syn::parse_str::<Schema>(input) {
+            Ok(_) => panic!("Expected an error for: {}", input),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_a_schema() -> syn::Result<()> {
+        let schema: Schema = syn::parse_str(
+            r#"pub struct Environment {
+                temperature: f64 in "°C",
+                humidity: Option<f64>,
+                location: group EnvironmentLocation {
+                    alti: f64 in "m",
+                    longi: f64,
+                },
+            }"#,
+        )?;
+
+        assert_eq!(schema.name, "Environment");
+        assert_eq!(schema.entries.len(), 3);
+        match &schema.entries[0] {
+            Entry::Measurement(measurement) => {
+                assert_eq!(measurement.key(), "temperature");
+                assert!(!measurement.optional);
+                assert_eq!(
+                    measurement.unit.as_ref().map(LitStr::value),
+                    Some("°C".into())
+                );
+            }
+            Entry::Group(_) => panic!("Expected a measurement"),
+        }
+        match &schema.entries[1] {
+            Entry::Measurement(measurement) => {
+                assert!(measurement.optional);
+                assert!(measurement.unit.is_none());
+            }
+            Entry::Group(_) => panic!("Expected a measurement"),
+        }
+        match &schema.entries[2] {
+            Entry::Group(group) => {
+                assert_eq!(group.key(), "location");
+                assert_eq!(group.struct_name, "EnvironmentLocation");
+                assert_eq!(group.measurements.len(), 2);
+            }
+            Entry::Measurement(_) => panic!("Expected a group"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn raw_identifiers_are_used_for_keywords() -> syn::Result<()> {
+        let schema: Schema = syn::parse_str("struct Motor { r#loop: f64 }")?;
+
+        match &schema.entries[0] {
+            Entry::Measurement(measurement) => assert_eq!(measurement.key(), "loop"),
+            Entry::Group(_) => panic!("Expected a measurement"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_schemas() {
+        assert_eq!(
+            parse_error("struct S { temperature: f32 }"),
+            "a measurement must be of type `f64` or `Option<f64>`"
+        );
+        assert_eq!(
+            parse_error("struct S { temperature: f64, temperature: f64 }"),
+            "`temperature` is defined twice"
+        );
+        assert_eq!(
+            parse_error("struct S { time: f64 }"),
+            "`time` is a reserved thin-edge JSON key"
+        );
+        assert_eq!(
+            parse_error(r#"struct S { temperature: f64 in "" }"#),
+            "a unit cannot be empty"
+        );
+        assert_eq!(
+            parse_error("struct S { g: group G { h: group H { x: f64 } } }"),
+            "groups cannot be nested in thin-edge JSON"
+        );
+        assert_eq!(
+            parse_error("struct S { g: group G { } }"),
+            "a group must define at least one measurement"
+        );
+        assert_eq!(
+            parse_error("struct S { }"),
+            "a schema must define at least one measurement"
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use std::convert::TryFrom;
+use thin_edge_json::json::ThinEdgeJson;
+use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+use thin_edge_json::typed::TypedMeasurementError;
+use thin_edge_schema_macro::define_measurement_schema;
+
+define_measurement_schema! {
+    /// The measurements of an engine
+    pub struct Engine {
+        /// The engine speed
+        speed: f64 in "rpm",
+        oil_level: Option<f64> in "%",
+        type_code: f64,
+        temperature: group EngineTemperature {
+            inlet: f64 in "°C",
+            outlet: Option<f64>,
+        },
+    }
+}
+
+fn parse(payload: &str) -> Result<Engine, TypedMeasurementError> {
+    let json = ThinEdgeJson::from_str(payload).expect("Valid thin-edge JSON");
+    Engine::try_from(&json)
+}
+
+#[test]
+fn a_payload_is_parsed_into_a_typed_schema() {
+    let engine = parse(
+        r#"{"speed":3000,"type_code":7,"temperature":{"inlet":80.5,"outlet":90.2},"oil_level":75}"#,
+    );
+
+    assert_eq!(
+        engine,
+        Ok(Engine {
+            speed: 3000.0,
+            oil_level: Some(75.0),
+            type_code: 7.0,
+            temperature: EngineTemperature {
+                inlet: 80.5,
+                outlet: Some(90.2),
+            },
+        })
+    );
+}
+
+#[test]
+fn optional_measurements_can_be_missing() {
+    let engine = parse(r#"{"speed":3000,"type_code":7,"temperature":{"inlet":80.5}}"#);
+
+    assert_eq!(engine.map(|engine| engine.oil_level), Ok(None));
+}
+
+#[test]
+fn missing_required_measurements_are_rejected() {
+    assert_eq!(
+        parse(r#"{"type_code":7,"temperature":{"inlet":80.5}}"#),
+        Err(TypedMeasurementError::MissingMeasurement {
+            name: "speed".into()
+        })
+    );
+    assert_eq!(
+        parse(r#"{"speed":3000,"type_code":7,"temperature":{"outlet":80.5}}"#),
+        Err(TypedMeasurementError::MissingMeasurement {
+            name: "temperature.inlet".into()
+        })
+    );
+    assert_eq!(
+        parse(r#"{"speed":3000,"type_code":7}"#),
+        Err(TypedMeasurementError::MissingGroup {
+            name: "temperature".into()
+        })
+    );
+}
+
+#[test]
+fn unknown_measurements_are_rejected() {
+    assert_eq!(
+        parse(r#"{"sped":3000,"type_code":7,"temperature":{"inlet":80.5}}"#),
+        Err(TypedMeasurementError::UnexpectedMeasurement {
+            name: "sped".into()
+        })
+    );
+    assert_eq!(
+        parse(r#"{"speed":3000,"type_code":7,"temperature":{"inlet":80.5,"inelt":80.5}}"#),
+        Err(TypedMeasurementError::UnexpectedMeasurement {
+            name: "temperature.inelt".into()
+        })
+    );
+    assert_eq!(
+        parse(r#"{"speed":3000,"type_code":7,"temperature":{"inlet":80.5},"pressure":{"oil":2}}"#),
+        Err(TypedMeasurementError::UnexpectedGroup {
+            name: "pressure".into()
+        })
+    );
+}
+
+#[test]
+fn a_typed_schema_is_visited_with_its_units() -> anyhow::Result<()> {
+    let engine = Engine {
+        speed: 3000.0,
+        oil_level: None,
+        type_code: 7.0,
+        temperature: EngineTemperature {
+            inlet: 80.5,
+            outlet: None,
+        },
+    };
+
+    let mut serializer = ThinEdgeJsonSerializer::new();
+    engine.visit(&mut serializer)?;
+
+    assert_eq!(
+        serializer.into_string()?,
+        r#"{"speed":{"value":3000.0,"unit":"rpm"},"oil_level":null,"type_code":7.0,"temperature":{"inlet":{"value":80.5,"unit":"°C"},"outlet":null}}"#
+    );
+    Ok(())
+}
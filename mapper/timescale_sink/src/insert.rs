@@ -0,0 +1,533 @@
+use chrono::offset::FixedOffset;
+use chrono::{DateTime, Utc};
+use log::{debug, error};
+use thin_edge_json::buffer::MeasurementBuffer;
+use thin_edge_json::context::MeasurementContext;
+use thin_edge_json::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use thin_edge_json::serialize::MeasurementStreamError;
+use thin_edge_json::trace::VisitorCall;
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tokio_postgres::types::Type;
+use tokio_postgres::{Client, NoTls};
+
+const DEFAULT_TABLE_NAME: &str = "measurements";
+
+/// The types of the columns filled by the `COPY` statement, in order
+const COLUMN_TYPES: [Type; 7] = [
+    Type::TIMESTAMPTZ,
+    Type::TEXT,
+    Type::TEXT,
+    Type::FLOAT8,
+    Type::TEXT,
+    Type::TEXT,
+    Type::TEXT,
+];
+
+#[derive(thiserror::Error, Debug)]
+pub enum TimescaleSinkError {
+    #[error("Invalid table name {name:?}: only ASCII letters, digits and underscores are allowed")]
+    InvalidTableName { name: String },
+
+    #[error("Failed to connect to the database: {0}")]
+    ConnectionError(tokio_postgres::Error),
+
+    #[error("Failed to create the hypertable {table:?}: {error}")]
+    SchemaError {
+        table: String,
+        error: tokio_postgres::Error,
+    },
+
+    #[error("Failed to insert the measurements into {table:?}: {error}")]
+    InsertError {
+        table: String,
+        error: tokio_postgres::Error,
+    },
+
+    #[error(transparent)]
+    InvalidMeasurements(#[from] MeasurementStreamError),
+}
+
+/// A row of the measurements table.
+#[derive(Debug, Clone, PartialEq)]
+struct MeasurementRow {
+    time: DateTime<Utc>,
+    group_name: Option<String>,
+    metric_name: String,
+    value: Option<f64>,
+    quality: Option<String>,
+    trace_id: Option<String>,
+    span_id: Option<String>,
+}
+
+/// A visitor that inserts the measurements into a TimescaleDB hypertable,
+/// one row per measurement:
+///
+/// ```sql
+/// measurements(time TIMESTAMPTZ, group_name TEXT, metric_name TEXT, value DOUBLE PRECISION,
+///     quality TEXT, trace_id TEXT, span_id TEXT)
+/// ```
+///
+/// The measurements are gathered and inserted on `flush()`, all at once using a `COPY` statement.
+///
+/// * The hypertable is created on the first flush if it doesn't exist,
+///   the `quality`, `trace_id` and `span_id` columns being added to a table created without.
+/// * The time of the rows is the timestamp of their group, if any, else of the measurements,
+///   or the time of the flush if the measurements have no timestamp.
+/// * The group name is `NULL` for the top-level measurements.
+/// * The value of a missing measurement is `NULL`.
+/// * A complex measurement is stored as two rows, `<name>_re` and `<name>_im`.
+/// * The quality is `NULL` unless given along the value.
+/// * The trace and span ids are those of the context of the measurements, in hexadecimal,
+///   and `NULL` if no context is set. The baggage is not stored.
+/// * The units of the measurements are not stored.
+/// * The measurements are discarded if the insertion fails.
+pub struct TimescaleVisitor {
+    client: Client,
+    table_name: String,
+    table_created: bool,
+    buffer: MeasurementBuffer,
+}
+
+impl TimescaleVisitor {
+    /// Connect to the database, given a `tokio_postgres` configuration string,
+    /// as `host=localhost user=postgres` or `postgresql://postgres@localhost/tedge`.
+    pub async fn connect(config: &str) -> Result<Self, TimescaleSinkError> {
+        let (client, connection) = tokio_postgres::connect(config, NoTls)
+            .await
+            .map_err(TimescaleSinkError::ConnectionError)?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                error!("Connection to the database lost: {}", err);
+            }
+        });
+
+        Ok(Self {
+            client,
+            table_name: DEFAULT_TABLE_NAME.to_string(),
+            table_created: false,
+            buffer: MeasurementBuffer::new(),
+        })
+    }
+
+    /// Set the name of the hypertable, `measurements` by default.
+    pub fn with_table_name(self, table_name: &str) -> Result<Self, TimescaleSinkError> {
+        if !is_identifier(table_name) {
+            return Err(TimescaleSinkError::InvalidTableName {
+                name: table_name.to_string(),
+            });
+        }
+        Ok(Self {
+            table_name: table_name.to_string(),
+            ..self
+        })
+    }
+
+    /// Insert the measurements gathered since the previous flush.
+    ///
+    /// Nothing is inserted if no measurements have been gathered.
+    pub async fn flush(&mut self) -> Result<(), TimescaleSinkError> {
+        let buffer = std::mem::take(&mut self.buffer);
+        if !buffer.has_measurements() {
+            return Ok(());
+        }
+        let rows = rows(&buffer, Utc::now())?;
+
+        if !self.table_created {
+            self.create_table().await?;
+        }
+        let count = self.copy_rows(&rows).await.map_err(|error| {
+            let table = self.table_name.clone();
+            TimescaleSinkError::InsertError { table, error }
+        })?;
+        debug!("{} measurements inserted into {}", count, self.table_name);
+        Ok(())
+    }
+
+    async fn create_table(&mut self) -> Result<(), TimescaleSinkError> {
+        self.client
+            .batch_execute(&create_table_statements(&self.table_name))
+            .await
+            .map_err(|error| TimescaleSinkError::SchemaError {
+                table: self.table_name.clone(),
+                error,
+            })?;
+        self.table_created = true;
+        Ok(())
+    }
+
+    async fn copy_rows(&self, rows: &[MeasurementRow]) -> Result<u64, tokio_postgres::Error> {
+        let sink = self
+            .client
+            .copy_in(copy_statement(&self.table_name).as_str())
+            .await?;
+        let writer = BinaryCopyInWriter::new(sink, &COLUMN_TYPES);
+        tokio::pin!(writer);
+        for row in rows.iter() {
+            writer
+                .as_mut()
+                .write(&[
+                    &row.time,
+                    &row.group_name,
+                    &row.metric_name,
+                    &row.value,
+                    &row.quality,
+                    &row.trace_id,
+                    &row.span_id,
+                ])
+                .await?;
+        }
+        writer.finish().await
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+fn create_table_statements(table_name: &str) -> String {
+    format!(
+        "CREATE TABLE IF NOT EXISTS {table} (
+            time TIMESTAMPTZ NOT NULL,
+            group_name TEXT,
+            metric_name TEXT NOT NULL,
+            value DOUBLE PRECISION,
+            quality TEXT,
+            trace_id TEXT,
+            span_id TEXT
+        );
+        ALTER TABLE {table} ADD COLUMN IF NOT EXISTS quality TEXT,
+            ADD COLUMN IF NOT EXISTS trace_id TEXT,
+            ADD COLUMN IF NOT EXISTS span_id TEXT;
+        SELECT create_hypertable('{table}', 'time', if_not_exists => TRUE);",
+        table = table_name
+    )
+}
+
+fn copy_statement(table_name: &str) -> String {
+    format!(
+        "COPY {} (time, group_name, metric_name, value, quality, trace_id, span_id) FROM STDIN BINARY",
+        table_name
+    )
+}
+
+/// One row per measurement, timestamped with the time of its group, of the series or `now` if none
+fn rows(
+    buffer: &MeasurementBuffer,
+    now: DateTime<Utc>,
+) -> Result<Vec<MeasurementRow>, MeasurementStreamError> {
+    let context = buffer.events().iter().rev().find_map(|event| match event {
+        VisitorCall::SetContext { context } => Some(context),
+        _ => None,
+    });
+    let trace_id = context.map(MeasurementContext::trace_id_hex);
+    let span_id = context.map(MeasurementContext::span_id_hex);
+
+    let mut rows = Vec::new();
+    for (group, split) in buffer.split_by_group()? {
+        let mut time = now;
+        let mut push = |time: DateTime<Utc>,
+                        name: &str,
+                        value: Option<f64>,
+                        quality: Option<MeasurementQuality>| {
+            rows.push(MeasurementRow {
+                time,
+                group_name: group.map(String::from),
+                metric_name: name.to_string(),
+                value,
+                quality: quality.map(|quality| quality.as_str().to_string()),
+                trace_id: trace_id.clone(),
+                span_id: span_id.clone(),
+            })
+        };
+        for event in split.events() {
+            match event {
+                VisitorCall::Timestamp { value }
+                | VisitorCall::StartGroupWithTimestamp { value, .. } => {
+                    time = value.with_timezone(&Utc)
+                }
+                VisitorCall::Measurement { name, value }
+                | VisitorCall::MeasurementWithUnit { name, value, .. } => {
+                    push(time, name, Some(*value), None)
+                }
+                VisitorCall::NullableMeasurement { name, value } => push(time, name, *value, None),
+                VisitorCall::ComplexMeasurement {
+                    name,
+                    real,
+                    imaginary,
+                } => {
+                    push(time, &format!("{}_re", name), Some(*real), None);
+                    push(time, &format!("{}_im", name), Some(*imaginary), None);
+                }
+                VisitorCall::AnnotatedMeasurement {
+                    name,
+                    value,
+                    quality,
+                } => push(time, name, Some(*value), Some(*quality)),
+                VisitorCall::StartGroup { .. }
+                | VisitorCall::EndGroup
+                | VisitorCall::SetContext { .. } => {}
+            }
+        }
+    }
+    Ok(rows)
+}
+
+impl GroupedMeasurementVisitor for TimescaleVisitor {
+    type Error = MeasurementStreamError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        Ok(self.buffer.timestamp(value)?)
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        Ok(self.buffer.measurement(name, value)?)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        Ok(self.buffer.start_group(group)?)
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        Ok(self.buffer.end_group()?)
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        Ok(self.buffer.measurement_with_unit(name, value, unit)?)
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        value: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        Ok(self.buffer.start_group_with_timestamp(group, value)?)
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        Ok(self.buffer.nullable_measurement(name, value)?)
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        Ok(self.buffer.complex_measurement(name, real, imaginary)?)
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        Ok(self.buffer.annotated_measurement(name, value, quality)?)
+    }
+
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        Ok(self.buffer.set_context(context)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use thin_edge_json::json::parse_str;
+
+    fn row(time: DateTime<Utc>, group: Option<&str>, name: &str, value: f64) -> MeasurementRow {
+        MeasurementRow {
+            time,
+            group_name: group.map(String::from),
+            metric_name: name.to_string(),
+            value: Some(value),
+            quality: None,
+            trace_id: None,
+            span_id: None,
+        }
+    }
+
+    #[test]
+    fn each_measurement_is_a_row() -> anyhow::Result<()> {
+        let mut buffer = MeasurementBuffer::new();
+        parse_str(
+            r#"{"time":"2021-04-30T17:03:14+02:00","temperature":25.5,"location":{"alti":2100.4}}"#,
+            &mut buffer,
+        )?;
+        let time = Utc.ymd(2021, 4, 30).and_hms(15, 3, 14);
+
+        assert_eq!(
+            rows(&buffer, Utc::now())?,
+            vec![
+                row(time, None, "temperature", 25.5),
+                row(time, Some("location"), "alti", 2100.4),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn measurements_with_no_timestamp_are_inserted_at_the_flush_time() -> anyhow::Result<()> {
+        let mut buffer = MeasurementBuffer::new();
+        parse_str(r#"{"temperature":25.5}"#, &mut buffer)?;
+        let now = Utc.ymd(2021, 5, 1).and_hms(8, 0, 0);
+
+        assert_eq!(
+            rows(&buffer, now)?,
+            vec![row(now, None, "temperature", 25.5)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn missing_values_are_null() -> anyhow::Result<()> {
+        let mut buffer = MeasurementBuffer::new();
+        buffer.nullable_measurement("temperature", None)?;
+        let now = Utc.ymd(2021, 5, 1).and_hms(8, 0, 0);
+
+        assert_eq!(
+            rows(&buffer, now)?,
+            vec![MeasurementRow {
+                value: None,
+                ..row(now, None, "temperature", 0.0)
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn the_quality_is_stored_along_the_value() -> anyhow::Result<()> {
+        let mut buffer = MeasurementBuffer::new();
+        buffer.annotated_measurement("temperature", 25.5, MeasurementQuality::Uncertain)?;
+        let now = Utc.ymd(2021, 5, 1).and_hms(8, 0, 0);
+
+        assert_eq!(
+            rows(&buffer, now)?,
+            vec![MeasurementRow {
+                quality: Some("UNCERTAIN".to_string()),
+                ..row(now, None, "temperature", 25.5)
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn the_trace_context_is_stored_along_all_the_rows() -> anyhow::Result<()> {
+        let mut buffer = MeasurementBuffer::new();
+        buffer.measurement("temperature", 25.5)?;
+        buffer.start_group("location")?;
+        buffer.measurement("alti", 2100.4)?;
+        buffer.end_group()?;
+        buffer.set_context(MeasurementContext::new([0x4b; 16], [0xf0; 8]))?;
+        let now = Utc.ymd(2021, 5, 1).and_hms(8, 0, 0);
+
+        let traced = |row: MeasurementRow| MeasurementRow {
+            trace_id: Some("4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b".to_string()),
+            span_id: Some("f0f0f0f0f0f0f0f0".to_string()),
+            ..row
+        };
+        assert_eq!(
+            rows(&buffer, now)?,
+            vec![
+                traced(row(now, None, "temperature", 25.5)),
+                traced(row(now, Some("location"), "alti", 2100.4)),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn complex_values_are_split_into_two_rows() -> anyhow::Result<()> {
+        let mut buffer = MeasurementBuffer::new();
+        buffer.start_group("phase")?;
+        buffer.complex_measurement("current", 1.5, -0.5)?;
+        buffer.end_group()?;
+        let now = Utc.ymd(2021, 5, 1).and_hms(8, 0, 0);
+
+        assert_eq!(
+            rows(&buffer, now)?,
+            vec![
+                row(now, Some("phase"), "current_re", 1.5),
+                row(now, Some("phase"), "current_im", -0.5),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn the_rows_of_a_group_are_timestamped_with_the_time_of_the_group() -> anyhow::Result<()> {
+        let mut buffer = MeasurementBuffer::new();
+        parse_str(
+            r#"{"time":"2021-04-30T17:03:14+02:00","temperature":25.5,"engine":{"time":"2021-04-30T17:03:10+02:00","speed":3000.0}}"#,
+            &mut buffer,
+        )?;
+
+        assert_eq!(
+            rows(&buffer, Utc::now())?,
+            vec![
+                row(
+                    Utc.ymd(2021, 4, 30).and_hms(15, 3, 14),
+                    None,
+                    "temperature",
+                    25.5
+                ),
+                row(
+                    Utc.ymd(2021, 4, 30).and_hms(15, 3, 10),
+                    Some("engine"),
+                    "speed",
+                    3000.0
+                ),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unbalanced_groups_are_rejected() -> anyhow::Result<()> {
+        let mut buffer = MeasurementBuffer::new();
+        buffer.start_group("location")?;
+        buffer.measurement("alti", 2100.4)?;
+
+        assert!(matches!(
+            rows(&buffer, Utc::now()),
+            Err(MeasurementStreamError::UnexpectedEndOfData)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn table_names_are_sql_identifiers() {
+        assert!(is_identifier("measurements"));
+        assert!(is_identifier("_tedge_measurements_2"));
+        assert!(!is_identifier(""));
+        assert!(!is_identifier("2measurements"));
+        assert!(!is_identifier("measurements; DROP TABLE users"));
+        assert!(!is_identifier("\"measurements\""));
+    }
+
+    #[test]
+    fn the_table_is_created_as_a_hypertable() {
+        let statements = create_table_statements("tedge");
+
+        assert!(statements.contains("CREATE TABLE IF NOT EXISTS tedge ("));
+        assert!(statements
+            .contains("SELECT create_hypertable('tedge', 'time', if_not_exists => TRUE);"));
+        assert_eq!(
+            copy_statement("tedge"),
+            "COPY tedge (time, group_name, metric_name, value, quality, trace_id, span_id) FROM STDIN BINARY"
+        );
+        assert!(statements.contains("ALTER TABLE tedge ADD COLUMN IF NOT EXISTS quality TEXT,"));
+    }
+}
@@ -0,0 +1,24 @@
+//! A sink inserting thin-edge JSON measurements into a TimescaleDB hypertable.
+//!
+//! ```no_run
+//! use thin_edge_json::measurement::GroupedMeasurementVisitor;
+//! use timescale_sink::TimescaleVisitor;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), anyhow::Error> {
+//! let mut visitor =
+//!     TimescaleVisitor::connect("host=localhost user=postgres password=secret").await?;
+//!
+//! visitor.measurement("temperature", 25.5)?;
+//! visitor.start_group("location")?;
+//! visitor.measurement("alti", 2100.4)?;
+//! visitor.end_group()?;
+//!
+//! // Inserts the rows (now, NULL, 'temperature', 25.5) and (now, 'location', 'alti', 2100.4)
+//! visitor.flush().await?;
+//! # Ok(()) }
+//! ```
+
+mod insert;
+
+pub use insert::{TimescaleSinkError, TimescaleVisitor};
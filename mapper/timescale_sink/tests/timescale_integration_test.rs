@@ -0,0 +1,184 @@
+#![cfg(feature = "integration-test")]
+// These tests require a docker daemon to start a TimescaleDB server.
+// Run them by calling 'cargo test --features integration-test' from the base path of the crate
+
+use chrono::{DateTime, TimeZone, Utc};
+use std::time::Duration;
+use testcontainers::images::generic::{GenericImage, WaitFor};
+use testcontainers::{clients, Container, Docker};
+use thin_edge_json::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use timescale_sink::TimescaleVisitor;
+use tokio_postgres::{Client, NoTls};
+
+fn timescaledb() -> GenericImage {
+    GenericImage::new("timescale/timescaledb:2.4.2-pg13")
+        .with_env_var("POSTGRES_PASSWORD", "tedge")
+        .with_wait_for(WaitFor::message_on_stderr(
+            "database system is ready to accept connections",
+        ))
+}
+
+fn database_config(server: &Container<clients::Cli, GenericImage>) -> String {
+    let port = server
+        .get_host_port(5432)
+        .expect("The server port is exposed");
+    format!("host=localhost port={} user=postgres password=tedge", port)
+}
+
+/// Connect once the server is ready,
+/// the server being restarted once initialized, after a first ready message
+async fn visitor(config: &str) -> anyhow::Result<TimescaleVisitor> {
+    let mut attempts = 0;
+    loop {
+        match TimescaleVisitor::connect(config).await {
+            Ok(visitor) => return Ok(visitor),
+            Err(_) if attempts < 30 => {
+                attempts += 1;
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+async fn client(config: &str) -> anyhow::Result<Client> {
+    let (client, connection) = tokio_postgres::connect(config, NoTls).await?;
+    tokio::spawn(connection);
+    Ok(client)
+}
+
+async fn measurements(
+    client: &Client,
+    table: &str,
+) -> anyhow::Result<Vec<(DateTime<Utc>, Option<String>, String, f64)>> {
+    let query = format!(
+        "SELECT time, group_name, metric_name, value FROM {} ORDER BY time, metric_name",
+        table
+    );
+    let rows = client.query(query.as_str(), &[]).await?;
+    Ok(rows
+        .iter()
+        .map(|row| (row.get(0), row.get(1), row.get(2), row.get(3)))
+        .collect())
+}
+
+#[tokio::test]
+async fn measurements_are_inserted_into_a_hypertable() -> anyhow::Result<()> {
+    let docker = clients::Cli::default();
+    let server = docker.run(timescaledb());
+    let config = database_config(&server);
+
+    let mut visitor = visitor(&config).await?;
+    visitor.timestamp(DateTime::parse_from_rfc3339("2021-04-30T17:03:14+02:00")?)?;
+    visitor.measurement_with_unit("temperature", 25.5, "°C")?;
+    visitor.start_group("location")?;
+    visitor.measurement("alti", 2100.4)?;
+    visitor.measurement("longi", 2200.4)?;
+    visitor.end_group()?;
+    visitor.flush().await?;
+
+    let client = client(&config).await?;
+    let time = Utc.ymd(2021, 4, 30).and_hms(15, 3, 14);
+    assert_eq!(
+        measurements(&client, "measurements").await?,
+        vec![
+            (
+                time,
+                Some("location".to_string()),
+                "alti".to_string(),
+                2100.4
+            ),
+            (
+                time,
+                Some("location".to_string()),
+                "longi".to_string(),
+                2200.4
+            ),
+            (time, None, "temperature".to_string(), 25.5),
+        ]
+    );
+
+    let hypertables = client
+        .query(
+            "SELECT hypertable_name FROM timescaledb_information.hypertables",
+            &[],
+        )
+        .await?;
+    assert_eq!(
+        hypertables
+            .iter()
+            .map(|row| row.get(0))
+            .collect::<Vec<String>>(),
+        vec!["measurements".to_string()]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn successive_flushes_append_to_the_table() -> anyhow::Result<()> {
+    let docker = clients::Cli::default();
+    let server = docker.run(timescaledb());
+    let config = database_config(&server);
+
+    let mut visitor = visitor(&config)
+        .await?
+        .with_table_name("tedge_measurements")?;
+    for (second, value) in [(0, 25.5), (10, 26.0)].iter() {
+        visitor.timestamp(Utc.ymd(2021, 4, 30).and_hms(15, 3, *second).into())?;
+        visitor.measurement("temperature", *value)?;
+        visitor.flush().await?;
+    }
+    // Nothing is inserted by an empty flush
+    visitor.flush().await?;
+
+    let client = client(&config).await?;
+    assert_eq!(
+        measurements(&client, "tedge_measurements").await?,
+        vec![
+            (
+                Utc.ymd(2021, 4, 30).and_hms(15, 3, 0),
+                None,
+                "temperature".to_string(),
+                25.5
+            ),
+            (
+                Utc.ymd(2021, 4, 30).and_hms(15, 3, 10),
+                None,
+                "temperature".to_string(),
+                26.0
+            ),
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn missing_values_and_qualities_are_inserted() -> anyhow::Result<()> {
+    let docker = clients::Cli::default();
+    let server = docker.run(timescaledb());
+    let config = database_config(&server);
+
+    let mut visitor = visitor(&config).await?;
+    visitor.timestamp(Utc.ymd(2021, 4, 30).and_hms(15, 3, 14).into())?;
+    visitor.nullable_measurement("temperature", None)?;
+    visitor.annotated_measurement("pressure", 98.0, MeasurementQuality::Bad)?;
+    visitor.flush().await?;
+
+    let client = client(&config).await?;
+    let rows = client
+        .query(
+            "SELECT metric_name, value, quality FROM measurements ORDER BY metric_name",
+            &[],
+        )
+        .await?;
+    assert_eq!(
+        rows.iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2)))
+            .collect::<Vec<(String, Option<f64>, Option<String>)>>(),
+        vec![
+            ("pressure".to_string(), Some(98.0), Some("BAD".to_string())),
+            ("temperature".to_string(), None, None),
+        ]
+    );
+    Ok(())
+}
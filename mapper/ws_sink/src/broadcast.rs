@@ -0,0 +1,460 @@
+use crate::cache::{CachedValue, LastValueCache};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use futures_util::{SinkExt, StreamExt};
+use log::{error, warn};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use thin_edge_json::context::MeasurementContext;
+use thin_edge_json::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+use thin_edge_json::serialize::{ThinEdgeJsonSerializationError, ThinEdgeJsonSerializer};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+
+/// The number of payloads a slow client can be late of before missing payloads
+pub(crate) const CHANNEL_CAPACITY: usize = 64;
+
+#[derive(thiserror::Error, Debug)]
+pub enum WebSocketSinkError {
+    #[error("Failed to listen on {addr}: {from}")]
+    BindError {
+        addr: SocketAddr,
+        from: std::io::Error,
+    },
+
+    #[error(transparent)]
+    WebSocketError(#[from] tokio_tungstenite::tungstenite::Error),
+
+    #[error(transparent)]
+    SerializationError(#[from] ThinEdgeJsonSerializationError),
+}
+
+/// A visitor that broadcasts thin-edge JSON payloads to WebSocket clients.
+///
+/// The measurements are gathered into a thin-edge JSON payload
+/// which is sent to all the connected clients on `flush()`.
+///
+/// A client connecting mid-stream first receives a payload
+/// with the last known value of each measurement,
+/// then all the payloads flushed after its connection.
+pub struct WebSocketBroadcastVisitor {
+    hub: Arc<Hub>,
+    local_addr: SocketAddr,
+    server: JoinHandle<()>,
+    serializer: ThinEdgeJsonSerializer,
+    timestamp: Option<DateTime<FixedOffset>>,
+    group_timestamps: Vec<(String, DateTime<FixedOffset>)>,
+    updates: Vec<(Option<String>, String, CachedValue)>,
+    group: Option<String>,
+}
+
+impl WebSocketBroadcastVisitor {
+    /// Start to accept WebSocket connections on the given address.
+    ///
+    /// The server runs on the current tokio runtime until the visitor is dropped.
+    pub async fn bind(addr: SocketAddr) -> Result<Self, WebSocketSinkError> {
+        let bind_error = |from| WebSocketSinkError::BindError { addr, from };
+        let listener = TcpListener::bind(addr).await.map_err(bind_error)?;
+        let local_addr = listener.local_addr().map_err(bind_error)?;
+
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        let hub = Arc::new(Hub {
+            cache: Mutex::new(LastValueCache::new()),
+            sender,
+        });
+        let server = tokio::spawn(accept_connections(listener, hub.clone()));
+
+        Ok(Self {
+            hub,
+            local_addr,
+            server,
+            serializer: ThinEdgeJsonSerializer::new(),
+            timestamp: None,
+            group_timestamps: Vec::new(),
+            updates: Vec::new(),
+            group: None,
+        })
+    }
+
+    /// The address the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// The number of clients currently connected
+    pub fn connection_count(&self) -> usize {
+        self.hub.sender.receiver_count()
+    }
+
+    /// Broadcast the measurements gathered since the previous flush.
+    ///
+    /// Nothing is sent if no measurements have been gathered.
+    pub fn flush(&mut self) -> Result<(), ThinEdgeJsonSerializationError> {
+        let mut serializer = std::mem::take(&mut self.serializer);
+        let timestamp = self.timestamp.take();
+        let group_timestamps = std::mem::take(&mut self.group_timestamps);
+        let updates = std::mem::take(&mut self.updates);
+        self.group = None;
+
+        let payload = serializer.into_string()?;
+        if !updates.is_empty() {
+            self.hub
+                .publish(timestamp, group_timestamps, updates, payload);
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, name: &str, value: CachedValue) {
+        self.updates
+            .push((self.group.clone(), name.to_string(), value));
+    }
+}
+
+impl Drop for WebSocketBroadcastVisitor {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+impl GroupedMeasurementVisitor for WebSocketBroadcastVisitor {
+    type Error = ThinEdgeJsonSerializationError;
+
+    fn timestamp(&mut self, value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        self.serializer.timestamp(value)?;
+        self.timestamp = Some(value);
+        Ok(())
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.serializer.measurement(name, value)?;
+        self.record(name, CachedValue::Value { value, unit: None });
+        Ok(())
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        self.serializer.start_group(group)?;
+        self.group = Some(group.to_string());
+        Ok(())
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        self.serializer.end_group()?;
+        self.group = None;
+        Ok(())
+    }
+
+    fn measurement_with_unit(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+    ) -> Result<(), Self::Error> {
+        self.serializer.measurement_with_unit(name, value, unit)?;
+        self.record(
+            name,
+            CachedValue::Value {
+                value,
+                unit: Some(unit.to_string()),
+            },
+        );
+        Ok(())
+    }
+
+    fn start_group_with_timestamp(
+        &mut self,
+        group: &str,
+        value: DateTime<FixedOffset>,
+    ) -> Result<(), Self::Error> {
+        self.serializer.start_group_with_timestamp(group, value)?;
+        self.group = Some(group.to_string());
+        self.group_timestamps.push((group.to_string(), value));
+        Ok(())
+    }
+
+    fn nullable_measurement(&mut self, name: &str, value: Option<f64>) -> Result<(), Self::Error> {
+        match value {
+            Some(value) => self.measurement(name, value),
+            None => {
+                self.serializer.nullable_measurement(name, None)?;
+                self.record(name, CachedValue::Missing);
+                Ok(())
+            }
+        }
+    }
+
+    fn complex_measurement(
+        &mut self,
+        name: &str,
+        real: f64,
+        imaginary: f64,
+    ) -> Result<(), Self::Error> {
+        self.serializer.complex_measurement(name, real, imaginary)?;
+        self.record(name, CachedValue::Complex { real, imaginary });
+        Ok(())
+    }
+
+    fn annotated_measurement(
+        &mut self,
+        name: &str,
+        value: f64,
+        quality: MeasurementQuality,
+    ) -> Result<(), Self::Error> {
+        self.serializer
+            .annotated_measurement(name, value, quality)?;
+        self.record(name, CachedValue::Annotated { value, quality });
+        Ok(())
+    }
+
+    /// The context is only sent along the payload it has been set for,
+    /// and not to the clients connecting later.
+    fn set_context(&mut self, context: MeasurementContext) -> Result<(), Self::Error> {
+        self.serializer.set_context(context)
+    }
+}
+
+/// The state shared by the visitor and the client connections
+struct Hub {
+    cache: Mutex<LastValueCache>,
+    sender: broadcast::Sender<String>,
+}
+
+impl Hub {
+    fn publish(
+        &self,
+        timestamp: Option<DateTime<FixedOffset>>,
+        group_timestamps: Vec<(String, DateTime<FixedOffset>)>,
+        updates: Vec<(Option<String>, String, CachedValue)>,
+        payload: String,
+    ) {
+        // The cache is updated and the payload sent under the same lock,
+        // so a new client gets either the payload or the updated cache, and never both.
+        let mut cache = self.cache.lock().expect("Poisoned lock");
+        if let Some(timestamp) = timestamp {
+            cache.set_timestamp(timestamp);
+        }
+        for (group, timestamp) in group_timestamps {
+            cache.set_group_timestamp(&group, timestamp);
+        }
+        for (group, name, value) in updates {
+            cache.update(group.as_deref(), &name, value);
+        }
+
+        // An error only means that no client is connected
+        let _ = self.sender.send(payload);
+    }
+}
+
+/// The source of the payloads sent to the WebSocket clients
+pub(crate) trait PayloadSource: Send + Sync + 'static {
+    /// The payloads to be sent first to a new client, along with a receiver for the payloads to come
+    fn subscribe(&self) -> Result<(Vec<String>, broadcast::Receiver<String>), WebSocketSinkError>;
+}
+
+impl PayloadSource for Hub {
+    /// The last known values along with a receiver for the payloads to come
+    fn subscribe(&self) -> Result<(Vec<String>, broadcast::Receiver<String>), WebSocketSinkError> {
+        let cache = self.cache.lock().expect("Poisoned lock");
+        let mut snapshot = Vec::new();
+        if !cache.is_empty() {
+            let mut serializer = ThinEdgeJsonSerializer::new();
+            cache.visit(&mut serializer)?;
+            snapshot.push(serializer.into_string()?);
+        }
+        Ok((snapshot, self.sender.subscribe()))
+    }
+}
+
+pub(crate) async fn accept_connections<S: PayloadSource>(listener: TcpListener, hub: Arc<S>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let hub = hub.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = serve_client(stream, hub).await {
+                        warn!("WebSocket connection with {} failed: {}", peer, err);
+                    }
+                });
+            }
+            Err(err) => error!("Failed to accept a WebSocket connection: {}", err),
+        }
+    }
+}
+
+async fn serve_client<S: PayloadSource>(
+    stream: TcpStream,
+    hub: Arc<S>,
+) -> Result<(), WebSocketSinkError> {
+    let mut ws = tokio_tungstenite::accept_async(stream).await?;
+    let (snapshot, mut payloads) = hub.subscribe()?;
+
+    // Release the hub, so the connection is closed when the visitor is dropped
+    drop(hub);
+
+    for payload in snapshot {
+        ws.send(Message::Text(payload)).await?;
+    }
+
+    loop {
+        tokio::select! {
+            payload = payloads.recv() => match payload {
+                Ok(payload) => ws.send(Message::Text(payload)).await?,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!("A slow WebSocket client missed {} payloads", missed)
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            incoming = ws.next() => match incoming {
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Err(err.into()),
+            },
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::{sleep, timeout};
+    use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    async fn start_server() -> anyhow::Result<WebSocketBroadcastVisitor> {
+        Ok(WebSocketBroadcastVisitor::bind("127.0.0.1:0".parse()?).await?)
+    }
+
+    async fn connect(visitor: &WebSocketBroadcastVisitor) -> anyhow::Result<Client> {
+        let url = format!("ws://{}", visitor.local_addr());
+        let (client, _) = connect_async(url).await?;
+        Ok(client)
+    }
+
+    async fn next_payload(client: &mut Client) -> anyhow::Result<String> {
+        match timeout(Duration::from_secs(2), client.next()).await? {
+            Some(Ok(Message::Text(payload))) => Ok(payload),
+            other => anyhow::bail!("Unexpected message: {:?}", other),
+        }
+    }
+
+    async fn wait_for_connections(visitor: &WebSocketBroadcastVisitor, count: usize) {
+        while visitor.connection_count() < count {
+            sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn a_new_client_receives_the_last_values_then_the_next_payloads() -> anyhow::Result<()> {
+        let mut visitor = start_server().await?;
+        visitor.measurement("temperature", 20.0)?;
+        visitor.measurement_with_unit("pressure", 98.0, "kPa")?;
+        visitor.flush()?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.end_group()?;
+        visitor.measurement("temperature", 21.0)?;
+        visitor.flush()?;
+
+        let mut client = connect(&visitor).await?;
+        assert_eq!(
+            next_payload(&mut client).await?,
+            r#"{"pressure":{"value":98.0,"unit":"kPa"},"temperature":21.0,"location":{"alti":2100.4}}"#
+        );
+
+        visitor.measurement("temperature", 22.0)?;
+        visitor.flush()?;
+        assert_eq!(next_payload(&mut client).await?, r#"{"temperature":22.0}"#);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn all_kinds_of_measurements_are_broadcast_and_cached() -> anyhow::Result<()> {
+        let mut visitor = start_server().await?;
+        let mut first = connect(&visitor).await?;
+        timeout(Duration::from_secs(2), wait_for_connections(&visitor, 1)).await?;
+
+        visitor.nullable_measurement("temperature", None)?;
+        visitor.annotated_measurement("pressure", 98.0, MeasurementQuality::Uncertain)?;
+        visitor.start_group_with_timestamp(
+            "phase",
+            DateTime::parse_from_rfc3339("2021-04-30T17:03:14+02:00")?,
+        )?;
+        visitor.complex_measurement("current", 1.5, -0.5)?;
+        visitor.end_group()?;
+        visitor.flush()?;
+
+        assert_eq!(
+            next_payload(&mut first).await?,
+            concat!(
+                r#"{"temperature":null,"pressure":{"value":98.0,"quality":"UNCERTAIN"},"#,
+                r#""phase":{"time":"2021-04-30T17:03:14+02:00","current":{"re":1.5,"im":-0.5}}}"#
+            )
+        );
+
+        let mut second = connect(&visitor).await?;
+        assert_eq!(
+            next_payload(&mut second).await?,
+            concat!(
+                r#"{"pressure":{"value":98.0,"quality":"UNCERTAIN"},"temperature":null,"#,
+                r#""phase":{"time":"2021-04-30T17:03:14+02:00","current":{"re":1.5,"im":-0.5}}}"#
+            )
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn the_context_is_sent_along_its_payload_only() -> anyhow::Result<()> {
+        let mut visitor = start_server().await?;
+        let mut first = connect(&visitor).await?;
+        timeout(Duration::from_secs(2), wait_for_connections(&visitor, 1)).await?;
+
+        visitor.set_context(MeasurementContext::new([0x4b; 16], [0xf0; 8]))?;
+        visitor.measurement("temperature", 25.0)?;
+        visitor.flush()?;
+
+        assert_eq!(
+            next_payload(&mut first).await?,
+            r#"{"_traceId":"4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b","_spanId":"f0f0f0f0f0f0f0f0","temperature":25.0}"#
+        );
+
+        let mut second = connect(&visitor).await?;
+        assert_eq!(next_payload(&mut second).await?, r#"{"temperature":25.0}"#);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn payloads_are_broadcast_to_all_the_clients() -> anyhow::Result<()> {
+        let mut visitor = start_server().await?;
+        let mut first = connect(&visitor).await?;
+        let mut second = connect(&visitor).await?;
+        timeout(Duration::from_secs(2), wait_for_connections(&visitor, 2)).await?;
+
+        visitor.measurement("temperature", 25.0)?;
+        visitor.flush()?;
+
+        assert_eq!(next_payload(&mut first).await?, r#"{"temperature":25.0}"#);
+        assert_eq!(next_payload(&mut second).await?, r#"{"temperature":25.0}"#);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn closed_connections_are_removed() -> anyhow::Result<()> {
+        let visitor = start_server().await?;
+        let mut client = connect(&visitor).await?;
+        timeout(Duration::from_secs(2), wait_for_connections(&visitor, 1)).await?;
+
+        client.close(None).await?;
+
+        timeout(Duration::from_secs(2), async {
+            while visitor.connection_count() > 0 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await?;
+        Ok(())
+    }
+}
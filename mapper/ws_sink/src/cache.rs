@@ -0,0 +1,225 @@
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use std::collections::BTreeMap;
+use thin_edge_json::measurement::{GroupedMeasurementVisitor, MeasurementQuality};
+
+/// The last known value of a measurement
+#[derive(Debug, Clone, PartialEq)]
+pub enum CachedValue {
+    /// A plain value, with an optional unit
+    Value {
+        value: f64,
+        unit: Option<String>,
+    },
+
+    /// The measurement was last given with no value
+    Missing,
+
+    Complex {
+        real: f64,
+        imaginary: f64,
+    },
+
+    /// A value given along its quality
+    Annotated {
+        value: f64,
+        quality: MeasurementQuality,
+    },
+}
+
+/// The last known value of each measurement,
+/// indexed by measurement name and group.
+///
+/// A measurement replaces any group with the same name, and vice versa,
+/// so the cache can always be rendered as a valid thin-edge JSON payload.
+#[derive(Debug, Clone, Default)]
+pub struct LastValueCache {
+    timestamp: Option<DateTime<FixedOffset>>,
+    measurements: BTreeMap<String, CachedValue>,
+    groups: BTreeMap<String, BTreeMap<String, CachedValue>>,
+    group_timestamps: BTreeMap<String, DateTime<FixedOffset>>,
+}
+
+impl LastValueCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.measurements.is_empty() && self.groups.is_empty()
+    }
+
+    pub fn set_timestamp(&mut self, timestamp: DateTime<FixedOffset>) {
+        self.timestamp = Some(timestamp);
+    }
+
+    /// Set the timestamp of a group, sent along the group until replaced.
+    ///
+    /// The timestamp is dropped along the group, when replaced by a measurement.
+    pub fn set_group_timestamp(&mut self, group: &str, timestamp: DateTime<FixedOffset>) {
+        self.group_timestamps.insert(group.to_string(), timestamp);
+    }
+
+    pub fn update(&mut self, group: Option<&str>, name: &str, value: CachedValue) {
+        match group {
+            Some(group) => {
+                self.measurements.remove(group);
+                self.groups
+                    .entry(group.to_string())
+                    .or_default()
+                    .insert(name.to_string(), value);
+            }
+            None => {
+                self.groups.remove(name);
+                self.group_timestamps.remove(name);
+                self.measurements.insert(name.to_string(), value);
+            }
+        }
+    }
+
+    pub fn get(&self, group: Option<&str>, name: &str) -> Option<&CachedValue> {
+        match group {
+            Some(group) => self.groups.get(group)?.get(name),
+            None => self.measurements.get(name),
+        }
+    }
+
+    /// Replay the cached values to a visitor: the timestamp,
+    /// the measurements with no group, then the groups, both sorted by name.
+    pub fn visit<V>(&self, visitor: &mut V) -> Result<(), V::Error>
+    where
+        V: GroupedMeasurementVisitor,
+    {
+        if let Some(timestamp) = self.timestamp {
+            visitor.timestamp(timestamp)?;
+        }
+        for (name, value) in self.measurements.iter() {
+            visit_value(visitor, name, value)?;
+        }
+        for (group, measurements) in self.groups.iter() {
+            match self.group_timestamps.get(group) {
+                Some(timestamp) => visitor.start_group_with_timestamp(group, *timestamp)?,
+                None => visitor.start_group(group)?,
+            }
+            for (name, value) in measurements.iter() {
+                visit_value(visitor, name, value)?;
+            }
+            visitor.end_group()?;
+        }
+        Ok(())
+    }
+}
+
+fn visit_value<V>(visitor: &mut V, name: &str, value: &CachedValue) -> Result<(), V::Error>
+where
+    V: GroupedMeasurementVisitor,
+{
+    match value {
+        CachedValue::Value {
+            value,
+            unit: Some(unit),
+        } => visitor.measurement_with_unit(name, *value, unit),
+        CachedValue::Value { value, unit: None } => visitor.measurement(name, *value),
+        CachedValue::Missing => visitor.nullable_measurement(name, None),
+        CachedValue::Complex { real, imaginary } => {
+            visitor.complex_measurement(name, *real, *imaginary)
+        }
+        CachedValue::Annotated { value, quality } => {
+            visitor.annotated_measurement(name, *value, *quality)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+
+    fn plain(value: f64) -> CachedValue {
+        CachedValue::Value { value, unit: None }
+    }
+
+    fn to_json(cache: &LastValueCache) -> anyhow::Result<String> {
+        let mut serializer = ThinEdgeJsonSerializer::new();
+        cache.visit(&mut serializer)?;
+        Ok(serializer.into_string()?)
+    }
+
+    #[test]
+    fn only_the_last_values_are_kept() -> anyhow::Result<()> {
+        let mut cache = LastValueCache::new();
+        cache.update(None, "temperature", plain(20.0));
+        cache.update(Some("location"), "alti", plain(2100.4));
+        cache.update(None, "temperature", plain(21.0));
+        cache.update(
+            None,
+            "pressure",
+            CachedValue::Value {
+                value: 98.0,
+                unit: Some("kPa".into()),
+            },
+        );
+
+        assert_eq!(
+            to_json(&cache)?,
+            r#"{"pressure":{"value":98.0,"unit":"kPa"},"temperature":21.0,"location":{"alti":2100.4}}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn a_group_replaces_a_measurement_with_the_same_name() -> anyhow::Result<()> {
+        let mut cache = LastValueCache::new();
+        cache.update(None, "engine", plain(1.0));
+        cache.update(Some("engine"), "speed", plain(3000.0));
+
+        assert_eq!(cache.get(None, "engine"), None);
+        assert_eq!(to_json(&cache)?, r#"{"engine":{"speed":3000.0}}"#);
+
+        cache.update(None, "engine", plain(2.0));
+
+        assert_eq!(cache.get(Some("engine"), "speed"), None);
+        assert_eq!(to_json(&cache)?, r#"{"engine":2.0}"#);
+        Ok(())
+    }
+
+    #[test]
+    fn all_kinds_of_values_are_cached() -> anyhow::Result<()> {
+        let timestamp = DateTime::parse_from_rfc3339("2021-04-30T17:03:14+02:00")?;
+        let mut cache = LastValueCache::new();
+        cache.update(None, "temperature", CachedValue::Missing);
+        cache.update(
+            None,
+            "pressure",
+            CachedValue::Annotated {
+                value: 98.0,
+                quality: MeasurementQuality::Bad,
+            },
+        );
+        cache.set_group_timestamp("phase", timestamp);
+        cache.update(
+            Some("phase"),
+            "current",
+            CachedValue::Complex {
+                real: 1.5,
+                imaginary: -0.5,
+            },
+        );
+
+        assert_eq!(
+            to_json(&cache)?,
+            concat!(
+                r#"{"pressure":{"value":98.0,"quality":"BAD"},"temperature":null,"#,
+                r#""phase":{"time":"2021-04-30T17:03:14+02:00","current":{"re":1.5,"im":-0.5}}}"#
+            )
+        );
+
+        cache.update(None, "phase", plain(1.0));
+        cache.update(Some("phase"), "current", plain(2.0));
+        assert_eq!(
+            to_json(&cache)?,
+            r#"{"pressure":{"value":98.0,"quality":"BAD"},"temperature":null,"phase":{"current":2.0}}"#
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,26 @@
+//! A sink broadcasting thin-edge JSON measurements to WebSocket clients,
+//! as browser dashboards.
+//!
+//! The measurements can also be sent as Node-RED messages, one per measurement,
+//! using the [`NodeRedSinkVisitor`].
+//!
+//! ```no_run
+//! use thin_edge_json::measurement::GroupedMeasurementVisitor;
+//! use ws_sink::WebSocketBroadcastVisitor;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), anyhow::Error> {
+//! let mut visitor = WebSocketBroadcastVisitor::bind("127.0.0.1:8080".parse()?).await?;
+//!
+//! visitor.measurement("temperature", 25.5)?;
+//! visitor.flush()?; // Sends `{"temperature":25.5}` to all the connected clients
+//! # Ok(()) }
+//! ```
+
+mod broadcast;
+mod cache;
+mod node_red;
+
+pub use broadcast::{WebSocketBroadcastVisitor, WebSocketSinkError};
+pub use cache::{CachedValue, LastValueCache};
+pub use node_red::{NodeRedError, NodeRedSinkVisitor};
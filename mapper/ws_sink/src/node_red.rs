@@ -0,0 +1,301 @@
+use crate::broadcast::{accept_connections, PayloadSource, WebSocketSinkError, CHANNEL_CAPACITY};
+use chrono::offset::FixedOffset;
+use chrono::DateTime;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use thin_edge_json::measurement::GroupedMeasurementVisitor;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+#[derive(thiserror::Error, Debug)]
+pub enum NodeRedError {
+    #[error("Unexpected time stamp within a group")]
+    UnexpectedTimestamp,
+
+    #[error("Unexpected start of group")]
+    UnexpectedStartOfGroup,
+
+    #[error("Unexpected end of group")]
+    UnexpectedEndOfGroup,
+
+    #[error("Invalid value for {topic:?}: the payload must be a finite number")]
+    InvalidValue { topic: String },
+}
+
+/// A Node-RED message, as `{"topic":"location/alti","payload":2100.4}`
+#[derive(Serialize)]
+struct NodeRedMessage<'a> {
+    topic: &'a str,
+    payload: f64,
+}
+
+/// A visitor that sends the measurements as Node-RED messages to WebSocket clients,
+/// as the `websocket in` nodes of Node-RED flows.
+///
+/// Each measurement is sent as a message of its own, `{"topic":"location/alti","payload":2100.4}`,
+/// the topic being the measurement name prefixed by its group name if any.
+/// The messages are sent to all the connected clients on `flush()`.
+///
+/// When the last values are retained, a client connecting mid-stream first receives
+/// the last message of each topic, sorted by topic, then all the messages flushed after its connection.
+pub struct NodeRedSinkVisitor {
+    hub: Arc<NodeRedHub>,
+    local_addr: SocketAddr,
+    server: JoinHandle<()>,
+    messages: Vec<(String, String)>,
+    group: Option<String>,
+}
+
+impl NodeRedSinkVisitor {
+    /// Start to accept WebSocket connections on the given address.
+    ///
+    /// The server runs on the current tokio runtime until the visitor is dropped.
+    pub async fn bind(
+        addr: SocketAddr,
+        retain_last_values: bool,
+    ) -> Result<Self, WebSocketSinkError> {
+        let bind_error = |from| WebSocketSinkError::BindError { addr, from };
+        let listener = TcpListener::bind(addr).await.map_err(bind_error)?;
+        let local_addr = listener.local_addr().map_err(bind_error)?;
+
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        let hub = Arc::new(NodeRedHub {
+            retained: Mutex::new(BTreeMap::new()),
+            retain_last_values,
+            sender,
+        });
+        let server = tokio::spawn(accept_connections(listener, hub.clone()));
+
+        Ok(Self {
+            hub,
+            local_addr,
+            server,
+            messages: Vec::new(),
+            group: None,
+        })
+    }
+
+    /// The address the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// The number of clients currently connected
+    pub fn connection_count(&self) -> usize {
+        self.hub.sender.receiver_count()
+    }
+
+    /// Send the messages gathered since the previous flush.
+    pub fn flush(&mut self) {
+        let messages = std::mem::take(&mut self.messages);
+        self.group = None;
+        self.hub.publish(messages);
+    }
+
+    fn record(&mut self, name: &str, value: f64) -> Result<(), NodeRedError> {
+        let topic = match self.group.as_ref() {
+            Some(group) => format!("{}/{}", group, name),
+            None => name.to_string(),
+        };
+        if !value.is_finite() {
+            return Err(NodeRedError::InvalidValue { topic });
+        }
+        let message = NodeRedMessage {
+            topic: &topic,
+            payload: value,
+        };
+        let message = serde_json::to_string(&message).expect("A finite number is serializable");
+        self.messages.push((topic, message));
+        Ok(())
+    }
+}
+
+impl Drop for NodeRedSinkVisitor {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+impl GroupedMeasurementVisitor for NodeRedSinkVisitor {
+    type Error = NodeRedError;
+
+    /// The timestamp is not part of the Node-RED messages
+    fn timestamp(&mut self, _value: DateTime<FixedOffset>) -> Result<(), Self::Error> {
+        if self.group.is_some() {
+            return Err(NodeRedError::UnexpectedTimestamp);
+        }
+        Ok(())
+    }
+
+    fn measurement(&mut self, name: &str, value: f64) -> Result<(), Self::Error> {
+        self.record(name, value)
+    }
+
+    fn start_group(&mut self, group: &str) -> Result<(), Self::Error> {
+        if self.group.is_some() {
+            return Err(NodeRedError::UnexpectedStartOfGroup);
+        }
+        self.group = Some(group.to_string());
+        Ok(())
+    }
+
+    fn end_group(&mut self) -> Result<(), Self::Error> {
+        match self.group.take() {
+            Some(_) => Ok(()),
+            None => Err(NodeRedError::UnexpectedEndOfGroup),
+        }
+    }
+}
+
+/// The state shared by the visitor and the client connections
+struct NodeRedHub {
+    retained: Mutex<BTreeMap<String, String>>,
+    retain_last_values: bool,
+    sender: broadcast::Sender<String>,
+}
+
+impl NodeRedHub {
+    fn publish(&self, messages: Vec<(String, String)>) {
+        // The retained messages are updated and the messages sent under the same lock,
+        // so a new client gets either a message or its retained copy, and never both.
+        let mut retained = self.retained.lock().expect("Poisoned lock");
+        for (topic, message) in messages {
+            if self.retain_last_values {
+                retained.insert(topic, message.clone());
+            }
+
+            // An error only means that no client is connected
+            let _ = self.sender.send(message);
+        }
+    }
+}
+
+impl PayloadSource for NodeRedHub {
+    /// The retained messages along with a receiver for the messages to come
+    fn subscribe(&self) -> Result<(Vec<String>, broadcast::Receiver<String>), WebSocketSinkError> {
+        let retained = self.retained.lock().expect("Poisoned lock");
+        let snapshot = retained.values().cloned().collect();
+        Ok((snapshot, self.sender.subscribe()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use std::time::Duration;
+    use tokio::net::TcpStream;
+    use tokio::time::{sleep, timeout};
+    use tokio_tungstenite::tungstenite::Message;
+    use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    async fn start_server(retain_last_values: bool) -> anyhow::Result<NodeRedSinkVisitor> {
+        Ok(NodeRedSinkVisitor::bind("127.0.0.1:0".parse()?, retain_last_values).await?)
+    }
+
+    async fn connect(visitor: &NodeRedSinkVisitor) -> anyhow::Result<Client> {
+        let url = format!("ws://{}", visitor.local_addr());
+        let (client, _) = connect_async(url).await?;
+        timeout(Duration::from_secs(2), async {
+            while visitor.connection_count() == 0 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await?;
+        Ok(client)
+    }
+
+    async fn next_message(client: &mut Client) -> anyhow::Result<String> {
+        match timeout(Duration::from_secs(2), client.next()).await? {
+            Some(Ok(Message::Text(message))) => Ok(message),
+            other => anyhow::bail!("Unexpected message: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn each_measurement_is_sent_as_a_node_red_message() -> anyhow::Result<()> {
+        let mut visitor = start_server(false).await?;
+        let mut client = connect(&visitor).await?;
+
+        visitor.measurement("temperature", 25.5)?;
+        visitor.start_group("location")?;
+        visitor.measurement_with_unit("alti", 2100.4, "m")?;
+        visitor.end_group()?;
+        visitor.flush();
+
+        assert_eq!(
+            next_message(&mut client).await?,
+            r#"{"topic":"temperature","payload":25.5}"#
+        );
+        assert_eq!(
+            next_message(&mut client).await?,
+            r#"{"topic":"location/alti","payload":2100.4}"#
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_new_client_receives_the_retained_values_then_the_next_messages() -> anyhow::Result<()>
+    {
+        let mut visitor = start_server(true).await?;
+        visitor.start_group("location")?;
+        visitor.measurement("alti", 2100.4)?;
+        visitor.end_group()?;
+        visitor.measurement("temperature", 20.0)?;
+        visitor.flush();
+        visitor.measurement("temperature", 21.0)?;
+        visitor.flush();
+
+        let mut client = connect(&visitor).await?;
+        assert_eq!(
+            next_message(&mut client).await?,
+            r#"{"topic":"location/alti","payload":2100.4}"#
+        );
+        assert_eq!(
+            next_message(&mut client).await?,
+            r#"{"topic":"temperature","payload":21.0}"#
+        );
+
+        visitor.measurement("temperature", 22.0)?;
+        visitor.flush();
+        assert_eq!(
+            next_message(&mut client).await?,
+            r#"{"topic":"temperature","payload":22.0}"#
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn no_values_are_retained_unless_requested() -> anyhow::Result<()> {
+        let mut visitor = start_server(false).await?;
+        visitor.measurement("temperature", 20.0)?;
+        visitor.flush();
+
+        let mut client = connect(&visitor).await?;
+        visitor.measurement("temperature", 21.0)?;
+        visitor.flush();
+
+        assert_eq!(
+            next_message(&mut client).await?,
+            r#"{"topic":"temperature","payload":21.0}"#
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn non_finite_values_are_rejected() -> anyhow::Result<()> {
+        let mut visitor = start_server(false).await?;
+        visitor.start_group("engine")?;
+
+        match visitor.measurement("speed", f64::INFINITY) {
+            Err(NodeRedError::InvalidValue { topic }) => assert_eq!(topic, "engine/speed"),
+            other => anyhow::bail!("Unexpected result: {:?}", other),
+        }
+        Ok(())
+    }
+}
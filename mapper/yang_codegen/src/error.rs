@@ -0,0 +1,40 @@
+use std::path::PathBuf;
+
+#[derive(thiserror::Error, Debug)]
+pub enum YangError {
+    #[error("Failed to read {path:?}: {from}")]
+    FileReadError { path: PathBuf, from: std::io::Error },
+
+    #[error("Failed to write {path:?}: {from}")]
+    FileWriteError { path: PathBuf, from: std::io::Error },
+
+    #[error("Invalid YANG syntax at line {line}: {reason}")]
+    SyntaxError { line: usize, reason: String },
+
+    #[error("Expected a single YANG module")]
+    NoModule,
+
+    #[error("The module {module:?} defines no container")]
+    NoContainer { module: String },
+
+    #[error("The `{keyword}` statement at line {line} is not supported")]
+    UnsupportedStatement { keyword: String, line: usize },
+
+    #[error("The leaf {leaf:?} is of type {type_name:?}: only numeric leaves are supported")]
+    UnsupportedType { leaf: String, type_name: String },
+
+    #[error("The container {name:?} is nested too deep: thin-edge JSON groups cannot be nested")]
+    NestedContainer { name: String },
+
+    #[error("The container {name:?} defines no leaf")]
+    EmptyContainer { name: String },
+
+    #[error("{name:?} cannot be used as a measurement or group name")]
+    InvalidName { name: String },
+
+    #[error("{name:?} is defined twice in the container {container:?}")]
+    DuplicateName { name: String, container: String },
+
+    #[error("The statement at line {line} has no argument")]
+    MissingArgument { line: usize },
+}
@@ -0,0 +1,438 @@
+use crate::error::YangError;
+use crate::parse::{parse_statements, Statement};
+use std::collections::HashSet;
+use std::fmt::Write;
+
+/// The YANG types of the leaves that can be given as thin-edge JSON measurements
+const NUMERIC_TYPES: [&str; 9] = [
+    "int8",
+    "int16",
+    "int32",
+    "int64",
+    "uint8",
+    "uint16",
+    "uint32",
+    "uint64",
+    "decimal64",
+];
+
+/// The statements defining data nodes which have no thin-edge JSON counterpart
+const UNSUPPORTED_STATEMENTS: [&str; 8] = [
+    "list",
+    "leaf-list",
+    "choice",
+    "uses",
+    "grouping",
+    "augment",
+    "anydata",
+    "anyxml",
+];
+
+/// The keys that can't be used for a measurement or a group
+const RESERVED_NAMES: [&str; 2] = ["time", "type"];
+
+/// The Rust keywords, which are given as raw identifiers
+const RUST_KEYWORDS: [&str; 46] = [
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in", "let", "loop",
+    "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return", "static",
+    "struct", "trait", "true", "try", "typeof", "unsafe", "unsized", "use", "virtual", "where",
+    "while", "yield",
+];
+
+/// The Rust keywords that can't be used even as raw identifiers
+const NON_RAW_KEYWORDS: [&str; 5] = ["crate", "self", "Self", "super", "_"];
+
+/// A container of the YANG module, translated into a measurement schema
+struct Schema {
+    struct_name: String,
+    doc: Option<String>,
+    entries: Vec<Entry>,
+}
+
+enum Entry {
+    Measurement(Measurement),
+    Group {
+        name: String,
+        struct_name: String,
+        doc: Option<String>,
+        measurements: Vec<Measurement>,
+    },
+}
+
+struct Measurement {
+    name: String,
+    doc: Option<String>,
+    optional: bool,
+    unit: Option<String>,
+}
+
+/// Generate the Rust code defining the measurement schemas of a YANG module.
+///
+/// Each top-level container of the module is translated into a `define_measurement_schema!`:
+///
+/// * the struct is named after the container, in camel case;
+/// * a leaf is a measurement, which is optional unless the leaf is `mandatory true`,
+///   and whose unit is given by the `units` of the leaf;
+/// * a nested container is a group, which can't be nested any further.
+///
+/// Only the numeric leaves are supported,
+/// and the `list`, `leaf-list` and `choice` nodes are rejected.
+/// The `description` statements are given as doc comments.
+pub fn generate(model: &str) -> Result<String, YangError> {
+    let statements = parse_statements(model)?;
+    let module = match statements.as_slice() {
+        [module] if module.keyword == "module" => module,
+        _ => return Err(YangError::NoModule),
+    };
+    let module_name = argument(module)?;
+
+    let mut schemas = Vec::new();
+    for statement in module.substatements.iter() {
+        match statement.keyword.as_str() {
+            "container" => schemas.push(schema(statement)?),
+            "leaf" => return Err(unsupported(statement)),
+            keyword if UNSUPPORTED_STATEMENTS.contains(&keyword) => {
+                return Err(unsupported(statement))
+            }
+            _ => {}
+        }
+    }
+    if schemas.is_empty() {
+        return Err(YangError::NoContainer {
+            module: module_name.to_string(),
+        });
+    }
+
+    let mut code = format!(
+        "// Generated by yang_codegen from the YANG module `{}`: do not edit.\n",
+        module_name
+    );
+    for schema in schemas.iter() {
+        code.push('\n');
+        write_schema(&mut code, schema).expect("Writing to a string never fails");
+    }
+    Ok(code)
+}
+
+fn schema(container: &Statement) -> Result<Schema, YangError> {
+    let name = argument(container)?;
+    let struct_name = struct_name(name)?;
+
+    let mut entries = Vec::new();
+    let mut names = HashSet::new();
+    for statement in container.substatements.iter() {
+        let entry = match statement.keyword.as_str() {
+            "leaf" => Entry::Measurement(measurement(statement)?),
+            "container" => group(statement, &struct_name)?,
+            keyword if UNSUPPORTED_STATEMENTS.contains(&keyword) => {
+                return Err(unsupported(statement))
+            }
+            _ => continue,
+        };
+        let entry_name = match &entry {
+            Entry::Measurement(measurement) => &measurement.name,
+            Entry::Group { name, .. } => name,
+        };
+        check_unique(entry_name, name, &mut names)?;
+        entries.push(entry);
+    }
+    if entries.is_empty() {
+        return Err(YangError::EmptyContainer { name: name.into() });
+    }
+
+    Ok(Schema {
+        struct_name,
+        doc: container.find_argument("description").map(String::from),
+        entries,
+    })
+}
+
+fn group(container: &Statement, schema_struct_name: &str) -> Result<Entry, YangError> {
+    let name = argument(container)?;
+    let field_name = field_name(name)?;
+
+    let mut measurements = Vec::new();
+    let mut names = HashSet::new();
+    for statement in container.substatements.iter() {
+        match statement.keyword.as_str() {
+            "leaf" => {
+                let measurement = measurement(statement)?;
+                check_unique(&measurement.name, name, &mut names)?;
+                measurements.push(measurement);
+            }
+            "container" => {
+                return Err(YangError::NestedContainer {
+                    name: argument(statement)?.into(),
+                })
+            }
+            keyword if UNSUPPORTED_STATEMENTS.contains(&keyword) => {
+                return Err(unsupported(statement))
+            }
+            _ => {}
+        }
+    }
+    if measurements.is_empty() {
+        return Err(YangError::EmptyContainer { name: name.into() });
+    }
+
+    Ok(Entry::Group {
+        name: field_name,
+        struct_name: format!("{}{}", schema_struct_name, struct_name(name)?),
+        doc: container.find_argument("description").map(String::from),
+        measurements,
+    })
+}
+
+fn measurement(leaf: &Statement) -> Result<Measurement, YangError> {
+    let name = argument(leaf)?;
+    let type_name = leaf.find_argument("type").unwrap_or_default();
+    if !NUMERIC_TYPES.contains(&type_name) {
+        return Err(YangError::UnsupportedType {
+            leaf: name.into(),
+            type_name: type_name.into(),
+        });
+    }
+
+    Ok(Measurement {
+        name: field_name(name)?,
+        doc: leaf.find_argument("description").map(String::from),
+        optional: leaf.find_argument("mandatory") != Some("true"),
+        unit: leaf
+            .find_argument("units")
+            .filter(|unit| !unit.is_empty())
+            .map(String::from),
+    })
+}
+
+fn argument(statement: &Statement) -> Result<&str, YangError> {
+    statement
+        .argument
+        .as_deref()
+        .ok_or(YangError::MissingArgument {
+            line: statement.line,
+        })
+}
+
+fn unsupported(statement: &Statement) -> YangError {
+    YangError::UnsupportedStatement {
+        keyword: statement.keyword.clone(),
+        line: statement.line,
+    }
+}
+
+fn check_unique(name: &str, container: &str, names: &mut HashSet<String>) -> Result<(), YangError> {
+    let key = name.trim_start_matches("r#");
+    if names.insert(key.to_string()) {
+        Ok(())
+    } else {
+        Err(YangError::DuplicateName {
+            name: key.into(),
+            container: container.into(),
+        })
+    }
+}
+
+/// The Rust field for a measurement or group name,
+/// which must be usable as is as a thin-edge JSON key
+fn field_name(name: &str) -> Result<String, YangError> {
+    let invalid_name = || YangError::InvalidName { name: name.into() };
+    let mut chars = name.chars();
+    let is_identifier = match chars.next() {
+        Some(c) => {
+            (c.is_ascii_alphabetic() || c == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    };
+    if !is_identifier || RESERVED_NAMES.contains(&name) || NON_RAW_KEYWORDS.contains(&name) {
+        return Err(invalid_name());
+    }
+
+    if RUST_KEYWORDS.contains(&name) {
+        Ok(format!("r#{}", name))
+    } else {
+        Ok(name.into())
+    }
+}
+
+/// The camel case struct name for a container name, as `EngineStatus` for `engine-status`
+fn struct_name(name: &str) -> Result<String, YangError> {
+    let struct_name: String = name
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .flat_map(|part| {
+            let mut chars = part.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase())
+                .into_iter()
+                .chain(chars)
+        })
+        .collect();
+    match struct_name.chars().next() {
+        Some(first) if first.is_ascii_alphabetic() => Ok(struct_name),
+        _ => Err(YangError::InvalidName { name: name.into() }),
+    }
+}
+
+fn write_doc(code: &mut String, doc: &Option<String>, indent: &str) -> std::fmt::Result {
+    if let Some(doc) = doc {
+        for line in doc.trim().lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                writeln!(code, "{}///", indent)?;
+            } else {
+                writeln!(code, "{}/// {}", indent, line)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_measurement(
+    code: &mut String,
+    measurement: &Measurement,
+    indent: &str,
+) -> std::fmt::Result {
+    write_doc(code, &measurement.doc, indent)?;
+    let measurement_type = if measurement.optional {
+        "Option<f64>"
+    } else {
+        "f64"
+    };
+    write!(code, "{}{}: {}", indent, measurement.name, measurement_type)?;
+    if let Some(unit) = &measurement.unit {
+        write!(code, " in {:?}", unit)?;
+    }
+    writeln!(code, ",")
+}
+
+fn write_schema(code: &mut String, schema: &Schema) -> std::fmt::Result {
+    writeln!(
+        code,
+        "thin_edge_schema_macro::define_measurement_schema! {{"
+    )?;
+    write_doc(code, &schema.doc, "    ")?;
+    writeln!(code, "    pub struct {} {{", schema.struct_name)?;
+    for entry in schema.entries.iter() {
+        match entry {
+            Entry::Measurement(measurement) => write_measurement(code, measurement, "        ")?,
+            Entry::Group {
+                name,
+                struct_name,
+                doc,
+                measurements,
+            } => {
+                write_doc(code, doc, "        ")?;
+                writeln!(code, "        {}: group {} {{", name, struct_name)?;
+                for measurement in measurements.iter() {
+                    write_measurement(code, measurement, "            ")?;
+                }
+                writeln!(code, "        }},")?;
+            }
+        }
+    }
+    writeln!(code, "    }}")?;
+    writeln!(code, "}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use pretty_assertions::assert_eq;
+
+    fn module(body: &str) -> String {
+        format!(
+            "module sensors {{ namespace \"urn:sensors\"; prefix s; {} }}",
+            body
+        )
+    }
+
+    #[test]
+    fn containers_are_translated_into_schemas() -> anyhow::Result<()> {
+        let code = generate(&module(
+            r#"
+            container env-sensor {
+                description "An environment sensor";
+                leaf temperature { type decimal64 { fraction-digits 1; } units "°C"; mandatory true; }
+                leaf match { type int32; }
+                container location {
+                    leaf alti { type int32; units "m"; }
+                }
+            }"#,
+        ))?;
+
+        assert_eq!(
+            code,
+            r#"// Generated by yang_codegen from the YANG module `sensors`: do not edit.
+
+thin_edge_schema_macro::define_measurement_schema! {
+    /// An environment sensor
+    pub struct EnvSensor {
+        temperature: f64 in "°C",
+        r#match: Option<f64>,
+        location: group EnvSensorLocation {
+            alti: Option<f64> in "m",
+        },
+    }
+}
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn non_numeric_leaves_are_rejected() {
+        assert_matches!(
+            generate(&module("container s { leaf status { type string; } }")),
+            Err(YangError::UnsupportedType { leaf, type_name }) if leaf == "status" && type_name == "string"
+        );
+    }
+
+    #[test]
+    fn nodes_without_thin_edge_json_counterpart_are_rejected() {
+        assert_matches!(
+            generate(&module("container s { container a { container b { leaf x { type int8; } } } }")),
+            Err(YangError::NestedContainer { name }) if name == "b"
+        );
+        assert_matches!(
+            generate(&module("container s { list l { key x; leaf x { type int8; } } }")),
+            Err(YangError::UnsupportedStatement { keyword, .. }) if keyword == "list"
+        );
+        assert_matches!(
+            generate(&module("leaf x { type int8; }")),
+            Err(YangError::UnsupportedStatement { keyword, .. }) if keyword == "leaf"
+        );
+    }
+
+    #[test]
+    fn names_must_be_valid_thin_edge_json_keys() {
+        assert_matches!(
+            generate(&module("container s { leaf oil-level { type int8; } }")),
+            Err(YangError::InvalidName { name }) if name == "oil-level"
+        );
+        assert_matches!(
+            generate(&module("container s { leaf time { type int8; } }")),
+            Err(YangError::InvalidName { .. })
+        );
+        assert_matches!(
+            generate(&module(
+                "container s { leaf x { type int8; } container x { leaf y { type int8; } } }"
+            )),
+            Err(YangError::DuplicateName { name, .. }) if name == "x"
+        );
+    }
+
+    #[test]
+    fn a_module_must_define_a_container() {
+        assert_matches!(
+            generate(&module("revision 2021-06-01;")),
+            Err(YangError::NoContainer { module }) if module == "sensors"
+        );
+        assert_matches!(
+            generate("container s { leaf x { type int8; } }"),
+            Err(YangError::NoModule)
+        );
+    }
+}
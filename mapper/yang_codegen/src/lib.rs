@@ -0,0 +1,48 @@
+//! Generate typed thin-edge JSON measurement schemas from YANG models.
+//!
+//! The code generated for a YANG module defines one `define_measurement_schema!` per container,
+//! giving a struct with a `visit()` method forwarding its measurements
+//! to any `GroupedMeasurementVisitor`, and `TryFrom<ThinEdgeJson>` implementations.
+//! See `generate()` for how the YANG nodes are translated.
+//!
+//! The code is meant to be generated by a build script,
+//! the crate depending on `thin_edge_json` and `thin_edge_schema_macro`:
+//!
+//! ```no_run
+//! // build.rs
+//! use std::path::PathBuf;
+//!
+//! fn main() -> Result<(), yang_codegen::YangError> {
+//!     println!("cargo:rerun-if-changed=models/engine.yang");
+//!     let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").unwrap());
+//!     yang_codegen::generate_file("models/engine.yang".as_ref(), &out_dir.join("engine.rs"))
+//! }
+//! ```
+//!
+//! The generated code being included in the crate with:
+//!
+//! ```ignore
+//! include!(concat!(env!("OUT_DIR"), "/engine.rs"));
+//! ```
+
+mod error;
+mod generate;
+mod parse;
+
+pub use error::YangError;
+pub use generate::generate;
+
+use std::path::Path;
+
+/// Generate the code of the YANG model read from `model` into the `output` file.
+pub fn generate_file(model: &Path, output: &Path) -> Result<(), YangError> {
+    let model = std::fs::read_to_string(model).map_err(|from| YangError::FileReadError {
+        path: model.into(),
+        from,
+    })?;
+    let code = generate(&model)?;
+    std::fs::write(output, code).map_err(|from| YangError::FileWriteError {
+        path: output.into(),
+        from,
+    })
+}
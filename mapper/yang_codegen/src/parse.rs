@@ -0,0 +1,305 @@
+use crate::error::YangError;
+
+/// A YANG statement: a keyword, an optional argument, and the nested statements, if any.
+///
+/// ```text
+/// leaf speed {
+///     type uint32;
+///     units "rpm";
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub keyword: String,
+    pub argument: Option<String>,
+    pub substatements: Vec<Statement>,
+    pub line: usize,
+}
+
+impl Statement {
+    /// The argument of the first substatement with the given keyword
+    pub fn find_argument(&self, keyword: &str) -> Option<&str> {
+        self.substatements
+            .iter()
+            .find(|statement| statement.keyword == keyword)
+            .and_then(|statement| statement.argument.as_deref())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// An unquoted string, as a keyword or an argument
+    Word(String),
+    /// A quoted string, the quotes removed and the concatenations done
+    Quoted(String),
+    OpenBrace,
+    CloseBrace,
+    SemiColon,
+}
+
+/// Parse the statements of a YANG file.
+///
+/// Only the generic statement syntax is checked here,
+/// the meaning of the statements being left to the code generator.
+pub fn parse_statements(input: &str) -> Result<Vec<Statement>, YangError> {
+    let tokens = tokenize(input)?;
+    let mut tokens = tokens.into_iter().peekable();
+    let mut statements = Vec::new();
+    while tokens.peek().is_some() {
+        statements.push(parse_statement(&mut tokens)?);
+    }
+    Ok(statements)
+}
+
+fn parse_statement(
+    tokens: &mut std::iter::Peekable<std::vec::IntoIter<(Token, usize)>>,
+) -> Result<Statement, YangError> {
+    let (keyword, line) = match tokens.next() {
+        Some((Token::Word(keyword), line)) => (keyword, line),
+        Some((token, line)) => return Err(unexpected(line, &token, "a keyword")),
+        None => return Err(end_of_input()),
+    };
+
+    let argument = match tokens.peek() {
+        Some((Token::Word(_), _)) | Some((Token::Quoted(_), _)) => match tokens.next() {
+            Some((Token::Word(argument), _)) | Some((Token::Quoted(argument), _)) => Some(argument),
+            _ => None,
+        },
+        _ => None,
+    };
+
+    let mut substatements = Vec::new();
+    match tokens.next() {
+        Some((Token::SemiColon, _)) => {}
+        Some((Token::OpenBrace, _)) => loop {
+            match tokens.peek() {
+                Some((Token::CloseBrace, _)) => {
+                    let _ = tokens.next();
+                    break;
+                }
+                Some(_) => substatements.push(parse_statement(tokens)?),
+                None => return Err(end_of_input()),
+            }
+        },
+        Some((token, line)) => return Err(unexpected(line, &token, "`;` or `{`")),
+        None => return Err(end_of_input()),
+    }
+
+    Ok(Statement {
+        keyword,
+        argument,
+        substatements,
+        line,
+    })
+}
+
+fn unexpected(line: usize, token: &Token, expected: &str) -> YangError {
+    let found = match token {
+        Token::Word(word) => format!("`{}`", word),
+        Token::Quoted(string) => format!("{:?}", string),
+        Token::OpenBrace => "`{`".into(),
+        Token::CloseBrace => "`}`".into(),
+        Token::SemiColon => "`;`".into(),
+    };
+    YangError::SyntaxError {
+        line,
+        reason: format!("expected {}, found {}", expected, found),
+    }
+}
+
+fn end_of_input() -> YangError {
+    YangError::SyntaxError {
+        line: 0,
+        reason: "unexpected end of input".into(),
+    }
+}
+
+/// Split the input into tokens, each with its line number
+fn tokenize(input: &str) -> Result<Vec<(Token, usize)>, YangError> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '{' => tokens.push((Token::OpenBrace, line)),
+            '}' => tokens.push((Token::CloseBrace, line)),
+            ';' => tokens.push((Token::SemiColon, line)),
+            '/' if chars.peek() == Some(&'/') => {
+                for c in &mut chars {
+                    if c == '\n' {
+                        line += 1;
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                let start = line;
+                let _ = chars.next();
+                let mut previous = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if previous == '*' => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            previous = c;
+                        }
+                        None => {
+                            return Err(YangError::SyntaxError {
+                                line: start,
+                                reason: "unterminated comment".into(),
+                            })
+                        }
+                    }
+                }
+            }
+            '"' | '\'' => {
+                let start = line;
+                let string = quoted_string(c, &mut chars, &mut line)?;
+                // A string concatenated to the previous one with `+`
+                if matches!(tokens.last(), Some((Token::Word(plus), _)) if plus == "+") {
+                    let _ = tokens.pop();
+                    match tokens.last_mut() {
+                        Some((Token::Quoted(previous), _)) => previous.push_str(&string),
+                        _ => {
+                            return Err(YangError::SyntaxError {
+                                line: start,
+                                reason: "`+` must follow a quoted string".into(),
+                            })
+                        }
+                    }
+                } else {
+                    tokens.push((Token::Quoted(string), start));
+                }
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '{' || c == '}' || c == ';' {
+                        break;
+                    }
+                    word.push(c);
+                    let _ = chars.next();
+                }
+                tokens.push((Token::Word(word), line));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Read a string up to its closing quote, the opening quote being already consumed
+fn quoted_string(
+    quote: char,
+    chars: &mut std::iter::Peekable<std::str::Chars<'_>>,
+    line: &mut usize,
+) -> Result<String, YangError> {
+    let start = *line;
+    let mut string = String::new();
+    loop {
+        match chars.next() {
+            Some(c) if c == quote => return Ok(string),
+            // No escape sequence in a single-quoted string
+            Some('\\') if quote == '"' => match chars.next() {
+                Some('n') => string.push('\n'),
+                Some('t') => string.push('\t'),
+                Some('"') => string.push('"'),
+                Some('\\') => string.push('\\'),
+                Some(c) => {
+                    return Err(YangError::SyntaxError {
+                        line: *line,
+                        reason: format!("invalid escape sequence `\\{}`", c),
+                    })
+                }
+                None => break,
+            },
+            Some(c) => {
+                if c == '\n' {
+                    *line += 1;
+                }
+                string.push(c);
+            }
+            None => break,
+        }
+    }
+    Err(YangError::SyntaxError {
+        line: start,
+        reason: "unterminated string".into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    fn statement(keyword: &str, argument: &str, line: usize) -> Statement {
+        Statement {
+            keyword: keyword.into(),
+            argument: Some(argument.into()),
+            substatements: vec![],
+            line,
+        }
+    }
+
+    #[test]
+    fn parse_nested_statements() -> anyhow::Result<()> {
+        let statements = parse_statements(
+            r#"
+            // An engine
+            leaf speed {
+                type uint32; /* in revolutions
+                                per minute */
+                units "rpm";
+            }"#,
+        )?;
+
+        assert_eq!(
+            statements,
+            vec![Statement {
+                keyword: "leaf".into(),
+                argument: Some("speed".into()),
+                substatements: vec![statement("type", "uint32", 4), statement("units", "rpm", 6)],
+                line: 3,
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_quoted_strings() -> anyhow::Result<()> {
+        let statements = parse_statements(
+            r#"description "The \"inlet\"" + ' temperature\n';
+               units '°C';"#,
+        )?;
+
+        assert_eq!(
+            statements,
+            vec![
+                statement("description", "The \"inlet\" temperature\\n", 1),
+                statement("units", "°C", 2),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn syntax_errors_are_reported_with_their_line() {
+        assert_matches!(
+            parse_statements("leaf speed {\n  type uint32\n}"),
+            Err(YangError::SyntaxError { line: 3, .. })
+        );
+        assert_matches!(
+            parse_statements("leaf speed {\n  units \"rpm;\n}"),
+            Err(YangError::SyntaxError { line: 2, .. })
+        );
+        assert_matches!(
+            parse_statements("container engine {"),
+            Err(YangError::SyntaxError { .. })
+        );
+    }
+}
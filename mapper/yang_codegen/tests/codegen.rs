@@ -0,0 +1,78 @@
+use pretty_assertions::assert_eq;
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use thin_edge_json::json::ThinEdgeJson;
+use thin_edge_json::serialize::ThinEdgeJsonSerializer;
+
+// The code generated from `fixtures/engine.yang`, checked to be up to date below
+include!("fixtures/engine.rs");
+
+fn fixture_path(name: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", "fixtures", name]
+        .iter()
+        .collect()
+}
+
+fn fixture(name: &str) -> String {
+    let path = fixture_path(name);
+    std::fs::read_to_string(&path).unwrap_or_else(|_| panic!("Missing fixture {:?}", path))
+}
+
+#[test]
+fn the_generated_code_is_up_to_date() -> anyhow::Result<()> {
+    assert_eq!(
+        yang_codegen::generate(&fixture("engine.yang"))?,
+        fixture("engine.rs")
+    );
+    Ok(())
+}
+
+#[test]
+fn the_generated_code_is_written_to_a_file() -> anyhow::Result<()> {
+    let dir = tempfile::TempDir::new()?;
+    let output = dir.path().join("engine.rs");
+
+    yang_codegen::generate_file(&fixture_path("engine.yang"), &output)?;
+
+    assert_eq!(std::fs::read_to_string(&output)?, fixture("engine.rs"));
+    Ok(())
+}
+
+#[test]
+fn the_generated_schema_produces_thin_edge_json() -> anyhow::Result<()> {
+    let engine = Engine {
+        speed: 3000.0,
+        oil_level: None,
+        temperature: EngineTemperature {
+            inlet: 80.5,
+            outlet: Some(90.25),
+        },
+    };
+
+    let mut serializer = ThinEdgeJsonSerializer::new();
+    engine.visit(&mut serializer)?;
+
+    assert_eq!(
+        serializer.into_string()?,
+        r#"{"speed":{"value":3000.0,"unit":"rpm"},"oil_level":null,"temperature":{"inlet":{"value":80.5,"unit":"°C"},"outlet":{"value":90.25,"unit":"°C"}}}"#
+    );
+    Ok(())
+}
+
+#[test]
+fn the_generated_schema_parses_thin_edge_json() -> anyhow::Result<()> {
+    let json = ThinEdgeJson::from_str(r#"{"speed": 3000, "temperature": {"inlet": 80.5}}"#)?;
+
+    assert_eq!(
+        Engine::try_from(json)?,
+        Engine {
+            speed: 3000.0,
+            oil_level: None,
+            temperature: EngineTemperature {
+                inlet: 80.5,
+                outlet: None,
+            },
+        }
+    );
+    Ok(())
+}
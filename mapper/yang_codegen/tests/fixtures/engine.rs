@@ -0,0 +1,15 @@
+// Generated by yang_codegen from the YANG module `engine-monitoring`: do not edit.
+
+thin_edge_schema_macro::define_measurement_schema! {
+    /// The measurements of an engine
+    pub struct Engine {
+        /// The engine speed
+        speed: f64 in "rpm",
+        oil_level: Option<f64> in "%",
+        /// The temperatures of the cooling circuit, as measured at the inlet and outlet
+        temperature: group EngineTemperature {
+            inlet: f64 in "°C",
+            outlet: Option<f64> in "°C",
+        },
+    }
+}
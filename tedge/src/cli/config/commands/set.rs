@@ -1,4 +1,4 @@
-use crate::cli::config::ConfigKey;
+use crate::cli::config::{ConfigKey, SECRET_MASK};
 use crate::command::{Command, ExecutionContext};
 use tedge_config::*;
 
@@ -10,9 +10,14 @@ pub struct SetConfigCommand {
 
 impl Command for SetConfigCommand {
     fn description(&self) -> String {
+        let value = if self.config_key.secret {
+            SECRET_MASK
+        } else {
+            self.value.as_str()
+        };
         format!(
             "set the configuration key: {} with value: {}.",
-            self.config_key.key, self.value
+            self.config_key.key, value
         )
     }
 
@@ -7,6 +7,8 @@ use tedge_config::*;
 pub struct ConfigKey {
     pub key: &'static str,
     pub description: &'static str,
+    /// A secret value is never displayed, but only reported as set or not
+    pub secret: bool,
     pub get: GetConfigStringValue<TEdgeConfig>,
     pub set: SetConfigStringValue<TEdgeConfig>,
     pub unset: UnsetConfigValue<TEdgeConfig>,
@@ -16,6 +18,9 @@ type GetConfigStringValue<C> = Box<dyn Fn(&C) -> ConfigSettingResult<String>>;
 type SetConfigStringValue<C> = Box<dyn Fn(&mut C, String) -> ConfigSettingResult<()>>;
 type UnsetConfigValue<C> = Box<dyn Fn(&mut C) -> ConfigSettingResult<()>>;
 
+/// What is displayed in place of a secret value
+pub const SECRET_MASK: &str = "********";
+
 impl std::fmt::Debug for ConfigKey {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "ConfigKey({})", self.key)
@@ -27,6 +32,7 @@ macro_rules! config_key {
         ConfigKey {
             key: $setting::KEY,
             description: $setting::DESCRIPTION,
+            secret: false,
             get: Box::new(move |config: &TEdgeConfig| config.query_string($setting)),
             set: Box::new(move |config: &mut TEdgeConfig, value: String| {
                 config.update_string($setting, value)
@@ -34,6 +40,20 @@ macro_rules! config_key {
             unset: Box::new(move |config: &mut TEdgeConfig| config.unset($setting)),
         }
     };
+    ($setting:tt, secret) => {
+        ConfigKey {
+            key: $setting::KEY,
+            description: $setting::DESCRIPTION,
+            secret: true,
+            get: Box::new(move |config: &TEdgeConfig| {
+                config.query($setting).map(|_| SECRET_MASK.to_string())
+            }),
+            set: Box::new(move |config: &mut TEdgeConfig, value: String| {
+                config.update($setting, value.into())
+            }),
+            unset: Box::new(move |config: &mut TEdgeConfig| config.unset($setting)),
+        }
+    };
 }
 
 impl ConfigKey {
@@ -48,6 +68,9 @@ impl ConfigKey {
             config_key!(AzureRootCertPathSetting),
             config_key!(AzureMapperTimestamp),
             config_key!(MqttPortSetting),
+            config_key!(MqttUsernameSetting),
+            config_key!(MqttPasswordSetting, secret),
+            config_key!(MqttNegotiateFormatSetting),
         ]
     }
 }
@@ -201,6 +201,41 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn run_config_set_get_secret_key() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_dir_path = temp_dir.path();
+        let test_home_str = temp_dir_path.to_str().unwrap();
+
+        let password = "s3cr3t-password";
+
+        let mut set_password_cmd = tedge_command_with_test_home(
+            test_home_str,
+            &["config", "set", "mqtt.password", password],
+        )?;
+
+        set_password_cmd.assert().success();
+
+        let mut get_password_cmd =
+            tedge_command_with_test_home(test_home_str, &["config", "get", "mqtt.password"])?;
+
+        get_password_cmd
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("********"))
+            .stdout(predicate::str::contains(password).not());
+
+        let mut list_cmd = tedge_command_with_test_home(test_home_str, &["config", "list"])?;
+
+        list_cmd
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("mqtt.password=********"))
+            .stdout(predicate::str::contains(password).not());
+
+        Ok(())
+    }
+
     #[test]
     fn run_config_defaults() -> Result<(), Box<dyn std::error::Error>> {
         let temp_dir = tempfile::tempdir().unwrap();
@@ -1,5 +1,6 @@
 pub mod connect_url;
 pub mod file_path;
 pub mod flag;
+pub mod password;
 pub mod port;
-pub use self::{connect_url::*, file_path::*, flag::*, port::*};
+pub use self::{connect_url::*, file_path::*, flag::*, password::*, port::*};
@@ -0,0 +1,39 @@
+/// Represents a password.
+///
+/// The value is neither displayed by `Debug` nor convertible into a `String`,
+/// so it cannot be printed by `tedge config get` or `tedge config list`.
+/// It has to be explicitly exposed to build the credentials of a client.
+#[derive(Clone, serde::Serialize, serde::Deserialize, Eq, PartialEq)]
+#[serde(transparent)]
+pub struct Password(String);
+
+impl Password {
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Password {
+    fn from(input: String) -> Self {
+        Password(input)
+    }
+}
+
+impl std::fmt::Debug for Password {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Password([REDACTED])")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_password_is_not_displayed_by_debug() {
+        let password = Password::from("s3cr3t".to_string());
+
+        assert_eq!(format!("{:?}", password), "Password([REDACTED])");
+        assert_eq!(password.expose_secret(), "s3cr3t");
+    }
+}
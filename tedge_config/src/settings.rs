@@ -158,3 +158,55 @@ impl ConfigSetting for MqttPortSetting {
 
     type Value = Port;
 }
+
+///
+/// Username used by the mqtt clients to authenticate on the broker.
+///
+/// Example: tedge
+///
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MqttUsernameSetting;
+
+impl ConfigSetting for MqttUsernameSetting {
+    const KEY: &'static str = "mqtt.username";
+
+    const DESCRIPTION: &'static str = concat!(
+        "Username used by the mqtt clients to authenticate on the broker, along mqtt.password. ",
+        "Example: tedge"
+    );
+
+    type Value = String;
+}
+
+///
+/// Password used by the mqtt clients to authenticate on the broker.
+///
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MqttPasswordSetting;
+
+impl ConfigSetting for MqttPasswordSetting {
+    const KEY: &'static str = "mqtt.password";
+
+    const DESCRIPTION: &'static str = concat!(
+        "Password used by the mqtt clients to authenticate on the broker, along mqtt.username. ",
+        "When unset, the credentials are read from the TEDGE_MQTT_USERNAME ",
+        "and TEDGE_MQTT_PASSWORD environment variables. ",
+        "The password is never displayed."
+    );
+
+    type Value = Password;
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MqttNegotiateFormatSetting;
+
+impl ConfigSetting for MqttNegotiateFormatSetting {
+    const KEY: &'static str = "mqtt.negotiate_format";
+
+    const DESCRIPTION: &'static str = concat!(
+        "Boolean whether the mappers negotiate the thin-edge JSON format with their peers ",
+        "when connecting to the mqtt broker. Example: true"
+    );
+
+    type Value = Flag;
+}
@@ -244,6 +244,79 @@ impl ConfigSettingAccessor<MqttPortSetting> for TEdgeConfig {
     }
 }
 
+impl ConfigSettingAccessor<MqttUsernameSetting> for TEdgeConfig {
+    fn query(&self, _setting: MqttUsernameSetting) -> ConfigSettingResult<String> {
+        self.data
+            .mqtt
+            .username
+            .clone()
+            .ok_or(ConfigSettingError::ConfigNotSet {
+                key: MqttUsernameSetting::KEY,
+            })
+    }
+
+    fn update(&mut self, _setting: MqttUsernameSetting, value: String) -> ConfigSettingResult<()> {
+        self.data.mqtt.username = Some(value);
+        Ok(())
+    }
+
+    fn unset(&mut self, _setting: MqttUsernameSetting) -> ConfigSettingResult<()> {
+        self.data.mqtt.username = None;
+        Ok(())
+    }
+}
+
+impl ConfigSettingAccessor<MqttPasswordSetting> for TEdgeConfig {
+    fn query(&self, _setting: MqttPasswordSetting) -> ConfigSettingResult<Password> {
+        self.data
+            .mqtt
+            .password
+            .clone()
+            .ok_or(ConfigSettingError::ConfigNotSet {
+                key: MqttPasswordSetting::KEY,
+            })
+    }
+
+    fn update(
+        &mut self,
+        _setting: MqttPasswordSetting,
+        value: Password,
+    ) -> ConfigSettingResult<()> {
+        self.data.mqtt.password = Some(value);
+        Ok(())
+    }
+
+    fn unset(&mut self, _setting: MqttPasswordSetting) -> ConfigSettingResult<()> {
+        self.data.mqtt.password = None;
+        Ok(())
+    }
+}
+
+impl ConfigSettingAccessor<MqttNegotiateFormatSetting> for TEdgeConfig {
+    fn query(&self, _setting: MqttNegotiateFormatSetting) -> ConfigSettingResult<Flag> {
+        Ok(self
+            .data
+            .mqtt
+            .negotiate_format
+            .map(Flag)
+            .unwrap_or_else(|| self.config_defaults.default_mqtt_negotiate_format.clone()))
+    }
+
+    fn update(
+        &mut self,
+        _setting: MqttNegotiateFormatSetting,
+        value: Flag,
+    ) -> ConfigSettingResult<()> {
+        self.data.mqtt.negotiate_format = Some(value.into());
+        Ok(())
+    }
+
+    fn unset(&mut self, _setting: MqttNegotiateFormatSetting) -> ConfigSettingResult<()> {
+        self.data.mqtt.negotiate_format = None;
+        Ok(())
+    }
+}
+
 /// Generic extension trait implementation for all `ConfigSetting`s of `TEdgeConfig`
 /// that provide `TryFrom`/`TryInto` implementations for `String`.
 impl<T, E, F> ConfigSettingAccessorStringExt<T> for TEdgeConfig
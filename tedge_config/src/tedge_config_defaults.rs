@@ -37,6 +37,9 @@ pub struct TEdgeConfigDefaults {
 
     /// Default port for mqtt
     pub default_mqtt_port: Port,
+
+    /// Default mqtt format negotiation bool
+    pub default_mqtt_negotiate_format: Flag,
 }
 
 impl From<&TEdgeConfigLocation> for TEdgeConfigDefaults {
@@ -57,6 +60,7 @@ impl From<&TEdgeConfigLocation> for TEdgeConfigDefaults {
             default_c8y_root_cert_path: system_cert_path.into(),
             default_mapper_timestamp: Flag(true),
             default_mqtt_port: Port(DEFAULT_PORT),
+            default_mqtt_negotiate_format: Flag(false),
         }
     }
 }
@@ -79,6 +83,7 @@ fn test_from_tedge_config_location() {
             default_c8y_root_cert_path: FilePath::from("/etc/ssl/certs"),
             default_mapper_timestamp: Flag(true),
             default_mqtt_port: Port(DEFAULT_PORT),
+            default_mqtt_negotiate_format: Flag(false),
         }
     );
 }
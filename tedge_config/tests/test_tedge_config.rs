@@ -24,6 +24,9 @@ mapper_timestamp = true
 
 [mqtt]
 port = 1234
+username = "tedge"
+password = "s3cr3t"
+negotiate_format = true
 "#;
 
     let (_tempdir, config_location) = create_temp_tedge_config(toml_conf)?;
@@ -62,6 +65,9 @@ port = 1234
     assert_eq!(config.query(AzureMapperTimestamp)?, Flag(true));
 
     assert_eq!(config.query(MqttPortSetting)?, Port(1234));
+    assert_eq!(config.query(MqttUsernameSetting)?, "tedge");
+    assert_eq!(config.query(MqttPasswordSetting)?.expose_secret(), "s3cr3t");
+    assert_eq!(config.query(MqttNegotiateFormatSetting)?, Flag(true));
 
     Ok(())
 }
@@ -473,6 +479,9 @@ fn test_parse_config_empty_file() -> Result<(), TEdgeConfigError> {
     assert_eq!(config.query(AzureMapperTimestamp)?, Flag(true));
 
     assert_eq!(config.query(MqttPortSetting)?, Port(1883));
+    assert!(config.query_optional(MqttUsernameSetting)?.is_none());
+    assert!(config.query_optional(MqttPasswordSetting)?.is_none());
+    assert_eq!(config.query(MqttNegotiateFormatSetting)?, Flag(false));
     Ok(())
 }
 
@@ -765,6 +774,7 @@ fn dummy_tedge_config_defaults() -> TEdgeConfigDefaults {
         default_azure_root_cert_path: FilePath::from("/dev/null"),
         default_mapper_timestamp: Flag(true),
         default_mqtt_port: Port(1883),
+        default_mqtt_negotiate_format: Flag(false),
     }
 }
 